@@ -110,6 +110,8 @@ pub struct ParsedSpec {
     pub spec: ComputeSpec,
     pub tenant_id: TenantId,
     pub timeline_id: TimelineId,
+    /// The pageserver to take the basebackup from: shard zero's for a sharded tenant,
+    /// which holds everything but relation blocks.
     pub pageserver_connstr: String,
     pub safekeeper_connstrings: Vec<String>,
     pub storage_auth_token: Option<String>,
@@ -125,8 +127,10 @@ impl TryFrom<ComputeSpec> for ParsedSpec {
         // may be empty. In that case, we need to dig them from the GUCs in the
         // cluster.settings field.
         let pageserver_connstr = spec
-            .pageserver_connstring
-            .clone()
+            .pageserver_connstrings
+            .first()
+            .or(spec.pageserver_connstring.as_ref())
+            .cloned()
             .or_else(|| spec.cluster.settings.find("neon.pageserver_connstring"))
             .ok_or("pageserver connstr should be provided")?;
         let safekeeper_connstrings = if spec.safekeeper_connstrings.is_empty() {
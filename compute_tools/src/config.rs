@@ -51,6 +51,22 @@ pub fn write_postgres_conf(
     if let Some(s) = &spec.pageserver_connstring {
         writeln!(file, "neon.pageserver_connstring={}", escape_conf_value(s))?;
     }
+    if !spec.pageserver_connstrings.is_empty() {
+        // A list setting: quote the elements, key-value connection strings have spaces.
+        let connstrings = spec
+            .pageserver_connstrings
+            .iter()
+            .map(|s| format!("\"{}\"", s.replace('"', "\"\"")))
+            .collect::<Vec<_>>();
+        writeln!(
+            file,
+            "neon.pageserver_connstrings={}",
+            escape_conf_value(&connstrings.join(","))
+        )?;
+    }
+    if let Some(stripe_size) = spec.shard_stripe_size {
+        writeln!(file, "neon.stripe_size={stripe_size}")?;
+    }
     if !spec.safekeeper_connstrings.is_empty() {
         writeln!(
             file,
@@ -5,6 +5,7 @@ mod config_tests {
     use std::io::{Read, Write};
     use std::path::Path;
 
+    use compute_api::spec::ComputeSpec;
     use compute_tools::config::*;
 
     fn write_test_file(path: &Path, content: &str) {
@@ -45,4 +46,31 @@ mod config_tests {
 
         remove_file(path).unwrap();
     }
+
+    #[test]
+    fn test_write_postgres_conf_sharded() {
+        let path = Path::new("./tests/tmp/sharded_postgresql.conf");
+        let spec = ComputeSpec {
+            pageserver_connstrings: vec![
+                "postgresql://no_user@ps1:6400".to_string(),
+                "host=ps2 port=6400".to_string(),
+            ],
+            shard_stripe_size: Some(32768),
+            ..Default::default()
+        };
+        write_postgres_conf(path, &spec, None).unwrap();
+
+        let mut content = String::new();
+        File::open(path)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains(
+            "neon.pageserver_connstrings='\"postgresql://no_user@ps1:6400\",\"host=ps2 port=6400\"'"
+        ));
+        assert!(!content.contains("neon.pageserver_connstring="));
+        assert!(content.contains("neon.stripe_size=32768"));
+
+        remove_file(path).unwrap();
+    }
 }
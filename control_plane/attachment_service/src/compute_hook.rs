@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+use control_plane::endpoint::ComputeControlPlane;
+use control_plane::local_env::LocalEnv;
+use pageserver_api::shard::ShardStripeSize;
+use utils::id::{NodeId, TenantId};
+
+/// Tells computes where their tenant's shards are attached, whenever that changes in a way
+/// that they must know about to route their reads.
+///
+/// In a real deployment, the control plane does this.  Under `neon_local`, the service
+/// reconfigures the running endpoints of the local repository itself.
+pub(crate) struct ComputeHook {
+    /// The `neon_local` repository whose endpoints to reconfigure, if any
+    neon_local_repo_dir: Option<PathBuf>,
+}
+
+impl ComputeHook {
+    pub(crate) fn new(neon_local_repo_dir: Option<PathBuf>) -> Self {
+        Self {
+            neon_local_repo_dir,
+        }
+    }
+
+    /// Point the running computes of `tenant_id` at `pageservers`, which hold the tenant's
+    /// shards in shard number order.  `stripe_size` is None for an unsharded tenant.
+    pub(crate) async fn notify(
+        &self,
+        tenant_id: TenantId,
+        pageservers: Vec<NodeId>,
+        stripe_size: Option<ShardStripeSize>,
+    ) -> anyhow::Result<()> {
+        let Some(repo_dir) = self.neon_local_repo_dir.clone() else {
+            tracing::info!("No neon_local repository, not notifying computes of {tenant_id}");
+            return Ok(());
+        };
+
+        // Endpoint configuration is file-based, and reconfiguring uses a blocking client
+        tokio::task::spawn_blocking(move || {
+            let env = LocalEnv::load_config_from(repo_dir)?;
+            let cplane = ComputeControlPlane::load(env)?;
+            for (endpoint_name, endpoint) in &cplane.endpoints {
+                if endpoint.tenant_id == tenant_id && endpoint.status() == "running" {
+                    tracing::info!("Reconfiguring endpoint {endpoint_name}");
+                    endpoint.reconfigure(pageservers.clone(), stripe_size)?;
+                }
+            }
+            Ok(())
+        })
+        .await?
+    }
+}
@@ -0,0 +1,98 @@
+/// The attachment service mimics the aspects of the control plane API
+/// that are required for a pageserver to operate.
+///
+/// This enables running & testing pageservers without a full-blown
+/// deployment of the Neon cloud platform.
+///
+use anyhow::anyhow;
+use attachment_service::http::make_router;
+use attachment_service::persistence::Persistence;
+use attachment_service::service::{Config, Service};
+use clap::Parser;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use utils::logging::{self, LogFormat};
+use utils::signals::{ShutdownSignals, Signal};
+
+use utils::tcp_listener;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+#[command(arg_required_else_help(true))]
+struct Cli {
+    /// Host and port to listen on, like `127.0.0.1:1234`
+    #[arg(short, long)]
+    listen: std::net::SocketAddr,
+
+    /// Path to the SQLite database to store state (will be created if it doesn't exist)
+    #[arg(short, long)]
+    path: PathBuf,
+
+    /// Token for authenticating this service with the pageservers it controls
+    #[arg(long)]
+    jwt_token: Option<String>,
+
+    /// How often to check that each pageserver is responsive
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    heartbeat_interval: Duration,
+
+    /// How long a pageserver may be unresponsive before its tenants are moved elsewhere
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    max_unavailable_interval: Duration,
+
+    /// The `neon_local` repository whose endpoints to reconfigure when their tenant's shards
+    /// move.  Without it, computes are not notified.
+    #[arg(long)]
+    neon_local_repo_dir: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    logging::init(
+        LogFormat::Plain,
+        logging::TracingErrorLayerEnablement::Disabled,
+        logging::Output::Stdout,
+    )?;
+
+    let args = Cli::parse();
+    tracing::info!(
+        "Starting, state at {}, listening on {}",
+        args.path.to_string_lossy(),
+        args.listen
+    );
+
+    let persistence = Arc::new(Persistence::open(&args.path).map_err(|e| {
+        anyhow!(
+            "Failed to open database '{}': {e:#} (maybe your .neon/ dir was written by an older version?)",
+            args.path.display()
+        )
+    })?);
+
+    let config = Config {
+        jwt_token: args.jwt_token,
+        heartbeat_interval: args.heartbeat_interval,
+        max_unavailable_interval: args.max_unavailable_interval,
+        neon_local_repo_dir: args.neon_local_repo_dir,
+    };
+    let service = Service::spawn(config, persistence).await?;
+
+    let http_listener = tcp_listener::bind(args.listen)?;
+    let router = make_router(service).build().map_err(|err| anyhow!(err))?;
+    let service = utils::http::RouterService::new(router).unwrap();
+    let server = hyper::Server::from_tcp(http_listener)?.serve(service);
+
+    tracing::info!("Serving on {0}", args.listen);
+
+    tokio::task::spawn(server);
+
+    ShutdownSignals::handle(|signal| match signal {
+        Signal::Interrupt | Signal::Terminate | Signal::Quit => {
+            tracing::info!("Got {}. Terminating", signal.name());
+            // We're just a test helper: no graceful shutdown.
+            std::process::exit(0);
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use control_plane::attachment_service::{NodeAvailability, NodeDescribeResponse};
+use pageserver_client::mgmt_api;
+use utils::id::NodeId;
+
+/// A pageserver registered with the attachment service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Node {
+    pub(crate) id: NodeId,
+
+    pub(crate) availability: NodeAvailability,
+
+    pub(crate) listen_http_addr: String,
+    pub(crate) listen_http_port: u16,
+
+    pub(crate) listen_pg_addr: String,
+    pub(crate) listen_pg_port: u16,
+}
+
+impl Node {
+    pub(crate) fn base_url(&self) -> String {
+        format!("http://{}:{}", self.listen_http_addr, self.listen_http_port)
+    }
+
+    /// A management API client for this node, sharing `http_client`'s connection pool.
+    pub(crate) fn client(
+        &self,
+        http_client: &reqwest::Client,
+        jwt: Option<&str>,
+    ) -> mgmt_api::Client {
+        mgmt_api::Client::from_client(http_client.clone(), self.base_url(), jwt)
+    }
+
+    /// Is this node eligible to have work scheduled onto it?
+    pub(crate) fn may_schedule(&self) -> bool {
+        match self.availability {
+            NodeAvailability::Active => true,
+            NodeAvailability::Offline => false,
+        }
+    }
+
+    pub(crate) fn describe(&self) -> NodeDescribeResponse {
+        NodeDescribeResponse {
+            node_id: self.id,
+            availability: self.availability,
+            listen_pg_addr: self.listen_pg_addr.clone(),
+            listen_pg_port: self.listen_pg_port,
+            listen_http_addr: self.listen_http_addr.clone(),
+            listen_http_port: self.listen_http_port,
+        }
+    }
+}
@@ -0,0 +1,948 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use control_plane::attachment_service::{NodeAvailability, PlacementPolicy};
+use pageserver_api::models::TenantConfig;
+use pageserver_api::shard::{
+    ShardCount, ShardNumber, ShardStripeSize, TenantShardId, DEFAULT_STRIPE_SIZE,
+};
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use utils::id::{NodeId, TenantId};
+
+use crate::node::Node;
+
+/// ## What do we store?
+///
+/// The attachment service does not store most of its state durably.  The
+/// essential things to store durably are:
+/// - generation numbers, as these must always advance monotonically to ensure data safety.
+/// - which pageserver each generation was issued to, so that re-attach requests can be
+///   answered after a restart.
+/// - the registry of pageservers, and where each tenant shard's locations were placed.
+/// - tenant shard parameters and configuration, which cannot be reconstructed from
+///   anywhere else.
+/// - the shard splits in progress, so that a split that stopped part way is resumed
+///   rather than leaving the tenant with shards of different counts.
+///
+/// ## Performance/efficiency
+///
+/// The attachment service only reads from the database at startup: while running,
+/// its in-memory state is authoritative for reads.  Writes are transactional and
+/// always go to the database before the in-memory state is updated, so a restart
+/// never hands out a generation number that was already issued.
+pub struct Persistence {
+    conn: Arc<Mutex<Connection>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum DatabaseError {
+    #[error(transparent)]
+    Query(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Serialization(#[from] serde_json::Error),
+    #[error("Logical error: {0}")]
+    Logical(String),
+    #[error("Database task failed: {0}")]
+    Join(#[from] tokio::task::JoinError),
+}
+
+pub(crate) type DatabaseResult<T> = Result<T, DatabaseError>;
+
+/// Schema migrations, applied in order.  The index of the last applied migration
+/// (plus one) is recorded in sqlite's `user_version` pragma.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    r#"
+    CREATE TABLE nodes (
+        node_id INTEGER PRIMARY KEY NOT NULL,
+        availability TEXT NOT NULL,
+        listen_http_addr TEXT NOT NULL,
+        listen_http_port INTEGER NOT NULL,
+        listen_pg_addr TEXT NOT NULL,
+        listen_pg_port INTEGER NOT NULL
+    );
+    CREATE TABLE tenant_shards (
+        tenant_id TEXT NOT NULL,
+        shard_number INTEGER NOT NULL,
+        shard_count INTEGER NOT NULL,
+        shard_stripe_size INTEGER NOT NULL,
+        generation INTEGER NOT NULL,
+        generation_pageserver INTEGER,
+        secondary_pageservers TEXT NOT NULL,
+        placement_policy TEXT NOT NULL,
+        config TEXT NOT NULL,
+        PRIMARY KEY (tenant_id, shard_number, shard_count)
+    );
+    "#,
+    // 2: shard splits in progress
+    r#"
+    CREATE TABLE tenant_shard_splits (
+        tenant_id TEXT PRIMARY KEY NOT NULL,
+        new_shard_count INTEGER NOT NULL
+    );
+    "#,
+];
+
+/// The state file of the versions of the attachment service before the database, which sits
+/// next to the database.  It is imported on the first start with the database.
+const LEGACY_STATE_FILE_NAME: &str = "attachments.json";
+
+/// The contents of [`LEGACY_STATE_FILE_NAME`]: the attached pageserver and the latest
+/// generation of each unsharded tenant.
+#[derive(Deserialize)]
+struct LegacyState {
+    tenants: HashMap<String, LegacyTenantState>,
+}
+
+#[derive(Deserialize)]
+struct LegacyTenantState {
+    pageserver: Option<NodeId>,
+    generation: u32,
+}
+
+/// The durable state of one tenant shard
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TenantShardPersistence {
+    pub(crate) tenant_shard_id: TenantShardId,
+    pub(crate) shard_stripe_size: ShardStripeSize,
+
+    // Latest generation number: next time we attach, increment this
+    // and use the incremented number when attaching
+    pub(crate) generation: u32,
+
+    // The pageserver that `generation` was issued to, if any.  This is the
+    // attached location of the shard.
+    pub(crate) generation_pageserver: Option<NodeId>,
+
+    pub(crate) secondary_pageservers: Vec<NodeId>,
+
+    pub(crate) placement_policy: PlacementPolicy,
+    pub(crate) config: TenantConfig,
+}
+
+/// A `tenant_shards` row in its raw sqlite representation, before parsing.
+type TenantShardRow = (
+    String,
+    u8,
+    u8,
+    u32,
+    u32,
+    Option<i64>,
+    String,
+    String,
+    String,
+);
+
+impl Persistence {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)?;
+        let persistence = Self::from_connection(conn)?;
+
+        let legacy_state_path = path.with_file_name(LEGACY_STATE_FILE_NAME);
+        if legacy_state_path.exists() {
+            persistence
+                .import_legacy_state(&legacy_state_path)
+                .with_context(|| format!("Failed to import {}", legacy_state_path.display()))?;
+        }
+
+        Ok(persistence)
+    }
+
+    /// Import the tenants of the JSON state file that the attachment service used before the
+    /// database, and rename the file so that it is only imported once.  Losing its generations
+    /// would break their monotonicity, so if the database already has tenants, this refuses
+    /// to choose between the two.
+    fn import_legacy_state(&self, legacy_state_path: &Path) -> anyhow::Result<()> {
+        let state: LegacyState = serde_json::from_slice(&std::fs::read(legacy_state_path)?)?;
+        let shards = state
+            .tenants
+            .into_iter()
+            .map(|(tenant_id, tenant)| {
+                Ok(TenantShardPersistence {
+                    tenant_shard_id: TenantShardId::from_str(&tenant_id)
+                        .with_context(|| format!("bad tenant id '{tenant_id}'"))?,
+                    shard_stripe_size: DEFAULT_STRIPE_SIZE,
+                    generation: tenant.generation,
+                    generation_pageserver: tenant.pageserver,
+                    secondary_pageservers: Vec::new(),
+                    placement_policy: PlacementPolicy::Single,
+                    config: TenantConfig::default(),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        {
+            let mut conn = self.conn.lock().unwrap();
+            let existing: i64 =
+                conn.query_row("SELECT COUNT(*) FROM tenant_shards", [], |row| row.get(0))?;
+            if existing > 0 {
+                anyhow::bail!(
+                    "The database already has tenants: remove the file if it was imported before"
+                );
+            }
+            let tx = conn.transaction()?;
+            for shard in &shards {
+                insert_tenant_shard(&tx, shard.clone())?;
+            }
+            tx.commit()?;
+        }
+
+        let imported_path = legacy_state_path.with_extension("json.imported");
+        std::fs::rename(legacy_state_path, &imported_path)?;
+        tracing::info!(
+            "Imported {} tenants from {}, renamed it to {}",
+            shards.len(),
+            legacy_state_path.display(),
+            imported_path.display()
+        );
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn open_in_memory() -> DatabaseResult<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut conn: Connection) -> DatabaseResult<Self> {
+        Self::migrate(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn migrate(conn: &mut Connection) -> DatabaseResult<()> {
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let version = usize::try_from(version)
+            .map_err(|_| DatabaseError::Logical(format!("invalid schema version {version}")))?;
+        if version > MIGRATIONS.len() {
+            return Err(DatabaseError::Logical(format!(
+                "database schema version {version} is newer than this binary supports ({})",
+                MIGRATIONS.len()
+            )));
+        }
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            tracing::info!("Applying database migration {}", i + 1);
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", (i + 1) as i64)?;
+            tx.commit()?;
+        }
+
+        Ok(())
+    }
+
+    /// Run a closure against the database connection on a blocking thread: sqlite
+    /// calls do synchronous I/O and must not run on the async executor.
+    async fn with_conn<F, R>(&self, func: F) -> DatabaseResult<R>
+    where
+        F: FnOnce(&mut Connection) -> DatabaseResult<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            func(&mut conn)
+        })
+        .await?
+    }
+
+    pub(crate) async fn list_nodes(&self) -> DatabaseResult<Vec<Node>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT node_id, availability, listen_http_addr, listen_http_port, \
+                 listen_pg_addr, listen_pg_port FROM nodes ORDER BY node_id",
+            )?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, u16>(3)?,
+                        row.get::<_, String>(4)?,
+                        row.get::<_, u16>(5)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            rows.into_iter()
+                .map(
+                    |(
+                        node_id,
+                        availability,
+                        listen_http_addr,
+                        listen_http_port,
+                        listen_pg_addr,
+                        listen_pg_port,
+                    )| {
+                        Ok(Node {
+                            id: NodeId(node_id as u64),
+                            availability: serde_json::from_str::<NodeAvailability>(&availability)?,
+                            listen_http_addr,
+                            listen_http_port,
+                            listen_pg_addr,
+                            listen_pg_port,
+                        })
+                    },
+                )
+                .collect()
+        })
+        .await
+    }
+
+    /// Insert a node, or update its addresses and availability if it is already registered.
+    pub(crate) async fn upsert_node(&self, node: &Node) -> DatabaseResult<()> {
+        let node = node.clone();
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO nodes (node_id, availability, listen_http_addr, listen_http_port, \
+                 listen_pg_addr, listen_pg_port) VALUES (?1, ?2, ?3, ?4, ?5, ?6) \
+                 ON CONFLICT(node_id) DO UPDATE SET availability=excluded.availability, \
+                 listen_http_addr=excluded.listen_http_addr, \
+                 listen_http_port=excluded.listen_http_port, \
+                 listen_pg_addr=excluded.listen_pg_addr, \
+                 listen_pg_port=excluded.listen_pg_port",
+                params![
+                    node.id.0 as i64,
+                    serde_json::to_string(&node.availability)?,
+                    node.listen_http_addr,
+                    node.listen_http_port,
+                    node.listen_pg_addr,
+                    node.listen_pg_port,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn update_node_availability(
+        &self,
+        node_id: NodeId,
+        availability: NodeAvailability,
+    ) -> DatabaseResult<()> {
+        self.with_conn(move |conn| {
+            let updated = conn.execute(
+                "UPDATE nodes SET availability=?1 WHERE node_id=?2",
+                params![serde_json::to_string(&availability)?, node_id.0 as i64],
+            )?;
+            if updated != 1 {
+                return Err(DatabaseError::Logical(format!("node {node_id} not found")));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn list_tenant_shards(&self) -> DatabaseResult<Vec<TenantShardPersistence>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT tenant_id, shard_number, shard_count, shard_stripe_size, generation, \
+                 generation_pageserver, secondary_pageservers, placement_policy, config \
+                 FROM tenant_shards ORDER BY tenant_id, shard_count, shard_number",
+            )?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                        row.get(7)?,
+                        row.get(8)?,
+                    ))
+                })?
+                .collect::<Result<Vec<TenantShardRow>, _>>()?;
+
+            rows.into_iter().map(Self::parse_tenant_shard_row).collect()
+        })
+        .await
+    }
+
+    fn parse_tenant_shard_row(row: TenantShardRow) -> DatabaseResult<TenantShardPersistence> {
+        let (
+            tenant_id,
+            shard_number,
+            shard_count,
+            shard_stripe_size,
+            generation,
+            generation_pageserver,
+            secondary_pageservers,
+            placement_policy,
+            config,
+        ) = row;
+
+        let tenant_id = TenantId::from_str(&tenant_id)
+            .map_err(|e| DatabaseError::Logical(format!("bad tenant id '{tenant_id}': {e}")))?;
+
+        Ok(TenantShardPersistence {
+            tenant_shard_id: TenantShardId {
+                tenant_id,
+                shard_number: ShardNumber(shard_number),
+                shard_count: ShardCount(shard_count),
+            },
+            shard_stripe_size: ShardStripeSize(shard_stripe_size),
+            generation,
+            generation_pageserver: generation_pageserver.map(|n| NodeId(n as u64)),
+            secondary_pageservers: serde_json::from_str(&secondary_pageservers)?,
+            placement_policy: serde_json::from_str(&placement_policy)?,
+            config: serde_json::from_str(&config)?,
+        })
+    }
+
+    /// Tenants must be persisted before we schedule them for the first time.  This enables us
+    /// to correctly retain generation monotonicity, and the externally provided placement policy & config.
+    ///
+    /// All the shards are inserted in a single transaction: a tenant is never left half-created.
+    pub(crate) async fn insert_tenant_shards(
+        &self,
+        shards: Vec<TenantShardPersistence>,
+    ) -> DatabaseResult<()> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            for shard in shards {
+                insert_tenant_shard(&tx, shard)?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Replace a shard that has been split with its children.  This is a single transaction,
+    /// so that a pageserver re-attaching at any point gets either the parent or all of
+    /// its children.
+    pub(crate) async fn complete_shard_split(
+        &self,
+        parent: TenantShardId,
+        children: Vec<TenantShardPersistence>,
+    ) -> DatabaseResult<()> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let deleted = tx.execute(
+                "DELETE FROM tenant_shards \
+                 WHERE tenant_id=?1 AND shard_number=?2 AND shard_count=?3",
+                params![
+                    parent.tenant_id.to_string(),
+                    parent.shard_number.0,
+                    parent.shard_count.0
+                ],
+            )?;
+            if deleted != 1 {
+                return Err(DatabaseError::Logical(format!(
+                    "Tenant shard {parent} not found"
+                )));
+            }
+            for child in children {
+                insert_tenant_shard(&tx, child)?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// The tenants with a shard split in progress, and the shard count they are split to.
+    pub(crate) async fn list_shard_splits(&self) -> DatabaseResult<HashMap<TenantId, ShardCount>> {
+        self.with_conn(|conn| {
+            let mut stmt =
+                conn.prepare("SELECT tenant_id, new_shard_count FROM tenant_shard_splits")?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, u8>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            rows.into_iter()
+                .map(|(tenant_id, new_shard_count)| {
+                    let tenant_id = TenantId::from_str(&tenant_id).map_err(|e| {
+                        DatabaseError::Logical(format!("bad tenant id '{tenant_id}': {e}"))
+                    })?;
+                    Ok((tenant_id, ShardCount(new_shard_count)))
+                })
+                .collect()
+        })
+        .await
+    }
+
+    /// Record that a tenant's shards are being split, before the first of them is: until
+    /// [`Self::end_shard_split`], the tenant's shards may have different shard counts.
+    pub(crate) async fn begin_shard_split(
+        &self,
+        tenant_id: TenantId,
+        new_shard_count: ShardCount,
+    ) -> DatabaseResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO tenant_shard_splits (tenant_id, new_shard_count) VALUES (?1, ?2)",
+                params![tenant_id.to_string(), new_shard_count.0],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Record that all the shards of a tenant were split, and its computes know about it.
+    pub(crate) async fn end_shard_split(&self, tenant_id: TenantId) -> DatabaseResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "DELETE FROM tenant_shard_splits WHERE tenant_id=?1",
+                params![tenant_id.to_string()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Remove all the shards of a tenant, and any split of them in progress.
+    pub(crate) async fn delete_tenant(&self, tenant_id: TenantId) -> DatabaseResult<()> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "DELETE FROM tenant_shards WHERE tenant_id=?1",
+                params![tenant_id.to_string()],
+            )?;
+            tx.execute(
+                "DELETE FROM tenant_shard_splits WHERE tenant_id=?1",
+                params![tenant_id.to_string()],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// When a pageserver starts up, it re-attaches all the shards whose generation was
+    /// issued to it: increment all their generations, and return the new values.
+    pub(crate) async fn re_attach(
+        &self,
+        node_id: NodeId,
+    ) -> DatabaseResult<HashMap<TenantShardId, u32>> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "UPDATE tenant_shards SET generation=generation + 1 \
+                 WHERE generation_pageserver=?1",
+                params![node_id.0 as i64],
+            )?;
+
+            let mut result = HashMap::new();
+            {
+                let mut stmt = tx.prepare(
+                    "SELECT tenant_id, shard_number, shard_count, generation \
+                     FROM tenant_shards WHERE generation_pageserver=?1",
+                )?;
+                let rows = stmt
+                    .query_map(params![node_id.0 as i64], |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, u8>(1)?,
+                            row.get::<_, u8>(2)?,
+                            row.get::<_, u32>(3)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                for (tenant_id, shard_number, shard_count, generation) in rows {
+                    let tenant_id = TenantId::from_str(&tenant_id).map_err(|e| {
+                        DatabaseError::Logical(format!("bad tenant id '{tenant_id}': {e}"))
+                    })?;
+                    result.insert(
+                        TenantShardId {
+                            tenant_id,
+                            shard_number: ShardNumber(shard_number),
+                            shard_count: ShardCount(shard_count),
+                        },
+                        generation,
+                    );
+                }
+            }
+
+            tx.commit()?;
+            Ok(result)
+        })
+        .await
+    }
+
+    /// Issue a new generation for a tenant shard to `node_id`, which becomes its attached
+    /// location.  Returns the new generation number.
+    pub(crate) async fn increment_generation(
+        &self,
+        tenant_shard_id: TenantShardId,
+        node_id: NodeId,
+    ) -> DatabaseResult<u32> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let updated = tx.execute(
+                "UPDATE tenant_shards SET generation=generation + 1, generation_pageserver=?1 \
+                 WHERE tenant_id=?2 AND shard_number=?3 AND shard_count=?4",
+                params![
+                    node_id.0 as i64,
+                    tenant_shard_id.tenant_id.to_string(),
+                    tenant_shard_id.shard_number.0,
+                    tenant_shard_id.shard_count.0,
+                ],
+            )?;
+            if updated != 1 {
+                return Err(DatabaseError::Logical(format!(
+                    "tenant shard {tenant_shard_id} not found"
+                )));
+            }
+
+            let generation: u32 = tx.query_row(
+                "SELECT generation FROM tenant_shards \
+                 WHERE tenant_id=?1 AND shard_number=?2 AND shard_count=?3",
+                params![
+                    tenant_shard_id.tenant_id.to_string(),
+                    tenant_shard_id.shard_number.0,
+                    tenant_shard_id.shard_count.0,
+                ],
+                |row| row.get(0),
+            )?;
+
+            tx.commit()?;
+            Ok(generation)
+        })
+        .await
+    }
+
+    /// Forget the attached location of a tenant shard: its generation is left as-is, and
+    /// the next attachment will increment it.
+    pub(crate) async fn detach(&self, tenant_shard_id: TenantShardId) -> DatabaseResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE tenant_shards SET generation_pageserver=NULL \
+                 WHERE tenant_id=?1 AND shard_number=?2 AND shard_count=?3",
+                params![
+                    tenant_shard_id.tenant_id.to_string(),
+                    tenant_shard_id.shard_number.0,
+                    tenant_shard_id.shard_count.0,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) async fn set_secondaries(
+        &self,
+        tenant_shard_id: TenantShardId,
+        secondaries: Vec<NodeId>,
+    ) -> DatabaseResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE tenant_shards SET secondary_pageservers=?1 \
+                 WHERE tenant_id=?2 AND shard_number=?3 AND shard_count=?4",
+                params![
+                    serde_json::to_string(&secondaries)?,
+                    tenant_shard_id.tenant_id.to_string(),
+                    tenant_shard_id.shard_number.0,
+                    tenant_shard_id.shard_count.0,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+fn insert_tenant_shard(
+    tx: &rusqlite::Transaction,
+    shard: TenantShardPersistence,
+) -> DatabaseResult<()> {
+    tx.execute(
+        "INSERT INTO tenant_shards (tenant_id, shard_number, shard_count, \
+         shard_stripe_size, generation, generation_pageserver, \
+         secondary_pageservers, placement_policy, config) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            shard.tenant_shard_id.tenant_id.to_string(),
+            shard.tenant_shard_id.shard_number.0,
+            shard.tenant_shard_id.shard_count.0,
+            shard.shard_stripe_size.0,
+            shard.generation,
+            shard.generation_pageserver.map(|n| n.0 as i64),
+            serde_json::to_string(&shard.secondary_pageservers)?,
+            serde_json::to_string(&shard.placement_policy)?,
+            serde_json::to_string(&shard.config)?,
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shard(tenant_id: TenantId, number: u8, count: u8) -> TenantShardPersistence {
+        TenantShardPersistence {
+            tenant_shard_id: TenantShardId {
+                tenant_id,
+                shard_number: ShardNumber(number),
+                shard_count: ShardCount(count),
+            },
+            shard_stripe_size: ShardStripeSize(32768),
+            generation: 0,
+            generation_pageserver: None,
+            secondary_pageservers: Vec::new(),
+            placement_policy: PlacementPolicy::Double(1),
+            config: TenantConfig::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn tenant_shards_roundtrip() -> anyhow::Result<()> {
+        let persistence = Persistence::open_in_memory()?;
+        let tenant_id = TenantId::generate();
+        let shards = vec![shard(tenant_id, 0, 2), shard(tenant_id, 1, 2)];
+        persistence.insert_tenant_shards(shards.clone()).await?;
+
+        // Inserting the same shards again is a conflict, and must not be partially applied
+        assert!(persistence
+            .insert_tenant_shards(vec![shard(tenant_id, 1, 2)])
+            .await
+            .is_err());
+
+        persistence
+            .set_secondaries(shards[0].tenant_shard_id, vec![NodeId(2)])
+            .await?;
+        let mut expect = shards;
+        expect[0].secondary_pageservers = vec![NodeId(2)];
+        assert_eq!(persistence.list_tenant_shards().await?, expect);
+
+        persistence.delete_tenant(tenant_id).await?;
+        assert!(persistence.list_tenant_shards().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shard_split_roundtrip() -> anyhow::Result<()> {
+        let persistence = Persistence::open_in_memory()?;
+        let tenant_id = TenantId::generate();
+        persistence
+            .insert_tenant_shards(vec![shard(tenant_id, 0, 2), shard(tenant_id, 1, 2)])
+            .await?;
+
+        persistence
+            .begin_shard_split(tenant_id, ShardCount(4))
+            .await?;
+        assert!(persistence
+            .begin_shard_split(tenant_id, ShardCount(8))
+            .await
+            .is_err());
+
+        // Part way through, the split is still recorded
+        persistence
+            .complete_shard_split(
+                shard(tenant_id, 0, 2).tenant_shard_id,
+                vec![shard(tenant_id, 0, 4), shard(tenant_id, 2, 4)],
+            )
+            .await?;
+        assert_eq!(
+            persistence.list_shard_splits().await?,
+            HashMap::from([(tenant_id, ShardCount(4))])
+        );
+        let counts = persistence
+            .list_tenant_shards()
+            .await?
+            .into_iter()
+            .map(|s| s.tenant_shard_id.shard_count.0)
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![2, 4, 4]);
+
+        persistence.end_shard_split(tenant_id).await?;
+        assert!(persistence.list_shard_splits().await?.is_empty());
+
+        persistence
+            .begin_shard_split(tenant_id, ShardCount(8))
+            .await?;
+        persistence.delete_tenant(tenant_id).await?;
+        assert!(persistence.list_shard_splits().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_legacy_state() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("attachment_service-{}", TenantId::generate()));
+        std::fs::create_dir_all(&dir)?;
+        let db_path = dir.join("attachments.db");
+        let legacy_state_path = dir.join(LEGACY_STATE_FILE_NAME);
+
+        let attached = TenantId::generate();
+        let detached = TenantId::generate();
+        let legacy_state = format!(
+            r#"{{"tenants": {{"{attached}": {{"pageserver": 1, "generation": 5}}, "{detached}": {{"pageserver": null, "generation": 2}}}}}}"#
+        );
+        std::fs::write(&legacy_state_path, &legacy_state)?;
+
+        let persistence = Persistence::open(&db_path)?;
+        assert!(!legacy_state_path.exists());
+        let mut expect = vec![
+            TenantShardPersistence {
+                generation: 5,
+                generation_pageserver: Some(NodeId(1)),
+                placement_policy: PlacementPolicy::Single,
+                ..shard(attached, 0, 0)
+            },
+            TenantShardPersistence {
+                generation: 2,
+                placement_policy: PlacementPolicy::Single,
+                ..shard(detached, 0, 0)
+            },
+        ];
+        expect.sort_by_key(|s| s.tenant_shard_id);
+        let mut shards = persistence.list_tenant_shards().await?;
+        shards.sort_by_key(|s| s.tenant_shard_id);
+        assert_eq!(shards, expect);
+        assert_eq!(
+            persistence.re_attach(NodeId(1)).await?,
+            HashMap::from([(TenantShardId::unsharded(attached), 6)])
+        );
+        drop(persistence);
+
+        // The imported state is never imported twice, or preferred over the database
+        Persistence::open(&db_path)?;
+        std::fs::write(&legacy_state_path, &legacy_state)?;
+        assert!(Persistence::open(&db_path).is_err());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shard_split() -> anyhow::Result<()> {
+        let persistence = Persistence::open_in_memory()?;
+        let tenant_id = TenantId::generate();
+        let parent = TenantShardPersistence {
+            generation: 3,
+            generation_pageserver: Some(NodeId(1)),
+            ..shard(tenant_id, 1, 2)
+        };
+        let sibling = shard(tenant_id, 0, 2);
+        persistence
+            .insert_tenant_shards(vec![sibling.clone(), parent.clone()])
+            .await?;
+
+        let children = vec![
+            TenantShardPersistence {
+                generation: 3,
+                generation_pageserver: Some(NodeId(1)),
+                ..shard(tenant_id, 1, 4)
+            },
+            TenantShardPersistence {
+                generation: 3,
+                generation_pageserver: Some(NodeId(1)),
+                ..shard(tenant_id, 3, 4)
+            },
+        ];
+        persistence
+            .complete_shard_split(parent.tenant_shard_id, children.clone())
+            .await?;
+
+        let mut expect = vec![sibling];
+        expect.extend(children.clone());
+        let mut shards = persistence.list_tenant_shards().await?;
+        shards.sort_by_key(|s| s.tenant_shard_id);
+        expect.sort_by_key(|s| s.tenant_shard_id);
+        assert_eq!(shards, expect);
+
+        // The children now re-attach in the parent's place
+        assert_eq!(
+            persistence.re_attach(NodeId(1)).await?,
+            HashMap::from([
+                (children[0].tenant_shard_id, 4),
+                (children[1].tenant_shard_id, 4)
+            ])
+        );
+
+        // A split that was already completed can't be completed again
+        assert!(persistence
+            .complete_shard_split(parent.tenant_shard_id, Vec::new())
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn generations() -> anyhow::Result<()> {
+        let persistence = Persistence::open_in_memory()?;
+        let tenant_id = TenantId::generate();
+        let a = shard(tenant_id, 0, 2);
+        let b = shard(tenant_id, 1, 2);
+        persistence
+            .insert_tenant_shards(vec![a.clone(), b.clone()])
+            .await?;
+
+        assert_eq!(
+            persistence
+                .increment_generation(a.tenant_shard_id, NodeId(1))
+                .await?,
+            1
+        );
+        assert_eq!(
+            persistence
+                .increment_generation(b.tenant_shard_id, NodeId(2))
+                .await?,
+            1
+        );
+
+        // Re-attach only touches the shards attached to the node
+        let reattached = persistence.re_attach(NodeId(1)).await?;
+        assert_eq!(reattached, HashMap::from([(a.tenant_shard_id, 2)]));
+
+        // Detaching keeps the generation: the next attach must not reuse it
+        persistence.detach(a.tenant_shard_id).await?;
+        assert!(persistence.re_attach(NodeId(1)).await?.is_empty());
+        assert_eq!(
+            persistence
+                .increment_generation(a.tenant_shard_id, NodeId(2))
+                .await?,
+            3
+        );
+
+        let missing = TenantShardId::unsharded(TenantId::generate());
+        assert!(persistence
+            .increment_generation(missing, NodeId(1))
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn nodes() -> anyhow::Result<()> {
+        let persistence = Persistence::open_in_memory()?;
+        let mut node = Node {
+            id: NodeId(1),
+            availability: NodeAvailability::Active,
+            listen_http_addr: "localhost".to_string(),
+            listen_http_port: 9898,
+            listen_pg_addr: "localhost".to_string(),
+            listen_pg_port: 64000,
+        };
+        persistence.upsert_node(&node).await?;
+
+        // Re-registering updates in place
+        node.listen_http_port = 9899;
+        persistence.upsert_node(&node).await?;
+
+        persistence
+            .update_node_availability(node.id, NodeAvailability::Offline)
+            .await?;
+        node.availability = NodeAvailability::Offline;
+        assert_eq!(persistence.list_nodes().await?, vec![node]);
+
+        assert!(persistence
+            .update_node_availability(NodeId(2), NodeAvailability::Active)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use pageserver_api::models::{LocationConfig, LocationConfigMode};
+use pageserver_client::mgmt_api;
+use utils::{
+    id::{NodeId, TimelineId},
+    lsn::Lsn,
+};
+
+use crate::{
+    node::Node,
+    persistence::{DatabaseError, Persistence},
+    tenant_state::{IntentState, TenantState},
+};
+
+/// How long to wait for a migration destination to catch up with the origin's LSNs
+/// before cutting over anyway.
+const AWAIT_LSN_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum ReconcileError {
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Drives the pageservers for one tenant shard from its `previous` locations
+/// to the locations in its intent.
+///
+/// The reconciler works on a snapshot of the shard's state, so that no locks are
+/// held while calling out to pageservers: the caller writes back the resulting
+/// generation when it is done.
+pub(crate) struct Reconciler {
+    pub(crate) tenant: TenantState,
+    pub(crate) previous: IntentState,
+    pub(crate) nodes: Arc<HashMap<NodeId, Node>>,
+    pub(crate) persistence: Arc<Persistence>,
+    pub(crate) http_client: reqwest::Client,
+    pub(crate) jwt_token: Option<String>,
+}
+
+impl Reconciler {
+    fn get_node(&self, node_id: NodeId) -> anyhow::Result<&Node> {
+        self.nodes
+            .get(&node_id)
+            .ok_or_else(|| anyhow::anyhow!("node {node_id} is not registered"))
+    }
+
+    fn client(&self, node: &Node) -> mgmt_api::Client {
+        node.client(&self.http_client, self.jwt_token.as_deref())
+    }
+
+    async fn location_config(
+        &self,
+        node_id: NodeId,
+        config: LocationConfig,
+        flush_ms: Option<Duration>,
+    ) -> anyhow::Result<()> {
+        let node = self.get_node(node_id)?;
+        tracing::info!(
+            "location_config({}) on node {}: {:?}",
+            self.tenant.tenant_shard_id,
+            node_id,
+            config.mode
+        );
+        self.client(node)
+            .location_config(self.tenant.tenant_shard_id, config, flush_ms)
+            .await?;
+        Ok(())
+    }
+
+    fn is_available(&self, node_id: NodeId) -> bool {
+        self.nodes
+            .get(&node_id)
+            .map(|n| n.may_schedule())
+            .unwrap_or(false)
+    }
+
+    /// Configure a location we are moving away from, or no longer need.  The node may
+    /// well be dead: failures are logged rather than failing the whole reconciliation,
+    /// as generation numbers protect data from any location we fail to clean up.
+    async fn location_config_best_effort(&self, node_id: NodeId, config: LocationConfig) {
+        if !self.is_available(node_id) {
+            tracing::info!(
+                "Skipping unavailable node {node_id} for {}",
+                self.tenant.tenant_shard_id
+            );
+            return;
+        }
+
+        if let Err(e) = self.location_config(node_id, config, None).await {
+            tracing::warn!(
+                "Failed to configure {} on node {node_id}: {e:#}",
+                self.tenant.tenant_shard_id
+            );
+        }
+    }
+
+    async fn get_lsns(&self, node_id: NodeId) -> anyhow::Result<HashMap<TimelineId, Lsn>> {
+        let node = self.get_node(node_id)?;
+        let timelines = self
+            .client(node)
+            .list_timelines(self.tenant.tenant_shard_id)
+            .await?;
+        Ok(timelines
+            .into_iter()
+            .map(|t| (t.timeline_id, t.last_record_lsn))
+            .collect())
+    }
+
+    /// Wait for the timeline LSNs on `node_id` to catch up with or overtake `baseline`.
+    async fn await_lsn(&self, node_id: NodeId, baseline: HashMap<TimelineId, Lsn>) {
+        let started_at = Instant::now();
+        loop {
+            let caught_up = match self.get_lsns(node_id).await {
+                Ok(latest) => baseline.iter().all(|(timeline_id, baseline_lsn)| {
+                    latest
+                        .get(timeline_id)
+                        .map(|lsn| lsn >= baseline_lsn)
+                        .unwrap_or(false)
+                }),
+                Err(e) => {
+                    tracing::info!("Can't get LSNs on node {node_id} yet, waiting ({e})");
+                    false
+                }
+            };
+
+            if caught_up {
+                tracing::info!("LSNs caught up on node {node_id}");
+                return;
+            }
+
+            if started_at.elapsed() > AWAIT_LSN_TIMEOUT {
+                tracing::warn!(
+                    "Timed out waiting for {} LSNs to catch up on node {node_id}, proceeding",
+                    self.tenant.tenant_shard_id
+                );
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+
+    pub(crate) async fn reconcile(&mut self) -> Result<(), ReconcileError> {
+        let tenant_shard_id = self.tenant.tenant_shard_id;
+        let intent = self.tenant.intent.clone();
+
+        // The previous attached location, if we are moving away from it
+        let origin = self
+            .previous
+            .attached
+            .filter(|o| Some(*o) != intent.attached);
+
+        match intent.attached {
+            Some(dest) if self.previous.attached != Some(dest) => {
+                // If the origin is still up, it keeps serving reads in stale mode (no
+                // deletions or uploads) while the destination warms up.  An origin that
+                // has failed is fenced by the generation we issue below.
+                let live_origin = origin.filter(|o| self.is_available(*o));
+                let mut baseline = None;
+                if let Some(origin) = live_origin {
+                    let stale_conf = self.tenant.attached_location_config(
+                        LocationConfigMode::AttachedStale,
+                        self.tenant.generation,
+                    );
+                    match self
+                        .location_config(origin, stale_conf, Some(Duration::from_secs(10)))
+                        .await
+                    {
+                        Ok(()) => match self.get_lsns(origin).await {
+                            Ok(lsns) => baseline = Some(lsns),
+                            Err(e) => tracing::warn!(
+                                "Failed to read LSNs from origin node {origin}: {e:#}"
+                            ),
+                        },
+                        Err(e) => tracing::warn!(
+                            "Failed to set origin node {origin} to stale, proceeding: {e:#}"
+                        ),
+                    }
+                }
+
+                let generation = self
+                    .persistence
+                    .increment_generation(tenant_shard_id, dest)
+                    .await?;
+                self.tenant.generation = generation;
+
+                let mode = if live_origin.is_some() {
+                    LocationConfigMode::AttachedMulti
+                } else {
+                    LocationConfigMode::AttachedSingle
+                };
+                self.location_config(
+                    dest,
+                    self.tenant.attached_location_config(mode, generation),
+                    None,
+                )
+                .await?;
+
+                if let Some(baseline) = baseline {
+                    self.await_lsn(dest, baseline).await;
+                }
+
+                if let Some(origin) = origin {
+                    let origin_conf = if intent.secondary.contains(&origin) {
+                        self.tenant.secondary_location_config()
+                    } else {
+                        self.tenant.detached_location_config()
+                    };
+                    self.location_config_best_effort(origin, origin_conf).await;
+                }
+
+                if live_origin.is_some() {
+                    self.location_config(
+                        dest,
+                        self.tenant.attached_location_config(
+                            LocationConfigMode::AttachedSingle,
+                            generation,
+                        ),
+                        None,
+                    )
+                    .await?;
+                }
+            }
+            Some(_) => {
+                // Attached location is unchanged: nothing to do.
+            }
+            None => {
+                if self.previous.attached.is_some() {
+                    self.persistence.detach(tenant_shard_id).await?;
+                }
+            }
+        }
+
+        for node_id in &intent.secondary {
+            if Some(*node_id) == origin || self.previous.secondary.contains(node_id) {
+                continue;
+            }
+            self.location_config(*node_id, self.tenant.secondary_location_config(), None)
+                .await?;
+        }
+
+        let wanted = intent.all_pageservers();
+        for node_id in self.previous.all_pageservers() {
+            if wanted.contains(&node_id) || Some(node_id) == origin {
+                continue;
+            }
+            self.location_config_best_effort(node_id, self.tenant.detached_location_config())
+                .await;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use utils::id::NodeId;
+
+use crate::{node::Node, tenant_state::TenantState};
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum ScheduleError {
+    #[error("No pageservers available")]
+    NoPageservers,
+    #[error("No pageserver found matching constraint")]
+    ImpossibleConstraint,
+}
+
+/// Places tenant shard locations on pageservers, spreading them evenly.
+///
+/// A scheduler is built from a snapshot of the service's state for the duration of
+/// one operation, and its counts are updated as it places locations.
+pub(crate) struct Scheduler {
+    /// Number of tenant shard locations (attached or secondary) on each node
+    /// that may have work scheduled onto it.
+    shard_counts: HashMap<NodeId, usize>,
+}
+
+impl Scheduler {
+    pub(crate) fn new<'a>(
+        tenants: impl Iterator<Item = &'a TenantState>,
+        nodes: &HashMap<NodeId, Node>,
+    ) -> Self {
+        let mut shard_counts: HashMap<NodeId, usize> = nodes
+            .values()
+            .filter(|n| n.may_schedule())
+            .map(|n| (n.id, 0))
+            .collect();
+
+        for tenant in tenants {
+            for node_id in tenant.intent.all_pageservers() {
+                if let Some(count) = shard_counts.get_mut(&node_id) {
+                    *count += 1;
+                }
+            }
+        }
+
+        Self { shard_counts }
+    }
+
+    /// Pick the least loaded node that is not in `hard_exclude`: ties are broken by node ID,
+    /// so that scheduling is deterministic.
+    pub(crate) fn schedule_shard(
+        &mut self,
+        hard_exclude: &[NodeId],
+    ) -> Result<NodeId, ScheduleError> {
+        if self.shard_counts.is_empty() {
+            return Err(ScheduleError::NoPageservers);
+        }
+
+        let node_id = self
+            .shard_counts
+            .iter()
+            .filter(|(node_id, _)| !hard_exclude.contains(node_id))
+            .min_by_key(|(node_id, count)| (**count, **node_id))
+            .map(|(node_id, _)| *node_id)
+            .ok_or(ScheduleError::ImpossibleConstraint)?;
+
+        *self.shard_counts.get_mut(&node_id).unwrap() += 1;
+        tracing::info!("scheduler selected node {node_id}");
+        Ok(node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use control_plane::attachment_service::NodeAvailability;
+
+    fn node(id: u64, availability: NodeAvailability) -> (NodeId, Node) {
+        (
+            NodeId(id),
+            Node {
+                id: NodeId(id),
+                availability,
+                listen_http_addr: "localhost".to_string(),
+                listen_http_port: 9898 + id as u16,
+                listen_pg_addr: "localhost".to_string(),
+                listen_pg_port: 64000 + id as u16,
+            },
+        )
+    }
+
+    #[test]
+    fn spreads_and_excludes() {
+        let nodes = HashMap::from([
+            node(1, NodeAvailability::Active),
+            node(2, NodeAvailability::Active),
+            node(3, NodeAvailability::Offline),
+        ]);
+        let mut scheduler = Scheduler::new(std::iter::empty(), &nodes);
+
+        assert_eq!(scheduler.schedule_shard(&[]).unwrap(), NodeId(1));
+        assert_eq!(scheduler.schedule_shard(&[]).unwrap(), NodeId(2));
+        assert_eq!(scheduler.schedule_shard(&[NodeId(1)]).unwrap(), NodeId(2));
+        assert_eq!(scheduler.schedule_shard(&[]).unwrap(), NodeId(1));
+
+        // Offline nodes are never candidates
+        assert!(matches!(
+            scheduler.schedule_shard(&[NodeId(1), NodeId(2)]),
+            Err(ScheduleError::ImpossibleConstraint)
+        ));
+
+        let mut empty = Scheduler::new(std::iter::empty(), &HashMap::new());
+        assert!(matches!(
+            empty.schedule_shard(&[]),
+            Err(ScheduleError::NoPageservers)
+        ));
+    }
+}
@@ -0,0 +1,1237 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use control_plane::attachment_service::{
+    AttachHookRequest, AttachHookResponse, InspectRequest, InspectResponse, NodeAvailability,
+    NodeConfigureRequest, NodeDescribeResponse, NodeRegisterRequest, PlacementPolicy,
+    TenantCreateRequest, TenantCreateResponse, TenantCreateResponseShard, TenantLocateResponse,
+    TenantLocateResponseShard, TenantShardMigrateRequest,
+};
+use pageserver_api::{
+    control_api::{
+        ReAttachRequest, ReAttachResponse, ReAttachResponseTenant, ValidateRequest,
+        ValidateResponse, ValidateResponseTenant,
+    },
+    models::{TenantConfig, TenantShardSplitRequest, TenantShardSplitResponse},
+    shard::{ShardCount, ShardIdentity, ShardNumber, TenantShardId, DEFAULT_STRIPE_SIZE},
+};
+use pageserver_client::mgmt_api;
+use reqwest::StatusCode;
+use utils::{
+    backoff::{
+        exponential_backoff_duration_seconds, DEFAULT_BASE_BACKOFF_SECONDS,
+        DEFAULT_MAX_BACKOFF_SECONDS,
+    },
+    http::error::ApiError,
+    id::{NodeId, TenantId},
+};
+
+use crate::{
+    compute_hook::ComputeHook,
+    node::Node,
+    persistence::{DatabaseError, Persistence, TenantShardPersistence},
+    reconciler::{ReconcileError, Reconciler},
+    scheduler::{ScheduleError, Scheduler},
+    tenant_state::{IntentState, TenantState},
+};
+
+/// How many node availability transitions may wait for [`Service::availability_loop`]: when
+/// the queue is full, the heartbeat loop requests the transition again on its next round.
+const AVAILABILITY_QUEUE_DEPTH: usize = 64;
+
+/// How many times to try telling the computes of a tenant where its shards are, see
+/// [`Service::notify_computes`].
+const COMPUTE_NOTIFY_ATTEMPTS: u32 = 5;
+
+pub struct Config {
+    /// JWT token for authenticating to pageservers, if they require it
+    pub jwt_token: Option<String>,
+
+    /// How often to poll each pageserver's status endpoint
+    pub heartbeat_interval: Duration,
+
+    /// How long a pageserver may fail heartbeats before it is marked offline, and its
+    /// tenants are moved elsewhere
+    pub max_unavailable_interval: Duration,
+
+    /// The `neon_local` repository whose endpoints to reconfigure when their tenant's shards
+    /// move, see [`ComputeHook`]
+    pub neon_local_repo_dir: Option<PathBuf>,
+}
+
+// Top level state available to all HTTP handlers
+struct ServiceState {
+    tenants: BTreeMap<TenantShardId, TenantState>,
+
+    // Replaced as a whole on change, so that reconcilers can work from a snapshot
+    nodes: Arc<HashMap<NodeId, Node>>,
+
+    // Tenants with a shard split in progress, and the shard count they are split to
+    splits: HashMap<TenantId, ShardCount>,
+}
+
+pub struct Service {
+    inner: Arc<RwLock<ServiceState>>,
+    config: Config,
+    persistence: Arc<Persistence>,
+
+    /// Shared by the management API clients for all pageservers
+    http_client: reqwest::Client,
+
+    /// For heartbeats, which time out after one heartbeat interval
+    heartbeat_client: reqwest::Client,
+
+    compute_hook: ComputeHook,
+
+    /// Operations that change where tenants are placed (create, migrate, delete, attach
+    /// hook) are serialized, so that their calls to pageservers cannot interleave.
+    placement_lock: tokio::sync::Mutex<()>,
+}
+
+impl From<DatabaseError> for ApiError {
+    fn from(err: DatabaseError) -> ApiError {
+        ApiError::InternalServerError(anyhow!("Database error: {err}"))
+    }
+}
+
+impl From<ScheduleError> for ApiError {
+    fn from(err: ScheduleError) -> ApiError {
+        ApiError::Conflict(format!("Scheduling error: {err}"))
+    }
+}
+
+impl From<ReconcileError> for ApiError {
+    fn from(err: ReconcileError) -> ApiError {
+        ApiError::InternalServerError(anyhow!("Reconcile error: {err:#}"))
+    }
+}
+
+impl Service {
+    /// Load the service's state from the database, and start heartbeating its pageservers.
+    pub async fn spawn(config: Config, persistence: Arc<Persistence>) -> anyhow::Result<Arc<Self>> {
+        let nodes = persistence
+            .list_nodes()
+            .await?
+            .into_iter()
+            .map(|n| (n.id, n))
+            .collect::<HashMap<_, _>>();
+
+        let tenants = persistence
+            .list_tenant_shards()
+            .await?
+            .into_iter()
+            .map(|p| TenantState::from_persistent(p).map(|t| (t.tenant_shard_id, t)))
+            .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
+        let splits = persistence.list_shard_splits().await?;
+
+        tracing::info!(
+            "Loaded {} nodes and {} tenant shards",
+            nodes.len(),
+            tenants.len()
+        );
+        for (tenant_id, new_shard_count) in &splits {
+            tracing::warn!(
+                "Tenant {tenant_id} has an unfinished split to {} shards: retry it to complete",
+                new_shard_count.0
+            );
+        }
+
+        let this = Arc::new(Self {
+            inner: Arc::new(RwLock::new(ServiceState {
+                tenants,
+                nodes: Arc::new(nodes),
+                splits,
+            })),
+            http_client: reqwest::Client::new(),
+            heartbeat_client: reqwest::Client::builder()
+                .timeout(config.heartbeat_interval)
+                .build()?,
+            compute_hook: ComputeHook::new(config.neon_local_repo_dir.clone()),
+            config,
+            persistence,
+            placement_lock: tokio::sync::Mutex::new(()),
+        });
+
+        let (availability_tx, availability_rx) =
+            tokio::sync::mpsc::channel(AVAILABILITY_QUEUE_DEPTH);
+        tokio::task::spawn(this.clone().heartbeat_loop(availability_tx));
+        tokio::task::spawn(this.clone().availability_loop(availability_rx));
+
+        Ok(this)
+    }
+
+    fn client(&self, node: &Node) -> mgmt_api::Client {
+        node.client(&self.http_client, self.config.jwt_token.as_deref())
+    }
+
+    /// Poll every registered pageserver, marking nodes offline once they have been
+    /// unresponsive for `max_unavailable_interval`, and active again when they respond.
+    ///
+    /// Bringing a node online or offline reconciles its tenants, which takes a while: the
+    /// transitions are applied by [`Self::availability_loop`], so that the heartbeats of the
+    /// other nodes carry on meanwhile.  They are requested again on every round until they
+    /// are applied.
+    async fn heartbeat_loop(
+        self: Arc<Self>,
+        availability_tx: tokio::sync::mpsc::Sender<(NodeId, NodeAvailability)>,
+    ) {
+        // When each node last responded.  A node we have never heard from gets its
+        // grace period from when we first tried it.
+        let mut last_seen: HashMap<NodeId, Instant> = HashMap::new();
+        // Requested transitions that are not applied yet, to log them once
+        let mut requested: HashMap<NodeId, NodeAvailability> = HashMap::new();
+
+        let mut interval = tokio::time::interval(self.config.heartbeat_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+
+            let nodes = self.inner.read().unwrap().nodes.clone();
+            let http_client = &self.heartbeat_client;
+            let jwt = self.config.jwt_token.as_deref();
+            let results =
+                futures::future::join_all(nodes.values().map(|node| async move {
+                    (node, node.client(http_client, jwt).status().await)
+                }))
+                .await;
+
+            let now = Instant::now();
+            for (node, result) in results {
+                let transition = match result {
+                    Ok(_) => {
+                        last_seen.insert(node.id, now);
+                        (node.availability == NodeAvailability::Offline)
+                            .then_some(NodeAvailability::Active)
+                    }
+                    Err(e) => {
+                        let seen = *last_seen.entry(node.id).or_insert(now);
+                        let unavailable_for = now.duration_since(seen);
+                        let offline = node.availability == NodeAvailability::Active
+                            && unavailable_for > self.config.max_unavailable_interval;
+                        if offline && requested.get(&node.id) != Some(&NodeAvailability::Offline) {
+                            tracing::warn!(
+                                "Node {} unresponsive for {unavailable_for:?}, marking offline: {e:#}",
+                                node.id
+                            );
+                        }
+                        offline.then_some(NodeAvailability::Offline)
+                    }
+                };
+
+                let Some(availability) = transition else {
+                    requested.remove(&node.id);
+                    continue;
+                };
+                if availability == NodeAvailability::Active
+                    && requested.get(&node.id) != Some(&availability)
+                {
+                    tracing::info!("Node {} is responding again", node.id);
+                }
+                requested.insert(node.id, availability);
+                match availability_tx.try_send((node.id, availability)) {
+                    Ok(()) => {}
+                    Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
+                        tracing::info!("Availability transitions are queued up, retrying later");
+                    }
+                    Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => {
+                        tracing::error!("Availability loop has exited");
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Apply the node availability transitions requested by [`Self::heartbeat_loop`], one at a
+    /// time.  Transitions that were already applied, e.g. requested again while an earlier
+    /// request was in progress, are skipped.
+    async fn availability_loop(
+        self: Arc<Self>,
+        mut availability_rx: tokio::sync::mpsc::Receiver<(NodeId, NodeAvailability)>,
+    ) {
+        while let Some((node_id, availability)) = availability_rx.recv().await {
+            let current = self
+                .inner
+                .read()
+                .unwrap()
+                .nodes
+                .get(&node_id)
+                .map(|node| node.availability);
+            if current.map_or(true, |current| current == availability) {
+                continue;
+            }
+            let result = match availability {
+                NodeAvailability::Active => self.node_online(node_id).await,
+                NodeAvailability::Offline => self.node_offline(node_id).await,
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to mark node {node_id} {availability:?}: {e}");
+            }
+        }
+    }
+
+    /// Mark a node offline, and move the tenant shard locations it holds to other nodes,
+    /// promoting an existing secondary location where a shard has one.  The node is fenced
+    /// by the new generations this issues: if it is still running, `/validate` refuses
+    /// its deletions from then on.  The computes of the tenants whose shards were moved are
+    /// pointed at the new locations.
+    async fn node_offline(&self, node_id: NodeId) -> Result<(), ApiError> {
+        self.set_node_availability(node_id, NodeAvailability::Offline)
+            .await?;
+
+        let _guard = self.placement_lock.lock().await;
+
+        let mut moves = Vec::new();
+        {
+            let mut locked = self.inner.write().unwrap();
+            let ServiceState { tenants, nodes, .. } = &mut *locked;
+            let mut scheduler = Scheduler::new(tenants.values(), nodes);
+
+            for tenant in tenants.values_mut() {
+                if matches!(tenant.policy, PlacementPolicy::External)
+                    || !tenant.intent.all_pageservers().contains(&node_id)
+                {
+                    continue;
+                }
+
+                let previous = tenant.intent.clone();
+                tenant.intent.secondary.retain(|n| *n != node_id);
+                if tenant.intent.attached == Some(node_id) {
+                    let promote = tenant
+                        .intent
+                        .secondary
+                        .iter()
+                        .position(|n| nodes.get(n).map(|n| n.may_schedule()).unwrap_or(false));
+                    tenant.intent.attached = promote.map(|i| tenant.intent.secondary.remove(i));
+                }
+
+                if let Err(e) = tenant.schedule(&mut scheduler) {
+                    // Leave the shard where it is: it comes back if the node does.
+                    tracing::warn!(
+                        "Can't reschedule {} away from node {node_id}: {e}",
+                        tenant.tenant_shard_id
+                    );
+                    tenant.intent = previous;
+                    continue;
+                }
+
+                tracing::info!(
+                    "Rescheduling {} away from node {node_id}: {:?} -> {:?}",
+                    tenant.tenant_shard_id,
+                    previous.attached,
+                    tenant.intent.attached
+                );
+                moves.push((tenant.clone(), previous));
+            }
+        }
+
+        let mut moved_tenants = BTreeSet::new();
+        for (tenant, previous) in moves {
+            let tenant_shard_id = tenant.tenant_shard_id;
+            let moved = tenant.intent.attached != previous.attached;
+            if tenant.intent.secondary != previous.secondary {
+                self.persistence
+                    .set_secondaries(tenant_shard_id, tenant.intent.secondary.clone())
+                    .await?;
+            }
+            match self.reconcile(tenant, previous).await {
+                Ok(_) if moved => {
+                    moved_tenants.insert(tenant_shard_id.tenant_id);
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Failed to reconcile {tenant_shard_id}: {e:#}"),
+            }
+        }
+
+        for tenant_id in moved_tenants {
+            if let Err(e) = self.notify_computes(tenant_id).await {
+                tracing::warn!("Failed to notify computes of {tenant_id} after failover: {e}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mark a node active again, and detach any locations it still holds for tenant
+    /// shards that were moved away while it was offline.
+    async fn node_online(&self, node_id: NodeId) -> Result<(), ApiError> {
+        self.set_node_availability(node_id, NodeAvailability::Active)
+            .await?;
+
+        let _guard = self.placement_lock.lock().await;
+
+        let Some(node) = self.inner.read().unwrap().nodes.get(&node_id).cloned() else {
+            return Ok(());
+        };
+        let tenants = self
+            .client(&node)
+            .list_tenants()
+            .await
+            .map_err(|e| ApiError::InternalServerError(e.into()))?;
+
+        for tenant_info in tenants {
+            let stale_location = self
+                .inner
+                .read()
+                .unwrap()
+                .tenants
+                .get(&tenant_info.id)
+                .filter(|t| !t.intent.all_pageservers().contains(&node_id))
+                .map(|t| t.detached_location_config());
+            if let Some(config) = stale_location {
+                tracing::info!(
+                    "Detaching stale location {} on node {node_id}",
+                    tenant_info.id
+                );
+                if let Err(e) = self
+                    .client(&node)
+                    .location_config(tenant_info.id, config, None)
+                    .await
+                {
+                    tracing::warn!(
+                        "Failed to detach {} from node {node_id}: {e:#}",
+                        tenant_info.id
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a reconciler for one tenant shard, and write back the generation it issued.
+    async fn reconcile(
+        &self,
+        tenant: TenantState,
+        previous: IntentState,
+    ) -> Result<u32, ReconcileError> {
+        let nodes = self.inner.read().unwrap().nodes.clone();
+        let mut reconciler = Reconciler {
+            tenant,
+            previous,
+            nodes,
+            persistence: self.persistence.clone(),
+            http_client: self.http_client.clone(),
+            jwt_token: self.config.jwt_token.clone(),
+        };
+        let result = reconciler.reconcile().await;
+
+        // Even if reconciliation failed part way, it may have issued a new generation
+        let tenant_shard_id = reconciler.tenant.tenant_shard_id;
+        let generation = reconciler.tenant.generation;
+        if let Some(tenant) = self
+            .inner
+            .write()
+            .unwrap()
+            .tenants
+            .get_mut(&tenant_shard_id)
+        {
+            tenant.generation = generation;
+        }
+
+        result.map(|()| generation)
+    }
+
+    /// Pageserver calls into this on startup, to learn which tenants it should attach
+    pub async fn re_attach(
+        &self,
+        reattach_req: ReAttachRequest,
+    ) -> Result<ReAttachResponse, ApiError> {
+        let node_id = reattach_req.node_id;
+
+        // A pageserver that is re-attaching is alive
+        let was_offline = self
+            .inner
+            .read()
+            .unwrap()
+            .nodes
+            .get(&node_id)
+            .map(|n| n.availability == NodeAvailability::Offline)
+            .unwrap_or(false);
+        if was_offline {
+            self.set_node_availability(node_id, NodeAvailability::Active)
+                .await?;
+        }
+
+        // Reconcilers issue generations under the placement lock: hold it too, so that the
+        // in-memory generations are written back in the order the database issued them.
+        let _guard = self.placement_lock.lock().await;
+        let generations = self.persistence.re_attach(node_id).await?;
+
+        let mut locked = self.inner.write().unwrap();
+        let mut response = ReAttachResponse {
+            tenants: Vec::new(),
+        };
+        for (tenant_shard_id, generation) in generations {
+            if let Some(tenant) = locked.tenants.get_mut(&tenant_shard_id) {
+                tenant.generation = std::cmp::max(tenant.generation, generation);
+            }
+            response.tenants.push(ReAttachResponseTenant {
+                id: tenant_shard_id,
+                gen: generation,
+            });
+        }
+
+        Ok(response)
+    }
+
+    /// Pageserver calls into this before doing deletions, to confirm that it still
+    /// holds the latest generation for the tenants with deletions enqueued
+    pub fn validate(&self, validate_req: ValidateRequest) -> ValidateResponse {
+        let locked = self.inner.read().unwrap();
+
+        let mut response = ValidateResponse {
+            tenants: Vec::new(),
+        };
+
+        for req_tenant in validate_req.tenants {
+            if let Some(tenant_state) = locked.tenants.get(&req_tenant.id) {
+                let valid = tenant_state.generation == req_tenant.gen;
+                tracing::info!(
+                    "handle_validate: {}(gen {}): valid={valid} (latest {})",
+                    req_tenant.id,
+                    req_tenant.gen,
+                    tenant_state.generation
+                );
+                response.tenants.push(ValidateResponseTenant {
+                    id: req_tenant.id,
+                    valid,
+                });
+            }
+        }
+
+        response
+    }
+
+    /// The shard that the attach hook and inspect APIs act on: these predate sharding,
+    /// and only work for tenants with a single shard.
+    fn single_shard_of(
+        tenants: &BTreeMap<TenantShardId, TenantState>,
+        tenant_id: TenantId,
+    ) -> Result<Option<TenantShardId>, ApiError> {
+        let mut shards = tenants.range(TenantShardId::tenant_range(tenant_id));
+        match (shards.next(), shards.next()) {
+            (None, _) => Ok(None),
+            (Some((id, _)), None) => Ok(Some(*id)),
+            (Some(_), Some(_)) => Err(ApiError::BadRequest(anyhow!(
+                "Tenant {tenant_id} has multiple shards"
+            ))),
+        }
+    }
+
+    /// Call into this before attaching a tenant to a pageserver, to acquire a generation number
+    /// (in the real control plane this is unnecessary, because the same program is managing
+    ///  generation numbers and doing attachments).
+    pub async fn attach_hook(
+        &self,
+        attach_req: AttachHookRequest,
+    ) -> Result<AttachHookResponse, ApiError> {
+        let _guard = self.placement_lock.lock().await;
+
+        let existing =
+            Self::single_shard_of(&self.inner.read().unwrap().tenants, attach_req.tenant_id)?;
+        let tenant_shard_id = match existing {
+            Some(id) => id,
+            None if attach_req.node_id.is_none() => {
+                tracing::info!(
+                    tenant_id = %attach_req.tenant_id,
+                    "no-op: tenant already has no pageserver");
+                return Ok(AttachHookResponse { gen: None });
+            }
+            None => {
+                // Tenants created outside the service's tenant API are unsharded, as
+                // the pageserver creates them when no shard parameters are given.
+                let tenant_shard_id = TenantShardId::unsharded(attach_req.tenant_id);
+                let tenant = TenantState::new(
+                    tenant_shard_id,
+                    ShardIdentity::unsharded(),
+                    PlacementPolicy::External,
+                    TenantConfig::default(),
+                );
+                self.persistence
+                    .insert_tenant_shards(vec![tenant.to_persistent()])
+                    .await?;
+                self.inner
+                    .write()
+                    .unwrap()
+                    .tenants
+                    .insert(tenant_shard_id, tenant);
+                tenant_shard_id
+            }
+        };
+
+        let generation = match attach_req.node_id {
+            Some(node_id) => {
+                let generation = self
+                    .persistence
+                    .increment_generation(tenant_shard_id, node_id)
+                    .await?;
+                tracing::info!(
+                    tenant_id = %attach_req.tenant_id,
+                    ps_id = %node_id,
+                    generation = %generation,
+                    "issuing",
+                );
+                Some(generation)
+            }
+            None => {
+                self.persistence.detach(tenant_shard_id).await?;
+                tracing::info!(
+                    tenant_id = %attach_req.tenant_id,
+                    "dropping",
+                );
+                None
+            }
+        };
+
+        let mut locked = self.inner.write().unwrap();
+        if let Some(tenant) = locked.tenants.get_mut(&tenant_shard_id) {
+            if let Some(generation) = generation {
+                tenant.generation = generation;
+            }
+            tenant.intent.attached = attach_req.node_id;
+            tenant
+                .intent
+                .secondary
+                .retain(|n| Some(*n) != attach_req.node_id);
+        }
+
+        Ok(AttachHookResponse { gen: generation })
+    }
+
+    pub fn inspect(&self, inspect_req: InspectRequest) -> Result<InspectResponse, ApiError> {
+        let locked = self.inner.read().unwrap();
+        let attachment = Self::single_shard_of(&locked.tenants, inspect_req.tenant_id)?
+            .and_then(|id| locked.tenants.get(&id))
+            .and_then(|t| t.intent.attached.map(|ps| (t.generation, ps)));
+
+        Ok(InspectResponse { attachment })
+    }
+
+    pub async fn node_register(&self, register_req: NodeRegisterRequest) -> Result<(), ApiError> {
+        let node = Node {
+            id: register_req.node_id,
+            availability: NodeAvailability::Active,
+            listen_http_addr: register_req.listen_http_addr,
+            listen_http_port: register_req.listen_http_port,
+            listen_pg_addr: register_req.listen_pg_addr,
+            listen_pg_port: register_req.listen_pg_port,
+        };
+
+        self.persistence.upsert_node(&node).await?;
+
+        let mut locked = self.inner.write().unwrap();
+        let mut nodes = (*locked.nodes).clone();
+        tracing::info!("Registered node {}", node.id);
+        nodes.insert(node.id, node);
+        locked.nodes = Arc::new(nodes);
+
+        Ok(())
+    }
+
+    async fn set_node_availability(
+        &self,
+        node_id: NodeId,
+        availability: NodeAvailability,
+    ) -> Result<(), ApiError> {
+        self.persistence
+            .update_node_availability(node_id, availability)
+            .await?;
+
+        let mut locked = self.inner.write().unwrap();
+        let mut nodes = (*locked.nodes).clone();
+        if let Some(node) = nodes.get_mut(&node_id) {
+            tracing::info!("Node {node_id} availability: {availability:?}");
+            node.availability = availability;
+        }
+        locked.nodes = Arc::new(nodes);
+
+        Ok(())
+    }
+
+    pub async fn node_configure(&self, config_req: NodeConfigureRequest) -> Result<(), ApiError> {
+        if !self
+            .inner
+            .read()
+            .unwrap()
+            .nodes
+            .contains_key(&config_req.node_id)
+        {
+            return Err(ApiError::NotFound(
+                anyhow!("Node {} not registered", config_req.node_id).into(),
+            ));
+        }
+
+        match config_req.availability {
+            Some(NodeAvailability::Offline) => self.node_offline(config_req.node_id).await?,
+            Some(NodeAvailability::Active) => self.node_online(config_req.node_id).await?,
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn node_list(&self) -> Vec<NodeDescribeResponse> {
+        let locked = self.inner.read().unwrap();
+        let mut nodes = locked
+            .nodes
+            .values()
+            .map(|n| n.describe())
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|n| n.node_id);
+        nodes
+    }
+
+    pub async fn tenant_create(
+        &self,
+        create_req: TenantCreateRequest,
+    ) -> Result<TenantCreateResponse, ApiError> {
+        let _guard = self.placement_lock.lock().await;
+        let tenant_id = create_req.new_tenant_id;
+
+        let mut shards = Vec::new();
+        if create_req.shard_count.0 == 0 {
+            shards.push(TenantState::new(
+                TenantShardId::unsharded(tenant_id),
+                ShardIdentity::unsharded(),
+                create_req.placement_policy.clone(),
+                create_req.config.clone(),
+            ));
+        } else {
+            let stripe_size = create_req.shard_stripe_size.unwrap_or(DEFAULT_STRIPE_SIZE);
+            for i in 0..create_req.shard_count.0 {
+                let shard_number = ShardNumber(i);
+                let shard = ShardIdentity::new(shard_number, create_req.shard_count, stripe_size)
+                    .map_err(|e| ApiError::BadRequest(e.into()))?;
+                shards.push(TenantState::new(
+                    TenantShardId {
+                        tenant_id,
+                        shard_number,
+                        shard_count: create_req.shard_count,
+                    },
+                    shard,
+                    create_req.placement_policy.clone(),
+                    create_req.config.clone(),
+                ));
+            }
+        }
+
+        {
+            let locked = self.inner.read().unwrap();
+            if locked
+                .tenants
+                .range(TenantShardId::tenant_range(tenant_id))
+                .next()
+                .is_some()
+            {
+                return Err(ApiError::Conflict(format!(
+                    "Tenant {tenant_id} already exists"
+                )));
+            }
+
+            let mut scheduler = Scheduler::new(locked.tenants.values(), &locked.nodes);
+            for shard in &mut shards {
+                shard.schedule(&mut scheduler)?;
+            }
+        }
+
+        // Persist before issuing any generations: the attached pageserver is only
+        // recorded once a generation has actually been issued to it.
+        self.persistence
+            .insert_tenant_shards(
+                shards
+                    .iter()
+                    .map(|s| TenantShardPersistence {
+                        generation_pageserver: None,
+                        ..s.to_persistent()
+                    })
+                    .collect(),
+            )
+            .await?;
+
+        {
+            let mut locked = self.inner.write().unwrap();
+            for shard in &shards {
+                locked.tenants.insert(shard.tenant_shard_id, shard.clone());
+            }
+        }
+
+        let mut response = TenantCreateResponse { shards: Vec::new() };
+        for shard in shards {
+            let tenant_shard_id = shard.tenant_shard_id;
+            let node_id = shard.intent.attached;
+            let generation = self.reconcile(shard, IntentState::default()).await?;
+            if let Some(node_id) = node_id {
+                response.shards.push(TenantCreateResponseShard {
+                    tenant_shard_id,
+                    node_id,
+                    generation,
+                });
+            }
+        }
+
+        Ok(response)
+    }
+
+    pub async fn tenant_delete(&self, tenant_id: TenantId) -> Result<(), ApiError> {
+        let _guard = self.placement_lock.lock().await;
+
+        let (shards, nodes) = {
+            let locked = self.inner.read().unwrap();
+            let shards = locked
+                .tenants
+                .range(TenantShardId::tenant_range(tenant_id))
+                .map(|(_, t)| t.clone())
+                .collect::<Vec<_>>();
+            (shards, locked.nodes.clone())
+        };
+
+        if shards.is_empty() {
+            return Err(ApiError::NotFound(
+                anyhow!("Tenant {tenant_id} not found").into(),
+            ));
+        }
+
+        for shard in &shards {
+            // Deletion of the attached location removes the tenant's data from remote storage:
+            // secondaries only hold local copies, and are simply detached.
+            if let Some(node) = shard.intent.attached.and_then(|n| nodes.get(&n)) {
+                match self.client(node).tenant_delete(shard.tenant_shard_id).await {
+                    // A shard that is already gone counts as deleted
+                    Ok(()) | Err(mgmt_api::Error::ApiError(StatusCode::NOT_FOUND, _)) => {}
+                    Err(e) => return Err(ApiError::InternalServerError(e.into())),
+                }
+            }
+            for node in shard.intent.secondary.iter().filter_map(|n| nodes.get(n)) {
+                if let Err(e) = self
+                    .client(node)
+                    .location_config(
+                        shard.tenant_shard_id,
+                        shard.detached_location_config(),
+                        None,
+                    )
+                    .await
+                {
+                    tracing::warn!(
+                        "Failed to detach {} from node {}: {e:#}",
+                        shard.tenant_shard_id,
+                        node.id
+                    );
+                }
+            }
+        }
+
+        self.persistence.delete_tenant(tenant_id).await?;
+
+        let mut locked = self.inner.write().unwrap();
+        for shard in shards {
+            locked.tenants.remove(&shard.tenant_shard_id);
+        }
+        locked.splits.remove(&tenant_id);
+
+        Ok(())
+    }
+
+    pub async fn tenant_shard_migrate(
+        &self,
+        migrate_req: TenantShardMigrateRequest,
+    ) -> Result<(), ApiError> {
+        let _guard = self.placement_lock.lock().await;
+        let tenant_shard_id = migrate_req.tenant_shard_id;
+        let node_id = migrate_req.node_id;
+
+        let (tenant, previous) = {
+            let mut locked = self.inner.write().unwrap();
+
+            match locked.nodes.get(&node_id) {
+                None => {
+                    return Err(ApiError::BadRequest(anyhow!(
+                        "Node {node_id} not registered"
+                    )))
+                }
+                Some(node) if !node.may_schedule() => {
+                    return Err(ApiError::Conflict(format!("Node {node_id} is not active")))
+                }
+                Some(_) => {}
+            }
+
+            let Some(tenant) = locked.tenants.get_mut(&tenant_shard_id) else {
+                return Err(ApiError::NotFound(
+                    anyhow!("Tenant shard {tenant_shard_id} not found").into(),
+                ));
+            };
+
+            if tenant.intent.attached == Some(node_id) {
+                tracing::info!("Migrating {tenant_shard_id}: already attached to {node_id}");
+                return Ok(());
+            }
+
+            let previous = tenant.intent.clone();
+            match tenant.policy {
+                PlacementPolicy::Detached => {
+                    return Err(ApiError::Conflict(format!(
+                        "Tenant shard {tenant_shard_id} is detached"
+                    )))
+                }
+                PlacementPolicy::Single | PlacementPolicy::External => {
+                    tenant.intent.secondary.clear();
+                }
+                PlacementPolicy::Double(secondary_count) => {
+                    // The previous attached location takes the place of the secondary
+                    // location we are promoting, if any.
+                    tenant.intent.secondary.retain(|n| *n != node_id);
+                    if let Some(origin) = previous.attached {
+                        if tenant.intent.secondary.len() < secondary_count {
+                            tenant.intent.secondary.push(origin);
+                        }
+                    }
+                }
+            }
+            tenant.intent.attached = Some(node_id);
+
+            (tenant.clone(), previous)
+        };
+
+        if tenant.intent.secondary != previous.secondary {
+            self.persistence
+                .set_secondaries(tenant_shard_id, tenant.intent.secondary.clone())
+                .await?;
+        }
+
+        tracing::info!(
+            "Migrating {tenant_shard_id}: {:?} -> {node_id}",
+            previous.attached
+        );
+        self.reconcile(tenant, previous).await?;
+
+        Ok(())
+    }
+
+    /// Split every shard of a tenant into shards with a higher shard count.  Each shard is
+    /// split on the pageserver where it is attached, and its children stay there, in the
+    /// same generation: the children may be migrated afterwards like any other shard.
+    ///
+    /// The split is recorded before the first shard is split, and only forgotten once the
+    /// tenant's computes were switched over to the children.  A split that fails part way
+    /// leaves the tenant with shards of both counts, which can't be located: retrying it
+    /// with the same shard count splits the remaining shards, and notifies the computes.
+    pub async fn tenant_shard_split(
+        &self,
+        tenant_id: TenantId,
+        split_req: TenantShardSplitRequest,
+    ) -> Result<TenantShardSplitResponse, ApiError> {
+        let _guard = self.placement_lock.lock().await;
+        let new_shard_count = ShardCount(split_req.new_shard_count);
+
+        let (shards, pending, nodes) = {
+            let locked = self.inner.read().unwrap();
+            let shards = locked
+                .tenants
+                .range(TenantShardId::tenant_range(tenant_id))
+                .map(|(_, t)| t.clone())
+                .collect::<Vec<_>>();
+            let pending = locked.splits.get(&tenant_id).copied();
+            (shards, pending, locked.nodes.clone())
+        };
+
+        if shards.is_empty() {
+            return Err(ApiError::NotFound(
+                anyhow!("Tenant {tenant_id} not found").into(),
+            ));
+        }
+        if let Some(pending) = pending.filter(|pending| *pending != new_shard_count) {
+            return Err(ApiError::Conflict(format!(
+                "Tenant {tenant_id} has an unfinished split to {} shards: retry that first",
+                pending.0
+            )));
+        }
+
+        // Shards that are already split, by an earlier attempt, are left as they are
+        let parents = shards
+            .into_iter()
+            .filter(|s| s.tenant_shard_id.shard_count != new_shard_count)
+            .collect::<Vec<_>>();
+        if let Some(first) = parents.first() {
+            let old_shard_count = std::cmp::max(first.tenant_shard_id.shard_count.0, 1);
+            if new_shard_count.0 <= old_shard_count || new_shard_count.0 % old_shard_count != 0 {
+                return Err(ApiError::BadRequest(anyhow!(
+                    "Shard count {} is not a multiple of the current count {old_shard_count}",
+                    new_shard_count.0
+                )));
+            }
+        } else if pending.is_none() {
+            return Err(ApiError::BadRequest(anyhow!(
+                "Tenant {tenant_id} already has {} shards",
+                new_shard_count.0
+            )));
+        }
+
+        // Check up front that every shard can be split, rather than failing part way
+        for parent in &parents {
+            match parent.intent.attached.and_then(|n| nodes.get(&n)) {
+                Some(node) if node.may_schedule() => {}
+                _ => {
+                    return Err(ApiError::Conflict(format!(
+                        "Tenant shard {} is not attached to an active node",
+                        parent.tenant_shard_id
+                    )))
+                }
+            }
+        }
+
+        if pending.is_none() {
+            self.persistence
+                .begin_shard_split(tenant_id, new_shard_count)
+                .await?;
+            self.inner
+                .write()
+                .unwrap()
+                .splits
+                .insert(tenant_id, new_shard_count);
+        }
+
+        for parent in parents {
+            self.split_shard(parent, new_shard_count, &nodes).await?;
+        }
+
+        // Give the children the secondary locations their policy asks for
+        let mut moves = Vec::new();
+        let children = {
+            let mut guard = self.inner.write().unwrap();
+            let ServiceState { tenants, nodes, .. } = &mut *guard;
+            let mut scheduler = Scheduler::new(tenants.values(), nodes);
+            let mut children = Vec::new();
+            for (tenant_shard_id, tenant) in
+                tenants.range_mut(TenantShardId::tenant_range(tenant_id))
+            {
+                children.push(*tenant_shard_id);
+                let previous = tenant.intent.clone();
+                if let Err(e) = tenant.schedule(&mut scheduler) {
+                    tracing::warn!("Can't schedule secondaries for {tenant_shard_id}: {e}");
+                    tenant.intent = previous;
+                    continue;
+                }
+                if tenant.intent != previous {
+                    moves.push((tenant.clone(), previous));
+                }
+            }
+            children
+        };
+
+        for (tenant, previous) in moves {
+            let tenant_shard_id = tenant.tenant_shard_id;
+            self.persistence
+                .set_secondaries(tenant_shard_id, tenant.intent.secondary.clone())
+                .await?;
+            if let Err(e) = self.reconcile(tenant, previous).await {
+                tracing::warn!("Failed to reconcile {tenant_shard_id}: {e:#}");
+            }
+        }
+
+        // Computes route their reads by shard, and the parents are gone from the pageservers:
+        // the split is only done once the computes were switched over to all the children.
+        self.notify_computes(tenant_id).await?;
+
+        self.persistence.end_shard_split(tenant_id).await?;
+        self.inner.write().unwrap().splits.remove(&tenant_id);
+
+        Ok(TenantShardSplitResponse {
+            new_shards: children,
+        })
+    }
+
+    /// Split one shard on the pageserver where it is attached, and replace it with its
+    /// children.  If an earlier attempt split it on the pageserver but failed to record
+    /// that, the children found there are taken as they are.
+    async fn split_shard(
+        &self,
+        parent: TenantState,
+        new_shard_count: ShardCount,
+        nodes: &HashMap<NodeId, Node>,
+    ) -> Result<(), ApiError> {
+        let node = &nodes[&parent.intent.attached.unwrap()];
+
+        // Secondary locations hold the parent's layers, under the parent's shard ID:
+        // the children get fresh ones once all the shards are split.
+        for secondary in parent.intent.secondary.iter().filter_map(|n| nodes.get(n)) {
+            if let Err(e) = self
+                .client(secondary)
+                .location_config(
+                    parent.tenant_shard_id,
+                    parent.detached_location_config(),
+                    None,
+                )
+                .await
+            {
+                tracing::warn!(
+                    "Failed to detach {} from node {}: {e:#}",
+                    parent.tenant_shard_id,
+                    secondary.id
+                );
+            }
+        }
+
+        let split = self
+            .client(node)
+            .tenant_shard_split(
+                parent.tenant_shard_id,
+                &TenantShardSplitRequest {
+                    new_shard_count: new_shard_count.0,
+                },
+            )
+            .await;
+        let child_ids = match split {
+            Ok(response) => response.new_shards,
+            Err(e) => {
+                let expect = parent.tenant_shard_id.split(new_shard_count);
+                let already_split = match self.client(node).list_tenants().await {
+                    Ok(attached) => expect
+                        .iter()
+                        .all(|child| attached.iter().any(|t| t.id == *child)),
+                    Err(_) => false,
+                };
+                if !already_split {
+                    return Err(ApiError::InternalServerError(e.into()));
+                }
+                tracing::info!(
+                    "Tenant shard {} was already split on node {}",
+                    parent.tenant_shard_id,
+                    node.id
+                );
+                expect
+            }
+        };
+        tracing::info!(
+            "Split {} on node {}: {child_ids:?}",
+            parent.tenant_shard_id,
+            node.id
+        );
+
+        let mut children = Vec::new();
+        for child_id in child_ids {
+            let shard = ShardIdentity::new(
+                child_id.shard_number,
+                child_id.shard_count,
+                parent.shard.stripe_size,
+            )
+            .map_err(|e| ApiError::InternalServerError(e.into()))?;
+            let mut child = TenantState::new(
+                child_id,
+                shard,
+                parent.policy.clone(),
+                parent.config.clone(),
+            );
+            child.generation = parent.generation;
+            child.intent.attached = parent.intent.attached;
+            children.push(child);
+        }
+
+        self.persistence
+            .complete_shard_split(
+                parent.tenant_shard_id,
+                children.iter().map(|c| c.to_persistent()).collect(),
+            )
+            .await?;
+
+        let mut locked = self.inner.write().unwrap();
+        locked.tenants.remove(&parent.tenant_shard_id);
+        for child in children {
+            locked.tenants.insert(child.tenant_shard_id, child);
+        }
+        Ok(())
+    }
+
+    /// Tell the computes of a tenant where its shards are attached now.  A compute that
+    /// misses this goes on reading from locations that may be gone, so this is retried a
+    /// few times before giving up.
+    async fn notify_computes(&self, tenant_id: TenantId) -> Result<(), ApiError> {
+        let locate = self.tenant_locate(tenant_id)?;
+        let pageservers = locate.shards.iter().map(|s| s.node_id).collect::<Vec<_>>();
+        let stripe_size = (locate.shards.len() > 1).then_some(locate.stripe_size);
+
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .compute_hook
+                .notify(tenant_id, pageservers.clone(), stripe_size)
+                .await;
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt + 1 >= COMPUTE_NOTIFY_ATTEMPTS => {
+                    return Err(ApiError::InternalServerError(e.context(format!(
+                        "Failed to notify computes of {tenant_id}'s shards"
+                    ))));
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to notify computes of {tenant_id}'s shards, retrying: {e:#}"
+                    );
+                    attempt += 1;
+                    tokio::time::sleep(Duration::from_secs_f64(
+                        exponential_backoff_duration_seconds(
+                            attempt,
+                            DEFAULT_BASE_BACKOFF_SECONDS,
+                            DEFAULT_MAX_BACKOFF_SECONDS,
+                        ),
+                    ))
+                    .await;
+                }
+            }
+        }
+    }
+
+    pub fn tenant_locate(&self, tenant_id: TenantId) -> Result<TenantLocateResponse, ApiError> {
+        let locked = self.inner.read().unwrap();
+
+        // Part way through a split, the shards overlap: none of them can be used
+        if let Some(new_shard_count) = locked.splits.get(&tenant_id) {
+            let mut shards = locked.tenants.range(TenantShardId::tenant_range(tenant_id));
+            if shards.any(|(id, _)| id.shard_count != *new_shard_count) {
+                return Err(ApiError::ResourceUnavailable(
+                    format!("Tenant {tenant_id} is being split").into(),
+                ));
+            }
+        }
+
+        let mut result = Vec::new();
+        let mut stripe_size = None;
+        for (tenant_shard_id, shard) in locked.tenants.range(TenantShardId::tenant_range(tenant_id))
+        {
+            let node_id = shard.intent.attached.ok_or_else(|| {
+                ApiError::ResourceUnavailable(
+                    format!("Tenant shard {tenant_shard_id} is not attached").into(),
+                )
+            })?;
+            let node = locked.nodes.get(&node_id).ok_or_else(|| {
+                ApiError::InternalServerError(anyhow!(
+                    "Tenant shard {tenant_shard_id} attached to unknown node {node_id}"
+                ))
+            })?;
+
+            stripe_size.get_or_insert(shard.shard.stripe_size);
+            result.push(TenantLocateResponseShard {
+                shard_id: *tenant_shard_id,
+                node_id,
+                listen_pg_addr: node.listen_pg_addr.clone(),
+                listen_pg_port: node.listen_pg_port,
+                listen_http_addr: node.listen_http_addr.clone(),
+                listen_http_port: node.listen_http_port,
+            });
+        }
+
+        let Some(stripe_size) = stripe_size else {
+            return Err(ApiError::NotFound(
+                anyhow!("Tenant {tenant_id} not found").into(),
+            ));
+        };
+
+        Ok(TenantLocateResponse {
+            shards: result,
+            stripe_size,
+        })
+    }
+}
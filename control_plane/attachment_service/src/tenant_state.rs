@@ -0,0 +1,170 @@
+use control_plane::attachment_service::PlacementPolicy;
+use pageserver_api::{
+    models::{LocationConfig, LocationConfigMode, LocationConfigSecondary, TenantConfig},
+    shard::{ShardIdentity, TenantShardId},
+};
+use utils::id::NodeId;
+
+use crate::{
+    persistence::TenantShardPersistence,
+    scheduler::{ScheduleError, Scheduler},
+};
+
+/// Where we want a tenant shard's locations to be.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct IntentState {
+    pub(crate) attached: Option<NodeId>,
+    pub(crate) secondary: Vec<NodeId>,
+}
+
+impl IntentState {
+    pub(crate) fn all_pageservers(&self) -> Vec<NodeId> {
+        self.attached
+            .iter()
+            .chain(self.secondary.iter())
+            .copied()
+            .collect()
+    }
+}
+
+/// In-memory state of one tenant shard.  The durable subset of this lives in
+/// [`TenantShardPersistence`].
+#[derive(Clone)]
+pub(crate) struct TenantState {
+    pub(crate) tenant_shard_id: TenantShardId,
+
+    pub(crate) shard: ShardIdentity,
+
+    // Latest generation number issued for this shard
+    pub(crate) generation: u32,
+
+    pub(crate) policy: PlacementPolicy,
+
+    pub(crate) intent: IntentState,
+
+    pub(crate) config: TenantConfig,
+}
+
+impl TenantState {
+    pub(crate) fn new(
+        tenant_shard_id: TenantShardId,
+        shard: ShardIdentity,
+        policy: PlacementPolicy,
+        config: TenantConfig,
+    ) -> Self {
+        Self {
+            tenant_shard_id,
+            shard,
+            generation: 0,
+            policy,
+            intent: IntentState::default(),
+            config,
+        }
+    }
+
+    pub(crate) fn from_persistent(p: TenantShardPersistence) -> anyhow::Result<Self> {
+        let tenant_shard_id = p.tenant_shard_id;
+        let shard = if tenant_shard_id.shard_count.0 == 0 {
+            ShardIdentity::unsharded()
+        } else {
+            ShardIdentity::new(
+                tenant_shard_id.shard_number,
+                tenant_shard_id.shard_count,
+                p.shard_stripe_size,
+            )?
+        };
+
+        Ok(Self {
+            tenant_shard_id,
+            shard,
+            generation: p.generation,
+            policy: p.placement_policy,
+            intent: IntentState {
+                attached: p.generation_pageserver,
+                secondary: p.secondary_pageservers,
+            },
+            config: p.config,
+        })
+    }
+
+    pub(crate) fn to_persistent(&self) -> TenantShardPersistence {
+        TenantShardPersistence {
+            tenant_shard_id: self.tenant_shard_id,
+            shard_stripe_size: self.shard.stripe_size,
+            generation: self.generation,
+            generation_pageserver: self.intent.attached,
+            secondary_pageservers: self.intent.secondary.clone(),
+            placement_policy: self.policy.clone(),
+            config: self.config.clone(),
+        }
+    }
+
+    /// Fill in any missing locations in the intent, according to the placement policy.
+    /// Locations that are already placed are left alone.
+    pub(crate) fn schedule(&mut self, scheduler: &mut Scheduler) -> Result<(), ScheduleError> {
+        match self.policy {
+            PlacementPolicy::Single => {
+                if self.intent.attached.is_none() {
+                    self.intent.attached = Some(scheduler.schedule_shard(&[])?);
+                }
+                self.intent.secondary.clear();
+            }
+            PlacementPolicy::Double(secondary_count) => {
+                if self.intent.attached.is_none() {
+                    self.intent.attached = Some(scheduler.schedule_shard(&[])?);
+                }
+                while self.intent.secondary.len() < secondary_count {
+                    let node_id = scheduler.schedule_shard(&self.intent.all_pageservers())?;
+                    self.intent.secondary.push(node_id);
+                }
+                self.intent.secondary.truncate(secondary_count);
+            }
+            PlacementPolicy::Detached => {
+                self.intent.attached = None;
+                self.intent.secondary.clear();
+            }
+            PlacementPolicy::External => {
+                // Placed by the attach hook's caller, not by us.
+            }
+        }
+
+        Ok(())
+    }
+
+    fn location_config(
+        &self,
+        mode: LocationConfigMode,
+        generation: Option<u32>,
+        secondary_conf: Option<LocationConfigSecondary>,
+    ) -> LocationConfig {
+        LocationConfig {
+            mode,
+            generation,
+            secondary_conf,
+            tenant_conf: self.config.clone(),
+            shard_number: self.shard.number.0,
+            shard_count: self.shard.count.0,
+            shard_stripe_size: self.shard.stripe_size.0,
+        }
+    }
+
+    pub(crate) fn attached_location_config(
+        &self,
+        mode: LocationConfigMode,
+        generation: u32,
+    ) -> LocationConfig {
+        self.location_config(mode, Some(generation), None)
+    }
+
+    pub(crate) fn secondary_location_config(&self) -> LocationConfig {
+        self.location_config(
+            LocationConfigMode::Secondary,
+            None,
+            Some(LocationConfigSecondary { warm: true }),
+        )
+    }
+
+    pub(crate) fn detached_location_config(&self) -> LocationConfig {
+        self.location_config(LocationConfigMode::Detached, None, None)
+    }
+}
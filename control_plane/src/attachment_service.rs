@@ -1,9 +1,18 @@
 use crate::{background_process, local_env::LocalEnv};
 use anyhow::anyhow;
 use camino::Utf8PathBuf;
-use serde::{Deserialize, Serialize};
+use pageserver_api::{
+    models::{TenantConfig, TenantShardSplitRequest, TenantShardSplitResponse},
+    shard::{ShardCount, ShardStripeSize, TenantShardId},
+};
+use postgres_backend::AuthType;
+use reqwest::Method;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{path::PathBuf, process::Child};
-use utils::id::{NodeId, TenantId};
+use utils::{
+    auth::{Claims, Scope},
+    id::{NodeId, TenantId},
+};
 
 pub struct AttachmentService {
     env: LocalEnv,
@@ -35,9 +44,123 @@ pub struct InspectResponse {
     pub attachment: Option<(u32, NodeId)>,
 }
 
+/// Whether the attachment service may place tenant shards on a node.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NodeAvailability {
+    // Normal, happy state
+    Active,
+    // Offline: we will not try to place tenants here, and will not send it requests
+    Offline,
+}
+
+/// How many locations a tenant shard should have, and of what kind.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Default)]
+pub enum PlacementPolicy {
+    /// Cheapest way to attach a tenant: just one pageserver, no secondary
+    #[default]
+    Single,
+    /// Production-ready way to attach a tenant: one attached pageserver and
+    /// some number of secondaries.
+    Double(usize),
+    /// Do not attach to any pageservers
+    Detached,
+    /// Attached wherever the attach hook last put it: tenants created outside the service's
+    /// tenant API are placed by their creator, and the service never moves them by itself.
+    External,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NodeRegisterRequest {
+    pub node_id: NodeId,
+
+    pub listen_pg_addr: String,
+    pub listen_pg_port: u16,
+
+    pub listen_http_addr: String,
+    pub listen_http_port: u16,
+}
+
+/// Availability is normally driven by the service's heartbeats: marking a node offline
+/// moves its tenants elsewhere straight away, and it becomes active again once it
+/// responds to heartbeats.
+#[derive(Serialize, Deserialize)]
+pub struct NodeConfigureRequest {
+    pub node_id: NodeId,
+
+    pub availability: Option<NodeAvailability>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NodeDescribeResponse {
+    pub node_id: NodeId,
+
+    pub availability: NodeAvailability,
+
+    pub listen_pg_addr: String,
+    pub listen_pg_port: u16,
+
+    pub listen_http_addr: String,
+    pub listen_http_port: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TenantCreateRequest {
+    pub new_tenant_id: TenantId,
+
+    /// A shard count of zero creates a single legacy unsharded tenant, as
+    /// the pageserver does when no shard parameters are given.
+    pub shard_count: ShardCount,
+
+    /// Stripe size for sharded tenants: if omitted, the pageserver's default is used.
+    #[serde(default)]
+    pub shard_stripe_size: Option<ShardStripeSize>,
+
+    #[serde(default)]
+    pub placement_policy: PlacementPolicy,
+
+    #[serde(default)]
+    pub config: TenantConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TenantCreateResponseShard {
+    pub tenant_shard_id: TenantShardId,
+    pub node_id: NodeId,
+    pub generation: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TenantCreateResponse {
+    pub shards: Vec<TenantCreateResponseShard>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TenantShardMigrateRequest {
+    pub tenant_shard_id: TenantShardId,
+    pub node_id: NodeId,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TenantLocateResponseShard {
+    pub shard_id: TenantShardId,
+    pub node_id: NodeId,
+
+    pub listen_pg_addr: String,
+    pub listen_pg_port: u16,
+
+    pub listen_http_addr: String,
+    pub listen_http_port: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TenantLocateResponse {
+    pub shards: Vec<TenantLocateResponseShard>,
+    pub stripe_size: ShardStripeSize,
+}
+
 impl AttachmentService {
     pub fn from_env(env: &LocalEnv) -> Self {
-        let path = env.base_data_dir.join("attachments.json");
+        let path = env.base_data_dir.join("attachments.db");
 
         // Makes no sense to construct this if pageservers aren't going to use it: assume
         // pageservers have control plane API set
@@ -65,17 +188,44 @@ impl AttachmentService {
     }
 
     pub fn start(&self) -> anyhow::Result<Child> {
-        let path_str = self.path.to_string_lossy();
+        let mut args = vec![
+            "-l".to_string(),
+            self.listen.clone(),
+            "-p".to_string(),
+            self.path.to_string_lossy().to_string(),
+        ];
+
+        // The service drives pageservers' location_config API, so it needs a token
+        // if any of them require one.
+        if self
+            .env
+            .pageservers
+            .iter()
+            .any(|ps| ps.http_auth_type == AuthType::NeonJWT)
+        {
+            let token = self
+                .env
+                .generate_auth_token(&Claims::new(None, Scope::PageServerApi))?;
+            args.push("--jwt-token".to_string());
+            args.push(token);
+        }
+
+        // Lets the service reconfigure our endpoints when it splits their tenant
+        args.push("--neon-local-repo-dir".to_string());
+        let repo_dir = std::fs::canonicalize(&self.env.base_data_dir)?;
+        args.push(repo_dir.to_string_lossy().to_string());
 
         background_process::start_process(
             COMMAND,
             &self.env.base_data_dir,
             &self.env.attachment_service_bin(),
-            ["-l", &self.listen, "-p", &path_str],
+            args,
             [],
             background_process::InitialPidFile::Create(&self.pid_file()),
-            // TODO: a real status check
-            || Ok(true),
+            || match self.status() {
+                Ok(()) => Ok(true),
+                Err(_) => Ok(false),
+            },
         )
     }
 
@@ -83,55 +233,121 @@ impl AttachmentService {
         background_process::stop_process(immediate, COMMAND, &self.pid_file())
     }
 
-    /// Call into the attach_hook API, for use before handing out attachments to pageservers
-    pub fn attach_hook(
-        &self,
-        tenant_id: TenantId,
-        pageserver_id: NodeId,
-    ) -> anyhow::Result<Option<u32>> {
-        use hyper::StatusCode;
-
+    /// Simple HTTP request wrapper for calling into the attachment service
+    fn dispatch<RQ, RS>(&self, method: Method, path: String, body: Option<RQ>) -> anyhow::Result<RS>
+    where
+        RQ: Serialize + Sized,
+        RS: DeserializeOwned + Sized,
+    {
         let url = self
             .env
             .control_plane_api
             .clone()
             .unwrap()
-            .join("attach-hook")
+            .join(&path)
             .unwrap();
 
+        let mut builder = self.client.request(method, url);
+        if let Some(body) = body {
+            builder = builder.json(&body)
+        }
+
+        let response = builder.send()?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().unwrap_or_default();
+            return Err(anyhow!("Unexpected status {status}: {body}"));
+        }
+
+        Ok(response.json()?)
+    }
+
+    pub fn status(&self) -> anyhow::Result<()> {
+        self.dispatch::<(), ()>(Method::GET, "status".to_string(), None)
+    }
+
+    /// Call into the attach_hook API, for use before handing out attachments to pageservers
+    pub fn attach_hook(
+        &self,
+        tenant_id: TenantId,
+        pageserver_id: NodeId,
+    ) -> anyhow::Result<Option<u32>> {
         let request = AttachHookRequest {
             tenant_id,
             node_id: Some(pageserver_id),
         };
 
-        let response = self.client.post(url).json(&request).send()?;
-        if response.status() != StatusCode::OK {
-            return Err(anyhow!("Unexpected status {}", response.status()));
-        }
-
-        let response = response.json::<AttachHookResponse>()?;
+        let response = self.dispatch::<_, AttachHookResponse>(
+            Method::POST,
+            "attach-hook".to_string(),
+            Some(request),
+        )?;
         Ok(response.gen)
     }
 
     pub fn inspect(&self, tenant_id: TenantId) -> anyhow::Result<Option<(u32, NodeId)>> {
-        use hyper::StatusCode;
+        let request = InspectRequest { tenant_id };
 
-        let url = self
-            .env
-            .control_plane_api
-            .clone()
-            .unwrap()
-            .join("inspect")
-            .unwrap();
+        let response = self.dispatch::<_, InspectResponse>(
+            Method::POST,
+            "inspect".to_string(),
+            Some(request),
+        )?;
+        Ok(response.attachment)
+    }
 
-        let request = InspectRequest { tenant_id };
+    pub fn node_register(&self, req: NodeRegisterRequest) -> anyhow::Result<()> {
+        self.dispatch::<_, ()>(Method::POST, "node".to_string(), Some(req))
+    }
 
-        let response = self.client.post(url).json(&request).send()?;
-        if response.status() != StatusCode::OK {
-            return Err(anyhow!("Unexpected status {}", response.status()));
-        }
+    pub fn node_configure(&self, req: NodeConfigureRequest) -> anyhow::Result<()> {
+        self.dispatch::<_, ()>(
+            Method::PUT,
+            format!("node/{}/config", req.node_id),
+            Some(req),
+        )
+    }
 
-        let response = response.json::<InspectResponse>()?;
-        Ok(response.attachment)
+    pub fn node_list(&self) -> anyhow::Result<Vec<NodeDescribeResponse>> {
+        self.dispatch::<(), _>(Method::GET, "node".to_string(), None)
+    }
+
+    pub fn tenant_create(&self, req: TenantCreateRequest) -> anyhow::Result<TenantCreateResponse> {
+        self.dispatch(Method::POST, "tenant".to_string(), Some(req))
+    }
+
+    pub fn tenant_locate(&self, tenant_id: TenantId) -> anyhow::Result<TenantLocateResponse> {
+        self.dispatch::<(), _>(Method::GET, format!("tenant/{tenant_id}/locate"), None)
+    }
+
+    pub fn tenant_migrate(
+        &self,
+        tenant_shard_id: TenantShardId,
+        node_id: NodeId,
+    ) -> anyhow::Result<()> {
+        self.dispatch(
+            Method::PUT,
+            format!("tenant/{tenant_shard_id}/migrate"),
+            Some(TenantShardMigrateRequest {
+                tenant_shard_id,
+                node_id,
+            }),
+        )
+    }
+
+    pub fn tenant_split(
+        &self,
+        tenant_id: TenantId,
+        new_shard_count: u8,
+    ) -> anyhow::Result<TenantShardSplitResponse> {
+        self.dispatch(
+            Method::PUT,
+            format!("tenant/{tenant_id}/shard_split"),
+            Some(TenantShardSplitRequest { new_shard_count }),
+        )
+    }
+
+    pub fn tenant_delete(&self, tenant_id: TenantId) -> anyhow::Result<()> {
+        self.dispatch::<(), ()>(Method::DELETE, format!("tenant/{tenant_id}"), None)
     }
 }
@@ -8,14 +8,18 @@
 use anyhow::{anyhow, bail, Context, Result};
 use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 use compute_api::spec::ComputeMode;
-use control_plane::attachment_service::AttachmentService;
-use control_plane::endpoint::ComputeControlPlane;
-use control_plane::local_env::LocalEnv;
+use control_plane::attachment_service::{AttachmentService, PlacementPolicy, TenantCreateRequest};
+use control_plane::endpoint::{ComputeControlPlane, Endpoint};
+use control_plane::env_spec::{
+    plan_services, Action, EnvSpec, ObservedEndpoint, ObservedEnv, ObservedTenant,
+};
+use control_plane::local_env::{LocalEnv, DEFAULT_BRANCH_NAME};
 use control_plane::pageserver::{PageServerNode, PAGESERVER_REMOTE_STORAGE_DIR};
 use control_plane::safekeeper::SafekeeperNode;
 use control_plane::tenant_migration::migrate_tenant;
 use control_plane::{broker, local_env};
 use pageserver_api::models::TimelineInfo;
+use pageserver_api::shard::{ShardCount, ShardStripeSize, TenantShardId};
 use pageserver_api::{
     DEFAULT_HTTP_LISTEN_PORT as DEFAULT_PAGESERVER_HTTP_PORT,
     DEFAULT_PG_LISTEN_PORT as DEFAULT_PAGESERVER_PG_PORT,
@@ -40,7 +44,6 @@ use utils::{
 // Default id of a safekeeper node, if not specified on the command line.
 const DEFAULT_SAFEKEEPER_ID: NodeId = NodeId(1);
 const DEFAULT_PAGESERVER_ID: NodeId = NodeId(1);
-const DEFAULT_BRANCH_NAME: &str = "main";
 project_git_version!(GIT_VERSION);
 
 const DEFAULT_PG_VERSION: &str = "15";
@@ -115,6 +118,9 @@ fn main() -> Result<()> {
     // Check for 'neon init' command first.
     let subcommand_result = if sub_name == "init" {
         handle_init(sub_args).map(Some)
+    } else if sub_name == "apply" {
+        // 'apply' initializes the repository if needed, and persists the config as it goes
+        handle_apply(sub_args).map(|()| None)
     } else {
         // all other commands need an existing config
         let mut env = LocalEnv::load_config().context("Error loading config")?;
@@ -274,7 +280,7 @@ fn get_timeline_infos(
     tenant_id: &TenantId,
 ) -> Result<HashMap<TimelineId, TimelineInfo>> {
     Ok(get_default_pageserver(env)
-        .timeline_list(tenant_id)?
+        .timeline_list(&TenantShardId::unsharded(*tenant_id))?
         .into_iter()
         .map(|timeline_info| (timeline_info.timeline_id, timeline_info))
         .collect())
@@ -329,10 +335,24 @@ fn handle_init(init_match: &ArgMatches) -> anyhow::Result<LocalEnv> {
         .get_one::<u32>("pg-version")
         .copied()
         .context("Failed to parse postgres version from the argument string")?;
+    let force = init_match.get_flag("force");
+
+    init_env(
+        &toml_file,
+        pg_version,
+        force,
+        &pageserver_config_overrides(init_match),
+    )
+}
 
+fn init_env(
+    toml_file: &str,
+    pg_version: u32,
+    force: bool,
+    pageserver_config_overrides: &[&str],
+) -> anyhow::Result<LocalEnv> {
     let mut env =
-        LocalEnv::parse_config(&toml_file).context("Failed to create neon configuration")?;
-    let force = init_match.get_flag("force");
+        LocalEnv::parse_config(toml_file).context("Failed to create neon configuration")?;
     env.init(pg_version, force)
         .context("Failed to initialize neon repository")?;
 
@@ -342,7 +362,7 @@ fn handle_init(init_match: &ArgMatches) -> anyhow::Result<LocalEnv> {
     // Initialize pageserver, create initial tenant and timeline.
     for ps_conf in &env.pageservers {
         PageServerNode::from_env(&env, ps_conf)
-            .initialize(&pageserver_config_overrides(init_match))
+            .initialize(pageserver_config_overrides)
             .unwrap_or_else(|e| {
                 eprintln!("pageserver init failed: {e:?}");
                 exit(1);
@@ -389,18 +409,12 @@ fn handle_tenant(tenant_match: &ArgMatches, env: &mut local_env::LocalEnv) -> an
 
             // If tenant ID was not specified, generate one
             let tenant_id = parse_tenant_id(create_match)?.unwrap_or_else(TenantId::generate);
+            let shard_count = create_match
+                .get_one::<u8>("shard-count")
+                .copied()
+                .unwrap_or(0);
 
-            let generation = if env.control_plane_api.is_some() {
-                // We must register the tenant with the attachment service, so
-                // that when the pageserver restarts, it will be re-attached.
-                let attachment_service = AttachmentService::from_env(env);
-                attachment_service.attach_hook(tenant_id, pageserver.conf.id)?
-            } else {
-                None
-            };
-
-            pageserver.tenant_create(tenant_id, generation, tenant_conf)?;
-            println!("tenant {tenant_id} successfully created on the pageserver");
+            let shards = create_tenant(env, tenant_id, shard_count, tenant_conf)?;
 
             // Create an initial timeline for the new tenant
             let new_timeline_id = parse_timeline_id(create_match)?;
@@ -409,14 +423,8 @@ fn handle_tenant(tenant_match: &ArgMatches, env: &mut local_env::LocalEnv) -> an
                 .copied()
                 .context("Failed to parse postgres version from the argument string")?;
 
-            let timeline_info = pageserver.timeline_create(
-                tenant_id,
-                new_timeline_id,
-                None,
-                None,
-                Some(pg_version),
-                None,
-            )?;
+            let timeline_info =
+                create_timeline(env, &shards, new_timeline_id, None, None, Some(pg_version))?;
             let new_timeline_id = timeline_info.timeline_id;
             let last_record_lsn = timeline_info.last_record_lsn;
 
@@ -461,6 +469,14 @@ fn handle_tenant(tenant_match: &ArgMatches, env: &mut local_env::LocalEnv) -> an
             migrate_tenant(env, tenant_id, new_pageserver)?;
             println!("tenant {tenant_id} migrated to {}", new_pageserver_id);
         }
+        Some(("split", matches)) => {
+            let tenant_id = get_tenant_id(matches, env)?;
+            let shard_count: u8 = *matches
+                .get_one::<u8>("shard-count")
+                .context("No shard count specified")?;
+
+            split_tenant(env, tenant_id, shard_count)?;
+        }
 
         Some((sub_name, _)) => bail!("Unexpected tenant subcommand '{}'", sub_name),
         None => bail!("no tenant subcommand provided"),
@@ -468,13 +484,131 @@ fn handle_tenant(tenant_match: &ArgMatches, env: &mut local_env::LocalEnv) -> an
     Ok(())
 }
 
+/// Create a tenant: sharded tenants are placed by the attachment service, others are created
+/// on the default pageserver.  Returns where the shards were created, in shard number order.
+fn create_tenant(
+    env: &local_env::LocalEnv,
+    tenant_id: TenantId,
+    shard_count: u8,
+    tenant_conf: HashMap<&str, &str>,
+) -> Result<Vec<(TenantShardId, NodeId)>> {
+    let mut shards = if shard_count > 0 {
+        if env.control_plane_api.is_none() {
+            bail!("Creating a sharded tenant requires the attachment service");
+        }
+        let attachment_service = AttachmentService::from_env(env);
+        let response = attachment_service.tenant_create(TenantCreateRequest {
+            new_tenant_id: tenant_id,
+            shard_count: ShardCount(shard_count),
+            shard_stripe_size: None,
+            placement_policy: PlacementPolicy::default(),
+            config: PageServerNode::parse_config(tenant_conf)?,
+        })?;
+        println!("tenant {tenant_id} successfully created with {shard_count} shards");
+        response
+            .shards
+            .into_iter()
+            .map(|s| (s.tenant_shard_id, s.node_id))
+            .collect::<Vec<_>>()
+    } else {
+        let pageserver = get_default_pageserver(env);
+        let generation = if env.control_plane_api.is_some() {
+            // We must register the tenant with the attachment service, so
+            // that when the pageserver restarts, it will be re-attached.
+            let attachment_service = AttachmentService::from_env(env);
+            attachment_service.attach_hook(tenant_id, pageserver.conf.id)?
+        } else {
+            None
+        };
+
+        pageserver.tenant_create(tenant_id, generation, tenant_conf)?;
+        println!("tenant {tenant_id} successfully created on the pageserver");
+        vec![(TenantShardId::unsharded(tenant_id), pageserver.conf.id)]
+    };
+    shards.sort_by_key(|(tenant_shard_id, _)| tenant_shard_id.shard_number);
+    Ok(shards)
+}
+
+/// Where the shards of a tenant are attached, in shard number order.  Empty if no pageserver
+/// has the tenant attached.
+fn tenant_shards(
+    env: &local_env::LocalEnv,
+    tenant_id: TenantId,
+) -> Result<Vec<(TenantShardId, NodeId)>> {
+    let mut shards = Vec::new();
+    for ps_conf in &env.pageservers {
+        let pageserver = PageServerNode::from_env(env, ps_conf);
+        for tenant in pageserver.tenant_list()? {
+            if tenant.id.tenant_id == tenant_id {
+                shards.push((tenant.id, ps_conf.id));
+            }
+        }
+    }
+
+    // While a split is in progress, the parent shards may still be attached next to the children
+    if let Some(shard_count) = shards.iter().map(|(id, _)| id.shard_count).max() {
+        shards.retain(|(id, _)| id.shard_count == shard_count);
+    }
+    shards.sort_by_key(|(tenant_shard_id, _)| tenant_shard_id.shard_number);
+    shards.dedup_by_key(|(tenant_shard_id, _)| tenant_shard_id.shard_number);
+    Ok(shards)
+}
+
+/// Create a timeline on every shard of a tenant, with the same timeline ID.  Returns the
+/// timeline as shard zero sees it.
+fn create_timeline(
+    env: &local_env::LocalEnv,
+    shards: &[(TenantShardId, NodeId)],
+    new_timeline_id: Option<TimelineId>,
+    ancestor_start_lsn: Option<Lsn>,
+    ancestor_timeline_id: Option<TimelineId>,
+    pg_version: Option<u32>,
+) -> Result<TimelineInfo> {
+    let mut new_timeline_id = new_timeline_id;
+    let mut shard_zero_info = None;
+    for (tenant_shard_id, node_id) in shards {
+        let pageserver = PageServerNode::from_env(env, env.get_pageserver_conf(*node_id)?);
+        let timeline_info = pageserver.timeline_create(
+            *tenant_shard_id,
+            new_timeline_id,
+            ancestor_start_lsn,
+            ancestor_timeline_id,
+            pg_version,
+            None,
+        )?;
+        // All shards get the timeline ID that shard zero was given
+        new_timeline_id = Some(timeline_info.timeline_id);
+        shard_zero_info.get_or_insert(timeline_info);
+    }
+    shard_zero_info.context("Tenant has no shards")
+}
+
+fn split_tenant(env: &local_env::LocalEnv, tenant_id: TenantId, shard_count: u8) -> Result<()> {
+    let attachment_service = AttachmentService::from_env(env);
+    let result = attachment_service.tenant_split(tenant_id, shard_count)?;
+    println!(
+        "Split tenant {} into shards {}",
+        tenant_id,
+        result
+            .new_shards
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+
+    // The attachment service has already pointed the tenant's running endpoints at the
+    // children, see its compute hook.
+    Ok(())
+}
+
 fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::LocalEnv) -> Result<()> {
     let pageserver = get_default_pageserver(env);
 
     match timeline_match.subcommand() {
         Some(("list", list_match)) => {
             let tenant_id = get_tenant_id(list_match, env)?;
-            let timelines = pageserver.timeline_list(&tenant_id)?;
+            let timelines = pageserver.timeline_list(&TenantShardId::unsharded(tenant_id))?;
             print_timelines_tree(timelines, env.timeline_name_mappings())?;
         }
         Some(("create", create_match)) => {
@@ -491,7 +625,7 @@ fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::LocalEnv) -
             let new_timeline_id_opt = parse_timeline_id(create_match)?;
 
             let timeline_info = pageserver.timeline_create(
-                tenant_id,
+                TenantShardId::unsharded(tenant_id),
                 new_timeline_id_opt,
                 None,
                 None,
@@ -579,7 +713,7 @@ fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::LocalEnv) -
                 .transpose()
                 .context("Failed to parse ancestor start Lsn from the request")?;
             let timeline_info = pageserver.timeline_create(
-                tenant_id,
+                TenantShardId::unsharded(tenant_id),
                 None,
                 start_lsn,
                 Some(ancestor_timeline_id),
@@ -604,6 +738,64 @@ fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::LocalEnv) -
     Ok(())
 }
 
+/// The pageservers an endpoint should read from, one per shard in shard number order, and
+/// the stripe size if the tenant is sharded.  Sharded tenants are located through the
+/// attachment service: others are read from the endpoint's own pageserver.
+fn endpoint_pageservers(
+    env: &local_env::LocalEnv,
+    endpoint: &Endpoint,
+) -> Result<(Vec<NodeId>, Option<ShardStripeSize>)> {
+    if env.control_plane_api.is_some() {
+        let attachment_service = AttachmentService::from_env(env);
+        match attachment_service.tenant_locate(endpoint.tenant_id) {
+            Ok(locate) if locate.shards.len() > 1 => {
+                let pageservers = locate.shards.iter().map(|s| s.node_id).collect();
+                return Ok((pageservers, Some(locate.stripe_size)));
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!(
+                "Could not locate tenant {} with the attachment service, using pageserver {}: {e:#}",
+                endpoint.tenant_id,
+                endpoint.pageserver_id()
+            ),
+        }
+    }
+    Ok((vec![endpoint.pageserver_id()], None))
+}
+
+fn start_endpoint(
+    env: &local_env::LocalEnv,
+    cplane: &ComputeControlPlane,
+    endpoint: &Endpoint,
+    pageserver_id: Option<NodeId>,
+    safekeepers: Vec<NodeId>,
+    remote_ext_config: Option<&String>,
+) -> Result<()> {
+    cplane.check_conflicting_endpoints(endpoint.mode, endpoint.tenant_id, endpoint.timeline_id)?;
+
+    let (pageservers, stripe_size) = match pageserver_id {
+        Some(pageserver_id) => (vec![pageserver_id], None),
+        None => endpoint_pageservers(env, endpoint)?,
+    };
+
+    let ps_conf = env.get_pageserver_conf(pageservers[0])?;
+    let auth_token = if matches!(ps_conf.pg_auth_type, AuthType::NeonJWT) {
+        let claims = Claims::new(Some(endpoint.tenant_id), Scope::Tenant);
+
+        Some(env.generate_auth_token(&claims)?)
+    } else {
+        None
+    };
+
+    endpoint.start(
+        &auth_token,
+        safekeepers,
+        pageservers,
+        stripe_size,
+        remote_ext_config,
+    )
+}
+
 fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<()> {
     let (sub_name, sub_args) = match ep_match.subcommand() {
         Some(ep_subcommand_data) => ep_subcommand_data,
@@ -748,9 +940,11 @@ fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<(
 
             let pageserver_id =
                 if let Some(id_str) = sub_args.get_one::<String>("endpoint-pageserver-id") {
-                    NodeId(id_str.parse().context("while parsing pageserver id")?)
+                    Some(NodeId(
+                        id_str.parse().context("while parsing pageserver id")?,
+                    ))
                 } else {
-                    DEFAULT_PAGESERVER_ID
+                    None
                 };
 
             let remote_ext_config = sub_args.get_one::<String>("remote-ext-config");
@@ -775,23 +969,15 @@ fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<(
                 .get(endpoint_id.as_str())
                 .ok_or_else(|| anyhow::anyhow!("endpoint {endpoint_id} not found"))?;
 
-            cplane.check_conflicting_endpoints(
-                endpoint.mode,
-                endpoint.tenant_id,
-                endpoint.timeline_id,
-            )?;
-
-            let ps_conf = env.get_pageserver_conf(pageserver_id)?;
-            let auth_token = if matches!(ps_conf.pg_auth_type, AuthType::NeonJWT) {
-                let claims = Claims::new(Some(endpoint.tenant_id), Scope::Tenant);
-
-                Some(env.generate_auth_token(&claims)?)
-            } else {
-                None
-            };
-
             println!("Starting existing endpoint {endpoint_id}...");
-            endpoint.start(&auth_token, safekeepers, remote_ext_config)?;
+            start_endpoint(
+                env,
+                &cplane,
+                endpoint,
+                pageserver_id,
+                safekeepers,
+                remote_ext_config,
+            )?;
         }
         "reconfigure" => {
             let endpoint_id = sub_args
@@ -801,15 +987,15 @@ fn handle_endpoint(ep_match: &ArgMatches, env: &local_env::LocalEnv) -> Result<(
                 .endpoints
                 .get(endpoint_id.as_str())
                 .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
-            let pageserver_id =
-                if let Some(id_str) = sub_args.get_one::<String>("endpoint-pageserver-id") {
-                    Some(NodeId(
-                        id_str.parse().context("while parsing pageserver id")?,
-                    ))
-                } else {
-                    None
-                };
-            endpoint.reconfigure(pageserver_id)?;
+            let (pageservers, stripe_size) = if let Some(id_str) =
+                sub_args.get_one::<String>("endpoint-pageserver-id")
+            {
+                let pageserver_id = NodeId(id_str.parse().context("while parsing pageserver id")?);
+                (vec![pageserver_id], None)
+            } else {
+                endpoint_pageservers(env, endpoint)?
+            };
+            endpoint.reconfigure(pageservers, stripe_size)?;
         }
         "stop" => {
             let endpoint_id = sub_args
@@ -1129,6 +1315,273 @@ fn try_stop_all(env: &local_env::LocalEnv, immediate: bool) {
     }
 }
 
+/// Start the services of the environment that are not running yet.
+fn start_services(env: &local_env::LocalEnv, pageserver_config_overrides: &[&str]) -> Result<()> {
+    if broker::check_broker_status(env).is_err() {
+        broker::start_broker_process(env)?;
+    }
+
+    if env.control_plane_api.is_some() {
+        let attachment_service = AttachmentService::from_env(env);
+        if attachment_service.status().is_err() {
+            attachment_service.start()?;
+        }
+    }
+
+    for ps_conf in &env.pageservers {
+        let pageserver = PageServerNode::from_env(env, ps_conf);
+        if pageserver.check_status().is_err() {
+            pageserver.start(pageserver_config_overrides)?;
+        }
+    }
+
+    for sk_conf in &env.safekeepers {
+        let safekeeper = SafekeeperNode::from_env(env, sk_conf);
+        if safekeeper.check_status().is_err() {
+            safekeeper.start(vec![])?;
+        }
+    }
+    Ok(())
+}
+
+/// What exists of the described tenants, and all the endpoints.
+fn observe_env(env: &local_env::LocalEnv, spec: &EnvSpec) -> Result<ObservedEnv> {
+    let mut observed = ObservedEnv::default();
+    for tenant in &spec.tenants {
+        let shards = tenant_shards(env, tenant.id)?;
+        let Some((shard_zero, node_id)) = shards.first() else {
+            continue;
+        };
+        let pageserver = PageServerNode::from_env(env, env.get_pageserver_conf(*node_id)?);
+        let timelines = pageserver
+            .timeline_list(shard_zero)?
+            .into_iter()
+            .map(|t| t.timeline_id)
+            .collect();
+        observed.tenants.insert(
+            tenant.id,
+            ObservedTenant {
+                shard_count: shards.len() as u8,
+                timelines,
+                config: pageserver.tenant_config_get(*shard_zero)?,
+            },
+        );
+    }
+
+    let cplane = ComputeControlPlane::load(env.clone())?;
+    for (endpoint_name, endpoint) in &cplane.endpoints {
+        observed.endpoints.insert(
+            endpoint_name.clone(),
+            ObservedEndpoint {
+                tenant_id: endpoint.tenant_id,
+                timeline_id: endpoint.timeline_id,
+                mode: endpoint.mode,
+                pg_port: endpoint.pg_address.port(),
+                http_port: endpoint.http_address.port(),
+                running: endpoint.status() == "running",
+            },
+        );
+    }
+    Ok(observed)
+}
+
+fn apply_action(
+    env: &mut local_env::LocalEnv,
+    pageserver_config_overrides: &[&str],
+    action: Action,
+) -> Result<()> {
+    match action {
+        Action::AddPageserver(ps_conf) => {
+            PageServerNode::from_env(env, &ps_conf).initialize(pageserver_config_overrides)?;
+            env.pageservers.push(ps_conf);
+        }
+        Action::AddSafekeeper(sk_conf) => {
+            std::fs::create_dir_all(SafekeeperNode::datadir_path_by_id(env, sk_conf.id))?;
+            env.safekeepers.push(sk_conf);
+        }
+        Action::CreateTenant {
+            tenant_id,
+            shard_count,
+            settings,
+        } => {
+            let tenant_conf = settings
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            create_tenant(env, tenant_id, shard_count, tenant_conf)?;
+        }
+        Action::SplitTenant {
+            tenant_id,
+            shard_count,
+        } => split_tenant(env, tenant_id, shard_count)?,
+        Action::ConfigureTenant {
+            tenant_id,
+            settings,
+        } => {
+            let shards = tenant_shards(env, tenant_id)?;
+            let (_, node_id) = shards
+                .first()
+                .with_context(|| format!("tenant {tenant_id} is not attached"))?;
+            let tenant_conf = settings
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            PageServerNode::from_env(env, env.get_pageserver_conf(*node_id)?)
+                .tenant_config(tenant_id, tenant_conf)?;
+        }
+        Action::CreateBranch {
+            tenant_id,
+            name,
+            timeline_id,
+            parent,
+            ancestor_start_lsn,
+            pg_version,
+        } => {
+            let ancestor_timeline_id = parent
+                .map(|parent| {
+                    env.get_branch_timeline_id(&parent, tenant_id)
+                        .ok_or_else(|| anyhow!("Found no timeline id for branch name '{parent}'"))
+                })
+                .transpose()?;
+            // Branches inherit the postgres version of their ancestor
+            let pg_version = ancestor_timeline_id.is_none().then_some(pg_version);
+
+            let shards = tenant_shards(env, tenant_id)?;
+            let timeline_info = create_timeline(
+                env,
+                &shards,
+                timeline_id,
+                ancestor_start_lsn,
+                ancestor_timeline_id,
+                pg_version,
+            )?;
+            env.register_branch_mapping(name, tenant_id, timeline_info.timeline_id)?;
+        }
+        Action::MapBranch {
+            tenant_id,
+            name,
+            timeline_id,
+        } => env.register_branch_mapping(name, tenant_id, timeline_id)?,
+        Action::SetDefaultTenant(tenant_id) => env.default_tenant_id = Some(tenant_id),
+        Action::StopEndpoint(endpoint_id) => {
+            let cplane = ComputeControlPlane::load(env.clone())?;
+            let endpoint = cplane
+                .endpoints
+                .get(&endpoint_id)
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            endpoint.stop(false)?;
+        }
+        Action::CreateEndpoint {
+            name,
+            tenant_id,
+            branch,
+            pg_port,
+            http_port,
+            pg_version,
+            mode,
+        } => {
+            let timeline_id = env
+                .get_branch_timeline_id(&branch, tenant_id)
+                .ok_or_else(|| anyhow!("Found no timeline id for branch name '{branch}'"))?;
+            let mut cplane = ComputeControlPlane::load(env.clone())?;
+            cplane.new_endpoint(
+                &name,
+                tenant_id,
+                timeline_id,
+                pg_port,
+                http_port,
+                pg_version,
+                mode,
+                get_default_pageserver(env).conf.id,
+            )?;
+        }
+        Action::StartEndpoint(endpoint_id) => {
+            let cplane = ComputeControlPlane::load(env.clone())?;
+            let endpoint = cplane
+                .endpoints
+                .get(&endpoint_id)
+                .with_context(|| format!("postgres endpoint {endpoint_id} is not found"))?;
+            let safekeepers = env.safekeepers.iter().map(|sk| sk.id).collect();
+            start_endpoint(env, &cplane, endpoint, None, safekeepers, None)?;
+        }
+    }
+    Ok(())
+}
+
+fn handle_apply(apply_match: &ArgMatches) -> Result<()> {
+    let spec_path = apply_match
+        .get_one::<PathBuf>("spec")
+        .expect("spec is a required argument");
+    let toml_file = std::fs::read_to_string(spec_path).with_context(|| {
+        format!(
+            "Could not read environment description '{}'",
+            spec_path.display()
+        )
+    })?;
+    let spec = EnvSpec::parse(&toml_file).context("Failed to parse environment description")?;
+    let desired =
+        LocalEnv::parse_config(&toml_file).context("Failed to parse environment description")?;
+    let dry_run = apply_match.get_flag("dry-run");
+    let overrides = pageserver_config_overrides(apply_match);
+
+    let mut env = if desired.base_data_dir.exists() {
+        LocalEnv::load_config().context("Error loading config")?
+    } else if dry_run {
+        println!(
+            "would initialize a new repository in '{}'",
+            desired.base_data_dir.display()
+        );
+        for action in spec.plan(&desired, &ObservedEnv::default())? {
+            println!("would {action}");
+        }
+        return Ok(());
+    } else {
+        let pg_version = apply_match
+            .get_one::<u32>("pg-version")
+            .copied()
+            .context("Failed to parse postgres version from the argument string")?;
+        println!(
+            "initializing a new repository in '{}'",
+            desired.base_data_dir.display()
+        );
+        init_env(&toml_file, pg_version, false, &overrides)?
+    };
+
+    let mut up_to_date = true;
+    for action in plan_services(&env, &desired)? {
+        up_to_date = false;
+        if dry_run {
+            println!("would {action}");
+            continue;
+        }
+        println!("{action}");
+        apply_action(&mut env, &overrides, action)?;
+        env.persist_config(&env.base_data_dir)?;
+    }
+
+    if !dry_run {
+        start_services(&env, &overrides)?;
+    }
+
+    let observed = observe_env(&env, &spec)
+        .context("Failed to inspect the environment, are its services running?")?;
+    for action in spec.plan(&env, &observed)? {
+        up_to_date = false;
+        if dry_run {
+            println!("would {action}");
+            continue;
+        }
+        println!("{action}");
+        apply_action(&mut env, &overrides, action)?;
+        env.persist_config(&env.base_data_dir)?;
+    }
+
+    if up_to_date {
+        println!("environment is up to date");
+    }
+    Ok(())
+}
+
 fn cli() -> Command {
     let branch_name_arg = Arg::new("branch-name")
         .long("branch-name")
@@ -1313,6 +1766,8 @@ fn cli() -> Command {
                 .arg(pg_version_arg.clone())
                 .arg(Arg::new("set-default").long("set-default").action(ArgAction::SetTrue).required(false)
                     .help("Use this tenant in future CLI commands where tenant_id is needed, but not specified"))
+                .arg(Arg::new("shard-count").value_parser(value_parser!(u8)).long("shard-count").action(ArgAction::Set)
+                    .help("Number of shards, placed across pageservers by the attachment service (default: unsharded)"))
                 )
             .subcommand(Command::new("set-default").arg(tenant_id_arg.clone().required(true))
                 .about("Set a particular tenant as default in future CLI commands where tenant_id is needed, but not specified"))
@@ -1323,6 +1778,11 @@ fn cli() -> Command {
                 .about("Migrate a tenant from one pageserver to another")
                 .arg(tenant_id_arg.clone())
                 .arg(pageserver_id_arg.clone()))
+            .subcommand(Command::new("split")
+                .about("Increase the number of shards in a tenant")
+                .arg(tenant_id_arg.clone())
+                .arg(Arg::new("shard-count").value_parser(value_parser!(u8)).long("shard-count").action(ArgAction::Set).required(true).help("Number of shards in the tenant after the split"))
+            )
         )
         .subcommand(
             Command::new("pageserver")
@@ -1439,6 +1899,20 @@ fn cli() -> Command {
                 .arg(Arg::new("ignore-rest").allow_hyphen_values(true).num_args(0..).required(false))
                 .trailing_var_arg(true)
         )
+        .subcommand(
+            Command::new("apply")
+                .about("Create or update the environment to match a description of its services, tenants, branches and endpoints")
+                .arg(Arg::new("spec")
+                    .value_parser(value_parser!(PathBuf))
+                    .required(true)
+                    .help("Environment description file, see control_plane/example_env.toml"))
+                .arg(Arg::new("dry-run")
+                    .long("dry-run")
+                    .action(ArgAction::SetTrue)
+                    .help("Only print the changes that would be made"))
+                .arg(pageserver_config_args.clone())
+                .arg(pg_version_arg.clone())
+        )
         .subcommand(
             Command::new("start")
                 .about("Start page server and safekeepers")
@@ -46,6 +46,15 @@ pub fn start_broker_process(env: &local_env::LocalEnv) -> anyhow::Result<()> {
     Ok(())
 }
 
+pub fn check_broker_status(env: &local_env::LocalEnv) -> anyhow::Result<()> {
+    let url = env.broker.client_url();
+    let status_url = url
+        .join("status")
+        .with_context(|| format!("Failed to append /status path to broker endpoint {url}"))?;
+    reqwest::blocking::get(status_url)?.error_for_status()?;
+    Ok(())
+}
+
 pub fn stop_broker_process(env: &local_env::LocalEnv) -> anyhow::Result<()> {
     background_process::stop_process(true, "storage_broker", &storage_broker_pid_file_path(env))
 }
@@ -46,6 +46,7 @@ use std::time::Duration;
 
 use anyhow::{anyhow, bail, Context, Result};
 use compute_api::spec::RemoteExtSpec;
+use pageserver_api::shard::ShardStripeSize;
 use serde::{Deserialize, Serialize};
 use utils::id::{NodeId, TenantId, TimelineId};
 
@@ -464,10 +465,44 @@ impl Endpoint {
         }
     }
 
+    /// Point `spec` at the given pageservers: one per shard, in shard number order.
+    /// An unsharded tenant's pageserver goes into `pageserver_connstring`, and a
+    /// sharded tenant's ones into `pageserver_connstrings`.
+    fn set_pageservers(&self, spec: &mut ComputeSpec, pageservers: &[NodeId]) -> Result<()> {
+        let mut connstrings = Vec::new();
+        for pageserver_id in pageservers {
+            let pageserver =
+                PageServerNode::from_env(&self.env, self.env.get_pageserver_conf(*pageserver_id)?);
+            let config = &pageserver.pg_connection_config;
+            let (host, port) = (config.host(), config.port());
+
+            // NOTE: avoid spaces in connection string, because it is less error prone if we forward it somewhere.
+            connstrings.push(format!("postgresql://no_user@{host}:{port}"));
+        }
+        if connstrings.len() == 1 {
+            spec.pageserver_connstring = connstrings.pop();
+            spec.pageserver_connstrings = Vec::new();
+        } else {
+            spec.pageserver_connstring = None;
+            spec.pageserver_connstrings = connstrings;
+        }
+        Ok(())
+    }
+
+    /// The pageserver this endpoint was created or last reconfigured with.  For a sharded
+    /// tenant, this is where shard zero is.
+    pub fn pageserver_id(&self) -> NodeId {
+        self.pageserver.conf.id
+    }
+
+    /// Start the endpoint, reading from `pageservers` (one per shard, in shard number
+    /// order).  `stripe_size` must be set if there is more than one shard.
     pub fn start(
         &self,
         auth_token: &Option<String>,
         safekeepers: Vec<NodeId>,
+        pageservers: Vec<NodeId>,
+        stripe_size: Option<ShardStripeSize>,
         remote_ext_config: Option<&String>,
     ) -> Result<()> {
         if self.status() == "running" {
@@ -482,13 +517,9 @@ impl Endpoint {
             std::fs::remove_dir_all(self.pgdata())?;
         }
 
-        let pageserver_connstring = {
-            let config = &self.pageserver.pg_connection_config;
-            let (host, port) = (config.host(), config.port());
-
-            // NOTE: avoid spaces in connection string, because it is less error prone if we forward it somewhere.
-            format!("postgresql://no_user@{host}:{port}")
-        };
+        if pageservers.is_empty() {
+            bail!("no pageservers given to start the endpoint with");
+        }
         let mut safekeeper_connstrings = Vec::new();
         if self.mode == ComputeMode::Primary {
             for sk_id in safekeepers {
@@ -515,7 +546,7 @@ impl Endpoint {
         };
 
         // Create spec file
-        let spec = ComputeSpec {
+        let mut spec = ComputeSpec {
             skip_pg_catalog_updates: self.skip_pg_catalog_updates,
             format_version: 1.0,
             operation_uuid: None,
@@ -533,11 +564,14 @@ impl Endpoint {
             tenant_id: Some(self.tenant_id),
             timeline_id: Some(self.timeline_id),
             mode: self.mode,
-            pageserver_connstring: Some(pageserver_connstring),
+            pageserver_connstring: None,
+            pageserver_connstrings: Vec::new(),
             safekeeper_connstrings,
             storage_auth_token: auth_token.clone(),
             remote_extensions,
+            shard_stripe_size: stripe_size.map(|s| s.0 as usize),
         };
+        self.set_pageservers(&mut spec, &pageservers)?;
         let spec_path = self.endpoint_path().join("spec.json");
         std::fs::write(spec_path, serde_json::to_string_pretty(&spec)?)?;
 
@@ -658,7 +692,14 @@ impl Endpoint {
         }
     }
 
-    pub fn reconfigure(&self, pageserver_id: Option<NodeId>) -> Result<()> {
+    /// Push a new configuration to the running compute.  If `pageservers` is not empty,
+    /// the compute is pointed at them (one per shard, in shard number order) with the
+    /// given stripe size: otherwise it keeps its current pageservers.
+    pub fn reconfigure(
+        &self,
+        pageservers: Vec<NodeId>,
+        stripe_size: Option<ShardStripeSize>,
+    ) -> Result<()> {
         let mut spec: ComputeSpec = {
             let spec_path = self.endpoint_path().join("spec.json");
             let file = std::fs::File::open(spec_path)?;
@@ -668,23 +709,20 @@ impl Endpoint {
         let postgresql_conf = self.read_postgresql_conf()?;
         spec.cluster.postgresql_conf = Some(postgresql_conf);
 
-        if let Some(pageserver_id) = pageserver_id {
+        if let Some(pageserver_id) = pageservers.first() {
             let endpoint_config_path = self.endpoint_path().join("endpoint.json");
             let mut endpoint_conf: EndpointConf = {
                 let file = std::fs::File::open(&endpoint_config_path)?;
                 serde_json::from_reader(file)?
             };
-            endpoint_conf.pageserver_id = pageserver_id;
+            endpoint_conf.pageserver_id = *pageserver_id;
             std::fs::write(
                 endpoint_config_path,
                 serde_json::to_string_pretty(&endpoint_conf)?,
             )?;
 
-            let pageserver =
-                PageServerNode::from_env(&self.env, self.env.get_pageserver_conf(pageserver_id)?);
-            let ps_http_conf = &pageserver.pg_connection_config;
-            let (host, port) = (ps_http_conf.host(), ps_http_conf.port());
-            spec.pageserver_connstring = Some(format!("postgresql://no_user@{host}:{port}"));
+            self.set_pageservers(&mut spec, &pageservers)?;
+            spec.shard_stripe_size = stripe_size.map(|s| s.0 as usize);
         }
 
         let client = reqwest::blocking::Client::new();
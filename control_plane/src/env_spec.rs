@@ -0,0 +1,778 @@
+//! Declarative description of a local environment, used by `neon_local apply`.
+//!
+//! The description is a TOML file with the same service sections as the
+//! `neon_local init --config` file (broker, pageservers, safekeepers, ...), plus
+//! the tenants, their branch trees and compute endpoints that should exist on top
+//! of them.  See `control_plane/example_env.toml` for an example.
+//!
+//! Applying a description is split in two: [`EnvSpec::plan`] and [`plan_services`]
+//! compare it against what is already there and produce a list of [`Action`]s, and
+//! the `neon_local` binary carries them out.  Nothing is ever deleted: tenants,
+//! branches, endpoints and nodes that are not in the description are left alone.
+//! The settings of a described tenant are the exception: they are replaced by the
+//! described ones, so settings that are not described revert to their defaults.
+//! Applying the same description twice produces no actions the second time.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use anyhow::{bail, Context};
+use compute_api::spec::ComputeMode;
+use pageserver_api::models::TenantConfig;
+use serde::Deserialize;
+use serde_json::Value;
+use utils::{
+    id::{TenantId, TimelineId},
+    lsn::Lsn,
+};
+
+use crate::local_env::{
+    LocalEnv, PageServerConf, SafekeeperConf, DEFAULT_BRANCH_NAME, DEFAULT_PG_VERSION,
+};
+use crate::pageserver::PageServerNode;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct EnvSpec {
+    #[serde(default)]
+    pub tenants: Vec<TenantSpec>,
+
+    #[serde(default)]
+    pub endpoints: Vec<EndpointSpec>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TenantSpec {
+    pub id: TenantId,
+
+    /// Use this tenant in CLI commands where the tenant id is not specified.
+    #[serde(default)]
+    pub default: bool,
+
+    /// Number of shards, placed across pageservers by the attachment service.  Zero
+    /// means an unsharded tenant.
+    #[serde(default)]
+    pub shard_count: u8,
+
+    #[serde(default = "default_pg_version")]
+    pub pg_version: u32,
+
+    /// Tenant settings, as passed to `neon_local tenant create -c key:value`.
+    #[serde(default)]
+    pub config: HashMap<String, toml::Value>,
+
+    #[serde(default)]
+    branches: Vec<BranchSpec>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BranchSpec {
+    pub name: String,
+
+    /// Branch to fork from.  Without a parent, the branch is a new, empty timeline.
+    pub parent: Option<String>,
+
+    /// Lsn on the parent to fork at.  Defaults to the parent's last record Lsn.
+    pub ancestor_start_lsn: Option<Lsn>,
+
+    pub timeline_id: Option<TimelineId>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EndpointSpec {
+    pub name: String,
+
+    pub tenant_id: TenantId,
+
+    #[serde(default = "default_branch_name")]
+    pub branch: String,
+
+    pub pg_port: Option<u16>,
+    pub http_port: Option<u16>,
+
+    /// Defaults to the tenant's postgres version.
+    pub pg_version: Option<u32>,
+
+    /// Makes this a static read-only endpoint at the given Lsn.
+    pub lsn: Option<Lsn>,
+
+    /// Makes this a hot standby replica of the branch.
+    #[serde(default)]
+    pub hot_standby: bool,
+
+    /// Whether the endpoint should be running, or only exist.
+    #[serde(default = "default_running")]
+    pub running: bool,
+}
+
+fn default_pg_version() -> u32 {
+    DEFAULT_PG_VERSION
+}
+
+fn default_branch_name() -> String {
+    DEFAULT_BRANCH_NAME.to_string()
+}
+
+fn default_running() -> bool {
+    true
+}
+
+impl TenantSpec {
+    /// The tenant's branches, parents before their children.
+    pub fn branches(&self) -> anyhow::Result<Vec<BranchSpec>> {
+        if self.branches.is_empty() {
+            return Ok(vec![BranchSpec {
+                name: DEFAULT_BRANCH_NAME.to_string(),
+                parent: None,
+                ancestor_start_lsn: None,
+                timeline_id: None,
+            }]);
+        }
+
+        let mut names = HashSet::new();
+        for branch in &self.branches {
+            if !names.insert(branch.name.as_str()) {
+                bail!("tenant {}: duplicate branch '{}'", self.id, branch.name);
+            }
+        }
+
+        let mut ordered: Vec<BranchSpec> = Vec::with_capacity(self.branches.len());
+        while ordered.len() < self.branches.len() {
+            let created = ordered.len();
+            for branch in &self.branches {
+                if ordered.iter().any(|b| b.name == branch.name) {
+                    continue;
+                }
+                let ready = match &branch.parent {
+                    None => true,
+                    Some(parent) if !names.contains(parent.as_str()) => {
+                        bail!(
+                            "tenant {}: branch '{}' has unknown parent '{parent}'",
+                            self.id,
+                            branch.name
+                        );
+                    }
+                    Some(parent) => ordered.iter().any(|b| &b.name == parent),
+                };
+                if ready {
+                    ordered.push(branch.clone());
+                }
+            }
+            if ordered.len() == created {
+                bail!("tenant {}: branch parents form a cycle", self.id);
+            }
+        }
+        Ok(ordered)
+    }
+
+    /// Tenant settings in the `key:value` string form that the pageserver client parses.
+    pub fn settings(&self) -> HashMap<String, String> {
+        self.config
+            .iter()
+            .map(|(k, v)| {
+                let v = match v {
+                    toml::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                (k.clone(), v)
+            })
+            .collect()
+    }
+
+    /// Tenant settings, parsed as the pageserver takes them.
+    pub fn tenant_config(&self) -> anyhow::Result<TenantConfig> {
+        let settings = self.settings();
+        PageServerNode::parse_config(
+            settings
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect(),
+        )
+        .with_context(|| format!("tenant {}: invalid config", self.id))
+    }
+}
+
+impl EndpointSpec {
+    pub fn mode(&self) -> anyhow::Result<ComputeMode> {
+        match (self.lsn, self.hot_standby) {
+            (Some(lsn), false) => Ok(ComputeMode::Static(lsn)),
+            (None, true) => Ok(ComputeMode::Replica),
+            (None, false) => Ok(ComputeMode::Primary),
+            (Some(_), true) => bail!(
+                "endpoint {}: cannot specify both lsn and hot_standby",
+                self.name
+            ),
+        }
+    }
+}
+
+/// What currently exists in the environment, as far as [`EnvSpec::plan`] is concerned.
+#[derive(Debug, Default)]
+pub struct ObservedEnv {
+    pub tenants: HashMap<TenantId, ObservedTenant>,
+    pub endpoints: HashMap<String, ObservedEndpoint>,
+}
+
+#[derive(Debug)]
+pub struct ObservedTenant {
+    /// Number of shards the tenant has: 1 for an unsharded tenant.
+    pub shard_count: u8,
+    pub timelines: HashSet<TimelineId>,
+    /// The tenant-specific settings of the tenant's first shard.
+    pub config: TenantConfig,
+}
+
+#[derive(Debug)]
+pub struct ObservedEndpoint {
+    pub tenant_id: TenantId,
+    pub timeline_id: TimelineId,
+    pub mode: ComputeMode,
+    pub pg_port: u16,
+    pub http_port: u16,
+    pub running: bool,
+}
+
+/// One step towards the environment described by an [`EnvSpec`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    AddPageserver(PageServerConf),
+    AddSafekeeper(SafekeeperConf),
+    CreateTenant {
+        tenant_id: TenantId,
+        shard_count: u8,
+        settings: HashMap<String, String>,
+    },
+    SplitTenant {
+        tenant_id: TenantId,
+        shard_count: u8,
+    },
+    /// Replace the settings of an existing tenant.
+    ConfigureTenant {
+        tenant_id: TenantId,
+        settings: HashMap<String, String>,
+    },
+    /// Create the branch's timeline, on every shard, and map the branch name to it.
+    CreateBranch {
+        tenant_id: TenantId,
+        name: String,
+        timeline_id: Option<TimelineId>,
+        parent: Option<String>,
+        ancestor_start_lsn: Option<Lsn>,
+        pg_version: u32,
+    },
+    /// The timeline exists already, only the branch name mapping is missing.
+    MapBranch {
+        tenant_id: TenantId,
+        name: String,
+        timeline_id: TimelineId,
+    },
+    SetDefaultTenant(TenantId),
+    StopEndpoint(String),
+    CreateEndpoint {
+        name: String,
+        tenant_id: TenantId,
+        branch: String,
+        pg_port: Option<u16>,
+        http_port: Option<u16>,
+        pg_version: u32,
+        mode: ComputeMode,
+    },
+    StartEndpoint(String),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::AddPageserver(conf) => write!(f, "add pageserver {}", conf.id),
+            Action::AddSafekeeper(conf) => write!(f, "add safekeeper {}", conf.id),
+            Action::CreateTenant {
+                tenant_id,
+                shard_count,
+                ..
+            } => {
+                if *shard_count > 0 {
+                    write!(f, "create tenant {tenant_id} with {shard_count} shards")
+                } else {
+                    write!(f, "create tenant {tenant_id}")
+                }
+            }
+            Action::SplitTenant {
+                tenant_id,
+                shard_count,
+            } => write!(f, "split tenant {tenant_id} into {shard_count} shards"),
+            Action::ConfigureTenant { tenant_id, .. } => write!(f, "configure tenant {tenant_id}"),
+            Action::CreateBranch {
+                tenant_id,
+                name,
+                parent,
+                ..
+            } => match parent {
+                Some(parent) => write!(
+                    f,
+                    "create branch '{name}' of tenant {tenant_id} from '{parent}'"
+                ),
+                None => write!(f, "create branch '{name}' of tenant {tenant_id}"),
+            },
+            Action::MapBranch {
+                tenant_id,
+                name,
+                timeline_id,
+            } => write!(
+                f,
+                "map branch '{name}' of tenant {tenant_id} to timeline {timeline_id}"
+            ),
+            Action::SetDefaultTenant(tenant_id) => {
+                write!(f, "set tenant {tenant_id} as the default")
+            }
+            Action::StopEndpoint(name) => write!(f, "stop endpoint {name}"),
+            Action::CreateEndpoint {
+                name,
+                tenant_id,
+                branch,
+                ..
+            } => write!(
+                f,
+                "create endpoint {name} on branch '{branch}' of tenant {tenant_id}"
+            ),
+            Action::StartEndpoint(name) => write!(f, "start endpoint {name}"),
+        }
+    }
+}
+
+impl EnvSpec {
+    /// Parse the tenants and endpoints of an environment description.  The service
+    /// sections of the same file are parsed by [`LocalEnv::parse_config`].
+    pub fn parse(toml: &str) -> anyhow::Result<Self> {
+        let spec: EnvSpec = toml::from_str(toml)?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut tenants = HashMap::new();
+        for tenant in &self.tenants {
+            if tenants.insert(tenant.id, tenant).is_some() {
+                bail!("duplicate tenant {}", tenant.id);
+            }
+            tenant.branches()?;
+            tenant.tenant_config()?;
+        }
+        if self.tenants.iter().filter(|t| t.default).count() > 1 {
+            bail!("only one tenant can be the default");
+        }
+
+        let mut endpoints = HashSet::new();
+        for endpoint in &self.endpoints {
+            if !endpoints.insert(endpoint.name.as_str()) {
+                bail!("duplicate endpoint {}", endpoint.name);
+            }
+            let tenant = tenants.get(&endpoint.tenant_id).with_context(|| {
+                format!(
+                    "endpoint {}: tenant {} is not described",
+                    endpoint.name, endpoint.tenant_id
+                )
+            })?;
+            if !tenant
+                .branches()?
+                .iter()
+                .any(|branch| branch.name == endpoint.branch)
+            {
+                bail!(
+                    "endpoint {}: tenant {} has no branch '{}'",
+                    endpoint.name,
+                    endpoint.tenant_id,
+                    endpoint.branch
+                );
+            }
+            endpoint.mode()?;
+        }
+        Ok(())
+    }
+
+    /// Compute the actions that take the observed environment to the described one.
+    /// `env` provides the branch name mappings.
+    pub fn plan(&self, env: &LocalEnv, observed: &ObservedEnv) -> anyhow::Result<Vec<Action>> {
+        let mut actions = Vec::new();
+
+        for tenant in &self.tenants {
+            let shard_count = tenant.shard_count.max(1);
+            let existing = observed.tenants.get(&tenant.id);
+            match existing {
+                None => actions.push(Action::CreateTenant {
+                    tenant_id: tenant.id,
+                    shard_count: tenant.shard_count,
+                    settings: tenant.settings(),
+                }),
+                Some(existing) if existing.shard_count < shard_count => {
+                    actions.push(Action::SplitTenant {
+                        tenant_id: tenant.id,
+                        shard_count,
+                    })
+                }
+                Some(existing) if existing.shard_count > shard_count => bail!(
+                    "tenant {} has {} shards, cannot reduce it to {shard_count}",
+                    tenant.id,
+                    existing.shard_count
+                ),
+                Some(_) => {}
+            }
+            if let Some(existing) = existing {
+                if !config_eq(&existing.config, &tenant.tenant_config()?) {
+                    // The pageserver API for changing the settings does not support shards yet
+                    if existing.shard_count > 1 {
+                        bail!(
+                            "tenant {} is sharded, cannot change its config in place",
+                            tenant.id
+                        );
+                    }
+                    actions.push(Action::ConfigureTenant {
+                        tenant_id: tenant.id,
+                        settings: tenant.settings(),
+                    });
+                }
+            }
+
+            for branch in tenant.branches()? {
+                let mapped = env.get_branch_timeline_id(&branch.name, tenant.id);
+                let timeline_id = match (mapped, branch.timeline_id) {
+                    (Some(mapped), Some(wanted)) if mapped != wanted => bail!(
+                        "branch '{}' of tenant {} is mapped to timeline {mapped}, not {wanted}",
+                        branch.name,
+                        tenant.id
+                    ),
+                    (mapped, wanted) => mapped.or(wanted),
+                };
+                let timeline_exists = match (existing, timeline_id) {
+                    (Some(existing), Some(timeline_id)) => {
+                        existing.timelines.contains(&timeline_id)
+                    }
+                    _ => false,
+                };
+
+                if !timeline_exists {
+                    actions.push(Action::CreateBranch {
+                        tenant_id: tenant.id,
+                        name: branch.name.clone(),
+                        timeline_id,
+                        parent: branch.parent.clone(),
+                        ancestor_start_lsn: branch.ancestor_start_lsn,
+                        pg_version: tenant.pg_version,
+                    });
+                } else if mapped.is_none() {
+                    actions.push(Action::MapBranch {
+                        tenant_id: tenant.id,
+                        name: branch.name.clone(),
+                        timeline_id: timeline_id.expect("checked above"),
+                    });
+                }
+            }
+
+            if tenant.default && env.default_tenant_id != Some(tenant.id) {
+                actions.push(Action::SetDefaultTenant(tenant.id));
+            }
+        }
+
+        // Stop endpoints before starting others, in case a primary moves between them.
+        let mut stops = Vec::new();
+        let mut creates = Vec::new();
+        let mut starts = Vec::new();
+        for endpoint in &self.endpoints {
+            let mode = endpoint.mode()?;
+            match observed.endpoints.get(&endpoint.name) {
+                Some(existing) => {
+                    let timeline_id =
+                        env.get_branch_timeline_id(&endpoint.branch, endpoint.tenant_id);
+                    if existing.tenant_id != endpoint.tenant_id
+                        || Some(existing.timeline_id) != timeline_id
+                        || existing.mode != mode
+                        || endpoint.pg_port.is_some_and(|p| p != existing.pg_port)
+                        || endpoint.http_port.is_some_and(|p| p != existing.http_port)
+                    {
+                        bail!(
+                            "endpoint {} exists with a different configuration: destroy it with 'neon_local endpoint stop --destroy' to recreate it",
+                            endpoint.name
+                        );
+                    }
+                    if existing.running && !endpoint.running {
+                        stops.push(Action::StopEndpoint(endpoint.name.clone()));
+                    }
+                }
+                None => {
+                    let tenant = self
+                        .tenants
+                        .iter()
+                        .find(|t| t.id == endpoint.tenant_id)
+                        .expect("validated on parse");
+                    creates.push(Action::CreateEndpoint {
+                        name: endpoint.name.clone(),
+                        tenant_id: endpoint.tenant_id,
+                        branch: endpoint.branch.clone(),
+                        pg_port: endpoint.pg_port,
+                        http_port: endpoint.http_port,
+                        pg_version: endpoint.pg_version.unwrap_or(tenant.pg_version),
+                        mode,
+                    });
+                }
+            }
+
+            let running = observed
+                .endpoints
+                .get(&endpoint.name)
+                .is_some_and(|e| e.running);
+            if endpoint.running && !running {
+                starts.push(Action::StartEndpoint(endpoint.name.clone()));
+            }
+        }
+        actions.extend(stops);
+        actions.extend(creates);
+        actions.extend(starts);
+
+        Ok(actions)
+    }
+}
+
+/// Whether two tenant configs have the same settings.  The pageserver reports durations
+/// in its own notation, e.g. `3600s` as `1h`, so durations are compared by value.
+fn config_eq(a: &TenantConfig, b: &TenantConfig) -> bool {
+    let a = serde_json::to_value(a).expect("tenant config serializes");
+    let b = serde_json::to_value(b).expect("tenant config serializes");
+    setting_eq(&a, &b)
+}
+
+fn setting_eq(a: &Value, b: &Value) -> bool {
+    // Settings that are not set may be missing, null, or objects with no settings set
+    fn is_unset(v: &Value) -> bool {
+        match v {
+            Value::Null => true,
+            Value::Object(o) => o.values().all(is_unset),
+            _ => false,
+        }
+    }
+
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => {
+            match (humantime::parse_duration(a), humantime::parse_duration(b)) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => a == b,
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| setting_eq(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.keys()
+                .chain(b.keys())
+                .all(|key| match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => setting_eq(a, b),
+                    (Some(v), None) | (None, Some(v)) => is_unset(v),
+                    (None, None) => unreachable!("key of one of the objects"),
+                })
+        }
+        (a, b) => (is_unset(a) && is_unset(b)) || a == b,
+    }
+}
+
+/// Compare the services of an initialized environment with the described ones.  New
+/// pageservers and safekeepers can be added, but existing services cannot be changed
+/// in place.
+pub fn plan_services(current: &LocalEnv, desired: &LocalEnv) -> anyhow::Result<Vec<Action>> {
+    if current.broker != desired.broker {
+        bail!("cannot change the broker of an initialized environment");
+    }
+    if current.control_plane_api != desired.control_plane_api {
+        bail!("cannot change the control plane API of an initialized environment");
+    }
+
+    let mut actions = Vec::new();
+    for ps_conf in &desired.pageservers {
+        match current.pageservers.iter().find(|ps| ps.id == ps_conf.id) {
+            Some(existing) if existing != ps_conf => {
+                bail!(
+                    "cannot change the configuration of pageserver {}",
+                    ps_conf.id
+                )
+            }
+            Some(_) => {}
+            None => actions.push(Action::AddPageserver(ps_conf.clone())),
+        }
+    }
+    for sk_conf in &desired.safekeepers {
+        match current.safekeepers.iter().find(|sk| sk.id == sk_conf.id) {
+            Some(existing) if existing != sk_conf => {
+                bail!(
+                    "cannot change the configuration of safekeeper {}",
+                    sk_conf.id
+                )
+            }
+            Some(_) => {}
+            None => actions.push(Action::AddSafekeeper(sk_conf.clone())),
+        }
+    }
+    Ok(actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../example_env.toml");
+
+    fn example() -> (EnvSpec, LocalEnv) {
+        let spec = EnvSpec::parse(EXAMPLE).expect("example parses");
+        let env = LocalEnv::parse_config(EXAMPLE).expect("example services parse");
+        (spec, env)
+    }
+
+    #[test]
+    fn example_parses() {
+        let (spec, env) = example();
+        assert_eq!(spec.tenants.len(), 2);
+        assert_eq!(spec.endpoints.len(), 3);
+        assert_eq!(env.pageservers.len(), 1);
+
+        let branches = spec.tenants[1].branches().unwrap();
+        assert_eq!(branches.len(), 1);
+        assert_eq!(branches[0].name, DEFAULT_BRANCH_NAME);
+    }
+
+    #[test]
+    fn plan_from_scratch() {
+        let (spec, env) = example();
+        let actions = spec.plan(&env, &ObservedEnv::default()).unwrap();
+        let actions: Vec<_> = actions.iter().map(|a| a.to_string()).collect();
+        assert_eq!(
+            actions,
+            [
+                "create tenant 3aa8fcc61f6d357410b7de754b1d9001",
+                "create branch 'main' of tenant 3aa8fcc61f6d357410b7de754b1d9001",
+                "create branch 'migration-check' of tenant 3aa8fcc61f6d357410b7de754b1d9001 from 'main'",
+                "set tenant 3aa8fcc61f6d357410b7de754b1d9001 as the default",
+                "create tenant 5b4e2cc7a8bdd96e50a2e4ffd7d51cd1 with 4 shards",
+                "create branch 'main' of tenant 5b4e2cc7a8bdd96e50a2e4ffd7d51cd1",
+                "create endpoint ep-main on branch 'main' of tenant 3aa8fcc61f6d357410b7de754b1d9001",
+                "create endpoint ep-migration-check on branch 'migration-check' of tenant 3aa8fcc61f6d357410b7de754b1d9001",
+                "create endpoint ep-sharded on branch 'main' of tenant 5b4e2cc7a8bdd96e50a2e4ffd7d51cd1",
+                "start endpoint ep-main",
+                "start endpoint ep-sharded",
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_converged() {
+        let (mut spec, mut env) = example();
+        let mut observed = ObservedEnv::default();
+        let mut timeline_ids = HashMap::new();
+        for tenant in &spec.tenants {
+            let mut timelines = HashSet::new();
+            for branch in tenant.branches().unwrap() {
+                let timeline_id = TimelineId::generate();
+                env.register_branch_mapping(branch.name.clone(), tenant.id, timeline_id)
+                    .unwrap();
+                timelines.insert(timeline_id);
+                timeline_ids.insert((tenant.id, branch.name), timeline_id);
+            }
+            observed.tenants.insert(
+                tenant.id,
+                ObservedTenant {
+                    shard_count: tenant.shard_count.max(1),
+                    timelines,
+                    config: tenant.tenant_config().unwrap(),
+                },
+            );
+        }
+        env.default_tenant_id = Some(spec.tenants[0].id);
+        for (i, endpoint) in spec.endpoints.iter().enumerate() {
+            observed.endpoints.insert(
+                endpoint.name.clone(),
+                ObservedEndpoint {
+                    tenant_id: endpoint.tenant_id,
+                    timeline_id: timeline_ids[&(endpoint.tenant_id, endpoint.branch.clone())],
+                    mode: ComputeMode::Primary,
+                    pg_port: endpoint.pg_port.unwrap_or(60000 + 2 * i as u16),
+                    http_port: endpoint.http_port.unwrap_or(60001 + 2 * i as u16),
+                    running: endpoint.running,
+                },
+            );
+        }
+
+        assert_eq!(spec.plan(&env, &observed).unwrap(), []);
+
+        // A growing shard count is a split, a stopped endpoint gets started
+        observed
+            .tenants
+            .get_mut(&spec.tenants[1].id)
+            .unwrap()
+            .shard_count = 2;
+        observed.endpoints.get_mut("ep-main").unwrap().running = false;
+        assert_eq!(
+            spec.plan(&env, &observed).unwrap(),
+            [
+                Action::SplitTenant {
+                    tenant_id: spec.tenants[1].id,
+                    shard_count: 4
+                },
+                Action::StartEndpoint("ep-main".to_string()),
+            ]
+        );
+
+        // Changed settings are applied, durations are compared by value
+        let tenant_id = spec.tenants[0].id;
+        let config = &mut observed.tenants.get_mut(&tenant_id).unwrap().config;
+        config.gc_horizon = None;
+        config.pitr_interval = Some("1h".to_string());
+        observed
+            .tenants
+            .get_mut(&spec.tenants[1].id)
+            .unwrap()
+            .shard_count = 4;
+        observed.endpoints.get_mut("ep-main").unwrap().running = true;
+        assert_eq!(
+            spec.plan(&env, &observed).unwrap(),
+            [Action::ConfigureTenant {
+                tenant_id,
+                settings: spec.tenants[0].settings(),
+            }]
+        );
+        spec.tenants[0]
+            .config
+            .insert("pitr_interval".to_string(), "3600s".into());
+        let config = &mut observed.tenants.get_mut(&tenant_id).unwrap().config;
+        config.gc_horizon = Some(67108864);
+        assert_eq!(spec.plan(&env, &observed).unwrap(), []);
+
+        // The settings of a sharded tenant cannot be changed
+        spec.tenants[1]
+            .config
+            .insert("gc_horizon".to_string(), toml::Value::Integer(1024));
+        assert!(spec.plan(&env, &observed).is_err());
+        spec.tenants[1].config.clear();
+
+        // An endpoint on another branch cannot be converged
+        observed.endpoints.get_mut("ep-main").unwrap().timeline_id = TimelineId::generate();
+        assert!(spec.plan(&env, &observed).is_err());
+    }
+
+    #[test]
+    fn invalid_branches() {
+        let tenant = |branches: &str| {
+            format!("[[tenants]]\nid = '3aa8fcc61f6d357410b7de754b1d9001'\n{branches}")
+        };
+
+        let unknown_parent = tenant("[[tenants.branches]]\nname = 'a'\nparent = 'b'\n");
+        assert!(EnvSpec::parse(&unknown_parent).is_err());
+
+        let cycle = tenant(
+            "[[tenants.branches]]\nname = 'a'\nparent = 'b'\n\
+             [[tenants.branches]]\nname = 'b'\nparent = 'a'\n",
+        );
+        assert!(EnvSpec::parse(&cycle).is_err());
+
+        let unknown_branch = tenant(
+            "[[endpoints]]\nname = 'ep'\ntenant_id = '3aa8fcc61f6d357410b7de754b1d9001'\nbranch = 'b'\n",
+        );
+        assert!(EnvSpec::parse(&unknown_branch).is_err());
+    }
+}
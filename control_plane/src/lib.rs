@@ -10,6 +10,7 @@ pub mod attachment_service;
 mod background_process;
 pub mod broker;
 pub mod endpoint;
+pub mod env_spec;
 pub mod local_env;
 pub mod pageserver;
 pub mod postgresql_conf;
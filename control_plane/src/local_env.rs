@@ -25,6 +25,9 @@ use crate::safekeeper::SafekeeperNode;
 
 pub const DEFAULT_PG_VERSION: u32 = 15;
 
+/// Name of the initial branch of a tenant.
+pub const DEFAULT_BRANCH_NAME: &str = "main";
+
 //
 // This data structures represents neon_local CLI config
 //
@@ -316,8 +319,12 @@ impl LocalEnv {
 
     /// Locate and load config
     pub fn load_config() -> anyhow::Result<Self> {
-        let repopath = base_path();
+        Self::load_config_from(base_path())
+    }
 
+    /// Load the config of the repository at `repopath`, for processes whose working
+    /// directory and environment do not point at it.
+    pub fn load_config_from(repopath: PathBuf) -> anyhow::Result<Self> {
         if !repopath.exists() {
             bail!(
                 "Neon config is not found in {}. You need to run 'neon_local init' first",
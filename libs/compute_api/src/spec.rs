@@ -45,7 +45,8 @@ pub struct ComputeSpec {
 
     // Information needed to connect to the storage layer.
     //
-    // `tenant_id`, `timeline_id` and `pageserver_connstring` are always needed.
+    // `tenant_id`, `timeline_id` and `pageserver_connstring` (or
+    // `pageserver_connstrings` for a sharded tenant) are always needed.
     //
     // Depending on `mode`, this can be a primary read-write node, a read-only
     // replica, or a read-only node pinned at an older LSN.
@@ -59,8 +60,14 @@ pub struct ComputeSpec {
 
     pub timeline_id: Option<TimelineId>,
 
+    /// Connection string of the pageserver of an unsharded tenant.
     pub pageserver_connstring: Option<String>,
 
+    /// Connection strings of the pageservers of a sharded tenant, one per shard,
+    /// in shard number order. Takes precedence over `pageserver_connstring`.
+    #[serde(default)]
+    pub pageserver_connstrings: Vec<String>,
+
     #[serde(default)]
     pub safekeeper_connstrings: Vec<String>,
 
@@ -73,6 +80,10 @@ pub struct ComputeSpec {
 
     // information about available remote extensions
     pub remote_extensions: Option<RemoteExtSpec>,
+
+    /// Stripe size of a sharded tenant, in pages. Unset for unsharded tenants.
+    #[serde(default)]
+    pub shard_stripe_size: Option<usize>,
 }
 
 /// Feature flag to signal `compute_ctl` to enable certain experimental functionality.
@@ -8,6 +8,7 @@ pub mod key;
 pub mod models;
 pub mod reltag;
 pub mod shard;
+pub mod trace;
 
 pub const DEFAULT_PG_LISTEN_PORT: u16 = 64000;
 pub const DEFAULT_PG_LISTEN_ADDR: &str = formatcp!("127.0.0.1:{DEFAULT_PG_LISTEN_PORT}");
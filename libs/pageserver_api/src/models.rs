@@ -17,7 +17,7 @@ use utils::{
 
 use crate::{reltag::RelTag, shard::TenantShardId};
 use anyhow::bail;
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 /// The state of a tenant in this pageserver.
 ///
@@ -196,7 +196,7 @@ pub struct TenantCreateRequest {
     pub config: TenantConfig, // as we have a flattened field, we should reject all unknown fields in it
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct TenantLoadRequest {
     #[serde(default)]
@@ -214,7 +214,7 @@ impl std::ops::Deref for TenantCreateRequest {
 
 /// An alternative representation of `pageserver::tenant::TenantConf` with
 /// simpler types.
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct TenantConfig {
     pub checkpoint_distance: Option<u64>,
     pub checkpoint_timeout: Option<String>,
@@ -238,6 +238,8 @@ pub struct TenantConfig {
     pub evictions_low_residence_duration_metric_threshold: Option<String>,
     pub gc_feedback: Option<bool>,
     pub heatmap_period: Option<String>,
+    // Parsed by the request handler, like the eviction_policy.
+    pub remote_storage_throttle: Option<serde_json::Value>,
 }
 
 /// A flattened analog of a `pagesever::tenant::LocationMode`, which
@@ -286,7 +288,7 @@ pub struct LocationConfig {
 #[serde(transparent)]
 pub struct TenantCreateResponse(pub TenantId);
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct StatusResponse {
     pub id: NodeId,
 }
@@ -299,6 +301,16 @@ pub struct TenantLocationConfigRequest {
     pub config: LocationConfig, // as we have a flattened field, we should reject all unknown fields in it
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TenantShardSplitRequest {
+    pub new_shard_count: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TenantShardSplitResponse {
+    pub new_shards: Vec<TenantShardId>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct TenantConfigRequest {
@@ -322,7 +334,7 @@ impl TenantConfigRequest {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TenantAttachRequest {
     #[serde(default)]
     pub config: TenantAttachConfig,
@@ -546,6 +558,68 @@ pub enum DownloadRemoteLayersTaskState {
     ShutDown,
 }
 
+/// Where the tar archive read by a timeline import comes from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum TimelineImportSource {
+    /// A file on the pageserver's local filesystem
+    LocalPath { path: String },
+    /// An object in the pageserver's remote storage, relative to its prefix
+    RemotePath { path: String },
+}
+
+/// Import a `pg_basebackup` tarball (`base.tar`) as a new timeline.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimelineImportBasebackupRequest {
+    pub source: TimelineImportSource,
+    pub base_lsn: Lsn,
+    pub pg_version: u32,
+    /// Don't fail the import on pages with bad checksums
+    #[serde(default)]
+    pub skip_checksum_verification: bool,
+}
+
+/// Import a tarball of WAL segments (`pg_wal.tar`) into an existing timeline.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimelineImportWalRequest {
+    pub source: TimelineImportSource,
+    /// Must be the timeline's last record LSN, which is also the default. After a
+    /// failed WAL import, that is where the import stopped, so the same request can
+    /// be retried to resume it.
+    #[serde(default)]
+    pub start_lsn: Option<Lsn>,
+    pub end_lsn: Lsn,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TimelineImportKind {
+    Basebackup,
+    Wal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TimelineImportState {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimelineImportInfo {
+    pub task_id: String,
+    pub kind: TimelineImportKind,
+    pub state: TimelineImportState,
+    /// Size of the archive, if the source reports it
+    pub bytes_total: Option<u64>,
+    pub bytes_read: u64,
+    pub files_imported: u64,
+    pub checksum_failures: u64,
+    /// End of the last WAL record imported
+    pub last_record_lsn: Option<Lsn>,
+    /// Why the import failed, if it did
+    pub error: Option<String>,
+}
+
 pub type ConfigureFailpointsRequest = Vec<FailpointConfig>;
 
 /// Information for configuring a single fail point
@@ -564,6 +638,24 @@ pub struct TimelineGcRequest {
     pub gc_horizon: Option<u64>,
 }
 
+/// Where the requested timestamp falls relative to the timeline's WAL,
+/// see `get_lsn_by_timestamp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LsnForTimestampKind {
+    Present,
+    Future,
+    Past,
+    NoData,
+}
+
+/// Response of `get_lsn_by_timestamp` with `version=2`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LsnByTimestampResponse {
+    pub lsn: Lsn,
+    pub kind: LsnForTimestampKind,
+}
+
 // Wrapped in libpq CopyData
 #[derive(PartialEq, Eq, Debug)]
 pub enum PagestreamFeMessage {
@@ -574,6 +666,7 @@ pub enum PagestreamFeMessage {
 }
 
 // Wrapped in libpq CopyData
+#[derive(Debug)]
 pub enum PagestreamBeMessage {
     Exists(PagestreamExistsResponse),
     Nblocks(PagestreamNblocksResponse),
@@ -766,11 +859,39 @@ impl PagestreamBeMessage {
 
         bytes.into()
     }
+
+    pub fn deserialize(buf: Bytes) -> anyhow::Result<Self> {
+        let mut buf = buf.reader();
+        let msg_tag = buf.read_u8()?;
+        match msg_tag {
+            100 => Ok(Self::Exists(PagestreamExistsResponse {
+                exists: buf.read_u8()? != 0,
+            })),
+            101 => Ok(Self::Nblocks(PagestreamNblocksResponse {
+                n_blocks: buf.read_u32::<BigEndian>()?,
+            })),
+            102 => Ok(Self::GetPage(PagestreamGetPageResponse {
+                page: buf.into_inner(),
+            })),
+            103 => {
+                let rest = buf.into_inner();
+                let Some((0, message)) = rest.split_last() else {
+                    bail!("error response is not null terminated");
+                };
+                Ok(Self::Error(PagestreamErrorResponse {
+                    message: String::from_utf8(message.to_vec())?,
+                }))
+            }
+            104 => Ok(Self::DbSize(PagestreamDbSizeResponse {
+                db_size: buf.read_i64::<BigEndian>()?,
+            })),
+            _ => bail!("unknown smgr message tag: {:?}", msg_tag),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use bytes::Buf;
     use serde_json::json;
 
     use super::*;
@@ -821,6 +942,24 @@ mod tests {
             let reconstructed = PagestreamFeMessage::parse(&mut bytes.reader()).unwrap();
             assert!(msg == reconstructed);
         }
+
+        // Test serialization/deserialization of PagestreamBeMessage
+        let messages = vec![
+            PagestreamBeMessage::Exists(PagestreamExistsResponse { exists: true }),
+            PagestreamBeMessage::Nblocks(PagestreamNblocksResponse { n_blocks: 42 }),
+            PagestreamBeMessage::GetPage(PagestreamGetPageResponse {
+                page: Bytes::from(vec![7; 8192]),
+            }),
+            PagestreamBeMessage::Error(PagestreamErrorResponse {
+                message: "could not read page".to_string(),
+            }),
+            PagestreamBeMessage::DbSize(PagestreamDbSizeResponse { db_size: -1 }),
+        ];
+        for msg in messages {
+            let bytes = msg.serialize();
+            let reconstructed = PagestreamBeMessage::deserialize(bytes.clone()).unwrap();
+            assert_eq!(bytes, reconstructed.serialize());
+        }
     }
 
     #[test]
@@ -919,6 +1058,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_timeline_import_requests_serde() {
+        let basebackup_request = json!({
+            "source": { "kind": "LocalPath", "path": "/tmp/base.tar" },
+            "base_lsn": "0/2000028",
+            "pg_version": 15,
+        });
+        let req =
+            serde_json::from_value::<TimelineImportBasebackupRequest>(basebackup_request).unwrap();
+        let TimelineImportSource::LocalPath { path } = &req.source else {
+            panic!("unexpected source {:?}", req.source);
+        };
+        assert_eq!(path, "/tmp/base.tar");
+        assert_eq!(req.base_lsn, Lsn(0x2000028));
+        assert!(!req.skip_checksum_verification);
+
+        let wal_request = json!({
+            "source": { "kind": "RemotePath", "path": "imports/pg_wal.tar" },
+            "end_lsn": "0/3000100",
+        });
+        let req = serde_json::from_value::<TimelineImportWalRequest>(wal_request).unwrap();
+        let TimelineImportSource::RemotePath { path } = &req.source else {
+            panic!("unexpected source {:?}", req.source);
+        };
+        assert_eq!(path, "imports/pg_wal.tar");
+        assert_eq!(req.start_lsn, None);
+        assert_eq!(req.end_lsn, Lsn(0x3000100));
+    }
+
     #[test]
     fn tenantstatus_activating_serde() {
         let states = [
@@ -3,7 +3,7 @@ use std::cmp::Ordering;
 use std::fmt;
 
 use postgres_ffi::pg_constants::GLOBALTABLESPACE_OID;
-use postgres_ffi::relfile_utils::forknumber_to_name;
+use postgres_ffi::relfile_utils::{forkname_to_number, forknumber_to_name, FilePathError};
 use postgres_ffi::Oid;
 
 ///
@@ -65,6 +65,30 @@ impl fmt::Display for RelTag {
     }
 }
 
+/// Parse RelTag from the format produced by its `Display` implementation.
+impl std::str::FromStr for RelTag {
+    type Err = FilePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        let (Some(spcnode), Some(dbnode), Some(rel), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(FilePathError::InvalidFileName);
+        };
+        let (relnode, forkname) = match rel.split_once('_') {
+            Some((relnode, forkname)) => (relnode, Some(forkname)),
+            None => (rel, None),
+        };
+        Ok(RelTag {
+            forknum: forkname_to_number(forkname)?,
+            spcnode: spcnode.parse()?,
+            dbnode: dbnode.parse()?,
+            relnode: relnode.parse()?,
+        })
+    }
+}
+
 impl RelTag {
     pub fn to_segfile_name(&self, segno: u32) -> String {
         let mut name = if self.spcnode == GLOBALTABLESPACE_OID {
@@ -124,3 +148,21 @@ impl SlruKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reltag_display_roundtrip() {
+        for s in ["1663/13010/1259", "1664/0/1262_vm", "1663/16384/16385_fsm"] {
+            let rel: RelTag = s.parse().unwrap();
+            assert_eq!(rel.to_string(), s);
+        }
+        assert_eq!(
+            "1663/13010/1259_xyz".parse::<RelTag>(),
+            Err(FilePathError::InvalidForkName)
+        );
+        assert!("1663/13010".parse::<RelTag>().is_err());
+    }
+}
@@ -81,6 +81,33 @@ impl TenantShardId {
     pub fn is_zero(&self) -> bool {
         self.shard_number == ShardNumber(0)
     }
+
+    pub fn to_index(&self) -> ShardIndex {
+        ShardIndex {
+            shard_number: self.shard_number,
+            shard_count: self.shard_count,
+        }
+    }
+
+    /// The shards that this shard splits into, if the tenant's shard count is raised
+    /// to `new_shard_count`.
+    ///
+    /// Keys map to shards by their hash modulo the shard count, so a key in shard N of
+    /// a tenant with M shards lands in one of the shards N, N+M, N+2M... after a split,
+    /// as long as the new count is a multiple of the old one: the caller must check this.
+    pub fn split(&self, new_shard_count: ShardCount) -> Vec<TenantShardId> {
+        // An unsharded tenant maps all its keys like a tenant with one shard
+        let effective_old_shard_count = std::cmp::max(self.shard_count.0, 1);
+
+        (0..new_shard_count.0)
+            .filter(|n| n % effective_old_shard_count == self.shard_number.0)
+            .map(|n| TenantShardId {
+                tenant_id: self.tenant_id,
+                shard_number: ShardNumber(n),
+                shard_count: new_shard_count,
+            })
+            .collect()
+    }
 }
 
 /// Formatting helper
@@ -321,7 +348,7 @@ const LAYOUT_V1: ShardLayout = ShardLayout(1);
 const LAYOUT_BROKEN: ShardLayout = ShardLayout(255);
 
 /// Default stripe size in pages: 256MiB divided by 8kiB page size.
-const DEFAULT_STRIPE_SIZE: ShardStripeSize = ShardStripeSize(256 * 1024 / 8);
+pub const DEFAULT_STRIPE_SIZE: ShardStripeSize = ShardStripeSize(256 * 1024 / 8);
 
 /// The ShardIdentity contains the information needed for one member of map
 /// to resolve a key to a shard, and then check whether that shard is ==self.
@@ -329,7 +356,7 @@ const DEFAULT_STRIPE_SIZE: ShardStripeSize = ShardStripeSize(256 * 1024 / 8);
 pub struct ShardIdentity {
     pub number: ShardNumber,
     pub count: ShardCount,
-    stripe_size: ShardStripeSize,
+    pub stripe_size: ShardStripeSize,
     layout: ShardLayout,
 }
 
@@ -418,6 +445,20 @@ impl ShardIdentity {
         }
     }
 
+    /// Return true if the key may be dropped when found in this shard's data, as happens
+    /// after a split: the child shards inherit all of their parent's layers, and shed the
+    /// keys that are not local to them as they compact.
+    ///
+    /// Keys that live on shard 0 are never disposable, because WAL ingest writes some of
+    /// them on every shard, and would fail to find their previous values.
+    pub fn is_key_disposable(&self, key: &Key) -> bool {
+        if key_is_shard0(key) {
+            false
+        } else {
+            !self.is_key_local(key)
+        }
+    }
+
     pub fn shard_slug(&self) -> String {
         if self.count > ShardCount(0) {
             format!("-{:02x}{:02x}", self.number.0, self.count.0)
@@ -759,4 +800,70 @@ mod tests {
         let shard = key_to_shard_number(ShardCount(10), DEFAULT_STRIPE_SIZE, &key);
         assert_eq!(shard, ShardNumber(8));
     }
+
+    #[test]
+    fn shard_split() {
+        let tenant_id = TenantId::from_str(EXAMPLE_TENANT_ID).unwrap();
+        let shard = |number, count| TenantShardId {
+            tenant_id,
+            shard_number: ShardNumber(number),
+            shard_count: ShardCount(count),
+        };
+
+        // An unsharded tenant splits into all the new shards
+        assert_eq!(
+            TenantShardId::unsharded(tenant_id).split(ShardCount(4)),
+            vec![shard(0, 4), shard(1, 4), shard(2, 4), shard(3, 4)]
+        );
+        assert_eq!(
+            shard(1, 2).split(ShardCount(8)),
+            vec![shard(1, 8), shard(3, 8), shard(5, 8), shard(7, 8)]
+        );
+
+        // Every key in a child shard was in its parent
+        for relnode in 0..64 {
+            for blkno in (0..64).map(|b| b * DEFAULT_STRIPE_SIZE.0) {
+                let key = Key {
+                    field1: 0x00,
+                    field2: 0x67f,
+                    field3: 0x5,
+                    field4: relnode,
+                    field5: 0x00,
+                    field6: blkno,
+                };
+                let parent = key_to_shard_number(ShardCount(2), DEFAULT_STRIPE_SIZE, &key);
+                let child = key_to_shard_number(ShardCount(8), DEFAULT_STRIPE_SIZE, &key);
+                assert!(shard(parent.0, 2)
+                    .split(ShardCount(8))
+                    .contains(&shard(child.0, 8)));
+            }
+        }
+    }
+
+    #[test]
+    fn shard_key_disposable() -> Result<(), ShardConfigError> {
+        let rel_block = Key {
+            field1: 0x00,
+            field2: 0x67f,
+            field3: 0x5,
+            field4: 0x400c,
+            field5: 0x00,
+            field6: 0x7d06,
+        };
+        let rel_size = Key {
+            field6: 0xffffffff,
+            ..rel_block
+        };
+
+        let local = ShardIdentity::new(ShardNumber(8), ShardCount(10), DEFAULT_STRIPE_SIZE)?;
+        let remote = ShardIdentity::new(ShardNumber(3), ShardCount(10), DEFAULT_STRIPE_SIZE)?;
+        assert!(!local.is_key_disposable(&rel_block));
+        assert!(remote.is_key_disposable(&rel_block));
+
+        // Shard 0 keys are kept everywhere
+        assert!(!local.is_key_disposable(&rel_size));
+        assert!(!remote.is_key_disposable(&rel_size));
+
+        Ok(())
+    }
 }
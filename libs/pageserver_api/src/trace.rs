@@ -0,0 +1,160 @@
+//! On-disk format of the read traces that the pageserver writes when the
+//! `trace_read_requests` tenant config option is enabled.
+//!
+//! A trace file holds the pagestream requests received on a single connection.
+//! It starts with [`TRACE_FILE_MAGIC`], followed by one record per request: the
+//! time the request was received, as big-endian microseconds since the UNIX epoch,
+//! followed by the serialized [`PagestreamFeMessage`].
+//!
+//! Traces written by older pageservers have no header and no timestamps, just the
+//! concatenated messages. [`TraceReader`] accepts both; records from such files
+//! have no timestamp.
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::models::PagestreamFeMessage;
+
+/// Magic bytes at the start of a timestamped trace file. The first byte can't be
+/// mistaken for a pagestream message tag, which is how legacy files are detected.
+pub const TRACE_FILE_MAGIC: &[u8; 8] = b"NEONTRC\x01";
+
+/// A single traced request.
+#[derive(Debug)]
+pub struct TraceRecord {
+    /// When the pageserver received the request. `None` for legacy traces.
+    pub timestamp: Option<SystemTime>,
+    pub msg: PagestreamFeMessage,
+}
+
+pub fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(TRACE_FILE_MAGIC)
+}
+
+/// Write one record. `msg` is the serialized [`PagestreamFeMessage`], as received
+/// from the client.
+pub fn write_record<W: Write>(writer: &mut W, timestamp: SystemTime, msg: &[u8]) -> io::Result<()> {
+    let micros = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_micros() as u64;
+    writer.write_u64::<BigEndian>(micros)?;
+    writer.write_all(msg)
+}
+
+/// Reads the records of a trace file, in either the timestamped or the legacy format.
+pub struct TraceReader<R> {
+    reader: R,
+    timestamped: bool,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let timestamped = reader.fill_buf()?.starts_with(TRACE_FILE_MAGIC);
+        if timestamped {
+            reader.consume(TRACE_FILE_MAGIC.len());
+        }
+        Ok(TraceReader {
+            reader,
+            timestamped,
+        })
+    }
+
+    /// Whether the records carry the time they were received at.
+    pub fn is_timestamped(&self) -> bool {
+        self.timestamped
+    }
+
+    /// Read the next record, or `None` at the end of the file.
+    pub fn read_record(&mut self) -> anyhow::Result<Option<TraceRecord>> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let timestamp = if self.timestamped {
+            let micros = self.reader.read_u64::<BigEndian>()?;
+            Some(UNIX_EPOCH + Duration::from_micros(micros))
+        } else {
+            None
+        };
+        let msg = PagestreamFeMessage::parse(&mut self.reader)?;
+        Ok(Some(TraceRecord { timestamp, msg }))
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = anyhow::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{PagestreamExistsRequest, PagestreamNblocksRequest};
+    use crate::reltag::RelTag;
+    use utils::lsn::Lsn;
+
+    fn messages() -> Vec<PagestreamFeMessage> {
+        let rel = RelTag {
+            forknum: 1,
+            spcnode: 2,
+            dbnode: 3,
+            relnode: 4,
+        };
+        vec![
+            PagestreamFeMessage::Exists(PagestreamExistsRequest {
+                latest: true,
+                lsn: Lsn(4),
+                rel,
+            }),
+            PagestreamFeMessage::Nblocks(PagestreamNblocksRequest {
+                latest: false,
+                lsn: Lsn(8),
+                rel,
+            }),
+        ]
+    }
+
+    #[test]
+    fn timestamped_roundtrip() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut buf = Vec::new();
+        write_header(&mut buf).unwrap();
+        for (i, msg) in messages().iter().enumerate() {
+            let ts = start + Duration::from_micros(i as u64 * 250);
+            write_record(&mut buf, ts, &msg.serialize()).unwrap();
+        }
+
+        let reader = TraceReader::new(&buf[..]).unwrap();
+        assert!(reader.is_timestamped());
+        let records = reader.collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(records.len(), 2);
+        for (i, (record, msg)) in records.iter().zip(messages()).enumerate() {
+            assert_eq!(
+                record.timestamp,
+                Some(start + Duration::from_micros(i as u64 * 250))
+            );
+            assert!(record.msg == msg);
+        }
+    }
+
+    #[test]
+    fn legacy_format() {
+        let mut buf = Vec::new();
+        for msg in messages() {
+            buf.extend_from_slice(&msg.serialize());
+        }
+
+        let reader = TraceReader::new(&buf[..]).unwrap();
+        assert!(!reader.is_timestamped());
+        let records = reader.collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(records.len(), 2);
+        for (record, msg) in records.iter().zip(messages()) {
+            assert_eq!(record.timestamp, None);
+            assert!(record.msg == msg);
+        }
+    }
+}
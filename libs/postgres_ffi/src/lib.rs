@@ -106,6 +106,7 @@ macro_rules! dispatch_pgversion {
     };
 }
 
+pub mod checksum_utils;
 pub mod pg_constants;
 pub mod relfile_utils;
 
@@ -6,36 +6,50 @@ use std::env;
 use std::num::NonZeroU32;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
 use super::REMOTE_STORAGE_PREFIX_SEPARATOR;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use azure_core::request_options::{MaxResults, Metadata, Range};
 use azure_core::RetryOptions;
 use azure_identity::DefaultAzureCredential;
 use azure_storage::StorageCredentials;
+use azure_storage_blobs::blob::CopyStatus;
 use azure_storage_blobs::prelude::ClientBuilder;
 use azure_storage_blobs::{blob::operations::GetBlobBuilder, prelude::ContainerClient};
 use bytes::Bytes;
 use futures::stream::Stream;
 use futures_util::StreamExt;
 use http_types::StatusCode;
+use tokio_util::sync::CancellationToken;
 use tracing::debug;
 
 use crate::s3_bucket::RequestKind;
+use crate::support::with_timeout;
 use crate::{
-    AzureConfig, ConcurrencyLimiter, Download, DownloadError, Listing, ListingMode, RemotePath,
-    RemoteStorage, StorageMetadata,
+    AzureConfig, ConcurrencyLimiter, Download, DownloadError, Listing, ListingMode, ListingObject,
+    ObjectVersion, RemotePath, RemoteStorage, StorageMetadata,
 };
 
+/// Backoff between the polls of a pending copy's status, doubled after every poll.
+const COPY_POLL_MIN_BACKOFF: Duration = Duration::from_millis(100);
+const COPY_POLL_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
 pub struct AzureBlobStorage {
     client: ContainerClient,
     prefix_in_container: Option<String>,
     max_keys_per_list_response: Option<NonZeroU32>,
     concurrency_limiter: ConcurrencyLimiter,
+    timeout: Duration,
+    small_timeout: Duration,
 }
 
 impl AzureBlobStorage {
-    pub fn new(azure_config: &AzureConfig) -> Result<Self> {
+    pub fn new(
+        azure_config: &AzureConfig,
+        timeout: Duration,
+        small_timeout: Duration,
+    ) -> Result<Self> {
         debug!(
             "Creating azure remote storage for azure container {}",
             azure_config.container_name
@@ -72,6 +86,8 @@ impl AzureBlobStorage {
             prefix_in_container: azure_config.prefix_in_container.to_owned(),
             max_keys_per_list_response,
             concurrency_limiter: ConcurrencyLimiter::new(azure_config.concurrency_limit.get()),
+            timeout,
+            small_timeout,
         })
     }
 
@@ -174,53 +190,58 @@ impl RemoteStorage for AzureBlobStorage {
         &self,
         prefix: Option<&RemotePath>,
         mode: ListingMode,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<Listing, DownloadError> {
-        // get the passed prefix or if it is not set use prefix_in_bucket value
-        let list_prefix = prefix
-            .map(|p| self.relative_path_to_name(p))
-            .or_else(|| self.prefix_in_container.clone())
-            .map(|mut p| {
-                // required to end with a separator
-                // otherwise request will return only the entry of a prefix
-                if matches!(mode, ListingMode::WithDelimiter)
-                    && !p.ends_with(REMOTE_STORAGE_PREFIX_SEPARATOR)
-                {
-                    p.push(REMOTE_STORAGE_PREFIX_SEPARATOR);
-                }
-                p
-            });
+        with_timeout(self.small_timeout, cancel, async {
+            // get the passed prefix or if it is not set use prefix_in_bucket value
+            let list_prefix = prefix
+                .map(|p| self.relative_path_to_name(p))
+                .or_else(|| self.prefix_in_container.clone())
+                .map(|mut p| {
+                    // required to end with a separator
+                    // otherwise request will return only the entry of a prefix
+                    if matches!(mode, ListingMode::WithDelimiter)
+                        && !p.ends_with(REMOTE_STORAGE_PREFIX_SEPARATOR)
+                    {
+                        p.push(REMOTE_STORAGE_PREFIX_SEPARATOR);
+                    }
+                    p
+                });
 
-        let mut builder = self.client.list_blobs();
+            let mut builder = self.client.list_blobs();
 
-        if let ListingMode::WithDelimiter = mode {
-            builder = builder.delimiter(REMOTE_STORAGE_PREFIX_SEPARATOR.to_string());
-        }
+            if let ListingMode::WithDelimiter = mode {
+                builder = builder.delimiter(REMOTE_STORAGE_PREFIX_SEPARATOR.to_string());
+            }
 
-        if let Some(prefix) = list_prefix {
-            builder = builder.prefix(Cow::from(prefix.to_owned()));
-        }
+            if let Some(prefix) = list_prefix {
+                builder = builder.prefix(Cow::from(prefix.to_owned()));
+            }
 
-        if let Some(limit) = self.max_keys_per_list_response {
-            builder = builder.max_results(MaxResults::new(limit));
-        }
+            if let Some(limit) = self.max_keys_per_list_response {
+                builder = builder.max_results(MaxResults::new(limit));
+            }
 
-        let mut response = builder.into_stream();
-        let mut res = Listing::default();
-        while let Some(l) = response.next().await {
-            let entry = l.map_err(to_download_error)?;
-            let prefix_iter = entry
-                .blobs
-                .prefixes()
-                .map(|prefix| self.name_to_relative_path(&prefix.name));
-            res.prefixes.extend(prefix_iter);
-
-            let blob_iter = entry
-                .blobs
-                .blobs()
-                .map(|k| self.name_to_relative_path(&k.name));
-            res.keys.extend(blob_iter);
-        }
-        Ok(res)
+            let mut response = builder.into_stream();
+            let mut res = Listing::default();
+            while let Some(l) = response.next().await {
+                let entry = l.map_err(to_download_error)?;
+                let prefix_iter = entry
+                    .blobs
+                    .prefixes()
+                    .map(|prefix| self.name_to_relative_path(&prefix.name));
+                res.prefixes.extend(prefix_iter);
+
+                let blob_iter = entry.blobs.blobs().map(|k| ListingObject {
+                    key: self.name_to_relative_path(&k.name),
+                    last_modified: k.properties.last_modified.into(),
+                    size: k.properties.content_length,
+                });
+                res.keys.extend(blob_iter);
+            }
+            Ok(res)
+        })
+        .await
     }
 
     async fn upload(
@@ -229,35 +250,46 @@ impl RemoteStorage for AzureBlobStorage {
         data_size_bytes: usize,
         to: &RemotePath,
         metadata: Option<StorageMetadata>,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
-        let _permit = self.permit(RequestKind::Put).await;
-        let blob_client = self.client.blob_client(self.relative_path_to_name(to));
+        with_timeout(self.timeout, cancel, async {
+            let _permit = self.permit(RequestKind::Put).await;
+            let blob_client = self.client.blob_client(self.relative_path_to_name(to));
 
-        let from: Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static>> =
-            Box::pin(from);
+            let from: Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static>> =
+                Box::pin(from);
 
-        let from = NonSeekableStream::new(from, data_size_bytes);
+            let from = NonSeekableStream::new(from, data_size_bytes);
 
-        let body = azure_core::Body::SeekableStream(Box::new(from));
+            let body = azure_core::Body::SeekableStream(Box::new(from));
 
-        let mut builder = blob_client.put_block_blob(body);
+            let mut builder = blob_client.put_block_blob(body);
 
-        if let Some(metadata) = metadata {
-            builder = builder.metadata(to_azure_metadata(metadata));
-        }
+            if let Some(metadata) = metadata {
+                builder = builder.metadata(to_azure_metadata(metadata));
+            }
 
-        let _response = builder.into_future().await?;
+            let _response = builder.into_future().await?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError> {
-        let _permit = self.permit(RequestKind::Get).await;
-        let blob_client = self.client.blob_client(self.relative_path_to_name(from));
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        with_timeout(self.timeout, cancel, async {
+            let _permit = self.permit(RequestKind::Get).await;
+            let blob_client = self.client.blob_client(self.relative_path_to_name(from));
 
-        let builder = blob_client.get();
+            let builder = blob_client.get();
 
-        self.download_for_builder(builder).await
+            self.download_for_builder(builder).await
+        })
+        .await
     }
 
     async fn download_byte_range(
@@ -265,52 +297,123 @@ impl RemoteStorage for AzureBlobStorage {
         from: &RemotePath,
         start_inclusive: u64,
         end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
-        let _permit = self.permit(RequestKind::Get).await;
-        let blob_client = self.client.blob_client(self.relative_path_to_name(from));
+        with_timeout(self.timeout, cancel, async {
+            let _permit = self.permit(RequestKind::Get).await;
+            let blob_client = self.client.blob_client(self.relative_path_to_name(from));
 
-        let mut builder = blob_client.get();
+            let mut builder = blob_client.get();
 
-        let range: Range = if let Some(end_exclusive) = end_exclusive {
-            (start_inclusive..end_exclusive).into()
-        } else {
-            (start_inclusive..).into()
-        };
-        builder = builder.range(range);
+            let range: Range = if let Some(end_exclusive) = end_exclusive {
+                (start_inclusive..end_exclusive).into()
+            } else {
+                (start_inclusive..).into()
+            };
+            builder = builder.range(range);
 
-        self.download_for_builder(builder).await
+            self.download_for_builder(builder).await
+        })
+        .await
     }
 
-    async fn delete(&self, path: &RemotePath) -> anyhow::Result<()> {
-        let _permit = self.permit(RequestKind::Delete).await;
-        let blob_client = self.client.blob_client(self.relative_path_to_name(path));
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        with_timeout(self.small_timeout, cancel, async {
+            let _permit = self.permit(RequestKind::Delete).await;
+            let blob_client = self.client.blob_client(self.relative_path_to_name(path));
 
-        let builder = blob_client.delete();
+            let builder = blob_client.delete();
 
-        match builder.into_future().await {
-            Ok(_response) => Ok(()),
-            Err(e) => {
-                if let Some(http_err) = e.as_http_error() {
-                    if http_err.status() == StatusCode::NotFound {
-                        return Ok(());
+            match builder.into_future().await {
+                Ok(_response) => Ok(()),
+                Err(e) => {
+                    if let Some(http_err) = e.as_http_error() {
+                        if http_err.status() == StatusCode::NotFound {
+                            return Ok(());
+                        }
                     }
+                    Err(anyhow::Error::new(e))
                 }
-                Err(anyhow::Error::new(e))
             }
-        }
+        })
+        .await
     }
 
-    async fn delete_objects<'a>(&self, paths: &'a [RemotePath]) -> anyhow::Result<()> {
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
         // Permit is already obtained by inner delete function
 
         // TODO batch requests are also not supported by the SDK
         // https://github.com/Azure/azure-sdk-for-rust/issues/1068
         // https://github.com/Azure/azure-sdk-for-rust/issues/1249
         for path in paths {
-            self.delete(path).await?;
+            self.delete(path, cancel).await?;
         }
         Ok(())
     }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.timeout, cancel, async {
+            let _permit = self.permit(RequestKind::Copy).await;
+            let source_url = self
+                .client
+                .blob_client(self.relative_path_to_name(from))
+                .url()?;
+            let blob_client = self.client.blob_client(self.relative_path_to_name(to));
+
+            let response = blob_client.copy(source_url).into_future().await?;
+
+            // Copies within the same storage account usually complete right away,
+            // but the service is free to finish them in the background.  The polling
+            // counts towards the operation's timeout, and stops on cancellation.
+            let mut copy_status = response.copy_status;
+            let mut poll_backoff = COPY_POLL_MIN_BACKOFF;
+            while matches!(copy_status, CopyStatus::Pending) {
+                tokio::time::sleep(poll_backoff).await;
+                poll_backoff = std::cmp::min(poll_backoff * 2, COPY_POLL_MAX_BACKOFF);
+                let properties = blob_client.get_properties().into_future().await?;
+                copy_status = properties
+                    .blob
+                    .properties
+                    .copy_status
+                    .context("blob properties have no copy status during copy")?;
+            }
+
+            match copy_status {
+                CopyStatus::Success => Ok(()),
+                status => anyhow::bail!("copy of {from} to {to} has not succeeded: {status:?}"),
+            }
+        })
+        .await
+    }
+
+    async fn list_versions(
+        &self,
+        _prefix: Option<&RemotePath>,
+        _cancel: &CancellationToken,
+    ) -> Result<Vec<ObjectVersion>, DownloadError> {
+        // TODO: Azure supports blob versioning, but we don't use it yet
+        Err(DownloadError::Other(anyhow::anyhow!(
+            "listing versions is not supported for Azure Blob storage"
+        )))
+    }
+
+    async fn restore_version(
+        &self,
+        _key: &RemotePath,
+        _version_id: &str,
+        _cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("restoring versions is not supported for Azure Blob storage")
+    }
 }
 
 pin_project_lite::pin_project! {
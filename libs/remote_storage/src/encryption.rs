@@ -0,0 +1,1241 @@
+//! This module provides a wrapper around a real RemoteStorage implementation that
+//! encrypts the objects on the client side, before they reach the storage.
+//!
+//! Every object is encrypted with its own random data key, which is stored next to the
+//! object in its [`StorageMetadata`], wrapped (encrypted) by the key of the tenant the object
+//! belongs to. Tenant keys never leave the process, and come from a [`KeyProvider`].
+//!
+//! The contents are split into chunks of [`DEFAULT_CHUNK_SIZE`] bytes, each encrypted and
+//! authenticated separately with AES-256-GCM, so that a byte range of the object can be
+//! downloaded and verified without reading the whole object. Chunk nonces are derived from
+//! the chunk index and mark the final chunk, so chunks can neither be reordered nor dropped
+//! from the end of the object unnoticed.
+//!
+//! Both the wrapped data key and every chunk authenticate the object path and the chunk layout
+//! as additional data, so an object can't be passed off as another one, even of the same
+//! tenant, and its size can't be changed by editing the metadata.
+//!
+//! Objects of tenants that have no key are stored and read as is. Objects of tenants with a key
+//! must have the encryption metadata: otherwise anyone able to write to the storage could
+//! substitute a plaintext object of their choosing. Objects uploaded before the tenant got
+//! its key can only be read while migrating, see [`EncryptingWrapper::allowing_unencrypted_reads`].
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, bail, ensure, Context as _};
+use bytes::{Bytes, BytesMut};
+use camino::Utf8Path;
+use futures::stream::Stream;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    Download, DownloadError, Listing, ListingMode, ObjectVersion, RemotePath, RemoteStorage,
+    StorageMetadata,
+};
+
+/// Size of the plaintext in every chunk but the last one.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+const TAG_LEN: usize = 16;
+const DATA_KEY_LEN: usize = 32;
+
+// Metadata entries describing the encryption of an object. Underscores rather than dashes,
+// as Azure requires metadata names to be valid C# identifiers.
+const METADATA_SCHEME: &str = "encryption_scheme";
+const METADATA_KEY_ID: &str = "encryption_key_id";
+const METADATA_DATA_KEY: &str = "encryption_data_key";
+const METADATA_CHUNK_SIZE: &str = "encryption_chunk_size";
+const METADATA_PLAINTEXT_SIZE: &str = "encryption_plaintext_size";
+
+const SCHEME_AES_256_GCM_CHUNKED: &str = "aes-256-gcm-chunked-v1";
+
+/// A tenant's key-encryption key, used to wrap the data keys of the tenant's objects.
+#[derive(Clone)]
+pub struct TenantKey([u8; 32]);
+
+impl TenantKey {
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let key = bytes
+            .try_into()
+            .map_err(|_| anyhow!("tenant key must be 32 bytes long, got {}", bytes.len()))?;
+        Ok(Self(key))
+    }
+
+    fn aead_key(&self) -> LessSafeKey {
+        LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM, &self.0).expect("key has the length of an AES-256 key"),
+        )
+    }
+
+    /// Encrypts a data key with this key, returns the nonce followed by the sealed data key.
+    fn wrap(
+        &self,
+        key_id: &str,
+        object_aad: &[u8],
+        data_key: &[u8; DATA_KEY_LEN],
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("failed to generate a nonce"))?;
+
+        let mut sealed = data_key.to_vec();
+        self.aead_key()
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(data_key_aad(key_id, object_aad)),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow!("failed to wrap the data key"))?;
+
+        let mut wrapped = nonce.to_vec();
+        wrapped.extend_from_slice(&sealed);
+        Ok(wrapped)
+    }
+
+    fn unwrap(
+        &self,
+        key_id: &str,
+        object_aad: &[u8],
+        wrapped: &[u8],
+    ) -> anyhow::Result<[u8; DATA_KEY_LEN]> {
+        ensure!(
+            wrapped.len() == NONCE_LEN + DATA_KEY_LEN + TAG_LEN,
+            "wrapped data key has unexpected length {}",
+            wrapped.len()
+        );
+        let (nonce, sealed) = wrapped.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).expect("nonce has the right length");
+
+        let mut sealed = sealed.to_vec();
+        let data_key = self
+            .aead_key()
+            .open_in_place(
+                nonce,
+                Aad::from(data_key_aad(key_id, object_aad)),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow!("failed to unwrap the data key with key {key_id:?}"))?;
+        Ok(data_key
+            .try_into()
+            .expect("unwrapped data key has the right length"))
+    }
+}
+
+/// The key id is of variable length, so it goes first, prefixed with its length.
+fn data_key_aad(key_id: &str, object_aad: &[u8]) -> Vec<u8> {
+    let mut aad = (key_id.len() as u64).to_be_bytes().to_vec();
+    aad.extend_from_slice(key_id.as_bytes());
+    aad.extend_from_slice(object_aad);
+    aad
+}
+
+impl Debug for TenantKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TenantKey(<redacted>)")
+    }
+}
+
+/// Source of the tenant keys for [`EncryptingWrapper`].
+///
+/// Keys are identified by an id, which is stored with every object, so that the keys can be
+/// rotated: new objects are encrypted with the current key, while the objects encrypted with
+/// the older keys can still be read as long as the provider knows those keys.
+pub trait KeyProvider: Send + Sync {
+    /// The id and the key to encrypt a new object at `path` with,
+    /// or `None` if the object should be stored unencrypted.
+    fn current_key(&self, path: &RemotePath) -> anyhow::Result<Option<(String, TenantKey)>>;
+
+    /// The key with the given id, which the object at `path` was encrypted with.
+    fn key(&self, path: &RemotePath, key_id: &str) -> anyhow::Result<TenantKey>;
+}
+
+/// How an object was encrypted, as recorded in its metadata.
+#[derive(Debug)]
+struct EncryptionHeader {
+    key_id: String,
+    wrapped_data_key: Vec<u8>,
+    chunk_size: usize,
+    plaintext_size: u64,
+}
+
+impl EncryptionHeader {
+    /// Splits the encryption entries off the object's metadata, leaving the user entries.
+    fn split_from(metadata: &mut Option<StorageMetadata>) -> anyhow::Result<Option<Self>> {
+        let Some(StorageMetadata(entries)) = metadata else {
+            return Ok(None);
+        };
+        let Some(scheme) = entries.remove(METADATA_SCHEME) else {
+            return Ok(None);
+        };
+        ensure!(
+            scheme == SCHEME_AES_256_GCM_CHUNKED,
+            "unsupported encryption scheme {scheme:?}"
+        );
+
+        let mut take = |name: &str| {
+            entries
+                .remove(name)
+                .with_context(|| format!("encrypted object has no {name} metadata"))
+        };
+        let header = Self {
+            key_id: take(METADATA_KEY_ID)?,
+            wrapped_data_key: base64::decode(take(METADATA_DATA_KEY)?)
+                .context("invalid wrapped data key")?,
+            chunk_size: take(METADATA_CHUNK_SIZE)?
+                .parse()
+                .context("invalid chunk size")?,
+            plaintext_size: take(METADATA_PLAINTEXT_SIZE)?
+                .parse()
+                .context("invalid plaintext size")?,
+        };
+        ensure!(header.chunk_size > 0, "invalid chunk size 0");
+
+        if entries.is_empty() {
+            *metadata = None;
+        }
+        Ok(Some(header))
+    }
+
+    fn add_to(&self, metadata: Option<StorageMetadata>) -> StorageMetadata {
+        let mut entries = metadata.map(|m| m.0).unwrap_or_default();
+        entries.extend([
+            (
+                METADATA_SCHEME.to_owned(),
+                SCHEME_AES_256_GCM_CHUNKED.to_owned(),
+            ),
+            (METADATA_KEY_ID.to_owned(), self.key_id.clone()),
+            (
+                METADATA_DATA_KEY.to_owned(),
+                base64::encode(&self.wrapped_data_key),
+            ),
+            (METADATA_CHUNK_SIZE.to_owned(), self.chunk_size.to_string()),
+            (
+                METADATA_PLAINTEXT_SIZE.to_owned(),
+                self.plaintext_size.to_string(),
+            ),
+        ]);
+        StorageMetadata(entries)
+    }
+
+    fn chunk_layout(&self) -> ChunkLayout {
+        ChunkLayout {
+            chunk_size: self.chunk_size as u64,
+            plaintext_size: self.plaintext_size,
+        }
+    }
+}
+
+/// Maps the plaintext of an object onto its encrypted chunks.
+#[derive(Debug, Clone, Copy)]
+struct ChunkLayout {
+    chunk_size: u64,
+    plaintext_size: u64,
+}
+
+impl ChunkLayout {
+    /// An empty object still has a single, empty chunk, to authenticate its emptiness.
+    fn last_chunk(&self) -> u64 {
+        self.plaintext_size.saturating_sub(1) / self.chunk_size
+    }
+
+    fn plaintext_chunk_len(&self, index: u64) -> usize {
+        let start = index * self.chunk_size;
+        (self.plaintext_size - start).min(self.chunk_size) as usize
+    }
+
+    fn encrypted_size(&self) -> u64 {
+        self.plaintext_size + (self.last_chunk() + 1) * TAG_LEN as u64
+    }
+
+    fn nonce(&self, index: u64) -> Nonce {
+        let mut nonce = [0; NONCE_LEN];
+        nonce[0] = u8::from(index == self.last_chunk());
+        nonce[NONCE_LEN - 8..].copy_from_slice(&index.to_be_bytes());
+        Nonce::assume_unique_for_key(nonce)
+    }
+
+    /// Additional data identifying the object at `path` with this layout. The path goes last,
+    /// as the only field of variable length.
+    fn object_aad(&self, path: &RemotePath) -> Vec<u8> {
+        let mut aad = self.chunk_size.to_be_bytes().to_vec();
+        aad.extend_from_slice(&self.plaintext_size.to_be_bytes());
+        aad.extend_from_slice(path.get_path().as_str().as_bytes());
+        aad
+    }
+
+    /// Additional data of a chunk: its index and whether it is the final one, then the object.
+    fn chunk_aad(&self, object_aad: &[u8], index: u64) -> Aad<Vec<u8>> {
+        let mut aad = index.to_be_bytes().to_vec();
+        aad.push(u8::from(index == self.last_chunk()));
+        aad.extend_from_slice(object_aad);
+        Aad::from(aad)
+    }
+}
+
+fn data_aead_key(data_key: &[u8; DATA_KEY_LEN]) -> LessSafeKey {
+    LessSafeKey::new(
+        UnboundKey::new(&AES_256_GCM, data_key).expect("key has the length of an AES-256 key"),
+    )
+}
+
+pin_project_lite::pin_project! {
+    /// Encrypts the plaintext stream of an upload chunk by chunk.
+    struct EncryptingStream<S> {
+        #[pin]
+        inner: S,
+        key: LessSafeKey,
+        layout: ChunkLayout,
+        object_aad: Vec<u8>,
+        buffer: BytesMut,
+        next_chunk: u64,
+    }
+}
+
+impl<S: Stream<Item = io::Result<Bytes>>> Stream for EncryptingStream<S> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if *this.next_chunk > this.layout.last_chunk() {
+                return Poll::Ready(None);
+            }
+
+            let chunk_len = this.layout.plaintext_chunk_len(*this.next_chunk);
+            if this.buffer.len() >= chunk_len {
+                let index = *this.next_chunk;
+                *this.next_chunk += 1;
+
+                let mut chunk = this.buffer.split_to(chunk_len);
+                if index == this.layout.last_chunk() && !this.buffer.is_empty() {
+                    return Poll::Ready(Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Provided stream was larger than expected",
+                    ))));
+                }
+                let sealed = this.key.seal_in_place_append_tag(
+                    this.layout.nonce(index),
+                    this.layout.chunk_aad(this.object_aad, index),
+                    &mut chunk,
+                );
+                if sealed.is_err() {
+                    return Poll::Ready(Some(Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "failed to encrypt a chunk",
+                    ))));
+                }
+                return Poll::Ready(Some(Ok(chunk.freeze())));
+            }
+
+            match futures::ready!(this.inner.as_mut().poll_next(cx)) {
+                Some(Ok(bytes)) => this.buffer.extend_from_slice(&bytes),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => {
+                    *this.next_chunk = this.layout.last_chunk() + 1;
+                    return Poll::Ready(Some(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Provided stream was shorter than expected",
+                    ))));
+                }
+            }
+        }
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// Decrypts and verifies a download chunk by chunk, yielding only the requested range
+    /// of the plaintext.
+    struct DecryptingStream<S> {
+        #[pin]
+        inner: S,
+        key: LessSafeKey,
+        layout: ChunkLayout,
+        object_aad: Vec<u8>,
+        buffer: BytesMut,
+        next_chunk: u64,
+        last_chunk: u64,
+        // The requested plaintext range
+        start: u64,
+        end: u64,
+    }
+}
+
+impl<S: Stream<Item = io::Result<Bytes>>> Stream for DecryptingStream<S> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if *this.next_chunk > *this.last_chunk {
+                return Poll::Ready(None);
+            }
+
+            let chunk_len = this.layout.plaintext_chunk_len(*this.next_chunk) + TAG_LEN;
+            if this.buffer.len() >= chunk_len {
+                let index = *this.next_chunk;
+                *this.next_chunk += 1;
+
+                let mut chunk = this.buffer.split_to(chunk_len);
+                let plaintext_len = match this.key.open_in_place(
+                    this.layout.nonce(index),
+                    this.layout.chunk_aad(this.object_aad, index),
+                    &mut chunk,
+                ) {
+                    Ok(plaintext) => plaintext.len(),
+                    Err(_) => {
+                        *this.next_chunk = *this.last_chunk + 1;
+                        return Poll::Ready(Some(Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("failed to decrypt chunk {index} of the object"),
+                        ))));
+                    }
+                };
+                chunk.truncate(plaintext_len);
+
+                let chunk_start = index * this.layout.chunk_size;
+                let to = this
+                    .end
+                    .saturating_sub(chunk_start)
+                    .min(plaintext_len as u64);
+                let from = this.start.saturating_sub(chunk_start).min(to);
+                let plaintext = chunk.freeze().slice(from as usize..to as usize);
+                if plaintext.is_empty() {
+                    continue;
+                }
+                return Poll::Ready(Some(Ok(plaintext)));
+            }
+
+            match futures::ready!(this.inner.as_mut().poll_next(cx)) {
+                Some(Ok(bytes)) => this.buffer.extend_from_slice(&bytes),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => {
+                    *this.next_chunk = *this.last_chunk + 1;
+                    return Poll::Ready(Some(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "encrypted object is truncated",
+                    ))));
+                }
+            }
+        }
+    }
+}
+
+pub struct EncryptingWrapper {
+    inner: crate::GenericRemoteStorage,
+    keys: Arc<dyn KeyProvider>,
+    chunk_size: usize,
+    allow_unencrypted_reads: bool,
+}
+
+impl EncryptingWrapper {
+    pub fn new(inner: crate::GenericRemoteStorage, keys: Arc<dyn KeyProvider>) -> Self {
+        Self::with_chunk_size(inner, keys, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(
+        inner: crate::GenericRemoteStorage,
+        keys: Arc<dyn KeyProvider>,
+        chunk_size: usize,
+    ) -> Self {
+        assert!(chunk_size > 0);
+        EncryptingWrapper {
+            inner,
+            keys,
+            chunk_size,
+            allow_unencrypted_reads: false,
+        }
+    }
+
+    /// Lets objects without the encryption metadata be read as plaintext even if their
+    /// tenant has a key, to migrate the tenants whose objects were uploaded before.
+    pub fn allowing_unencrypted_reads(mut self) -> Self {
+        self.allow_unencrypted_reads = true;
+        self
+    }
+
+    /// The storage holding the encrypted objects.
+    pub(crate) fn inner(&self) -> &crate::GenericRemoteStorage {
+        &self.inner
+    }
+
+    /// Checks that an object without the encryption metadata may be read as is.
+    fn check_unencrypted_read(&self, path: &RemotePath) -> Result<(), DownloadError> {
+        if self.allow_unencrypted_reads {
+            return Ok(());
+        }
+        match self.keys.current_key(path) {
+            Ok(None) => Ok(()),
+            Ok(Some(_)) => Err(DownloadError::Other(anyhow!(
+                "Object at {path} is not encrypted, but its tenant has a key"
+            ))),
+            Err(e) => Err(DownloadError::Other(e)),
+        }
+    }
+
+    fn data_key(
+        &self,
+        path: &RemotePath,
+        header: &EncryptionHeader,
+    ) -> Result<[u8; DATA_KEY_LEN], DownloadError> {
+        let object_aad = header.chunk_layout().object_aad(path);
+        self.keys
+            .key(path, &header.key_id)
+            .and_then(|key| key.unwrap(&header.key_id, &object_aad, &header.wrapped_data_key))
+            .with_context(|| format!("Failed to get the data key of the object at {path}"))
+            .map_err(DownloadError::Other)
+    }
+
+    /// Turns a download of the encrypted chunks `first_chunk..` of an object into a download
+    /// of the `start..end` plaintext range, which must lie within these chunks.
+    fn decrypt(
+        &self,
+        from: &RemotePath,
+        download: Download,
+        header: &EncryptionHeader,
+        first_chunk: u64,
+        start: u64,
+        end: Option<u64>,
+    ) -> Result<Download, DownloadError> {
+        let layout = header.chunk_layout();
+        if start >= layout.plaintext_size && layout.plaintext_size > 0 {
+            return Err(DownloadError::Other(anyhow!(
+                "Invalid range, start ({start}) is beyond the object size ({})",
+                layout.plaintext_size
+            )));
+        }
+        let end = end
+            .unwrap_or(layout.plaintext_size)
+            .min(layout.plaintext_size);
+        let last_chunk = if end > start {
+            (end - 1) / layout.chunk_size
+        } else {
+            // Still verify a chunk, to not return unauthenticated empty ranges.
+            first_chunk
+        };
+
+        let data_key = self.data_key(from, header)?;
+        Ok(Download {
+            download_stream: Box::pin(DecryptingStream {
+                inner: download.download_stream,
+                key: data_aead_key(&data_key),
+                layout,
+                object_aad: layout.object_aad(from),
+                buffer: BytesMut::new(),
+                next_chunk: first_chunk,
+                last_chunk,
+                start,
+                end,
+            }),
+            metadata: download.metadata,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl RemoteStorage for EncryptingWrapper {
+    async fn list_prefixes(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<RemotePath>, DownloadError> {
+        self.inner.list_prefixes(prefix, cancel).await
+    }
+
+    async fn list_files(
+        &self,
+        folder: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Vec<RemotePath>> {
+        self.inner.list_files(folder, cancel).await
+    }
+
+    async fn list(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        self.inner.list(prefix, mode, cancel).await
+    }
+
+    async fn upload(
+        &self,
+        data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        // S3 PUT request requires the content length to be specified,
+        // otherwise it starts to fail with the concurrent connection count increasing.
+        data_size_bytes: usize,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        if let Some(StorageMetadata(entries)) = &metadata {
+            if entries.keys().any(|name| name.starts_with("encryption_")) {
+                bail!("Metadata names starting with 'encryption_' are reserved");
+            }
+        }
+
+        let Some((key_id, tenant_key)) = self.keys.current_key(to)? else {
+            return self
+                .inner
+                .upload(data, data_size_bytes, to, metadata, cancel)
+                .await;
+        };
+
+        let mut data_key = [0; DATA_KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut data_key)
+            .map_err(|_| anyhow!("failed to generate a data key"))?;
+        let layout = ChunkLayout {
+            chunk_size: self.chunk_size as u64,
+            plaintext_size: data_size_bytes as u64,
+        };
+        let object_aad = layout.object_aad(to);
+        let header = EncryptionHeader {
+            wrapped_data_key: tenant_key.wrap(&key_id, &object_aad, &data_key)?,
+            key_id,
+            chunk_size: self.chunk_size,
+            plaintext_size: data_size_bytes as u64,
+        };
+
+        // Boxed, as the inner storage may well be another wrapper of this kind, and the
+        // nested stream types would never end otherwise.
+        let encrypted: Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send + Sync>> =
+            Box::pin(EncryptingStream {
+                inner: data,
+                key: data_aead_key(&data_key),
+                layout,
+                object_aad,
+                buffer: BytesMut::new(),
+                next_chunk: 0,
+            });
+        self.inner
+            .upload(
+                encrypted,
+                layout.encrypted_size() as usize,
+                to,
+                Some(header.add_to(metadata)),
+                cancel,
+            )
+            .await
+    }
+
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        let mut download = self.inner.download(from, cancel).await?;
+        let Some(header) =
+            EncryptionHeader::split_from(&mut download.metadata).map_err(DownloadError::Other)?
+        else {
+            self.check_unencrypted_read(from)?;
+            return Ok(download);
+        };
+        self.decrypt(from, download, &header, 0, 0, None)
+    }
+
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        if let Some(end_exclusive) = end_exclusive {
+            if end_exclusive <= start_inclusive {
+                return Err(DownloadError::Other(anyhow!("Invalid range, start ({start_inclusive}) is not less than end_exclusive ({end_exclusive:?})")));
+            }
+        }
+
+        // The encryption metadata only arrives with the response, so guess that the object
+        // is encrypted with our chunk size and request the chunks covering the range. If the
+        // guess was wrong, request the right range again.
+        let chunk_range = |chunk_size: u64| {
+            let encrypted_chunk_size = chunk_size + TAG_LEN as u64;
+            let first_chunk = start_inclusive / chunk_size;
+            let end = end_exclusive
+                .map(|end| (end - 1) / chunk_size + 1)
+                .map(|end_chunk| end_chunk * encrypted_chunk_size);
+            (first_chunk, first_chunk * encrypted_chunk_size, end)
+        };
+
+        let (first_chunk, start, end) = chunk_range(self.chunk_size as u64);
+        let mut download = self
+            .inner
+            .download_byte_range(from, start, end, cancel)
+            .await?;
+        match EncryptionHeader::split_from(&mut download.metadata).map_err(DownloadError::Other)? {
+            Some(header) if header.chunk_size == self.chunk_size => self.decrypt(
+                from,
+                download,
+                &header,
+                first_chunk,
+                start_inclusive,
+                end_exclusive,
+            ),
+            Some(header) => {
+                let (first_chunk, start, end) = chunk_range(header.chunk_size as u64);
+                let download = self
+                    .inner
+                    .download_byte_range(from, start, end, cancel)
+                    .await?;
+                self.decrypt(
+                    from,
+                    download,
+                    &header,
+                    first_chunk,
+                    start_inclusive,
+                    end_exclusive,
+                )
+            }
+            None => {
+                self.check_unencrypted_read(from)?;
+                self.inner
+                    .download_byte_range(from, start_inclusive, end_exclusive, cancel)
+                    .await
+            }
+        }
+    }
+
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.inner.delete(path, cancel).await
+    }
+
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.inner.delete_objects(paths, cancel).await
+    }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let mut download = self.inner.download(from, cancel).await?;
+        let Some(header) = EncryptionHeader::split_from(&mut download.metadata)? else {
+            self.check_unencrypted_read(from)?;
+            return self.inner.copy(from, to, cancel).await;
+        };
+
+        // Objects are bound to their path, so the copy is encrypted anew for its own path.
+        let metadata = download.metadata.take();
+        let download = self.decrypt(from, download, &header, 0, 0, None)?;
+        self.upload(
+            download.download_stream,
+            header.plaintext_size as usize,
+            to,
+            metadata,
+            cancel,
+        )
+        .await
+    }
+
+    async fn list_versions(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ObjectVersion>, DownloadError> {
+        self.inner.list_versions(prefix, cancel).await
+    }
+
+    async fn restore_version(
+        &self,
+        key: &RemotePath,
+        version_id: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.inner.restore_version(key, version_id, cancel).await
+    }
+}
+
+/// A [`KeyProvider`] with a fixed set of keys per tenant. The tenant of an object is found by
+/// its path: the first path component that is the id of a tenant with keys, or of its shard.
+#[derive(Debug, Default)]
+pub struct StaticKeyProvider {
+    /// Keys by id, the current key of the tenant is the last one.
+    tenants: HashMap<String, Vec<(String, TenantKey)>>,
+}
+
+impl StaticKeyProvider {
+    /// Loads the keys from a TOML file with a `[[keys]]` table per key, each with the
+    /// `tenant_id`, the `key_id` and the base64 encoded 32 bytes of the `key`. The current key
+    /// of a tenant is the last one of the tenant in the file.
+    pub fn from_file(path: &Utf8Path) -> anyhow::Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+        let document = contents
+            .parse::<toml_edit::Document>()
+            .with_context(|| format!("Failed to parse {path}"))?;
+
+        let mut provider = Self::default();
+        let Some(keys) = document.get("keys") else {
+            return Ok(provider);
+        };
+        let keys = keys
+            .as_array_of_tables()
+            .context("'keys' is not an array of tables")?;
+        for (i, entry) in keys.iter().enumerate() {
+            let field = |name: &str| {
+                entry
+                    .get(name)
+                    .and_then(|value| value.as_str())
+                    .with_context(|| format!("key #{i} in {path} has no '{name}' string"))
+            };
+            let key = base64::decode(field("key")?)
+                .with_context(|| format!("key #{i} in {path} is not valid base64"))?;
+            provider.add_key(
+                field("tenant_id")?,
+                field("key_id")?.to_owned(),
+                TenantKey::from_bytes(&key)?,
+            );
+        }
+        Ok(provider)
+    }
+
+    /// Adds a key for the tenant, making it the tenant's current key.
+    pub fn add_key(&mut self, tenant_id: impl Into<String>, key_id: String, key: TenantKey) {
+        self.tenants
+            .entry(tenant_id.into())
+            .or_default()
+            .push((key_id, key));
+    }
+
+    fn tenant_keys(&self, path: &RemotePath) -> Option<&[(String, TenantKey)]> {
+        path.get_path()
+            .components()
+            .find_map(|component| {
+                // Shard ids are the tenant id followed by a shard suffix.
+                let component = component.as_str();
+                let tenant_id = component.split_once('-').map_or(component, |(id, _)| id);
+                self.tenants.get(tenant_id)
+            })
+            .map(Vec::as_slice)
+    }
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn current_key(&self, path: &RemotePath) -> anyhow::Result<Option<(String, TenantKey)>> {
+        Ok(self.tenant_keys(path).and_then(|keys| keys.last()).cloned())
+    }
+
+    fn key(&self, path: &RemotePath, key_id: &str) -> anyhow::Result<TenantKey> {
+        self.tenant_keys(path)
+            .and_then(|keys| keys.iter().find(|(id, _)| id == key_id))
+            .map(|(_, key)| key.clone())
+            .with_context(|| format!("No key {key_id:?} for the tenant of {path}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GenericRemoteStorage, LocalFs, RemoteStorageConfig};
+    use camino_tempfile::Utf8TempDir;
+    use futures::StreamExt;
+    use utils::crashsafe::path_with_suffix_extension;
+
+    const TENANT: &str = "3aa8fcc61f6d357410b7de754b1d9001";
+    const CHUNK_SIZE: usize = 100;
+
+    fn key(byte: u8) -> TenantKey {
+        TenantKey::from_bytes(&[byte; 32]).unwrap()
+    }
+
+    fn local_fs(dir: &Utf8TempDir) -> LocalFs {
+        LocalFs::new(
+            dir.path().to_owned(),
+            RemoteStorageConfig::DEFAULT_TIMEOUT,
+            RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
+        )
+        .unwrap()
+    }
+
+    fn create_storage(keys: StaticKeyProvider) -> (Utf8TempDir, GenericRemoteStorage) {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let inner = GenericRemoteStorage::LocalFs(local_fs(&dir));
+        let storage = GenericRemoteStorage::Encrypted(Arc::new(
+            EncryptingWrapper::with_chunk_size(inner, Arc::new(keys), CHUNK_SIZE),
+        ));
+        (dir, storage)
+    }
+
+    fn tenant_keys() -> StaticKeyProvider {
+        let mut keys = StaticKeyProvider::default();
+        keys.add_key(TENANT, "key-1".to_owned(), key(1));
+        keys
+    }
+
+    fn path(name: &str) -> RemotePath {
+        RemotePath::from_string(&format!("tenants/{TENANT}/timelines/{name}")).unwrap()
+    }
+
+    fn contents(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn upload(
+        storage: &GenericRemoteStorage,
+        path: &RemotePath,
+        contents: &[u8],
+        metadata: Option<StorageMetadata>,
+    ) -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        // Split the contents into uneven pieces, to not line up with the chunks.
+        let pieces = contents
+            .chunks(37)
+            .map(|piece| Ok::<_, io::Error>(Bytes::copy_from_slice(piece)))
+            .collect::<Vec<_>>();
+        storage
+            .upload(
+                futures::stream::iter(pieces),
+                contents.len(),
+                path,
+                metadata,
+                &cancel,
+            )
+            .await
+    }
+
+    async fn read(download: Download) -> io::Result<Vec<u8>> {
+        let mut stream = download.download_stream;
+        let mut buf = Vec::new();
+        while let Some(bytes) = stream.next().await {
+            buf.extend_from_slice(&bytes?);
+        }
+        Ok(buf)
+    }
+
+    #[tokio::test]
+    async fn roundtrip() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let (dir, storage) = create_storage(tenant_keys());
+        let metadata = StorageMetadata(HashMap::from([("one".to_owned(), "1".to_owned())]));
+
+        for len in [
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            10 * CHUNK_SIZE + 7,
+        ] {
+            let path = path(&format!("file_{len}"));
+            let contents = contents(len);
+            upload(&storage, &path, &contents, Some(metadata.clone())).await?;
+
+            let download = storage.download(&path, &cancel).await?;
+            assert_eq!(download.metadata.as_ref(), Some(&metadata));
+            assert_eq!(read(download).await?, contents);
+
+            // The stored object is encrypted
+            let stored = std::fs::read(path.with_base(dir.path()))?;
+            assert_eq!(
+                stored.len(),
+                len + (len.saturating_sub(1) / CHUNK_SIZE + 1) * TAG_LEN
+            );
+            if len > 0 {
+                assert!(!stored.windows(len).any(|w| w == contents.as_slice()));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn byte_ranges() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let (_dir, storage) = create_storage(tenant_keys());
+        let path = path("file");
+        let contents = contents(5 * CHUNK_SIZE + 50);
+        upload(&storage, &path, &contents, None).await?;
+
+        let len = contents.len() as u64;
+        let chunk = CHUNK_SIZE as u64;
+        for (start, end) in [
+            (0, Some(1)),
+            (0, Some(chunk)),
+            (1, Some(chunk + 1)),
+            (chunk - 1, Some(chunk + 1)),
+            (chunk, Some(2 * chunk)),
+            (2 * chunk + 3, Some(4 * chunk + 10)),
+            (len - 1, Some(len)),
+            (3 * chunk + 5, None),
+            (0, None),
+            (10, Some(len + 100)),
+        ] {
+            let download = storage
+                .download_byte_range(&path, start, end, &cancel)
+                .await?;
+            assert_eq!(download.metadata, None);
+            let end = end.unwrap_or(len).min(len);
+            assert_eq!(
+                read(download).await?,
+                &contents[start as usize..end as usize],
+                "range {start}..{end}"
+            );
+        }
+
+        // Objects with a different chunk size than the wrapper's are still readable.
+        let (dir, other_storage) = create_storage(tenant_keys());
+        upload(&other_storage, &path, &contents, None).await?;
+        let inner = GenericRemoteStorage::LocalFs(local_fs(&dir));
+        let storage = EncryptingWrapper::new(inner, Arc::new(tenant_keys()));
+        let download = storage
+            .download_byte_range(&path, 150, Some(420), &cancel)
+            .await?;
+        assert_eq!(read(download).await?, &contents[150..420]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unencrypted_objects() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let (dir, storage) = create_storage(tenant_keys());
+
+        // No key for the tenant: stored as is
+        let other_tenant =
+            RemotePath::from_string("tenants/0f2b0e4ea2d1da8a36d4b6cf8e3a1b5a/timelines/file")?;
+        let contents = contents(3 * CHUNK_SIZE);
+        upload(&storage, &other_tenant, &contents, None).await?;
+        assert_eq!(std::fs::read(other_tenant.with_base(dir.path()))?, contents);
+        assert_eq!(
+            read(storage.download(&other_tenant, &cancel).await?).await?,
+            contents
+        );
+        let download = storage
+            .download_byte_range(&other_tenant, 120, Some(250), &cancel)
+            .await?;
+        assert_eq!(read(download).await?, &contents[120..250]);
+
+        // Objects uploaded before the tenant got its key: a plaintext object could have been
+        // planted by anyone with write access to the storage, so they are only read when
+        // migrating.
+        let path = path("plaintext");
+        std::fs::create_dir_all(path.with_base(dir.path()).parent().unwrap())?;
+        std::fs::write(path.with_base(dir.path()), &contents)?;
+        assert!(storage.download(&path, &cancel).await.is_err());
+        assert!(storage
+            .download_byte_range(&path, 120, Some(250), &cancel)
+            .await
+            .is_err());
+
+        let migrating = GenericRemoteStorage::Encrypted(Arc::new(
+            EncryptingWrapper::with_chunk_size(
+                GenericRemoteStorage::LocalFs(local_fs(&dir)),
+                Arc::new(tenant_keys()),
+                CHUNK_SIZE,
+            )
+            .allowing_unencrypted_reads(),
+        ));
+        assert_eq!(
+            read(migrating.download(&path, &cancel).await?).await?,
+            contents
+        );
+        let download = migrating
+            .download_byte_range(&path, 120, Some(250), &cancel)
+            .await?;
+        assert_eq!(read(download).await?, &contents[120..250]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn keys_from_file() -> anyhow::Result<()> {
+        let dir = camino_tempfile::tempdir()?;
+        let keys_path = dir.path().join("keys.toml");
+        std::fs::write(
+            &keys_path,
+            format!(
+                "[[keys]]\ntenant_id = '{TENANT}'\nkey_id = 'key-1'\nkey = '{}'\n\
+                 [[keys]]\ntenant_id = '{TENANT}'\nkey_id = 'key-2'\nkey = '{}'\n",
+                base64::encode([1; 32]),
+                base64::encode([2; 32]),
+            ),
+        )?;
+
+        let keys = StaticKeyProvider::from_file(&keys_path)?;
+        let (key_id, _) = keys.current_key(&path("file"))?.unwrap();
+        assert_eq!(key_id, "key-2");
+        assert!(keys.key(&path("file"), "key-1").is_ok());
+
+        std::fs::write(
+            &keys_path,
+            format!("[[keys]]\ntenant_id = '{TENANT}'\nkey_id = 'key-1'\nkey = 'AAAA'\n"),
+        )?;
+        assert!(StaticKeyProvider::from_file(&keys_path).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn key_rotation() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let (dir, storage) = create_storage(tenant_keys());
+        let old = path("old");
+        upload(&storage, &old, &contents(250), None).await?;
+
+        let mut keys = tenant_keys();
+        keys.add_key(TENANT, "key-2".to_owned(), key(2));
+        let inner = GenericRemoteStorage::LocalFs(local_fs(&dir));
+        let storage = EncryptingWrapper::with_chunk_size(inner, Arc::new(keys), CHUNK_SIZE);
+        let new = path("new");
+        upload(
+            &GenericRemoteStorage::Encrypted(Arc::new(storage)),
+            &new,
+            &contents(250),
+            None,
+        )
+        .await?;
+
+        // Without the old key, only the new object can be read.
+        let mut keys = StaticKeyProvider::default();
+        keys.add_key(TENANT, "key-2".to_owned(), key(2));
+        let inner = GenericRemoteStorage::LocalFs(local_fs(&dir));
+        let storage = EncryptingWrapper::with_chunk_size(inner, Arc::new(keys), CHUNK_SIZE);
+        assert_eq!(
+            read(storage.download(&new, &cancel).await?).await?,
+            contents(250)
+        );
+        assert!(storage.download(&old, &cancel).await.is_err());
+
+        // A key with a known id but the wrong contents is rejected too.
+        let mut keys = StaticKeyProvider::default();
+        keys.add_key(TENANT, "key-1".to_owned(), key(3));
+        let inner = GenericRemoteStorage::LocalFs(local_fs(&dir));
+        let storage = EncryptingWrapper::with_chunk_size(inner, Arc::new(keys), CHUNK_SIZE);
+        assert!(storage.download(&old, &cancel).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tampering_is_detected() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let (dir, storage) = create_storage(tenant_keys());
+        let path = path("file");
+        let contents = contents(3 * CHUNK_SIZE + 10);
+        upload(&storage, &path, &contents, None).await?;
+        let local_path = path.with_base(dir.path());
+        let stored = std::fs::read(&local_path)?;
+
+        // A flipped bit
+        let mut corrupted = stored.clone();
+        corrupted[CHUNK_SIZE + TAG_LEN + 5] ^= 1;
+        std::fs::write(&local_path, &corrupted)?;
+        assert!(read(storage.download(&path, &cancel).await?).await.is_err());
+        let download = storage
+            .download_byte_range(&path, 0, Some(10), &cancel)
+            .await?;
+        assert_eq!(read(download).await?, &contents[..10]);
+        let download = storage
+            .download_byte_range(
+                &path,
+                CHUNK_SIZE as u64,
+                Some(CHUNK_SIZE as u64 + 10),
+                &cancel,
+            )
+            .await?;
+        assert!(read(download).await.is_err());
+
+        // Truncation at a chunk boundary
+        std::fs::write(&local_path, &stored[..2 * (CHUNK_SIZE + TAG_LEN)])?;
+        assert!(read(storage.download(&path, &cancel).await?).await.is_err());
+
+        // Swapped chunks
+        let mut swapped = stored[CHUNK_SIZE + TAG_LEN..2 * (CHUNK_SIZE + TAG_LEN)].to_vec();
+        swapped.extend_from_slice(&stored[..CHUNK_SIZE + TAG_LEN]);
+        swapped.extend_from_slice(&stored[2 * (CHUNK_SIZE + TAG_LEN)..]);
+        std::fs::write(&local_path, &swapped)?;
+        assert!(read(storage.download(&path, &cancel).await?).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn objects_are_bound_to_path_and_size() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let (dir, storage) = create_storage(tenant_keys());
+        let (a, b) = (path("a"), path("b"));
+        let contents_a = contents(3 * CHUNK_SIZE + 10);
+        upload(&storage, &a, &contents_a, None).await?;
+        upload(&storage, &b, &contents(50), None).await?;
+
+        let local = |path: &RemotePath| {
+            let path = path.with_base(dir.path());
+            let metadata = path_with_suffix_extension(&path, "metadata");
+            (path, metadata)
+        };
+        let (a_path, a_metadata) = local(&a);
+        let (b_path, b_metadata) = local(&b);
+
+        // An object swapped for another object of the same tenant, along with its metadata.
+        std::fs::copy(&a_path, &b_path)?;
+        std::fs::copy(&a_metadata, &b_metadata)?;
+        assert!(storage.download(&b, &cancel).await.is_err());
+
+        // Chunks dropped from the end, with the size in the metadata adjusted to match.
+        let stored = std::fs::read(&a_path)?;
+        let mut metadata: HashMap<String, String> =
+            serde_json::from_str(&std::fs::read_to_string(&a_metadata)?)?;
+        metadata.insert(
+            METADATA_PLAINTEXT_SIZE.to_owned(),
+            (2 * CHUNK_SIZE).to_string(),
+        );
+        std::fs::write(&a_path, &stored[..2 * (CHUNK_SIZE + TAG_LEN)])?;
+        std::fs::write(&a_metadata, serde_json::to_string(&metadata)?)?;
+        assert!(storage.download(&a, &cancel).await.is_err());
+
+        // Copies are encrypted for their new path, and stay readable.
+        let (c, d) = (path("c"), path("d"));
+        upload(&storage, &c, &contents_a, None).await?;
+        storage.copy(&c, &d, &cancel).await?;
+        assert_eq!(
+            read(storage.download(&d, &cancel).await?).await?,
+            contents_a
+        );
+        assert_ne!(std::fs::read(local(&c).0)?, std::fs::read(local(&d).0)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_size_mismatch() -> anyhow::Result<()> {
+        let cancel = CancellationToken::new();
+        let (_dir, storage) = create_storage(tenant_keys());
+        let path = path("file");
+        let contents = contents(250);
+
+        let data = futures::stream::iter([Ok::<_, io::Error>(Bytes::from(contents.clone()))]);
+        storage
+            .upload(data, 300, &path, None, &cancel)
+            .await
+            .expect_err("shorter stream should fail");
+        let data = futures::stream::iter([Ok::<_, io::Error>(Bytes::from(contents.clone()))]);
+        storage
+            .upload(data, 200, &path, None, &cancel)
+            .await
+            .expect_err("longer stream should fail");
+
+        let metadata = StorageMetadata(HashMap::from([(
+            METADATA_KEY_ID.to_owned(),
+            "mine".to_owned(),
+        )]));
+        upload(&storage, &path, &contents, Some(metadata))
+            .await
+            .expect_err("reserved metadata names should be rejected");
+
+        Ok(())
+    }
+}
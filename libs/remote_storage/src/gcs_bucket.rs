@@ -0,0 +1,1030 @@
+//! Google Cloud Storage wrapper, talking to the GCS JSON API directly.
+//!
+//! The S3 interoperability API of GCS has neither batch deletes nor consistent ranged reads,
+//! so the native API is used instead.  Respects `prefix_in_bucket` property from [`GcsConfig`],
+//! the same way as the S3 storage does.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context as _;
+use bytes::Bytes;
+use futures::stream::Stream;
+use futures_util::TryStreamExt;
+use reqwest::header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, RANGE};
+use reqwest::{Body, Method, RequestBuilder, Response, StatusCode, Url};
+use serde::Deserialize;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::s3_bucket::{PermitCarrying, RequestKind};
+use crate::support::{with_timeout, DownloadStream};
+use crate::{
+    ConcurrencyLimiter, Download, DownloadError, GcsConfig, Listing, ListingMode, ListingObject,
+    ObjectVersion, ObjectVersionKind, RemotePath, RemoteStorage, StorageMetadata,
+    REMOTE_STORAGE_PREFIX_SEPARATOR,
+};
+
+/// The endpoint of the real GCS, used when no other endpoint is configured.
+const DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
+
+/// GCS allows up to 100 requests in a single batch request.
+/// <https://cloud.google.com/storage/docs/batch>
+const MAX_KEYS_PER_BATCH_DELETE: usize = 100;
+
+const BATCH_BOUNDARY: &str = "remote_storage_batch_boundary";
+
+const OAUTH_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Access tokens are refreshed this long before they expire.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Google Cloud Storage.
+pub struct GcsBucket {
+    client: reqwest::Client,
+    endpoint: Url,
+    bucket_name: String,
+    prefix_in_bucket: Option<String>,
+    max_keys_per_list_response: Option<i32>,
+    concurrency_limiter: ConcurrencyLimiter,
+    token_provider: TokenProvider,
+    timeout: Duration,
+    small_timeout: Duration,
+}
+
+impl GcsBucket {
+    /// Creates the GCS storage, errors if incorrect GCS configuration provided.
+    pub fn new(
+        gcs_config: &GcsConfig,
+        timeout: Duration,
+        small_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        tracing::debug!(
+            "Creating gcs remote storage for GCS bucket {}",
+            gcs_config.bucket_name
+        );
+
+        let endpoint = gcs_config.endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT);
+        let endpoint = Url::parse(endpoint)
+            .with_context(|| format!("Failed to parse GCS endpoint {endpoint:?}"))?;
+        anyhow::ensure!(
+            !endpoint.cannot_be_a_base(),
+            "GCS endpoint {endpoint} can not be used as a base URL"
+        );
+
+        let credentials = Credentials::from_env(gcs_config.endpoint.is_some())?;
+
+        let prefix_in_bucket = gcs_config.prefix_in_bucket.as_deref().map(|prefix| {
+            prefix
+                .trim_matches(REMOTE_STORAGE_PREFIX_SEPARATOR)
+                .to_string()
+        });
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            endpoint,
+            bucket_name: gcs_config.bucket_name.clone(),
+            prefix_in_bucket,
+            max_keys_per_list_response: gcs_config.max_keys_per_list_response,
+            concurrency_limiter: ConcurrencyLimiter::new(gcs_config.concurrency_limit.get()),
+            token_provider: TokenProvider {
+                credentials,
+                cached: tokio::sync::Mutex::new(None),
+            },
+            timeout,
+            small_timeout,
+        })
+    }
+
+    fn gcs_object_to_relative_path(&self, key: &str) -> RemotePath {
+        let relative_path =
+            match key.strip_prefix(self.prefix_in_bucket.as_deref().unwrap_or_default()) {
+                Some(stripped) => stripped,
+                // we rely on GCS to return properly prefixed paths
+                // for requests with a certain prefix
+                None => panic!(
+                    "Key {} does not start with bucket prefix {:?}",
+                    key, self.prefix_in_bucket
+                ),
+            };
+        RemotePath(
+            relative_path
+                .split(REMOTE_STORAGE_PREFIX_SEPARATOR)
+                .collect(),
+        )
+    }
+
+    pub fn relative_path_to_gcs_object(&self, path: &RemotePath) -> String {
+        assert_eq!(std::path::MAIN_SEPARATOR, REMOTE_STORAGE_PREFIX_SEPARATOR);
+        let path_string = path
+            .get_path()
+            .as_str()
+            .trim_end_matches(REMOTE_STORAGE_PREFIX_SEPARATOR);
+        match &self.prefix_in_bucket {
+            Some(prefix) => prefix.clone() + "/" + path_string,
+            None => path_string.to_string(),
+        }
+    }
+
+    /// `{endpoint}/{segments}`, every segment is escaped, including the `/` in object names.
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.endpoint.clone();
+        url.path_segments_mut()
+            .expect("checked in the constructor")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    fn objects_url(&self) -> Url {
+        self.url(&["storage", "v1", "b", &self.bucket_name, "o"])
+    }
+
+    fn object_url(&self, name: &str) -> Url {
+        self.url(&["storage", "v1", "b", &self.bucket_name, "o", name])
+    }
+
+    async fn permit(&self, kind: RequestKind) -> tokio::sync::SemaphorePermit<'_> {
+        self.concurrency_limiter
+            .acquire(kind)
+            .await
+            .expect("semaphore is never closed")
+    }
+
+    async fn owned_permit(&self, kind: RequestKind) -> tokio::sync::OwnedSemaphorePermit {
+        self.concurrency_limiter
+            .acquire_owned(kind)
+            .await
+            .expect("semaphore is never closed")
+    }
+
+    /// Authorizes and sends the request, turning the unsuccessful responses into errors.
+    async fn send(&self, request: RequestBuilder) -> Result<Response, DownloadError> {
+        let request = match self
+            .token_provider
+            .token(&self.client)
+            .await
+            .map_err(DownloadError::Other)?
+        {
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {token}")),
+            None => request,
+        };
+
+        let response = request
+            .send()
+            .await
+            .context("Failed to send GCS request")
+            .map_err(DownloadError::Other)?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        let error = anyhow::anyhow!("GCS request failed with {status}: {body}");
+        Err(match status {
+            StatusCode::NOT_FOUND => DownloadError::NotFound,
+            StatusCode::BAD_REQUEST | StatusCode::RANGE_NOT_SATISFIABLE => {
+                DownloadError::BadInput(error)
+            }
+            _ => DownloadError::Other(error),
+        })
+    }
+
+    /// Lists all objects under the prefix, going through all the result pages.
+    async fn list_objects(
+        &self,
+        prefix: Option<String>,
+        mode: ListingMode,
+        versions: bool,
+    ) -> Result<ObjectList, DownloadError> {
+        let kind = RequestKind::List;
+        let mut result = ObjectList::default();
+        let mut page_token = None;
+
+        loop {
+            let _guard = self.permit(kind).await;
+
+            let mut request = self.client.get(self.objects_url());
+            if let Some(prefix) = &prefix {
+                request = request.query(&[("prefix", prefix)]);
+            }
+            if let ListingMode::WithDelimiter = mode {
+                request =
+                    request.query(&[("delimiter", REMOTE_STORAGE_PREFIX_SEPARATOR.to_string())]);
+            }
+            if let Some(max_keys) = self.max_keys_per_list_response {
+                request = request.query(&[("maxResults", max_keys)]);
+            }
+            if versions {
+                request = request.query(&[("versions", true)]);
+            }
+            if let Some(page_token) = &page_token {
+                request = request.query(&[("pageToken", page_token)]);
+            }
+
+            let page: ObjectList = self
+                .send(request)
+                .await?
+                .json()
+                .await
+                .context("Failed to parse GCS object list")
+                .map_err(DownloadError::Other)?;
+
+            tracing::debug!(
+                "list: {} prefixes, {} keys",
+                page.prefixes.len(),
+                page.items.len()
+            );
+
+            result.items.extend(page.items);
+            result.prefixes.extend(page.prefixes);
+
+            page_token = match page.next_page_token {
+                Some(new_token) => Some(new_token),
+                None => break,
+            };
+        }
+
+        Ok(result)
+    }
+
+    async fn download_object(
+        &self,
+        from: &RemotePath,
+        range: Option<String>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        with_timeout(self.timeout, cancel, async {
+            let permit = self.owned_permit(RequestKind::Get).await;
+            let object_url = self.object_url(&self.relative_path_to_gcs_object(from));
+
+            // The media download does not return the custom metadata, so get the object resource
+            // first, and then download exactly that generation of the object.
+            let object: ObjectResource = self
+                .send(self.client.get(object_url.clone()))
+                .await?
+                .json()
+                .await
+                .context("Failed to parse GCS object resource")
+                .map_err(DownloadError::Other)?;
+
+            let mut request = self
+                .client
+                .get(object_url)
+                .query(&[("alt", "media"), ("generation", object.generation.as_str())]);
+            if let Some(range) = range {
+                request = request.header(RANGE, range);
+            }
+            let response = self.send(request).await?;
+
+            let body = response
+                .bytes_stream()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+            let metadata =
+                (!object.metadata.is_empty()).then_some(StorageMetadata(object.metadata));
+
+            Ok(Download {
+                download_stream: Box::pin(DownloadStream::new(
+                    self.timeout,
+                    cancel,
+                    PermitCarrying::new(permit, body),
+                )),
+                metadata,
+            })
+        })
+        .await
+    }
+
+    /// Rewrites the object `from`, or its given generation, into the object `to`, along with
+    /// its metadata.  Large objects take several requests to rewrite.
+    async fn rewrite(
+        &self,
+        from: &str,
+        source_generation: Option<&str>,
+        to: &str,
+    ) -> anyhow::Result<()> {
+        let _guard = self.permit(RequestKind::Copy).await;
+        let url = self.url(&[
+            "storage",
+            "v1",
+            "b",
+            &self.bucket_name,
+            "o",
+            from,
+            "rewriteTo",
+            "b",
+            &self.bucket_name,
+            "o",
+            to,
+        ]);
+
+        let mut rewrite_token = None;
+        loop {
+            // An empty body keeps the metadata of the source object
+            let mut request = self.client.post(url.clone()).header(CONTENT_LENGTH, 0);
+            if let Some(generation) = source_generation {
+                request = request.query(&[("sourceGeneration", generation)]);
+            }
+            if let Some(token) = &rewrite_token {
+                request = request.query(&[("rewriteToken", token)]);
+            }
+
+            let response: RewriteResponse = self
+                .send(request)
+                .await?
+                .json()
+                .await
+                .context("Failed to parse GCS rewrite response")?;
+            if response.done {
+                return Ok(());
+            }
+            rewrite_token = Some(
+                response
+                    .rewrite_token
+                    .context("unfinished GCS rewrite without a rewrite token")?,
+            );
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RemoteStorage for GcsBucket {
+    async fn list(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        with_timeout(self.small_timeout, cancel, async {
+            // get the passed prefix or if it is not set use prefix_in_bucket value
+            let list_prefix = prefix
+                .map(|p| self.relative_path_to_gcs_object(p))
+                .or_else(|| self.prefix_in_bucket.clone())
+                .map(|mut p| {
+                    // required to end with a separator
+                    // otherwise request will return only the entry of a prefix
+                    if matches!(mode, ListingMode::WithDelimiter)
+                        && !p.ends_with(REMOTE_STORAGE_PREFIX_SEPARATOR)
+                    {
+                        p.push(REMOTE_STORAGE_PREFIX_SEPARATOR);
+                    }
+                    p
+                });
+
+            let objects = self.list_objects(list_prefix, mode, false).await?;
+
+            let mut result = Listing::default();
+            for object in objects.items {
+                let last_modified =
+                    parse_timestamp(&object.updated).map_err(DownloadError::Other)?;
+                let size = object
+                    .size
+                    .parse()
+                    .with_context(|| format!("GCS object size {:?}", object.size))
+                    .map_err(DownloadError::Other)?;
+                result.keys.push(ListingObject {
+                    key: self.gcs_object_to_relative_path(&object.name),
+                    last_modified,
+                    size,
+                });
+            }
+            result.prefixes.extend(
+                objects
+                    .prefixes
+                    .iter()
+                    .map(|p| self.gcs_object_to_relative_path(p)),
+            );
+            Ok(result)
+        })
+        .await
+    }
+
+    async fn upload(
+        &self,
+        from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        from_size_bytes: usize,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.timeout, cancel, async {
+            let _guard = self.permit(RequestKind::Put).await;
+
+            let name = self.relative_path_to_gcs_object(to);
+            let upload_url = self.url(&["upload", "storage", "v1", "b", &self.bucket_name, "o"]);
+
+            let request = match metadata {
+                // A simple upload can not carry the metadata, it takes a resumable upload instead:
+                // the first request creates the upload session with the object resource, the second
+                // one uploads the contents.
+                Some(StorageMetadata(metadata)) => {
+                    let response = self
+                        .send(
+                            self.client
+                                .post(upload_url)
+                                .query(&[("uploadType", "resumable")])
+                                .json(&serde_json::json!({ "name": name, "metadata": metadata })),
+                        )
+                        .await?;
+                    let session_url = response
+                        .headers()
+                        .get(LOCATION)
+                        .context("GCS resumable upload without a session location")?
+                        .to_str()
+                        .context("GCS resumable upload session location")?;
+                    self.client.put(session_url)
+                }
+                None => self
+                    .client
+                    .post(upload_url)
+                    .query(&[("uploadType", "media"), ("name", name.as_str())]),
+            };
+
+            self.send(
+                request
+                    .header(CONTENT_LENGTH, from_size_bytes)
+                    .body(Body::wrap_stream(from)),
+            )
+            .await
+            .with_context(|| format!("Failed to upload {to} to GCS"))?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        self.download_object(from, None, cancel).await
+    }
+
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        // HTTP ranges are inclusive
+        let range = match end_exclusive {
+            Some(end_exclusive) if end_exclusive <= start_inclusive => {
+                return Err(DownloadError::BadInput(anyhow::anyhow!(
+                    "Invalid range {start_inclusive}..{end_exclusive}"
+                )))
+            }
+            Some(end_exclusive) => format!("bytes={start_inclusive}-{}", end_exclusive - 1),
+            None => format!("bytes={start_inclusive}-"),
+        };
+        self.download_object(from, Some(range), cancel).await
+    }
+
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        with_timeout(self.small_timeout, cancel, async {
+            let _guard = self.permit(RequestKind::Delete).await;
+            let url = self.object_url(&self.relative_path_to_gcs_object(path));
+
+            match self.send(self.client.delete(url)).await {
+                Ok(_) | Err(DownloadError::NotFound) => Ok(()),
+                Err(e) => Err(anyhow::Error::new(e).context(format!("Failed to delete {path}"))),
+            }
+        })
+        .await
+    }
+
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.small_timeout, cancel, async {
+            for chunk in paths.chunks(MAX_KEYS_PER_BATCH_DELETE) {
+                let _guard = self.permit(RequestKind::Delete).await;
+
+                let mut body = String::new();
+                for (i, path) in chunk.iter().enumerate() {
+                    let url = self.object_url(&self.relative_path_to_gcs_object(path));
+                    write!(
+                        body,
+                        "--{BATCH_BOUNDARY}\r\n\
+                     Content-Type: application/http\r\n\
+                     Content-ID: <{i}>\r\n\
+                     \r\n\
+                     DELETE {} HTTP/1.1\r\n\
+                     \r\n",
+                        url.path()
+                    )?;
+                }
+                write!(body, "--{BATCH_BOUNDARY}--\r\n")?;
+
+                let response = self
+                    .send(
+                        self.client
+                            .request(Method::POST, self.url(&["batch", "storage", "v1"]))
+                            .header(
+                                CONTENT_TYPE,
+                                format!("multipart/mixed; boundary={BATCH_BOUNDARY}"),
+                            )
+                            .body(body),
+                    )
+                    .await?;
+                let content_type = response
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .context("GCS batch response without a content type")?
+                    .to_str()
+                    .context("GCS batch response content type")?
+                    .to_owned();
+                let response_body = response
+                    .text()
+                    .await
+                    .context("Failed to read GCS batch response")?;
+
+                let statuses = parse_batch_response(&content_type, &response_body)?;
+                for (i, path) in chunk.iter().enumerate() {
+                    match statuses.get(&i) {
+                        // already deleted objects are fine
+                        Some(status) if status.is_success() || *status == StatusCode::NOT_FOUND => {
+                        }
+                        Some(status) => anyhow::bail!("Failed to delete {path}: {status}"),
+                        None => anyhow::bail!("GCS batch response has no status for {path}"),
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.timeout, cancel, async {
+            self.rewrite(
+                &self.relative_path_to_gcs_object(from),
+                None,
+                &self.relative_path_to_gcs_object(to),
+            )
+            .await
+            .with_context(|| format!("Failed to copy {from} to {to}"))
+        })
+        .await
+    }
+
+    async fn list_versions(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ObjectVersion>, DownloadError> {
+        with_timeout(self.small_timeout, cancel, async {
+            let list_prefix = prefix
+                .map(|p| self.relative_path_to_gcs_object(p))
+                .or_else(|| self.prefix_in_bucket.clone());
+
+            let objects = self
+                .list_objects(list_prefix, ListingMode::NoDelimiter, true)
+                .await?;
+
+            object_versions(objects.items, |name| self.gcs_object_to_relative_path(name))
+                .map_err(DownloadError::Other)
+        })
+        .await
+    }
+
+    async fn restore_version(
+        &self,
+        key: &RemotePath,
+        version_id: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.timeout, cancel, async {
+            let name = self.relative_path_to_gcs_object(key);
+            self.rewrite(&name, Some(version_id), &name)
+                .await
+                .with_context(|| format!("Failed to restore {key} to version {version_id}"))
+        })
+        .await
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct ObjectList {
+    #[serde(default)]
+    items: Vec<ObjectResource>,
+    #[serde(default)]
+    prefixes: Vec<String>,
+    next_page_token: Option<String>,
+}
+
+/// The fields of the object resource that we use, GCS sends the numbers as strings.
+/// <https://cloud.google.com/storage/docs/json_api/v1/objects#resource>
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectResource {
+    name: String,
+    generation: String,
+    size: String,
+    updated: String,
+    time_created: String,
+    /// Set for the noncurrent versions of the object: when it was overwritten or deleted.
+    time_deleted: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RewriteResponse {
+    done: bool,
+    rewrite_token: Option<String>,
+}
+
+fn parse_timestamp(timestamp: &str) -> anyhow::Result<SystemTime> {
+    humantime::parse_rfc3339(timestamp)
+        .with_context(|| format!("Failed to parse GCS timestamp {timestamp:?}"))
+}
+
+/// Converts the generations of the objects into versions, newest first like S3 lists them.
+/// GCS has no delete markers: a deleted object keeps its generations as noncurrent versions,
+/// with the time of the deletion.
+fn object_versions(
+    objects: Vec<ObjectResource>,
+    to_relative_path: impl Fn(&str) -> RemotePath,
+) -> anyhow::Result<Vec<ObjectVersion>> {
+    let generation = |object: &ObjectResource| {
+        object
+            .generation
+            .parse::<u64>()
+            .with_context(|| format!("GCS object generation {:?}", object.generation))
+    };
+    let mut sorted = Vec::with_capacity(objects.len());
+    for object in objects {
+        sorted.push((object.name.clone(), generation(&object)?, object));
+    }
+    sorted.sort_by(|(a_name, a_gen, _), (b_name, b_gen, _)| (a_name, a_gen).cmp(&(b_name, b_gen)));
+
+    let mut versions = Vec::with_capacity(sorted.len());
+    for (i, (name, _, object)) in sorted.iter().enumerate() {
+        let key = to_relative_path(name);
+        let created = parse_timestamp(&object.time_created)?;
+        versions.push(ObjectVersion {
+            key: key.clone(),
+            version_id: object.generation.clone(),
+            last_modified: created,
+            kind: ObjectVersionKind::Version,
+            is_latest: object.time_deleted.is_none(),
+        });
+
+        let Some(time_deleted) = &object.time_deleted else {
+            continue;
+        };
+        let deleted = parse_timestamp(time_deleted)?;
+        // A version overwritten by the next one was not deleted
+        let next_created = match sorted.get(i + 1) {
+            Some((next_name, _, next)) if next_name == name => {
+                Some(parse_timestamp(&next.time_created)?)
+            }
+            _ => None,
+        };
+        if next_created.map_or(true, |next_created| next_created > deleted) {
+            versions.push(ObjectVersion {
+                key,
+                version_id: format!("{}-deleted", object.generation),
+                last_modified: deleted,
+                kind: ObjectVersionKind::DeleteMarker,
+                // Only the last generation of a deleted object has no next one
+                is_latest: next_created.is_none(),
+            });
+        }
+    }
+    versions.reverse();
+    Ok(versions)
+}
+
+/// Returns the statuses of the parts of a `multipart/mixed` batch response, by their index
+/// in the request.
+fn parse_batch_response(
+    content_type: &str,
+    body: &str,
+) -> anyhow::Result<HashMap<usize, StatusCode>> {
+    let boundary = content_type
+        .split(';')
+        .filter_map(|param| param.trim().strip_prefix("boundary="))
+        .next()
+        .with_context(|| format!("no boundary in GCS batch response type {content_type:?}"))?
+        .trim_matches('"');
+
+    let mut statuses = HashMap::new();
+    for part in body.split(&format!("--{boundary}")) {
+        let mut index = None;
+        let mut status = None;
+        for line in part.lines() {
+            if let Some(content_id) = line
+                .strip_prefix("Content-ID:")
+                .or_else(|| line.strip_prefix("content-id:"))
+            {
+                // we send `<i>`, the response has `<response-i>`
+                let content_id = content_id
+                    .trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>');
+                let content_id = content_id.strip_prefix("response-").unwrap_or(content_id);
+                index = Some(
+                    content_id
+                        .parse::<usize>()
+                        .with_context(|| format!("GCS batch response part id {content_id:?}"))?,
+                );
+            } else if line.starts_with("HTTP/") && status.is_none() {
+                let code = line
+                    .split_whitespace()
+                    .nth(1)
+                    .with_context(|| format!("GCS batch response status line {line:?}"))?;
+                status = Some(
+                    code.parse::<u16>()
+                        .ok()
+                        .and_then(|code| StatusCode::from_u16(code).ok())
+                        .with_context(|| format!("GCS batch response status {code:?}"))?,
+                );
+            }
+        }
+        if let (Some(index), Some(status)) = (index, status) {
+            statuses.insert(index, status);
+        }
+    }
+    Ok(statuses)
+}
+
+/// Where the access tokens for the requests come from.
+enum Credentials {
+    /// No authentication, for the GCS emulators.
+    Anonymous,
+    /// A token obtained elsewhere, e.g. with `gcloud auth print-access-token`.
+    Static(String),
+    /// A service account key file, exchanged for the access tokens.
+    ServiceAccount(ServiceAccountKey),
+    /// The service account of the GCE instance, from the metadata server.
+    MetadataServer,
+}
+
+impl Credentials {
+    /// Uses the `GOOGLE_OAUTH_ACCESS_TOKEN` or the `GOOGLE_APPLICATION_CREDENTIALS` key file
+    /// if set.  Otherwise, custom endpoints are assumed to be emulators not requiring any
+    /// authentication, and the real GCS is accessed with the instance service account.
+    fn from_env(custom_endpoint: bool) -> anyhow::Result<Self> {
+        if let Ok(token) = std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+            return Ok(Self::Static(token));
+        }
+        if let Ok(path) = std::env::var("GOOGLE_APPLICATION_CREDENTIALS") {
+            let key = std::fs::read(&path)
+                .with_context(|| format!("Failed to read GCS credentials from {path}"))?;
+            let key = serde_json::from_slice(&key)
+                .with_context(|| format!("Failed to parse GCS service account key {path}"))?;
+            return Ok(Self::ServiceAccount(key));
+        }
+        Ok(if custom_endpoint {
+            Self::Anonymous
+        } else {
+            Self::MetadataServer
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(serde::Serialize)]
+struct JwtClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+struct AccessToken {
+    token: String,
+    expires_at: Instant,
+}
+
+struct TokenProvider {
+    credentials: Credentials,
+    cached: tokio::sync::Mutex<Option<AccessToken>>,
+}
+
+impl TokenProvider {
+    /// Returns the access token to authorize the requests with, if any.
+    async fn token(&self, client: &reqwest::Client) -> anyhow::Result<Option<String>> {
+        match &self.credentials {
+            Credentials::Anonymous => return Ok(None),
+            Credentials::Static(token) => return Ok(Some(token.clone())),
+            Credentials::ServiceAccount(_) | Credentials::MetadataServer => {}
+        }
+
+        let mut cached = self.cached.lock().await;
+        if let Some(cached) = cached.as_ref() {
+            if Instant::now() + TOKEN_EXPIRY_MARGIN < cached.expires_at {
+                return Ok(Some(cached.token.clone()));
+            }
+        }
+
+        let requested_at = Instant::now();
+        let response = match &self.credentials {
+            Credentials::ServiceAccount(key) => {
+                let iat = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .context("system time before the unix epoch")?
+                    .as_secs();
+                let claims = JwtClaims {
+                    iss: &key.client_email,
+                    scope: OAUTH_SCOPE,
+                    aud: &key.token_uri,
+                    iat,
+                    exp: iat + 3600,
+                };
+                let assertion = jsonwebtoken::encode(
+                    &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+                    &claims,
+                    &jsonwebtoken::EncodingKey::from_rsa_pem(key.private_key.as_bytes())
+                        .context("GCS service account private key")?,
+                )
+                .context("Failed to sign GCS token request")?;
+                client.post(&key.token_uri).form(&[
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                    ("assertion", assertion.as_str()),
+                ])
+            }
+            Credentials::MetadataServer => client
+                .get(METADATA_TOKEN_URL)
+                .header("Metadata-Flavor", "Google"),
+            Credentials::Anonymous | Credentials::Static(_) => unreachable!(),
+        }
+        .send()
+        .await
+        .and_then(Response::error_for_status)
+        .context("Failed to get GCS access token")?;
+
+        let response: TokenResponse = response
+            .json()
+            .await
+            .context("Failed to parse GCS access token")?;
+        let token = response.access_token.clone();
+        *cached = Some(AccessToken {
+            token: response.access_token,
+            expires_at: requested_at + Duration::from_secs(response.expires_in),
+        });
+        Ok(Some(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(name: &str, generation: u64, created: &str, deleted: Option<&str>) -> ObjectResource {
+        ObjectResource {
+            name: name.to_owned(),
+            generation: generation.to_string(),
+            size: "0".to_owned(),
+            updated: created.to_owned(),
+            time_created: created.to_owned(),
+            time_deleted: deleted.map(str::to_owned),
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn deleted_generations_become_delete_markers() {
+        let objects = vec![
+            object("overwritten", 2, "2024-01-01T00:00:10Z", None),
+            object(
+                "overwritten",
+                1,
+                "2024-01-01T00:00:00Z",
+                Some("2024-01-01T00:00:10Z"),
+            ),
+            object(
+                "deleted",
+                3,
+                "2024-01-01T00:00:00Z",
+                Some("2024-01-01T00:00:20Z"),
+            ),
+            object(
+                "recreated",
+                4,
+                "2024-01-01T00:00:00Z",
+                Some("2024-01-01T00:00:20Z"),
+            ),
+            object("recreated", 5, "2024-01-01T00:00:30Z", None),
+        ];
+        let versions = object_versions(objects, |name| RemotePath::from_string(name).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.key.to_string(), v.version_id, v.kind, v.is_latest))
+            .collect::<Vec<_>>();
+
+        use ObjectVersionKind::*;
+        assert_eq!(
+            versions,
+            [
+                ("recreated".to_owned(), "5".to_owned(), Version, true),
+                (
+                    "recreated".to_owned(),
+                    "4-deleted".to_owned(),
+                    DeleteMarker,
+                    false
+                ),
+                ("recreated".to_owned(), "4".to_owned(), Version, false),
+                ("overwritten".to_owned(), "2".to_owned(), Version, true),
+                ("overwritten".to_owned(), "1".to_owned(), Version, false),
+                (
+                    "deleted".to_owned(),
+                    "3-deleted".to_owned(),
+                    DeleteMarker,
+                    true
+                ),
+                ("deleted".to_owned(), "3".to_owned(), Version, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn batch_response_statuses() {
+        let body = "--batch_abc\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: <response-1>\r\n\
+            \r\n\
+            HTTP/1.1 404 Not Found\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {\"error\": {\"code\": 404}}\r\n\
+            --batch_abc\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: <response-0>\r\n\
+            \r\n\
+            HTTP/1.1 204 No Content\r\n\
+            \r\n\
+            --batch_abc\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: <response-2>\r\n\
+            \r\n\
+            HTTP/1.1 429 Too Many Requests\r\n\
+            \r\n\
+            --batch_abc--\r\n";
+
+        let statuses = parse_batch_response("multipart/mixed; boundary=batch_abc", body).unwrap();
+        assert_eq!(
+            statuses,
+            HashMap::from([
+                (0, StatusCode::NO_CONTENT),
+                (1, StatusCode::NOT_FOUND),
+                (2, StatusCode::TOO_MANY_REQUESTS),
+            ])
+        );
+    }
+
+    #[test]
+    fn object_names_are_escaped() {
+        let bucket = GcsBucket::new(
+            &GcsConfig {
+                bucket_name: "bucket".to_owned(),
+                prefix_in_bucket: Some("/pageserver/".to_owned()),
+                endpoint: Some("http://127.0.0.1:4443".to_owned()),
+                concurrency_limit: std::num::NonZeroUsize::new(1).unwrap(),
+                max_keys_per_list_response: None,
+            },
+            Duration::from_secs(120),
+            Duration::from_secs(30),
+        )
+        .unwrap();
+
+        let path = RemotePath::from_string("tenants/a b/index_part.json").unwrap();
+        let name = bucket.relative_path_to_gcs_object(&path);
+        assert_eq!(name, "pageserver/tenants/a b/index_part.json");
+        assert_eq!(
+            bucket.object_url(&name).as_str(),
+            "http://127.0.0.1:4443/storage/v1/b/bucket/o/pageserver%2Ftenants%2Fa%20b%2Findex_part.json"
+        );
+        assert_eq!(bucket.gcs_object_to_relative_path(&name), path);
+    }
+}
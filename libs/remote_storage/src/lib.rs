@@ -5,16 +5,31 @@
 //!   * [`local_fs`] allows to use local file system as an external storage
 //!   * [`s3_bucket`] uses AWS S3 bucket as an external storage
 //!   * [`azure_blob`] allows to use Azure Blob storage as an external storage
+//!   * [`gcs_bucket`] uses Google Cloud Storage bucket as an external storage
+//!
+//! [`EncryptingWrapper`] can be put on top of any of them, to encrypt the objects on the client side,
+//! and [`ReplicatedStorage`] mirrors the writes to one of them into another one, for disaster recovery.
 //!
 #![deny(unsafe_code)]
 #![deny(clippy::undocumented_unsafe_blocks)]
 
 mod azure_blob;
+mod encryption;
+mod gcs_bucket;
 mod local_fs;
+mod replicated;
 mod s3_bucket;
 mod simulate_failures;
-
-use std::{collections::HashMap, fmt::Debug, num::NonZeroUsize, pin::Pin, sync::Arc};
+mod support;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{bail, Context};
 use camino::{Utf8Path, Utf8PathBuf};
@@ -23,12 +38,19 @@ use bytes::Bytes;
 use futures::stream::Stream;
 use serde::{Deserialize, Serialize};
 use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
 use toml_edit::Item;
 use tracing::info;
 
 pub use self::{
-    azure_blob::AzureBlobStorage, local_fs::LocalFs, s3_bucket::S3Bucket,
+    azure_blob::AzureBlobStorage,
+    encryption::{EncryptingWrapper, KeyProvider, StaticKeyProvider, TenantKey},
+    gcs_bucket::GcsBucket,
+    local_fs::LocalFs,
+    replicated::{MirrorMode, ReplicatedStorage},
+    s3_bucket::S3Bucket,
     simulate_failures::UnreliableWrapper,
+    support::TimeoutOrCancel,
 };
 use s3_bucket::RequestKind;
 
@@ -43,6 +65,10 @@ pub const DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT: usize = 100;
 /// Here, a limit of max 20k concurrent connections was noted.
 /// <https://learn.microsoft.com/en-us/answers/questions/1301863/is-there-any-limitation-to-concurrent-connections>
 pub const DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT: usize = 30;
+/// GCS has no fixed per-bucket request limits, it scales with the load, but ramping up
+/// takes time, so start the same way as S3 does.
+/// <https://cloud.google.com/storage/docs/request-rate>
+pub const DEFAULT_REMOTE_STORAGE_GCS_CONCURRENCY_LIMIT: usize = 100;
 /// No limits on the client side, which currenltly means 1000 for AWS S3.
 /// <https://docs.aws.amazon.com/AmazonS3/latest/API/API_ListObjectsV2.html#API_ListObjectsV2_RequestSyntax>
 pub const DEFAULT_MAX_KEYS_PER_LIST_RESPONSE: Option<i32> = None;
@@ -126,6 +152,7 @@ impl RemotePath {
 ///
 /// The WithDelimiter mode will populate `prefixes` and `keys` in the result.  The
 /// NoDelimiter mode will only populate `keys`.
+#[derive(Debug, Clone, Copy)]
 pub enum ListingMode {
     WithDelimiter,
     NoDelimiter,
@@ -134,12 +161,95 @@ pub enum ListingMode {
 #[derive(Default)]
 pub struct Listing {
     pub prefixes: Vec<RemotePath>,
-    pub keys: Vec<RemotePath>,
+    pub keys: Vec<ListingObject>,
+}
+
+/// An object returned by [`RemoteStorage::list`], with the metadata that the listing
+/// returns without extra requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingObject {
+    pub key: RemotePath,
+    pub last_modified: SystemTime,
+    /// Size of the object, as stored in the remote storage.
+    pub size: u64,
+}
+
+/// A version of a remote storage entry, kept by storages with versioning enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectVersion {
+    pub key: RemotePath,
+    pub version_id: String,
+    /// S3 only has a resolution of a second, so versions of the same entry can have the same
+    /// modification time: they are listed newest first, and `is_latest` tells the current one.
+    pub last_modified: SystemTime,
+    pub kind: ObjectVersionKind,
+    /// Whether this is the current version of the entry.
+    pub is_latest: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectVersionKind {
+    /// The contents of the entry, as uploaded at the time.
+    Version,
+    /// The entry was deleted at the time.
+    DeleteMarker,
+}
+
+/// What [`RemoteStorage::time_travel_recover`] needs to do with a single entry.
+#[derive(Debug, PartialEq, Eq)]
+enum RecoveryAction<'a> {
+    /// Make the given version the current one again.
+    Restore(&'a ObjectVersion),
+    /// The entry didn't exist at the time.
+    Delete(&'a RemotePath),
+}
+
+/// Compares the current state of every entry in `versions` with its state at `timestamp`.
+///
+/// Versions of an entry with the same modification time are told apart by `is_latest`, and
+/// then by their order in the listing, which is newest first.
+fn time_travel_recovery_actions(
+    versions: &[ObjectVersion],
+    timestamp: SystemTime,
+) -> Vec<RecoveryAction<'_>> {
+    let mut by_key = BTreeMap::<&RemotePath, Vec<&ObjectVersion>>::new();
+    for version in versions {
+        by_key.entry(&version.key).or_default().push(version);
+    }
+
+    let mut actions = Vec::new();
+    for (key, versions) in by_key {
+        let latest = versions.iter().find(|v| v.is_latest);
+        // `max_by_key` returns the last of the maximums: reverse the listing to get the first.
+        let at_timestamp = versions
+            .iter()
+            .rev()
+            .filter(|v| v.last_modified <= timestamp)
+            .max_by_key(|v| (v.last_modified, v.is_latest));
+
+        match (at_timestamp, latest) {
+            (Some(then), Some(now)) if then.kind == ObjectVersionKind::Version => {
+                if then.version_id != now.version_id {
+                    actions.push(RecoveryAction::Restore(then));
+                }
+            }
+            (_, Some(now)) if now.kind == ObjectVersionKind::Version => {
+                actions.push(RecoveryAction::Delete(key));
+            }
+            _ => {}
+        }
+    }
+    actions
 }
 
 /// Storage (potentially remote) API to manage its state.
 /// This storage tries to be unaware of any layered repository context,
 /// providing basic CRUD operations for storage files.
+///
+/// Every operation can be cancelled with its `cancel` token, and fails after a timeout, see
+/// [`RemoteStorageConfig::timeout`] and [`RemoteStorageConfig::small_timeout`].  Such failures
+/// are reported as [`DownloadError::Cancelled`] and [`DownloadError::Timeout`], or as
+/// [`TimeoutOrCancel`] in the [`anyhow::Error`] chain.
 #[async_trait::async_trait]
 pub trait RemoteStorage: Send + Sync + 'static {
     /// Lists all top level subdirectories for a given prefix
@@ -149,9 +259,10 @@ pub trait RemoteStorage: Send + Sync + 'static {
     async fn list_prefixes(
         &self,
         prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
     ) -> Result<Vec<RemotePath>, DownloadError> {
         let result = self
-            .list(prefix, ListingMode::WithDelimiter)
+            .list(prefix, ListingMode::WithDelimiter, cancel)
             .await?
             .prefixes;
         Ok(result)
@@ -167,15 +278,23 @@ pub trait RemoteStorage: Send + Sync + 'static {
     /// whereas,
     /// list_prefixes("foo/bar/") = ["cat", "dog"]
     /// See `test_real_s3.rs` for more details.
-    async fn list_files(&self, prefix: Option<&RemotePath>) -> anyhow::Result<Vec<RemotePath>> {
-        let result = self.list(prefix, ListingMode::NoDelimiter).await?.keys;
-        Ok(result)
+    async fn list_files(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Vec<RemotePath>> {
+        let result = self
+            .list(prefix, ListingMode::NoDelimiter, cancel)
+            .await?
+            .keys;
+        Ok(result.into_iter().map(|object| object.key).collect())
     }
 
     async fn list(
         &self,
         prefix: Option<&RemotePath>,
         _mode: ListingMode,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<Listing, DownloadError>;
 
     /// Streams the local file contents into remote into the remote storage entry.
@@ -187,11 +306,19 @@ pub trait RemoteStorage: Send + Sync + 'static {
         data_size_bytes: usize,
         to: &RemotePath,
         metadata: Option<StorageMetadata>,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<()>;
 
     /// Streams the remote storage entry contents into the buffered writer given, returns the filled writer.
     /// Returns the metadata, if any was stored with the file previously.
-    async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError>;
+    ///
+    /// The timeout and the cancellation apply to reading the whole stream, which fails with
+    /// a [`TimeoutOrCancel`] I/O error when they fire.
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError>;
 
     /// Streams a given byte range of the remote storage entry contents into the buffered writer given, returns the filled writer.
     /// Returns the metadata, if any was stored with the file previously.
@@ -200,11 +327,83 @@ pub trait RemoteStorage: Send + Sync + 'static {
         from: &RemotePath,
         start_inclusive: u64,
         end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
     ) -> Result<Download, DownloadError>;
 
-    async fn delete(&self, path: &RemotePath) -> anyhow::Result<()>;
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()>;
+
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()>;
 
-    async fn delete_objects<'a>(&self, paths: &'a [RemotePath]) -> anyhow::Result<()>;
+    /// Copies a remote storage entry to another path within the same storage, server-side.
+    /// The metadata of the source entry, if any, is copied along with it.
+    /// An existing entry at the destination path is overwritten.
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()>;
+
+    /// Lists all versions of all entries under the given prefix, including the delete markers,
+    /// with the versions of each entry newest first.  Requires versioning to be enabled for the
+    /// storage.
+    async fn list_versions(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ObjectVersion>, DownloadError>;
+
+    /// Makes the given version of the entry its current version, as a new version.
+    async fn restore_version(
+        &self,
+        key: &RemotePath,
+        version_id: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()>;
+
+    /// Brings every entry under the given prefix back to its state at `timestamp`: entries
+    /// overwritten or deleted since are restored, entries created since are deleted.
+    /// Requires versioning to be enabled for the storage.
+    async fn time_travel_recover(
+        &self,
+        prefix: Option<&RemotePath>,
+        timestamp: SystemTime,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let versions = self.list_versions(prefix, cancel).await?;
+
+        let mut to_delete = Vec::new();
+        for action in time_travel_recovery_actions(&versions, timestamp) {
+            match action {
+                RecoveryAction::Restore(version) => {
+                    info!(
+                        "Restoring {} to version {} from {}",
+                        version.key,
+                        version.version_id,
+                        humantime::format_rfc3339(version.last_modified)
+                    );
+                    self.restore_version(&version.key, &version.version_id, cancel)
+                        .await?;
+                }
+                RecoveryAction::Delete(key) => to_delete.push(key.clone()),
+            }
+        }
+
+        if !to_delete.is_empty() {
+            info!(
+                "Deleting {} entries created after the timestamp",
+                to_delete.len()
+            );
+            for chunk in to_delete.chunks(MAX_KEYS_PER_DELETE) {
+                self.delete_objects(chunk, cancel).await?;
+            }
+        }
+        Ok(())
+    }
 }
 
 pub struct Download {
@@ -230,6 +429,8 @@ pub enum DownloadError {
     /// A cancellation token aborted the download, typically during
     /// tenant detach or process shutdown.
     Cancelled,
+    /// The download did not finish within the configured timeout.
+    Timeout,
     /// The file was found in the remote storage, but the download failed.
     Other(anyhow::Error),
 }
@@ -241,6 +442,7 @@ impl std::fmt::Display for DownloadError {
                 write!(f, "Failed to download a remote file due to user input: {e}")
             }
             DownloadError::Cancelled => write!(f, "Cancelled, shutting down"),
+            DownloadError::Timeout => write!(f, "Timed out"),
             DownloadError::NotFound => write!(f, "No file found for the remote object id given"),
             DownloadError::Other(e) => write!(f, "Failed to download a remote file: {e:?}"),
         }
@@ -249,6 +451,19 @@ impl std::fmt::Display for DownloadError {
 
 impl std::error::Error for DownloadError {}
 
+impl DownloadError {
+    /// Returns true if the error should not be retried: repeating the download
+    /// cannot fix the input, make the object appear, or undo a cancellation.
+    /// Timeouts are worth retrying.
+    pub fn is_permanent(&self) -> bool {
+        use DownloadError::*;
+        match self {
+            BadInput(_) | NotFound | Cancelled => true,
+            Timeout | Other(_) => false,
+        }
+    }
+}
+
 /// Every storage, currently supported.
 /// Serves as a simple way to pass around the [`RemoteStorage`] without dealing with generics.
 #[derive(Clone)]
@@ -256,7 +471,10 @@ pub enum GenericRemoteStorage {
     LocalFs(LocalFs),
     AwsS3(Arc<S3Bucket>),
     AzureBlob(Arc<AzureBlobStorage>),
+    Gcs(Arc<GcsBucket>),
     Unreliable(Arc<UnreliableWrapper>),
+    Encrypted(Arc<EncryptingWrapper>),
+    Replicated(Arc<ReplicatedStorage>),
 }
 
 impl GenericRemoteStorage {
@@ -264,24 +482,35 @@ impl GenericRemoteStorage {
         &self,
         prefix: Option<&RemotePath>,
         mode: ListingMode,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<Listing, DownloadError> {
         match self {
-            Self::LocalFs(s) => s.list(prefix, mode).await,
-            Self::AwsS3(s) => s.list(prefix, mode).await,
-            Self::AzureBlob(s) => s.list(prefix, mode).await,
-            Self::Unreliable(s) => s.list(prefix, mode).await,
+            Self::LocalFs(s) => s.list(prefix, mode, cancel).await,
+            Self::AwsS3(s) => s.list(prefix, mode, cancel).await,
+            Self::AzureBlob(s) => s.list(prefix, mode, cancel).await,
+            Self::Gcs(s) => s.list(prefix, mode, cancel).await,
+            Self::Unreliable(s) => s.list(prefix, mode, cancel).await,
+            Self::Encrypted(s) => s.list(prefix, mode, cancel).await,
+            Self::Replicated(s) => s.list(prefix, mode, cancel).await,
         }
     }
 
     // A function for listing all the files in a "directory"
     // Example:
     // list_files("foo/bar") = ["foo/bar/a.txt", "foo/bar/b.txt"]
-    pub async fn list_files(&self, folder: Option<&RemotePath>) -> anyhow::Result<Vec<RemotePath>> {
+    pub async fn list_files(
+        &self,
+        folder: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Vec<RemotePath>> {
         match self {
-            Self::LocalFs(s) => s.list_files(folder).await,
-            Self::AwsS3(s) => s.list_files(folder).await,
-            Self::AzureBlob(s) => s.list_files(folder).await,
-            Self::Unreliable(s) => s.list_files(folder).await,
+            Self::LocalFs(s) => s.list_files(folder, cancel).await,
+            Self::AwsS3(s) => s.list_files(folder, cancel).await,
+            Self::AzureBlob(s) => s.list_files(folder, cancel).await,
+            Self::Gcs(s) => s.list_files(folder, cancel).await,
+            Self::Unreliable(s) => s.list_files(folder, cancel).await,
+            Self::Encrypted(s) => s.list_files(folder, cancel).await,
+            Self::Replicated(s) => s.list_files(folder, cancel).await,
         }
     }
 
@@ -291,12 +520,16 @@ impl GenericRemoteStorage {
     pub async fn list_prefixes(
         &self,
         prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
     ) -> Result<Vec<RemotePath>, DownloadError> {
         match self {
-            Self::LocalFs(s) => s.list_prefixes(prefix).await,
-            Self::AwsS3(s) => s.list_prefixes(prefix).await,
-            Self::AzureBlob(s) => s.list_prefixes(prefix).await,
-            Self::Unreliable(s) => s.list_prefixes(prefix).await,
+            Self::LocalFs(s) => s.list_prefixes(prefix, cancel).await,
+            Self::AwsS3(s) => s.list_prefixes(prefix, cancel).await,
+            Self::AzureBlob(s) => s.list_prefixes(prefix, cancel).await,
+            Self::Gcs(s) => s.list_prefixes(prefix, cancel).await,
+            Self::Unreliable(s) => s.list_prefixes(prefix, cancel).await,
+            Self::Encrypted(s) => s.list_prefixes(prefix, cancel).await,
+            Self::Replicated(s) => s.list_prefixes(prefix, cancel).await,
         }
     }
 
@@ -306,21 +539,32 @@ impl GenericRemoteStorage {
         data_size_bytes: usize,
         to: &RemotePath,
         metadata: Option<StorageMetadata>,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
         match self {
-            Self::LocalFs(s) => s.upload(from, data_size_bytes, to, metadata).await,
-            Self::AwsS3(s) => s.upload(from, data_size_bytes, to, metadata).await,
-            Self::AzureBlob(s) => s.upload(from, data_size_bytes, to, metadata).await,
-            Self::Unreliable(s) => s.upload(from, data_size_bytes, to, metadata).await,
+            Self::LocalFs(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
+            Self::AwsS3(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
+            Self::AzureBlob(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
+            Self::Gcs(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
+            Self::Unreliable(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
+            Self::Encrypted(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
+            Self::Replicated(s) => s.upload(from, data_size_bytes, to, metadata, cancel).await,
         }
     }
 
-    pub async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError> {
+    pub async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
         match self {
-            Self::LocalFs(s) => s.download(from).await,
-            Self::AwsS3(s) => s.download(from).await,
-            Self::AzureBlob(s) => s.download(from).await,
-            Self::Unreliable(s) => s.download(from).await,
+            Self::LocalFs(s) => s.download(from, cancel).await,
+            Self::AwsS3(s) => s.download(from, cancel).await,
+            Self::AzureBlob(s) => s.download(from, cancel).await,
+            Self::Gcs(s) => s.download(from, cancel).await,
+            Self::Unreliable(s) => s.download(from, cancel).await,
+            Self::Encrypted(s) => s.download(from, cancel).await,
+            Self::Replicated(s) => s.download(from, cancel).await,
         }
     }
 
@@ -329,62 +573,248 @@ impl GenericRemoteStorage {
         from: &RemotePath,
         start_inclusive: u64,
         end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
         match self {
             Self::LocalFs(s) => {
-                s.download_byte_range(from, start_inclusive, end_exclusive)
+                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
                     .await
             }
             Self::AwsS3(s) => {
-                s.download_byte_range(from, start_inclusive, end_exclusive)
+                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
                     .await
             }
             Self::AzureBlob(s) => {
-                s.download_byte_range(from, start_inclusive, end_exclusive)
+                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
+                    .await
+            }
+            Self::Gcs(s) => {
+                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
                     .await
             }
             Self::Unreliable(s) => {
-                s.download_byte_range(from, start_inclusive, end_exclusive)
+                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
                     .await
             }
+            Self::Encrypted(s) => {
+                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
+                    .await
+            }
+            Self::Replicated(s) => {
+                s.download_byte_range(from, start_inclusive, end_exclusive, cancel)
+                    .await
+            }
+        }
+    }
+
+    pub async fn delete(
+        &self,
+        path: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::LocalFs(s) => s.delete(path, cancel).await,
+            Self::AwsS3(s) => s.delete(path, cancel).await,
+            Self::AzureBlob(s) => s.delete(path, cancel).await,
+            Self::Gcs(s) => s.delete(path, cancel).await,
+            Self::Unreliable(s) => s.delete(path, cancel).await,
+            Self::Encrypted(s) => s.delete(path, cancel).await,
+            Self::Replicated(s) => s.delete(path, cancel).await,
+        }
+    }
+
+    pub async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::LocalFs(s) => s.delete_objects(paths, cancel).await,
+            Self::AwsS3(s) => s.delete_objects(paths, cancel).await,
+            Self::AzureBlob(s) => s.delete_objects(paths, cancel).await,
+            Self::Gcs(s) => s.delete_objects(paths, cancel).await,
+            Self::Unreliable(s) => s.delete_objects(paths, cancel).await,
+            Self::Encrypted(s) => s.delete_objects(paths, cancel).await,
+            Self::Replicated(s) => s.delete_objects(paths, cancel).await,
+        }
+    }
+
+    pub async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::LocalFs(s) => s.copy(from, to, cancel).await,
+            Self::AwsS3(s) => s.copy(from, to, cancel).await,
+            Self::AzureBlob(s) => s.copy(from, to, cancel).await,
+            Self::Gcs(s) => s.copy(from, to, cancel).await,
+            Self::Unreliable(s) => s.copy(from, to, cancel).await,
+            Self::Encrypted(s) => s.copy(from, to, cancel).await,
+            Self::Replicated(s) => s.copy(from, to, cancel).await,
         }
     }
 
-    pub async fn delete(&self, path: &RemotePath) -> anyhow::Result<()> {
+    pub async fn restore_version(
+        &self,
+        key: &RemotePath,
+        version_id: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
         match self {
-            Self::LocalFs(s) => s.delete(path).await,
-            Self::AwsS3(s) => s.delete(path).await,
-            Self::AzureBlob(s) => s.delete(path).await,
-            Self::Unreliable(s) => s.delete(path).await,
+            Self::LocalFs(s) => s.restore_version(key, version_id, cancel).await,
+            Self::AwsS3(s) => s.restore_version(key, version_id, cancel).await,
+            Self::AzureBlob(s) => s.restore_version(key, version_id, cancel).await,
+            Self::Gcs(s) => s.restore_version(key, version_id, cancel).await,
+            Self::Unreliable(s) => s.restore_version(key, version_id, cancel).await,
+            Self::Encrypted(s) => s.restore_version(key, version_id, cancel).await,
+            Self::Replicated(s) => s.restore_version(key, version_id, cancel).await,
         }
     }
 
-    pub async fn delete_objects<'a>(&self, paths: &'a [RemotePath]) -> anyhow::Result<()> {
+    pub async fn list_versions(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ObjectVersion>, DownloadError> {
         match self {
-            Self::LocalFs(s) => s.delete_objects(paths).await,
-            Self::AwsS3(s) => s.delete_objects(paths).await,
-            Self::AzureBlob(s) => s.delete_objects(paths).await,
-            Self::Unreliable(s) => s.delete_objects(paths).await,
+            Self::LocalFs(s) => s.list_versions(prefix, cancel).await,
+            Self::AwsS3(s) => s.list_versions(prefix, cancel).await,
+            Self::AzureBlob(s) => s.list_versions(prefix, cancel).await,
+            Self::Gcs(s) => s.list_versions(prefix, cancel).await,
+            Self::Unreliable(s) => s.list_versions(prefix, cancel).await,
+            Self::Encrypted(s) => s.list_versions(prefix, cancel).await,
+            Self::Replicated(s) => s.list_versions(prefix, cancel).await,
         }
     }
+
+    pub async fn time_travel_recover(
+        &self,
+        prefix: Option<&RemotePath>,
+        timestamp: SystemTime,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        match self {
+            Self::LocalFs(s) => s.time_travel_recover(prefix, timestamp, cancel).await,
+            Self::AwsS3(s) => s.time_travel_recover(prefix, timestamp, cancel).await,
+            Self::AzureBlob(s) => s.time_travel_recover(prefix, timestamp, cancel).await,
+            Self::Gcs(s) => s.time_travel_recover(prefix, timestamp, cancel).await,
+            Self::Unreliable(s) => s.time_travel_recover(prefix, timestamp, cancel).await,
+            Self::Encrypted(s) => s.time_travel_recover(prefix, timestamp, cancel).await,
+            Self::Replicated(s) => s.time_travel_recover(prefix, timestamp, cancel).await,
+        }
+    }
+
+    /// Whether the storage can have versioning enabled, as needed by [`Self::list_versions`],
+    /// [`Self::restore_version`] and [`Self::time_travel_recover`].  The buckets may still
+    /// have it disabled in their settings.
+    pub fn supports_versioning(&self) -> bool {
+        match self {
+            Self::LocalFs(s) => s.versioning_enabled(),
+            Self::AwsS3(_) | Self::Gcs(_) => true,
+            // TODO: Azure supports blob versioning, but we don't use it yet
+            Self::AzureBlob(_) => false,
+            Self::Unreliable(s) => s.inner().supports_versioning(),
+            Self::Encrypted(s) => s.inner().supports_versioning(),
+            Self::Replicated(s) => s.primary().supports_versioning(),
+        }
+    }
+
+    /// Makes the mirror of a storage configured with a `mirror` catch up with the writes
+    /// under `prefix` that the mirroring missed, see [`ReplicatedStorage::resync`].
+    pub async fn resync_mirror(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let replicated = match self {
+            Self::Replicated(s) => s,
+            Self::Encrypted(s) => match s.inner() {
+                Self::Replicated(s) => s,
+                _ => bail!("the remote storage has no mirror"),
+            },
+            _ => bail!("the remote storage has no mirror"),
+        };
+        replicated.resync(prefix, cancel).await
+    }
 }
 
 impl GenericRemoteStorage {
     pub fn from_config(storage_config: &RemoteStorageConfig) -> anyhow::Result<Self> {
-        Ok(match &storage_config.storage {
-            RemoteStorageKind::LocalFs(root) => {
-                info!("Using fs root '{root}' as a remote storage");
-                Self::LocalFs(LocalFs::new(root.clone())?)
+        Self::from_kind(
+            &storage_config.storage,
+            storage_config.timeout,
+            storage_config.small_timeout,
+        )
+    }
+
+    fn from_kind(
+        kind: &RemoteStorageKind,
+        timeout: Duration,
+        small_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        Ok(match kind {
+            RemoteStorageKind::LocalFs(local_fs_config) => {
+                let root = &local_fs_config.local_path;
+                info!(
+                    "Using fs root '{root}' as a remote storage, version retention: {:?}",
+                    local_fs_config.version_retention
+                );
+                let mut storage = LocalFs::new(root.clone(), timeout, small_timeout)?;
+                if let Some(retention) = local_fs_config.version_retention {
+                    storage = storage.with_versioning(retention);
+                }
+                Self::LocalFs(storage)
             }
             RemoteStorageKind::AwsS3(s3_config) => {
                 info!("Using s3 bucket '{}' in region '{}' as a remote storage, prefix in bucket: '{:?}', bucket endpoint: '{:?}'",
                       s3_config.bucket_name, s3_config.bucket_region, s3_config.prefix_in_bucket, s3_config.endpoint);
-                Self::AwsS3(Arc::new(S3Bucket::new(s3_config)?))
+                Self::AwsS3(Arc::new(S3Bucket::new(s3_config, timeout, small_timeout)?))
             }
             RemoteStorageKind::AzureContainer(azure_config) => {
                 info!("Using azure container '{}' in region '{}' as a remote storage, prefix in container: '{:?}'",
                       azure_config.container_name, azure_config.container_region, azure_config.prefix_in_container);
-                Self::AzureBlob(Arc::new(AzureBlobStorage::new(azure_config)?))
+                Self::AzureBlob(Arc::new(AzureBlobStorage::new(
+                    azure_config,
+                    timeout,
+                    small_timeout,
+                )?))
+            }
+            RemoteStorageKind::Gcs(gcs_config) => {
+                info!("Using gcs bucket '{}' as a remote storage, prefix in bucket: '{:?}', bucket endpoint: '{:?}'",
+                      gcs_config.bucket_name, gcs_config.prefix_in_bucket, gcs_config.endpoint);
+                Self::Gcs(Arc::new(GcsBucket::new(
+                    gcs_config,
+                    timeout,
+                    small_timeout,
+                )?))
+            }
+            RemoteStorageKind::Replicated(replicated_config) => {
+                info!(
+                    "Mirroring the remote storage writes to a secondary storage, mode: {:?}",
+                    replicated_config.mode
+                );
+                Self::Replicated(Arc::new(ReplicatedStorage::new(
+                    Self::from_kind(&replicated_config.primary, timeout, small_timeout)?,
+                    Self::from_kind(&replicated_config.secondary, timeout, small_timeout)?,
+                    replicated_config.mode,
+                )?))
+            }
+            RemoteStorageKind::Encrypted(encrypted_config) => {
+                info!(
+                    "Encrypting the remote storage objects with the keys from '{}'",
+                    encrypted_config.keys_path
+                );
+                let keys = StaticKeyProvider::from_file(&encrypted_config.keys_path)?;
+                let mut wrapper = EncryptingWrapper::new(
+                    Self::from_kind(&encrypted_config.inner, timeout, small_timeout)?,
+                    Arc::new(keys),
+                );
+                if encrypted_config.allow_unencrypted_reads {
+                    wrapper = wrapper.allowing_unencrypted_reads();
+                }
+                Self::Encrypted(Arc::new(wrapper))
             }
         })
     }
@@ -393,6 +823,10 @@ impl GenericRemoteStorage {
         Self::Unreliable(Arc::new(UnreliableWrapper::new(s, fail_first)))
     }
 
+    pub fn encrypting_wrapper(s: Self, keys: Arc<dyn KeyProvider>) -> Self {
+        Self::Encrypted(Arc::new(EncryptingWrapper::new(s, keys)))
+    }
+
     /// Takes storage object contents and its size and uploads to remote storage,
     /// mapping `from_path` to the corresponding remote object id in the storage.
     ///
@@ -403,8 +837,9 @@ impl GenericRemoteStorage {
         from: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
         from_size_bytes: usize,
         to: &RemotePath,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
-        self.upload(from, from_size_bytes, to, None)
+        self.upload(from, from_size_bytes, to, None, cancel)
             .await
             .with_context(|| {
                 format!("Failed to upload data of length {from_size_bytes} to storage path {to:?}")
@@ -417,10 +852,11 @@ impl GenericRemoteStorage {
         &self,
         byte_range: Option<(u64, Option<u64>)>,
         from: &RemotePath,
+        cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
         match byte_range {
-            Some((start, end)) => self.download_byte_range(from, start, end).await,
-            None => self.download(from).await,
+            Some((start, end)) => self.download_byte_range(from, start, end, cancel).await,
+            None => self.download(from, cancel).await,
         }
     }
 }
@@ -435,6 +871,11 @@ pub struct StorageMetadata(HashMap<String, String>);
 pub struct RemoteStorageConfig {
     /// The storage connection configuration.
     pub storage: RemoteStorageKind,
+    /// How long the data transfers may take: uploads, downloads and copies.
+    pub timeout: Duration,
+    /// How long the other operations may take, which only transfer the metadata: listings
+    /// and deletions.
+    pub small_timeout: Duration,
 }
 
 /// A kind of a remote storage to connect to, with its connection configuration.
@@ -442,13 +883,62 @@ pub struct RemoteStorageConfig {
 pub enum RemoteStorageKind {
     /// Storage based on local file system.
     /// Specify a root folder to place all stored files into.
-    LocalFs(Utf8PathBuf),
+    LocalFs(LocalFsConfig),
     /// AWS S3 based storage, storing all files in the S3 bucket
     /// specified by the config
     AwsS3(S3Config),
     /// Azure Blob based storage, storing all files in the container
     /// specified by the config
     AzureContainer(AzureConfig),
+    /// Google Cloud Storage based storage, storing all files in the GCS bucket
+    /// specified by the config
+    Gcs(GcsConfig),
+    /// Two storages, with all writes to the primary one mirrored to the secondary one
+    Replicated(ReplicatedConfig),
+    /// Another storage, with the objects of the tenants that have keys encrypted
+    Encrypted(EncryptedConfig),
+}
+
+/// A local directory used as a storage by [`LocalFs`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalFsConfig {
+    /// The root directory to place all stored files into.
+    pub local_path: Utf8PathBuf,
+    /// Keep the versions of the files for time travel recovery, for this long after they are
+    /// replaced.  Unversioned if not set.
+    pub version_retention: Option<Duration>,
+}
+
+impl LocalFsConfig {
+    /// An unversioned storage in `local_path`.
+    pub fn new(local_path: Utf8PathBuf) -> Self {
+        LocalFsConfig {
+            local_path,
+            version_retention: None,
+        }
+    }
+}
+
+/// A pair of storages for [`ReplicatedStorage`], e.g. buckets in different regions.
+/// Both use the timeouts of the [`RemoteStorageConfig`] they are in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicatedConfig {
+    /// The storage all writes and reads go to first.
+    pub primary: Box<RemoteStorageKind>,
+    /// The storage that gets a copy of every write, and serves the reads the primary storage fails.
+    pub secondary: Box<RemoteStorageKind>,
+    pub mode: MirrorMode,
+}
+
+/// Client-side encryption of a storage's objects with [`EncryptingWrapper`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedConfig {
+    /// The storage to keep the encrypted objects in.
+    pub inner: Box<RemoteStorageKind>,
+    /// The file with the tenants' keys, see [`StaticKeyProvider::from_file`].
+    pub keys_path: Utf8PathBuf,
+    /// Whether to read the unencrypted objects of the tenants that have keys, while migrating them.
+    pub allow_unencrypted_reads: bool,
 }
 
 /// AWS S3 bucket coordinates and access credentials to manage the bucket contents (read and write).
@@ -518,18 +1008,119 @@ impl Debug for AzureConfig {
     }
 }
 
+/// Google Cloud Storage bucket coordinates to manage the bucket contents (read and write).
+/// The credentials are taken from the environment, see [`GcsBucket`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct GcsConfig {
+    /// Name of the bucket to connect to.
+    pub bucket_name: String,
+    /// A "subfolder" in the bucket, to use the same bucket separately by multiple remote storage users at once.
+    pub prefix_in_bucket: Option<String>,
+    /// A base URL to send GCS requests to, instead of the real GCS.
+    /// Endpoint provides a way to use GCS emulators, which are accessed without authentication.
+    ///
+    /// Example: `http://127.0.0.1:4443`
+    pub endpoint: Option<String>,
+    /// See [`DEFAULT_REMOTE_STORAGE_GCS_CONCURRENCY_LIMIT`] for more details.
+    pub concurrency_limit: NonZeroUsize,
+    pub max_keys_per_list_response: Option<i32>,
+}
+
+impl Debug for GcsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GcsConfig")
+            .field("bucket_name", &self.bucket_name)
+            .field("prefix_in_bucket", &self.prefix_in_bucket)
+            .field("endpoint", &self.endpoint)
+            .field("concurrency_limit", &self.concurrency_limit)
+            .field(
+                "max_keys_per_list_response",
+                &self.max_keys_per_list_response,
+            )
+            .finish()
+    }
+}
+
 impl RemoteStorageConfig {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+    pub const DEFAULT_SMALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Parses the storage config, where a `mirror` table with another storage config and
+    /// an optional `mode` of `'async'` (the default) or `'sync'` turns on the mirroring
+    /// to another storage, see [`ReplicatedStorage`].
+    ///
+    /// An `encryption` table with the `keys_path` and an optional `allow_unencrypted_reads`
+    /// turns on the encryption, see [`EncryptingWrapper`].  The objects are encrypted before
+    /// they are mirrored, so both storages hold the same encrypted objects.
     pub fn from_toml(toml: &toml_edit::Item) -> anyhow::Result<Option<RemoteStorageConfig>> {
+        let Some(mut config) = Self::from_toml_unmirrored(toml)? else {
+            if toml.get("mirror").is_some() {
+                bail!("'mirror' requires a primary remote storage to be configured")
+            }
+            if toml.get("encryption").is_some() {
+                bail!("'encryption' requires a remote storage to be configured")
+            }
+            return Ok(None);
+        };
+
+        if let Some(mirror) = toml.get("mirror") {
+            if mirror.get("mirror").is_some() {
+                bail!("'mirror' cannot be nested")
+            }
+            if mirror.get("encryption").is_some() {
+                bail!("'encryption' applies to both storages, configure it next to 'mirror'")
+            }
+            let secondary = Self::from_toml_unmirrored(mirror)?
+                .context("'mirror' does not configure a remote storage")?;
+            let mode = mirror
+                .get("mode")
+                .map(|mode| parse_toml_string("mode", mode)?.parse::<MirrorMode>())
+                .transpose()?
+                .unwrap_or_default();
+            config.storage = RemoteStorageKind::Replicated(ReplicatedConfig {
+                primary: Box::new(config.storage),
+                secondary: Box::new(secondary.storage),
+                mode,
+            });
+        }
+
+        if let Some(encryption) = toml.get("encryption") {
+            let keys_path = encryption
+                .get("keys_path")
+                .context("'encryption' requires 'keys_path'")?;
+            let allow_unencrypted_reads = encryption
+                .get("allow_unencrypted_reads")
+                .map(|allow| {
+                    allow
+                        .as_bool()
+                        .context("'allow_unencrypted_reads' is not a boolean")
+                })
+                .transpose()?
+                .unwrap_or(false);
+            config.storage = RemoteStorageKind::Encrypted(EncryptedConfig {
+                inner: Box::new(config.storage),
+                keys_path: Utf8PathBuf::from(parse_toml_string("keys_path", keys_path)?),
+                allow_unencrypted_reads,
+            });
+        }
+
+        Ok(Some(config))
+    }
+
+    fn from_toml_unmirrored(toml: &toml_edit::Item) -> anyhow::Result<Option<RemoteStorageConfig>> {
         let local_path = toml.get("local_path");
         let bucket_name = toml.get("bucket_name");
         let bucket_region = toml.get("bucket_region");
         let container_name = toml.get("container_name");
         let container_region = toml.get("container_region");
+        let gcs_bucket_name = toml.get("gcs_bucket_name");
 
         let use_azure = container_name.is_some() && container_region.is_some();
 
         let default_concurrency_limit = if use_azure {
             DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT
+        } else if gcs_bucket_name.is_some() {
+            DEFAULT_REMOTE_STORAGE_GCS_CONCURRENCY_LIMIT
         } else {
             DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT
         };
@@ -548,6 +1139,36 @@ impl RemoteStorageConfig {
             .map(|endpoint| parse_toml_string("endpoint", endpoint))
             .transpose()?;
 
+        if toml.get("version_retention").is_some() && local_path.is_none() {
+            bail!("'version_retention' is only supported with 'local_path', the buckets are versioned by their own settings")
+        }
+
+        let timeout = parse_optional_duration("timeout", toml)?.unwrap_or(Self::DEFAULT_TIMEOUT);
+        let small_timeout =
+            parse_optional_duration("small_timeout", toml)?.unwrap_or(Self::DEFAULT_SMALL_TIMEOUT);
+
+        if let Some(gcs_bucket_name) = gcs_bucket_name {
+            if local_path.is_some() || bucket_name.is_some() || container_name.is_some() {
+                bail!("'gcs_bucket_name' is mutually exclusive with 'local_path', 'bucket_name' and 'container_name'")
+            }
+            return Ok(Some(RemoteStorageConfig {
+                storage: RemoteStorageKind::Gcs(GcsConfig {
+                    bucket_name: parse_toml_string("gcs_bucket_name", gcs_bucket_name)?,
+                    prefix_in_bucket: toml
+                        .get("prefix_in_bucket")
+                        .map(|prefix_in_bucket| {
+                            parse_toml_string("prefix_in_bucket", prefix_in_bucket)
+                        })
+                        .transpose()?,
+                    endpoint,
+                    concurrency_limit,
+                    max_keys_per_list_response,
+                }),
+                timeout,
+                small_timeout,
+            }));
+        }
+
         let storage = match (
             local_path,
             bucket_name,
@@ -598,9 +1219,12 @@ impl RemoteStorageConfig {
                     max_keys_per_list_response,
                 })
             }
-            (Some(local_path), None, None, None, None) => RemoteStorageKind::LocalFs(
-                Utf8PathBuf::from(parse_toml_string("local_path", local_path)?),
-            ),
+            (Some(local_path), None, None, None, None) => {
+                RemoteStorageKind::LocalFs(LocalFsConfig {
+                    local_path: Utf8PathBuf::from(parse_toml_string("local_path", local_path)?),
+                    version_retention: parse_optional_duration("version_retention", toml)?,
+                })
+            }
             (Some(_), Some(_), ..) => {
                 bail!("'local_path' and 'bucket_name' are mutually exclusive")
             }
@@ -609,7 +1233,11 @@ impl RemoteStorageConfig {
             }
         };
 
-        Ok(Some(RemoteStorageConfig { storage }))
+        Ok(Some(RemoteStorageConfig {
+            storage,
+            timeout,
+            small_timeout,
+        }))
     }
 }
 
@@ -631,6 +1259,16 @@ where
         .with_context(|| format!("configure option {name} is too large"))
 }
 
+fn parse_optional_duration(name: &str, item: &toml_edit::Item) -> anyhow::Result<Option<Duration>> {
+    item.get(name)
+        .map(|item| {
+            let s = parse_toml_string(name, item)?;
+            humantime::parse_duration(&s)
+                .with_context(|| format!("configure option {name} is not a valid duration"))
+        })
+        .transpose()
+}
+
 fn parse_toml_string(name: &str, item: &Item) -> anyhow::Result<String> {
     let s = item
         .as_str()
@@ -653,6 +1291,7 @@ impl ConcurrencyLimiter {
             RequestKind::Put => &self.write,
             RequestKind::List => &self.read,
             RequestKind::Delete => &self.write,
+            RequestKind::Copy => &self.write,
         }
     }
 
@@ -703,4 +1342,214 @@ mod tests {
         let err = RemotePath::new(Utf8Path::new("/")).expect_err("Should fail on absolute paths");
         assert_eq!(err.to_string(), "Path \"/\" is not relative");
     }
+
+    #[test]
+    fn parse_gcs_config() {
+        let toml = "gcs_bucket_name = 'some-bucket'\nprefix_in_bucket = 'pageserver/'\nendpoint = 'http://127.0.0.1:4443'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item()).unwrap();
+        assert_eq!(
+            config,
+            Some(RemoteStorageConfig {
+                storage: RemoteStorageKind::Gcs(GcsConfig {
+                    bucket_name: "some-bucket".to_owned(),
+                    prefix_in_bucket: Some("pageserver/".to_owned()),
+                    endpoint: Some("http://127.0.0.1:4443".to_owned()),
+                    concurrency_limit: NonZeroUsize::new(
+                        DEFAULT_REMOTE_STORAGE_GCS_CONCURRENCY_LIMIT
+                    )
+                    .unwrap(),
+                    max_keys_per_list_response: DEFAULT_MAX_KEYS_PER_LIST_RESPONSE,
+                }),
+                timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+                small_timeout: RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
+            })
+        );
+
+        let toml = "gcs_bucket_name = 'some-bucket'\nbucket_name = 'other-bucket'\nbucket_region = 'eu-north-1'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        RemoteStorageConfig::from_toml(toml.as_item()).expect_err("both buckets are given");
+    }
+
+    #[test]
+    fn parse_timeouts() {
+        let toml = "local_path = '/tmp/remote'\ntimeout = '5m'\nsmall_timeout = '10s'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.timeout, Duration::from_secs(300));
+        assert_eq!(config.small_timeout, Duration::from_secs(10));
+
+        let toml = "local_path = '/tmp/remote'\ntimeout = 'forever'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        RemoteStorageConfig::from_toml(toml.as_item()).expect_err("invalid duration");
+    }
+
+    #[test]
+    fn parse_local_fs_versioning() {
+        let toml = "local_path = '/tmp/remote'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            config.storage,
+            RemoteStorageKind::LocalFs(LocalFsConfig::new(Utf8PathBuf::from("/tmp/remote")))
+        );
+
+        let toml = "local_path = '/tmp/remote'\nversion_retention = '1h'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            config.storage,
+            RemoteStorageKind::LocalFs(LocalFsConfig {
+                local_path: Utf8PathBuf::from("/tmp/remote"),
+                version_retention: Some(Duration::from_secs(3600)),
+            })
+        );
+
+        let toml =
+            "bucket_name = 'some-bucket'\nbucket_region = 'eu-north-1'\nversion_retention = '1h'\n"
+                .parse::<toml_edit::Document>()
+                .unwrap();
+        RemoteStorageConfig::from_toml(toml.as_item()).expect_err("versioned bucket");
+    }
+
+    #[test]
+    fn parse_mirror_config() {
+        let toml = "local_path = '/tmp/primary'\ntimeout = '5m'\n[mirror]\nbucket_name = 'dr-bucket'\nbucket_region = 'eu-west-1'\nmode = 'sync'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.timeout, Duration::from_secs(300));
+        let RemoteStorageKind::Replicated(replicated) = config.storage else {
+            panic!("expected a replicated storage, got {:?}", config.storage);
+        };
+        assert_eq!(
+            *replicated.primary,
+            RemoteStorageKind::LocalFs(LocalFsConfig::new(Utf8PathBuf::from("/tmp/primary")))
+        );
+        assert!(
+            matches!(&*replicated.secondary, RemoteStorageKind::AwsS3(s3) if s3.bucket_name == "dr-bucket")
+        );
+        assert_eq!(replicated.mode, MirrorMode::Sync);
+
+        let toml = "[mirror]\nlocal_path = '/tmp/secondary'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        RemoteStorageConfig::from_toml(toml.as_item()).expect_err("no primary storage");
+
+        let toml = "local_path = '/tmp/primary'\n[mirror]\nlocal_path = '/tmp/secondary'\nmode = 'eventually'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        RemoteStorageConfig::from_toml(toml.as_item()).expect_err("invalid mode");
+    }
+
+    #[test]
+    fn parse_encryption_config() {
+        let toml = "local_path = '/tmp/primary'\n[mirror]\nlocal_path = '/tmp/secondary'\n[encryption]\nkeys_path = '/etc/neon/keys.toml'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .unwrap();
+        let RemoteStorageKind::Encrypted(encrypted) = config.storage else {
+            panic!("expected an encrypted storage, got {:?}", config.storage);
+        };
+        assert_eq!(
+            encrypted.keys_path,
+            Utf8PathBuf::from("/etc/neon/keys.toml")
+        );
+        assert!(!encrypted.allow_unencrypted_reads);
+        assert!(matches!(*encrypted.inner, RemoteStorageKind::Replicated(_)));
+
+        let toml = "local_path = '/tmp/remote'\n[encryption]\nkeys_path = '/etc/neon/keys.toml'\nallow_unencrypted_reads = true\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        let config = RemoteStorageConfig::from_toml(toml.as_item())
+            .unwrap()
+            .unwrap();
+        assert!(
+            matches!(config.storage, RemoteStorageKind::Encrypted(e) if e.allow_unencrypted_reads)
+        );
+
+        let toml = "local_path = '/tmp/primary'\n[mirror]\nlocal_path = '/tmp/secondary'\n[mirror.encryption]\nkeys_path = '/etc/neon/keys.toml'\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        RemoteStorageConfig::from_toml(toml.as_item()).expect_err("encryption of the mirror only");
+
+        let toml = "local_path = '/tmp/remote'\n[encryption]\nallow_unencrypted_reads = true\n"
+            .parse::<toml_edit::Document>()
+            .unwrap();
+        RemoteStorageConfig::from_toml(toml.as_item()).expect_err("no keys path");
+    }
+
+    #[test]
+    fn test_time_travel_recovery_actions() {
+        let t = |secs| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        let version = |key: &str, id: &str, secs, kind, is_latest| ObjectVersion {
+            key: RemotePath::from_string(key).unwrap(),
+            version_id: id.to_owned(),
+            last_modified: t(secs),
+            kind,
+            is_latest,
+        };
+        use ObjectVersionKind::*;
+
+        // Listed newest first, like S3 does
+        let versions = [
+            // unchanged since
+            version("unchanged", "1", 10, Version, true),
+            // overwritten after
+            version("overwritten", "3", 30, Version, true),
+            version("overwritten", "2", 10, Version, false),
+            // deleted after
+            version("deleted", "5", 30, DeleteMarker, true),
+            version("deleted", "4", 10, Version, false),
+            // created after
+            version("created", "6", 30, Version, true),
+            // created and deleted after
+            version("created_and_deleted", "8", 40, DeleteMarker, true),
+            version("created_and_deleted", "7", 30, Version, false),
+            // deleted before, recreated after
+            version("recreated", "11", 30, Version, true),
+            version("recreated", "10", 15, DeleteMarker, false),
+            version("recreated", "9", 10, Version, false),
+            // overwritten within the same second as the versions before and after
+            version("same_second", "14", 20, Version, true),
+            version("same_second", "13", 20, Version, false),
+            version("same_second", "12", 10, Version, false),
+        ];
+
+        let actions = time_travel_recovery_actions(&versions, t(20));
+        assert_eq!(
+            actions,
+            [
+                RecoveryAction::Delete(&versions[5].key),
+                RecoveryAction::Restore(&versions[4]),
+                RecoveryAction::Restore(&versions[2]),
+                RecoveryAction::Delete(&versions[8].key),
+            ]
+        );
+
+        // The latest version is the current one even if it is not listed first
+        let mut versions = versions[11..].to_vec();
+        versions.swap(0, 1);
+        assert!(time_travel_recovery_actions(&versions, t(20)).is_empty());
+        assert_eq!(
+            time_travel_recovery_actions(&versions, t(15)),
+            [RecoveryAction::Restore(&versions[2])]
+        );
+    }
 }
@@ -3,8 +3,22 @@
 //!
 //! This storage used in tests, but can also be used in cases when a certain persistent
 //! volume is mounted to the local FS.
-
-use std::{borrow::Cow, future::Future, io::ErrorKind, pin::Pin};
+//!
+//! To emulate bucket versioning, when enabled with [`LocalFs::with_versioning`], every object
+//! written or deleted through this storage also leaves a version (or a delete marker) in a
+//! hidden `.versions` directory under the storage root, which is excluded from all listings.
+//! Like with a lifecycle rule on a bucket, the versions that stopped being current more than
+//! the retention period ago are deleted, and so are the delete markers older than it.
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    future::Future,
+    io::ErrorKind,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{bail, ensure, Context};
 use bytes::Bytes;
@@ -14,25 +28,46 @@ use tokio::{
     fs,
     io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
 };
-use tokio_util::io::ReaderStream;
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
 use tracing::*;
 use utils::{crashsafe::path_with_suffix_extension, fs_ext::is_directory_empty};
 
-use crate::{Download, DownloadError, Listing, ListingMode, RemotePath};
+use crate::{
+    support::{with_timeout, DownloadStream},
+    Download, DownloadError, Listing, ListingMode, ListingObject, ObjectVersion, ObjectVersionKind,
+    RemotePath,
+};
 
 use super::{RemoteStorage, StorageMetadata};
 
 const LOCAL_FS_TEMP_FILE_SUFFIX: &str = "___temp";
 
+/// Directory under the storage root with the versions of every object, laid out as
+/// `.versions/<object key>/<version id>`, where the version id is the version creation time in
+/// nanoseconds since the UNIX epoch.
+const LOCAL_FS_VERSIONS_DIR: &str = ".versions";
+const LOCAL_FS_DELETE_MARKER_SUFFIX: &str = "deleted";
+
 #[derive(Debug, Clone)]
 pub struct LocalFs {
     storage_root: Utf8PathBuf,
+    timeout: Duration,
+    small_timeout: Duration,
+    /// How long the versions of an object are kept after a newer version replaces them, if
+    /// the versioning is enabled.
+    version_retention: Option<Duration>,
+    /// When the versions of all objects were last pruned, in nanoseconds since the UNIX epoch.
+    all_versions_pruned_at: Arc<Mutex<Option<u64>>>,
 }
 
 impl LocalFs {
     /// Attempts to create local FS storage, along with its root directory.
     /// Storage root will be created (if does not exist) and transformed into an absolute path (if passed as relative).
-    pub fn new(mut storage_root: Utf8PathBuf) -> anyhow::Result<Self> {
+    pub fn new(
+        mut storage_root: Utf8PathBuf,
+        timeout: Duration,
+        small_timeout: Duration,
+    ) -> anyhow::Result<Self> {
         if !storage_root.exists() {
             std::fs::create_dir_all(&storage_root).with_context(|| {
                 format!("Failed to create all directories in the given root path {storage_root:?}")
@@ -44,7 +79,23 @@ impl LocalFs {
             })?;
         }
 
-        Ok(Self { storage_root })
+        Ok(Self {
+            storage_root,
+            timeout,
+            small_timeout,
+            version_retention: None,
+            all_versions_pruned_at: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Enables the versioning of the objects, keeping the replaced versions for `retention`.
+    pub fn with_versioning(mut self, retention: Duration) -> Self {
+        self.version_retention = Some(retention);
+        self
+    }
+
+    pub fn versioning_enabled(&self) -> bool {
+        self.version_retention.is_some()
     }
 
     // mirrors S3Bucket::s3_object_to_relative_path
@@ -77,11 +128,158 @@ impl LocalFs {
         }
     }
 
+    fn versions_root(&self) -> Utf8PathBuf {
+        self.storage_root.join(LOCAL_FS_VERSIONS_DIR)
+    }
+
+    /// Records the current state of the object as its new latest version, or a delete marker
+    /// if `deleted` is set.  Does nothing if the versioning is not enabled.
+    async fn record_version(&self, key: &RemotePath, deleted: bool) -> anyhow::Result<()> {
+        let Some(retention) = self.version_retention else {
+            return Ok(());
+        };
+        let versions_dir = key.with_base(&self.versions_root());
+        fs::create_dir_all(&versions_dir)
+            .await
+            .with_context(|| format!("Failed to create versions directory at '{versions_dir}'"))?;
+
+        let mut nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .context("System time is before the UNIX epoch")?
+            .as_nanos() as u64;
+        let version_path = loop {
+            let version_path = versions_dir.join(format!("{nanos:020}"));
+            if !version_path.exists() && !delete_marker_path(&version_path).exists() {
+                break version_path;
+            }
+            nanos += 1;
+        };
+
+        if deleted {
+            let delete_marker_path = delete_marker_path(&version_path);
+            fs::write(&delete_marker_path, b"").await.with_context(|| {
+                format!("Failed to write delete marker at '{delete_marker_path}'")
+            })?;
+        } else {
+            copy_file_with_metadata(&key.with_base(&self.storage_root), &version_path).await?;
+        }
+
+        self.prune_versions(&versions_dir, retention, nanos).await?;
+        self.prune_all_versions(retention, nanos).await
+    }
+
+    /// Prunes the versions of every object, at most once per `retention`, so that the versions
+    /// of the objects that are not written anymore, e.g. the deleted ones, expire too.
+    async fn prune_all_versions(&self, retention: Duration, now_nanos: u64) -> anyhow::Result<()> {
+        {
+            let mut pruned_at = self.all_versions_pruned_at.lock().unwrap();
+            if pruned_at.is_some_and(|pruned_at| {
+                now_nanos.saturating_sub(pruned_at) < retention.as_nanos() as u64
+            }) {
+                return Ok(());
+            }
+            *pruned_at = Some(now_nanos);
+        }
+
+        let versions_dirs: HashSet<Utf8PathBuf> = get_all_files(&self.versions_root(), true)
+            .await?
+            .into_iter()
+            .filter_map(|file| file.parent().map(Utf8Path::to_path_buf))
+            .collect();
+        for versions_dir in versions_dirs {
+            self.prune_versions(&versions_dir, retention, now_nanos)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the versions in `versions_dir` that were replaced by a newer version more than
+    /// `retention` before `now_nanos`, and the latest version too if it is such an old delete
+    /// marker.  The directory is removed once empty, with its empty parents.
+    async fn prune_versions(
+        &self,
+        versions_dir: &Utf8Path,
+        retention: Duration,
+        now_nanos: u64,
+    ) -> anyhow::Result<()> {
+        let mut versions = Vec::new();
+        let mut entries = fs::read_dir(versions_dir)
+            .await
+            .with_context(|| format!("Failed to read versions directory at '{versions_dir}'"))?;
+        while let Some(entry) = entries.next_entry().await? {
+            // Versions of the objects with this object's key as a prefix are in subdirectories
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            let (version_id, deleted) = match file_name.split_once('.') {
+                None => (file_name, false),
+                Some((version_id, LOCAL_FS_DELETE_MARKER_SUFFIX)) => (version_id, true),
+                // Metadata of a version, deleted along with it, or a temp file
+                Some(_) => continue,
+            };
+            if let Ok(nanos) = version_id.parse::<u64>() {
+                versions.push((nanos, versions_dir.join(file_name), deleted));
+            }
+        }
+        versions.sort();
+
+        let retention = retention.as_nanos() as u64;
+        let expired = |nanos: u64| now_nanos.saturating_sub(nanos) > retention;
+        let mut to_delete = Vec::new();
+        for pair in versions.windows(2) {
+            let ((_, version_path, _), (replaced_at, ..)) = (&pair[0], &pair[1]);
+            if !expired(*replaced_at) {
+                break;
+            }
+            to_delete.push(version_path);
+        }
+        if let Some((deleted_at, delete_marker_path, true)) = versions.last() {
+            if expired(*deleted_at) && to_delete.len() + 1 == versions.len() {
+                to_delete.push(delete_marker_path);
+            }
+        }
+
+        for version_path in to_delete {
+            for path in [version_path.clone(), storage_metadata_path(version_path)] {
+                match fs::remove_file(&path).await {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => {
+                        return Err(e)
+                            .with_context(|| format!("Failed to delete old version at '{path}'"))
+                    }
+                }
+            }
+        }
+
+        // Stops at the first directory that is not empty, or that a concurrent write just
+        // recorded a version in.
+        let versions_root = self.versions_root();
+        let mut dir = versions_dir;
+        while dir != versions_root && is_directory_empty(dir).await.unwrap_or(false) {
+            if let Err(e) = fs::remove_dir(dir).await {
+                debug!("Failed to remove empty versions directory '{dir}': {e}");
+                break;
+            }
+            let Some(parent) = dir.parent() else {
+                break;
+            };
+            dir = parent;
+        }
+        Ok(())
+    }
+
     #[cfg(test)]
     async fn list_all(&self) -> anyhow::Result<Vec<RemotePath>> {
+        let versions_root = self.versions_root();
         Ok(get_all_files(&self.storage_root, true)
             .await?
             .into_iter()
+            .filter(|path| !path.starts_with(&versions_root))
             .map(|path| {
                 path.strip_prefix(&self.storage_root)
                     .context("Failed to strip storage root prefix")
@@ -135,6 +333,7 @@ impl LocalFs {
         // object prefixes are arbitrary strings, so we need the strings for doing
         // starts_with later.
         let prefix = full_path.as_str();
+        let versions_root = self.versions_root();
 
         let mut files = vec![];
         let mut directory_queue = vec![initial_dir];
@@ -143,6 +342,9 @@ impl LocalFs {
             while let Some(Ok(entry)) = entries.next() {
                 let file_name = entry.file_name();
                 let full_file_name = cur_folder.join(file_name);
+                if full_file_name == versions_root {
+                    continue;
+                }
                 if full_file_name.as_str().starts_with(prefix) {
                     let file_remote_path = self.local_file_to_relative_path(full_file_name.clone());
                     files.push(file_remote_path);
@@ -163,61 +365,76 @@ impl RemoteStorage for LocalFs {
         &self,
         prefix: Option<&RemotePath>,
         mode: ListingMode,
+        cancel: &CancellationToken,
     ) -> Result<Listing, DownloadError> {
-        let mut result = Listing::default();
+        with_timeout(self.small_timeout, cancel, async {
+            let mut result = Listing::default();
 
-        if let ListingMode::NoDelimiter = mode {
-            let keys = self
-                .list_recursive(prefix)
-                .await
-                .map_err(DownloadError::Other)?;
-
-            result.keys = keys
-                .into_iter()
-                .filter(|k| {
-                    let path = k.with_base(&self.storage_root);
-                    !path.is_dir()
-                })
-                .collect();
-
-            return Ok(result);
-        }
+            if let ListingMode::NoDelimiter = mode {
+                let keys = self
+                    .list_recursive(prefix)
+                    .await
+                    .map_err(DownloadError::Other)?;
+
+                for key in keys {
+                    let path = key.with_base(&self.storage_root);
+                    if !path.is_dir() {
+                        result.keys.push(
+                            listing_object(key, &path)
+                                .await
+                                .map_err(DownloadError::Other)?,
+                        );
+                    }
+                }
 
-        let path = match prefix {
-            Some(prefix) => Cow::Owned(prefix.with_base(&self.storage_root)),
-            None => Cow::Borrowed(&self.storage_root),
-        };
+                return Ok(result);
+            }
 
-        let prefixes_to_filter = get_all_files(path.as_ref(), false)
-            .await
-            .map_err(DownloadError::Other)?;
+            let path = match prefix {
+                Some(prefix) => Cow::Owned(prefix.with_base(&self.storage_root)),
+                None => Cow::Borrowed(&self.storage_root),
+            };
 
-        // filter out empty directories to mirror s3 behavior.
-        for prefix in prefixes_to_filter {
-            if prefix.is_dir()
-                && is_directory_empty(&prefix)
-                    .await
-                    .map_err(DownloadError::Other)?
-            {
-                continue;
-            }
+            let prefixes_to_filter = get_all_files(path.as_ref(), false)
+                .await
+                .map_err(DownloadError::Other)?;
 
-            let stripped = prefix
-                .strip_prefix(&self.storage_root)
-                .context("Failed to strip prefix")
-                .and_then(RemotePath::new)
-                .expect(
-                    "We list files for storage root, hence should be able to remote the prefix",
-                );
+            // filter out empty directories to mirror s3 behavior.
+            let versions_root = self.versions_root();
+            for prefix in prefixes_to_filter {
+                if prefix == versions_root {
+                    continue;
+                }
+                if prefix.is_dir()
+                    && is_directory_empty(&prefix)
+                        .await
+                        .map_err(DownloadError::Other)?
+                {
+                    continue;
+                }
 
-            if prefix.is_dir() {
-                result.prefixes.push(stripped);
-            } else {
-                result.keys.push(stripped);
+                let stripped = prefix
+                    .strip_prefix(&self.storage_root)
+                    .context("Failed to strip prefix")
+                    .and_then(RemotePath::new)
+                    .expect(
+                        "We list files for storage root, hence should be able to remote the prefix",
+                    );
+
+                if prefix.is_dir() {
+                    result.prefixes.push(stripped);
+                } else {
+                    result.keys.push(
+                        listing_object(stripped, &prefix)
+                            .await
+                            .map_err(DownloadError::Other)?,
+                    );
+                }
             }
-        }
 
-        Ok(result)
+            Ok(result)
+        })
+        .await
     }
 
     async fn upload(
@@ -226,7 +443,9 @@ impl RemoteStorage for LocalFs {
         data_size_bytes: usize,
         to: &RemotePath,
         metadata: Option<StorageMetadata>,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
+        with_timeout(self.timeout, cancel, async {
         let target_file_path = to.with_base(&self.storage_root);
         create_target_directory(&target_file_path).await?;
         // We need this dance with sort of durable rename (without fsyncs)
@@ -308,10 +527,16 @@ impl RemoteStorage for LocalFs {
             })?;
         }
 
-        Ok(())
+        self.record_version(to, false).await
+        })
+        .await
     }
 
-    async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError> {
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
         let target_path = from.with_base(&self.storage_root);
         if file_exists(&target_path).map_err(DownloadError::BadInput)? {
             let source = ReaderStream::new(
@@ -331,7 +556,7 @@ impl RemoteStorage for LocalFs {
                 .map_err(DownloadError::Other)?;
             Ok(Download {
                 metadata,
-                download_stream: Box::pin(source),
+                download_stream: Box::pin(DownloadStream::new(self.timeout, cancel, source)),
             })
         } else {
             Err(DownloadError::NotFound)
@@ -343,6 +568,7 @@ impl RemoteStorage for LocalFs {
         from: &RemotePath,
         start_inclusive: u64,
         end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
         if let Some(end_exclusive) = end_exclusive {
             if end_exclusive <= start_inclusive {
@@ -375,13 +601,19 @@ impl RemoteStorage for LocalFs {
             Ok(match end_exclusive {
                 Some(end_exclusive) => Download {
                     metadata,
-                    download_stream: Box::pin(ReaderStream::new(
-                        source.take(end_exclusive - start_inclusive),
+                    download_stream: Box::pin(DownloadStream::new(
+                        self.timeout,
+                        cancel,
+                        ReaderStream::new(source.take(end_exclusive - start_inclusive)),
                     )),
                 },
                 None => Download {
                     metadata,
-                    download_stream: Box::pin(ReaderStream::new(source)),
+                    download_stream: Box::pin(DownloadStream::new(
+                        self.timeout,
+                        cancel,
+                        ReaderStream::new(source),
+                    )),
                 },
             })
         } else {
@@ -389,23 +621,152 @@ impl RemoteStorage for LocalFs {
         }
     }
 
-    async fn delete(&self, path: &RemotePath) -> anyhow::Result<()> {
-        let file_path = path.with_base(&self.storage_root);
-        match fs::remove_file(&file_path).await {
-            Ok(()) => Ok(()),
-            // The file doesn't exist. This shouldn't yield an error to mirror S3's behaviour.
-            // See https://docs.aws.amazon.com/AmazonS3/latest/API/API_DeleteObject.html
-            // > If there isn't a null version, Amazon S3 does not remove any objects but will still respond that the command was successful.
-            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
-            Err(e) => Err(anyhow::anyhow!(e)),
-        }
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        with_timeout(self.small_timeout, cancel, async {
+            let file_path = path.with_base(&self.storage_root);
+            match fs::remove_file(&file_path).await {
+                Ok(()) => self.record_version(path, true).await,
+                // The file doesn't exist. This shouldn't yield an error to mirror S3's behaviour.
+                // See https://docs.aws.amazon.com/AmazonS3/latest/API/API_DeleteObject.html
+                // > If there isn't a null version, Amazon S3 does not remove any objects but will still respond that the command was successful.
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(anyhow::anyhow!(e)),
+            }
+        })
+        .await
     }
 
-    async fn delete_objects<'a>(&self, paths: &'a [RemotePath]) -> anyhow::Result<()> {
-        for path in paths {
-            self.delete(path).await?
-        }
-        Ok(())
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.small_timeout, cancel, async {
+            for path in paths {
+                self.delete(path, cancel).await?
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.timeout, cancel, async {
+            let source_path = from.with_base(&self.storage_root);
+            let target_path = to.with_base(&self.storage_root);
+            ensure!(
+                file_exists(&source_path)?,
+                "Failed to copy: source file '{source_path}' does not exist"
+            );
+            if source_path == target_path {
+                return Ok(());
+            }
+            copy_file_with_metadata(&source_path, &target_path).await?;
+            self.record_version(to, false).await
+        })
+        .await
+    }
+
+    async fn list_versions(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ObjectVersion>, DownloadError> {
+        with_timeout(self.small_timeout, cancel, async {
+            if !self.versioning_enabled() {
+                return Err(DownloadError::Other(anyhow::anyhow!(
+                    "versioning is not enabled for the local fs remote storage"
+                )));
+            }
+            let versions_root = self.versions_root();
+            let prefix = match prefix {
+                Some(prefix) => prefix.with_base(&self.storage_root),
+                None => self.storage_root.clone(),
+            };
+
+            let mut versions = Vec::new();
+            let files = get_all_files(&versions_root, true)
+                .await
+                .map_err(DownloadError::Other)?;
+            for file in files {
+                let (Some(file_name), Some(key_path)) = (file.file_name(), file.parent()) else {
+                    continue;
+                };
+                let (version_id, kind) = match file_name.split_once('.') {
+                    None => (file_name, ObjectVersionKind::Version),
+                    Some((version_id, LOCAL_FS_DELETE_MARKER_SUFFIX)) => {
+                        (version_id, ObjectVersionKind::DeleteMarker)
+                    }
+                    // Metadata of a version
+                    Some(_) => continue,
+                };
+                let nanos = version_id
+                    .parse::<u64>()
+                    .with_context(|| format!("Invalid object version file '{file}'"))
+                    .map_err(DownloadError::Other)?;
+
+                let key = key_path
+                    .strip_prefix(&versions_root)
+                    .context("Failed to strip versions root prefix")
+                    .and_then(RemotePath::new)
+                    .map_err(DownloadError::Other)?;
+                // Object prefixes are arbitrary strings, same as in `list_recursive`.
+                if !key
+                    .with_base(&self.storage_root)
+                    .as_str()
+                    .starts_with(prefix.as_str())
+                {
+                    continue;
+                }
+
+                versions.push(ObjectVersion {
+                    key,
+                    version_id: version_id.to_owned(),
+                    last_modified: SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos),
+                    kind,
+                    is_latest: false,
+                });
+            }
+
+            // Newest first for each entry, like S3 lists them
+            versions.sort_by(|a, b| (&a.key, b.last_modified).cmp(&(&b.key, a.last_modified)));
+            let mut previous_key = None;
+            for version in &mut versions {
+                version.is_latest = previous_key.as_ref() != Some(&version.key);
+                previous_key = Some(version.key.clone());
+            }
+
+            Ok(versions)
+        })
+        .await
+    }
+
+    async fn restore_version(
+        &self,
+        key: &RemotePath,
+        version_id: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.timeout, cancel, async {
+            ensure!(
+                self.versioning_enabled(),
+                "versioning is not enabled for the local fs remote storage"
+            );
+            let version_path = key.with_base(&self.versions_root()).join(version_id);
+            ensure!(
+                file_exists(&version_path)?,
+                "Failed to restore: version '{version_id}' of '{key}' does not exist"
+            );
+
+            copy_file_with_metadata(&version_path, &key.with_base(&self.storage_root)).await?;
+            self.record_version(key, false).await
+        })
+        .await
     }
 }
 
@@ -413,6 +774,71 @@ fn storage_metadata_path(original_path: &Utf8Path) -> Utf8PathBuf {
     path_with_suffix_extension(original_path, "metadata")
 }
 
+/// Replaces `target_path` and its metadata with a copy of `source_path` and its metadata.
+async fn copy_file_with_metadata(
+    source_path: &Utf8Path,
+    target_path: &Utf8Path,
+) -> anyhow::Result<()> {
+    create_target_directory(target_path).await?;
+
+    // Same rename dance as in `upload`, to never expose a partially copied file.
+    let temp_file_path = path_with_suffix_extension(target_path, LOCAL_FS_TEMP_FILE_SUFFIX);
+    match fs::remove_file(&temp_file_path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to remove stale temp file at '{temp_file_path}'"))
+        }
+    }
+
+    // Files are only ever replaced by renames and never modified in place, so a hard link
+    // is as good as a copy. If linking isn't possible (e.g. the storage root spans
+    // multiple filesystems), fall back to a regular copy, which uses `copy_file_range`
+    // on Linux.
+    if let Err(e) = fs::hard_link(source_path, &temp_file_path).await {
+        debug!("Failed to hard link '{source_path}', copying it instead: {e}");
+        fs::copy(source_path, &temp_file_path)
+            .await
+            .with_context(|| {
+                format!("Failed to copy file to the local storage at '{temp_file_path}'")
+            })?;
+    }
+
+    fs::rename(&temp_file_path, target_path)
+        .await
+        .with_context(|| {
+            format!("Failed to copy (rename) file to the local storage at '{target_path}'")
+        })?;
+
+    // Mirror S3, where the metadata is copied along with the object.
+    let source_metadata_path = storage_metadata_path(source_path);
+    let target_metadata_path = storage_metadata_path(target_path);
+    if source_metadata_path.exists() {
+        fs::copy(&source_metadata_path, &target_metadata_path)
+            .await
+            .with_context(|| {
+                format!("Failed to copy metadata to the local storage at '{target_metadata_path}'")
+            })?;
+    } else {
+        match fs::remove_file(&target_metadata_path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to remove stale metadata at '{target_metadata_path}'")
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn delete_marker_path(version_path: &Utf8Path) -> Utf8PathBuf {
+    path_with_suffix_extension(version_path, LOCAL_FS_DELETE_MARKER_SUFFIX)
+}
+
 fn get_all_files<'a, P>(
     directory_path: P,
     recursive: bool,
@@ -457,6 +883,17 @@ where
     })
 }
 
+async fn listing_object(key: RemotePath, file_path: &Utf8Path) -> anyhow::Result<ListingObject> {
+    let metadata = fs::metadata(file_path)
+        .await
+        .with_context(|| format!("Failed to get metadata of file {file_path:?}"))?;
+    Ok(ListingObject {
+        key,
+        last_modified: metadata.modified()?,
+        size: metadata.len(),
+    })
+}
+
 async fn create_target_directory(target_file_path: &Utf8Path) -> anyhow::Result<()> {
     let target_dir = match target_file_path.parent() {
         Some(parent_dir) => parent_dir,
@@ -481,6 +918,7 @@ fn file_exists(file_path: &Utf8Path) -> anyhow::Result<bool> {
 mod fs_tests {
     use super::*;
 
+    use crate::TimeoutOrCancel;
     use bytes::Bytes;
     use camino_tempfile::tempdir;
     use futures_util::Stream;
@@ -493,8 +931,9 @@ mod fs_tests {
         remote_storage_path: &RemotePath,
         expected_metadata: Option<&StorageMetadata>,
     ) -> anyhow::Result<String> {
+        let cancel = CancellationToken::new();
         let download = storage
-            .download(remote_storage_path)
+            .download(remote_storage_path, &cancel)
             .await
             .map_err(|e| anyhow::anyhow!("Download failed: {e}"))?;
         ensure!(
@@ -531,6 +970,7 @@ mod fs_tests {
     #[tokio::test]
     async fn upload_file_negatives() -> anyhow::Result<()> {
         let storage = create_storage()?;
+        let cancel = CancellationToken::new();
 
         let id = RemotePath::new(Utf8Path::new("dummy"))?;
         let content = Bytes::from_static(b"12345");
@@ -539,32 +979,37 @@ mod fs_tests {
         // Check that you get an error if the size parameter doesn't match the actual
         // size of the stream.
         storage
-            .upload(content(), 0, &id, None)
+            .upload(content(), 0, &id, None, &cancel)
             .await
             .expect_err("upload with zero size succeeded");
         storage
-            .upload(content(), 4, &id, None)
+            .upload(content(), 4, &id, None, &cancel)
             .await
             .expect_err("upload with too short size succeeded");
         storage
-            .upload(content(), 6, &id, None)
+            .upload(content(), 6, &id, None, &cancel)
             .await
             .expect_err("upload with too large size succeeded");
 
         // Correct size is 5, this should succeed.
-        storage.upload(content(), 5, &id, None).await?;
+        storage.upload(content(), 5, &id, None, &cancel).await?;
 
         Ok(())
     }
 
     fn create_storage() -> anyhow::Result<LocalFs> {
         let storage_root = tempdir()?.path().to_path_buf();
-        LocalFs::new(storage_root)
+        LocalFs::new(
+            storage_root,
+            Duration::from_secs(120),
+            Duration::from_secs(30),
+        )
     }
 
     #[tokio::test]
     async fn download_file() -> anyhow::Result<()> {
         let storage = create_storage()?;
+        let cancel = CancellationToken::new();
         let upload_name = "upload_1";
         let upload_target = upload_dummy_file(&storage, upload_name, None).await?;
 
@@ -576,7 +1021,7 @@ mod fs_tests {
         );
 
         let non_existing_path = "somewhere/else";
-        match storage.download(&RemotePath::new(Utf8Path::new(non_existing_path))?).await {
+        match storage.download(&RemotePath::new(Utf8Path::new(non_existing_path))?, &cancel).await {
             Err(DownloadError::NotFound) => {} // Should get NotFound for non existing keys
             other => panic!("Should get a NotFound error when downloading non-existing storage files, but got: {other:?}"),
         }
@@ -586,6 +1031,7 @@ mod fs_tests {
     #[tokio::test]
     async fn download_file_range_positive() -> anyhow::Result<()> {
         let storage = create_storage()?;
+        let cancel = CancellationToken::new();
         let upload_name = "upload_1";
         let upload_target = upload_dummy_file(&storage, upload_name, None).await?;
 
@@ -601,7 +1047,12 @@ mod fs_tests {
         let (first_part_local, second_part_local) = uploaded_bytes.split_at(3);
 
         let first_part_download = storage
-            .download_byte_range(&upload_target, 0, Some(first_part_local.len() as u64))
+            .download_byte_range(
+                &upload_target,
+                0,
+                Some(first_part_local.len() as u64),
+                &cancel,
+            )
             .await?;
         assert!(
             first_part_download.metadata.is_none(),
@@ -619,6 +1070,7 @@ mod fs_tests {
                 &upload_target,
                 first_part_local.len() as u64,
                 Some((first_part_local.len() + second_part_local.len()) as u64),
+                &cancel,
             )
             .await?;
         assert!(
@@ -638,6 +1090,7 @@ mod fs_tests {
     #[tokio::test]
     async fn download_file_range_negative() -> anyhow::Result<()> {
         let storage = create_storage()?;
+        let cancel = CancellationToken::new();
         let upload_name = "upload_1";
         let upload_target = upload_dummy_file(&storage, upload_name, None).await?;
 
@@ -648,6 +1101,7 @@ mod fs_tests {
                 &upload_target,
                 start,
                 Some(end), // exclusive end
+                &cancel,
             )
             .await
         {
@@ -664,7 +1118,7 @@ mod fs_tests {
         let end = 234;
         assert!(start > end, "Should test an incorrect range");
         match storage
-            .download_byte_range(&upload_target, start, Some(end))
+            .download_byte_range(&upload_target, start, Some(end), &cancel)
             .await
         {
             Ok(_) => panic!("Should not allow downloading wrong ranges"),
@@ -682,14 +1136,15 @@ mod fs_tests {
     #[tokio::test]
     async fn delete_file() -> anyhow::Result<()> {
         let storage = create_storage()?;
+        let cancel = CancellationToken::new();
         let upload_name = "upload_1";
         let upload_target = upload_dummy_file(&storage, upload_name, None).await?;
 
-        storage.delete(&upload_target).await?;
+        storage.delete(&upload_target, &cancel).await?;
         assert!(storage.list_all().await?.is_empty());
 
         storage
-            .delete(&upload_target)
+            .delete(&upload_target, &cancel)
             .await
             .expect("Should allow deleting non-existing storage files");
 
@@ -699,6 +1154,7 @@ mod fs_tests {
     #[tokio::test]
     async fn file_with_metadata() -> anyhow::Result<()> {
         let storage = create_storage()?;
+        let cancel = CancellationToken::new();
         let upload_name = "upload_1";
         let metadata = StorageMetadata(HashMap::from([
             ("one".to_string(), "1".to_string()),
@@ -719,7 +1175,12 @@ mod fs_tests {
         let (first_part_local, _) = uploaded_bytes.split_at(3);
 
         let partial_download_with_metadata = storage
-            .download_byte_range(&upload_target, 0, Some(first_part_local.len() as u64))
+            .download_byte_range(
+                &upload_target,
+                0,
+                Some(first_part_local.len() as u64),
+                &cancel,
+            )
             .await?;
         let first_part_remote = aggregate(partial_download_with_metadata.download_stream).await?;
         assert_eq!(
@@ -737,19 +1198,219 @@ mod fs_tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn copy_file() -> anyhow::Result<()> {
+        let storage = create_storage()?;
+        let cancel = CancellationToken::new();
+        let metadata = StorageMetadata(HashMap::from([("one".to_string(), "1".to_string())]));
+        let source = upload_dummy_file(&storage, "source", Some(metadata.clone())).await?;
+        let target = upload_dummy_file(&storage, "target", None).await?;
+
+        // Existing files are overwritten, together with their metadata.
+        storage.copy(&source, &target, &cancel).await?;
+        let contents =
+            read_and_assert_remote_file_contents(&storage, &target, Some(&metadata)).await?;
+        assert_eq!(dummy_contents("source"), contents);
+
+        // The copy doesn't depend on the source.
+        storage.delete(&source, &cancel).await?;
+        let contents =
+            read_and_assert_remote_file_contents(&storage, &target, Some(&metadata)).await?;
+        assert_eq!(dummy_contents("source"), contents);
+        storage
+            .copy(&source, &target, &cancel)
+            .await
+            .expect_err("copying a deleted file should fail");
+
+        // Copying into a new directory works, and copies without metadata drop it.
+        let plain = upload_dummy_file(&storage, "plain", None).await?;
+        let nested = RemotePath::from_string("timelines/other_timeline/copied")?;
+        storage.copy(&target, &nested, &cancel).await?;
+        storage.copy(&plain, &nested, &cancel).await?;
+        let contents = read_and_assert_remote_file_contents(&storage, &nested, None).await?;
+        assert_eq!(dummy_contents("plain"), contents);
+
+        // Copying a file onto itself is a no-op.
+        storage.copy(&nested, &nested, &cancel).await?;
+        let contents = read_and_assert_remote_file_contents(&storage, &nested, None).await?;
+        assert_eq!(dummy_contents("plain"), contents);
+
+        assert!(
+            storage
+                .list_all()
+                .await?
+                .iter()
+                .all(|path| !path.0.as_str().ends_with(LOCAL_FS_TEMP_FILE_SUFFIX)),
+            "No temp files should be left behind"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn time_travel_recover() -> anyhow::Result<()> {
+        let storage = create_storage()?.with_versioning(Duration::from_secs(3600));
+        let cancel = CancellationToken::new();
+        let metadata = StorageMetadata(HashMap::from([("one".to_string(), "1".to_string())]));
+        let overwritten =
+            upload_dummy_file(&storage, "overwritten", Some(metadata.clone())).await?;
+        let deleted = upload_dummy_file(&storage, "deleted", None).await?;
+        let unchanged = upload_dummy_file(&storage, "unchanged", None).await?;
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let timestamp = SystemTime::now();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        storage.copy(&unchanged, &overwritten, &cancel).await?;
+        storage.delete(&deleted, &cancel).await?;
+        let created = upload_dummy_file(&storage, "created", None).await?;
+        let outside = RemotePath::from_string("other_prefix/outside")?;
+        storage.copy(&unchanged, &outside, &cancel).await?;
+
+        let versions = storage.list_versions(None, &cancel).await?;
+        assert!(versions
+            .iter()
+            .any(|v| v.key == deleted && v.kind == ObjectVersionKind::DeleteMarker));
+        assert_eq!(
+            versions.iter().filter(|v| v.key == overwritten).count(),
+            2,
+            "Expected the original and the overwritten version, got: {versions:?}"
+        );
+        assert!(
+            storage
+                .list(None, ListingMode::NoDelimiter, &cancel)
+                .await?
+                .keys
+                .iter()
+                .all(|object| !object.key.0.starts_with(LOCAL_FS_VERSIONS_DIR)),
+            "Versions should not be listed"
+        );
+
+        let prefix = RemotePath::from_string("timelines")?;
+        storage
+            .time_travel_recover(Some(&prefix), timestamp, &cancel)
+            .await?;
+
+        let contents =
+            read_and_assert_remote_file_contents(&storage, &overwritten, Some(&metadata)).await?;
+        assert_eq!(dummy_contents("overwritten"), contents);
+        let contents = read_and_assert_remote_file_contents(&storage, &deleted, None).await?;
+        assert_eq!(dummy_contents("deleted"), contents);
+        let contents = read_and_assert_remote_file_contents(&storage, &unchanged, None).await?;
+        assert_eq!(dummy_contents("unchanged"), contents);
+        assert!(matches!(
+            storage.download(&created, &cancel).await,
+            Err(DownloadError::NotFound)
+        ));
+        // Entries outside of the prefix are left alone.
+        let contents = read_and_assert_remote_file_contents(&storage, &outside, None).await?;
+        assert_eq!(dummy_contents("unchanged"), contents);
+
+        // Recovering to the same timestamp again yields the same state.
+        storage
+            .time_travel_recover(Some(&prefix), timestamp, &cancel)
+            .await?;
+        let contents =
+            read_and_assert_remote_file_contents(&storage, &overwritten, Some(&metadata)).await?;
+        assert_eq!(dummy_contents("overwritten"), contents);
+        assert!(matches!(
+            storage.download(&created, &cancel).await,
+            Err(DownloadError::NotFound)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn old_versions_are_pruned() -> anyhow::Result<()> {
+        let storage = create_storage()?.with_versioning(Duration::ZERO);
+        let cancel = CancellationToken::new();
+        let metadata = StorageMetadata(HashMap::from([("one".to_string(), "1".to_string())]));
+
+        let path = upload_dummy_file(&storage, "file", Some(metadata.clone())).await?;
+        upload_dummy_file(&storage, "file", Some(metadata.clone())).await?;
+        let versions = storage.list_versions(None, &cancel).await?;
+        assert_eq!(versions.len(), 2, "{versions:?}");
+
+        // Only the versions replaced before the latest write are gone
+        storage.delete(&path, &cancel).await?;
+        let versions = storage.list_versions(None, &cancel).await?;
+        assert_eq!(versions.len(), 2, "{versions:?}");
+        assert!(versions[0].is_latest);
+        assert_eq!(versions[0].kind, ObjectVersionKind::DeleteMarker);
+        assert!(!versions[1].is_latest);
+        assert_eq!(versions[1].kind, ObjectVersionKind::Version);
+        assert!(versions[0].last_modified > versions[1].last_modified);
+
+        let version_files = get_all_files(&storage.versions_root(), true).await?;
+        assert_eq!(
+            version_files.len(),
+            3,
+            "Expected a version with its metadata and a delete marker, got: {version_files:?}"
+        );
+
+        // The next write prunes the versions of the deleted object too, with their directory
+        let other = upload_dummy_file(&storage, "other", None).await?;
+        assert!(!path.with_base(&storage.versions_root()).exists());
+        let versions = storage.list_versions(None, &cancel).await?;
+        assert_eq!(versions.len(), 1, "{versions:?}");
+        assert_eq!(versions[0].key, other);
+
+        // Empty parent directories are removed as well
+        let elsewhere = RemotePath::from_string("elsewhere")?;
+        storage.copy(&other, &elsewhere, &cancel).await?;
+        storage.delete(&other, &cancel).await?;
+        storage.delete(&elsewhere, &cancel).await?;
+        assert!(!storage.versions_root().join("timelines").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn versioning_is_opt_in() -> anyhow::Result<()> {
+        let storage = create_storage()?;
+        let cancel = CancellationToken::new();
+
+        let path = upload_dummy_file(&storage, "file", None).await?;
+        storage.delete(&path, &cancel).await?;
+        assert!(!storage.versions_root().exists());
+        storage
+            .list_versions(None, &cancel)
+            .await
+            .expect_err("listing versions without versioning should fail");
+        storage
+            .time_travel_recover(None, SystemTime::now(), &cancel)
+            .await
+            .expect_err("time travel without versioning should fail");
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn list() -> anyhow::Result<()> {
         // No delimiter: should recursively list everything
         let storage = create_storage()?;
+        let cancel = CancellationToken::new();
         let child = upload_dummy_file(&storage, "grandparent/parent/child", None).await?;
         let uncle = upload_dummy_file(&storage, "grandparent/uncle", None).await?;
 
-        let listing = storage.list(None, ListingMode::NoDelimiter).await?;
+        let listing = storage
+            .list(None, ListingMode::NoDelimiter, &cancel)
+            .await?;
         assert!(listing.prefixes.is_empty());
-        assert_eq!(listing.keys, [uncle.clone(), child.clone()].to_vec());
+        assert_eq!(
+            listing_keys(&listing),
+            [uncle.clone(), child.clone()].to_vec()
+        );
+        assert_eq!(
+            listing.keys[0].size,
+            dummy_contents("grandparent/uncle").len() as u64
+        );
 
         // Delimiter: should only go one deep
-        let listing = storage.list(None, ListingMode::WithDelimiter).await?;
+        let listing = storage
+            .list(None, ListingMode::WithDelimiter, &cancel)
+            .await?;
 
         assert_eq!(
             listing.prefixes,
@@ -762,6 +1423,7 @@ mod fs_tests {
             .list(
                 Some(&RemotePath::from_string("timelines/some_timeline/grandparent").unwrap()),
                 ListingMode::WithDelimiter,
+                &cancel,
             )
             .await?;
         assert_eq!(
@@ -769,16 +1431,116 @@ mod fs_tests {
             [RemotePath::from_string("timelines/some_timeline/grandparent/parent").unwrap()]
                 .to_vec()
         );
-        assert_eq!(listing.keys, [uncle.clone()].to_vec());
+        assert_eq!(listing_keys(&listing), [uncle.clone()].to_vec());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stalled_upload_times_out() -> anyhow::Result<()> {
+        let storage_root = tempdir()?.path().to_path_buf();
+        let storage = LocalFs::new(storage_root, Duration::from_millis(100), Duration::ZERO)?;
+        let cancel = CancellationToken::new();
+
+        let target = RemotePath::from_string("stalled")?;
+        let err = storage
+            .upload(stalled_stream(), 10, &target, None, &cancel)
+            .await
+            .expect_err("stalled upload should time out");
+        assert_eq!(
+            TimeoutOrCancel::caused(&err),
+            Some(TimeoutOrCancel::Timeout)
+        );
+        assert!(matches!(
+            storage.download(&target, &cancel).await,
+            Err(DownloadError::NotFound)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stalled_upload_is_cancelled() -> anyhow::Result<()> {
+        let storage = create_storage()?;
+        let cancel = CancellationToken::new();
+
+        let target = RemotePath::from_string("stalled")?;
+        let upload = storage.upload(stalled_stream(), 10, &target, None, &cancel);
+        let canceller = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        };
+        let (res, ()) = tokio::join!(upload, canceller);
+        let err = res.expect_err("stalled upload should be cancelled");
+        assert!(TimeoutOrCancel::caused_by_cancel(&err));
+
+        // Nothing is attempted with an already cancelled token.
+        assert!(matches!(
+            storage.list(None, ListingMode::NoDelimiter, &cancel).await,
+            Err(DownloadError::Cancelled)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn download_stream_fails_on_cancel_and_timeout() -> anyhow::Result<()> {
+        use futures::stream::StreamExt;
+
+        let storage_root = tempdir()?.path().to_path_buf();
+        let storage = LocalFs::new(
+            storage_root,
+            Duration::from_millis(100),
+            Duration::from_secs(30),
+        )?;
+        let upload_target = upload_dummy_file(&storage, "upload_1", None).await?;
+
+        // The reader of the download stalls, until the timeout passes.
+        let download = storage
+            .download(&upload_target, &CancellationToken::new())
+            .await?;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let err = aggregate(download.download_stream)
+            .await
+            .expect_err("stalled download should time out");
+        assert_eq!(
+            TimeoutOrCancel::caused(&err),
+            Some(TimeoutOrCancel::Timeout)
+        );
+
+        let cancel = CancellationToken::new();
+        let mut download = storage
+            .download_byte_range(&upload_target, 0, None, &cancel)
+            .await?;
+        cancel.cancel();
+        let err = download
+            .download_stream
+            .next()
+            .await
+            .expect("stream should yield the cancellation")
+            .expect_err("cancelled download should fail");
+        let reason = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<TimeoutOrCancel>());
+        assert_eq!(reason, Some(&TimeoutOrCancel::Cancel));
 
         Ok(())
     }
 
+    /// A stream which yields a few bytes and then never completes.
+    fn stalled_stream() -> impl Stream<Item = std::io::Result<Bytes>> + Send + Sync {
+        use futures::stream::StreamExt;
+
+        futures::stream::once(futures::future::ready(Ok(Bytes::from_static(b"12345"))))
+            .chain(futures::stream::pending())
+    }
+
     async fn upload_dummy_file(
         storage: &LocalFs,
         name: &str,
         metadata: Option<StorageMetadata>,
     ) -> anyhow::Result<RemotePath> {
+        let cancel = CancellationToken::new();
         let from_path = storage
             .storage_root
             .join("timelines")
@@ -799,7 +1561,9 @@ mod fs_tests {
 
         let file = tokio_util::io::ReaderStream::new(file);
 
-        storage.upload(file, size, &relative_path, metadata).await?;
+        storage
+            .upload(file, size, &relative_path, metadata, &cancel)
+            .await?;
         Ok(relative_path)
     }
 
@@ -825,6 +1589,14 @@ mod fs_tests {
         format!("contents for {name}")
     }
 
+    fn listing_keys(listing: &Listing) -> Vec<RemotePath> {
+        listing
+            .keys
+            .iter()
+            .map(|object| object.key.clone())
+            .collect()
+    }
+
     async fn list_files_sorted(storage: &LocalFs) -> anyhow::Result<Vec<RemotePath>> {
         let mut files = storage.list_all().await?;
         files.sort_by(|a, b| a.0.cmp(&b.0));
@@ -0,0 +1,914 @@
+//! This module provides a wrapper around two real RemoteStorage implementations, that keeps
+//! a copy of everything written to the primary storage in a secondary storage, e.g. a bucket
+//! in another region, for disaster recovery.
+//!
+//! Writes go to the primary storage first, and are then mirrored to the secondary storage,
+//! either before the write returns ([`MirrorMode::Sync`]), or by a background task
+//! ([`MirrorMode::Async`]). Uploads, copies and restored versions are mirrored by streaming
+//! the object back from the primary storage, so the caller's stream is consumed only once,
+//! and the secondary storage does not need to have the source of a copy.
+//!
+//! The queue of writes waiting for the background task is bounded: when it is full, writes
+//! wait for room in it. It only lives in memory, and the task gives up on writes that keep
+//! failing, so writes can be missed by the secondary storage. [`ReplicatedStorage::resync`]
+//! compares the listings of both storages under a prefix, and copies or deletes whatever the
+//! mirroring missed. It is a one-shot job for an operator to run, e.g. with the
+//! `s3_scrubber resync-mirror` command, rather than something every process sharing the
+//! storages does on its own: the listings can be large, and concurrent resyncs would race.
+//!
+//! Reads go to the primary storage, and fall back to the secondary storage when the primary
+//! storage fails or does not have the object. Reads of recently deleted objects may thus be
+//! served from the secondary storage, until the deletion is mirrored.
+//! Versioning only uses the primary storage: the version ids of one storage mean nothing to
+//! the other one.
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{bail, Context};
+use bytes::Bytes;
+use futures::stream::Stream;
+use metrics::{
+    register_gauge, register_int_counter, register_int_gauge, Gauge, IntCounter, IntGauge,
+};
+use once_cell::sync::Lazy;
+use tokio::sync::{watch, Notify};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utils::backoff;
+
+use crate::{
+    Download, DownloadError, GenericRemoteStorage, Listing, ListingMode, ListingObject,
+    ObjectVersion, RemotePath, RemoteStorage, StorageMetadata, TimeoutOrCancel,
+};
+
+/// How many writes the [`MirrorMode::Async`] queue holds before writes wait for room in it.
+const MIRROR_QUEUE_CAPACITY: usize = 10_000;
+
+/// How many times mirroring a write is attempted, before it is left to a resync.
+const MIRROR_MAX_ATTEMPTS: u32 = 10;
+
+/// When the writes reach the secondary storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MirrorMode {
+    /// Writes complete once the primary storage has them, a background task copies them to
+    /// the secondary storage. See the `remote_storage_mirror_lag_seconds` metric.
+    #[default]
+    Async,
+    /// Writes complete once both storages have them, and fail if either of them fails.
+    Sync,
+}
+
+impl FromStr for MirrorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "async" => Ok(MirrorMode::Async),
+            "sync" => Ok(MirrorMode::Sync),
+            _ => bail!("unknown mirror mode '{s}', expected 'async' or 'sync'"),
+        }
+    }
+}
+
+struct MirrorMetrics {
+    pending_operations: IntGauge,
+    lag_seconds: Gauge,
+    fallback_reads: IntCounter,
+    failed_operations: IntCounter,
+    resynced_objects: IntCounter,
+}
+
+static MIRROR_METRICS: Lazy<MirrorMetrics> = Lazy::new(|| MirrorMetrics {
+    pending_operations: register_int_gauge!(
+        "remote_storage_mirror_pending_operations",
+        "Writes not yet mirrored to the secondary remote storage"
+    )
+    .unwrap(),
+    lag_seconds: register_gauge!(
+        "remote_storage_mirror_lag_seconds",
+        "Age of the oldest write not yet mirrored to the secondary remote storage"
+    )
+    .unwrap(),
+    fallback_reads: register_int_counter!(
+        "remote_storage_mirror_fallback_reads_total",
+        "Reads served by the secondary remote storage, because the primary one failed them"
+    )
+    .unwrap(),
+    failed_operations: register_int_counter!(
+        "remote_storage_mirror_failed_operations_total",
+        "Writes that could not be mirrored to the secondary remote storage, left to a resync"
+    )
+    .unwrap(),
+    resynced_objects: register_int_counter!(
+        "remote_storage_mirror_resynced_objects_total",
+        "Objects copied to or deleted from the secondary remote storage by a resync"
+    )
+    .unwrap(),
+});
+
+/// A write to mirror to the secondary storage.
+#[derive(Debug, Clone)]
+enum MirrorOp {
+    /// Copy the current contents of the object from the primary storage.
+    Upload(RemotePath),
+    Delete(Vec<RemotePath>),
+}
+
+struct PendingOp {
+    seq: u64,
+    op: MirrorOp,
+    enqueued_at: Instant,
+}
+
+struct PendingOps {
+    last_seq: u64,
+    ops: VecDeque<PendingOp>,
+}
+
+/// The writes waiting for the [`MirrorMode::Async`] mirroring task, in the order they were
+/// made to the primary storage.
+struct MirrorQueue {
+    capacity: usize,
+    pending: Mutex<PendingOps>,
+    new_ops: Notify,
+    /// Notified when a write leaves a full queue.
+    space: Notify,
+    /// The sequence number of the latest mirrored write.
+    mirrored: watch::Sender<u64>,
+}
+
+impl MirrorQueue {
+    fn new(capacity: usize) -> Self {
+        MirrorQueue {
+            capacity,
+            pending: Mutex::new(PendingOps {
+                last_seq: 0,
+                ops: VecDeque::new(),
+            }),
+            new_ops: Notify::new(),
+            space: Notify::new(),
+            mirrored: watch::channel(0).0,
+        }
+    }
+
+    /// Queues a write, waiting for room in the queue while it is full. If the wait is
+    /// cancelled, the write is left to a resync.
+    async fn push(&self, op: MirrorOp, cancel: &CancellationToken) -> anyhow::Result<()> {
+        loop {
+            let space = self.space.notified();
+            tokio::pin!(space);
+            // Register before looking at the queue, not to miss a pop in between
+            space.as_mut().enable();
+            {
+                let mut pending = self.pending.lock().unwrap();
+                if pending.ops.len() < self.capacity {
+                    pending.last_seq += 1;
+                    let seq = pending.last_seq;
+                    pending.ops.push_back(PendingOp {
+                        seq,
+                        op,
+                        enqueued_at: Instant::now(),
+                    });
+                    MIRROR_METRICS.pending_operations.inc();
+                    self.new_ops.notify_one();
+                    return Ok(());
+                }
+            }
+            tokio::select! {
+                _ = space => {}
+                _ = cancel.cancelled() => return Err(TimeoutOrCancel::Cancel.into()),
+            }
+        }
+    }
+
+    /// Returns the oldest pending write, waiting for one if there are none. The write stays
+    /// in the queue until [`MirrorQueue::pop`] is called, so that it counts towards the lag.
+    async fn front(&self) -> (u64, MirrorOp) {
+        loop {
+            if let Some(op) = self.pending.lock().unwrap().ops.front() {
+                return (op.seq, op.op.clone());
+            }
+            self.new_ops.notified().await;
+        }
+    }
+
+    fn pop(&self) {
+        let seq = {
+            let mut pending = self.pending.lock().unwrap();
+            let op = pending
+                .ops
+                .pop_front()
+                .expect("popping an operation returned by front");
+            op.seq
+        };
+        MIRROR_METRICS.pending_operations.dec();
+        self.space.notify_waiters();
+        self.mirrored.send_replace(seq);
+    }
+
+    fn lag(&self) -> Duration {
+        let pending = self.pending.lock().unwrap();
+        pending
+            .ops
+            .front()
+            .map(|op| op.enqueued_at.elapsed())
+            .unwrap_or_default()
+    }
+}
+
+pub struct ReplicatedStorage {
+    primary: GenericRemoteStorage,
+    secondary: GenericRemoteStorage,
+    mode: MirrorMode,
+    queue: Arc<MirrorQueue>,
+    /// Stops the mirroring task when the storage is dropped.
+    cancel: CancellationToken,
+}
+
+impl ReplicatedStorage {
+    /// With [`MirrorMode::Async`], this spawns the mirroring task, and has to be called within
+    /// a tokio runtime.
+    pub fn new(
+        primary: GenericRemoteStorage,
+        secondary: GenericRemoteStorage,
+        mode: MirrorMode,
+    ) -> anyhow::Result<Self> {
+        Self::with_queue_capacity(primary, secondary, mode, MIRROR_QUEUE_CAPACITY)
+    }
+
+    fn with_queue_capacity(
+        primary: GenericRemoteStorage,
+        secondary: GenericRemoteStorage,
+        mode: MirrorMode,
+        queue_capacity: usize,
+    ) -> anyhow::Result<Self> {
+        let queue = Arc::new(MirrorQueue::new(queue_capacity));
+        let cancel = CancellationToken::new();
+        if mode == MirrorMode::Async {
+            let runtime = tokio::runtime::Handle::try_current()
+                .context("asynchronous mirroring needs a tokio runtime")?;
+            runtime.spawn(mirror_task(
+                Arc::clone(&queue),
+                primary.clone(),
+                secondary.clone(),
+                cancel.clone(),
+            ));
+        }
+        Ok(ReplicatedStorage {
+            primary,
+            secondary,
+            mode,
+            queue,
+            cancel,
+        })
+    }
+
+    /// The storage that the reads and the versioning go to.
+    pub(crate) fn primary(&self) -> &GenericRemoteStorage {
+        &self.primary
+    }
+
+    /// How long the oldest write not yet mirrored to the secondary storage has been waiting.
+    /// Always zero with [`MirrorMode::Sync`].
+    pub fn mirror_lag(&self) -> Duration {
+        self.queue.lag()
+    }
+
+    /// Waits until every write made so far has been mirrored to the secondary storage, or
+    /// given up on.
+    pub async fn wait_mirrored(&self) {
+        let last_seq = self.queue.pending.lock().unwrap().last_seq;
+        let mut mirrored = self.queue.mirrored.subscribe();
+        tokio::select! {
+            _ = mirrored.wait_for(|seq| *seq >= last_seq) => {}
+            _ = self.cancel.cancelled() => {}
+        }
+    }
+
+    /// Makes the secondary storage catch up with the writes under `prefix`, or all of the
+    /// storage, that the mirroring missed, see [`resync`].
+    pub async fn resync(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        resync(&self.primary, &self.secondary, prefix, cancel).await
+    }
+
+    async fn mirror_write(&self, op: MirrorOp, cancel: &CancellationToken) -> anyhow::Result<()> {
+        match self.mode {
+            MirrorMode::Sync => mirror(&self.primary, &self.secondary, &op, cancel)
+                .await
+                .with_context(|| format!("mirror {op:?} to the secondary storage")),
+            MirrorMode::Async => self.queue.push(op, cancel).await,
+        }
+    }
+
+    /// Picks the result of a read from the secondary storage, after the primary storage failed it.
+    fn fall_back<T>(
+        &self,
+        primary_error: DownloadError,
+        secondary_result: Result<T, DownloadError>,
+    ) -> Result<T, DownloadError> {
+        match secondary_result {
+            Ok(result) => {
+                MIRROR_METRICS.fallback_reads.inc();
+                warn!(
+                    "Read from the secondary storage, as the primary one failed: {primary_error}"
+                );
+                Ok(result)
+            }
+            // Report the failure of the primary storage, the source of truth, rather than
+            // e.g. a NotFound from the lagging secondary storage.
+            Err(_) => Err(primary_error),
+        }
+    }
+}
+
+impl Drop for ReplicatedStorage {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// Whether a read that failed on the primary storage should be retried on the secondary one.
+fn should_fall_back(error: &DownloadError) -> bool {
+    match error {
+        DownloadError::Timeout | DownloadError::Other(_) | DownloadError::NotFound => true,
+        DownloadError::BadInput(_) | DownloadError::Cancelled => false,
+    }
+}
+
+/// Whether retrying to mirror a write cannot help.
+fn is_permanent_mirror_error(error: &anyhow::Error) -> bool {
+    TimeoutOrCancel::caused_by_cancel(error)
+        || matches!(
+            error.downcast_ref::<DownloadError>(),
+            Some(DownloadError::BadInput(_))
+        )
+}
+
+/// Streams the current contents of an object from the primary storage to the secondary one.
+async fn mirror_upload(
+    primary: &GenericRemoteStorage,
+    secondary: &GenericRemoteStorage,
+    path: &RemotePath,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    // The upload needs the size up front, and the object is not necessarily of the size it
+    // had when the write was made. If it changes again before the download, the upload
+    // fails on the size mismatch, and is retried.
+    let listing = primary
+        .list(Some(path), ListingMode::NoDelimiter, cancel)
+        .await
+        .context("list the primary storage")?;
+    let Some(object) = listing.keys.into_iter().find(|object| &object.key == path) else {
+        // Deleted from the primary storage since, that deletion gets mirrored too.
+        return Ok(());
+    };
+    let download = match primary.download(path, cancel).await {
+        Ok(download) => download,
+        Err(DownloadError::NotFound) => return Ok(()),
+        Err(e) => return Err(e).context("read back from the primary storage"),
+    };
+    secondary
+        .upload(
+            download.download_stream,
+            object.size as usize,
+            path,
+            download.metadata,
+            cancel,
+        )
+        .await
+}
+
+/// Applies a write made to the primary storage to the secondary one.
+async fn mirror(
+    primary: &GenericRemoteStorage,
+    secondary: &GenericRemoteStorage,
+    op: &MirrorOp,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    match op {
+        MirrorOp::Upload(path) => mirror_upload(primary, secondary, path, cancel).await,
+        MirrorOp::Delete(paths) => secondary.delete_objects(paths, cancel).await,
+    }
+}
+
+/// Mirrors the queued writes one by one, in order, retrying each a few times before
+/// leaving it to a resync.
+async fn mirror_task(
+    queue: Arc<MirrorQueue>,
+    primary: GenericRemoteStorage,
+    secondary: GenericRemoteStorage,
+    cancel: CancellationToken,
+) {
+    loop {
+        let (seq, op) = tokio::select! {
+            op = queue.front() => op,
+            _ = cancel.cancelled() => break,
+        };
+
+        let description = format!("mirroring {op:?}");
+        let res = backoff::retry(
+            || async {
+                MIRROR_METRICS.lag_seconds.set(queue.lag().as_secs_f64());
+                mirror(&primary, &secondary, &op, &cancel).await
+            },
+            is_permanent_mirror_error,
+            3,
+            MIRROR_MAX_ATTEMPTS,
+            &description,
+            backoff::Cancel::new(cancel.clone(), || anyhow::anyhow!("Cancelled")),
+        )
+        .await;
+        match res {
+            Ok(()) => tracing::trace!("mirrored write {seq}"),
+            Err(_) if cancel.is_cancelled() => break,
+            Err(e) => {
+                MIRROR_METRICS.failed_operations.inc();
+                warn!("Gave up {description}, leaving it to a resync: {e:#}");
+            }
+        }
+
+        queue.pop();
+        MIRROR_METRICS.lag_seconds.set(queue.lag().as_secs_f64());
+    }
+
+    let abandoned = queue.pending.lock().unwrap().ops.len();
+    if abandoned > 0 {
+        warn!("Stopped mirroring with {abandoned} writes left to a resync");
+    } else {
+        info!("Stopped mirroring");
+    }
+}
+
+/// Compares the listings of the two storages under `prefix`, and makes the secondary storage
+/// catch up with the writes that the mirroring missed. Objects that the secondary storage
+/// does not have, or has an older or differently sized copy of, are copied from the primary
+/// storage.
+/// Objects that the primary storage does not have are deleted from the secondary storage,
+/// unless written there after the primary storage was listed: those are new objects that
+/// the mirroring task just copied.
+///
+/// This runs concurrently with the mirroring tasks of the processes writing to the storages,
+/// so it can race with them, e.g. copy an object that is being deleted. Running it again
+/// repairs such objects.
+async fn resync(
+    primary: &GenericRemoteStorage,
+    secondary: &GenericRemoteStorage,
+    prefix: Option<&RemotePath>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let started_at = SystemTime::now();
+    let primary_objects = primary
+        .list(prefix, ListingMode::NoDelimiter, cancel)
+        .await
+        .context("list the primary storage")?
+        .keys;
+    let mut secondary_objects: HashMap<RemotePath, ListingObject> = secondary
+        .list(prefix, ListingMode::NoDelimiter, cancel)
+        .await
+        .context("list the secondary storage")?
+        .keys
+        .into_iter()
+        .map(|object| (object.key.clone(), object))
+        .collect();
+
+    let mut copied = 0;
+    let mut failed = 0;
+    for object in primary_objects {
+        let secondary_object = secondary_objects.remove(&object.key);
+        let up_to_date = secondary_object.is_some_and(|secondary_object| {
+            secondary_object.size == object.size
+                && secondary_object.last_modified >= object.last_modified
+        });
+        if up_to_date {
+            continue;
+        }
+        match mirror_upload(primary, secondary, &object.key, cancel).await {
+            Ok(()) => copied += 1,
+            Err(e) if TimeoutOrCancel::caused_by_cancel(&e) => return Err(e),
+            Err(e) => {
+                failed += 1;
+                warn!(
+                    "Failed to copy {} to the secondary storage: {e:#}",
+                    object.key
+                );
+            }
+        }
+    }
+
+    let deleted: Vec<RemotePath> = secondary_objects
+        .into_values()
+        .filter(|object| object.last_modified < started_at)
+        .map(|object| object.key)
+        .collect();
+    if !deleted.is_empty() {
+        secondary
+            .delete_objects(&deleted, cancel)
+            .await
+            .context("delete from the secondary storage")?;
+    }
+
+    MIRROR_METRICS
+        .resynced_objects
+        .inc_by((copied + deleted.len()) as u64);
+    if failed > 0 {
+        bail!(
+            "{failed} objects failed to copy, {copied} copied and {} deleted",
+            deleted.len()
+        );
+    }
+    info!(
+        "Resynced the secondary storage: {copied} objects copied, {} deleted",
+        deleted.len()
+    );
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl RemoteStorage for ReplicatedStorage {
+    async fn list(
+        &self,
+        prefix: Option<&RemotePath>,
+        mode: ListingMode,
+        cancel: &CancellationToken,
+    ) -> Result<Listing, DownloadError> {
+        match self.primary.list(prefix, mode, cancel).await {
+            Err(e) if should_fall_back(&e) => {
+                let res = self.secondary.list(prefix, mode, cancel).await;
+                self.fall_back(e, res)
+            }
+            res => res,
+        }
+    }
+
+    async fn upload(
+        &self,
+        data: impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+        data_size_bytes: usize,
+        to: &RemotePath,
+        metadata: Option<StorageMetadata>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.primary
+            .upload(data, data_size_bytes, to, metadata, cancel)
+            .await?;
+        self.mirror_write(MirrorOp::Upload(to.clone()), cancel)
+            .await
+    }
+
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        match self.primary.download(from, cancel).await {
+            Err(e) if should_fall_back(&e) => {
+                let res = self.secondary.download(from, cancel).await;
+                self.fall_back(e, res)
+            }
+            res => res,
+        }
+    }
+
+    async fn download_byte_range(
+        &self,
+        from: &RemotePath,
+        start_inclusive: u64,
+        end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        match self
+            .primary
+            .download_byte_range(from, start_inclusive, end_exclusive, cancel)
+            .await
+        {
+            Err(e) if should_fall_back(&e) => {
+                let res = self
+                    .secondary
+                    .download_byte_range(from, start_inclusive, end_exclusive, cancel)
+                    .await;
+                self.fall_back(e, res)
+            }
+            res => res,
+        }
+    }
+
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        self.primary.delete(path, cancel).await?;
+        self.mirror_write(MirrorOp::Delete(vec![path.clone()]), cancel)
+            .await
+    }
+
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.primary.delete_objects(paths, cancel).await?;
+        self.mirror_write(MirrorOp::Delete(paths.to_vec()), cancel)
+            .await
+    }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.primary.copy(from, to, cancel).await?;
+        self.mirror_write(MirrorOp::Upload(to.clone()), cancel)
+            .await
+    }
+
+    async fn list_versions(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ObjectVersion>, DownloadError> {
+        self.primary.list_versions(prefix, cancel).await
+    }
+
+    async fn restore_version(
+        &self,
+        key: &RemotePath,
+        version_id: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.primary
+            .restore_version(key, version_id, cancel)
+            .await?;
+        self.mirror_write(MirrorOp::Upload(key.clone()), cancel)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+    use camino_tempfile::Utf8TempDir;
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::{LocalFs, RemoteStorageConfig};
+
+    fn local_fs(dir: &Utf8Path) -> GenericRemoteStorage {
+        GenericRemoteStorage::LocalFs(
+            LocalFs::new(
+                dir.to_owned(),
+                RemoteStorageConfig::DEFAULT_TIMEOUT,
+                RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
+            )
+            .unwrap(),
+        )
+    }
+
+    struct Storages {
+        _dirs: [Utf8TempDir; 2],
+        primary: GenericRemoteStorage,
+        secondary: GenericRemoteStorage,
+    }
+
+    fn storages() -> Storages {
+        let dirs = [
+            camino_tempfile::tempdir().unwrap(),
+            camino_tempfile::tempdir().unwrap(),
+        ];
+        let primary = local_fs(dirs[0].path());
+        let secondary = local_fs(dirs[1].path());
+        Storages {
+            _dirs: dirs,
+            primary,
+            secondary,
+        }
+    }
+
+    /// Returns the storage, and the same storage as a [`GenericRemoteStorage`] for the helpers.
+    fn replicated(
+        primary: GenericRemoteStorage,
+        secondary: GenericRemoteStorage,
+        mode: MirrorMode,
+    ) -> (Arc<ReplicatedStorage>, GenericRemoteStorage) {
+        let replicated = Arc::new(ReplicatedStorage::new(primary, secondary, mode).unwrap());
+        let generic = GenericRemoteStorage::Replicated(Arc::clone(&replicated));
+        (replicated, generic)
+    }
+
+    async fn upload(
+        storage: &GenericRemoteStorage,
+        path: &RemotePath,
+        contents: &'static str,
+    ) -> anyhow::Result<()> {
+        let data = futures::stream::once(futures::future::ready(Ok(Bytes::from(contents))));
+        storage
+            .upload(data, contents.len(), path, None, &CancellationToken::new())
+            .await
+    }
+
+    async fn read(
+        storage: &GenericRemoteStorage,
+        path: &RemotePath,
+    ) -> Result<String, DownloadError> {
+        let download = storage.download(path, &CancellationToken::new()).await?;
+        let mut contents = Vec::new();
+        let mut stream = download.download_stream;
+        while let Some(chunk) = stream.next().await {
+            contents.extend_from_slice(&chunk.unwrap());
+        }
+        Ok(String::from_utf8(contents).unwrap())
+    }
+
+    #[tokio::test]
+    async fn async_mode_mirrors_writes() -> anyhow::Result<()> {
+        let storages = storages();
+        let (replicated, storage) = replicated(
+            storages.primary.clone(),
+            storages.secondary.clone(),
+            MirrorMode::Async,
+        );
+        let cancel = CancellationToken::new();
+
+        let a = RemotePath::from_string("timelines/a")?;
+        let b = RemotePath::from_string("timelines/b")?;
+        upload(&storage, &a, "first").await?;
+        upload(&storage, &a, "second").await?;
+        storage.copy(&a, &b, &cancel).await?;
+        replicated.wait_mirrored().await;
+
+        assert_eq!(read(&storages.secondary, &a).await?, "second");
+        assert_eq!(read(&storages.secondary, &b).await?, "second");
+        assert_eq!(replicated.mirror_lag(), Duration::ZERO);
+
+        storage.delete(&a, &cancel).await?;
+        replicated.wait_mirrored().await;
+        assert!(matches!(
+            read(&storages.secondary, &a).await,
+            Err(DownloadError::NotFound)
+        ));
+        assert_eq!(read(&storages.secondary, &b).await?, "second");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_mode_retries_failed_mirroring() -> anyhow::Result<()> {
+        let storages = storages();
+        let (replicated, storage) = replicated(
+            storages.primary.clone(),
+            GenericRemoteStorage::unreliable_wrapper(storages.secondary.clone(), 2),
+            MirrorMode::Async,
+        );
+
+        let a = RemotePath::from_string("a")?;
+        upload(&storage, &a, "contents").await?;
+        replicated.wait_mirrored().await;
+        assert_eq!(read(&storages.secondary, &a).await?, "contents");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sync_mode_fails_writes_when_the_secondary_fails() -> anyhow::Result<()> {
+        let storages = storages();
+        let (replicated, storage) = replicated(
+            storages.primary.clone(),
+            GenericRemoteStorage::unreliable_wrapper(storages.secondary.clone(), 2),
+            MirrorMode::Sync,
+        );
+
+        let a = RemotePath::from_string("a")?;
+        upload(&storage, &a, "contents")
+            .await
+            .expect_err("the secondary storage fails the first attempt");
+        // The primary storage has the object already, and the retry gets it mirrored.
+        assert_eq!(read(&storages.primary, &a).await?, "contents");
+        upload(&storage, &a, "contents").await?;
+        assert_eq!(read(&storages.secondary, &a).await?, "contents");
+        assert_eq!(replicated.mirror_lag(), Duration::ZERO);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reads_fall_back_to_the_secondary() -> anyhow::Result<()> {
+        let storages = storages();
+        let (_, storage) = replicated(
+            GenericRemoteStorage::unreliable_wrapper(storages.primary.clone(), 2),
+            storages.secondary.clone(),
+            MirrorMode::Sync,
+        );
+
+        // Only in the secondary storage, e.g. deleted from the primary one, with the
+        // deletion not mirrored yet.
+        let a = RemotePath::from_string("a")?;
+        upload(&storages.secondary, &a, "secondary").await?;
+        assert_eq!(read(&storage, &a).await?, "secondary");
+        assert_eq!(read(&storage, &a).await?, "secondary");
+
+        // In both storages, but the primary storage fails the first read.
+        let b = RemotePath::from_string("b")?;
+        upload(&storages.primary, &b, "primary").await?;
+        upload(&storages.secondary, &b, "secondary").await?;
+        assert_eq!(read(&storage, &b).await?, "secondary");
+        assert_eq!(read(&storage, &b).await?, "primary");
+
+        // The failure of the primary storage is reported when the secondary storage does not
+        // have the object either.
+        let c = RemotePath::from_string("c")?;
+        assert!(matches!(
+            read(&storage, &c).await,
+            Err(DownloadError::Other(_))
+        ));
+        assert!(matches!(
+            read(&storage, &c).await,
+            Err(DownloadError::NotFound)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resync_repairs_the_secondary() -> anyhow::Result<()> {
+        let storages = storages();
+        let (replicated, _) = replicated(
+            storages.primary.clone(),
+            storages.secondary.clone(),
+            MirrorMode::Async,
+        );
+        let cancel = CancellationToken::new();
+
+        // Written while the mirroring was not running, e.g. queued before a restart.
+        let missing = RemotePath::from_string("timelines/missing")?;
+        upload(&storages.primary, &missing, "missing").await?;
+        let stale = RemotePath::from_string("timelines/stale")?;
+        upload(&storages.secondary, &stale, "old").await?;
+        upload(&storages.primary, &stale, "overwritten").await?;
+        let deleted = RemotePath::from_string("timelines/deleted")?;
+        upload(&storages.secondary, &deleted, "deleted").await?;
+        let mirrored = RemotePath::from_string("timelines/mirrored")?;
+        upload(&storages.primary, &mirrored, "mirrored").await?;
+        upload(&storages.secondary, &mirrored, "mirrored").await?;
+        // Outside of the resynced prefix.
+        let other_missing = RemotePath::from_string("other/missing")?;
+        upload(&storages.primary, &other_missing, "missing").await?;
+        let other_deleted = RemotePath::from_string("other/deleted")?;
+        upload(&storages.secondary, &other_deleted, "deleted").await?;
+
+        let prefix = RemotePath::from_string("timelines")?;
+        replicated.resync(Some(&prefix), &cancel).await?;
+
+        assert_eq!(read(&storages.secondary, &missing).await?, "missing");
+        assert_eq!(read(&storages.secondary, &stale).await?, "overwritten");
+        assert_eq!(read(&storages.secondary, &mirrored).await?, "mirrored");
+        assert!(matches!(
+            read(&storages.secondary, &deleted).await,
+            Err(DownloadError::NotFound)
+        ));
+        assert!(matches!(
+            read(&storages.secondary, &other_missing).await,
+            Err(DownloadError::NotFound)
+        ));
+        assert_eq!(read(&storages.secondary, &other_deleted).await?, "deleted");
+
+        replicated.resync(None, &cancel).await?;
+        assert_eq!(read(&storages.secondary, &other_missing).await?, "missing");
+        assert!(matches!(
+            read(&storages.secondary, &other_deleted).await,
+            Err(DownloadError::NotFound)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_mode_waits_for_room_in_the_queue() -> anyhow::Result<()> {
+        let storages = storages();
+        let replicated = Arc::new(ReplicatedStorage::with_queue_capacity(
+            storages.primary.clone(),
+            storages.secondary.clone(),
+            MirrorMode::Async,
+            1,
+        )?);
+        let storage = GenericRemoteStorage::Replicated(Arc::clone(&replicated));
+
+        let paths = ["a", "b", "c"].map(|path| RemotePath::from_string(path).unwrap());
+        for path in &paths {
+            upload(&storage, path, "contents").await?;
+            assert!(replicated.queue.pending.lock().unwrap().ops.len() <= 1);
+        }
+        replicated.wait_mirrored().await;
+        for path in &paths {
+            assert_eq!(read(&storages.secondary, path).await?, "contents");
+        }
+
+        // A write that gives up waiting for room is left to a resync.
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let full = MirrorQueue::new(0);
+        let err = full
+            .push(MirrorOp::Upload(paths[0].clone()), &cancel)
+            .await
+            .expect_err("the queue has no room");
+        assert!(TimeoutOrCancel::caused_by_cancel(&err));
+        Ok(())
+    }
+}
@@ -9,6 +9,7 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
+    time::{Duration, SystemTime},
 };
 
 use anyhow::Context as _;
@@ -26,7 +27,7 @@ use aws_sdk_s3::{
     config::{AsyncSleep, Builder, IdentityCache, Region, SharedAsyncSleep},
     error::SdkError,
     operation::get_object::GetObjectError,
-    types::{Delete, ObjectIdentifier},
+    types::{CompletedMultipartUpload, CompletedPart, Delete, ObjectIdentifier},
     Client,
 };
 use aws_smithy_async::rt::sleep::TokioSleep;
@@ -37,11 +38,14 @@ use bytes::Bytes;
 use futures::stream::Stream;
 use hyper::Body;
 use scopeguard::ScopeGuard;
+use tokio_util::sync::CancellationToken;
 
 use super::StorageMetadata;
 use crate::{
-    ConcurrencyLimiter, Download, DownloadError, Listing, ListingMode, RemotePath, RemoteStorage,
-    S3Config, MAX_KEYS_PER_DELETE, REMOTE_STORAGE_PREFIX_SEPARATOR,
+    support::{with_timeout, DownloadStream},
+    ConcurrencyLimiter, Download, DownloadError, Listing, ListingMode, ListingObject,
+    ObjectVersion, ObjectVersionKind, RemotePath, RemoteStorage, S3Config, MAX_KEYS_PER_DELETE,
+    REMOTE_STORAGE_PREFIX_SEPARATOR,
 };
 
 pub(super) mod metrics;
@@ -49,6 +53,13 @@ pub(super) mod metrics;
 use self::metrics::AttemptOutcome;
 pub(super) use self::metrics::RequestKind;
 
+/// S3 rejects `CopyObject` requests for objects larger than 5 GiB, those need a multipart copy.
+/// <https://docs.aws.amazon.com/AmazonS3/latest/API/API_CopyObject.html>
+const MAX_SINGLE_COPY_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+
+/// Size of a single `UploadPartCopy` request, S3 allows between 5 MiB and 5 GiB.
+const MULTIPART_COPY_PART_SIZE: i64 = 1024 * 1024 * 1024;
+
 /// AWS S3 storage.
 pub struct S3Bucket {
     client: Client,
@@ -56,6 +67,8 @@ pub struct S3Bucket {
     prefix_in_bucket: Option<String>,
     max_keys_per_list_response: Option<i32>,
     concurrency_limiter: ConcurrencyLimiter,
+    timeout: Duration,
+    small_timeout: Duration,
 }
 
 #[derive(Default)]
@@ -66,12 +79,11 @@ struct GetObjectRequest {
 }
 impl S3Bucket {
     /// Creates the S3 storage, errors if incorrect AWS S3 configuration provided.
-    pub fn new(aws_config: &S3Config) -> anyhow::Result<Self> {
-        tracing::debug!(
-            "Creating s3 remote storage for S3 bucket {}",
-            aws_config.bucket_name
-        );
-
+    pub fn new(
+        aws_config: &S3Config,
+        timeout: Duration,
+        small_timeout: Duration,
+    ) -> anyhow::Result<Self> {
         let region = Some(Region::new(aws_config.bucket_region.clone()));
 
         let credentials_provider = {
@@ -83,7 +95,7 @@ impl S3Bucket {
             // uses "AWS_WEB_IDENTITY_TOKEN_FILE", "AWS_ROLE_ARN", "AWS_ROLE_SESSION_NAME"
             // needed to access remote extensions bucket
             .or_else("token", {
-                let provider_conf = ProviderConfig::without_region().with_region(region.clone());
+                let provider_conf = ProviderConfig::without_region().with_region(region);
                 WebIdentityTokenCredentialsProvider::builder()
                     .configure(&provider_conf)
                     .build()
@@ -92,6 +104,29 @@ impl S3Bucket {
             .or_else("imds", ImdsCredentialsProvider::builder().build())
         };
 
+        Self::with_credentials_provider(
+            aws_config,
+            timeout,
+            small_timeout,
+            SharedCredentialsProvider::new(credentials_provider),
+        )
+    }
+
+    /// Creates the S3 storage with credentials from `credentials_provider` instead of the
+    /// default chain, e.g. for the offline tools that run with developers' SSO sessions.
+    pub fn with_credentials_provider(
+        aws_config: &S3Config,
+        timeout: Duration,
+        small_timeout: Duration,
+        credentials_provider: SharedCredentialsProvider,
+    ) -> anyhow::Result<Self> {
+        tracing::debug!(
+            "Creating s3 remote storage for S3 bucket {}",
+            aws_config.bucket_name
+        );
+
+        let region = Some(Region::new(aws_config.bucket_region.clone()));
+
         // AWS SDK requires us to specify how the RetryConfig should sleep when it wants to back off
         let sleep_impl: Arc<dyn AsyncSleep> = Arc::new(TokioSleep::new());
 
@@ -107,7 +142,7 @@ impl S3Bucket {
             .behavior_version(BehaviorVersion::v2023_11_09())
             .region(region)
             .identity_cache(IdentityCache::lazy().build())
-            .credentials_provider(SharedCredentialsProvider::new(credentials_provider))
+            .credentials_provider(credentials_provider)
             .retry_config(retry_config.build())
             .sleep_impl(SharedAsyncSleep::from(sleep_impl));
 
@@ -137,6 +172,8 @@ impl S3Bucket {
             max_keys_per_list_response: aws_config.max_keys_per_list_response,
             prefix_in_bucket,
             concurrency_limiter: ConcurrencyLimiter::new(aws_config.concurrency_limit.get()),
+            timeout,
+            small_timeout,
         })
     }
 
@@ -201,52 +238,187 @@ impl S3Bucket {
         permit
     }
 
-    async fn download_object(&self, request: GetObjectRequest) -> Result<Download, DownloadError> {
-        let kind = RequestKind::Get;
-        let permit = self.owned_permit(kind).await;
+    async fn download_object(
+        &self,
+        request: GetObjectRequest,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
+        with_timeout(self.timeout, cancel, async {
+            let kind = RequestKind::Get;
+            let permit = self.owned_permit(kind).await;
 
-        let started_at = start_measuring_requests(kind);
+            let started_at = start_measuring_requests(kind);
 
-        let get_object = self
-            .client
-            .get_object()
-            .bucket(request.bucket)
-            .key(request.key)
-            .set_range(request.range)
-            .send()
-            .await;
+            let get_object = self
+                .client
+                .get_object()
+                .bucket(request.bucket)
+                .key(request.key)
+                .set_range(request.range)
+                .send()
+                .await;
 
-        let started_at = ScopeGuard::into_inner(started_at);
+            let started_at = ScopeGuard::into_inner(started_at);
 
-        if get_object.is_err() {
-            metrics::BUCKET_METRICS.req_seconds.observe_elapsed(
-                kind,
-                AttemptOutcome::Err,
-                started_at,
-            );
-        }
+            if get_object.is_err() {
+                metrics::BUCKET_METRICS.req_seconds.observe_elapsed(
+                    kind,
+                    AttemptOutcome::Err,
+                    started_at,
+                );
+            }
 
-        match get_object {
-            Ok(object_output) => {
-                let metadata = object_output.metadata().cloned().map(StorageMetadata);
+            match get_object {
+                Ok(object_output) => {
+                    let metadata = object_output.metadata().cloned().map(StorageMetadata);
 
-                let body = object_output.body;
-                let body = ByteStreamAsStream::from(body);
-                let body = PermitCarrying::new(permit, body);
-                let body = TimedDownload::new(started_at, body);
+                    let body = object_output.body;
+                    let body = ByteStreamAsStream::from(body);
+                    let body = PermitCarrying::new(permit, body);
+                    let body = TimedDownload::new(started_at, body);
+                    let body = DownloadStream::new(self.timeout, cancel, body);
 
-                Ok(Download {
-                    metadata,
-                    download_stream: Box::pin(body),
-                })
+                    Ok(Download {
+                        metadata,
+                        download_stream: Box::pin(body),
+                    })
+                }
+                Err(SdkError::ServiceError(e))
+                    if matches!(e.err(), GetObjectError::NoSuchKey(_)) =>
+                {
+                    Err(DownloadError::NotFound)
+                }
+                Err(e) => Err(DownloadError::Other(
+                    anyhow::Error::new(e).context("download s3 object"),
+                )),
             }
-            Err(SdkError::ServiceError(e)) if matches!(e.err(), GetObjectError::NoSuchKey(_)) => {
-                Err(DownloadError::NotFound)
+        })
+        .await
+    }
+
+    async fn copy_object(
+        &self,
+        source_key: &str,
+        source_version_id: Option<&str>,
+        target_key: &str,
+    ) -> anyhow::Result<()> {
+        // Our keys only consist of URL-safe characters, so there's no need to percent-encode them.
+        let copy_source = match source_version_id {
+            Some(version_id) => format!("{}/{source_key}?versionId={version_id}", self.bucket_name),
+            None => format!("{}/{source_key}", self.bucket_name),
+        };
+
+        let head = self
+            .client
+            .head_object()
+            .bucket(self.bucket_name.clone())
+            .key(source_key)
+            .set_version_id(source_version_id.map(str::to_owned))
+            .send()
+            .await
+            .with_context(|| format!("head s3 object {source_key}"))?;
+        let size = head.content_length().unwrap_or_default();
+
+        if size <= MAX_SINGLE_COPY_SIZE {
+            self.client
+                .copy_object()
+                .bucket(self.bucket_name.clone())
+                .key(target_key)
+                .copy_source(copy_source)
+                .send()
+                .await
+                .context("copy s3 object")?;
+            return Ok(());
+        }
+
+        // Unlike CopyObject, multipart uploads don't copy the metadata by themselves.
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(self.bucket_name.clone())
+            .key(target_key)
+            .set_metadata(head.metadata().cloned())
+            .send()
+            .await
+            .context("create multipart upload")?;
+        let upload_id = upload
+            .upload_id()
+            .context("multipart upload response has no upload id")?;
+
+        let res = self
+            .copy_object_parts(&copy_source, target_key, upload_id, size)
+            .await;
+        if res.is_err() {
+            // The parts copied so far are kept (and billed) until the upload is aborted.
+            let abort = self
+                .client
+                .abort_multipart_upload()
+                .bucket(self.bucket_name.clone())
+                .key(target_key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+            if let Err(e) = abort {
+                tracing::warn!("Failed to abort multipart upload {upload_id}: {e}");
             }
-            Err(e) => Err(DownloadError::Other(
-                anyhow::Error::new(e).context("download s3 object"),
-            )),
         }
+        res
+    }
+
+    async fn copy_object_parts(
+        &self,
+        copy_source: &str,
+        target_key: &str,
+        upload_id: &str,
+        size: i64,
+    ) -> anyhow::Result<()> {
+        let mut parts = Vec::new();
+        let mut start = 0;
+        let mut part_number = 1;
+        while start < size {
+            let end = (start + MULTIPART_COPY_PART_SIZE).min(size);
+            let part = self
+                .client
+                .upload_part_copy()
+                .bucket(self.bucket_name.clone())
+                .key(target_key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .copy_source(copy_source)
+                // both ends are inclusive
+                .copy_source_range(format!("bytes={start}-{}", end - 1))
+                .send()
+                .await
+                .with_context(|| format!("copy part {part_number} of s3 object"))?;
+            let e_tag = part
+                .copy_part_result()
+                .and_then(|res| res.e_tag())
+                .with_context(|| format!("copy part {part_number} response has no ETag"))?;
+            parts.push(
+                CompletedPart::builder()
+                    .e_tag(e_tag)
+                    .part_number(part_number)
+                    .build(),
+            );
+
+            start = end;
+            part_number += 1;
+        }
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(self.bucket_name.clone())
+            .key(target_key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .context("complete multipart upload")?;
+        Ok(())
     }
 }
 
@@ -277,7 +449,7 @@ impl Stream for ByteStreamAsStream {
 
 pin_project_lite::pin_project! {
     /// An `AsyncRead` adapter which carries a permit for the lifetime of the value.
-    struct PermitCarrying<S> {
+    pub(super) struct PermitCarrying<S> {
         permit: tokio::sync::OwnedSemaphorePermit,
         #[pin]
         inner: S,
@@ -285,7 +457,7 @@ pin_project_lite::pin_project! {
 }
 
 impl<S> PermitCarrying<S> {
-    fn new(permit: tokio::sync::OwnedSemaphorePermit, inner: S) -> Self {
+    pub(super) fn new(permit: tokio::sync::OwnedSemaphorePermit, inner: S) -> Self {
         Self { permit, inner }
     }
 }
@@ -357,82 +529,96 @@ impl RemoteStorage for S3Bucket {
         &self,
         prefix: Option<&RemotePath>,
         mode: ListingMode,
+        cancel: &CancellationToken,
     ) -> Result<Listing, DownloadError> {
-        let kind = RequestKind::List;
-        let mut result = Listing::default();
-
-        // get the passed prefix or if it is not set use prefix_in_bucket value
-        let list_prefix = prefix
-            .map(|p| self.relative_path_to_s3_object(p))
-            .or_else(|| self.prefix_in_bucket.clone())
-            .map(|mut p| {
-                // required to end with a separator
-                // otherwise request will return only the entry of a prefix
-                if matches!(mode, ListingMode::WithDelimiter)
-                    && !p.ends_with(REMOTE_STORAGE_PREFIX_SEPARATOR)
-                {
-                    p.push(REMOTE_STORAGE_PREFIX_SEPARATOR);
-                }
-                p
-            });
-
-        let mut continuation_token = None;
-
-        loop {
-            let _guard = self.permit(kind).await;
-            let started_at = start_measuring_requests(kind);
-
-            let mut request = self
-                .client
-                .list_objects_v2()
-                .bucket(self.bucket_name.clone())
-                .set_prefix(list_prefix.clone())
-                .set_continuation_token(continuation_token)
-                .set_max_keys(self.max_keys_per_list_response);
-
-            if let ListingMode::WithDelimiter = mode {
-                request = request.delimiter(REMOTE_STORAGE_PREFIX_SEPARATOR.to_string());
-            }
+        with_timeout(self.small_timeout, cancel, async {
+            let kind = RequestKind::List;
+            let mut result = Listing::default();
+
+            // get the passed prefix or if it is not set use prefix_in_bucket value
+            let list_prefix = prefix
+                .map(|p| self.relative_path_to_s3_object(p))
+                .or_else(|| self.prefix_in_bucket.clone())
+                .map(|mut p| {
+                    // required to end with a separator
+                    // otherwise request will return only the entry of a prefix
+                    if matches!(mode, ListingMode::WithDelimiter)
+                        && !p.ends_with(REMOTE_STORAGE_PREFIX_SEPARATOR)
+                    {
+                        p.push(REMOTE_STORAGE_PREFIX_SEPARATOR);
+                    }
+                    p
+                });
 
-            let response = request
-                .send()
-                .await
-                .context("Failed to list S3 prefixes")
-                .map_err(DownloadError::Other);
+            let mut continuation_token = None;
 
-            let started_at = ScopeGuard::into_inner(started_at);
+            loop {
+                let _guard = self.permit(kind).await;
+                let started_at = start_measuring_requests(kind);
 
-            metrics::BUCKET_METRICS
-                .req_seconds
-                .observe_elapsed(kind, &response, started_at);
+                let mut request = self
+                    .client
+                    .list_objects_v2()
+                    .bucket(self.bucket_name.clone())
+                    .set_prefix(list_prefix.clone())
+                    .set_continuation_token(continuation_token)
+                    .set_max_keys(self.max_keys_per_list_response);
 
-            let response = response?;
+                if let ListingMode::WithDelimiter = mode {
+                    request = request.delimiter(REMOTE_STORAGE_PREFIX_SEPARATOR.to_string());
+                }
 
-            let keys = response.contents();
-            let empty = Vec::new();
-            let prefixes = response.common_prefixes.as_ref().unwrap_or(&empty);
+                let response = request
+                    .send()
+                    .await
+                    .context("Failed to list S3 prefixes")
+                    .map_err(DownloadError::Other);
+
+                let started_at = ScopeGuard::into_inner(started_at);
+
+                metrics::BUCKET_METRICS
+                    .req_seconds
+                    .observe_elapsed(kind, &response, started_at);
+
+                let response = response?;
+
+                let keys = response.contents();
+                let empty = Vec::new();
+                let prefixes = response.common_prefixes.as_ref().unwrap_or(&empty);
+
+                tracing::debug!("list: {} prefixes, {} keys", prefixes.len(), keys.len());
+
+                for object in keys {
+                    let object_path = object.key().expect("response does not contain a key");
+                    let last_modified = object
+                        .last_modified()
+                        .context("S3 object without a modification time")
+                        .and_then(|t| {
+                            SystemTime::try_from(*t).context("S3 object modification time")
+                        })
+                        .map_err(DownloadError::Other)?;
+                    result.keys.push(ListingObject {
+                        key: self.s3_object_to_relative_path(object_path),
+                        last_modified,
+                        size: object.size().unwrap_or_default() as u64,
+                    });
+                }
 
-            tracing::debug!("list: {} prefixes, {} keys", prefixes.len(), keys.len());
+                result.prefixes.extend(
+                    prefixes
+                        .iter()
+                        .filter_map(|o| Some(self.s3_object_to_relative_path(o.prefix()?))),
+                );
 
-            for object in keys {
-                let object_path = object.key().expect("response does not contain a key");
-                let remote_path = self.s3_object_to_relative_path(object_path);
-                result.keys.push(remote_path);
+                continuation_token = match response.next_continuation_token {
+                    Some(new_token) => Some(new_token),
+                    None => break,
+                };
             }
 
-            result.prefixes.extend(
-                prefixes
-                    .iter()
-                    .filter_map(|o| Some(self.s3_object_to_relative_path(o.prefix()?))),
-            );
-
-            continuation_token = match response.next_continuation_token {
-                Some(new_token) => Some(new_token),
-                None => break,
-            };
-        }
-
-        Ok(result)
+            Ok(result)
+        })
+        .await
     }
 
     async fn upload(
@@ -441,44 +627,55 @@ impl RemoteStorage for S3Bucket {
         from_size_bytes: usize,
         to: &RemotePath,
         metadata: Option<StorageMetadata>,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<()> {
-        let kind = RequestKind::Put;
-        let _guard = self.permit(kind).await;
+        with_timeout(self.timeout, cancel, async {
+            let kind = RequestKind::Put;
+            let _guard = self.permit(kind).await;
 
-        let started_at = start_measuring_requests(kind);
+            let started_at = start_measuring_requests(kind);
 
-        let body = Body::wrap_stream(from);
-        let bytes_stream = ByteStream::new(SdkBody::from_body_0_4(body));
+            let body = Body::wrap_stream(from);
+            let bytes_stream = ByteStream::new(SdkBody::from_body_0_4(body));
 
-        let res = self
-            .client
-            .put_object()
-            .bucket(self.bucket_name.clone())
-            .key(self.relative_path_to_s3_object(to))
-            .set_metadata(metadata.map(|m| m.0))
-            .content_length(from_size_bytes.try_into()?)
-            .body(bytes_stream)
-            .send()
-            .await;
+            let res = self
+                .client
+                .put_object()
+                .bucket(self.bucket_name.clone())
+                .key(self.relative_path_to_s3_object(to))
+                .set_metadata(metadata.map(|m| m.0))
+                .content_length(from_size_bytes.try_into()?)
+                .body(bytes_stream)
+                .send()
+                .await;
 
-        let started_at = ScopeGuard::into_inner(started_at);
-        metrics::BUCKET_METRICS
-            .req_seconds
-            .observe_elapsed(kind, &res, started_at);
+            let started_at = ScopeGuard::into_inner(started_at);
+            metrics::BUCKET_METRICS
+                .req_seconds
+                .observe_elapsed(kind, &res, started_at);
 
-        res?;
+            res?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    async fn download(&self, from: &RemotePath) -> Result<Download, DownloadError> {
+    async fn download(
+        &self,
+        from: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> Result<Download, DownloadError> {
         // if prefix is not none then download file `prefix/from`
         // if prefix is none then download file `from`
-        self.download_object(GetObjectRequest {
-            bucket: self.bucket_name.clone(),
-            key: self.relative_path_to_s3_object(from),
-            range: None,
-        })
+        self.download_object(
+            GetObjectRequest {
+                bucket: self.bucket_name.clone(),
+                key: self.relative_path_to_s3_object(from),
+                range: None,
+            },
+            cancel,
+        )
         .await
     }
 
@@ -487,6 +684,7 @@ impl RemoteStorage for S3Bucket {
         from: &RemotePath,
         start_inclusive: u64,
         end_exclusive: Option<u64>,
+        cancel: &CancellationToken,
     ) -> Result<Download, DownloadError> {
         // S3 accepts ranges as https://www.w3.org/Protocols/rfc2616/rfc2616-sec14.html#sec14.35
         // and needs both ends to be exclusive
@@ -496,82 +694,240 @@ impl RemoteStorage for S3Bucket {
             None => format!("bytes={start_inclusive}-"),
         });
 
-        self.download_object(GetObjectRequest {
-            bucket: self.bucket_name.clone(),
-            key: self.relative_path_to_s3_object(from),
-            range,
+        self.download_object(
+            GetObjectRequest {
+                bucket: self.bucket_name.clone(),
+                key: self.relative_path_to_s3_object(from),
+                range,
+            },
+            cancel,
+        )
+        .await
+    }
+    async fn delete_objects<'a>(
+        &self,
+        paths: &'a [RemotePath],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.small_timeout, cancel, async {
+            let kind = RequestKind::Delete;
+            let _guard = self.permit(kind).await;
+
+            let mut delete_objects = Vec::with_capacity(paths.len());
+            for path in paths {
+                let obj_id = ObjectIdentifier::builder()
+                    .set_key(Some(self.relative_path_to_s3_object(path)))
+                    .build()?;
+                delete_objects.push(obj_id);
+            }
+
+            for chunk in delete_objects.chunks(MAX_KEYS_PER_DELETE) {
+                let started_at = start_measuring_requests(kind);
+
+                let resp = self
+                    .client
+                    .delete_objects()
+                    .bucket(self.bucket_name.clone())
+                    .delete(
+                        Delete::builder()
+                            .set_objects(Some(chunk.to_vec()))
+                            .build()?,
+                    )
+                    .send()
+                    .await;
+
+                let started_at = ScopeGuard::into_inner(started_at);
+                metrics::BUCKET_METRICS
+                    .req_seconds
+                    .observe_elapsed(kind, &resp, started_at);
+
+                match resp {
+                    Ok(resp) => {
+                        metrics::BUCKET_METRICS
+                            .deleted_objects_total
+                            .inc_by(chunk.len() as u64);
+                        if let Some(errors) = resp.errors {
+                            // Log a bounded number of the errors within the response:
+                            // these requests can carry 1000 keys so logging each one
+                            // would be too verbose, especially as errors may lead us
+                            // to retry repeatedly.
+                            const LOG_UP_TO_N_ERRORS: usize = 10;
+                            for e in errors.iter().take(LOG_UP_TO_N_ERRORS) {
+                                tracing::warn!(
+                                    "DeleteObjects key {} failed: {}: {}",
+                                    e.key.as_ref().map(Cow::from).unwrap_or("".into()),
+                                    e.code.as_ref().map(Cow::from).unwrap_or("".into()),
+                                    e.message.as_ref().map(Cow::from).unwrap_or("".into())
+                                );
+                            }
+
+                            return Err(anyhow::format_err!(
+                                "Failed to delete {} objects",
+                                errors.len()
+                            ));
+                        }
+                    }
+                    Err(e) => {
+                        return Err(e.into());
+                    }
+                }
+            }
+            Ok(())
         })
         .await
     }
-    async fn delete_objects<'a>(&self, paths: &'a [RemotePath]) -> anyhow::Result<()> {
-        let kind = RequestKind::Delete;
-        let _guard = self.permit(kind).await;
-
-        let mut delete_objects = Vec::with_capacity(paths.len());
-        for path in paths {
-            let obj_id = ObjectIdentifier::builder()
-                .set_key(Some(self.relative_path_to_s3_object(path)))
-                .build()?;
-            delete_objects.push(obj_id);
-        }
 
-        for chunk in delete_objects.chunks(MAX_KEYS_PER_DELETE) {
+    async fn delete(&self, path: &RemotePath, cancel: &CancellationToken) -> anyhow::Result<()> {
+        let paths = std::array::from_ref(path);
+        self.delete_objects(paths, cancel).await
+    }
+
+    async fn copy(
+        &self,
+        from: &RemotePath,
+        to: &RemotePath,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.timeout, cancel, async {
+            let kind = RequestKind::Copy;
+            let _guard = self.permit(kind).await;
+
             let started_at = start_measuring_requests(kind);
 
-            let resp = self
-                .client
-                .delete_objects()
-                .bucket(self.bucket_name.clone())
-                .delete(
-                    Delete::builder()
-                        .set_objects(Some(chunk.to_vec()))
-                        .build()?,
+            let res = self
+                .copy_object(
+                    &self.relative_path_to_s3_object(from),
+                    None,
+                    &self.relative_path_to_s3_object(to),
                 )
-                .send()
                 .await;
 
             let started_at = ScopeGuard::into_inner(started_at);
             metrics::BUCKET_METRICS
                 .req_seconds
-                .observe_elapsed(kind, &resp, started_at);
-
-            match resp {
-                Ok(resp) => {
-                    metrics::BUCKET_METRICS
-                        .deleted_objects_total
-                        .inc_by(chunk.len() as u64);
-                    if let Some(errors) = resp.errors {
-                        // Log a bounded number of the errors within the response:
-                        // these requests can carry 1000 keys so logging each one
-                        // would be too verbose, especially as errors may lead us
-                        // to retry repeatedly.
-                        const LOG_UP_TO_N_ERRORS: usize = 10;
-                        for e in errors.iter().take(LOG_UP_TO_N_ERRORS) {
-                            tracing::warn!(
-                                "DeleteObjects key {} failed: {}: {}",
-                                e.key.as_ref().map(Cow::from).unwrap_or("".into()),
-                                e.code.as_ref().map(Cow::from).unwrap_or("".into()),
-                                e.message.as_ref().map(Cow::from).unwrap_or("".into())
-                            );
-                        }
+                .observe_elapsed(kind, &res, started_at);
 
-                        return Err(anyhow::format_err!(
-                            "Failed to delete {} objects",
-                            errors.len()
-                        ));
-                    }
+            res
+        })
+        .await
+    }
+
+    async fn list_versions(
+        &self,
+        prefix: Option<&RemotePath>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ObjectVersion>, DownloadError> {
+        with_timeout(self.small_timeout, cancel, async {
+            let kind = RequestKind::List;
+            let mut versions = Vec::new();
+
+            let list_prefix = prefix
+                .map(|p| self.relative_path_to_s3_object(p))
+                .or_else(|| self.prefix_in_bucket.clone());
+
+            let mut key_marker = None;
+            let mut version_id_marker = None;
+
+            loop {
+                let _guard = self.permit(kind).await;
+                let started_at = start_measuring_requests(kind);
+
+                let response = self
+                    .client
+                    .list_object_versions()
+                    .bucket(self.bucket_name.clone())
+                    .set_prefix(list_prefix.clone())
+                    .set_key_marker(key_marker)
+                    .set_version_id_marker(version_id_marker)
+                    .set_max_keys(self.max_keys_per_list_response)
+                    .send()
+                    .await
+                    .context("Failed to list S3 object versions")
+                    .map_err(DownloadError::Other);
+
+                let started_at = ScopeGuard::into_inner(started_at);
+
+                metrics::BUCKET_METRICS
+                    .req_seconds
+                    .observe_elapsed(kind, &response, started_at);
+
+                let response = response?;
+
+                let object_versions = response.versions().iter().map(|v| {
+                    (
+                        v.key(),
+                        v.version_id(),
+                        v.last_modified(),
+                        ObjectVersionKind::Version,
+                        v.is_latest(),
+                    )
+                });
+                let delete_markers = response.delete_markers().iter().map(|m| {
+                    (
+                        m.key(),
+                        m.version_id(),
+                        m.last_modified(),
+                        ObjectVersionKind::DeleteMarker,
+                        m.is_latest(),
+                    )
+                });
+                let entries = object_versions.chain(delete_markers);
+                for (key, version_id, last_modified, version_kind, is_latest) in entries {
+                    let (Some(key), Some(version_id), Some(last_modified)) =
+                        (key, version_id, last_modified)
+                    else {
+                        return Err(DownloadError::Other(anyhow::anyhow!(
+                            "S3 object version without a key, version id or modification time"
+                        )));
+                    };
+                    let last_modified = SystemTime::try_from(*last_modified)
+                        .context("S3 object version modification time")
+                        .map_err(DownloadError::Other)?;
+                    versions.push(ObjectVersion {
+                        key: self.s3_object_to_relative_path(key),
+                        version_id: version_id.to_owned(),
+                        last_modified,
+                        kind: version_kind,
+                        is_latest: is_latest.unwrap_or(false),
+                    });
                 }
-                Err(e) => {
-                    return Err(e.into());
+
+                if response.is_truncated != Some(true) {
+                    break;
                 }
+                key_marker = response.next_key_marker;
+                version_id_marker = response.next_version_id_marker;
             }
-        }
-        Ok(())
+
+            Ok(versions)
+        })
+        .await
     }
 
-    async fn delete(&self, path: &RemotePath) -> anyhow::Result<()> {
-        let paths = std::array::from_ref(path);
-        self.delete_objects(paths).await
+    async fn restore_version(
+        &self,
+        key: &RemotePath,
+        version_id: &str,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        with_timeout(self.timeout, cancel, async {
+            let kind = RequestKind::Copy;
+            let _guard = self.permit(kind).await;
+
+            let started_at = start_measuring_requests(kind);
+
+            // Copying an object version over the object makes it the latest version again.
+            let key = self.relative_path_to_s3_object(key);
+            let res = self.copy_object(&key, Some(version_id), &key).await;
+
+            let started_at = ScopeGuard::into_inner(started_at);
+            metrics::BUCKET_METRICS
+                .req_seconds
+                .observe_elapsed(kind, &res, started_at);
+
+            res
+        })
+        .await
     }
 }
 
@@ -602,7 +958,7 @@ mod tests {
     use camino::Utf8Path;
     use std::num::NonZeroUsize;
 
-    use crate::{RemotePath, S3Bucket, S3Config};
+    use crate::{RemotePath, RemoteStorageConfig, S3Bucket, S3Config};
 
     #[test]
     fn relative_path() {
@@ -647,7 +1003,12 @@ mod tests {
                 concurrency_limit: NonZeroUsize::new(100).unwrap(),
                 max_keys_per_list_response: Some(5),
             };
-            let storage = S3Bucket::new(&config).expect("remote storage init");
+            let storage = S3Bucket::new(
+                &config,
+                RemoteStorageConfig::DEFAULT_TIMEOUT,
+                RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
+            )
+            .expect("remote storage init");
             for (test_path_idx, test_path) in all_paths.iter().enumerate() {
                 let result = storage.relative_path_to_s3_object(test_path);
                 let expected = expected_outputs[prefix_idx][test_path_idx];
@@ -0,0 +1,198 @@
+//! Timeouts and cancellation shared by the [`crate::RemoteStorage`] implementations.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::stream::Stream;
+use tokio_util::sync::CancellationToken;
+
+use crate::DownloadError;
+
+/// The reason a remote storage operation was stopped before it finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutOrCancel {
+    Timeout,
+    Cancel,
+}
+
+impl std::fmt::Display for TimeoutOrCancel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimeoutOrCancel::Timeout => write!(f, "timeout"),
+            TimeoutOrCancel::Cancel => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for TimeoutOrCancel {}
+
+impl TimeoutOrCancel {
+    /// Returns the reason if the operation failed because of a timeout or a cancellation.
+    ///
+    /// The operations returning [`anyhow::Error`] carry it somewhere in the error chain, the
+    /// ones returning [`DownloadError`] map it into their own variants, and the download
+    /// streams wrap it into their [`std::io::Error`].
+    pub fn caused(error: &anyhow::Error) -> Option<TimeoutOrCancel> {
+        error.chain().find_map(|e| {
+            if let Some(e) = e.downcast_ref::<TimeoutOrCancel>() {
+                return Some(*e);
+            }
+            if let Some(e) = e.downcast_ref::<std::io::Error>() {
+                return e
+                    .get_ref()
+                    .and_then(|e| e.downcast_ref::<TimeoutOrCancel>())
+                    .copied();
+            }
+            match e.downcast_ref::<DownloadError>()? {
+                DownloadError::Timeout => Some(TimeoutOrCancel::Timeout),
+                DownloadError::Cancelled => Some(TimeoutOrCancel::Cancel),
+                _ => None,
+            }
+        })
+    }
+
+    /// Returns true if the operation failed because it was cancelled.  Cancellations are
+    /// not worth retrying, unlike timeouts.
+    pub fn caused_by_cancel(error: &anyhow::Error) -> bool {
+        Self::caused(error) == Some(TimeoutOrCancel::Cancel)
+    }
+}
+
+impl From<TimeoutOrCancel> for DownloadError {
+    fn from(value: TimeoutOrCancel) -> Self {
+        match value {
+            TimeoutOrCancel::Timeout => DownloadError::Timeout,
+            TimeoutOrCancel::Cancel => DownloadError::Cancelled,
+        }
+    }
+}
+
+/// Completes when the `timeout` passes, or `cancel` is cancelled, whichever happens first.
+///
+/// The timeout counts from the call, not from the first poll of the returned future.
+pub(crate) fn cancel_or_timeout(
+    timeout: Duration,
+    cancel: CancellationToken,
+) -> impl Future<Output = TimeoutOrCancel> + Send + Sync + 'static {
+    let sleep = tokio::time::sleep(timeout);
+    async move {
+        tokio::select! {
+            // the cancellation is checked first, so that a cancelled operation does not report
+            // a timeout
+            biased;
+            _ = cancel.cancelled() => TimeoutOrCancel::Cancel,
+            _ = sleep => TimeoutOrCancel::Timeout,
+        }
+    }
+}
+
+/// Runs the operation, stopping it with a [`TimeoutOrCancel`] error if it takes longer than
+/// `timeout`, or if `cancel` is cancelled first.
+pub(crate) async fn with_timeout<T, E>(
+    timeout: Duration,
+    cancel: &CancellationToken,
+    op: impl Future<Output = Result<T, E>>,
+) -> Result<T, E>
+where
+    E: From<TimeoutOrCancel>,
+{
+    if cancel.is_cancelled() {
+        return Err(TimeoutOrCancel::Cancel.into());
+    }
+    tokio::select! {
+        res = op => res,
+        reason = cancel_or_timeout(timeout, cancel.clone()) => Err(reason.into()),
+    }
+}
+
+pin_project_lite::pin_project! {
+    /// A download stream which fails with a [`TimeoutOrCancel`] error once the `timeout` passes
+    /// or `cancel` is cancelled, so that a stalled download does not hold up its reader forever.
+    pub(crate) struct DownloadStream<S> {
+        cancel_or_timeout: Pin<Box<dyn Future<Output = TimeoutOrCancel> + Send + Sync>>,
+        #[pin]
+        inner: S,
+        failed: bool,
+    }
+}
+
+impl<S> DownloadStream<S> {
+    pub(crate) fn new(timeout: Duration, cancel: &CancellationToken, inner: S) -> Self {
+        Self {
+            cancel_or_timeout: Box::pin(cancel_or_timeout(timeout, cancel.clone())),
+            inner,
+            failed: false,
+        }
+    }
+}
+
+impl<S> Stream for DownloadStream<S>
+where
+    S: Stream<Item = std::io::Result<Bytes>>,
+{
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if *this.failed {
+            return Poll::Ready(None);
+        }
+
+        if let Poll::Ready(reason) = this.cancel_or_timeout.as_mut().poll(cx) {
+            *this.failed = true;
+            return Poll::Ready(Some(Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                reason,
+            ))));
+        }
+
+        this.inner.poll_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::StreamExt;
+
+    #[tokio::test(start_paused = true)]
+    async fn operations_time_out() {
+        let cancel = CancellationToken::new();
+        let res: Result<(), DownloadError> =
+            with_timeout(Duration::from_secs(1), &cancel, futures::future::pending()).await;
+        assert!(matches!(res, Err(DownloadError::Timeout)), "{res:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancellation_wins_over_timeout() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let res: anyhow::Result<()> =
+            with_timeout(Duration::ZERO, &cancel, futures::future::pending()).await;
+        assert!(TimeoutOrCancel::caused_by_cancel(&res.unwrap_err()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_download_stream_times_out() {
+        let inner = futures::stream::iter([Ok(Bytes::from_static(b"first"))])
+            .chain(futures::stream::pending());
+        let mut stream =
+            DownloadStream::new(Duration::from_secs(1), &CancellationToken::new(), inner);
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), "first");
+        let err = stream.next().await.unwrap().unwrap_err();
+        let reason = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<TimeoutOrCancel>());
+        assert_eq!(reason, Some(&TimeoutOrCancel::Timeout));
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -4,7 +4,7 @@ use std::num::NonZeroUsize;
 use std::ops::ControlFlow;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::UNIX_EPOCH;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Context;
 use bytes::Bytes;
@@ -12,10 +12,12 @@ use camino::Utf8Path;
 use futures::stream::Stream;
 use once_cell::sync::OnceCell;
 use remote_storage::{
-    GenericRemoteStorage, RemotePath, RemoteStorageConfig, RemoteStorageKind, S3Config,
+    DownloadError, GenericRemoteStorage, RemotePath, RemoteStorageConfig, RemoteStorageKind,
+    S3Config,
 };
 use test_context::{test_context, AsyncTestContext};
 use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info};
 
 static LOGGING_DONE: OnceCell<()> = OnceCell::new();
@@ -49,6 +51,7 @@ const BASE_PREFIX: &str = "test";
 #[test_context(MaybeEnabledS3WithTestBlobs)]
 #[tokio::test]
 async fn s3_pagination_should_work(ctx: &mut MaybeEnabledS3WithTestBlobs) -> anyhow::Result<()> {
+    let cancel = CancellationToken::new();
     let ctx = match ctx {
         MaybeEnabledS3WithTestBlobs::Enabled(ctx) => ctx,
         MaybeEnabledS3WithTestBlobs::Disabled => return Ok(()),
@@ -61,7 +64,7 @@ async fn s3_pagination_should_work(ctx: &mut MaybeEnabledS3WithTestBlobs) -> any
     let base_prefix = RemotePath::new(Utf8Path::new(ctx.enabled.base_prefix))
         .context("common_prefix construction")?;
     let root_remote_prefixes = test_client
-        .list_prefixes(None)
+        .list_prefixes(None, &cancel)
         .await
         .context("client list root prefixes failure")?
         .into_iter()
@@ -72,7 +75,7 @@ async fn s3_pagination_should_work(ctx: &mut MaybeEnabledS3WithTestBlobs) -> any
     );
 
     let nested_remote_prefixes = test_client
-        .list_prefixes(Some(&base_prefix))
+        .list_prefixes(Some(&base_prefix), &cancel)
         .await
         .context("client list nested prefixes failure")?
         .into_iter()
@@ -102,6 +105,7 @@ async fn s3_pagination_should_work(ctx: &mut MaybeEnabledS3WithTestBlobs) -> any
 #[test_context(MaybeEnabledS3WithSimpleTestBlobs)]
 #[tokio::test]
 async fn s3_list_files_works(ctx: &mut MaybeEnabledS3WithSimpleTestBlobs) -> anyhow::Result<()> {
+    let cancel = CancellationToken::new();
     let ctx = match ctx {
         MaybeEnabledS3WithSimpleTestBlobs::Enabled(ctx) => ctx,
         MaybeEnabledS3WithSimpleTestBlobs::Disabled => return Ok(()),
@@ -113,7 +117,7 @@ async fn s3_list_files_works(ctx: &mut MaybeEnabledS3WithSimpleTestBlobs) -> any
     let base_prefix =
         RemotePath::new(Utf8Path::new("folder1")).context("common_prefix construction")?;
     let root_files = test_client
-        .list_files(None)
+        .list_files(None, &cancel)
         .await
         .context("client list root files failure")?
         .into_iter()
@@ -124,7 +128,7 @@ async fn s3_list_files_works(ctx: &mut MaybeEnabledS3WithSimpleTestBlobs) -> any
         "remote storage list_files on root mismatches with the uploads."
     );
     let nested_remote_files = test_client
-        .list_files(Some(&base_prefix))
+        .list_files(Some(&base_prefix), &cancel)
         .await
         .context("client list nested files failure")?
         .into_iter()
@@ -146,6 +150,7 @@ async fn s3_list_files_works(ctx: &mut MaybeEnabledS3WithSimpleTestBlobs) -> any
 #[test_context(MaybeEnabledS3)]
 #[tokio::test]
 async fn s3_delete_non_exising_works(ctx: &mut MaybeEnabledS3) -> anyhow::Result<()> {
+    let cancel = CancellationToken::new();
     let ctx = match ctx {
         MaybeEnabledS3::Enabled(ctx) => ctx,
         MaybeEnabledS3::Disabled => return Ok(()),
@@ -156,7 +161,10 @@ async fn s3_delete_non_exising_works(ctx: &mut MaybeEnabledS3) -> anyhow::Result
     ))
     .with_context(|| "RemotePath conversion")?;
 
-    ctx.client.delete(&path).await.expect("should succeed");
+    ctx.client
+        .delete(&path, &cancel)
+        .await
+        .expect("should succeed");
 
     Ok(())
 }
@@ -164,6 +172,7 @@ async fn s3_delete_non_exising_works(ctx: &mut MaybeEnabledS3) -> anyhow::Result
 #[test_context(MaybeEnabledS3)]
 #[tokio::test]
 async fn s3_delete_objects_works(ctx: &mut MaybeEnabledS3) -> anyhow::Result<()> {
+    let cancel = CancellationToken::new();
     let ctx = match ctx {
         MaybeEnabledS3::Enabled(ctx) => ctx,
         MaybeEnabledS3::Disabled => return Ok(()),
@@ -179,21 +188,126 @@ async fn s3_delete_objects_works(ctx: &mut MaybeEnabledS3) -> anyhow::Result<()>
         .with_context(|| "RemotePath conversion")?;
 
     let (data, len) = upload_stream("remote blob data1".as_bytes().into());
-    ctx.client.upload(data, len, &path1, None).await?;
+    ctx.client.upload(data, len, &path1, None, &cancel).await?;
 
     let (data, len) = upload_stream("remote blob data2".as_bytes().into());
-    ctx.client.upload(data, len, &path2, None).await?;
+    ctx.client.upload(data, len, &path2, None, &cancel).await?;
 
     let (data, len) = upload_stream("remote blob data3".as_bytes().into());
-    ctx.client.upload(data, len, &path3, None).await?;
+    ctx.client.upload(data, len, &path3, None, &cancel).await?;
 
-    ctx.client.delete_objects(&[path1, path2]).await?;
+    ctx.client.delete_objects(&[path1, path2], &cancel).await?;
 
-    let prefixes = ctx.client.list_prefixes(None).await?;
+    let prefixes = ctx.client.list_prefixes(None, &cancel).await?;
 
     assert_eq!(prefixes.len(), 1);
 
-    ctx.client.delete_objects(&[path3]).await?;
+    ctx.client.delete_objects(&[path3], &cancel).await?;
+
+    Ok(())
+}
+
+#[test_context(MaybeEnabledS3)]
+#[tokio::test]
+async fn s3_copy_works(ctx: &mut MaybeEnabledS3) -> anyhow::Result<()> {
+    let cancel = CancellationToken::new();
+    let MaybeEnabledS3::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let path = RemotePath::new(Utf8Path::new(
+        format!("{}/file_to_copy", ctx.base_prefix).as_str(),
+    ))
+    .with_context(|| "RemotePath conversion")?;
+    let path_dest = RemotePath::new(Utf8Path::new(
+        format!("{}/file_dest", ctx.base_prefix).as_str(),
+    ))
+    .with_context(|| "RemotePath conversion")?;
+
+    let orig = bytes::Bytes::from_static("remote blob data content".as_bytes());
+
+    let (data, len) = wrap_stream(orig.clone());
+
+    ctx.client.upload(data, len, &path, None, &cancel).await?;
+
+    ctx.client.copy(&path, &path_dest, &cancel).await?;
+
+    let dl = ctx.client.download(&path_dest, &cancel).await?;
+    let mut buf = Vec::new();
+    tokio::io::copy_buf(
+        &mut tokio_util::io::StreamReader::new(dl.download_stream),
+        &mut buf,
+    )
+    .await?;
+    assert_eq!(&buf, &orig);
+
+    debug!("Cleanup: deleting file at path {path:?}");
+    ctx.client
+        .delete_objects(&[path.clone(), path_dest.clone()], &cancel)
+        .await
+        .with_context(|| format!("{path:?} removal"))?;
+
+    Ok(())
+}
+
+#[test_context(MaybeEnabledS3)]
+#[tokio::test]
+async fn s3_time_travel_recovery_works(ctx: &mut MaybeEnabledS3) -> anyhow::Result<()> {
+    let cancel = CancellationToken::new();
+    let MaybeEnabledS3::Enabled(ctx) = ctx else {
+        return Ok(());
+    };
+
+    let prefix = RemotePath::new(Utf8Path::new(
+        format!("{}/time_travel", ctx.base_prefix).as_str(),
+    ))
+    .with_context(|| "RemotePath conversion")?;
+    let path = prefix.join(Utf8Path::new("file"));
+    let path_created = prefix.join(Utf8Path::new("file_created"));
+
+    let orig = bytes::Bytes::from_static("remote blob data content".as_bytes());
+    let (data, len) = wrap_stream(orig.clone());
+    ctx.client.upload(data, len, &path, None, &cancel).await?;
+
+    // S3 modification times have a second precision
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let timestamp = SystemTime::now();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let (data, len) = wrap_stream(bytes::Bytes::from_static("overwritten".as_bytes()));
+    ctx.client.upload(data, len, &path, None, &cancel).await?;
+    let (data, len) = wrap_stream(orig.clone());
+    ctx.client
+        .upload(data, len, &path_created, None, &cancel)
+        .await?;
+
+    let versions = ctx.client.list_versions(Some(&prefix), &cancel).await?;
+    if versions.iter().all(|v| v.version_id == "null") {
+        info!("Bucket versioning is not enabled, skipping the time travel check");
+    } else {
+        ctx.client
+            .time_travel_recover(Some(&prefix), timestamp, &cancel)
+            .await?;
+
+        let dl = ctx.client.download(&path, &cancel).await?;
+        let mut buf = Vec::new();
+        tokio::io::copy_buf(
+            &mut tokio_util::io::StreamReader::new(dl.download_stream),
+            &mut buf,
+        )
+        .await?;
+        assert_eq!(&buf, &orig);
+        assert!(matches!(
+            ctx.client.download(&path_created, &cancel).await,
+            Err(DownloadError::NotFound)
+        ));
+    }
+
+    debug!("Cleanup: deleting files under prefix {prefix:?}");
+    ctx.client
+        .delete_objects(&[path.clone(), path_created.clone()], &cancel)
+        .await
+        .with_context(|| format!("{prefix:?} removal"))?;
 
     Ok(())
 }
@@ -398,6 +512,8 @@ fn create_s3_client(
             concurrency_limit: NonZeroUsize::new(100).unwrap(),
             max_keys_per_list_response,
         }),
+        timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+        small_timeout: RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
     };
     Ok(Arc::new(
         GenericRemoteStorage::from_config(&remote_storage_config).context("remote storage init")?,
@@ -419,6 +535,7 @@ async fn upload_s3_data(
     for i in 1..upload_tasks_count + 1 {
         let task_client = Arc::clone(client);
         upload_tasks.spawn(async move {
+            let cancel = CancellationToken::new();
             let prefix = format!("{base_prefix_str}/sub_prefix_{i}/");
             let blob_prefix = RemotePath::new(Utf8Path::new(&prefix))
                 .with_context(|| format!("{prefix:?} to RemotePath conversion"))?;
@@ -427,7 +544,9 @@ async fn upload_s3_data(
 
             let (data, data_len) =
                 upload_stream(format!("remote blob data {i}").into_bytes().into());
-            task_client.upload(data, data_len, &blob_path, None).await?;
+            task_client
+                .upload(data, data_len, &blob_path, None, &cancel)
+                .await?;
 
             Ok::<_, anyhow::Error>((blob_prefix, blob_path))
         });
@@ -472,9 +591,10 @@ async fn cleanup(client: &Arc<GenericRemoteStorage>, objects_to_delete: HashSet<
     for object_to_delete in objects_to_delete {
         let task_client = Arc::clone(client);
         delete_tasks.spawn(async move {
+            let cancel = CancellationToken::new();
             debug!("Deleting remote item at path {object_to_delete:?}");
             task_client
-                .delete(&object_to_delete)
+                .delete(&object_to_delete, &cancel)
                 .await
                 .with_context(|| format!("{object_to_delete:?} removal"))
         });
@@ -501,6 +621,7 @@ async fn upload_simple_s3_data(
     for i in 1..upload_tasks_count + 1 {
         let task_client = Arc::clone(client);
         upload_tasks.spawn(async move {
+            let cancel = CancellationToken::new();
             let blob_path = PathBuf::from(format!("folder{}/blob_{}.txt", i / 7, i));
             let blob_path = RemotePath::new(
                 Utf8Path::from_path(blob_path.as_path()).expect("must be valid blob path"),
@@ -510,7 +631,9 @@ async fn upload_simple_s3_data(
 
             let (data, data_len) =
                 upload_stream(format!("remote blob data {i}").into_bytes().into());
-            task_client.upload(data, data_len, &blob_path, None).await?;
+            task_client
+                .upload(data, data_len, &blob_path, None, &cancel)
+                .await?;
 
             Ok::<_, anyhow::Error>(blob_path)
         });
@@ -0,0 +1,6 @@
+//! Clients for the pageserver's APIs, for use by the control plane, tools and tests.
+//!
+//! The pageserver itself does not depend on this crate: the request and response
+//! types are shared through `pageserver_api`.
+pub mod mgmt_api;
+pub mod page_service;
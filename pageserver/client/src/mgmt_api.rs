@@ -0,0 +1,706 @@
+//! Client for the pageserver management API, i.e. the `/v1` HTTP routes in
+//! `pageserver/src/http/routes.rs`.
+//!
+//! Requests and responses use the `pageserver_api::models` types.  The few endpoints
+//! whose responses are defined inside the pageserver itself (synthetic size, GC results,
+//! layer maps, ...) return them as [`serde_json::Value`].
+
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use pageserver_api::{
+    models::{
+        ConfigureFailpointsRequest, DownloadRemoteLayersTaskInfo,
+        DownloadRemoteLayersTaskSpawnRequest, LocationConfig, LsnByTimestampResponse,
+        StatusResponse, TenantAttachRequest, TenantConfigRequest, TenantCreateRequest,
+        TenantCreateResponse, TenantInfo, TenantLoadRequest, TenantLocationConfigRequest,
+        TenantShardSplitRequest, TenantShardSplitResponse, TimelineCreateRequest,
+        TimelineGcRequest, TimelineInfo,
+    },
+    shard::TenantShardId,
+};
+use reqwest::{IntoUrl, Method, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use utils::{
+    http::error::HttpErrorBody,
+    id::{TenantId, TimelineId},
+    lsn::Lsn,
+};
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    mgmt_api_endpoint: String,
+    authorization_header: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The request did not get a response: the pageserver is down, unreachable, or
+    /// the request timed out.
+    #[error("send request: {0}")]
+    SendRequest(reqwest::Error),
+
+    #[error("receive body: {0}")]
+    ReceiveBody(reqwest::Error),
+
+    #[error("receive error body: {0}")]
+    ReceiveErrorBody(String),
+
+    /// The pageserver responded with an error status.  The message is the one from
+    /// its `ApiError` JSON body, if it had one.
+    #[error("pageserver API: {1}")]
+    ApiError(StatusCode, String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The status code the pageserver responded with, if it responded at all.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::ApiError(status, _) => Some(*status),
+            Error::SendRequest(e) | Error::ReceiveBody(e) => e.status(),
+            Error::ReceiveErrorBody(_) => None,
+        }
+    }
+}
+
+/// Turn an error status into an [`Error::ApiError`], using the message from the
+/// pageserver's `HttpErrorBody` where the body is one.
+async fn error_from_body(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error()) {
+        return Ok(response);
+    }
+
+    let url = response.url().to_owned();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::ReceiveErrorBody(format!("Http error ({status}) at {url}: {e}")))?;
+    let msg = match serde_json::from_str::<HttpErrorBody>(&body) {
+        Ok(HttpErrorBody { msg }) => msg,
+        Err(_) => format!("Http error ({}) at {}: {}", status.as_u16(), url, body),
+    };
+    Err(Error::ApiError(status, msg))
+}
+
+async fn json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    response.json().await.map_err(Error::ReceiveBody)
+}
+
+impl Client {
+    /// `mgmt_api_endpoint` is the pageserver's HTTP address, e.g. `http://127.0.0.1:9898`.
+    pub fn new(mgmt_api_endpoint: String, jwt: Option<&str>) -> Self {
+        Self::from_client(reqwest::Client::new(), mgmt_api_endpoint, jwt)
+    }
+
+    /// Like [`Client::new`], but sharing the connection pool and settings (e.g. timeouts)
+    /// of an existing `reqwest::Client`: useful when talking to many pageservers.
+    pub fn from_client(
+        client: reqwest::Client,
+        mgmt_api_endpoint: String,
+        jwt: Option<&str>,
+    ) -> Self {
+        Self {
+            mgmt_api_endpoint,
+            authorization_header: jwt.map(|jwt| format!("Bearer {jwt}")),
+            client,
+        }
+    }
+
+    fn start_request<U: IntoUrl>(&self, method: Method, uri: U) -> RequestBuilder {
+        let req = self.client.request(method, uri);
+        if let Some(value) = &self.authorization_header {
+            req.header(reqwest::header::AUTHORIZATION, value)
+        } else {
+            req
+        }
+    }
+
+    async fn send(&self, req: RequestBuilder) -> Result<reqwest::Response> {
+        let response = req.send().await.map_err(Error::SendRequest)?;
+        error_from_body(response).await
+    }
+
+    async fn request<U: IntoUrl>(&self, method: Method, uri: U) -> Result<reqwest::Response> {
+        self.send(self.start_request(method, uri)).await
+    }
+
+    async fn request_json<U: IntoUrl, B: Serialize>(
+        &self,
+        method: Method,
+        uri: U,
+        body: &B,
+    ) -> Result<reqwest::Response> {
+        self.send(self.start_request(method, uri).json(body)).await
+    }
+
+    async fn get<U: IntoUrl>(&self, uri: U) -> Result<reqwest::Response> {
+        self.request(Method::GET, uri).await
+    }
+
+    fn tenant_url(&self, tenant_shard_id: TenantShardId) -> String {
+        format!("{}/v1/tenant/{}", self.mgmt_api_endpoint, tenant_shard_id)
+    }
+
+    fn timeline_url(&self, tenant_shard_id: TenantShardId, timeline_id: TimelineId) -> String {
+        format!(
+            "{}/timeline/{}",
+            self.tenant_url(tenant_shard_id),
+            timeline_id
+        )
+    }
+
+    pub async fn status(&self) -> Result<StatusResponse> {
+        let uri = format!("{}/v1/status", self.mgmt_api_endpoint);
+        json(self.get(uri).await?).await
+    }
+
+    pub async fn configure_failpoints(
+        &self,
+        failpoints: &ConfigureFailpointsRequest,
+    ) -> Result<()> {
+        let uri = format!("{}/v1/failpoints", self.mgmt_api_endpoint);
+        self.request_json(Method::PUT, uri, failpoints).await?;
+        Ok(())
+    }
+
+    pub async fn reload_auth_validation_keys(&self) -> Result<()> {
+        let uri = format!("{}/v1/reload_auth_validation_keys", self.mgmt_api_endpoint);
+        self.request(Method::POST, uri).await?;
+        Ok(())
+    }
+
+    pub async fn list_tenants(&self) -> Result<Vec<TenantInfo>> {
+        let uri = format!("{}/v1/tenant", self.mgmt_api_endpoint);
+        json(self.get(uri).await?).await
+    }
+
+    pub async fn tenant_create(&self, req: &TenantCreateRequest) -> Result<TenantId> {
+        let uri = format!("{}/v1/tenant", self.mgmt_api_endpoint);
+        let response: TenantCreateResponse =
+            json(self.request_json(Method::POST, uri, req).await?).await?;
+        Ok(response.0)
+    }
+
+    pub async fn tenant_details(&self, tenant_shard_id: TenantShardId) -> Result<TenantInfo> {
+        json(self.get(self.tenant_url(tenant_shard_id)).await?).await
+    }
+
+    /// Start deleting a tenant shard, including its remote data.  The pageserver
+    /// completes the deletion in the background.
+    pub async fn tenant_delete(&self, tenant_shard_id: TenantShardId) -> Result<()> {
+        self.request(Method::DELETE, self.tenant_url(tenant_shard_id))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn tenant_synthetic_size(
+        &self,
+        tenant_shard_id: TenantShardId,
+        inputs_only: bool,
+    ) -> Result<serde_json::Value> {
+        let uri = format!(
+            "{}/synthetic_size?inputs_only={inputs_only}",
+            self.tenant_url(tenant_shard_id)
+        );
+        json(self.get(uri).await?).await
+    }
+
+    pub async fn tenant_config(&self, req: &TenantConfigRequest) -> Result<()> {
+        let uri = format!("{}/v1/tenant/config", self.mgmt_api_endpoint);
+        self.request_json(Method::PUT, uri, req).await?;
+        Ok(())
+    }
+
+    /// The tenant's own config overrides and its effective config.
+    pub async fn tenant_config_get(
+        &self,
+        tenant_shard_id: TenantShardId,
+    ) -> Result<serde_json::Value> {
+        let uri = format!("{}/config", self.tenant_url(tenant_shard_id));
+        json(self.get(uri).await?).await
+    }
+
+    /// Set the location of a tenant shard on this pageserver.  With `flush_ms`, the
+    /// pageserver waits up to that long for pending uploads before responding.
+    pub async fn location_config(
+        &self,
+        tenant_shard_id: TenantShardId,
+        config: LocationConfig,
+        flush_ms: Option<Duration>,
+    ) -> Result<()> {
+        let req_body = TenantLocationConfigRequest {
+            tenant_id: tenant_shard_id.tenant_id,
+            config,
+        };
+        let mut uri = format!("{}/location_config", self.tenant_url(tenant_shard_id));
+        if let Some(flush_ms) = flush_ms {
+            uri = format!("{}?flush_ms={}", uri, flush_ms.as_millis());
+        }
+        self.request_json(Method::PUT, uri, &req_body).await?;
+        Ok(())
+    }
+
+    pub async fn tenant_shard_split(
+        &self,
+        tenant_shard_id: TenantShardId,
+        req: &TenantShardSplitRequest,
+    ) -> Result<TenantShardSplitResponse> {
+        let uri = format!("{}/shard_split", self.tenant_url(tenant_shard_id));
+        json(self.request_json(Method::PUT, uri, req).await?).await
+    }
+
+    pub async fn tenant_attach(
+        &self,
+        tenant_id: TenantId,
+        req: &TenantAttachRequest,
+    ) -> Result<()> {
+        let uri = format!("{}/v1/tenant/{}/attach", self.mgmt_api_endpoint, tenant_id);
+        self.request_json(Method::POST, uri, req).await?;
+        Ok(())
+    }
+
+    pub async fn tenant_detach(&self, tenant_id: TenantId, detach_ignored: bool) -> Result<()> {
+        let uri = format!(
+            "{}/v1/tenant/{}/detach?detach_ignored={detach_ignored}",
+            self.mgmt_api_endpoint, tenant_id
+        );
+        self.request(Method::POST, uri).await?;
+        Ok(())
+    }
+
+    pub async fn tenant_reset(
+        &self,
+        tenant_shard_id: TenantShardId,
+        drop_cache: bool,
+    ) -> Result<()> {
+        let uri = format!(
+            "{}/reset?drop_cache={drop_cache}",
+            self.tenant_url(tenant_shard_id)
+        );
+        self.request(Method::POST, uri).await?;
+        Ok(())
+    }
+
+    /// Roll back the remote storage of a detached tenant shard to how it was at `travel_to`.
+    pub async fn tenant_time_travel_remote_storage(
+        &self,
+        tenant_shard_id: TenantShardId,
+        travel_to: SystemTime,
+    ) -> Result<()> {
+        let uri = format!(
+            "{}/time_travel_remote_storage?travel_to={}",
+            self.tenant_url(tenant_shard_id),
+            humantime::format_rfc3339(travel_to)
+        );
+        self.request(Method::PUT, uri).await?;
+        Ok(())
+    }
+
+    pub async fn tenant_load(&self, tenant_id: TenantId, req: &TenantLoadRequest) -> Result<()> {
+        let uri = format!("{}/v1/tenant/{}/load", self.mgmt_api_endpoint, tenant_id);
+        self.request_json(Method::POST, uri, req).await?;
+        Ok(())
+    }
+
+    pub async fn tenant_ignore(&self, tenant_id: TenantId) -> Result<()> {
+        let uri = format!("{}/v1/tenant/{}/ignore", self.mgmt_api_endpoint, tenant_id);
+        self.request(Method::POST, uri).await?;
+        Ok(())
+    }
+
+    /// Upload the heatmap of an attached tenant shard, for its secondary locations.
+    pub async fn tenant_heatmap_upload(&self, tenant_shard_id: TenantShardId) -> Result<()> {
+        let uri = format!("{}/heatmap_upload", self.tenant_url(tenant_shard_id));
+        self.request(Method::POST, uri).await?;
+        Ok(())
+    }
+
+    /// Testing API: set the tenant shard's state to broken.
+    pub async fn tenant_break(&self, tenant_shard_id: TenantShardId) -> Result<()> {
+        let uri = format!("{}/break", self.tenant_url(tenant_shard_id));
+        self.request(Method::PUT, uri).await?;
+        Ok(())
+    }
+
+    pub async fn list_timelines(
+        &self,
+        tenant_shard_id: TenantShardId,
+    ) -> Result<Vec<TimelineInfo>> {
+        let uri = format!("{}/timeline", self.tenant_url(tenant_shard_id));
+        json(self.get(uri).await?).await
+    }
+
+    pub async fn timeline_create(
+        &self,
+        tenant_shard_id: TenantShardId,
+        req: &TimelineCreateRequest,
+    ) -> Result<TimelineInfo> {
+        let uri = format!("{}/timeline", self.tenant_url(tenant_shard_id));
+        json(self.request_json(Method::POST, uri, req).await?).await
+    }
+
+    pub async fn timeline_info(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        include_non_incremental_logical_size: bool,
+    ) -> Result<TimelineInfo> {
+        let uri = format!(
+            "{}?include-non-incremental-logical-size={include_non_incremental_logical_size}",
+            self.timeline_url(tenant_shard_id, timeline_id)
+        );
+        json(self.get(uri).await?).await
+    }
+
+    /// Start deleting a timeline.  The pageserver completes the deletion in the background.
+    pub async fn timeline_delete(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+    ) -> Result<()> {
+        self.request(
+            Method::DELETE,
+            self.timeline_url(tenant_shard_id, timeline_id),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Only available on shard zero.
+    pub async fn timeline_get_lsn_by_timestamp(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        timestamp: SystemTime,
+    ) -> Result<LsnByTimestampResponse> {
+        let uri = format!(
+            "{}/get_lsn_by_timestamp?timestamp={}&version=2",
+            self.timeline_url(tenant_shard_id, timeline_id),
+            humantime::format_rfc3339(timestamp)
+        );
+        json(self.get(uri).await?).await
+    }
+
+    /// The commit timestamp at `lsn`, in RFC 3339 format.  Only available on shard zero.
+    pub async fn timeline_get_timestamp_of_lsn(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        lsn: Lsn,
+    ) -> Result<String> {
+        let uri = format!(
+            "{}/get_timestamp_of_lsn?lsn={lsn}",
+            self.timeline_url(tenant_shard_id, timeline_id)
+        );
+        json(self.get(uri).await?).await
+    }
+
+    pub async fn timeline_gc(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        req: &TimelineGcRequest,
+    ) -> Result<serde_json::Value> {
+        let uri = format!("{}/do_gc", self.timeline_url(tenant_shard_id, timeline_id));
+        json(self.request_json(Method::PUT, uri, req).await?).await
+    }
+
+    /// Testing API: run compaction on the timeline.
+    pub async fn timeline_compact(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        force_repartition: bool,
+    ) -> Result<()> {
+        let uri = format!(
+            "{}/compact?force_repartition={force_repartition}",
+            self.timeline_url(tenant_shard_id, timeline_id)
+        );
+        self.request(Method::PUT, uri).await?;
+        Ok(())
+    }
+
+    /// Testing API: flush the timeline's in-memory layers, then compact it.
+    pub async fn timeline_checkpoint(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        force_repartition: bool,
+    ) -> Result<()> {
+        let uri = format!(
+            "{}/checkpoint?force_repartition={force_repartition}",
+            self.timeline_url(tenant_shard_id, timeline_id)
+        );
+        self.request(Method::PUT, uri).await?;
+        Ok(())
+    }
+
+    /// Start downloading all of the timeline's remote layers.  Fails with a conflict if
+    /// a download task is already running.
+    pub async fn timeline_download_remote_layers(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        req: &DownloadRemoteLayersTaskSpawnRequest,
+    ) -> Result<DownloadRemoteLayersTaskInfo> {
+        let uri = format!(
+            "{}/download_remote_layers",
+            self.timeline_url(tenant_shard_id, timeline_id)
+        );
+        json(self.request_json(Method::POST, uri, req).await?).await
+    }
+
+    pub async fn timeline_download_remote_layers_status(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+    ) -> Result<DownloadRemoteLayersTaskInfo> {
+        let uri = format!(
+            "{}/download_remote_layers",
+            self.timeline_url(tenant_shard_id, timeline_id)
+        );
+        json(self.get(uri).await?).await
+    }
+
+    pub async fn layer_map_info(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+    ) -> Result<serde_json::Value> {
+        let uri = format!("{}/layer", self.timeline_url(tenant_shard_id, timeline_id));
+        json(self.get(uri).await?).await
+    }
+
+    /// Download a layer file.  Returns false if it was already resident.
+    pub async fn layer_ondemand_download(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        layer_file_name: &str,
+    ) -> Result<bool> {
+        let uri = format!(
+            "{}/layer/{}",
+            self.timeline_url(tenant_shard_id, timeline_id),
+            layer_file_name
+        );
+        let response = self.get(uri).await?;
+        Ok(response.status() != StatusCode::NOT_MODIFIED)
+    }
+
+    /// Evict a layer file.  Returns false if it was not resident.
+    pub async fn layer_evict(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        layer_file_name: &str,
+    ) -> Result<bool> {
+        let uri = format!(
+            "{}/layer/{}",
+            self.timeline_url(tenant_shard_id, timeline_id),
+            layer_file_name
+        );
+        let response = self.request(Method::DELETE, uri).await?;
+        Ok(response.status() != StatusCode::NOT_MODIFIED)
+    }
+
+    /// Testing API: read a page at `lsn`.  `key` is the hex representation of the key.
+    pub async fn timeline_getpage(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        key: &str,
+        lsn: Lsn,
+    ) -> Result<Bytes> {
+        let uri = format!(
+            "{}/getpage?key={key}&lsn={lsn}",
+            self.timeline_url(tenant_shard_id, timeline_id)
+        );
+        self.get(uri)
+            .await?
+            .bytes()
+            .await
+            .map_err(Error::ReceiveBody)
+    }
+
+    /// Testing API: the timeline's keyspace at `at_lsn`, or at its last record LSN.
+    pub async fn timeline_keyspace(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+        at_lsn: Option<Lsn>,
+    ) -> Result<serde_json::Value> {
+        let mut uri = format!(
+            "{}/keyspace",
+            self.timeline_url(tenant_shard_id, timeline_id)
+        );
+        if let Some(at_lsn) = at_lsn {
+            uri = format!("{uri}?at_lsn={at_lsn}");
+        }
+        json(self.get(uri).await?).await
+    }
+
+    /// Run one iteration of disk usage based eviction, trying to free `evict_bytes`.
+    pub async fn disk_usage_eviction_run(&self, evict_bytes: u64) -> Result<serde_json::Value> {
+        let uri = format!("{}/v1/disk_usage_eviction/run", self.mgmt_api_endpoint);
+        let req = serde_json::json!({ "evict_bytes": evict_bytes });
+        json(self.request_json(Method::PUT, uri, &req).await?).await
+    }
+
+    /// Wait for the deletion queue to be written out, and with `execute`, for the
+    /// deletions in it to be executed.
+    pub async fn deletion_queue_flush(&self, execute: bool) -> Result<()> {
+        let uri = format!(
+            "{}/v1/deletion_queue/flush?execute={execute}",
+            self.mgmt_api_endpoint
+        );
+        self.request(Method::PUT, uri).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Response,
+    };
+    use pageserver_api::shard::TenantShardId;
+    use reqwest::StatusCode;
+    use utils::id::{NodeId, TenantId};
+
+    use super::{Client, Error};
+
+    /// The `Authorization` headers of the requests a mock pageserver received.
+    type Headers = Arc<Mutex<Vec<Option<String>>>>;
+
+    /// Start a pageserver that answers every request with `status` and `body`.  Returns its
+    /// endpoint and the headers of the requests it receives.
+    fn mock_pageserver(status: StatusCode, body: &'static str) -> (String, Headers) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let headers = Headers::default();
+        let headers2 = headers.clone();
+
+        let server = hyper::server::Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service_fn(move |_| {
+                let headers = headers.clone();
+                async move {
+                    Ok::<_, anyhow::Error>(service_fn(move |req| {
+                        let headers = headers.clone();
+                        async move {
+                            let authorization = req
+                                .headers()
+                                .get(hyper::header::AUTHORIZATION)
+                                .map(|value| value.to_str().unwrap().to_owned());
+                            headers.lock().unwrap().push(authorization);
+                            Response::builder()
+                                .status(status.as_u16())
+                                .body(Body::from(body))
+                        }
+                    }))
+                }
+            }));
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        (format!("http://{addr}"), headers2)
+    }
+
+    #[tokio::test]
+    async fn parses_success_body() {
+        let (endpoint, _) = mock_pageserver(StatusCode::OK, r#"{"id": 7}"#);
+        let client = Client::new(endpoint, None);
+
+        let status = client.status().await.unwrap();
+        assert_eq!(status.id, NodeId(7));
+    }
+
+    #[tokio::test]
+    async fn api_error_uses_message_from_body() {
+        let (endpoint, _) =
+            mock_pageserver(StatusCode::NOT_FOUND, r#"{"msg": "NotFound: tenant 1234"}"#);
+        let client = Client::new(endpoint, None);
+
+        let err = client
+            .tenant_details(TenantShardId::unsharded(TenantId::generate()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+        match err {
+            Error::ApiError(status, msg) => {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(msg, "NotFound: tenant 1234");
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn api_error_without_json_body() {
+        let (endpoint, _) = mock_pageserver(StatusCode::SERVICE_UNAVAILABLE, "shutting down");
+        let client = Client::new(endpoint, None);
+
+        let err = client.reload_auth_validation_keys().await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        match err {
+            Error::ApiError(status, msg) => {
+                assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+                assert!(msg.starts_with("Http error (503) at "), "{msg}");
+                assert!(msg.ends_with("shutting down"), "{msg}");
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_success_body() {
+        let (endpoint, _) = mock_pageserver(StatusCode::OK, "not json");
+        let client = Client::new(endpoint, None);
+
+        let err = client.status().await.unwrap_err();
+        assert!(matches!(err, Error::ReceiveBody(_)), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn unreachable_pageserver() {
+        // Nothing listens on the port once the listener is dropped
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = Client::new(format!("http://{addr}"), None);
+
+        let err = client.status().await.unwrap_err();
+        assert!(matches!(err, Error::SendRequest(_)), "{err:?}");
+        assert_eq!(err.status(), None);
+    }
+
+    #[tokio::test]
+    async fn sends_authorization_header() {
+        let (endpoint, headers) = mock_pageserver(StatusCode::OK, "");
+
+        Client::new(endpoint.clone(), Some("secret"))
+            .reload_auth_validation_keys()
+            .await
+            .unwrap();
+        Client::new(endpoint, None)
+            .reload_auth_validation_keys()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *headers.lock().unwrap(),
+            vec![Some("Bearer secret".to_owned()), None]
+        );
+    }
+}
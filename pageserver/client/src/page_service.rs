@@ -0,0 +1,91 @@
+//! Client for the pageserver's libpq page service.
+use std::pin::Pin;
+
+use futures::{SinkExt, StreamExt};
+use pageserver_api::models::{PagestreamBeMessage, PagestreamFeMessage};
+use tokio::task::JoinHandle;
+use tokio_postgres::CopyBothDuplex;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use utils::id::{TenantId, TimelineId};
+
+pub struct Client {
+    client: tokio_postgres::Client,
+    cancel_on_client_drop: Option<DropGuard>,
+    conn_task: JoinHandle<()>,
+}
+
+impl Client {
+    pub async fn new(connstring: &str) -> anyhow::Result<Self> {
+        let (client, connection) =
+            tokio_postgres::connect(connstring, tokio_postgres::NoTls).await?;
+
+        let conn_task_cancel = CancellationToken::new();
+        let conn_task = tokio::spawn({
+            let conn_task_cancel = conn_task_cancel.clone();
+            async move {
+                tokio::select! {
+                    _ = conn_task_cancel.cancelled() => {}
+                    res = connection => {
+                        if let Err(e) = res {
+                            tracing::warn!("page service connection failed: {e}");
+                        }
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            client,
+            cancel_on_client_drop: Some(conn_task_cancel.drop_guard()),
+            conn_task,
+        })
+    }
+
+    /// Switch the connection into pagestream mode for the given timeline.
+    pub async fn pagestream(
+        self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> anyhow::Result<PagestreamClient> {
+        let copy_both: CopyBothDuplex<bytes::Bytes> = self
+            .client
+            .copy_both_simple(&format!("pagestream {tenant_id} {timeline_id}"))
+            .await?;
+        let Client {
+            cancel_on_client_drop,
+            conn_task,
+            client: _,
+        } = self;
+        Ok(PagestreamClient {
+            copy_both: Box::pin(copy_both),
+            cancel_on_client_drop,
+            conn_task,
+        })
+    }
+}
+
+/// A connection in pagestream mode. Requests are answered in order, one at a time.
+pub struct PagestreamClient {
+    copy_both: Pin<Box<CopyBothDuplex<bytes::Bytes>>>,
+    cancel_on_client_drop: Option<DropGuard>,
+    conn_task: JoinHandle<()>,
+}
+
+impl PagestreamClient {
+    pub async fn shutdown(mut self) {
+        let _ = self.cancel_on_client_drop.take();
+        let _ = self.conn_task.await;
+    }
+
+    /// Send a request and wait for its response. Error responses from the pageserver
+    /// are returned as [`PagestreamBeMessage::Error`], not as `Err`.
+    pub async fn request(
+        &mut self,
+        req: &PagestreamFeMessage,
+    ) -> anyhow::Result<PagestreamBeMessage> {
+        self.copy_both.send(req.serialize()).await?;
+        let Some(next) = self.copy_both.next().await else {
+            anyhow::bail!("pagestream connection closed by the pageserver");
+        };
+        PagestreamBeMessage::deserialize(next?)
+    }
+}
@@ -1,16 +1,22 @@
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
 use camino::{Utf8Path, Utf8PathBuf};
 use clap::Subcommand;
+use pageserver::config::PageServerConf;
 use pageserver::context::{DownloadBehavior, RequestContext};
 use pageserver::task_mgr::TaskKind;
 use pageserver::tenant::block_io::BlockCursor;
 use pageserver::tenant::disk_btree::DiskBtreeReader;
+use pageserver::tenant::remote_timeline_client::index::IndexLayerMetadata;
 use pageserver::tenant::storage_layer::delta_layer::{BlobRef, Summary};
 use pageserver::tenant::storage_layer::{delta_layer, image_layer};
-use pageserver::tenant::storage_layer::{DeltaLayer, ImageLayer};
-use pageserver::tenant::{TENANTS_SEGMENT_NAME, TIMELINES_SEGMENT_NAME};
+use pageserver::tenant::storage_layer::{
+    DeltaLayer, ImageLayer, LayerFileName, PersistentLayerDesc, RewrittenLayer,
+};
+use pageserver::tenant::{IndexPart, TENANTS_SEGMENT_NAME, TIMELINES_SEGMENT_NAME};
 use pageserver::{page_cache, virtual_file};
 use pageserver::{
     repository::{Key, KEY_SIZE},
@@ -20,9 +26,11 @@ use pageserver::{
     },
     virtual_file::VirtualFile,
 };
+use pageserver::{DELTA_FILE_MAGIC, IMAGE_FILE_MAGIC};
 use std::fs;
 use utils::bin_ser::BeSer;
 use utils::id::{TenantId, TimelineId};
+use utils::lsn::Lsn;
 
 use crate::layer_map_analyzer::parse_filename;
 
@@ -55,6 +63,126 @@ pub(crate) enum LayerCmd {
         #[clap(long)]
         new_timeline_id: Option<TimelineId>,
     },
+    /// Write a repaired copy of a delta or image layer file
+    ///
+    /// The copy is re-encoded with the current format version, and gets a freshly
+    /// computed summary and file name. The original file is left untouched.
+    ///
+    /// Example: `cargo run --bin pagectl layer rewrite --exclude-keys <start>-<end> <layer>`
+    Rewrite {
+        layer_file_path: Utf8PathBuf,
+        /// Drop the values of the keys in this range, given as `<start>-<end>` in hex.
+        /// The key range of the new layer is shrunk to the keys that remain. Image layers
+        /// cover every key of their range, so excluded keys left inside it no longer exist.
+        #[clap(long)]
+        exclude_keys: Option<String>,
+        /// Drop the delta values at and above this LSN, and end the layer's LSN range there
+        #[clap(long)]
+        truncate_lsn: Option<Lsn>,
+        /// Directory to write the new layer file to. Defaults to the directory of the
+        /// original file.
+        #[clap(long)]
+        output_dir: Option<Utf8PathBuf>,
+        /// `index_part.json` of the timeline. A copy that references the new layer
+        /// instead of the original is written next to it, and the change is printed.
+        #[clap(long)]
+        index_part: Option<Utf8PathBuf>,
+    },
+}
+
+fn parse_key_range(s: &str) -> Result<Range<Key>> {
+    let Some((start, end)) = s.split_once('-') else {
+        bail!("key range must be given as <start>-<end>: {s}");
+    };
+    let range = Key::from_hex(start)?..Key::from_hex(end)?;
+    ensure!(range.start < range.end, "empty key range: {s}");
+    Ok(range)
+}
+
+/// Write the rewritten copy of the layer at `path` under `conf`'s workdir. Returns
+/// the descriptor of the original layer along with the result.
+async fn rewrite_layer_file(
+    conf: &'static PageServerConf,
+    path: &Utf8Path,
+    exclude_keys: Option<Range<Key>>,
+    truncate_lsn: Option<Lsn>,
+    ctx: &RequestContext,
+) -> Result<(PersistentLayerDesc, Option<RewrittenLayer>)> {
+    let keep_key = |key: &Key| {
+        !exclude_keys
+            .as_ref()
+            .is_some_and(|range| range.contains(key))
+    };
+
+    // All layer files start with a two-byte "magic" value, to identify the kind of
+    // file.
+    let file = fs::File::open(path).with_context(|| format!("open {path}"))?;
+    let mut header_buf = [0u8; 2];
+    file.read_exact_at(&mut header_buf, 0)?;
+
+    match u16::from_be_bytes(header_buf) {
+        IMAGE_FILE_MAGIC => {
+            let layer = ImageLayer::new_for_path(path, file)?;
+            if let Some(lsn) = truncate_lsn {
+                ensure!(
+                    lsn > layer.lsn,
+                    "truncating at {lsn} would drop all of the image layer at {}",
+                    layer.lsn
+                );
+            }
+            fs::create_dir_all(
+                conf.timeline_path(&layer.desc.tenant_shard_id, &layer.desc.timeline_id),
+            )?;
+            let rewritten = layer.rewrite(conf, keep_key, ctx).await?;
+            Ok((layer.desc, rewritten))
+        }
+        DELTA_FILE_MAGIC => {
+            let layer = DeltaLayer::new_for_path(path, file)?;
+            fs::create_dir_all(
+                conf.timeline_path(&layer.desc.tenant_shard_id, &layer.desc.timeline_id),
+            )?;
+            let lsn_end = truncate_lsn.unwrap_or(Lsn::MAX);
+            let rewritten = layer.rewrite(conf, lsn_end, keep_key, ctx).await?;
+            Ok((layer.desc, rewritten))
+        }
+        magic => bail!("unrecognized magic identifier: {:?}", magic),
+    }
+}
+
+/// Write a copy of `index_part.json` in which `old_name` is replaced by `new_name`,
+/// and print the change.
+fn rewrite_index_part(
+    path: &Utf8Path,
+    old_name: &LayerFileName,
+    new_name: &LayerFileName,
+    new_file_size: u64,
+) -> Result<()> {
+    let bytes = fs::read(path).with_context(|| format!("read {path}"))?;
+    let mut index_part = IndexPart::from_s3_bytes(&bytes).context("deserialize")?;
+
+    let Some(old_metadata) = index_part.layer_metadata.remove(old_name) else {
+        bail!("{path} does not reference layer {old_name}");
+    };
+    let new_metadata = IndexLayerMetadata {
+        file_size: new_file_size,
+        ..old_metadata.clone()
+    };
+    index_part
+        .layer_metadata
+        .insert(new_name.clone(), new_metadata.clone());
+
+    let new_path = Utf8PathBuf::from(format!("{path}.rewritten"));
+    fs::write(&new_path, index_part.to_s3_bytes()?).with_context(|| format!("write {new_path}"))?;
+
+    println!("--- {path}");
+    println!("+++ {new_path}");
+    println!("-  {old_name}: {}", serde_json::to_string(&old_metadata)?);
+    println!("+  {new_name}: {}", serde_json::to_string(&new_metadata)?);
+    println!(
+        "The new layer must be uploaded as {new_name}{}",
+        new_metadata.generation.get_suffix()
+    );
+    Ok(())
 }
 
 async fn read_delta_file(path: impl AsRef<Path>, ctx: &RequestContext) -> Result<()> {
@@ -238,5 +366,67 @@ pub(crate) async fn main(cmd: &LayerCmd) -> Result<()> {
 
             anyhow::bail!("not an image or delta layer: {layer_file_path}");
         }
+        LayerCmd::Rewrite {
+            layer_file_path,
+            exclude_keys,
+            truncate_lsn,
+            output_dir,
+            index_part,
+        } => {
+            pageserver::virtual_file::init(10);
+            pageserver::page_cache::init(100);
+
+            let exclude_keys = exclude_keys.as_deref().map(parse_key_range).transpose()?;
+            let output_dir = match output_dir {
+                Some(output_dir) => output_dir.clone(),
+                None => layer_file_path
+                    .parent()
+                    .context("layer file path has no parent")?
+                    .to_owned(),
+            };
+
+            // The layer writers create their temporary files in the timeline directory
+            // of a pageserver workdir. Use a scratch one inside the output directory, so
+            // that the final rename stays within one filesystem.
+            let workdir = output_dir.join(".pagectl-rewrite");
+            let conf: &'static PageServerConf =
+                Box::leak(Box::new(PageServerConf::dummy_conf(workdir.clone())));
+
+            let res = async {
+                let (old_desc, rewritten) =
+                    rewrite_layer_file(conf, layer_file_path, exclude_keys, *truncate_lsn, &ctx)
+                        .await?;
+                let Some(rewritten) = rewritten else {
+                    bail!("no values would be left in the rewritten layer");
+                };
+                let new_path = output_dir.join(rewritten.desc.filename().file_name());
+                ensure!(
+                    !new_path.exists(),
+                    "{new_path} already exists, use --output-dir to write the new layer elsewhere"
+                );
+                fs::rename(&rewritten.temp_path, &new_path)
+                    .with_context(|| format!("rename to {new_path}"))?;
+                println!(
+                    "Wrote {new_path}: kept {} values, dropped {}",
+                    rewritten.values_kept, rewritten.values_dropped
+                );
+                Ok((old_desc, rewritten.desc))
+            }
+            .await;
+            if workdir.exists() {
+                fs::remove_dir_all(&workdir).with_context(|| format!("remove {workdir}"))?;
+            }
+            let (old_desc, new_desc) = res?;
+
+            if let Some(index_part) = index_part {
+                rewrite_index_part(
+                    index_part,
+                    &old_desc.filename(),
+                    &new_desc.filename(),
+                    new_desc.file_size,
+                )?;
+            }
+            Ok(())
+        }
     }
 }
@@ -8,6 +8,7 @@ mod draw_timeline_dir;
 mod index_part;
 mod layer_map_analyzer;
 mod layers;
+mod reconstruct;
 
 use camino::{Utf8Path, Utf8PathBuf};
 use clap::{Parser, Subcommand};
@@ -21,6 +22,7 @@ use pageserver::{
     virtual_file,
 };
 use postgres_ffi::ControlFileData;
+use reconstruct::ReconstructCmd;
 use utils::{lsn::Lsn, project_git_version};
 
 project_git_version!(GIT_VERSION);
@@ -47,6 +49,7 @@ enum Commands {
     AnalyzeLayerMap(AnalyzeLayerMapCmd),
     #[command(subcommand)]
     Layer(LayerCmd),
+    Reconstruct(ReconstructCmd),
 }
 
 /// Read and update pageserver metadata file
@@ -96,6 +99,9 @@ async fn main() -> anyhow::Result<()> {
         Commands::AnalyzeLayerMap(cmd) => {
             layer_map_analyzer::main(&cmd).await?;
         }
+        Commands::Reconstruct(cmd) => {
+            reconstruct::main(&cmd).await?;
+        }
         Commands::PrintLayerFile(cmd) => {
             if let Err(e) = read_pg_control_file(&cmd.path) {
                 println!(
@@ -0,0 +1,419 @@
+//! Offline page reconstruction, for debugging pages that come back wrong.
+//!
+//! Walks the layer files of a local tenant directory the same way
+//! `Timeline::get_reconstruct_data` does, printing every layer visited and the base
+//! image and WAL records collected from it. Optionally runs WAL redo on the result
+//! and prints the header of the reconstructed page.
+//!
+//! The layers to search are the ones in the latest `index_part.json` of each timeline if
+//! a copy of the tenant's remote storage prefix is given, and the local layer files otherwise.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use bytes::Bytes;
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use pageserver::config::PageServerConf;
+use pageserver::context::{DownloadBehavior, RequestContext};
+use pageserver::page_cache::{self, PAGE_SZ};
+use pageserver::pgdatadir_mapping::{is_inherited_key, rel_block_to_key};
+use pageserver::repository::Key;
+use pageserver::task_mgr::TaskKind;
+use pageserver::tenant::layer_map::{LayerMap, SearchResult};
+use pageserver::tenant::metadata::TimelineMetadata;
+use pageserver::tenant::storage_layer::{
+    DeltaLayer, ImageLayer, LayerFileName, PersistentLayerDesc, ValueReconstructResult,
+    ValueReconstructState,
+};
+use pageserver::tenant::{IndexPart, TIMELINES_SEGMENT_NAME};
+use pageserver::walrecord::describe_wal_record;
+use pageserver::walredo::PostgresRedoManager;
+use pageserver::{virtual_file, METADATA_FILE_NAME};
+use pageserver_api::reltag::RelTag;
+use pageserver_api::shard::TenantShardId;
+use utils::generation::Generation;
+use utils::id::TimelineId;
+use utils::lsn::Lsn;
+
+#[derive(Parser)]
+pub(crate) struct ReconstructCmd {
+    /// Local tenant directory, e.g. `.neon/pageserver_1/tenants/<tenant_shard_id>`
+    tenant_path: Utf8PathBuf,
+    /// A copy of the tenant's remote storage prefix, e.g.
+    /// `.neon/local_fs_remote_storage/pageserver/tenants/<tenant_shard_id>`, to read the
+    /// timelines' layers and metadata from their latest `index_part.json`
+    #[arg(long)]
+    remote_tenant_path: Option<Utf8PathBuf>,
+    /// Timeline to read the page from
+    timeline_id: TimelineId,
+    /// LSN to reconstruct the page at
+    #[arg(long)]
+    lsn: Lsn,
+    /// Key to reconstruct, in hex
+    #[arg(long, conflicts_with = "rel", required_unless_present = "rel")]
+    key: Option<String>,
+    /// Relation of the page, as `<spcnode>/<dbnode>/<relnode>[_fsm|_vm|_init]`
+    #[arg(long, requires = "blkno")]
+    rel: Option<RelTag>,
+    /// Block number of the page within `--rel`
+    #[arg(long, requires = "rel")]
+    blkno: Option<u32>,
+    /// Run WAL redo with the postgres binaries from this directory
+    #[arg(long)]
+    pg_distrib_dir: Option<Utf8PathBuf>,
+    /// Write the reconstructed page to this file. Requires `--pg-distrib-dir` if
+    /// there are WAL records to apply.
+    #[arg(long)]
+    output: Option<Utf8PathBuf>,
+}
+
+/// The layers and metadata of one timeline, and which of the layers are on local disk.
+struct LocalTimeline {
+    timeline_id: TimelineId,
+    path: Utf8PathBuf,
+    metadata: TimelineMetadata,
+    layers: LayerMap,
+    local_layers: HashSet<LayerFileName>,
+}
+
+fn load_timeline(
+    tenant_path: &Utf8Path,
+    remote_tenant_path: Option<&Utf8Path>,
+    tenant_shard_id: TenantShardId,
+    timeline_id: TimelineId,
+) -> anyhow::Result<LocalTimeline> {
+    let path = tenant_path
+        .join(TIMELINES_SEGMENT_NAME)
+        .join(timeline_id.to_string());
+
+    let mut local_layers = HashMap::new();
+    for entry in path
+        .read_dir_utf8()
+        .with_context(|| format!("read {path}"))?
+    {
+        let entry = entry?;
+        let Ok(file_name) = LayerFileName::from_str(entry.file_name()) else {
+            continue;
+        };
+        local_layers.insert(file_name, entry.metadata()?.len());
+    }
+
+    let (metadata, layer_sizes) = match remote_tenant_path {
+        Some(remote_tenant_path) => {
+            let IndexPart {
+                metadata,
+                layer_metadata,
+                ..
+            } = read_latest_index_part(remote_tenant_path, timeline_id)?;
+            for file_name in local_layers.keys() {
+                if !layer_metadata.contains_key(file_name) {
+                    eprintln!(
+                        "warning: ignoring layer {} of timeline {timeline_id}, not in the index",
+                        file_name.file_name()
+                    );
+                }
+            }
+            let missing = layer_metadata
+                .keys()
+                .filter(|file_name| !local_layers.contains_key(file_name))
+                .count();
+            if missing > 0 {
+                eprintln!(
+                    "warning: {missing} of the {} indexed layers of timeline {timeline_id} \
+                     are not on local disk",
+                    layer_metadata.len()
+                );
+            }
+            let layer_sizes = layer_metadata
+                .into_iter()
+                .map(|(file_name, meta)| (file_name, meta.file_size))
+                .collect::<Vec<_>>();
+            (metadata, layer_sizes)
+        }
+        None => {
+            let metadata_path = path.join(METADATA_FILE_NAME);
+            let metadata = TimelineMetadata::from_bytes(
+                &fs::read(&metadata_path).with_context(|| format!("read {metadata_path}"))?,
+            )?;
+            let layer_sizes = local_layers
+                .iter()
+                .map(|(file_name, size)| (file_name.clone(), *size))
+                .collect::<Vec<_>>();
+            (metadata, layer_sizes)
+        }
+    };
+
+    let mut layers = LayerMap::default();
+    let mut updates = layers.batch_update();
+    for (file_name, size) in layer_sizes {
+        updates.insert_historic(PersistentLayerDesc::from_filename(
+            tenant_shard_id,
+            timeline_id,
+            file_name,
+            size,
+        ));
+    }
+    updates.flush();
+
+    Ok(LocalTimeline {
+        timeline_id,
+        path,
+        metadata,
+        layers,
+        local_layers: local_layers.into_keys().collect(),
+    })
+}
+
+/// Reads the `index_part.json` of the latest generation of a timeline in a copy of the
+/// tenant's remote storage prefix.
+fn read_latest_index_part(
+    remote_tenant_path: &Utf8Path,
+    timeline_id: TimelineId,
+) -> anyhow::Result<IndexPart> {
+    let dir = remote_tenant_path
+        .join(TIMELINES_SEGMENT_NAME)
+        .join(timeline_id.to_string());
+
+    let mut latest: Option<(Generation, Utf8PathBuf)> = None;
+    for entry in dir.read_dir_utf8().with_context(|| format!("read {dir}"))? {
+        let entry = entry?;
+        let Some(suffix) = entry.file_name().strip_prefix(IndexPart::FILE_NAME) else {
+            continue;
+        };
+        let generation = match suffix.strip_prefix('-') {
+            None if suffix.is_empty() => Generation::none(),
+            Some(suffix) => match Generation::parse_suffix(suffix) {
+                Some(generation) => generation,
+                None => continue,
+            },
+            None => continue,
+        };
+        if latest
+            .as_ref()
+            .map_or(true, |(latest, _)| generation > *latest)
+        {
+            latest = Some((generation, entry.path().to_owned()));
+        }
+    }
+
+    let (_, path) = latest.with_context(|| format!("no {} in {dir}", IndexPart::FILE_NAME))?;
+    println!("reading the layers of timeline {timeline_id} from {path}");
+    IndexPart::from_s3_bytes(&fs::read(&path).with_context(|| format!("read {path}"))?)
+        .with_context(|| format!("parse {path}"))
+}
+
+async fn get_layer_reconstruct_data(
+    path: &Utf8Path,
+    desc: &PersistentLayerDesc,
+    key: Key,
+    lsn_range: std::ops::Range<Lsn>,
+    reconstruct_state: &mut ValueReconstructState,
+    ctx: &RequestContext,
+) -> anyhow::Result<ValueReconstructResult> {
+    let file = File::open(path).with_context(|| format!("open {path}"))?;
+    if desc.is_delta() {
+        DeltaLayer::new_for_path(path, file)?
+            .get_value_reconstruct_data(key, lsn_range, reconstruct_state, ctx)
+            .await
+    } else {
+        ImageLayer::new_for_path(path, file)?
+            .get_value_reconstruct_data(key, lsn_range, reconstruct_state, ctx)
+            .await
+    }
+}
+
+fn print_page_header(page: &[u8]) {
+    let u16_at = |off: usize| u16::from_le_bytes([page[off], page[off + 1]]);
+    println!("page header:");
+    println!(
+        "  pd_lsn:              {}",
+        postgres_ffi::page_get_lsn(page)
+    );
+    println!("  pd_checksum:         {:#06x}", u16_at(8));
+    println!("  pd_flags:            {:#06x}", u16_at(10));
+    println!("  pd_lower:            {}", u16_at(12));
+    println!("  pd_upper:            {}", u16_at(14));
+    println!("  pd_special:          {}", u16_at(16));
+    println!("  pd_pagesize_version: {:#06x}", u16_at(18));
+    println!(
+        "  pd_prune_xid:        {}",
+        u32::from_le_bytes(page[20..24].try_into().unwrap())
+    );
+    if postgres_ffi::page_is_new(page) {
+        println!("  (page is new, i.e. all zeros)");
+    }
+}
+
+pub(crate) async fn main(cmd: &ReconstructCmd) -> anyhow::Result<()> {
+    let key = match (&cmd.key, cmd.rel, cmd.blkno) {
+        (Some(key), _, _) => Key::from_hex(key)?,
+        (None, Some(rel), Some(blkno)) => rel_block_to_key(rel, blkno),
+        _ => bail!("either --key or --rel and --blkno must be given"),
+    };
+    let tenant_shard_id = TenantShardId::from_str(
+        cmd.tenant_path
+            .file_name()
+            .context("tenant path has no file name")?,
+    )
+    .context("tenant directory name is not a tenant shard id")?;
+
+    virtual_file::init(10);
+    page_cache::init(100);
+    let ctx = RequestContext::new(TaskKind::DebugTool, DownloadBehavior::Error);
+
+    let remote_tenant_path = cmd.remote_tenant_path.as_deref();
+    let mut timeline = load_timeline(
+        &cmd.tenant_path,
+        remote_tenant_path,
+        tenant_shard_id,
+        cmd.timeline_id,
+    )?;
+    let pg_version = timeline.metadata.pg_version();
+    println!(
+        "reconstructing key {key} at {} on timeline {}",
+        cmd.lsn, timeline.timeline_id
+    );
+
+    let mut reconstruct_state = ValueReconstructState {
+        records: Vec::new(),
+        img: None,
+    };
+    let mut result = ValueReconstructResult::Continue;
+    let mut cont_lsn = Lsn(cmd.lsn.0 + 1);
+    let mut prev_lsn = Lsn(u64::MAX);
+
+    loop {
+        match result {
+            ValueReconstructResult::Complete => break,
+            ValueReconstructResult::Continue => {
+                if prev_lsn <= cont_lsn {
+                    bail!(
+                        "could not find layer with more data for key {key} at LSN {}",
+                        Lsn(cont_lsn.0 - 1)
+                    );
+                }
+                prev_lsn = cont_lsn;
+            }
+            ValueReconstructResult::Missing => {
+                bail!("could not find data for key {key} at LSN {cont_lsn}");
+            }
+        }
+
+        if is_inherited_key(key) && Lsn(cont_lsn.0 - 1) <= timeline.metadata.ancestor_lsn() {
+            let Some(ancestor_id) = timeline.metadata.ancestor_timeline() else {
+                bail!(
+                    "reached the start of timeline {} without finding a base image",
+                    timeline.timeline_id
+                );
+            };
+            println!(
+                "going into ancestor timeline {ancestor_id} at {}",
+                timeline.metadata.ancestor_lsn()
+            );
+            timeline = load_timeline(
+                &cmd.tenant_path,
+                remote_tenant_path,
+                tenant_shard_id,
+                ancestor_id,
+            )?;
+            prev_lsn = Lsn(u64::MAX);
+            continue;
+        }
+
+        let Some(SearchResult { layer, lsn_floor }) = timeline.layers.search(key, cont_lsn) else {
+            if timeline.metadata.ancestor_timeline().is_none() {
+                bail!(
+                    "no layer of timeline {} contains key {key} below LSN {cont_lsn}",
+                    timeline.timeline_id
+                );
+            }
+            // Nothing on this timeline: carry on from the branch point in the ancestor
+            println!(
+                "no layer of timeline {} contains key {key} below LSN {cont_lsn}",
+                timeline.timeline_id
+            );
+            result = ValueReconstructResult::Continue;
+            cont_lsn = Lsn(timeline.metadata.ancestor_lsn().0 + 1);
+            continue;
+        };
+        let file_name = layer.filename().file_name();
+        let path = timeline.path.join(&file_name);
+        if !timeline.local_layers.contains(&layer.filename()) {
+            bail!(
+                "layer {file_name} of timeline {} is not on local disk, download it to {path}",
+                timeline.timeline_id
+            );
+        }
+        let n_records = reconstruct_state.records.len();
+        result = get_layer_reconstruct_data(
+            &path,
+            &layer,
+            key,
+            lsn_floor..cont_lsn,
+            &mut reconstruct_state,
+            &ctx,
+        )
+        .await
+        .with_context(|| format!("read layer {path}"))?;
+        println!(
+            "timeline {} layer {file_name}, LSN range {lsn_floor}..{cont_lsn}: {result:?}",
+            timeline.timeline_id
+        );
+        for (lsn, rec) in &reconstruct_state.records[n_records..] {
+            let desc = describe_wal_record(rec)
+                .unwrap_or_else(|e| format!("{rec:?} (failed to describe: {e})"));
+            println!("  WAL record at {lsn}: {desc}");
+        }
+        if let Some((lsn, img)) = &reconstruct_state.img {
+            println!("  base image at {lsn}, {} bytes", img.len());
+        }
+        cont_lsn = lsn_floor;
+    }
+
+    // Records were collected newest first, redo wants them in LSN order
+    let ValueReconstructState { mut records, img } = reconstruct_state;
+    records.reverse();
+    println!(
+        "collected {} WAL records{}",
+        records.len(),
+        match &img {
+            Some((lsn, _)) => format!(" on top of a base image at {lsn}"),
+            None => String::new(),
+        }
+    );
+
+    let page: Bytes = if records.is_empty() {
+        let Some((_, img)) = img else {
+            bail!("found neither a base image nor WAL records");
+        };
+        img
+    } else if let Some(pg_distrib_dir) = &cmd.pg_distrib_dir {
+        let mut conf = PageServerConf::dummy_conf(cmd.tenant_path.clone());
+        conf.pg_distrib_dir = pg_distrib_dir.clone();
+        let conf: &'static PageServerConf = Box::leak(Box::new(conf));
+        let redo_manager = PostgresRedoManager::new(conf, tenant_shard_id.tenant_id);
+        redo_manager
+            .request_redo(key, cmd.lsn, img, records, pg_version)
+            .await
+            .context("WAL redo")?
+    } else {
+        if cmd.output.is_some() {
+            bail!("pass --pg-distrib-dir to apply the WAL records");
+        }
+        return Ok(());
+    };
+
+    if page.len() == PAGE_SZ {
+        print_page_header(&page);
+    } else {
+        println!("value is {} bytes, not a page", page.len());
+    }
+    if let Some(output) = &cmd.output {
+        fs::write(output, &page).with_context(|| format!("write {output}"))?;
+        println!("wrote reconstructed value to {output}");
+    }
+    Ok(())
+}
@@ -694,7 +694,8 @@ fn create_remote_storage_client(
         return Ok(None);
     };
 
-    // Create the client
+    // Create the client. A mirrored storage spawns its background tasks here.
+    let _rt_guard = BACKGROUND_RUNTIME.enter();
     let mut remote_storage = GenericRemoteStorage::from_config(config)?;
 
     // If `test_remote_failures` is non-zero, wrap the client with a
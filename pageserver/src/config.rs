@@ -118,6 +118,7 @@ pub mod defaults {
 #min_resident_size_override = .. # in bytes
 #evictions_low_residence_duration_metric_threshold = '{DEFAULT_EVICTIONS_LOW_RESIDENCE_DURATION_METRIC_THRESHOLD}'
 #gc_feedback = false
+#remote_storage_throttle = {{ on_demand_download = {{ ops_per_second = .., bytes_per_second = .. }}, upload = .., background_download = .. }}
 
 #heatmap_upload_concurrency = {DEFAULT_HEATMAP_UPLOAD_CONCURRENCY}
 
@@ -1052,7 +1053,7 @@ mod tests {
     };
 
     use camino_tempfile::{tempdir, Utf8TempDir};
-    use remote_storage::{RemoteStorageKind, S3Config};
+    use remote_storage::{LocalFsConfig, RemoteStorageKind, S3Config};
     use utils::serde_percent::Percent;
 
     use super::*;
@@ -1247,7 +1248,9 @@ broker_endpoint = '{broker_endpoint}'
             assert_eq!(
                 parsed_remote_storage_config,
                 RemoteStorageConfig {
-                    storage: RemoteStorageKind::LocalFs(local_storage_path.clone()),
+                    storage: RemoteStorageKind::LocalFs(LocalFsConfig::new(local_storage_path.clone())),
+                    timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+                    small_timeout: RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
                 },
                 "Remote storage config should correctly parse the local FS config and fill other storage defaults"
             );
@@ -1315,6 +1318,8 @@ broker_endpoint = '{broker_endpoint}'
                         concurrency_limit: s3_concurrency_limit,
                         max_keys_per_list_response: None,
                     }),
+                    timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+                    small_timeout: RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
                 },
                 "Remote storage config should correctly parse the S3 config"
             );
@@ -726,7 +726,7 @@ mod test {
     use std::{io::ErrorKind, time::Duration};
     use tracing::info;
 
-    use remote_storage::{RemoteStorageConfig, RemoteStorageKind};
+    use remote_storage::{LocalFsConfig, RemoteStorageConfig, RemoteStorageKind};
     use tokio::task::JoinHandle;
 
     use crate::{
@@ -866,7 +866,9 @@ mod test {
         std::fs::create_dir_all(remote_fs_dir)?;
         let remote_fs_dir = harness.conf.workdir.join("remote_fs").canonicalize_utf8()?;
         let storage_config = RemoteStorageConfig {
-            storage: RemoteStorageKind::LocalFs(remote_fs_dir.clone()),
+            storage: RemoteStorageKind::LocalFs(LocalFsConfig::new(remote_fs_dir.clone())),
+            timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+            small_timeout: RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
         };
         let storage = GenericRemoteStorage::from_config(&storage_config).unwrap();
 
@@ -1175,6 +1177,7 @@ pub(crate) mod mock {
     impl ConsumerState {
         async fn consume(&mut self, remote_storage: &GenericRemoteStorage) -> usize {
             let mut executed = 0;
+            let cancel = CancellationToken::new();
 
             info!("Executing all pending deletions");
 
@@ -1183,7 +1186,7 @@ pub(crate) mod mock {
                 match msg {
                     DeleterMessage::Delete(objects) => {
                         for path in objects {
-                            match remote_storage.delete(&path).await {
+                            match remote_storage.delete(&path, &cancel).await {
                                 Ok(_) => {
                                     debug!("Deleted {path}");
                                 }
@@ -1216,7 +1219,7 @@ pub(crate) mod mock {
 
                         for path in objects {
                             info!("Executing deletion {path}");
-                            match remote_storage.delete(&path).await {
+                            match remote_storage.delete(&path, &cancel).await {
                                 Ok(_) => {
                                     debug!("Deleted {path}");
                                 }
@@ -15,10 +15,12 @@ use hyper::StatusCode;
 use hyper::{Body, Request, Response, Uri};
 use metrics::launch_timestamp::LaunchTimestamp;
 use pageserver_api::models::{
-    DownloadRemoteLayersTaskSpawnRequest, LocationConfigMode, TenantAttachRequest,
-    TenantLoadRequest, TenantLocationConfigRequest,
+    DownloadRemoteLayersTaskSpawnRequest, LocationConfigMode, LsnByTimestampResponse,
+    LsnForTimestampKind, TenantAttachRequest, TenantLoadRequest, TenantLocationConfigRequest,
+    TenantShardSplitRequest, TenantShardSplitResponse, TimelineImportBasebackupRequest,
+    TimelineImportInfo, TimelineImportWalRequest,
 };
-use pageserver_api::shard::TenantShardId;
+use pageserver_api::shard::{ShardCount, TenantShardId};
 use remote_storage::GenericRemoteStorage;
 use tenant_size_model::{SizeResult, StorageModel};
 use tokio_util::sync::CancellationToken;
@@ -47,6 +49,7 @@ use crate::tenant::size::ModelInputs;
 use crate::tenant::storage_layer::LayerAccessStatsReset;
 use crate::tenant::timeline::CompactFlags;
 use crate::tenant::timeline::Timeline;
+use crate::tenant::timeline_import::TimelineImportError;
 use crate::tenant::{LogicalSizeCalculationCause, PageReconstructError, TenantSharedResources};
 use crate::{config::PageServerConf, tenant::mgr};
 use crate::{disk_usage_eviction_task, tenant};
@@ -586,18 +589,13 @@ async fn get_lsn_by_timestamp_handler(
         .await?;
 
     if version.unwrap_or(0) > 1 {
-        #[derive(serde::Serialize)]
-        struct Result {
-            lsn: Lsn,
-            kind: &'static str,
-        }
         let (lsn, kind) = match result {
-            LsnForTimestamp::Present(lsn) => (lsn, "present"),
-            LsnForTimestamp::Future(lsn) => (lsn, "future"),
-            LsnForTimestamp::Past(lsn) => (lsn, "past"),
-            LsnForTimestamp::NoData(lsn) => (lsn, "nodata"),
+            LsnForTimestamp::Present(lsn) => (lsn, LsnForTimestampKind::Present),
+            LsnForTimestamp::Future(lsn) => (lsn, LsnForTimestampKind::Future),
+            LsnForTimestamp::Past(lsn) => (lsn, LsnForTimestampKind::Past),
+            LsnForTimestamp::NoData(lsn) => (lsn, LsnForTimestampKind::NoData),
         };
-        json_response(StatusCode::OK, Result { lsn, kind })
+        json_response(StatusCode::OK, LsnByTimestampResponse { lsn, kind })
     } else {
         // FIXME: this is a temporary crutch not to break backwards compatibility
         // See https://github.com/neondatabase/neon/pull/5608
@@ -729,6 +727,51 @@ async fn tenant_detach_handler(
     json_response(StatusCode::OK, ())
 }
 
+async fn tenant_time_travel_remote_storage_handler(
+    request: Request<Body>,
+    cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let timestamp_raw = must_get_query_param(&request, "travel_to")?;
+    let timestamp = humantime::parse_rfc3339(&timestamp_raw)
+        .with_context(|| format!("Invalid time for travel_to: {timestamp_raw:?}"))
+        .map_err(ApiError::BadRequest)?;
+
+    // Rewriting the remote storage under an attached tenant would race with its uploads
+    // and leave its in-memory state out of sync with the bucket.
+    match mgr::get_tenant(tenant_shard_id, false) {
+        Err(GetTenantError::NotFound(_)) => {}
+        _ => {
+            return Err(ApiError::PreconditionFailed(
+                "tenant must be detached from this pageserver to run a time travel recovery".into(),
+            ))
+        }
+    }
+
+    let state = get_state(&request);
+    let Some(storage) = state.remote_storage.as_ref() else {
+        return Err(ApiError::InternalServerError(anyhow::anyhow!(
+            "remote storage not configured, cannot run time travel"
+        )));
+    };
+    if !storage.supports_versioning() {
+        return Err(ApiError::BadRequest(anyhow::anyhow!(
+            "remote storage does not support versioning, cannot run time travel"
+        )));
+    }
+
+    let prefix = tenant::remote_timeline_client::remote_timelines_path(&tenant_shard_id);
+    storage
+        .time_travel_recover(Some(&prefix), timestamp, &cancel)
+        .instrument(info_span!("tenant_time_travel_remote_storage", tenant_id=%tenant_shard_id.tenant_id, shard=%tenant_shard_id.shard_slug(), travel_to=%timestamp_raw))
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(StatusCode::OK, ())
+}
+
 async fn tenant_reset_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -1249,6 +1292,26 @@ async fn put_tenant_location_config_handler(
     json_response(StatusCode::OK, ())
 }
 
+async fn tenant_shard_split_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let split_req: TenantShardSplitRequest = json_request(&mut request).await?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let state = get_state(&request);
+    let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Warn);
+
+    let new_shards = state
+        .tenant_manager
+        .shard_split(tenant_shard_id, ShardCount(split_req.new_shard_count), &ctx)
+        .await
+        .map_err(ApiError::InternalServerError)?;
+
+    json_response(StatusCode::OK, TenantShardSplitResponse { new_shards })
+}
+
 /// Testing helper to transition a tenant to [`crate::tenant::TenantState::Broken`].
 async fn handle_tenant_break(
     r: Request<Body>,
@@ -1404,6 +1467,75 @@ async fn timeline_download_remote_layers_handler_get(
     json_response(StatusCode::OK, info)
 }
 
+fn timeline_import_response(
+    res: Result<TimelineImportInfo, TimelineImportError>,
+) -> Result<Response<Body>, ApiError> {
+    match res {
+        Ok(info) => json_response(StatusCode::ACCEPTED, info),
+        Err(TimelineImportError::AlreadyRunning(info)) => json_response(StatusCode::CONFLICT, info),
+        Err(e @ TimelineImportError::TimelineExists) => Err(ApiError::Conflict(e.to_string())),
+        Err(TimelineImportError::BadRequest(e)) => Err(ApiError::BadRequest(e)),
+        Err(TimelineImportError::Other(e)) => Err(ApiError::InternalServerError(e)),
+    }
+}
+
+async fn timeline_import_basebackup_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let body: TimelineImportBasebackupRequest = json_request(&mut request).await?;
+    // The source can be any file on the pageserver or in its remote storage, not
+    // just the tenant's
+    check_permission(&request, None)?;
+
+    let state = get_state(&request);
+    let tenant = state
+        .tenant_manager
+        .get_attached_tenant_shard(tenant_shard_id, true)?;
+    let res = tenant
+        .spawn_import_basebackup(timeline_id, body, state.broker_client.clone())
+        .await;
+    timeline_import_response(res)
+}
+
+async fn timeline_import_wal_handler(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let body: TimelineImportWalRequest = json_request(&mut request).await?;
+    // The source can be any file on the pageserver or in its remote storage, not
+    // just the tenant's
+    check_permission(&request, None)?;
+
+    let tenant = get_state(&request)
+        .tenant_manager
+        .get_attached_tenant_shard(tenant_shard_id, true)?;
+    let res = tenant.spawn_import_wal(timeline_id, body).await;
+    timeline_import_response(res)
+}
+
+async fn timeline_import_status_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let tenant = get_state(&request)
+        .tenant_manager
+        .get_attached_tenant_shard(tenant_shard_id, false)?;
+    let info = tenant
+        .get_timeline_import_info(timeline_id)
+        .context("no import into this timeline since the tenant was attached")
+        .map_err(|e| ApiError::NotFound(e.into()))?;
+    json_response(StatusCode::OK, info)
+}
+
 async fn deletion_queue_flush(
     r: Request<Body>,
     cancel: CancellationToken,
@@ -1859,6 +1991,9 @@ pub fn make_router(
         .put("/v1/tenant/:tenant_shard_id/location_config", |r| {
             api_handler(r, put_tenant_location_config_handler)
         })
+        .put("/v1/tenant/:tenant_shard_id/shard_split", |r| {
+            api_handler(r, tenant_shard_split_handler)
+        })
         .get("/v1/tenant/:tenant_shard_id/timeline", |r| {
             api_handler(r, timeline_list_handler)
         })
@@ -1874,6 +2009,10 @@ pub fn make_router(
         .post("/v1/tenant/:tenant_shard_id/reset", |r| {
             api_handler(r, tenant_reset_handler)
         })
+        .put(
+            "/v1/tenant/:tenant_shard_id/time_travel_remote_storage",
+            |r| api_handler(r, tenant_time_travel_remote_storage_handler),
+        )
         .post("/v1/tenant/:tenant_id/load", |r| {
             api_handler(r, tenant_load_handler)
         })
@@ -1911,6 +2050,18 @@ pub fn make_router(
             "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/download_remote_layers",
             |r| api_handler(r, timeline_download_remote_layers_handler_get),
         )
+        .post(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/import_basebackup",
+            |r| api_handler(r, timeline_import_basebackup_handler),
+        )
+        .post(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/import_wal",
+            |r| api_handler(r, timeline_import_wal_handler),
+        )
+        .get(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/import",
+            |r| api_handler(r, timeline_import_status_handler),
+        )
         .delete("/v1/tenant/:tenant_shard_id/timeline/:timeline_id", |r| {
             api_handler(r, timeline_delete_handler)
         })
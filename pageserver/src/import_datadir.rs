@@ -4,6 +4,7 @@
 //!
 use std::io::SeekFrom;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use anyhow::{bail, ensure, Context, Result};
 use async_compression::tokio::bufread::ZstdDecoder;
@@ -27,6 +28,7 @@ use crate::tenant::Timeline;
 use crate::walingest::WalIngest;
 use crate::walrecord::DecodedWALRecord;
 use pageserver_api::reltag::{RelTag, SlruKind};
+use postgres_ffi::checksum_utils::{page_get_checksum, pg_checksum_page};
 use postgres_ffi::pg_constants;
 use postgres_ffi::relfile_utils::*;
 use postgres_ffi::waldecoder::WalStreamDecoder;
@@ -35,7 +37,33 @@ use postgres_ffi::DBState_DB_SHUTDOWNED;
 use postgres_ffi::Oid;
 use postgres_ffi::XLogFileName;
 use postgres_ffi::{BLCKSZ, WAL_SEGMENT_SIZE};
-use utils::lsn::Lsn;
+use utils::lsn::{AtomicLsn, Lsn};
+
+/// How many pages with a bad checksum to log, before only counting them
+const MAX_LOGGED_CHECKSUM_FAILURES: u64 = 10;
+
+/// Progress of a basebackup or WAL import, updated as the import goes, so that it
+/// can be reported while the import is running.
+pub struct ImportProgress {
+    /// Verify the checksums of relation pages. The import fails if any page has a bad
+    /// checksum and the cluster's pg_control says that data checksums are enabled.
+    pub verify_checksums: bool,
+    pub files_imported: AtomicU64,
+    pub checksum_failures: AtomicU64,
+    /// End of the last WAL record imported
+    pub last_record_lsn: AtomicLsn,
+}
+
+impl ImportProgress {
+    pub fn new(verify_checksums: bool) -> Self {
+        ImportProgress {
+            verify_checksums,
+            files_imported: AtomicU64::new(0),
+            checksum_failures: AtomicU64::new(0),
+            last_record_lsn: AtomicLsn::new(0),
+        }
+    }
+}
 
 // Returns checkpoint LSN from controlfile
 pub fn get_lsn_from_controlfile(path: &Utf8Path) -> Result<Lsn> {
@@ -62,6 +90,7 @@ pub async fn import_timeline_from_postgres_datadir(
     ctx: &RequestContext,
 ) -> Result<()> {
     let mut pg_control: Option<ControlFileData> = None;
+    let progress = ImportProgress::new(false);
 
     // TODO this shoud be start_lsn, which is not necessarily equal to end_lsn (aka lsn)
     // Then fishing out pg_control would be unnecessary
@@ -81,8 +110,15 @@ pub async fn import_timeline_from_postgres_datadir(
 
             let mut file = tokio::fs::File::open(absolute_path).await?;
             let len = metadata.len() as usize;
-            if let Some(control_file) =
-                import_file(&mut modification, relative_path, &mut file, len, ctx).await?
+            if let Some(control_file) = import_file(
+                &mut modification,
+                relative_path,
+                &mut file,
+                len,
+                &progress,
+                ctx,
+            )
+            .await?
             {
                 pg_control = Some(control_file);
             }
@@ -120,6 +156,7 @@ pub async fn import_timeline_from_postgres_datadir(
 }
 
 // subroutine of import_timeline_from_postgres_datadir(), to load one relation file.
+#[allow(clippy::too_many_arguments)]
 async fn import_rel(
     modification: &mut DatadirModification<'_>,
     path: &Path,
@@ -127,6 +164,7 @@ async fn import_rel(
     dboid: Oid,
     reader: &mut (impl AsyncRead + Unpin),
     len: usize,
+    progress: &ImportProgress,
     ctx: &RequestContext,
 ) -> anyhow::Result<()> {
     // Does it look like a relation file?
@@ -177,6 +215,20 @@ async fn import_rel(
         let r = reader.read_exact(&mut buf).await;
         match r {
             Ok(_) => {
+                if progress.verify_checksums
+                    && !postgres_ffi::page_is_new(&buf)
+                    && page_get_checksum(&buf) != pg_checksum_page(&buf, blknum)
+                {
+                    let failures = progress.checksum_failures.fetch_add(1, Ordering::Relaxed);
+                    if failures < MAX_LOGGED_CHECKSUM_FAILURES {
+                        warn!(
+                            "checksum mismatch in {} block {blknum}: stored {}, calculated {}",
+                            rel,
+                            page_get_checksum(&buf),
+                            pg_checksum_page(&buf, blknum)
+                        );
+                    }
+                }
                 modification.put_rel_page_image(rel, blknum, Bytes::copy_from_slice(&buf))?;
             }
 
@@ -346,6 +398,7 @@ pub async fn import_basebackup_from_tar(
     tline: &Timeline,
     reader: &mut (impl AsyncRead + Send + Sync + Unpin),
     base_lsn: Lsn,
+    progress: &ImportProgress,
     ctx: &RequestContext,
 ) -> Result<()> {
     info!("importing base at {base_lsn}");
@@ -364,13 +417,15 @@ pub async fn import_basebackup_from_tar(
 
         match header.entry_type() {
             tokio_tar::EntryType::Regular => {
+                let path = file_path.as_ref();
                 if let Some(res) =
-                    import_file(&mut modification, file_path.as_ref(), &mut entry, len, ctx).await?
+                    import_file(&mut modification, path, &mut entry, len, progress, ctx).await?
                 {
                     // We found the pg_control file.
                     pg_control = Some(res);
                 }
                 modification.flush(ctx).await?;
+                progress.files_imported.fetch_add(1, Ordering::Relaxed);
             }
             tokio_tar::EntryType::Directory => {
                 debug!("directory {:?}", file_path);
@@ -386,17 +441,36 @@ pub async fn import_basebackup_from_tar(
     }
 
     // sanity check: ensure that pg_control is loaded
-    let _pg_control = pg_control.context("pg_control file not found")?;
+    let pg_control = pg_control.context("pg_control file not found")?;
+
+    // pg_control is usually the last file in the archive, so only now do we know
+    // whether the checksums of the pages we have seen are meaningful.
+    if progress.verify_checksums {
+        if pg_control.data_checksum_version == 0 {
+            info!("data checksums are not enabled in the cluster, not verifying pages");
+            progress.checksum_failures.store(0, Ordering::Relaxed);
+        } else {
+            let failures = progress.checksum_failures.load(Ordering::Relaxed);
+            ensure!(
+                failures == 0,
+                "{failures} pages failed checksum verification"
+            );
+        }
+    }
 
     modification.commit(ctx).await?;
     Ok(())
 }
 
+/// Import the WAL between `start_lsn` and `end_lsn` from a tar archive of WAL
+/// segments. Segments that end before `start_lsn` are skipped, so that an import
+/// can be resumed with the same archive after a failure.
 pub async fn import_wal_from_tar(
     tline: &Timeline,
     reader: &mut (impl AsyncRead + Send + Sync + Unpin),
     start_lsn: Lsn,
     end_lsn: Lsn,
+    progress: &ImportProgress,
     ctx: &RequestContext,
 ) -> Result<()> {
     // Set up walingest mutable state
@@ -412,10 +486,9 @@ pub async fn import_wal_from_tar(
     let mut pg_wal_entries = pg_wal_tar.entries()?;
     while last_lsn <= end_lsn {
         let bytes = {
-            let mut entry = pg_wal_entries
-                .next()
-                .await
-                .ok_or_else(|| anyhow::anyhow!("expected more wal"))??;
+            let mut entry = pg_wal_entries.next().await.ok_or_else(|| {
+                anyhow::anyhow!("WAL archive ends at {last_lsn}, before end LSN {end_lsn}")
+            })??;
             let header = entry.header();
             let file_path = header.path()?.into_owned();
 
@@ -427,7 +500,15 @@ pub async fn import_wal_from_tar(
                         .file_name()
                         .expect("missing wal filename")
                         .to_string_lossy();
-                    ensure!(expected_filename == file_name);
+                    if file_name.len() == expected_filename.len() && *file_name < *expected_filename
+                    {
+                        debug!("skipping wal file {:?} before start LSN", file_path);
+                        continue;
+                    }
+                    ensure!(
+                        expected_filename == file_name,
+                        "expected wal file {expected_filename}, found {file_name}"
+                    );
 
                     debug!("processing wal file {:?}", file_path);
                     read_all_bytes(&mut entry).await?
@@ -456,12 +537,17 @@ pub async fn import_wal_from_tar(
                     .ingest_record(recdata, lsn, &mut modification, &mut decoded, ctx)
                     .await?;
                 last_lsn = lsn;
+                progress.last_record_lsn.store(lsn);
 
                 debug!("imported record at {} (end {})", lsn, end_lsn);
+            } else {
+                // The rest of the WAL is in the next segment
+                break;
             }
         }
 
         debug!("imported records up to {}", last_lsn);
+        progress.files_imported.fetch_add(1, Ordering::Relaxed);
         segno += 1;
         offset = 0;
     }
@@ -488,6 +574,7 @@ async fn import_file(
     file_path: &Path,
     reader: &mut (impl AsyncRead + Send + Sync + Unpin),
     len: usize,
+    progress: &ImportProgress,
     ctx: &RequestContext,
 ) -> Result<Option<ControlFileData>> {
     let file_name = match file_path.file_name() {
@@ -530,7 +617,17 @@ async fn import_file(
                 debug!("ignored PG_VERSION file");
             }
             _ => {
-                import_rel(modification, file_path, spcnode, dbnode, reader, len, ctx).await?;
+                import_rel(
+                    modification,
+                    file_path,
+                    spcnode,
+                    dbnode,
+                    reader,
+                    len,
+                    progress,
+                    ctx,
+                )
+                .await?;
                 debug!("imported rel creation");
             }
         }
@@ -555,7 +652,17 @@ async fn import_file(
                 debug!("ignored PG_VERSION file");
             }
             _ => {
-                import_rel(modification, file_path, spcnode, dbnode, reader, len, ctx).await?;
+                import_rel(
+                    modification,
+                    file_path,
+                    spcnode,
+                    dbnode,
+                    reader,
+                    len,
+                    progress,
+                    ctx,
+                )
+                .await?;
                 debug!("imported rel creation");
             }
         }
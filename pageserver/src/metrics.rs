@@ -444,6 +444,24 @@ pub(crate) static REMOTE_ONDEMAND_DOWNLOADED_BYTES: Lazy<IntCounter> = Lazy::new
     .unwrap()
 });
 
+pub(crate) static REMOTE_STORAGE_THROTTLED_SECONDS: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "pageserver_remote_storage_throttled_seconds_total",
+        "Time spent waiting for the tenant's remote storage request budget, by request class",
+        &["tenant_id", "shard_id", "class"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static REMOTE_STORAGE_THROTTLED_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_remote_storage_throttled_requests_total",
+        "Number of remote storage requests that waited for the tenant's budget, by request class",
+        &["tenant_id", "shard_id", "class"]
+    )
+    .expect("failed to define a metric")
+});
+
 static CURRENT_LOGICAL_SIZE: Lazy<UIntGaugeVec> = Lazy::new(|| {
     register_uint_gauge_vec!(
         "pageserver_current_logical_size",
@@ -50,7 +50,7 @@ use crate::auth::check_permission;
 use crate::basebackup;
 use crate::config::PageServerConf;
 use crate::context::{DownloadBehavior, RequestContext};
-use crate::import_datadir::import_wal_from_tar;
+use crate::import_datadir::{import_wal_from_tar, ImportProgress};
 use crate::metrics;
 use crate::metrics::LIVE_CONNECTIONS_COUNT;
 use crate::pgdatadir_mapping::rel_block_to_key;
@@ -600,6 +600,7 @@ impl PageServerHandler {
             .import_basebackup_from_tar(
                 &mut copyin_reader,
                 base_lsn,
+                &ImportProgress::new(false),
                 self.broker_client.clone(),
                 &ctx,
             )
@@ -651,7 +652,15 @@ impl PageServerHandler {
         pgb.write_message_noflush(&BeMessage::CopyInResponse)?;
         self.flush_cancellable(pgb, &timeline.cancel).await?;
         let mut copyin_reader = pin!(StreamReader::new(self.copyin_stream(pgb, &timeline.cancel)));
-        import_wal_from_tar(&timeline, &mut copyin_reader, start_lsn, end_lsn, &ctx).await?;
+        import_wal_from_tar(
+            &timeline,
+            &mut copyin_reader,
+            start_lsn,
+            end_lsn,
+            &ImportProgress::new(false),
+            &ctx,
+        )
+        .await?;
         info!("wal import complete");
 
         // Read the end of the tar archive.
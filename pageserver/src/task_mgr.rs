@@ -289,6 +289,10 @@ pub enum TaskKind {
 
     // task that drives downloading layers
     DownloadAllRemoteLayers,
+
+    // task that imports a basebackup or WAL archive, started via the management API
+    TimelineImport,
+
     // Task that calculates synthetis size for all active tenants
     CalculateSyntheticSize,
 
@@ -22,6 +22,7 @@ use pageserver_api::shard::ShardIdentity;
 use pageserver_api::shard::TenantShardId;
 use remote_storage::DownloadError;
 use remote_storage::GenericRemoteStorage;
+use remote_storage::TimeoutOrCancel;
 use std::fmt;
 use storage_broker::BrokerClientChannel;
 use tokio::io::BufReader;
@@ -40,6 +41,7 @@ use utils::sync::gate::GateGuard;
 use self::config::AttachedLocationConfig;
 use self::config::AttachmentMode;
 use self::config::LocationConf;
+use self::config::RemoteStorageThrottleConfig;
 use self::config::TenantConf;
 use self::delete::DeleteTenantFlow;
 use self::metadata::LoadMetadataError;
@@ -47,6 +49,7 @@ use self::metadata::TimelineMetadata;
 use self::mgr::GetActiveTenantError;
 use self::mgr::GetTenantError;
 use self::mgr::TenantsMap;
+use self::remote_timeline_client::throttle::RemoteStorageThrottle;
 use self::remote_timeline_client::RemoteTimelineClient;
 use self::timeline::uninit::TimelineUninitMark;
 use self::timeline::uninit::UninitializedTimeline;
@@ -67,6 +70,7 @@ use crate::tenant::config::LocationMode;
 use crate::tenant::config::TenantConfOpt;
 use crate::tenant::metadata::load_metadata;
 pub use crate::tenant::remote_timeline_client::index::IndexPart;
+use crate::tenant::remote_timeline_client::split_cleanup::{self, SplitCleanupMode};
 use crate::tenant::remote_timeline_client::MaybeDeletedIndexPart;
 use crate::tenant::remote_timeline_client::INITDB_PATH;
 use crate::tenant::storage_layer::DeltaLayer;
@@ -149,6 +153,7 @@ pub mod tasks;
 pub mod upload_queue;
 
 pub(crate) mod timeline;
+pub(crate) mod timeline_import;
 
 pub mod size;
 
@@ -264,10 +269,16 @@ pub struct Tenant {
     // Access to global deletion queue for when this tenant wants to schedule a deletion
     deletion_queue_client: DeletionQueueClient,
 
+    /// Budgets of the remote storage requests, shared by the timelines' remote clients.
+    pub(crate) remote_storage_throttle: Arc<RemoteStorageThrottle>,
+
     /// Cached logical sizes updated updated on each [`Tenant::gather_size_inputs`].
     cached_logical_sizes: tokio::sync::Mutex<HashMap<(TimelineId, Lsn), u64>>,
     cached_synthetic_tenant_size: Arc<AtomicU64>,
 
+    /// The latest import started via the management API, per timeline.
+    import_jobs: Mutex<timeline_import::TimelineImportJobs>,
+
     eviction_task_tenant_state: tokio::sync::Mutex<EvictionTaskTenantState>,
 
     pub(crate) delete_progress: Arc<tokio::sync::Mutex<DeleteTenantFlow>>,
@@ -1211,6 +1222,7 @@ impl Tenant {
             let client = RemoteTimelineClient::new(
                 remote_storage.clone(),
                 self.deletion_queue_client.clone(),
+                self.remote_storage_throttle.clone(),
                 self.conf,
                 self.tenant_shard_id,
                 timeline_id,
@@ -2273,8 +2285,17 @@ impl Tenant {
         }
     }
 
+    fn get_remote_storage_throttle(&self) -> RemoteStorageThrottleConfig {
+        let tenant_conf = self.tenant_conf.read().unwrap().tenant_conf;
+        tenant_conf
+            .remote_storage_throttle
+            .unwrap_or(self.conf.default_tenant_conf.remote_storage_throttle)
+    }
+
     pub fn set_new_tenant_config(&self, new_tenant_conf: TenantConfOpt) {
         self.tenant_conf.write().unwrap().tenant_conf = new_tenant_conf;
+        self.remote_storage_throttle
+            .reconfigure(self.get_remote_storage_throttle());
         // Don't hold self.timelines.lock() during the notifies.
         // There's no risk of deadlock right now, but there could be if we consolidate
         // mutexes in struct Timeline in the future.
@@ -2286,6 +2307,8 @@ impl Tenant {
 
     pub(crate) fn set_new_location_config(&self, new_conf: AttachedTenantConf) {
         *self.tenant_conf.write().unwrap() = new_conf;
+        self.remote_storage_throttle
+            .reconfigure(self.get_remote_storage_throttle());
         // Don't hold self.timelines.lock() during the notifies.
         // There's no risk of deadlock right now, but there could be if we consolidate
         // mutexes in struct Timeline in the future.
@@ -2412,6 +2435,16 @@ impl Tenant {
             }
         });
 
+        let cancel = CancellationToken::default();
+        let remote_storage_throttle = Arc::new(RemoteStorageThrottle::new(
+            tenant_shard_id,
+            attached_conf
+                .tenant_conf
+                .remote_storage_throttle
+                .unwrap_or(conf.default_tenant_conf.remote_storage_throttle),
+            cancel.clone(),
+        ));
+
         Tenant {
             tenant_shard_id,
             shard_identity,
@@ -2426,12 +2459,14 @@ impl Tenant {
             walredo_mgr,
             remote_storage,
             deletion_queue_client,
+            remote_storage_throttle,
             state,
             cached_logical_sizes: tokio::sync::Mutex::new(HashMap::new()),
             cached_synthetic_tenant_size: Arc::new(AtomicU64::new(0)),
+            import_jobs: Mutex::new(HashMap::new()),
             eviction_task_tenant_state: tokio::sync::Mutex::new(EvictionTaskTenantState::default()),
             delete_progress: Arc::new(tokio::sync::Mutex::new(DeleteTenantFlow::default())),
-            cancel: CancellationToken::default(),
+            cancel,
             gate: Gate::new(format!("Tenant<{tenant_shard_id}>")),
         }
     }
@@ -3001,6 +3036,7 @@ impl Tenant {
                     storage,
                     &self.tenant_shard_id,
                     &existing_initdb_timeline_id,
+                    &self.cancel,
                 )
                 .await
                 .context("download initdb tar")?;
@@ -3041,15 +3077,15 @@ impl Tenant {
                             &timeline_id,
                             pgdata_zstd.try_clone().await?,
                             tar_zst_size,
+                            &self.cancel,
                         )
                         .await
                     },
-                    |_| false,
+                    TimeoutOrCancel::caused_by_cancel,
                     3,
                     u32::MAX,
                     "persist_initdb_tar_zst",
-                    // TODO: use a cancellation token (https://github.com/neondatabase/neon/issues/5066)
-                    backoff::Cancel::new(CancellationToken::new(), || unreachable!()),
+                    backoff::Cancel::new(self.cancel.clone(), || anyhow::anyhow!("Cancelled")),
                 )
                 .await?;
 
@@ -3142,6 +3178,7 @@ impl Tenant {
             let remote_client = RemoteTimelineClient::new(
                 remote_storage.clone(),
                 self.deletion_queue_client.clone(),
+                self.remote_storage_throttle.clone(),
                 self.conf,
                 self.tenant_shard_id,
                 timeline_id,
@@ -3408,6 +3445,92 @@ impl Tenant {
 
         Ok(())
     }
+
+    /// First phase of a shard split: write each timeline's index for each of the
+    /// `child_shards`, referencing our layers.
+    ///
+    /// This stops the timelines' remote clients, so that nothing we do afterwards can
+    /// delete a layer that the children's indices reference: the caller must replace
+    /// this tenant with its children, or call [`Self::split_abort`] and restart it.
+    #[tracing::instrument(skip_all)]
+    pub(crate) async fn split_prepare(&self, child_shards: &[TenantShardId]) -> anyhow::Result<()> {
+        let timelines = self.timelines.lock().unwrap().clone();
+        for timeline in timelines.values() {
+            let Some(remote_client) = &timeline.remote_client else {
+                anyhow::bail!("Remote storage is required to split a shard");
+            };
+
+            // Write out everything we have, so that the children's indices are as
+            // fresh as possible.
+            remote_client.schedule_index_upload_for_file_changes()?;
+            remote_client.wait_completion().await?;
+            remote_client.shutdown().await?;
+
+            // Use the index that is really in remote storage, rather than our in-memory
+            // idea of it: downloads work after the remote client has shut down.
+            let index_part = match remote_client
+                .download_index_file(self.cancel.clone())
+                .await?
+            {
+                MaybeDeletedIndexPart::IndexPart(index_part) => index_part,
+                MaybeDeletedIndexPart::Deleted(_) => {
+                    anyhow::bail!("Timeline {} was deleted during split", timeline.timeline_id)
+                }
+            };
+
+            tracing::info!(
+                timeline_id = %timeline.timeline_id,
+                "Writing indices for {} child shards",
+                child_shards.len()
+            );
+            remote_client
+                .upload_child_indices(&index_part, child_shards, &self.cancel)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Undo [`Self::split_prepare`] for a split that did not go through: delete the
+    /// children's remote objects, so that nothing refers to our layers behind our back.
+    /// Failures are logged: a retried split overwrites the children's indices anyway.
+    #[tracing::instrument(skip_all)]
+    pub(crate) async fn split_abort(&self, child_shards: &[TenantShardId]) {
+        let timelines = self.timelines.lock().unwrap().clone();
+        for timeline in timelines.values() {
+            let Some(remote_client) = &timeline.remote_client else {
+                continue;
+            };
+            if let Err(e) = remote_client
+                .delete_child_objects(child_shards, &self.cancel)
+                .await
+            {
+                tracing::warn!(
+                    timeline_id = %timeline.timeline_id,
+                    "Failed to delete child shard objects of aborted split: {e:#}"
+                );
+            }
+        }
+    }
+
+    /// Delete the remote layers of the shards that we were split from, once none of their
+    /// descendants reference them any more.
+    pub(crate) async fn cleanup_split_ancestors(
+        &self,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let Some(remote_storage) = &self.remote_storage else {
+            return Ok(());
+        };
+        split_cleanup::cleanup_split_ancestors(
+            remote_storage,
+            &self.deletion_queue_client,
+            &self.tenant_shard_id,
+            SplitCleanupMode::Background,
+            cancel,
+        )
+        .await
+    }
 }
 
 fn remove_timeline_and_uninit_mark(
@@ -3716,6 +3839,7 @@ pub(crate) mod harness {
                 ),
                 gc_feedback: Some(tenant_conf.gc_feedback),
                 heatmap_period: Some(tenant_conf.heatmap_period),
+                remote_storage_throttle: Some(tenant_conf.remote_storage_throttle),
             }
         }
     }
@@ -3779,11 +3903,13 @@ pub(crate) mod harness {
             fs::create_dir_all(conf.tenant_path(&tenant_shard_id))?;
             fs::create_dir_all(conf.timelines_path(&tenant_shard_id))?;
 
-            use remote_storage::{RemoteStorageConfig, RemoteStorageKind};
+            use remote_storage::{LocalFsConfig, RemoteStorageConfig, RemoteStorageKind};
             let remote_fs_dir = conf.workdir.join("localfs");
             std::fs::create_dir_all(&remote_fs_dir).unwrap();
             let config = RemoteStorageConfig {
-                storage: RemoteStorageKind::LocalFs(remote_fs_dir.clone()),
+                storage: RemoteStorageKind::LocalFs(LocalFsConfig::new(remote_fs_dir.clone())),
+                timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+                small_timeout: RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
             };
             let remote_storage = GenericRemoteStorage::from_config(&config).unwrap();
             let deletion_queue = MockDeletionQueue::new(Some(remote_storage.clone()));
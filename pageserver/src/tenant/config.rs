@@ -339,6 +339,9 @@ pub struct TenantConf {
     /// may be disabled if a Tenant will not have secondary locations: only secondary
     /// locations will use the heatmap uploaded by attached locations.
     pub heatmap_period: Duration,
+
+    /// Per-tenant budgets for the remote storage requests, see [`RemoteStorageThrottleConfig`].
+    pub remote_storage_throttle: RemoteStorageThrottleConfig,
 }
 
 /// Same as TenantConf, but this struct preserves the information about
@@ -424,6 +427,10 @@ pub struct TenantConfOpt {
     #[serde(with = "humantime_serde")]
     #[serde(default)]
     pub heatmap_period: Option<Duration>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub remote_storage_throttle: Option<RemoteStorageThrottleConfig>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -450,6 +457,31 @@ pub struct EvictionPolicyLayerAccessThreshold {
     pub threshold: Duration,
 }
 
+/// Budgets of a tenant's remote storage requests, separately for every priority class, so that
+/// e.g. a mass download of layers in the background can not eat up the budget of the on-demand
+/// downloads that page requests are waiting for.  Budgets that are not set are not enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct RemoteStorageThrottleConfig {
+    #[serde(default)]
+    pub on_demand_download: RemoteStorageThrottleBudget,
+    #[serde(default)]
+    pub upload: RemoteStorageThrottleBudget,
+    #[serde(default)]
+    pub background_download: RemoteStorageThrottleBudget,
+}
+
+/// Request rate and bandwidth allowed for a priority class.  Requests may burst up to a
+/// second's worth of the budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct RemoteStorageThrottleBudget {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub ops_per_second: Option<NonZeroU64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub bytes_per_second: Option<NonZeroU64>,
+}
+
 impl TenantConfOpt {
     pub fn merge(&self, global_conf: TenantConf) -> TenantConf {
         TenantConf {
@@ -493,6 +525,9 @@ impl TenantConfOpt {
                 .unwrap_or(global_conf.evictions_low_residence_duration_metric_threshold),
             gc_feedback: self.gc_feedback.unwrap_or(global_conf.gc_feedback),
             heatmap_period: self.heatmap_period.unwrap_or(global_conf.heatmap_period),
+            remote_storage_throttle: self
+                .remote_storage_throttle
+                .unwrap_or(global_conf.remote_storage_throttle),
         }
     }
 }
@@ -531,6 +566,7 @@ impl Default for TenantConf {
             .expect("cannot parse default evictions_low_residence_duration_metric_threshold"),
             gc_feedback: false,
             heatmap_period: Duration::ZERO,
+            remote_storage_throttle: RemoteStorageThrottleConfig::default(),
         }
     }
 }
@@ -628,4 +664,29 @@ mod tests {
             Some(Duration::from_secs(5))
         );
     }
+
+    #[test]
+    fn test_try_from_models_tenant_config_remote_storage_throttle() {
+        let tenant_config = models::TenantConfig {
+            remote_storage_throttle: Some(serde_json::json!({
+                "on_demand_download": { "ops_per_second": 100 },
+                "background_download": { "ops_per_second": 10, "bytes_per_second": 10485760 },
+            })),
+            ..TenantConfig::default()
+        };
+
+        let tenant_conf_opt = TenantConfOpt::try_from(&tenant_config).unwrap();
+
+        let throttle = tenant_conf_opt.remote_storage_throttle.unwrap();
+        assert_eq!(
+            throttle.on_demand_download.ops_per_second,
+            NonZeroU64::new(100)
+        );
+        assert_eq!(throttle.on_demand_download.bytes_per_second, None);
+        assert_eq!(throttle.upload, RemoteStorageThrottleBudget::default());
+        assert_eq!(
+            throttle.background_download.bytes_per_second,
+            NonZeroU64::new(10 * 1024 * 1024)
+        );
+    }
 }
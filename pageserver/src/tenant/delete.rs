@@ -19,7 +19,10 @@ use crate::{
 
 use super::{
     mgr::{GetTenantError, TenantSlotError, TenantSlotUpsertError, TenantsMap},
-    remote_timeline_client::{FAILED_REMOTE_OP_RETRIES, FAILED_UPLOAD_WARN_THRESHOLD},
+    remote_timeline_client::{
+        split_cleanup::{cleanup_split_ancestors, SplitCleanupMode},
+        FAILED_REMOTE_OP_RETRIES, FAILED_UPLOAD_WARN_THRESHOLD,
+    },
     span,
     timeline::delete::DeleteTimelineFlow,
     tree_sort_timelines, DeleteTimelineError, Tenant, TenantPreload,
@@ -75,20 +78,22 @@ async fn create_remote_delete_mark(
     let remote_mark_path = remote_tenant_delete_mark_path(conf, tenant_shard_id)?;
 
     let data: &[u8] = &[];
+    // Deletion runs after the tenant has been shut down, so there is no tenant token to use here.
+    // TODO: use a cancellation token (https://github.com/neondatabase/neon/issues/5066)
+    let cancel = CancellationToken::new();
     backoff::retry(
         || async {
             let data = bytes::Bytes::from_static(data);
             let stream = futures::stream::once(futures::future::ready(Ok(data)));
             remote_storage
-                .upload(stream, 0, &remote_mark_path, None)
+                .upload(stream, 0, &remote_mark_path, None, &cancel)
                 .await
         },
         |_e| false,
         FAILED_UPLOAD_WARN_THRESHOLD,
         FAILED_REMOTE_OP_RETRIES,
         "mark_upload",
-        // TODO: use a cancellation token (https://github.com/neondatabase/neon/issues/5066)
-        backoff::Cancel::new(CancellationToken::new(), || unreachable!()),
+        backoff::Cancel::new(cancel.clone(), || unreachable!()),
     )
     .await
     .context("mark_upload")?;
@@ -173,14 +178,15 @@ async fn remove_tenant_remote_delete_mark(
 ) -> Result<(), DeleteTenantError> {
     if let Some(remote_storage) = remote_storage {
         let path = remote_tenant_delete_mark_path(conf, tenant_shard_id)?;
+        // TODO: use a cancellation token (https://github.com/neondatabase/neon/issues/5066)
+        let cancel = CancellationToken::new();
         backoff::retry(
-            || async { remote_storage.delete(&path).await },
+            || async { remote_storage.delete(&path, &cancel).await },
             |_e| false,
             FAILED_UPLOAD_WARN_THRESHOLD,
             FAILED_REMOTE_OP_RETRIES,
             "remove_tenant_remote_delete_mark",
-            // TODO: use a cancellation token (https://github.com/neondatabase/neon/issues/5066)
-            backoff::Cancel::new(CancellationToken::new(), || unreachable!()),
+            backoff::Cancel::new(cancel.clone(), || unreachable!()),
         )
         .await
         .context("remove_tenant_remote_delete_mark")?;
@@ -524,6 +530,20 @@ impl DeleteTenantFlow {
                 .context("timelines dir not empty")?;
         }
 
+        // With our timelines gone, the layers of the shards we were split from may no longer
+        // be referenced: the last shard of the tenant to get here deletes them.
+        if let Some(remote_storage) = &remote_storage {
+            cleanup_split_ancestors(
+                remote_storage,
+                &tenant.deletion_queue_client,
+                &tenant.tenant_shard_id,
+                SplitCleanupMode::TenantDeletion,
+                &task_mgr::shutdown_token(),
+            )
+            .await
+            .context("cleanup_split_ancestors")?;
+        }
+
         remove_tenant_remote_delete_mark(conf, remote_storage.as_ref(), &tenant.tenant_shard_id)
             .await?;
 
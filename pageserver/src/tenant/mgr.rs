@@ -3,7 +3,7 @@
 
 use camino::{Utf8DirEntry, Utf8Path, Utf8PathBuf};
 use pageserver_api::key::Key;
-use pageserver_api::shard::{ShardIdentity, ShardNumber, TenantShardId};
+use pageserver_api::shard::{ShardCount, ShardIdentity, ShardNumber, TenantShardId};
 use rand::{distributions::Alphanumeric, Rng};
 use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
@@ -1094,6 +1094,219 @@ impl TenantManager {
         Ok(())
     }
 
+    /// Split an attached tenant shard into child shards with `new_shard_count`, which must
+    /// be a multiple of the shard's current count.  The children are attached here in the
+    /// parent's generation, and the parent is shut down and removed.
+    ///
+    /// The children start with the parent's layers, referenced in their remote indices by
+    /// the parent's shard index: they shed the keys that don't belong to them as they
+    /// compact, and the parent's layers are deleted once no child references them, see
+    /// [`Tenant::cleanup_split_ancestors`].
+    ///
+    /// If the split fails, the children are shut down and the parent carries on as before.
+    #[instrument(skip_all, fields(tenant_id=%tenant_shard_id.tenant_id, shard_id=%tenant_shard_id.shard_slug(), new_shard_count=%new_shard_count.0))]
+    pub(crate) async fn shard_split(
+        &self,
+        tenant_shard_id: TenantShardId,
+        new_shard_count: ShardCount,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Vec<TenantShardId>> {
+        let tenant = self.get_attached_tenant_shard(tenant_shard_id, true)?;
+
+        let effective_old_shard_count = std::cmp::max(tenant_shard_id.shard_count.0, 1);
+        if new_shard_count.0 <= effective_old_shard_count
+            || new_shard_count.0 % effective_old_shard_count != 0
+        {
+            anyhow::bail!(
+                "Shard count {} is not a multiple of the current count {}",
+                new_shard_count.0,
+                effective_old_shard_count
+            );
+        }
+
+        let child_shards = tenant_shard_id.split(new_shard_count);
+        tracing::info!(
+            "Splitting into {}",
+            child_shards
+                .iter()
+                .map(|s| s.shard_slug().to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+
+        let parent_location_conf = LocationConf {
+            mode: LocationMode::Attached(AttachedLocationConfig {
+                generation: tenant.generation,
+                attach_mode: AttachmentMode::Single,
+            }),
+            shard: tenant.shard_identity,
+            tenant_conf: tenant.tenant_specific_overrides(),
+        };
+
+        // Phase 1: write the children's remote indices.  This stops the parent's uploads:
+        // on failure, restart the parent so that it carries on as before, and the split
+        // may be retried.
+        if let Err(e) = tenant.split_prepare(&child_shards).await {
+            tracing::warn!("Failed to prepare split, resetting parent: {e:#}");
+            tenant.split_abort(&child_shards).await;
+            drop(tenant);
+            self.reset_split_parent(tenant_shard_id, ctx).await;
+            return Err(e);
+        }
+
+        // Phase 2: hold the parent's slot while the children start, so that nothing else
+        // can reconfigure it under us.
+        let mut parent_slot_guard =
+            match tenant_map_acquire_slot(&tenant_shard_id, TenantSlotAcquireMode::Any) {
+                Ok(guard) => guard,
+                Err(e) => {
+                    tenant.split_abort(&child_shards).await;
+                    return Err(e.into());
+                }
+            };
+        let parent = match parent_slot_guard.get_old_value() {
+            Some(TenantSlot::Attached(t)) if Arc::ptr_eq(t, &tenant) => Some(t.clone()),
+            _ => None,
+        };
+        let Some(parent) = parent else {
+            // Reconfigured or detached while we were writing indices
+            parent_slot_guard.revert();
+            tenant.split_abort(&child_shards).await;
+            anyhow::bail!("Parent shard is no longer attached");
+        };
+        drop(tenant);
+
+        // Phase 3: attach the children.  They load from the indices written above, and
+        // resume WAL ingest from the parent's remote_consistent_lsn.
+        let mut attached_children = Vec::new();
+        let attach_result = async {
+            for child_shard in &child_shards {
+                let shard = ShardIdentity::new(
+                    child_shard.shard_number,
+                    child_shard.shard_count,
+                    parent_location_conf.shard.stripe_size,
+                )?;
+                let location_conf = LocationConf {
+                    mode: parent_location_conf.mode.clone(),
+                    shard,
+                    tenant_conf: parent_location_conf.tenant_conf,
+                };
+                // A failed attach may leave the child's local directory behind
+                attached_children.push(*child_shard);
+                self.upsert_location(*child_shard, location_conf, None, ctx)
+                    .await?;
+            }
+            fail::fail_point!("shard-split-post-child-attach", |_| Err(anyhow::anyhow!(
+                "failpoint: shard-split-post-child-attach"
+            )));
+            Ok::<(), anyhow::Error>(())
+        }
+        .await;
+        if let Err(e) = attach_result {
+            tracing::warn!("Failed to attach children, rolling back split: {e:#}");
+            self.detach_split_children(&attached_children).await;
+            parent.split_abort(&child_shards).await;
+            drop(parent);
+            parent_slot_guard.revert();
+            self.reset_split_parent(tenant_shard_id, ctx).await;
+            return Err(e);
+        }
+
+        // Phase 4: shut down the parent and remove its local state.  Its layers live on in
+        // remote storage, referenced by the children.
+        let (_guard, progress) = utils::completion::channel();
+        match parent.shutdown(progress, false).await {
+            Ok(()) => {}
+            Err(barrier) => {
+                info!("Shutdown already in progress, waiting for it to complete");
+                barrier.wait().await;
+            }
+        }
+        drop(parent);
+        parent_slot_guard.drop_old_value()?;
+
+        // The parent's directory must go before its slot is released: if it was left behind,
+        // the parent would be loaded again on restart, next to its children.  Renaming is
+        // enough for that, the removal can happen in the background.
+        let parent_path = self.conf.tenant_path(&tenant_shard_id);
+        let tmp_path = match safe_rename_tenant_dir(&parent_path).await {
+            Ok(tmp_path) => tmp_path,
+            Err(e) => {
+                tracing::warn!("Failed to remove parent directory, rolling back split: {e:#}");
+                self.detach_split_children(&child_shards).await;
+                drop(parent_slot_guard);
+                if let Err(e) = self
+                    .upsert_location(tenant_shard_id, parent_location_conf, None, ctx)
+                    .await
+                {
+                    tracing::error!("Failed to re-attach parent after failed split: {e:#}");
+                }
+                return Err(anyhow::Error::new(e)
+                    .context(format!("Removing parent shard directory {parent_path}")));
+            }
+        };
+        task_mgr::spawn(
+            task_mgr::BACKGROUND_RUNTIME.handle(),
+            TaskKind::MgmtRequest,
+            None,
+            None,
+            "split_parent_files_delete",
+            false,
+            async move {
+                fs::remove_dir_all(tmp_path.as_path())
+                    .await
+                    .with_context(|| format!("split parent directory {tmp_path} deletion"))
+            },
+        );
+
+        // Dropping the guard removes the parent's now-empty slot
+        drop(parent_slot_guard);
+
+        Ok(child_shards)
+    }
+
+    /// Restart the parent of a failed split, whose remote clients `split_prepare` shut down.
+    async fn reset_split_parent(&self, tenant_shard_id: TenantShardId, ctx: &RequestContext) {
+        if let Err(e) = self
+            .reset_tenant(
+                tenant_shard_id,
+                false,
+                ctx.detached_child(TaskKind::MgmtRequest, DownloadBehavior::Warn),
+            )
+            .await
+        {
+            tracing::error!("Failed to reset parent after failed split: {e:#}");
+        }
+    }
+
+    /// Shut down and remove the children of a failed split.  Children that failed to attach
+    /// are not in the tenant map, but may have a local directory.
+    async fn detach_split_children(&self, child_shards: &[TenantShardId]) {
+        for child_shard in child_shards {
+            match detach_tenant(
+                self.conf,
+                *child_shard,
+                false,
+                &self.resources.deletion_queue_client,
+            )
+            .await
+            {
+                Ok(()) => {}
+                Err(TenantStateError::SlotError(TenantSlotError::NotFound(_))) => {
+                    let child_path = self.conf.tenant_path(child_shard);
+                    if child_path.exists() {
+                        if let Err(e) = safe_remove_tenant_dir_all(&child_path).await {
+                            tracing::error!("Failed to remove child directory {child_path}: {e}");
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Failed to detach child {child_shard} of failed split: {e:#}");
+                }
+            }
+        }
+    }
+
     pub(crate) fn get_attached_active_tenant_shards(&self) -> Vec<Arc<Tenant>> {
         let locked = self.tenants.read().unwrap();
         match &*locked {
@@ -182,6 +182,8 @@
 
 pub(crate) mod download;
 pub mod index;
+pub(crate) mod split_cleanup;
+pub(crate) mod throttle;
 mod upload;
 
 use anyhow::Context;
@@ -201,7 +203,7 @@ use std::collections::{HashMap, VecDeque};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
-use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
+use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath, TimeoutOrCancel};
 use std::ops::DerefMut;
 use tracing::{debug, error, info, instrument, warn};
 use tracing::{info_span, Instrument};
@@ -232,6 +234,7 @@ use crate::{
 use utils::id::{TenantId, TimelineId};
 
 use self::index::IndexPart;
+use self::throttle::{RemoteStorageThrottle, RequestClass};
 
 use super::storage_layer::{Layer, LayerFileName, ResidentLayer};
 use super::upload_queue::SetDeletedFlagProgress;
@@ -316,6 +319,13 @@ pub struct RemoteTimelineClient {
     storage_impl: GenericRemoteStorage,
 
     deletion_queue_client: DeletionQueueClient,
+
+    /// The tenant's remote storage request budgets
+    throttle: Arc<RemoteStorageThrottle>,
+
+    /// Cancelled by [`RemoteTimelineClient::stop`], so that in-progress uploads
+    /// do not hold up the shutdown of the timeline.
+    cancel: CancellationToken,
 }
 
 impl RemoteTimelineClient {
@@ -328,6 +338,7 @@ impl RemoteTimelineClient {
     pub fn new(
         remote_storage: GenericRemoteStorage,
         deletion_queue_client: DeletionQueueClient,
+        throttle: Arc<RemoteStorageThrottle>,
         conf: &'static PageServerConf,
         tenant_shard_id: TenantShardId,
         timeline_id: TimelineId,
@@ -346,11 +357,13 @@ impl RemoteTimelineClient {
             generation,
             storage_impl: remote_storage,
             deletion_queue_client,
+            throttle,
             upload_queue: Mutex::new(UploadQueue::Uninitialized),
             metrics: Arc::new(RemoteTimelineClientMetrics::new(
                 &tenant_shard_id,
                 &timeline_id,
             )),
+            cancel: CancellationToken::new(),
         }
     }
 
@@ -469,6 +482,12 @@ impl RemoteTimelineClient {
             },
         );
 
+        // The tenant cannot serve any reads before its indices are loaded.
+        self.throttle
+            .throttle(RequestClass::OnDemandDownload, 0)
+            .await
+            .map_err(|_| DownloadError::Cancelled)?;
+
         let index_part = download::download_index_part(
             &self.storage_impl,
             &self.tenant_shard_id,
@@ -492,6 +511,67 @@ impl RemoteTimelineClient {
         }
     }
 
+    /// Upload `index_part` as this timeline's index in each of `child_shards`, in our
+    /// generation.  This is how a shard split hands its layers to the children: their
+    /// indices reference the layers by our shard index, so nothing is copied.
+    ///
+    /// This does not go through the upload queue: the caller must make sure that nothing
+    /// is uploaded or deleted concurrently, e.g. by shutting the queue down first.
+    pub(crate) async fn upload_child_indices(
+        &self,
+        index_part: &IndexPart,
+        child_shards: &[TenantShardId],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        for child_shard in child_shards {
+            upload::upload_index_part(
+                &self.storage_impl,
+                child_shard,
+                &self.timeline_id,
+                self.generation,
+                index_part,
+                cancel,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Undo [`Self::upload_child_indices`] for a split that did not go through: delete
+    /// everything the children have in this timeline, including anything they uploaded
+    /// while they were attached.
+    pub(crate) async fn delete_child_objects(
+        &self,
+        child_shards: &[TenantShardId],
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let mut objects = Vec::new();
+        for child_shard in child_shards {
+            let timeline_path = remote_timeline_path(child_shard, &self.timeline_id);
+            let child_objects = backoff::retry(
+                || async {
+                    self.storage_impl
+                        .list_files(Some(&timeline_path), cancel)
+                        .await
+                },
+                TimeoutOrCancel::caused_by_cancel,
+                FAILED_DOWNLOAD_WARN_THRESHOLD,
+                FAILED_REMOTE_OP_RETRIES,
+                "list child shard objects",
+                backoff::Cancel::new(cancel.clone(), || anyhow::anyhow!("Cancelled")),
+            )
+            .await
+            .with_context(|| format!("list objects of child shard {child_shard}"))?;
+            objects.extend(child_objects);
+        }
+
+        self.deletion_queue_client.push_immediate(objects).await?;
+        self.deletion_queue_client.flush_immediate().await?;
+
+        Ok(())
+    }
+
     /// Download a (layer) file from `path`, into local filesystem.
     ///
     /// 'layer_metadata' is the metadata from the remote index file.
@@ -501,7 +581,13 @@ impl RemoteTimelineClient {
         &self,
         layer_file_name: &LayerFileName,
         layer_metadata: &LayerFileMetadata,
+        class: RequestClass,
+        cancel: &CancellationToken,
     ) -> anyhow::Result<u64> {
+        self.throttle
+            .throttle(class, layer_metadata.file_size())
+            .await?;
+
         let downloaded_size = {
             let _unfinished_gauge_guard = self.metrics.call_begin(
                 &RemoteOpFileKind::Layer,
@@ -517,6 +603,7 @@ impl RemoteTimelineClient {
                 self.timeline_id,
                 layer_file_name,
                 layer_metadata,
+                cancel,
             )
             .measure_remote_op(
                 self.tenant_shard_id.tenant_id,
@@ -772,17 +859,8 @@ impl RemoteTimelineClient {
     fn schedule_deletion_of_unlinked0(
         self: &Arc<Self>,
         upload_queue: &mut UploadQueueInitialized,
-        with_metadata: Vec<(LayerFileName, LayerFileMetadata)>,
+        mut with_metadata: Vec<(LayerFileName, LayerFileMetadata)>,
     ) {
-        for (name, meta) in &with_metadata {
-            info!(
-                "scheduling deletion of layer {}{} (shard {})",
-                name,
-                meta.generation.get_suffix(),
-                meta.shard
-            );
-        }
-
         #[cfg(feature = "testing")]
         for (name, meta) in &with_metadata {
             let gen = meta.generation;
@@ -797,6 +875,31 @@ impl RemoteTimelineClient {
             }
         }
 
+        // Layers written by some ancestor shard before a split are referenced by all of
+        // that shard's children: unlinking them from our index is fine, but they are only
+        // deleted by `split_cleanup`, once none of the children reference them.
+        let our_shard = self.tenant_shard_id.to_index();
+        with_metadata.retain(|(name, meta)| {
+            if meta.shard != our_shard {
+                debug!(
+                    "not deleting layer {name} owned by ancestor shard {}",
+                    meta.shard
+                );
+                false
+            } else {
+                true
+            }
+        });
+
+        for (name, meta) in &with_metadata {
+            info!(
+                "scheduling deletion of layer {}{} (shard {})",
+                name,
+                meta.generation.get_suffix(),
+                meta.shard
+            );
+        }
+
         // schedule the actual deletions
         let op = UploadOp::Delete(Delete {
             layers: with_metadata,
@@ -963,6 +1066,9 @@ impl RemoteTimelineClient {
 
         pausable_failpoint!("persist_deleted_index_part");
 
+        // The queue is stopped by now, which has cancelled `self.cancel`.
+        // TODO: use a cancellation token (https://github.com/neondatabase/neon/issues/5066)
+        let cancel = CancellationToken::new();
         backoff::retry(
             || {
                 upload::upload_index_part(
@@ -971,6 +1077,7 @@ impl RemoteTimelineClient {
                     &self.timeline_id,
                     self.generation,
                     &index_part_with_deleted_at,
+                    &cancel,
                 )
             },
             |_e| false,
@@ -980,8 +1087,7 @@ impl RemoteTimelineClient {
             // when executed as part of tenant deletion this happens in the background
             2,
             "persist_index_part_with_deleted_flag",
-            // TODO: use a cancellation token (https://github.com/neondatabase/neon/issues/5066)
-            backoff::Cancel::new(CancellationToken::new(), || unreachable!()),
+            backoff::Cancel::new(cancel.clone(), || unreachable!()),
         )
         .await?;
 
@@ -1019,10 +1125,14 @@ impl RemoteTimelineClient {
 
             debug_assert!(stopped.upload_queue_for_deletion.no_pending_work());
 
+            // Layers inherited from a shard split belong to our ancestor, and may still be
+            // referenced by our siblings: they are cleaned up by `split_cleanup`.
+            let shard = self.tenant_shard_id.to_index();
             stopped
                 .upload_queue_for_deletion
                 .latest_files
                 .drain()
+                .filter(|(_, meta)| meta.shard == shard)
                 .map(|(file_name, meta)| {
                     remote_layer_path(
                         &self.tenant_shard_id.tenant_id,
@@ -1036,6 +1146,7 @@ impl RemoteTimelineClient {
         };
 
         let layer_deletion_count = layers.len();
+        self.throttle.throttle(RequestClass::Upload, 0).await?;
         self.deletion_queue_client.push_immediate(layers).await?;
 
         // Do not delete index part yet, it is needed for possible retry. If we remove it first
@@ -1046,17 +1157,18 @@ impl RemoteTimelineClient {
         // taking the burden of listing all the layers that we already know we should delete.
         self.deletion_queue_client.flush_immediate().await?;
 
+        let cancel = shutdown_token();
         let remaining = backoff::retry(
             || async {
                 self.storage_impl
-                    .list_files(Some(&timeline_storage_path))
+                    .list_files(Some(&timeline_storage_path), &cancel)
                     .await
             },
             |_e| false,
             FAILED_DOWNLOAD_WARN_THRESHOLD,
             FAILED_REMOTE_OP_RETRIES,
             "list_prefixes",
-            backoff::Cancel::new(shutdown_token(), || anyhow::anyhow!("Cancelled!")),
+            backoff::Cancel::new(cancel.clone(), || anyhow::anyhow!("Cancelled!")),
         )
         .await
         .context("list prefixes")?;
@@ -1254,13 +1366,10 @@ impl RemoteTimelineClient {
         loop {
             // If we're requested to shut down, close up shop and exit.
             //
-            // Note: We only check for the shutdown requests between retries, so
-            // if a shutdown request arrives while we're busy uploading, in the
-            // upload::upload:*() call below, we will wait not exit until it has
-            // finished. We probably could cancel the upload by simply dropping
-            // the Future, but we're not 100% sure if the remote storage library
-            // is cancellation safe, so we don't dare to do that. Hopefully, the
-            // upload finishes or times out soon enough.
+            // Note: We only check for the shutdown requests between retries. An
+            // upload that is in progress when the queue gets stopped is aborted
+            // through `self.cancel`, otherwise it runs until it finishes or hits
+            // the remote storage timeout.
             if task_mgr::is_shutdown_requested() {
                 info!("upload task cancelled by shutdown request");
                 match self.stop() {
@@ -1275,21 +1384,31 @@ impl RemoteTimelineClient {
             let upload_result: anyhow::Result<()> = match &task.op {
                 UploadOp::UploadLayer(ref layer, ref layer_metadata) => {
                     let path = layer.local_path();
-                    upload::upload_timeline_layer(
-                        self.conf,
-                        &self.storage_impl,
-                        path,
-                        layer_metadata,
-                        self.generation,
-                    )
-                    .measure_remote_op(
-                        self.tenant_shard_id.tenant_id,
-                        self.timeline_id,
-                        RemoteOpFileKind::Layer,
-                        RemoteOpKind::Upload,
-                        Arc::clone(&self.metrics),
-                    )
-                    .await
+                    let throttled = self
+                        .throttle
+                        .throttle(RequestClass::Upload, layer_metadata.file_size())
+                        .await;
+                    match throttled {
+                        Ok(()) => {
+                            upload::upload_timeline_layer(
+                                self.conf,
+                                &self.storage_impl,
+                                path,
+                                layer_metadata,
+                                self.generation,
+                                &self.cancel,
+                            )
+                            .measure_remote_op(
+                                self.tenant_shard_id.tenant_id,
+                                self.timeline_id,
+                                RemoteOpFileKind::Layer,
+                                RemoteOpKind::Upload,
+                                Arc::clone(&self.metrics),
+                            )
+                            .await
+                        }
+                        Err(e) => Err(e.into()),
+                    }
                 }
                 UploadOp::UploadMetadata(ref index_part, _lsn) => {
                     let mention_having_future_layers = if cfg!(feature = "testing") {
@@ -1301,21 +1420,28 @@ impl RemoteTimelineClient {
                         false
                     };
 
-                    let res = upload::upload_index_part(
-                        &self.storage_impl,
-                        &self.tenant_shard_id,
-                        &self.timeline_id,
-                        self.generation,
-                        index_part,
-                    )
-                    .measure_remote_op(
-                        self.tenant_shard_id.tenant_id,
-                        self.timeline_id,
-                        RemoteOpFileKind::Index,
-                        RemoteOpKind::Upload,
-                        Arc::clone(&self.metrics),
-                    )
-                    .await;
+                    // Indices are small: only count them against the request budget.
+                    let res = match self.throttle.throttle(RequestClass::Upload, 0).await {
+                        Ok(()) => {
+                            upload::upload_index_part(
+                                &self.storage_impl,
+                                &self.tenant_shard_id,
+                                &self.timeline_id,
+                                self.generation,
+                                index_part,
+                                &self.cancel,
+                            )
+                            .measure_remote_op(
+                                self.tenant_shard_id.tenant_id,
+                                self.timeline_id,
+                                RemoteOpFileKind::Index,
+                                RemoteOpKind::Upload,
+                                Arc::clone(&self.metrics),
+                            )
+                            .await
+                        }
+                        Err(e) => Err(e.into()),
+                    };
                     if res.is_ok() {
                         self.update_remote_physical_size_gauge(Some(index_part));
                         if mention_having_future_layers {
@@ -1327,15 +1453,20 @@ impl RemoteTimelineClient {
                 }
                 UploadOp::Delete(delete) => {
                     pausable_failpoint!("before-delete-layer-pausable");
-                    self.deletion_queue_client
-                        .push_layers(
-                            self.tenant_shard_id,
-                            self.timeline_id,
-                            self.generation,
-                            delete.layers.clone(),
-                        )
-                        .await
-                        .map_err(|e| anyhow::anyhow!(e))
+                    // The deletion queue deletes the layers of one op in a single request.
+                    match self.throttle.throttle(RequestClass::Upload, 0).await {
+                        Ok(()) => self
+                            .deletion_queue_client
+                            .push_layers(
+                                self.tenant_shard_id,
+                                self.timeline_id,
+                                self.generation,
+                                delete.layers.clone(),
+                            )
+                            .await
+                            .map_err(|e| anyhow::anyhow!(e)),
+                        Err(e) => Err(e.into()),
+                    }
                 }
                 unexpected @ UploadOp::Barrier(_) | unexpected @ UploadOp::Shutdown => {
                     // unreachable. Barrier operations are handled synchronously in
@@ -1350,6 +1481,13 @@ impl RemoteTimelineClient {
                     break;
                 }
                 Err(e) => {
+                    if self.cancel.is_cancelled() {
+                        // The queue was stopped while we were uploading: nobody
+                        // is waiting for this task to complete any more.
+                        info!("upload task cancelled by stop: {e:#}");
+                        return;
+                    }
+
                     let retries = task.retries.fetch_add(1, Ordering::SeqCst);
 
                     // Uploads can fail due to rate limits (IAM, S3), spurious network problems,
@@ -1536,6 +1674,9 @@ impl RemoteTimelineClient {
             UploadQueue::Initialized(initialized) => {
                 info!("shutting down upload queue");
 
+                // Abort in-progress uploads, see `perform_upload_task`.
+                self.cancel.cancel();
+
                 // Replace the queue with the Stopped state, taking ownership of the old
                 // Initialized queue. We will do some checks on it, and then drop it.
                 let qi = {
@@ -1823,11 +1964,13 @@ mod tests {
                 generation,
                 storage_impl: self.harness.remote_storage.clone(),
                 deletion_queue_client: self.harness.deletion_queue.new_client(),
+                throttle: self.tenant.remote_storage_throttle.clone(),
                 upload_queue: Mutex::new(UploadQueue::Uninitialized),
                 metrics: Arc::new(RemoteTimelineClientMetrics::new(
                     &self.harness.tenant_shard_id,
                     &TIMELINE_ID,
                 )),
+                cancel: CancellationToken::new(),
             })
         }
 
@@ -5,7 +5,6 @@
 
 use std::collections::HashSet;
 use std::future::Future;
-use std::time::Duration;
 
 use anyhow::{anyhow, Context};
 use camino::{Utf8Path, Utf8PathBuf};
@@ -22,7 +21,7 @@ use crate::tenant::storage_layer::LayerFileName;
 use crate::tenant::timeline::span::debug_assert_current_span_has_tenant_and_timeline_id;
 use crate::tenant::Generation;
 use crate::TEMP_FILE_SUFFIX;
-use remote_storage::{DownloadError, GenericRemoteStorage, ListingMode};
+use remote_storage::{DownloadError, GenericRemoteStorage, ListingMode, TimeoutOrCancel};
 use utils::crashsafe::path_with_suffix_extension;
 use utils::id::TimelineId;
 
@@ -32,8 +31,6 @@ use super::{
     FAILED_DOWNLOAD_WARN_THRESHOLD, FAILED_REMOTE_OP_RETRIES, INITDB_PATH,
 };
 
-static MAX_DOWNLOAD_DURATION: Duration = Duration::from_secs(120);
-
 ///
 /// If 'metadata' is given, we will validate that the downloaded file's size matches that
 /// in the metadata. (In the future, we might do more cross-checks, like CRC validation)
@@ -46,6 +43,7 @@ pub async fn download_layer_file<'a>(
     timeline_id: TimelineId,
     layer_file_name: &'a LayerFileName,
     layer_metadata: &'a LayerFileMetadata,
+    cancel: &CancellationToken,
 ) -> Result<u64, DownloadError> {
     debug_assert_current_span_has_tenant_and_timeline_id();
 
@@ -79,39 +77,30 @@ pub async fn download_layer_file<'a>(
                 .await
                 .with_context(|| format!("create a destination file for layer '{temp_file_path}'"))
                 .map_err(DownloadError::Other)?;
-            let download = storage
-                .download(&remote_path)
-                .await
-                .with_context(|| {
-                    format!(
-                    "open a download stream for layer with remote storage path '{remote_path:?}'"
-                )
-                })
-                .map_err(DownloadError::Other)?;
+            let download = storage.download(&remote_path, cancel).await?;
 
             let mut destination_file =
                 tokio::io::BufWriter::with_capacity(super::BUFFER_SIZE, destination_file);
 
             let mut reader = tokio_util::io::StreamReader::new(download.download_stream);
 
-            let bytes_amount = tokio::time::timeout(
-                MAX_DOWNLOAD_DURATION,
-                tokio::io::copy_buf(&mut reader, &mut destination_file),
-            )
-            .await
-            .map_err(|e| DownloadError::Other(anyhow::anyhow!("Timed out  {:?}", e)))?
-            .with_context(|| {
-                format!(
-                    "download layer at remote path '{remote_path:?}' into file {temp_file_path:?}"
-                )
-            })
-            .map_err(DownloadError::Other)?;
+            // The download stream fails on its own once the storage timeout passes or
+            // `cancel` fires, so the copy needs no timeout of its own.
+            let bytes_amount = tokio::io::copy_buf(&mut reader, &mut destination_file)
+                .await
+                .with_context(|| {
+                    format!(
+                        "download layer at remote path '{remote_path:?}' into file {temp_file_path:?}"
+                    )
+                })
+                .map_err(download_error)?;
 
             let destination_file = destination_file.into_inner();
 
             Ok((destination_file, bytes_amount))
         },
         &format!("download {remote_path:?}"),
+        cancel,
     )
     .await?;
 
@@ -189,7 +178,7 @@ pub async fn list_remote_timelines(
     });
 
     let listing = download_retry_forever(
-        || storage.list(Some(&remote_path), ListingMode::WithDelimiter),
+        || storage.list(Some(&remote_path), ListingMode::WithDelimiter, &cancel),
         &format!("list timelines for {tenant_shard_id}"),
         cancel,
     )
@@ -209,7 +198,7 @@ pub async fn list_remote_timelines(
         };
     }
 
-    for key in listing.keys {
+    for key in listing.keys.into_iter().map(|object| object.key) {
         let object_name = key
             .object_name()
             .ok_or_else(|| anyhow::anyhow!("object name for key {key}"))?;
@@ -232,20 +221,20 @@ async fn do_download_index_part(
 
     let index_part_bytes = download_retry_forever(
         || async {
-            let index_part_download = storage.download(&remote_path).await?;
+            let index_part_download = storage.download(&remote_path, &cancel).await?;
 
             let mut index_part_bytes = Vec::new();
             let mut stream = std::pin::pin!(index_part_download.download_stream);
             while let Some(chunk) = stream.next().await {
                 let chunk = chunk
                     .with_context(|| format!("download index part at {remote_path:?}"))
-                    .map_err(DownloadError::Other)?;
+                    .map_err(download_error)?;
                 index_part_bytes.extend_from_slice(&chunk[..]);
             }
             Ok(index_part_bytes)
         },
         &format!("download {remote_path:?}"),
-        cancel,
+        cancel.clone(),
     )
     .await?;
 
@@ -342,18 +331,15 @@ pub(super) async fn download_index_part(
     // to constructing a full index path with no generation, because the generation is a suffix.
     let index_prefix = remote_index_path(tenant_shard_id, timeline_id, Generation::none());
     let indices = backoff::retry(
-        || async { storage.list_files(Some(&index_prefix)).await },
-        |_| false,
+        || async { storage.list_files(Some(&index_prefix), &cancel).await },
+        TimeoutOrCancel::caused_by_cancel,
         FAILED_DOWNLOAD_WARN_THRESHOLD,
         FAILED_REMOTE_OP_RETRIES,
         "listing index_part files",
-        // TODO: use a cancellation token (https://github.com/neondatabase/neon/issues/5066)
-        backoff::Cancel::new(CancellationToken::new(), || -> anyhow::Error {
-            unreachable!()
-        }),
+        backoff::Cancel::new(cancel.clone(), || TimeoutOrCancel::Cancel.into()),
     )
     .await
-    .map_err(DownloadError::Other)?;
+    .map_err(download_error)?;
 
     // General case logic for which index to use: the latest index whose generation
     // is <= our own.  See "Finding the remote indices for timelines" in docs/rfcs/025-generation-numbers.md
@@ -384,11 +370,58 @@ pub(super) async fn download_index_part(
     }
 }
 
+/// Download the index with the highest generation, regardless of our own generation.  This
+/// is for looking at other shards' timelines, whose generations are unrelated to ours.
+///
+/// Returns `None` if the timeline has no index in that shard.
+pub(crate) async fn download_latest_index_part(
+    storage: &GenericRemoteStorage,
+    tenant_shard_id: &TenantShardId,
+    timeline_id: &TimelineId,
+    cancel: &CancellationToken,
+) -> Result<Option<IndexPart>, DownloadError> {
+    let index_prefix = remote_index_path(tenant_shard_id, timeline_id, Generation::none());
+    let indices = backoff::retry(
+        || async { storage.list_files(Some(&index_prefix), cancel).await },
+        TimeoutOrCancel::caused_by_cancel,
+        FAILED_DOWNLOAD_WARN_THRESHOLD,
+        FAILED_REMOTE_OP_RETRIES,
+        "listing index_part files",
+        backoff::Cancel::new(cancel.clone(), || TimeoutOrCancel::Cancel.into()),
+    )
+    .await
+    .map_err(download_error)?;
+
+    let Some(latest_generation) = indices
+        .into_iter()
+        .filter_map(parse_remote_index_path)
+        .max()
+    else {
+        return Ok(None);
+    };
+
+    match do_download_index_part(
+        storage,
+        tenant_shard_id,
+        timeline_id,
+        latest_generation,
+        cancel.clone(),
+    )
+    .await
+    {
+        Ok(index_part) => Ok(Some(index_part)),
+        // Deleted since we listed it
+        Err(DownloadError::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 pub(crate) async fn download_initdb_tar_zst(
     conf: &'static PageServerConf,
     storage: &GenericRemoteStorage,
     tenant_shard_id: &TenantShardId,
     timeline_id: &TimelineId,
+    cancel: &CancellationToken,
 ) -> Result<(Utf8PathBuf, File), DownloadError> {
     debug_assert_current_span_has_tenant_and_timeline_id();
 
@@ -418,14 +451,14 @@ pub(crate) async fn download_initdb_tar_zst(
                 .with_context(|| format!("tempfile creation {temp_path}"))
                 .map_err(DownloadError::Other)?;
 
-            let download = storage.download(&remote_path).await?;
+            let download = storage.download(&remote_path, cancel).await?;
             let mut download = tokio_util::io::StreamReader::new(download.download_stream);
             let mut writer = tokio::io::BufWriter::with_capacity(8 * 1024, file);
 
             tokio::io::copy_buf(&mut download, &mut writer)
                 .await
                 .with_context(|| format!("download initdb.tar.zst at {remote_path:?}"))
-                .map_err(DownloadError::Other)?;
+                .map_err(download_error)?;
 
             let mut file = writer.into_inner();
 
@@ -437,6 +470,7 @@ pub(crate) async fn download_initdb_tar_zst(
             Ok(file)
         },
         &format!("download {remote_path}"),
+        cancel,
     )
     .await
     .map_err(|e| {
@@ -460,21 +494,22 @@ pub(crate) async fn download_initdb_tar_zst(
 /// with backoff.
 ///
 /// (See similar logic for uploads in `perform_upload_task`)
-async fn download_retry<T, O, F>(op: O, description: &str) -> Result<T, DownloadError>
+async fn download_retry<T, O, F>(
+    op: O,
+    description: &str,
+    cancel: &CancellationToken,
+) -> Result<T, DownloadError>
 where
     O: FnMut() -> F,
     F: Future<Output = Result<T, DownloadError>>,
 {
     backoff::retry(
         op,
-        |e| matches!(e, DownloadError::BadInput(_) | DownloadError::NotFound),
+        DownloadError::is_permanent,
         FAILED_DOWNLOAD_WARN_THRESHOLD,
         FAILED_REMOTE_OP_RETRIES,
         description,
-        // TODO: use a cancellation token (https://github.com/neondatabase/neon/issues/5066)
-        backoff::Cancel::new(CancellationToken::new(), || -> DownloadError {
-            unreachable!()
-        }),
+        backoff::Cancel::new(cancel.clone(), || DownloadError::Cancelled),
     )
     .await
 }
@@ -490,7 +525,7 @@ where
 {
     backoff::retry(
         op,
-        |e| matches!(e, DownloadError::BadInput(_) | DownloadError::NotFound),
+        DownloadError::is_permanent,
         FAILED_DOWNLOAD_WARN_THRESHOLD,
         u32::MAX,
         description,
@@ -498,3 +533,12 @@ where
     )
     .await
 }
+
+/// Keeps timeouts and cancellations of a download stream distinguishable from other
+/// failures, so that the retry loops above can tell them apart.
+fn download_error(e: anyhow::Error) -> DownloadError {
+    match TimeoutOrCancel::caused(&e) {
+        Some(reason) => reason.into(),
+        None => DownloadError::Other(e),
+    }
+}
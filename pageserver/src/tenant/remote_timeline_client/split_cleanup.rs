@@ -0,0 +1,311 @@
+//! Deletion of the remote objects that shard splits leave behind.
+//!
+//! A split does not copy the parent's layers: the children's indices reference them under
+//! the parent's shard index, see [`super::RemoteTimelineClient::upload_child_indices`].
+//! Descendants never add references to an ancestor's layers, they only drop them as they
+//! compact, so a layer of an ancestor can be deleted once the latest index of none of the
+//! ancestor's descendants references it.
+//!
+//! Ancestors are cleaned up oldest first, and an ancestor's index parts are only deleted once
+//! all of its own ancestors are empty: descendants of later splits inherited their references
+//! to older ancestors' layers through those indices, so the indices must stay to account for
+//! the references.
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use pageserver_api::shard::{ShardCount, ShardNumber, TenantShardId};
+use remote_storage::{DownloadError, GenericRemoteStorage, ListingMode, RemotePath};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utils::backoff;
+use utils::id::TimelineId;
+
+use crate::deletion_queue::DeletionQueueClient;
+
+use super::download::{download_latest_index_part, list_remote_timelines};
+use super::index::IndexPart;
+use super::{
+    remote_layer_path, remote_timeline_path, FAILED_DOWNLOAD_WARN_THRESHOLD,
+    FAILED_REMOTE_OP_RETRIES, INITDB_PATH,
+};
+
+/// Objects younger than this are left alone by background cleanup: the parent of a split
+/// that was rolled back carries on uploading under its own prefix.
+const MIN_OBJECT_AGE: Duration = Duration::from_secs(24 * 3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SplitCleanupMode {
+    /// Background cleanup from a live shard.  Each ancestor is cleaned up by the one of its
+    /// descendants that has the same shard number, so that shards don't duplicate work.
+    Background,
+    /// The shard is being deleted along with the rest of the tenant: clean up all of its
+    /// ancestors, however recent their objects are.
+    TenantDeletion,
+}
+
+/// The shards that `tenant_shard_id` may have been split from, oldest first.  Shard counts only
+/// ever grow by multiples, so the ancestors are the shards at each count that divides ours.
+fn split_ancestors(tenant_shard_id: &TenantShardId) -> Vec<TenantShardId> {
+    let count = tenant_shard_id.shard_count.0;
+    (1..count)
+        .filter(|d| count % d == 0)
+        .map(|d| TenantShardId {
+            tenant_id: tenant_shard_id.tenant_id,
+            shard_number: ShardNumber(tenant_shard_id.shard_number.0 % d),
+            // A single shard is the unsharded tenant that the first split started from
+            shard_count: ShardCount(if d == 1 { 0 } else { d }),
+        })
+        .collect()
+}
+
+/// The shards that may hold references to `ancestor`'s layers: its descendants at each count
+/// between its own and ours.  Some of them never existed, which is fine: they have no indices.
+fn split_descendants(ancestor: &TenantShardId, shard_count: ShardCount) -> Vec<TenantShardId> {
+    let ancestor_count = std::cmp::max(ancestor.shard_count.0, 1);
+    (ancestor_count + 1..=shard_count.0)
+        .filter(|c| shard_count.0 % c == 0 && c % ancestor_count == 0)
+        .flat_map(|c| ancestor.split(ShardCount(c)))
+        .collect()
+}
+
+/// Delete the objects of `tenant_shard_id`'s ancestors that no descendant references any more.
+pub(crate) async fn cleanup_split_ancestors(
+    storage: &GenericRemoteStorage,
+    deletion_queue_client: &DeletionQueueClient,
+    tenant_shard_id: &TenantShardId,
+    mode: SplitCleanupMode,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    let min_age = match mode {
+        SplitCleanupMode::Background => MIN_OBJECT_AGE,
+        SplitCleanupMode::TenantDeletion => Duration::ZERO,
+    };
+
+    let ancestors = split_ancestors(tenant_shard_id);
+    if ancestors.is_empty() {
+        return Ok(());
+    }
+
+    let mut older_ancestors_empty = true;
+    for ancestor in ancestors {
+        let ours = match mode {
+            SplitCleanupMode::Background => ancestor.shard_number == tenant_shard_id.shard_number,
+            SplitCleanupMode::TenantDeletion => true,
+        };
+        let descendants = split_descendants(&ancestor, tenant_shard_id.shard_count);
+
+        let (timeline_ids, _) = list_remote_timelines(storage, ancestor, cancel.clone())
+            .await
+            .with_context(|| format!("list timelines of split ancestor {ancestor}"))?;
+
+        let mut ancestor_empty = true;
+        for timeline_id in timeline_ids {
+            let timeline_empty = cleanup_ancestor_timeline(
+                storage,
+                deletion_queue_client,
+                AncestorTimeline {
+                    ancestor: &ancestor,
+                    timeline_id: &timeline_id,
+                    descendants: &descendants,
+                },
+                ours.then_some(CleanupLimits {
+                    min_age,
+                    delete_indices: older_ancestors_empty,
+                }),
+                cancel,
+            )
+            .await
+            .with_context(|| format!("clean up timeline {timeline_id} of {ancestor}"))?;
+            ancestor_empty &= timeline_empty;
+        }
+        older_ancestors_empty &= ancestor_empty;
+    }
+
+    deletion_queue_client.flush_immediate().await?;
+
+    Ok(())
+}
+
+struct AncestorTimeline<'a> {
+    ancestor: &'a TenantShardId,
+    timeline_id: &'a TimelineId,
+    descendants: &'a [TenantShardId],
+}
+
+struct CleanupLimits {
+    min_age: Duration,
+    /// Whether the ancestor's index parts may go once none of its layers are left
+    delete_indices: bool,
+}
+
+/// Delete the unreferenced objects of one of an ancestor's timelines, or only look at them if
+/// `limits` is None.  Returns whether the timeline has no objects left, besides the initdb
+/// archive which is shared by all shards.
+async fn cleanup_ancestor_timeline(
+    storage: &GenericRemoteStorage,
+    deletion_queue_client: &DeletionQueueClient,
+    timeline: AncestorTimeline<'_>,
+    limits: Option<CleanupLimits>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<bool> {
+    let AncestorTimeline {
+        ancestor,
+        timeline_id,
+        ..
+    } = timeline;
+
+    let timeline_path = remote_timeline_path(ancestor, timeline_id);
+    let listing = backoff::retry(
+        || storage.list(Some(&timeline_path), ListingMode::NoDelimiter, cancel),
+        DownloadError::is_permanent,
+        FAILED_DOWNLOAD_WARN_THRESHOLD,
+        FAILED_REMOTE_OP_RETRIES,
+        "list split ancestor timeline",
+        backoff::Cancel::new(cancel.clone(), || DownloadError::Cancelled),
+    )
+    .await?;
+
+    let objects: Vec<_> = listing
+        .keys
+        .into_iter()
+        .filter(|object| object.key.object_name() != Some(INITDB_PATH))
+        .collect();
+    let Some(limits) = limits else {
+        return Ok(objects.is_empty());
+    };
+
+    let referenced = referenced_layers(storage, &timeline, cancel).await?;
+
+    let now = SystemTime::now();
+    let old_enough = |last_modified: SystemTime| {
+        now.duration_since(last_modified).unwrap_or_default() >= limits.min_age
+    };
+
+    let (indices, layers): (Vec<_>, Vec<_>) = objects.into_iter().partition(|object| {
+        object
+            .key
+            .object_name()
+            .map(|name| name.starts_with(IndexPart::FILE_NAME))
+            .unwrap_or(false)
+    });
+
+    let mut delete = Vec::new();
+    let mut layers_left = 0;
+    for layer in layers {
+        if referenced.contains(&layer.key) || !old_enough(layer.last_modified) {
+            layers_left += 1;
+        } else {
+            delete.push(layer.key);
+        }
+    }
+
+    let mut indices_left = indices.len();
+    if layers_left == 0 && limits.delete_indices {
+        for index in indices {
+            if old_enough(index.last_modified) {
+                delete.push(index.key);
+                indices_left -= 1;
+            }
+        }
+    }
+
+    if !delete.is_empty() {
+        info!(
+            %ancestor,
+            %timeline_id,
+            "Deleting {} objects of split ancestor, {layers_left} layers left",
+            delete.len()
+        );
+        deletion_queue_client.push_immediate(delete).await?;
+    }
+
+    Ok(layers_left == 0 && indices_left == 0)
+}
+
+/// The remote paths of the ancestor's layers that its descendants' latest indices reference.
+async fn referenced_layers(
+    storage: &GenericRemoteStorage,
+    timeline: &AncestorTimeline<'_>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<HashSet<RemotePath>> {
+    let ancestor_index = timeline.ancestor.to_index();
+
+    let mut referenced = HashSet::new();
+    for descendant in timeline.descendants {
+        let Some(index_part) =
+            download_latest_index_part(storage, descendant, timeline.timeline_id, cancel)
+                .await
+                .with_context(|| format!("download index of {descendant}"))?
+        else {
+            continue;
+        };
+        if index_part.deleted_at.is_some() {
+            // Still counts: a deletion in progress may be retried from this index
+            warn!(%descendant, "Descendant timeline is being deleted");
+        }
+        referenced.extend(
+            index_part
+                .layer_metadata
+                .iter()
+                .filter(|(_, meta)| meta.shard == ancestor_index)
+                .map(|(name, meta)| {
+                    remote_layer_path(
+                        &timeline.ancestor.tenant_id,
+                        timeline.timeline_id,
+                        meta.shard,
+                        name,
+                        meta.generation,
+                    )
+                }),
+        );
+    }
+
+    Ok(referenced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::id::TenantId;
+
+    fn shard(tenant_id: TenantId, number: u8, count: u8) -> TenantShardId {
+        TenantShardId {
+            tenant_id,
+            shard_number: ShardNumber(number),
+            shard_count: ShardCount(count),
+        }
+    }
+
+    #[test]
+    fn ancestors_and_descendants() {
+        let tenant_id = TenantId::generate();
+
+        assert!(split_ancestors(&shard(tenant_id, 0, 0)).is_empty());
+        assert_eq!(
+            split_ancestors(&shard(tenant_id, 5, 8)),
+            vec![
+                shard(tenant_id, 0, 0),
+                shard(tenant_id, 1, 2),
+                shard(tenant_id, 1, 4)
+            ]
+        );
+
+        assert_eq!(
+            split_descendants(&shard(tenant_id, 1, 2), ShardCount(8)),
+            vec![
+                shard(tenant_id, 1, 4),
+                shard(tenant_id, 3, 4),
+                shard(tenant_id, 1, 8),
+                shard(tenant_id, 3, 8),
+                shard(tenant_id, 5, 8),
+                shard(tenant_id, 7, 8)
+            ]
+        );
+        assert_eq!(
+            split_descendants(&shard(tenant_id, 0, 0), ShardCount(2)),
+            vec![shard(tenant_id, 0, 2), shard(tenant_id, 1, 2)]
+        );
+    }
+}
@@ -0,0 +1,332 @@
+//! Per-tenant throttling of the remote storage requests.
+//!
+//! The remote storage backends only limit the number of concurrent requests of the whole
+//! pageserver, so a single tenant downloading all of its layers could starve the on-demand
+//! downloads of everyone else.  To prevent that, every tenant has budgets of requests and bytes
+//! per second for each [`RequestClass`], see [`RemoteStorageThrottleConfig`].  Requests wait
+//! until they fit into their budget before they are issued.
+//!
+//! The classes are ordered by priority: on-demand downloads before uploads before background
+//! downloads.  The priority is not enforced by preemption: a class never waits for the requests
+//! of another one.  Instead, it is expressed by the budgets, which the operator should size so
+//! that the higher classes get the larger share of the tenant's requests, e.g. by only limiting
+//! the background downloads.
+
+use std::num::NonZeroU64;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use metrics::{Counter, IntCounter};
+use pageserver_api::shard::TenantShardId;
+use strum::IntoEnumIterator;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::context::RequestContext;
+use crate::metrics::{REMOTE_STORAGE_THROTTLED_REQUESTS, REMOTE_STORAGE_THROTTLED_SECONDS};
+use crate::task_mgr::TaskKind;
+use crate::tenant::config::{RemoteStorageThrottleBudget, RemoteStorageThrottleConfig};
+use crate::tenant::tasks::Cancelled;
+
+/// How far requests may run ahead of the budget, e.g. after a period of inactivity.
+const BURST: Duration = Duration::from_secs(1);
+
+/// Priority class of a remote storage request, in the order of decreasing priority.  Every
+/// class has a budget of its own, see the module docs for how the priorities are applied.
+#[derive(
+    Debug, PartialEq, Eq, Clone, Copy, strum_macros::IntoStaticStr, strum_macros::EnumIter,
+)]
+#[strum(serialize_all = "snake_case")]
+pub(crate) enum RequestClass {
+    /// Downloads that a read is waiting for, e.g. a page request.
+    OnDemandDownload,
+    /// Requests that modify the remote storage: uploads of layers, indices and heatmaps, and
+    /// deletions.
+    Upload,
+    /// Downloads that nobody is waiting for, e.g. for compaction or a `download_remote_layers`.
+    BackgroundDownload,
+}
+
+impl RequestClass {
+    /// The class of a download made on behalf of the given request.  Downloads without a request
+    /// context are made by background operations.
+    pub(crate) fn for_download(ctx: Option<&RequestContext>) -> Self {
+        match ctx.map(|ctx| ctx.task_kind()) {
+            Some(
+                TaskKind::PageRequestHandler
+                | TaskKind::WalReceiverConnectionHandler
+                | TaskKind::OndemandLogicalSizeCalculation
+                | TaskKind::MgmtRequest,
+            ) => RequestClass::OnDemandDownload,
+            _ => RequestClass::BackgroundDownload,
+        }
+    }
+}
+
+/// A leaky bucket, which rather than its level keeps the time at which it will be empty.
+#[derive(Default)]
+struct LeakyBucket {
+    empty_at: Option<Instant>,
+}
+
+impl LeakyBucket {
+    /// Pours `amount` into the bucket, which leaks `rate` per second.  Returns how long to
+    /// wait until the bucket leaks enough for the amount to fit under the burst allowance.
+    fn pour(&mut self, now: Instant, amount: u64, rate: Option<NonZeroU64>) -> Duration {
+        let Some(rate) = rate else {
+            self.empty_at = None;
+            return Duration::ZERO;
+        };
+
+        let empty_at = self.empty_at.map_or(now, |empty_at| empty_at.max(now))
+            + Duration::from_secs_f64(amount as f64 / rate.get() as f64);
+        self.empty_at = Some(empty_at);
+        empty_at
+            .saturating_duration_since(now)
+            .saturating_sub(BURST)
+    }
+}
+
+#[derive(Default)]
+struct ClassBuckets {
+    ops: LeakyBucket,
+    bytes: LeakyBucket,
+}
+
+struct ClassThrottle {
+    buckets: Mutex<ClassBuckets>,
+    throttled_seconds: Counter,
+    throttled_requests: IntCounter,
+}
+
+impl ClassThrottle {
+    fn new(tenant_id: &str, shard_id: &str, class: RequestClass) -> Self {
+        let labels = [tenant_id, shard_id, class.into()];
+        Self {
+            buckets: Mutex::default(),
+            throttled_seconds: REMOTE_STORAGE_THROTTLED_SECONDS.with_label_values(&labels),
+            throttled_requests: REMOTE_STORAGE_THROTTLED_REQUESTS.with_label_values(&labels),
+        }
+    }
+}
+
+/// The remote storage request budgets of a tenant, shared by its timelines.
+pub(crate) struct RemoteStorageThrottle {
+    tenant_shard_id: TenantShardId,
+    config: RwLock<RemoteStorageThrottleConfig>,
+    on_demand_download: ClassThrottle,
+    upload: ClassThrottle,
+    background_download: ClassThrottle,
+    /// The tenant's cancellation token: waiting for the budget is interrupted on shutdown.
+    cancel: CancellationToken,
+}
+
+impl RemoteStorageThrottle {
+    pub(crate) fn new(
+        tenant_shard_id: TenantShardId,
+        config: RemoteStorageThrottleConfig,
+        cancel: CancellationToken,
+    ) -> Self {
+        let tenant_id = tenant_shard_id.tenant_id.to_string();
+        let shard_id = tenant_shard_id.shard_slug().to_string();
+        let class = |class| ClassThrottle::new(&tenant_id, &shard_id, class);
+        Self {
+            tenant_shard_id,
+            config: RwLock::new(config),
+            on_demand_download: class(RequestClass::OnDemandDownload),
+            upload: class(RequestClass::Upload),
+            background_download: class(RequestClass::BackgroundDownload),
+            cancel,
+        }
+    }
+
+    pub(crate) fn reconfigure(&self, config: RemoteStorageThrottleConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    fn class(&self, class: RequestClass) -> (&ClassThrottle, RemoteStorageThrottleBudget) {
+        let config = self.config.read().unwrap();
+        match class {
+            RequestClass::OnDemandDownload => (&self.on_demand_download, config.on_demand_download),
+            RequestClass::Upload => (&self.upload, config.upload),
+            RequestClass::BackgroundDownload => {
+                (&self.background_download, config.background_download)
+            }
+        }
+    }
+
+    /// Wait until a request of the given class, transferring `bytes`, fits into the budget.
+    ///
+    /// Returns an error if the tenant is shut down while waiting.
+    pub(crate) async fn throttle(&self, class: RequestClass, bytes: u64) -> Result<(), Cancelled> {
+        let (throttle, budget) = self.class(class);
+        let wait = {
+            let now = Instant::now();
+            let mut buckets = throttle.buckets.lock().unwrap();
+            let ops_wait = buckets.ops.pour(now, 1, budget.ops_per_second);
+            let bytes_wait = buckets.bytes.pour(now, bytes, budget.bytes_per_second);
+            ops_wait.max(bytes_wait)
+        };
+        if wait.is_zero() {
+            return Ok(());
+        }
+
+        tracing::debug!(?class, ?wait, "throttling remote storage request");
+        throttle.throttled_requests.inc();
+        let started_at = Instant::now();
+        let result = tokio::select! {
+            _ = tokio::time::sleep(wait) => Ok(()),
+            _ = self.cancel.cancelled() => Err(Cancelled),
+        };
+        throttle
+            .throttled_seconds
+            .inc_by(started_at.elapsed().as_secs_f64());
+        result
+    }
+}
+
+impl Drop for RemoteStorageThrottle {
+    fn drop(&mut self) {
+        let tenant_id = self.tenant_shard_id.tenant_id.to_string();
+        let shard_id = self.tenant_shard_id.shard_slug().to_string();
+        for class in RequestClass::iter() {
+            let labels = [tenant_id.as_str(), shard_id.as_str(), class.into()];
+            let _ = REMOTE_STORAGE_THROTTLED_SECONDS.remove_label_values(&labels);
+            let _ = REMOTE_STORAGE_THROTTLED_REQUESTS.remove_label_values(&labels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn unlimited_bucket_never_waits() {
+        let now = Instant::now();
+        let mut bucket = LeakyBucket::default();
+        for _ in 0..1000 {
+            assert_eq!(bucket.pour(now, u64::MAX, None), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_throttles() {
+        let rate = NonZeroU64::new(10);
+        let now = Instant::now();
+        let mut bucket = LeakyBucket::default();
+
+        // A second worth of requests goes through right away
+        for _ in 0..10 {
+            assert_eq!(bucket.pour(now, 1, rate), Duration::ZERO);
+        }
+        assert_eq!(bucket.pour(now, 1, rate), SECOND / 10);
+        assert_eq!(bucket.pour(now, 1, rate), SECOND / 5);
+
+        // The bucket leaks while nobody pours
+        let later = now + 2 * SECOND;
+        assert_eq!(bucket.pour(later, 1, rate), Duration::ZERO);
+    }
+
+    #[test]
+    fn large_amounts_wait_for_their_share() {
+        let rate = NonZeroU64::new(1024 * 1024);
+        let now = Instant::now();
+        let mut bucket = LeakyBucket::default();
+
+        assert_eq!(bucket.pour(now, 3 * 1024 * 1024, rate), 2 * SECOND);
+        assert_eq!(bucket.pour(now, 1024 * 1024, rate), 3 * SECOND);
+        assert_eq!(
+            bucket.pour(now + 5 * SECOND, 1024 * 1024, rate),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn download_class_follows_the_task_kind() {
+        use crate::context::DownloadBehavior;
+
+        let class_of = |task_kind| {
+            let ctx = RequestContext::new(task_kind, DownloadBehavior::Download);
+            RequestClass::for_download(Some(&ctx))
+        };
+        for task_kind in [
+            TaskKind::PageRequestHandler,
+            TaskKind::WalReceiverConnectionHandler,
+            TaskKind::OndemandLogicalSizeCalculation,
+            TaskKind::MgmtRequest,
+        ] {
+            assert_eq!(class_of(task_kind), RequestClass::OnDemandDownload);
+        }
+        for task_kind in [
+            TaskKind::Compaction,
+            TaskKind::Eviction,
+            TaskKind::DownloadAllRemoteLayers,
+        ] {
+            assert_eq!(class_of(task_kind), RequestClass::BackgroundDownload);
+        }
+        assert_eq!(
+            RequestClass::for_download(None),
+            RequestClass::BackgroundDownload
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn classes_have_separate_budgets() {
+        let config = RemoteStorageThrottleConfig {
+            background_download: RemoteStorageThrottleBudget {
+                ops_per_second: NonZeroU64::new(1),
+                bytes_per_second: None,
+            },
+            ..RemoteStorageThrottleConfig::default()
+        };
+        let throttle = RemoteStorageThrottle::new(
+            TenantShardId::unsharded(utils::id::TenantId::generate()),
+            config,
+            CancellationToken::new(),
+        );
+
+        let started_at = Instant::now();
+        for _ in 0..3 {
+            throttle
+                .throttle(RequestClass::BackgroundDownload, 0)
+                .await
+                .unwrap();
+        }
+        assert_eq!(started_at.elapsed(), 2 * SECOND);
+        assert_eq!(throttle.background_download.throttled_requests.get(), 2);
+
+        let started_at = Instant::now();
+        for _ in 0..100 {
+            throttle
+                .throttle(RequestClass::OnDemandDownload, 1024)
+                .await
+                .unwrap();
+        }
+        assert_eq!(started_at.elapsed(), Duration::ZERO);
+        assert_eq!(throttle.on_demand_download.throttled_requests.get(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_interrupts_throttling() {
+        let config = RemoteStorageThrottleConfig {
+            upload: RemoteStorageThrottleBudget {
+                ops_per_second: None,
+                bytes_per_second: NonZeroU64::new(1),
+            },
+            ..RemoteStorageThrottleConfig::default()
+        };
+        let cancel = CancellationToken::new();
+        let throttle = RemoteStorageThrottle::new(
+            TenantShardId::unsharded(utils::id::TenantId::generate()),
+            config,
+            cancel.clone(),
+        );
+
+        cancel.cancel();
+        let result = throttle.throttle(RequestClass::Upload, 1024 * 1024).await;
+        assert!(matches!(result, Err(Cancelled)));
+    }
+}
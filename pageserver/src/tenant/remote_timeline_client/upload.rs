@@ -6,6 +6,7 @@ use fail::fail_point;
 use pageserver_api::shard::TenantShardId;
 use std::io::ErrorKind;
 use tokio::fs::{self, File};
+use tokio_util::sync::CancellationToken;
 
 use super::Generation;
 use crate::{
@@ -28,6 +29,7 @@ pub(super) async fn upload_index_part<'a>(
     timeline_id: &TimelineId,
     generation: Generation,
     index_part: &'a IndexPart,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
     tracing::trace!("uploading new index part");
 
@@ -48,6 +50,7 @@ pub(super) async fn upload_index_part<'a>(
             futures::stream::once(futures::future::ready(Ok(index_part_bytes))),
             index_part_size,
             &remote_path,
+            cancel,
         )
         .await
         .with_context(|| format!("upload index part for '{tenant_shard_id} / {timeline_id}'"))
@@ -63,6 +66,7 @@ pub(super) async fn upload_timeline_layer<'a>(
     source_path: &'a Utf8Path,
     known_metadata: &'a LayerFileMetadata,
     generation: Generation,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
     fail_point!("before-upload-layer", |_| {
         bail!("failpoint before-upload-layer")
@@ -107,7 +111,7 @@ pub(super) async fn upload_timeline_layer<'a>(
     let reader = tokio_util::io::ReaderStream::with_capacity(source_file, super::BUFFER_SIZE);
 
     storage
-        .upload(reader, fs_size, &storage_path, None)
+        .upload(reader, fs_size, &storage_path, None, cancel)
         .await
         .with_context(|| format!("upload layer from local path '{source_path}'"))?;
 
@@ -121,6 +125,7 @@ pub(crate) async fn upload_initdb_dir(
     timeline_id: &TimelineId,
     initdb_tar_zst: File,
     size: u64,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
     tracing::trace!("uploading initdb dir");
 
@@ -128,7 +133,7 @@ pub(crate) async fn upload_initdb_dir(
 
     let remote_path = remote_initdb_archive_path(tenant_id, timeline_id);
     storage
-        .upload_storage_object(file, size as usize, &remote_path)
+        .upload_storage_object(file, size as usize, &remote_path, cancel)
         .await
         .with_context(|| format!("upload initdb dir for '{tenant_id} / {timeline_id}'"))
 }
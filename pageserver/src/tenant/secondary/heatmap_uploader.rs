@@ -7,8 +7,12 @@ use std::{
 use crate::{
     metrics::SECONDARY_MODE,
     tenant::{
-        config::AttachmentMode, mgr::TenantManager, remote_timeline_client::remote_heatmap_path,
-        secondary::CommandResponse, span::debug_assert_current_span_has_tenant_id, Tenant,
+        config::AttachmentMode,
+        mgr::TenantManager,
+        remote_timeline_client::{remote_heatmap_path, throttle::RequestClass},
+        secondary::CommandResponse,
+        span::debug_assert_current_span_has_tenant_id,
+        Tenant,
     },
 };
 
@@ -550,6 +554,12 @@ async fn upload_tenant_heatmap(
 
     let path = remote_heatmap_path(tenant.get_tenant_shard_id());
 
+    tenant
+        .remote_storage_throttle
+        .throttle(RequestClass::Upload, size as u64)
+        .await
+        .map_err(|_| UploadHeatmapError::Cancelled)?;
+
     // Write the heatmap.
     tracing::debug!("Uploading {size} byte heatmap to {path}");
     if let Err(e) = backoff::retry(
@@ -558,7 +568,7 @@ async fn upload_tenant_heatmap(
                 bytes.clone(),
             ))));
             remote_storage
-                .upload_storage_object(bytes, size, &path)
+                .upload_storage_object(bytes, size, &path, &tenant_cancel)
                 .await
         },
         |_| false,
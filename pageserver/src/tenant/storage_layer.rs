@@ -11,6 +11,7 @@ use crate::context::{AccessStatsBehavior, RequestContext};
 use crate::task_mgr::TaskKind;
 use crate::walrecord::NeonWalRecord;
 use bytes::Bytes;
+use camino::Utf8PathBuf;
 use enum_map::EnumMap;
 use enumset::EnumSet;
 use once_cell::sync::Lazy;
@@ -82,6 +83,16 @@ pub enum ValueReconstructResult {
     Missing,
 }
 
+/// Result of [`DeltaLayer::rewrite`] and [`ImageLayer::rewrite`].
+#[derive(Debug)]
+pub struct RewrittenLayer {
+    pub desc: PersistentLayerDesc,
+    /// Temporary path the new layer file was written to
+    pub temp_path: Utf8PathBuf,
+    pub values_kept: usize,
+    pub values_dropped: usize,
+}
+
 #[derive(Debug)]
 pub struct LayerAccessStats(Mutex<LayerAccessStatsLocked>);
 
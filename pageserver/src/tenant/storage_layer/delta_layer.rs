@@ -34,7 +34,9 @@ use crate::repository::{Key, Value, KEY_SIZE};
 use crate::tenant::blob_io::BlobWriter;
 use crate::tenant::block_io::{BlockBuf, BlockCursor, BlockLease, BlockReader, FileBlockReader};
 use crate::tenant::disk_btree::{DiskBtreeBuilder, DiskBtreeReader, VisitDirection};
-use crate::tenant::storage_layer::{Layer, ValueReconstructResult, ValueReconstructState};
+use crate::tenant::storage_layer::{
+    Layer, RewrittenLayer, ValueReconstructResult, ValueReconstructState,
+};
 use crate::tenant::Timeline;
 use crate::virtual_file::VirtualFile;
 use crate::{walrecord, TEMP_FILE_SUFFIX};
@@ -247,6 +249,84 @@ impl DeltaLayer {
         inner.dump(ctx).await
     }
 
+    /// Collect the data needed to reconstruct `key` from this layer file.
+    ///
+    /// This variant is only used for debugging purposes, by the 'pagectl' binary.
+    pub async fn get_value_reconstruct_data(
+        &self,
+        key: Key,
+        lsn_range: Range<Lsn>,
+        reconstruct_state: &mut ValueReconstructState,
+        ctx: &RequestContext,
+    ) -> Result<ValueReconstructResult> {
+        ensure!(self.desc.key_range.contains(&key));
+        ensure!(lsn_range.start >= self.desc.lsn_range.start);
+
+        let inner = self
+            .load(LayerAccessKind::GetValueReconstructData, ctx)
+            .await?;
+        inner
+            .get_value_reconstruct_data(key, lsn_range, reconstruct_state, ctx)
+            .await
+    }
+
+    /// Write a new delta layer with the values of this one whose key passes
+    /// `keep_key` and whose LSN is below `lsn_end`. The key range of the new layer
+    /// is shrunk to the keys that remain, and its summary is written with the
+    /// current format version. Returns `None` if no values remain.
+    ///
+    /// This is only used for debugging purposes, by the 'pagectl' binary.
+    pub async fn rewrite<F>(
+        &self,
+        conf: &'static PageServerConf,
+        lsn_end: Lsn,
+        keep_key: F,
+        ctx: &RequestContext,
+    ) -> Result<Option<RewrittenLayer>>
+    where
+        F: Fn(&Key) -> bool,
+    {
+        let lsn_range = self.desc.lsn_range.start..std::cmp::min(lsn_end, self.desc.lsn_range.end);
+        ensure!(
+            lsn_range.start < lsn_range.end,
+            "LSN {lsn_end} is not above the start of the layer's LSN range {}",
+            self.desc.lsn_range.start
+        );
+
+        let inner = self.load(LayerAccessKind::Iter, ctx).await?;
+        let entries = inner.load_keys(ctx).await?;
+        let total = entries.len();
+        let kept = entries
+            .into_iter()
+            .filter(|entry| entry.lsn < lsn_range.end && keep_key(&entry.key))
+            .collect::<Vec<_>>();
+        let (Some(first), Some(last)) = (kept.first(), kept.last()) else {
+            return Ok(None);
+        };
+        let key_range = first.key..last.key.next();
+
+        let mut writer = DeltaLayerWriter::new(
+            conf,
+            self.desc.timeline_id,
+            self.desc.tenant_shard_id,
+            key_range.start,
+            lsn_range,
+        )
+        .await?;
+        for entry in &kept {
+            let val = entry.val.load(ctx).await?;
+            writer.put_value(entry.key, entry.lsn, val).await?;
+        }
+        let (desc, temp_path) = writer.finish_standalone(key_range.end).await?;
+
+        Ok(Some(RewrittenLayer {
+            desc,
+            temp_path,
+            values_kept: kept.len(),
+            values_dropped: total - kept.len(),
+        }))
+    }
+
     fn temp_path_for(
         conf: &PageServerConf,
         tenant_shard_id: &TenantShardId,
@@ -447,6 +527,21 @@ impl DeltaLayerWriterInner {
     /// Finish writing the delta layer.
     ///
     async fn finish(self, key_end: Key, timeline: &Arc<Timeline>) -> anyhow::Result<ResidentLayer> {
+        let conf = self.conf;
+        let (desc, path) = self.finish_file(key_end).await?;
+
+        let layer = Layer::finish_creating(conf, timeline, desc, &path)?;
+
+        trace!("created delta layer {}", layer.local_path());
+
+        Ok(layer)
+    }
+
+    ///
+    /// Write out the index and the summary, and fsync the file. The file is left
+    /// at its temporary path.
+    ///
+    async fn finish_file(self, key_end: Key) -> anyhow::Result<(PersistentLayerDesc, Utf8PathBuf)> {
         let index_start_blk =
             ((self.blob_writer.size() + PAGE_SZ as u64 - 1) / PAGE_SZ as u64) as u32;
 
@@ -515,11 +610,7 @@ impl DeltaLayerWriterInner {
         // fsync the file
         file.sync_all().await?;
 
-        let layer = Layer::finish_creating(self.conf, timeline, desc, &self.path)?;
-
-        trace!("created delta layer {}", layer.local_path());
-
-        Ok(layer)
+        Ok((desc, self.path))
     }
 }
 
@@ -611,6 +702,20 @@ impl DeltaLayerWriter {
     ) -> anyhow::Result<ResidentLayer> {
         self.inner.take().unwrap().finish(key_end, timeline).await
     }
+
+    ///
+    /// Finish writing the delta layer without adding it to a timeline. Returns the
+    /// descriptor of the new layer and the temporary path it was written to; the
+    /// caller is responsible for moving it into place.
+    ///
+    /// This variant is only used for debugging purposes, by the 'pagectl' binary.
+    ///
+    pub async fn finish_standalone(
+        mut self,
+        key_end: Key,
+    ) -> anyhow::Result<(PersistentLayerDesc, Utf8PathBuf)> {
+        self.inner.take().unwrap().finish_file(key_end).await
+    }
 }
 
 impl Drop for DeltaLayerWriter {
@@ -962,3 +1067,96 @@ impl AsRef<DeltaLayerInner> for DeltaLayerInner {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::DownloadBehavior, task_mgr::TaskKind, walrecord::NeonWalRecord};
+    use bytes::Bytes;
+
+    fn key(blknum: u32) -> Key {
+        let mut key = Key::from_hex("000000067F00000001000000010000000000").unwrap();
+        key.field6 = blknum;
+        key
+    }
+
+    fn record(blknum: u32, lsn: Lsn) -> NeonWalRecord {
+        NeonWalRecord::Postgres {
+            will_init: false,
+            rec: Bytes::from(format!("record {blknum} at {lsn}")),
+        }
+    }
+
+    #[tokio::test]
+    async fn rewrite_excludes_keys_and_truncates() -> anyhow::Result<()> {
+        let temp_dir = camino_tempfile::tempdir()?;
+        let conf: &'static PageServerConf = Box::leak(Box::new(PageServerConf::dummy_conf(
+            temp_dir.path().to_owned(),
+        )));
+        let ctx = RequestContext::new(TaskKind::UnitTest, DownloadBehavior::Error);
+        let tenant_shard_id = TenantShardId::unsharded(TenantId::generate());
+        let timeline_id = TimelineId::generate();
+        std::fs::create_dir_all(conf.timeline_path(&tenant_shard_id, &timeline_id))?;
+
+        let lsns = [Lsn(0x10), Lsn(0x20), Lsn(0x30)];
+        let mut writer = DeltaLayerWriter::new(
+            conf,
+            timeline_id,
+            tenant_shard_id,
+            key(0),
+            Lsn(0x10)..Lsn(0x40),
+        )
+        .await?;
+        for blknum in 10..14 {
+            for lsn in lsns {
+                let val = Value::WalRecord(record(blknum, lsn));
+                writer.put_value(key(blknum), lsn, val).await?;
+            }
+        }
+        let (_, path) = writer.finish_standalone(key(100)).await?;
+        let layer = DeltaLayer::new_for_path(&path, File::open(&path)?)?;
+
+        // drop key 11 and the last key, and everything at and above 0x30
+        let rewritten = layer
+            .rewrite(conf, Lsn(0x30), |k| *k != key(11) && *k != key(13), &ctx)
+            .await?
+            .expect("some values are kept");
+        assert_eq!(rewritten.values_kept, 4);
+        assert_eq!(rewritten.values_dropped, 8);
+
+        let path = &rewritten.temp_path;
+        let summary = Summary::des_prefix(&std::fs::read(path)?)?;
+        assert_eq!(summary.magic, DELTA_FILE_MAGIC);
+        assert_eq!(summary.format_version, STORAGE_FORMAT_VERSION);
+        assert_eq!(summary.key_range, key(10)..key(13));
+        assert_eq!(summary.lsn_range, Lsn(0x10)..Lsn(0x30));
+
+        let layer = DeltaLayer::new_for_path(path, File::open(path)?)?;
+        assert_eq!(layer.desc, rewritten.desc);
+        for blknum in 10..13 {
+            let mut state = ValueReconstructState {
+                records: Vec::new(),
+                img: None,
+            };
+            layer
+                .get_value_reconstruct_data(key(blknum), Lsn(0x10)..Lsn(0x40), &mut state, &ctx)
+                .await?;
+            let expected = if blknum == 11 {
+                vec![]
+            } else {
+                vec![
+                    (Lsn(0x20), record(blknum, Lsn(0x20))),
+                    (Lsn(0x10), record(blknum, Lsn(0x10))),
+                ]
+            };
+            assert_eq!(state.records, expected, "{blknum}");
+        }
+
+        // truncating below the start of the layer is refused
+        assert!(layer
+            .rewrite(conf, Lsn(0x10), |_| true, &ctx)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
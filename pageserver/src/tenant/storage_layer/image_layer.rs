@@ -31,7 +31,7 @@ use crate::tenant::blob_io::BlobWriter;
 use crate::tenant::block_io::{BlockBuf, BlockReader, FileBlockReader};
 use crate::tenant::disk_btree::{DiskBtreeBuilder, DiskBtreeReader, VisitDirection};
 use crate::tenant::storage_layer::{
-    LayerAccessStats, ValueReconstructResult, ValueReconstructState,
+    LayerAccessStats, RewrittenLayer, ValueReconstructResult, ValueReconstructState,
 };
 use crate::tenant::Timeline;
 use crate::virtual_file::VirtualFile;
@@ -215,6 +215,84 @@ impl ImageLayer {
         Ok(())
     }
 
+    /// Collect the data needed to reconstruct `key` from this layer file.
+    ///
+    /// This variant is only used for debugging purposes, by the 'pagectl' binary.
+    pub async fn get_value_reconstruct_data(
+        &self,
+        key: Key,
+        lsn_range: Range<Lsn>,
+        reconstruct_state: &mut ValueReconstructState,
+        ctx: &RequestContext,
+    ) -> Result<ValueReconstructResult> {
+        ensure!(self.desc.key_range.contains(&key));
+        ensure!(lsn_range.end > self.desc.image_layer_lsn());
+
+        let inner = self
+            .load(LayerAccessKind::GetValueReconstructData, ctx)
+            .await?;
+        inner
+            .get_value_reconstruct_data(key, reconstruct_state, ctx)
+            .await
+    }
+
+    /// Write a new image layer with the images of this one whose key passes
+    /// `keep_key`. The key range of the new layer is shrunk to the keys that remain,
+    /// and its summary is written with the current format version. Returns `None` if
+    /// no images remain.
+    ///
+    /// Keys that are excluded in between the remaining ones stay within the new key
+    /// range, and so no longer exist at this LSN: reads don't fall through to older
+    /// layers for them.
+    ///
+    /// This is only used for debugging purposes, by the 'pagectl' binary.
+    pub async fn rewrite<F>(
+        &self,
+        conf: &'static PageServerConf,
+        keep_key: F,
+        ctx: &RequestContext,
+    ) -> Result<Option<RewrittenLayer>>
+    where
+        F: Fn(&Key) -> bool,
+    {
+        let inner = self.load(LayerAccessKind::Iter, ctx).await?;
+        let entries = inner.load_keys(ctx).await?;
+        let total = entries.len();
+        let kept = entries
+            .into_iter()
+            .filter(|(key, _)| keep_key(key))
+            .collect::<Vec<_>>();
+        let (Some((first, _)), Some((last, _))) = (kept.first(), kept.last()) else {
+            return Ok(None);
+        };
+        let key_range = *first..last.next();
+
+        let mut writer = ImageLayerWriter::new(
+            conf,
+            self.desc.timeline_id,
+            self.desc.tenant_shard_id,
+            &key_range,
+            self.lsn,
+        )
+        .await?;
+        let cursor = inner.file.block_cursor();
+        for (key, offset) in &kept {
+            let img = cursor
+                .read_blob(*offset, ctx)
+                .await
+                .with_context(|| format!("failed to read value from offset {}", offset))?;
+            writer.put_image(*key, &img).await?;
+        }
+        let (desc, temp_path) = writer.finish_standalone().await?;
+
+        Ok(Some(RewrittenLayer {
+            desc,
+            temp_path,
+            values_kept: kept.len(),
+            values_dropped: total - kept.len(),
+        }))
+    }
+
     fn temp_path_for(
         conf: &PageServerConf,
         timeline_id: TimelineId,
@@ -406,6 +484,32 @@ impl ImageLayerInner {
         }))
     }
 
+    /// Load the keys of all images in the layer, in key order, with the offsets
+    /// of their values.
+    pub(super) async fn load_keys(&self, ctx: &RequestContext) -> Result<Vec<(Key, u64)>> {
+        let tree_reader = DiskBtreeReader::<_, KEY_SIZE>::new(
+            self.index_start_blk,
+            self.index_root_blk,
+            &self.file,
+        );
+
+        let mut keys = Vec::new();
+        tree_reader
+            .visit(
+                &[0u8; KEY_SIZE],
+                VisitDirection::Forwards,
+                |key, value| {
+                    keys.push((Key::from_slice(key), value));
+                    true
+                },
+                &RequestContextBuilder::extend(ctx)
+                    .page_content_kind(PageContentKind::ImageLayerBtreeNode)
+                    .build(),
+            )
+            .await?;
+        Ok(keys)
+    }
+
     pub(super) async fn get_value_reconstruct_data(
         &self,
         key: Key,
@@ -539,6 +643,22 @@ impl ImageLayerWriterInner {
     /// Finish writing the image layer.
     ///
     async fn finish(self, timeline: &Arc<Timeline>) -> anyhow::Result<ResidentLayer> {
+        let conf = self.conf;
+        let (desc, path) = self.finish_file().await?;
+
+        // FIXME: why not carry the virtualfile here, it supports renaming?
+        let layer = Layer::finish_creating(conf, timeline, desc, &path)?;
+
+        trace!("created image layer {}", layer.local_path());
+
+        Ok(layer)
+    }
+
+    ///
+    /// Write out the index and the summary, and fsync the file. The file is left
+    /// at its temporary path.
+    ///
+    async fn finish_file(self) -> anyhow::Result<(PersistentLayerDesc, Utf8PathBuf)> {
         let index_start_blk =
             ((self.blob_writer.size() + PAGE_SZ as u64 - 1) / PAGE_SZ as u64) as u32;
 
@@ -596,12 +716,7 @@ impl ImageLayerWriterInner {
         // fsync the file
         file.sync_all().await?;
 
-        // FIXME: why not carry the virtualfile here, it supports renaming?
-        let layer = Layer::finish_creating(self.conf, timeline, desc, &self.path)?;
-
-        trace!("created image layer {}", layer.local_path());
-
-        Ok(layer)
+        Ok((desc, self.path))
     }
 }
 
@@ -668,6 +783,17 @@ impl ImageLayerWriter {
     ) -> anyhow::Result<super::ResidentLayer> {
         self.inner.take().unwrap().finish(timeline).await
     }
+
+    ///
+    /// Finish writing the image layer without adding it to a timeline. Returns the
+    /// descriptor of the new layer and the temporary path it was written to; the
+    /// caller is responsible for moving it into place.
+    ///
+    /// This variant is only used for debugging purposes, by the 'pagectl' binary.
+    ///
+    pub async fn finish_standalone(mut self) -> anyhow::Result<(PersistentLayerDesc, Utf8PathBuf)> {
+        self.inner.take().unwrap().finish_file().await
+    }
 }
 
 impl Drop for ImageLayerWriter {
@@ -677,3 +803,81 @@ impl Drop for ImageLayerWriter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::DownloadBehavior, task_mgr::TaskKind};
+
+    fn key(blknum: u32) -> Key {
+        let mut key = Key::from_hex("000000067F00000001000000010000000000").unwrap();
+        key.field6 = blknum;
+        key
+    }
+
+    #[tokio::test]
+    async fn rewrite_excludes_keys() -> anyhow::Result<()> {
+        let temp_dir = camino_tempfile::tempdir()?;
+        let conf: &'static PageServerConf = Box::leak(Box::new(PageServerConf::dummy_conf(
+            temp_dir.path().to_owned(),
+        )));
+        let ctx = RequestContext::new(TaskKind::UnitTest, DownloadBehavior::Error);
+        let tenant_shard_id = TenantShardId::unsharded(TenantId::generate());
+        let timeline_id = TimelineId::generate();
+        std::fs::create_dir_all(conf.timeline_path(&tenant_shard_id, &timeline_id))?;
+
+        let lsn = Lsn(0x10);
+        let mut writer =
+            ImageLayerWriter::new(conf, timeline_id, tenant_shard_id, &(key(0)..key(100)), lsn)
+                .await?;
+        for blknum in 10..20 {
+            writer
+                .put_image(key(blknum), format!("image {blknum}").as_bytes())
+                .await?;
+        }
+        let (_, path) = writer.finish_standalone().await?;
+        let layer = ImageLayer::new_for_path(&path, File::open(&path)?)?;
+
+        // drop 12..14 in the middle, and everything from 17 on
+        let rewritten = layer
+            .rewrite(
+                conf,
+                |k| !(key(12)..key(14)).contains(k) && *k < key(17),
+                &ctx,
+            )
+            .await?
+            .expect("some images are kept");
+        assert_eq!(rewritten.values_kept, 5);
+        assert_eq!(rewritten.values_dropped, 5);
+
+        let path = &rewritten.temp_path;
+        let summary = Summary::des_prefix(&std::fs::read(path)?)?;
+        assert_eq!(summary.magic, IMAGE_FILE_MAGIC);
+        assert_eq!(summary.format_version, STORAGE_FORMAT_VERSION);
+        assert_eq!(summary.key_range, key(10)..key(17));
+        assert_eq!(summary.lsn, lsn);
+
+        let layer = ImageLayer::new_for_path(path, File::open(path)?)?;
+        assert_eq!(layer.desc, rewritten.desc);
+        for blknum in 10..17 {
+            let mut state = ValueReconstructState {
+                records: Vec::new(),
+                img: None,
+            };
+            let res = layer
+                .get_value_reconstruct_data(key(blknum), lsn..Lsn(0x20), &mut state, &ctx)
+                .await?;
+            if (12..14).contains(&blknum) {
+                assert!(matches!(res, ValueReconstructResult::Missing), "{blknum}");
+            } else {
+                assert!(matches!(res, ValueReconstructResult::Complete), "{blknum}");
+                let expected = Bytes::from(format!("image {blknum}"));
+                assert_eq!(state.img, Some((lsn, expected)));
+            }
+        }
+
+        // nothing left
+        assert!(layer.rewrite(conf, |_| false, &ctx).await?.is_none());
+        Ok(())
+    }
+}
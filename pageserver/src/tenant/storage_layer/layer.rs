@@ -15,6 +15,7 @@ use utils::sync::heavier_once_cell;
 use crate::config::PageServerConf;
 use crate::context::RequestContext;
 use crate::repository::Key;
+use crate::tenant::remote_timeline_client::throttle::RequestClass;
 use crate::tenant::{remote_timeline_client::LayerFileMetadata, RemoteTimelineClient, Timeline};
 
 use super::delta_layer::{self, DeltaEntry};
@@ -658,6 +659,7 @@ impl LayerInner {
         ctx: Option<&RequestContext>,
     ) -> Result<Arc<DownloadedLayer>, DownloadError> {
         let mut init_permit = None;
+        let class = RequestClass::for_download(ctx);
 
         loop {
             let download = move |permit| async move {
@@ -713,7 +715,8 @@ impl LayerInner {
 
                     tracing::info!(%reason, "downloading on-demand");
 
-                    self.spawn_download_and_wait(timeline, permit).await?
+                    self.spawn_download_and_wait(timeline, permit, class)
+                        .await?
                 } else {
                     // the file is present locally, probably by a previous but cancelled call to
                     // get_or_maybe_download. alternatively we might be running without remote storage.
@@ -824,6 +827,7 @@ impl LayerInner {
         self: &Arc<Self>,
         timeline: Arc<Timeline>,
         permit: heavier_once_cell::InitPermit,
+        class: RequestClass,
     ) -> Result<heavier_once_cell::InitPermit, DownloadError> {
         let task_name = format!("download layer {}", self);
 
@@ -851,6 +855,8 @@ impl LayerInner {
                 let result = client.download_layer_file(
                     &this.desc.filename(),
                     &this.metadata(),
+                    class,
+                    &timeline.cancel,
                 )
                 .await;
 
@@ -276,6 +276,9 @@ async fn gc_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
                     wait_duration
                 } else {
                     error_run_count = 0;
+                    if let Err(e) = tenant.cleanup_split_ancestors(&cancel).await {
+                        warn!("Failed to clean up the remote layers of split ancestors: {e:#}");
+                    }
                     period
                 }
             };
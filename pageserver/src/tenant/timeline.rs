@@ -3015,6 +3015,13 @@ impl Timeline {
                 for range in &partition.ranges {
                     let mut key = range.start;
                     while key < range.end {
+                        if self.shard_identity.is_key_disposable(&key) {
+                            // After a shard split, we inherit all of our parent's keys: drop
+                            // the ones that now belong to other shards as we rewrite them.
+                            key = key.next();
+                            continue;
+                        }
+
                         let img = match self.get(key, lsn, ctx).await {
                             Ok(img) => img,
                             Err(err) => {
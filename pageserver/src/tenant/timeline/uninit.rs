@@ -5,7 +5,11 @@ use camino::Utf8PathBuf;
 use tracing::{error, info, info_span, warn};
 use utils::{crashsafe, fs_ext, id::TimelineId, lsn::Lsn};
 
-use crate::{context::RequestContext, import_datadir, tenant::Tenant};
+use crate::{
+    context::RequestContext,
+    import_datadir::{self, ImportProgress},
+    tenant::Tenant,
+};
 
 use super::Timeline;
 
@@ -96,14 +100,21 @@ impl<'t> UninitializedTimeline<'t> {
         self,
         copyin_read: &mut (impl tokio::io::AsyncRead + Send + Sync + Unpin),
         base_lsn: Lsn,
+        progress: &ImportProgress,
         broker_client: storage_broker::BrokerClientChannel,
         ctx: &RequestContext,
     ) -> anyhow::Result<Arc<Timeline>> {
         let raw_timeline = self.raw_timeline()?;
 
-        import_datadir::import_basebackup_from_tar(raw_timeline, copyin_read, base_lsn, ctx)
-            .await
-            .context("Failed to import basebackup")?;
+        import_datadir::import_basebackup_from_tar(
+            raw_timeline,
+            copyin_read,
+            base_lsn,
+            progress,
+            ctx,
+        )
+        .await
+        .context("Failed to import basebackup")?;
 
         // Flush the new layer files to disk, before we make the timeline as available to
         // the outside world.
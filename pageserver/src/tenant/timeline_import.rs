@@ -0,0 +1,347 @@
+//! Timeline imports started via the management API.
+//!
+//! An import reads a tar archive from a file on the pageserver's local filesystem or
+//! from an object in remote storage: either a `pg_basebackup` tarball, to create a new
+//! timeline from, or WAL segments to append to an existing timeline. The import runs
+//! as a background task, and its progress is kept in the [`Tenant`] until the next
+//! import into the same timeline, see [`Tenant::get_timeline_import_info`].
+//!
+//! A failed basebackup import leaves nothing behind: the new timeline is only made
+//! visible once all of its data has been flushed, and its files are removed otherwise,
+//! see [`UninitializedTimeline`](super::timeline::uninit::UninitializedTimeline). A
+//! failed WAL import keeps the WAL that was imported before the failure. It can be
+//! resumed with the same archive, from the timeline's last record LSN.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use anyhow::Context as _;
+use pageserver_api::models::{
+    TimelineImportBasebackupRequest, TimelineImportInfo, TimelineImportKind, TimelineImportSource,
+    TimelineImportState, TimelineImportWalRequest,
+};
+use remote_storage::RemotePath;
+use storage_broker::BrokerClientChannel;
+use tokio::io::{AsyncRead, BufReader, ReadBuf};
+use tokio_util::io::StreamReader;
+use tracing::{error, info, info_span, Instrument};
+use utils::id::TimelineId;
+
+use super::Tenant;
+use crate::context::{DownloadBehavior, RequestContext};
+use crate::import_datadir::{import_wal_from_tar, ImportProgress};
+use crate::task_mgr::{self, PageserverTaskId, TaskKind};
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum TimelineImportError {
+    #[error("an import into this timeline is already running")]
+    AlreadyRunning(Box<TimelineImportInfo>),
+    #[error("timeline already exists")]
+    TimelineExists,
+    #[error(transparent)]
+    BadRequest(anyhow::Error),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+type ImportReader = Box<dyn AsyncRead + Send + Sync + Unpin>;
+
+/// The latest import into each timeline, and the task running it.
+pub(crate) type TimelineImportJobs =
+    HashMap<TimelineId, (PageserverTaskId, Arc<TimelineImportJob>)>;
+
+/// State of one import, shared between its task and the status endpoint.
+pub(crate) struct TimelineImportJob {
+    kind: TimelineImportKind,
+    bytes_total: Option<u64>,
+    bytes_read: AtomicU64,
+    progress: ImportProgress,
+    outcome: Mutex<(TimelineImportState, Option<String>)>,
+}
+
+impl TimelineImportJob {
+    fn info(&self, task_id: PageserverTaskId) -> TimelineImportInfo {
+        let (state, error) = self.outcome.lock().unwrap().clone();
+        let last_record_lsn = self.progress.last_record_lsn.load();
+        TimelineImportInfo {
+            task_id: format!("{task_id}"),
+            kind: self.kind,
+            state,
+            bytes_total: self.bytes_total,
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            files_imported: self.progress.files_imported.load(Ordering::Relaxed),
+            checksum_failures: self.progress.checksum_failures.load(Ordering::Relaxed),
+            last_record_lsn: last_record_lsn.is_valid().then_some(last_record_lsn),
+            error,
+        }
+    }
+}
+
+/// Counts the bytes read from the archive, for reporting progress.
+struct CountingReader {
+    inner: ImportReader,
+    job: Arc<TimelineImportJob>,
+}
+
+impl AsyncRead for CountingReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let n = buf.filled().len() - filled_before;
+        self.job.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+        res
+    }
+}
+
+impl Tenant {
+    /// Start importing a `pg_basebackup` tarball as the new timeline `timeline_id`.
+    pub(crate) async fn spawn_import_basebackup(
+        self: &Arc<Self>,
+        timeline_id: TimelineId,
+        request: TimelineImportBasebackupRequest,
+        broker_client: BrokerClientChannel,
+    ) -> Result<TimelineImportInfo, TimelineImportError> {
+        self.check_can_import(timeline_id)?;
+        if self.get_timeline(timeline_id, false).is_ok() {
+            return Err(TimelineImportError::TimelineExists);
+        }
+        let (reader, bytes_total) = self.open_import_source(&request.source).await?;
+        let progress = ImportProgress::new(!request.skip_checksum_verification);
+
+        let tenant = Arc::clone(self);
+        self.spawn_timeline_import(
+            timeline_id,
+            TimelineImportKind::Basebackup,
+            progress,
+            bytes_total,
+            reader,
+            move |mut reader, job, ctx| async move {
+                let timeline = tenant
+                    .create_empty_timeline(timeline_id, request.base_lsn, request.pg_version, &ctx)
+                    .await?;
+                timeline
+                    .import_basebackup_from_tar(
+                        &mut reader,
+                        request.base_lsn,
+                        &job.progress,
+                        broker_client,
+                        &ctx,
+                    )
+                    .await?;
+                Ok(())
+            },
+        )
+    }
+
+    /// Start importing WAL segments into the existing timeline `timeline_id`.
+    pub(crate) async fn spawn_import_wal(
+        self: &Arc<Self>,
+        timeline_id: TimelineId,
+        request: TimelineImportWalRequest,
+    ) -> Result<TimelineImportInfo, TimelineImportError> {
+        self.check_can_import(timeline_id)?;
+        let timeline = self
+            .get_timeline(timeline_id, true)
+            .map_err(|e| TimelineImportError::BadRequest(e.into()))?;
+        let last_record_lsn = timeline.get_last_record_lsn();
+        let start_lsn = request.start_lsn.unwrap_or(last_record_lsn);
+        if start_lsn != last_record_lsn {
+            return Err(TimelineImportError::BadRequest(anyhow::anyhow!(
+                "cannot import WAL from {start_lsn}, the timeline ends at {last_record_lsn}"
+            )));
+        }
+        if request.end_lsn <= start_lsn {
+            return Err(TimelineImportError::BadRequest(anyhow::anyhow!(
+                "end LSN {} is not after start LSN {start_lsn}",
+                request.end_lsn
+            )));
+        }
+        let (reader, bytes_total) = self.open_import_source(&request.source).await?;
+        let progress = ImportProgress::new(false);
+        progress.last_record_lsn.store(start_lsn);
+
+        self.spawn_timeline_import(
+            timeline_id,
+            TimelineImportKind::Wal,
+            progress,
+            bytes_total,
+            reader,
+            move |mut reader, job, ctx| async move {
+                let end_lsn = request.end_lsn;
+                import_wal_from_tar(
+                    &timeline,
+                    &mut reader,
+                    start_lsn,
+                    end_lsn,
+                    &job.progress,
+                    &ctx,
+                )
+                .await?;
+
+                let last_record_lsn = timeline.get_last_record_lsn();
+                anyhow::ensure!(
+                    last_record_lsn >= end_lsn,
+                    "WAL archive ends at {last_record_lsn}, before {end_lsn}"
+                );
+
+                // Persist the imported WAL. It doesn't matter if it ends up in delta or
+                // image layers, so there's no need for a forced checkpoint.
+                timeline.freeze_and_flush().await?;
+                Ok(())
+            },
+        )
+    }
+
+    pub(crate) fn get_timeline_import_info(
+        &self,
+        timeline_id: TimelineId,
+    ) -> Option<TimelineImportInfo> {
+        let jobs = self.import_jobs.lock().unwrap();
+        jobs.get(&timeline_id)
+            .map(|(task_id, job)| job.info(*task_id))
+    }
+
+    fn check_can_import(&self, timeline_id: TimelineId) -> Result<(), TimelineImportError> {
+        if !self.shard_identity.is_unsharded() {
+            return Err(TimelineImportError::BadRequest(anyhow::anyhow!(
+                "importing into a sharded tenant is not supported"
+            )));
+        }
+        match self.get_timeline_import_info(timeline_id) {
+            Some(info) if info.state == TimelineImportState::Running => {
+                Err(TimelineImportError::AlreadyRunning(Box::new(info)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Open the archive to import. Errors here are reported to the caller directly,
+    /// rather than through the status of the import.
+    async fn open_import_source(
+        &self,
+        source: &TimelineImportSource,
+    ) -> Result<(ImportReader, Option<u64>), TimelineImportError> {
+        match source {
+            TimelineImportSource::LocalPath { path } => {
+                let file = tokio::fs::File::open(path)
+                    .await
+                    .with_context(|| format!("open {path}"))
+                    .map_err(TimelineImportError::BadRequest)?;
+                let len = file
+                    .metadata()
+                    .await
+                    .with_context(|| format!("stat {path}"))
+                    .map_err(TimelineImportError::Other)?
+                    .len();
+                Ok((Box::new(BufReader::new(file)), Some(len)))
+            }
+            TimelineImportSource::RemotePath { path } => {
+                let Some(remote_storage) = &self.remote_storage else {
+                    return Err(TimelineImportError::BadRequest(anyhow::anyhow!(
+                        "remote storage is not configured"
+                    )));
+                };
+                let remote_path =
+                    RemotePath::from_string(path).map_err(TimelineImportError::BadRequest)?;
+                let download = remote_storage
+                    .download(&remote_path, &self.cancel)
+                    .await
+                    .with_context(|| format!("download {path}"))
+                    .map_err(TimelineImportError::BadRequest)?;
+                let reader = StreamReader::new(download.download_stream);
+                Ok((Box::new(BufReader::new(reader)), None))
+            }
+        }
+    }
+
+    fn spawn_timeline_import<F, Fut>(
+        self: &Arc<Self>,
+        timeline_id: TimelineId,
+        kind: TimelineImportKind,
+        progress: ImportProgress,
+        bytes_total: Option<u64>,
+        reader: ImportReader,
+        import: F,
+    ) -> Result<TimelineImportInfo, TimelineImportError>
+    where
+        F: FnOnce(CountingReader, Arc<TimelineImportJob>, RequestContext) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let gate_guard = self
+            .gate
+            .enter()
+            .map_err(|_| TimelineImportError::Other(anyhow::anyhow!("tenant is shutting down")))?;
+
+        // Check again under the lock, there may have been a concurrent request
+        let mut jobs = self.import_jobs.lock().unwrap();
+        if let Some((task_id, job)) = jobs.get(&timeline_id) {
+            let info = job.info(*task_id);
+            if info.state == TimelineImportState::Running {
+                return Err(TimelineImportError::AlreadyRunning(Box::new(info)));
+            }
+        }
+
+        let job = Arc::new(TimelineImportJob {
+            kind,
+            bytes_total,
+            bytes_read: AtomicU64::new(0),
+            progress,
+            outcome: Mutex::new((TimelineImportState::Running, None)),
+        });
+        let reader = CountingReader {
+            inner: reader,
+            job: Arc::clone(&job),
+        };
+        let ctx = RequestContext::new(TaskKind::TimelineImport, DownloadBehavior::Download);
+        let tenant_cancel = self.cancel.clone();
+
+        let task_job = Arc::clone(&job);
+        let task_id = task_mgr::spawn(
+            task_mgr::BACKGROUND_RUNTIME.handle(),
+            TaskKind::TimelineImport,
+            Some(self.tenant_shard_id),
+            Some(timeline_id),
+            "timeline import",
+            false,
+            async move {
+                let _gate_guard = gate_guard;
+                info!("starting {kind:?} import");
+                let res = tokio::select! {
+                    res = import(reader, Arc::clone(&task_job), ctx) => res,
+                    _ = task_mgr::shutdown_watcher() => Err(anyhow::anyhow!("cancelled")),
+                    _ = tenant_cancel.cancelled() => Err(anyhow::anyhow!("cancelled")),
+                };
+                let outcome = match res {
+                    Ok(()) => {
+                        info!("import complete");
+                        (TimelineImportState::Completed, None)
+                    }
+                    Err(e) => {
+                        error!("import failed: {e:#}");
+                        (TimelineImportState::Failed, Some(format!("{e:#}")))
+                    }
+                };
+                *task_job.outcome.lock().unwrap() = outcome;
+                Ok(())
+            }
+            .instrument(info_span!(
+                parent: None,
+                "timeline_import",
+                tenant_id = %self.tenant_shard_id.tenant_id,
+                shard_id = %self.tenant_shard_id.shard_slug(),
+                %timeline_id
+            )),
+        );
+
+        let info = job.info(task_id);
+        jobs.insert(timeline_id, (task_id, job));
+        Ok(info)
+    }
+}
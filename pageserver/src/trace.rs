@@ -1,8 +1,10 @@
 use bytes::Bytes;
 use camino::Utf8PathBuf;
+use pageserver_api::trace::{write_header, write_record};
 use std::{
     fs::{create_dir_all, File},
     io::{BufWriter, Write},
+    time::SystemTime,
 };
 
 pub struct Tracer {
@@ -21,13 +23,13 @@ impl Tracer {
         create_dir_all(parent).expect("failed to create trace dir");
 
         let file = File::create(path).expect("failed to create trace file");
-        Tracer {
-            writer: BufWriter::new(file),
-        }
+        let mut writer = BufWriter::new(file);
+        write_header(&mut writer).expect("failed to write trace header");
+        Tracer { writer }
     }
 
     pub fn trace(&mut self, msg: &Bytes) {
-        self.writer.write_all(msg).expect("failed to write trace");
+        write_record(&mut self.writer, SystemTime::now(), msg).expect("failed to write trace");
     }
 
     pub fn flush(&mut self) {
@@ -18,6 +18,7 @@
 #include "fmgr.h"
 #include "access/xlog.h"
 #include "access/xlogutils.h"
+#include "common/hashfn.h"
 #include "storage/buf_internals.h"
 #include "storage/lwlock.h"
 #include "storage/ipc.h"
@@ -32,6 +33,7 @@
 #include "miscadmin.h"
 #include "pgstat.h"
 #include "utils/guc.h"
+#include "utils/varlena.h"
 
 #include "neon.h"
 #include "walproposer.h"
@@ -41,23 +43,44 @@
 
 #define RECONNECT_INTERVAL_USEC 1000000
 
-bool		connected = false;
-PGconn	   *pageserver_conn = NULL;
+#define MAX_PAGESERVER_CONNSTRING_SIZE 256
 
 /*
- * WaitEventSet containing:
- * - WL_SOCKET_READABLE on pageserver_conn,
- * - WL_LATCH_SET on MyLatch, and
- * - WL_EXIT_ON_PM_DEATH.
+ * Connection strings of the tenant's pageservers, one per shard, indexed by
+ * shard number. An unsharded tenant has a single entry, taken from
+ * neon.pageserver_connstring; a sharded one gets them from the
+ * neon.pageserver_connstrings list.
  */
-WaitEventSet *pageserver_conn_wes = NULL;
+typedef struct
+{
+	size_t		num_shards;
+	char		connstring[MAX_SHARDS][MAX_PAGESERVER_CONNSTRING_SIZE];
+} ShardMap;
+
+/* Connection to the pageserver of one shard */
+typedef struct
+{
+	PGconn	   *conn;
+
+	/*
+	 * WaitEventSet containing:
+	 * - WL_SOCKET_READABLE on conn,
+	 * - WL_LATCH_SET on MyLatch, and
+	 * - WL_EXIT_ON_PM_DEATH.
+	 */
+	WaitEventSet *wes;
+} PageServer;
+
+static PageServer page_servers[MAX_SHARDS];
 
 /* GUCs */
 char	   *neon_timeline;
 char	   *neon_tenant;
 int32		max_cluster_size;
 char	   *page_server_connstring;
+char	   *page_server_connstrings;
 char	   *neon_auth_token;
+int			stripe_size;
 
 int			readahead_buffer_size = 128;
 int			flush_every_n_requests = 8;
@@ -65,13 +88,17 @@ int			flush_every_n_requests = 8;
 int			n_reconnect_attempts = 0;
 int			max_reconnect_attempts = 60;
 
-#define MAX_PAGESERVER_CONNSTRING_SIZE 256
-
+/*
+ * The shard map and stripe size are kept in shared memory, so that every
+ * backend notices a change (e.g. after a shard split) on its next request,
+ * and picks up both values together.
+ */
 typedef struct
 {
 	LWLockId	lock;
 	pg_atomic_uint64 update_counter;
-	char		pageserver_connstring[MAX_PAGESERVER_CONNSTRING_SIZE];
+	int			stripe_size;
+	ShardMap	shard_map;
 } PagestoreShmemState;
 
 #if PG_VERSION_NUM >= 150000
@@ -81,12 +108,14 @@ static void walproposer_shmem_request(void);
 static shmem_startup_hook_type prev_shmem_startup_hook;
 static PagestoreShmemState *pagestore_shared;
 static uint64 pagestore_local_counter = 0;
-static char local_pageserver_connstring[MAX_PAGESERVER_CONNSTRING_SIZE];
+static ShardMap local_shard_map;
+static int	local_stripe_size;
 
 bool		(*old_redo_read_buffer_filter) (XLogReaderState *record, uint8 block_id) = NULL;
 
 static bool pageserver_flush(void);
 static void pageserver_disconnect(void);
+static bool pageserver_disconnect_shard(shardno_t shard_no);
 
 static bool
 PagestoreShmemIsValid()
@@ -94,19 +123,122 @@ PagestoreShmemIsValid()
 	return pagestore_shared && UsedShmemSegAddr;
 }
 
+/*
+ * Fill 'result' with the connection strings of the tenant's shards: the
+ * 'connstrings' list if it's set, or else the single 'connstring' of an
+ * unsharded tenant. If 'result' is NULL, only check that they are valid.
+ */
+static bool
+ParseShardMap(const char *connstring, const char *connstrings, ShardMap *result)
+{
+	char	   *rawstring;
+	List	   *elemlist;
+	ListCell   *lc;
+	size_t		nshards = 0;
+	bool		ok = true;
+
+	if (result)
+		memset(result, 0, sizeof(ShardMap));
+
+	if (connstrings == NULL || connstrings[0] == '\0')
+	{
+		/* An empty string means no pageserver is configured */
+		if (connstring == NULL || connstring[0] == '\0')
+			return true;
+		if (strlen(connstring) >= MAX_PAGESERVER_CONNSTRING_SIZE)
+		{
+			GUC_check_errdetail("Connection string too long.");
+			return false;
+		}
+		if (result)
+		{
+			strlcpy(result->connstring[0], connstring, MAX_PAGESERVER_CONNSTRING_SIZE);
+			result->num_shards = 1;
+		}
+		return true;
+	}
+
+	/* Elements with spaces or commas in them must be double-quoted */
+	rawstring = pstrdup(connstrings);
+	if (!SplitGUCList(rawstring, ',', &elemlist))
+	{
+		GUC_check_errdetail("List syntax is invalid.");
+		ok = false;
+	}
+	else
+	{
+		foreach(lc, elemlist)
+		{
+			const char *elem = (const char *) lfirst(lc);
+
+			if (strlen(elem) >= MAX_PAGESERVER_CONNSTRING_SIZE)
+			{
+				GUC_check_errdetail("Connection string too long.");
+				ok = false;
+				break;
+			}
+			if (nshards >= MAX_SHARDS)
+			{
+				GUC_check_errdetail("Too many shards: at most %d are supported.", MAX_SHARDS);
+				ok = false;
+				break;
+			}
+			if (result)
+				strlcpy(result->connstring[nshards], elem, MAX_PAGESERVER_CONNSTRING_SIZE);
+			nshards++;
+		}
+	}
+	list_free(elemlist);
+	pfree(rawstring);
+
+	if (ok && result)
+		result->num_shards = nshards;
+	return ok;
+}
+
 static bool
 CheckPageserverConnstring(char **newval, void **extra, GucSource source)
 {
-	return strlen(*newval) < MAX_PAGESERVER_CONNSTRING_SIZE;
+	return ParseShardMap(*newval, NULL, NULL);
+}
+
+static bool
+CheckPageserverConnstrings(char **newval, void **extra, GucSource source)
+{
+	return ParseShardMap(NULL, *newval, NULL);
 }
 
+static void
+UpdateShardMap(const char *connstring, const char *connstrings)
+{
+	if (!PagestoreShmemIsValid())
+		return;
+	LWLockAcquire(pagestore_shared->lock, LW_EXCLUSIVE);
+	ParseShardMap(connstring, connstrings, &pagestore_shared->shard_map);
+	pg_atomic_fetch_add_u64(&pagestore_shared->update_counter, 1);
+	LWLockRelease(pagestore_shared->lock);
+}
+
+/* The GUC variable still holds the old value while an assign hook runs */
 static void
 AssignPageserverConnstring(const char *newval, void *extra)
+{
+	UpdateShardMap(newval, page_server_connstrings);
+}
+
+static void
+AssignPageserverConnstrings(const char *newval, void *extra)
+{
+	UpdateShardMap(page_server_connstring, newval);
+}
+
+static void
+AssignStripeSize(int newval, void *extra)
 {
 	if (!PagestoreShmemIsValid())
 		return;
 	LWLockAcquire(pagestore_shared->lock, LW_EXCLUSIVE);
-	strlcpy(pagestore_shared->pageserver_connstring, newval, MAX_PAGESERVER_CONNSTRING_SIZE);
+	pagestore_shared->stripe_size = newval;
 	pg_atomic_fetch_add_u64(&pagestore_shared->update_counter, 1);
 	LWLockRelease(pagestore_shared->lock);
 }
@@ -125,30 +257,62 @@ ReloadConnstring()
 	if (!PagestoreShmemIsValid())
 		return;
 	LWLockAcquire(pagestore_shared->lock, LW_SHARED);
-	strlcpy(local_pageserver_connstring, pagestore_shared->pageserver_connstring, sizeof(local_pageserver_connstring));
+	memcpy(&local_shard_map, &pagestore_shared->shard_map, sizeof(ShardMap));
+	local_stripe_size = pagestore_shared->stripe_size;
 	pagestore_local_counter = pg_atomic_read_u64(&pagestore_shared->update_counter);
 	LWLockRelease(pagestore_shared->lock);
 }
 
+/*
+ * Pick up a new shard map, if there is one. Requests in flight were sent
+ * according to the old map, so all connections are dropped first.
+ */
+static void
+RefreshShardMap(void)
+{
+	if (CheckConnstringUpdated())
+	{
+		pageserver_disconnect();
+		ReloadConnstring();
+	}
+}
+
+/*
+ * Get the shard that serves a page. This must match the pageserver's
+ * key_to_shard_number(): blocks are distributed in stripes of 'stripe_size'
+ * pages, hashed together with the relation number.
+ */
+shardno_t
+get_shard_number(BufferTag *tag)
+{
+	uint32		hash;
+
+	RefreshShardMap();
+
+	if (local_shard_map.num_shards < 2)
+		return 0;
+
+	hash = murmurhash32(NInfoGetRelNumber(BufTagGetNRelFileInfo((*tag))));
+	hash = hash_combine(hash, murmurhash32(tag->blockNum / local_stripe_size));
+
+	return hash % local_shard_map.num_shards;
+}
+
 static bool
-pageserver_connect(int elevel)
+pageserver_connect(shardno_t shard_no, int elevel)
 {
+	PageServer *shard = &page_servers[shard_no];
 	char	   *query;
 	int			ret;
 	const char *keywords[3];
 	const char *values[3];
 	int			n;
 
-	Assert(!connected);
-
-	if (CheckConnstringUpdated())
-	{
-		ReloadConnstring();
-	}
+	Assert(shard->conn == NULL);
 
 	/*
-	 * Connect using the connection string we got from the
-	 * neon.pageserver_connstring GUC. If the NEON_AUTH_TOKEN environment
+	 * Connect using the shard's connection string from the shard map, see
+	 * ParseShardMap(). If the NEON_AUTH_TOKEN environment
 	 * variable was set, use that as the password.
 	 *
 	 * The connection options are parsed in the order they're given, so when
@@ -164,19 +328,19 @@ pageserver_connect(int elevel)
 		n++;
 	}
 	keywords[n] = "dbname";
-	values[n] = local_pageserver_connstring;
+	values[n] = local_shard_map.connstring[shard_no];
 	n++;
 	keywords[n] = NULL;
 	values[n] = NULL;
 	n++;
-	pageserver_conn = PQconnectdbParams(keywords, values, 1);
+	shard->conn = PQconnectdbParams(keywords, values, 1);
 
-	if (PQstatus(pageserver_conn) == CONNECTION_BAD)
+	if (PQstatus(shard->conn) == CONNECTION_BAD)
 	{
-		char	   *msg = pchomp(PQerrorMessage(pageserver_conn));
+		char	   *msg = pchomp(PQerrorMessage(shard->conn));
 
-		PQfinish(pageserver_conn);
-		pageserver_conn = NULL;
+		PQfinish(shard->conn);
+		shard->conn = NULL;
 
 		ereport(elevel,
 				(errcode(ERRCODE_SQLCLIENT_UNABLE_TO_ESTABLISH_SQLCONNECTION),
@@ -186,28 +350,28 @@ pageserver_connect(int elevel)
 	}
 
 	query = psprintf("pagestream %s %s", neon_tenant, neon_timeline);
-	ret = PQsendQuery(pageserver_conn, query);
+	ret = PQsendQuery(shard->conn, query);
 	if (ret != 1)
 	{
-		PQfinish(pageserver_conn);
-		pageserver_conn = NULL;
+		PQfinish(shard->conn);
+		shard->conn = NULL;
 		neon_log(elevel, "could not send pagestream command to pageserver");
 		return false;
 	}
 
-	pageserver_conn_wes = CreateWaitEventSet(TopMemoryContext, 3);
-	AddWaitEventToSet(pageserver_conn_wes, WL_LATCH_SET, PGINVALID_SOCKET,
+	shard->wes = CreateWaitEventSet(TopMemoryContext, 3);
+	AddWaitEventToSet(shard->wes, WL_LATCH_SET, PGINVALID_SOCKET,
 					  MyLatch, NULL);
-	AddWaitEventToSet(pageserver_conn_wes, WL_EXIT_ON_PM_DEATH, PGINVALID_SOCKET,
+	AddWaitEventToSet(shard->wes, WL_EXIT_ON_PM_DEATH, PGINVALID_SOCKET,
 					  NULL, NULL);
-	AddWaitEventToSet(pageserver_conn_wes, WL_SOCKET_READABLE, PQsocket(pageserver_conn), NULL, NULL);
+	AddWaitEventToSet(shard->wes, WL_SOCKET_READABLE, PQsocket(shard->conn), NULL, NULL);
 
-	while (PQisBusy(pageserver_conn))
+	while (PQisBusy(shard->conn))
 	{
 		WaitEvent	event;
 
 		/* Sleep until there's something to do */
-		(void) WaitEventSetWait(pageserver_conn_wes, -1L, &event, 1, PG_WAIT_EXTENSION);
+		(void) WaitEventSetWait(shard->wes, -1L, &event, 1, PG_WAIT_EXTENSION);
 		ResetLatch(MyLatch);
 
 		CHECK_FOR_INTERRUPTS();
@@ -215,14 +379,14 @@ pageserver_connect(int elevel)
 		/* Data available in socket? */
 		if (event.events & WL_SOCKET_READABLE)
 		{
-			if (!PQconsumeInput(pageserver_conn))
+			if (!PQconsumeInput(shard->conn))
 			{
-				char	   *msg = pchomp(PQerrorMessage(pageserver_conn));
+				char	   *msg = pchomp(PQerrorMessage(shard->conn));
 
-				PQfinish(pageserver_conn);
-				pageserver_conn = NULL;
-				FreeWaitEventSet(pageserver_conn_wes);
-				pageserver_conn_wes = NULL;
+				PQfinish(shard->conn);
+				shard->conn = NULL;
+				FreeWaitEventSet(shard->wes);
+				shard->wes = NULL;
 
 				neon_log(elevel, "could not complete handshake with pageserver: %s",
 						 msg);
@@ -231,9 +395,9 @@ pageserver_connect(int elevel)
 		}
 	}
 
-	neon_log(LOG, "libpagestore: connected to '%s'", page_server_connstring);
+	neon_log(LOG, "libpagestore: connected to shard %d at '%s'",
+			 shard_no, local_shard_map.connstring[shard_no]);
 
-	connected = true;
 	return true;
 }
 
@@ -241,19 +405,19 @@ pageserver_connect(int elevel)
  * A wrapper around PQgetCopyData that checks for interrupts while sleeping.
  */
 static int
-call_PQgetCopyData(char **buffer)
+call_PQgetCopyData(PageServer *shard, char **buffer)
 {
 	int			ret;
 
 retry:
-	ret = PQgetCopyData(pageserver_conn, buffer, 1 /* async */ );
+	ret = PQgetCopyData(shard->conn, buffer, 1 /* async */ );
 
 	if (ret == 0)
 	{
 		WaitEvent	event;
 
 		/* Sleep until there's something to do */
-		(void) WaitEventSetWait(pageserver_conn_wes, -1L, &event, 1, PG_WAIT_EXTENSION);
+		(void) WaitEventSetWait(shard->wes, -1L, &event, 1, PG_WAIT_EXTENSION);
 		ResetLatch(MyLatch);
 
 		CHECK_FOR_INTERRUPTS();
@@ -261,9 +425,9 @@ retry:
 		/* Data available in socket? */
 		if (event.events & WL_SOCKET_READABLE)
 		{
-			if (!PQconsumeInput(pageserver_conn))
+			if (!PQconsumeInput(shard->conn))
 			{
-				char	   *msg = pchomp(PQerrorMessage(pageserver_conn));
+				char	   *msg = pchomp(PQerrorMessage(shard->conn));
 
 				neon_log(LOG, "could not get response from pageserver: %s", msg);
 				pfree(msg);
@@ -278,45 +442,67 @@ retry:
 }
 
 
+static bool
+pageserver_disconnect_shard(shardno_t shard_no)
+{
+	PageServer *shard = &page_servers[shard_no];
+	bool		was_connected = shard->conn != NULL;
+
+	if (shard->conn != NULL)
+	{
+		neon_log(LOG, "dropping connection to shard %d", shard_no);
+		PQfinish(shard->conn);
+		shard->conn = NULL;
+	}
+	if (shard->wes != NULL)
+	{
+		FreeWaitEventSet(shard->wes);
+		shard->wes = NULL;
+	}
+	return was_connected;
+}
+
 static void
 pageserver_disconnect(void)
 {
+	bool		was_connected = false;
+	shardno_t	shard_no;
+
 	/*
 	 * If anything goes wrong while we were sending a request, it's not clear
 	 * what state the connection is in. For example, if we sent the request
 	 * but didn't receive a response yet, we might receive the response some
 	 * time later after we have already sent a new unrelated request. Close
 	 * the connection to avoid getting confused.
+	 *
+	 * The prefetch state tracks requests to all shards in a single queue, so
+	 * the connections to the other shards are closed too.
 	 */
-	if (connected)
+	for (shard_no = 0; shard_no < MAX_SHARDS; shard_no++)
 	{
-		neon_log(LOG, "dropping connection to page server due to error");
-		PQfinish(pageserver_conn);
-		pageserver_conn = NULL;
-		connected = false;
+		if (pageserver_disconnect_shard(shard_no))
+			was_connected = true;
+	}
 
+	if (was_connected)
 		prefetch_on_ps_disconnect();
-	}
-	if (pageserver_conn_wes != NULL)
-	{
-		FreeWaitEventSet(pageserver_conn_wes);
-		pageserver_conn_wes = NULL;
-	}
 }
 
 static bool
-pageserver_send(NeonRequest *request)
+pageserver_send(shardno_t shard_no, NeonRequest *request)
 {
+	PageServer *shard;
 	StringInfoData req_buff;
 
-	if (CheckConnstringUpdated())
-	{
-		pageserver_disconnect();
-		ReloadConnstring();
-	}
+	RefreshShardMap();
+
+	if (shard_no >= local_shard_map.num_shards)
+		neon_log(ERROR, "shard %d is out of range: the tenant has %zu shards",
+				 shard_no, local_shard_map.num_shards);
+	shard = &page_servers[shard_no];
 
 	/* If the connection was lost for some reason, reconnect */
-	if (connected && PQstatus(pageserver_conn) == CONNECTION_BAD)
+	if (shard->conn != NULL && PQstatus(shard->conn) == CONNECTION_BAD)
 	{
 		neon_log(LOG, "pageserver_send disconnect bad connection");
 		pageserver_disconnect();
@@ -333,9 +519,9 @@ pageserver_send(NeonRequest *request)
 	 * https://github.com/neondatabase/neon/issues/1138 So try to reestablish
 	 * connection in case of failure.
 	 */
-	if (!connected)
+	if (shard->conn == NULL)
 	{
-		while (!pageserver_connect(n_reconnect_attempts < max_reconnect_attempts ? LOG : ERROR))
+		while (!pageserver_connect(shard_no, n_reconnect_attempts < max_reconnect_attempts ? LOG : ERROR))
 		{
 			HandleMainLoopInterrupts();
 			n_reconnect_attempts += 1;
@@ -352,9 +538,9 @@ pageserver_send(NeonRequest *request)
 	 * practice, our requests are small enough to always fit in the output and
 	 * TCP buffer.
 	 */
-	if (PQputCopyData(pageserver_conn, req_buff.data, req_buff.len) <= 0)
+	if (PQputCopyData(shard->conn, req_buff.data, req_buff.len) <= 0)
 	{
-		char	   *msg = pchomp(PQerrorMessage(pageserver_conn));
+		char	   *msg = pchomp(PQerrorMessage(shard->conn));
 
 		pageserver_disconnect();
 		neon_log(LOG, "pageserver_send disconnect because failed to send page request (try to reconnect): %s", msg);
@@ -376,12 +562,13 @@ pageserver_send(NeonRequest *request)
 }
 
 static NeonResponse *
-pageserver_receive(void)
+pageserver_receive(shardno_t shard_no)
 {
+	PageServer *shard = &page_servers[shard_no];
 	StringInfoData resp_buff;
 	NeonResponse *resp;
 
-	if (!connected)
+	if (shard->conn == NULL)
 		return NULL;
 
 	PG_TRY();
@@ -389,7 +576,7 @@ pageserver_receive(void)
 		/* read response */
 		int			rc;
 
-		rc = call_PQgetCopyData(&resp_buff.data);
+		rc = call_PQgetCopyData(shard, &resp_buff.data);
 		if (rc >= 0)
 		{
 			resp_buff.len = rc;
@@ -407,13 +594,13 @@ pageserver_receive(void)
 		}
 		else if (rc == -1)
 		{
-			neon_log(LOG, "pageserver_receive disconnect because call_PQgetCopyData returns -1: %s", pchomp(PQerrorMessage(pageserver_conn)));
+			neon_log(LOG, "pageserver_receive disconnect because call_PQgetCopyData returns -1: %s", pchomp(PQerrorMessage(shard->conn)));
 			pageserver_disconnect();
 			resp = NULL;
 		}
 		else if (rc == -2)
 		{
-			char	   *msg = pchomp(PQerrorMessage(pageserver_conn));
+			char	   *msg = pchomp(PQerrorMessage(shard->conn));
 
 			pageserver_disconnect();
 			neon_log(ERROR, "pageserver_receive disconnect because could not read COPY data: %s", msg);
@@ -436,18 +623,26 @@ pageserver_receive(void)
 }
 
 
+/*
+ * Flush the requests sent to all shards.
+ */
 static bool
 pageserver_flush(void)
 {
-	if (!connected)
-	{
-		neon_log(WARNING, "Tried to flush while disconnected");
-	}
-	else
+	bool		any_connected = false;
+	shardno_t	shard_no;
+
+	for (shard_no = 0; shard_no < local_shard_map.num_shards; shard_no++)
 	{
-		if (PQflush(pageserver_conn))
+		PageServer *shard = &page_servers[shard_no];
+
+		if (shard->conn == NULL)
+			continue;
+		any_connected = true;
+
+		if (PQflush(shard->conn))
 		{
-			char	   *msg = pchomp(PQerrorMessage(pageserver_conn));
+			char	   *msg = pchomp(PQerrorMessage(shard->conn));
 
 			pageserver_disconnect();
 			neon_log(LOG, "pageserver_flush disconnect because failed to flush page requests: %s", msg);
@@ -455,6 +650,9 @@ pageserver_flush(void)
 			return false;
 		}
 	}
+
+	if (!any_connected)
+		neon_log(WARNING, "Tried to flush while disconnected");
 	return true;
 }
 
@@ -492,7 +690,8 @@ PagestoreShmemInit(void)
 	{
 		pagestore_shared->lock = &(GetNamedLWLockTranche("neon_libpagestore")->lock);
 		pg_atomic_init_u64(&pagestore_shared->update_counter, 0);
-		AssignPageserverConnstring(page_server_connstring, NULL);
+		UpdateShardMap(page_server_connstring, page_server_connstrings);
+		AssignStripeSize(stripe_size, NULL);
 	}
 	LWLockRelease(AddinShmemInitLock);
 	return found;
@@ -542,13 +741,33 @@ pg_init_libpagestore(void)
 
 	DefineCustomStringVariable("neon.pageserver_connstring",
 							   "connection string to the page server",
-							   NULL,
+							   "Used for unsharded tenants.",
 							   &page_server_connstring,
 							   "",
 							   PGC_SIGHUP,
 							   0,	/* no flags required */
 							   CheckPageserverConnstring, AssignPageserverConnstring, NULL);
 
+	DefineCustomStringVariable("neon.pageserver_connstrings",
+							   "connection strings to the page servers of a sharded tenant",
+							   "One per shard, in shard number order. Takes precedence "
+							   "over neon.pageserver_connstring if set.",
+							   &page_server_connstrings,
+							   "",
+							   PGC_SIGHUP,
+							   GUC_LIST_INPUT | GUC_LIST_QUOTE,
+							   CheckPageserverConnstrings, AssignPageserverConnstrings, NULL);
+
+	DefineCustomIntVariable("neon.stripe_size",
+							"sharding stripe size",
+							"Number of consecutive pages of a relation that are "
+							"stored on the same shard of a sharded tenant.",
+							&stripe_size,
+							32768, 1, INT_MAX,
+							PGC_SIGHUP,
+							GUC_UNIT_BLOCKS,
+							NULL, AssignStripeSize, NULL);
+
 	DefineCustomStringVariable("neon.timeline_id",
 							   "Neon timeline_id the server is running on",
 							   NULL,
@@ -621,7 +840,8 @@ pg_init_libpagestore(void)
 	if (neon_auth_token)
 		neon_log(LOG, "using storage auth token from NEON_AUTH_TOKEN environment variable");
 
-	if (page_server_connstring && page_server_connstring[0])
+	if ((page_server_connstring && page_server_connstring[0]) ||
+		(page_server_connstrings && page_server_connstrings[0]))
 	{
 		neon_log(PageStoreTrace, "set neon_smgr hook");
 		smgr_hook = smgr_neon;
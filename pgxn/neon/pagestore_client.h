@@ -19,6 +19,7 @@
 #include "access/xlogdefs.h"
 #include RELFILEINFO_HDR
 #include "storage/block.h"
+#include "storage/buf_internals.h"
 #include "storage/smgr.h"
 #include "lib/stringinfo.h"
 #include "libpq/pqformat.h"
@@ -144,18 +145,30 @@ extern char *nm_to_string(NeonMessage *msg);
  * API
  */
 
+#define MAX_SHARDS 128
+
+typedef uint16 shardno_t;
+
+/*
+ * Requests are sent to, and responses received from, the pageserver of one
+ * shard. Responses come back in the order the requests were sent to that
+ * shard. flush() flushes the requests sent to all shards.
+ */
 typedef struct
 {
-	bool		(*send) (NeonRequest *request);
-	NeonResponse *(*receive) (void);
+	bool		(*send) (shardno_t shard_no, NeonRequest *request);
+	NeonResponse *(*receive) (shardno_t shard_no);
 	bool		(*flush) (void);
 } page_server_api;
 
 extern void prefetch_on_ps_disconnect(void);
 
+extern shardno_t get_shard_number(BufferTag *tag);
+
 extern page_server_api *page_server;
 
 extern char *page_server_connstring;
+extern char *page_server_connstrings;
 extern int	flush_every_n_requests;
 extern int	readahead_buffer_size;
 extern bool seqscan_prefetch_enabled;
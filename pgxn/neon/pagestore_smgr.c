@@ -168,6 +168,7 @@ typedef struct PrefetchRequest
 	XLogRecPtr	actual_request_lsn;
 	NeonResponse *response;		/* may be null */
 	PrefetchStatus status;
+	shardno_t	shard_no;		/* shard the request was sent to */
 	uint64		my_ring_index;
 } PrefetchRequest;
 
@@ -327,6 +328,7 @@ compact_prefetch_buffers(void)
 		target_slot->response = source_slot->response;
 		target_slot->effective_request_lsn = source_slot->effective_request_lsn;
 		target_slot->actual_request_lsn = source_slot->actual_request_lsn;
+		target_slot->shard_no = source_slot->shard_no;
 		target_slot->my_ring_index = empty_ring_index;
 
 		prfh_delete(MyPState->prf_hash, source_slot);
@@ -543,7 +545,7 @@ prefetch_read(PrefetchRequest *slot)
 	Assert(slot->my_ring_index == MyPState->ring_receive);
 
 	old = MemoryContextSwitchTo(MyPState->errctx);
-	response = (NeonResponse *) page_server->receive();
+	response = (NeonResponse *) page_server->receive(slot->shard_no);
 	MemoryContextSwitchTo(old);
 	if (response)
 	{
@@ -700,7 +702,8 @@ prefetch_do_request(PrefetchRequest *slot, bool *force_latest, XLogRecPtr *force
 	Assert(slot->response == NULL);
 	Assert(slot->my_ring_index == MyPState->ring_unused);
 
-	while (!page_server->send((NeonRequest *) &request));
+	slot->shard_no = get_shard_number(&slot->buftag);
+	while (!page_server->send(slot->shard_no, (NeonRequest *) &request));
 
 	/* update prefetch state */
 	MyPState->n_requests_inflight += 1;
@@ -900,6 +903,10 @@ Retry:
 	return ring_index;
 }
 
+/*
+ * Send a request other than GetPage, and wait for its response. These are
+ * all served by shard zero, which holds everything but relation blocks.
+ */
 static NeonResponse *
 page_server_request(void const *req)
 {
@@ -907,10 +914,10 @@ page_server_request(void const *req)
 
 	do
 	{
-		while (!page_server->send((NeonRequest *) req) || !page_server->flush());
+		while (!page_server->send(0, (NeonRequest *) req) || !page_server->flush());
 		MyPState->ring_flush = MyPState->ring_unused;
 		consume_prefetch_responses();
-		resp = page_server->receive();
+		resp = page_server->receive(0);
 	} while (resp == NULL);
 	return resp;
 
@@ -0,0 +1,526 @@
+//! Structured per-session audit log.
+//!
+//! Every client session (a TCP or WebSocket connection, or a single SQL-over-HTTP request)
+//! produces exactly one [`SessionRecord`] when it ends, even if the client goes away early.
+//! The records are buffered in memory and periodically flushed to the configured sink as
+//! JSON lines.
+use crate::{
+    auth::{self, ClientCredsParseError},
+    compute,
+    config::{AuditLogConfig, AuditLogSink},
+    console::{errors::WakeComputeError, messages::MetricsAuxInfo},
+    http,
+    rate_limiter::ConnectionLimitError,
+};
+use chrono::{DateTime, Utc};
+use consumption_metrics::CHUNK_SIZE;
+use once_cell::sync::Lazy;
+use pq_proto::StartupMessageParams;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use serde::Serialize;
+use smol_str::SmolStr;
+use std::{
+    convert::Infallible,
+    fmt,
+    net::IpAddr,
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, instrument, trace};
+
+const DEFAULT_HTTP_REPORTING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Upper bound on the number of records waiting to be flushed.
+/// If the sink can't keep up, new records are dropped (and counted).
+const MAX_BUFFERED_RECORDS: usize = 100_000;
+
+static AUDIT_LOG_RECORDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_audit_log_records_total",
+        "Number of session records processed by the audit log",
+        // sent/failed/dropped
+        &["outcome"],
+    )
+    .unwrap()
+});
+
+/// A single finished client session.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SessionRecord {
+    pub session_id: uuid::Uuid,
+    /// `tcp`, `ws` or `http`.
+    pub protocol: &'static str,
+    pub peer_addr: IpAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_link_id: Option<SmolStr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<SmolStr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<SmolStr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application_name: Option<SmolStr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint_id: Option<SmolStr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<SmolStr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch_id: Option<SmolStr>,
+    /// See [`crate::auth::BackendType::auth_method`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_method: Option<&'static str>,
+    /// Address of the compute node the session was proxied to. HTTP requests may be served by
+    /// a pooled connection, so they record the `host:port` of the woken compute instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compute_addr: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// Bytes received from the client.
+    pub bytes_rx: u64,
+    /// Bytes sent to the client.
+    pub bytes_tx: u64,
+    /// `success` or `error`.
+    pub outcome: &'static str,
+    /// See [`classify_error`]. Sessions dropped before they finish are `cancelled`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<&'static str>,
+}
+
+/// Traffic counters of a single session, updated by [`crate::proxy::proxy_pass`].
+#[derive(Debug, Default)]
+pub struct SessionBytes {
+    rx: AtomicU64,
+    tx: AtomicU64,
+}
+
+impl SessionBytes {
+    /// Record that some bytes were received from the client.
+    pub fn record_rx(&self, bytes: u64) {
+        self.rx.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Record that some bytes were sent to the client.
+    pub fn record_tx(&self, bytes: u64) {
+        self.tx.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Collects the details of a session while it's in progress.
+/// Call [`SessionLog::finish`] once the session is over.
+pub struct SessionLog {
+    record: parking_lot::Mutex<SessionRecord>,
+    bytes: SessionBytes,
+    started: Instant,
+    finished: bool,
+}
+
+impl SessionLog {
+    pub fn new(
+        session_id: uuid::Uuid,
+        protocol: &'static str,
+        peer_addr: IpAddr,
+        private_link_id: Option<SmolStr>,
+    ) -> Self {
+        let now = Utc::now();
+        Self {
+            record: parking_lot::Mutex::new(SessionRecord {
+                session_id,
+                protocol,
+                peer_addr,
+                private_link_id,
+                user: None,
+                database: None,
+                application_name: None,
+                endpoint_id: None,
+                project_id: None,
+                branch_id: None,
+                auth_method: None,
+                compute_addr: None,
+                started_at: now,
+                ended_at: now,
+                duration_ms: 0,
+                bytes_rx: 0,
+                bytes_tx: 0,
+                outcome: "success",
+                error_kind: None,
+            }),
+            bytes: SessionBytes::default(),
+            started: Instant::now(),
+            finished: false,
+        }
+    }
+
+    pub fn set_startup_params(&self, params: &StartupMessageParams) {
+        let mut record = self.record.lock();
+        record.user = params.get("user").map(SmolStr::from);
+        record.database = params.get("database").map(SmolStr::from);
+        record.application_name = params.get("application_name").map(SmolStr::from);
+    }
+
+    pub fn set_user(&self, user: SmolStr, database: SmolStr) {
+        let mut record = self.record.lock();
+        record.user = Some(user);
+        record.database = Some(database);
+    }
+
+    pub fn set_endpoint_id(&self, endpoint_id: SmolStr) {
+        self.record.lock().endpoint_id = Some(endpoint_id);
+    }
+
+    pub fn set_auth_method(&self, auth_method: &'static str) {
+        self.record.lock().auth_method = Some(auth_method);
+    }
+
+    pub fn set_metrics_aux(&self, aux: &MetricsAuxInfo) {
+        let mut record = self.record.lock();
+        record.endpoint_id = Some(aux.endpoint_id.clone());
+        record.project_id = Some(aux.project_id.clone());
+        record.branch_id = Some(aux.branch_id.clone());
+    }
+
+    pub fn set_compute_addr(&self, addr: impl fmt::Display) {
+        self.record.lock().compute_addr = Some(addr.to_string());
+    }
+
+    /// Traffic counters to be updated while the session is in progress.
+    pub fn bytes(&self) -> &SessionBytes {
+        &self.bytes
+    }
+
+    /// Complete the record and submit it to [`AUDIT_LOG`].
+    /// The session is considered failed if there's an `error_kind`, see [`classify_error`].
+    pub fn finish(mut self, error_kind: Option<&'static str>) {
+        AUDIT_LOG.push(self.take_record(error_kind));
+    }
+
+    fn take_record(&mut self, error_kind: Option<&'static str>) -> SessionRecord {
+        self.finished = true;
+        let mut record = self.record.get_mut().clone();
+        record.ended_at = Utc::now();
+        record.duration_ms = self.started.elapsed().as_millis() as u64;
+        record.bytes_rx = self.bytes.rx.load(Ordering::Relaxed);
+        record.bytes_tx = self.bytes.tx.load(Ordering::Relaxed);
+        if error_kind.is_some() {
+            record.outcome = "error";
+            record.error_kind = error_kind;
+        }
+        record
+    }
+}
+
+/// The session's future was dropped, e.g. hyper cancels the request handler
+/// when an HTTP client disconnects before getting the response.
+impl Drop for SessionLog {
+    fn drop(&mut self) {
+        if !self.finished {
+            AUDIT_LOG.push(self.take_record(Some("cancelled")));
+        }
+    }
+}
+
+/// Coarse classification of the reason a session has ended with an error.
+pub fn classify_error(e: &anyhow::Error) -> &'static str {
+    for cause in e.chain() {
+        if cause.is::<ClientCredsParseError>() {
+            return "client";
+        }
+        if cause.is::<auth::AuthError>() {
+            return "auth";
+        }
+        if cause.is::<ConnectionLimitError>() {
+            return "connection_limit";
+        }
+        if cause.is::<WakeComputeError>() {
+            return "console";
+        }
+        if cause.is::<compute::ConnectionError>() || cause.is::<tokio_postgres::Error>() {
+            return "compute";
+        }
+        if cause.is::<std::io::Error>() {
+            return "io";
+        }
+    }
+    "other"
+}
+
+#[derive(Default)]
+pub struct AuditLog {
+    enabled: AtomicBool,
+    records: parking_lot::Mutex<Vec<SessionRecord>>,
+}
+
+impl AuditLog {
+    fn push(&self, record: SessionRecord) {
+        // don't pile up records if nobody is going to flush them
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut records = self.records.lock();
+        if records.len() >= MAX_BUFFERED_RECORDS {
+            AUDIT_LOG_RECORDS.with_label_values(&["dropped"]).inc();
+            return;
+        }
+        records.push(record);
+    }
+
+    fn take(&self) -> Vec<SessionRecord> {
+        std::mem::take(&mut *self.records.lock())
+    }
+}
+
+pub static AUDIT_LOG: Lazy<AuditLog> = Lazy::new(AuditLog::default);
+
+pub async fn task_main(config: &AuditLogConfig) -> anyhow::Result<Infallible> {
+    info!("audit log config: {config:?}");
+    scopeguard::defer! {
+        info!("audit log has shut down");
+    }
+
+    let http_client = http::new_client_with_timeout(DEFAULT_HTTP_REPORTING_TIMEOUT);
+    AUDIT_LOG.enabled.store(true, Ordering::Relaxed);
+
+    let mut ticker = tokio::time::interval(config.interval);
+    loop {
+        ticker.tick().await;
+        flush_iteration(&AUDIT_LOG, &http_client, &config.sink).await;
+    }
+}
+
+#[instrument(skip_all)]
+async fn flush_iteration(log: &AuditLog, client: &http::ClientWithMiddleware, sink: &AuditLogSink) {
+    let records = log.take();
+    if records.is_empty() {
+        trace!("no new session records to flush");
+        return;
+    }
+
+    for chunk in records.chunks(CHUNK_SIZE) {
+        let res = match sink {
+            AuditLogSink::File(path) => write_to_file(path, chunk).await,
+            AuditLogSink::Http(endpoint) => send_to_endpoint(client, endpoint, chunk).await,
+        };
+
+        let outcome = match res {
+            Ok(()) => "sent",
+            Err(e) => {
+                error!("failed to flush {} session records: {e:#}", chunk.len());
+                "failed"
+            }
+        };
+        AUDIT_LOG_RECORDS
+            .with_label_values(&[outcome])
+            .inc_by(chunk.len() as u64);
+    }
+}
+
+fn to_json_lines(records: &[SessionRecord]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for record in records {
+        serde_json::to_writer(&mut buf, record).expect("json serialization should not fail");
+        buf.push(b'\n');
+    }
+    buf
+}
+
+/// The file is reopened on every flush, so that it can be rotated by simply renaming it.
+async fn write_to_file(path: &Path, records: &[SessionRecord]) -> anyhow::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(&to_json_lines(records)).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+async fn send_to_endpoint(
+    client: &http::ClientWithMiddleware,
+    endpoint: &reqwest::Url,
+    records: &[SessionRecord],
+) -> anyhow::Result<()> {
+    let res = client
+        .post(endpoint.clone())
+        .header(hyper::header::CONTENT_TYPE, "application/x-ndjson")
+        .body(to_json_lines(records))
+        .send()
+        .await?;
+
+    if !res.status().is_success() {
+        anyhow::bail!("audit log endpoint refused the records: {}", res.status());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        sync::{atomic::Ordering, Arc, Mutex},
+    };
+
+    use anyhow::{anyhow, Error};
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Response,
+    };
+    use pq_proto::StartupMessageParams;
+    use url::Url;
+
+    use super::{classify_error, flush_iteration, AuditLog, SessionLog, SessionRecord};
+    use crate::{
+        auth, config::AuditLogSink, console::messages::MetricsAuxInfo, http,
+        rate_limiter::RateLimiterConfig,
+    };
+
+    fn session() -> SessionLog {
+        let log = SessionLog::new(
+            uuid::Uuid::new_v4(),
+            "tcp",
+            "127.0.0.1".parse().unwrap(),
+            Some("vpce-1".into()),
+        );
+        log.set_startup_params(&StartupMessageParams::new([
+            ("user", "john_doe"),
+            ("database", "neondb"),
+        ]));
+        log.set_auth_method("scram");
+        log.set_metrics_aux(&MetricsAuxInfo {
+            endpoint_id: "ep-foo".into(),
+            project_id: "p1".into(),
+            branch_id: "b1".into(),
+        });
+        log
+    }
+
+    #[test]
+    fn session_record() {
+        let mut log = session();
+        log.bytes().record_rx(10);
+        log.bytes().record_tx(20);
+        let record = log.take_record(None);
+        assert_eq!(record.user.as_deref(), Some("john_doe"));
+        assert_eq!(record.database.as_deref(), Some("neondb"));
+        assert_eq!(record.endpoint_id.as_deref(), Some("ep-foo"));
+        assert_eq!(record.bytes_rx, 10);
+        assert_eq!(record.bytes_tx, 20);
+        assert_eq!(record.outcome, "success");
+        assert_eq!(record.error_kind, None);
+
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["private_link_id"], "vpce-1");
+        assert_eq!(json["auth_method"], "scram");
+        assert!(json.get("compute_addr").is_none());
+
+        let mut log = session();
+        log.set_compute_addr("10.0.0.1:5432".parse::<std::net::SocketAddr>().unwrap());
+        let json = serde_json::to_value(log.take_record(None)).unwrap();
+        assert_eq!(json["compute_addr"], "10.0.0.1:5432");
+
+        let err = auth::AuthError::auth_failed("john_doe").into();
+        let record = session().take_record(Some(classify_error(&err)));
+        assert_eq!(record.outcome, "error");
+        assert_eq!(record.error_kind, Some("auth"));
+    }
+
+    #[test]
+    fn error_classification() {
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        assert_eq!(classify_error(&io.into()), "io");
+        assert_eq!(classify_error(&anyhow!("something else")), "other");
+        let wrapped = Error::from(auth::AuthError::too_many_connections()).context("wrapped");
+        assert_eq!(classify_error(&wrapped), "auth");
+    }
+
+    #[test]
+    fn disabled_log_discards_records() {
+        let log = AuditLog::default();
+        log.push(session().take_record(None));
+        assert!(log.take().is_empty());
+
+        log.enabled.store(true, Ordering::Relaxed);
+        log.push(session().take_record(None));
+        assert_eq!(log.take().len(), 1);
+        assert!(log.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn flush_to_file() {
+        let path = std::env::temp_dir().join(format!("audit-{}.log", uuid::Uuid::new_v4()));
+        let sink = AuditLogSink::File(path.clone());
+        let client = http::new_client(RateLimiterConfig::default());
+
+        let log = AuditLog::default();
+        log.enabled.store(true, Ordering::Relaxed);
+        log.push(session().take_record(None));
+        flush_iteration(&log, &client, &sink).await;
+        log.push(session().take_record(None));
+        flush_iteration(&log, &client, &sink).await;
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(record["endpoint_id"], "ep-foo");
+        }
+    }
+
+    #[tokio::test]
+    async fn flush_to_endpoint() {
+        let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+
+        let reports = Arc::new(Mutex::new(vec![]));
+        let reports2 = reports.clone();
+
+        let server = hyper::server::Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service_fn(move |_| {
+                let reports = reports.clone();
+                async move {
+                    Ok::<_, Error>(service_fn(move |req| {
+                        let reports = reports.clone();
+                        async move {
+                            let bytes = hyper::body::to_bytes(req.into_body()).await?;
+                            reports
+                                .lock()
+                                .unwrap()
+                                .push(String::from_utf8(bytes.to_vec())?);
+                            Ok::<_, Error>(Response::new(Body::from(vec![])))
+                        }
+                    }))
+                }
+            }));
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let sink = AuditLogSink::Http(Url::parse(&format!("http://{addr}")).unwrap());
+        let client = http::new_client(RateLimiterConfig::default());
+        let log = AuditLog::default();
+        log.enabled.store(true, Ordering::Relaxed);
+
+        // nothing to send
+        flush_iteration(&log, &client, &sink).await;
+        assert!(reports2.lock().unwrap().is_empty());
+
+        let records: Vec<SessionRecord> = (0..3).map(|_| session().take_record(None)).collect();
+        for record in &records {
+            log.push(record.clone());
+        }
+        flush_iteration(&log, &client, &sink).await;
+
+        let r = std::mem::take(&mut *reports2.lock().unwrap());
+        assert_eq!(r.len(), 1);
+        let session_ids: Vec<_> = r[0]
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .map(|record| record["session_id"].as_str().unwrap().to_owned())
+            .collect();
+        let expected: Vec<_> = records.iter().map(|r| r.session_id.to_string()).collect();
+        assert_eq!(session_ids, expected);
+    }
+}
@@ -4,7 +4,10 @@ pub mod backend;
 pub use backend::BackendType;
 
 mod credentials;
-pub use credentials::{check_peer_addr_is_in_list, ClientCredentials};
+pub use credentials::{
+    check_peer_addr_is_in_list, check_private_link_id_is_in_list, parse_target_session_attrs_param,
+    ClientCredentials, ClientCredsParseError, ComputeTarget,
+};
 
 mod password_hack;
 pub use password_hack::parse_endpoint_param;
@@ -63,6 +66,13 @@ pub enum AuthErrorImpl {
     )]
     IpAddressNotAllowed,
 
+    #[error(
+        "This connection is not allowed to this endpoint. \
+        The endpoint only accepts connections through the private links \
+        (VPC endpoints) configured in the Neon console."
+    )]
+    PrivateLinkNotAllowed,
+
     #[error("Too many connections to this endpoint. Please try again later.")]
     TooManyConnections,
 }
@@ -84,6 +94,10 @@ impl AuthError {
         AuthErrorImpl::IpAddressNotAllowed.into()
     }
 
+    pub fn private_link_not_allowed() -> Self {
+        AuthErrorImpl::PrivateLinkNotAllowed.into()
+    }
+
     pub fn too_many_connections() -> Self {
         AuthErrorImpl::TooManyConnections.into()
     }
@@ -109,6 +123,7 @@ impl UserFacingError for AuthError {
             MissingEndpointName => self.to_string(),
             Io(_) => "Internal error".to_string(),
             IpAddressNotAllowed => self.to_string(),
+            PrivateLinkNotAllowed => self.to_string(),
             TooManyConnections => self.to_string(),
         }
     }
@@ -6,7 +6,7 @@ pub use link::LinkAuthError;
 use smol_str::SmolStr;
 use tokio_postgres::config::AuthKeys;
 
-use crate::auth::credentials::check_peer_addr_is_in_list;
+use crate::auth::credentials::{check_peer_addr_is_in_list, check_private_link_id_is_in_list};
 use crate::auth::validate_password_and_exchange;
 use crate::console::errors::GetAuthInfoError;
 use crate::console::provider::AuthInfo;
@@ -15,7 +15,7 @@ use crate::proxy::{handle_try_wake, retry_after, LatencyTimer};
 use crate::scram;
 use crate::stream::Stream;
 use crate::{
-    auth::{self, ClientCredentials},
+    auth::{self, ClientCredentials, ComputeTarget},
     config::AuthenticationConfig,
     console::{
         self,
@@ -43,6 +43,8 @@ use tracing::{error, info, warn};
 pub enum BackendType<'a, T> {
     /// Current Cloud API (V2).
     Console(Cow<'a, console::provider::neon::Api>, T),
+    /// Endpoints served from a local file, without the Neon console.
+    File(Cow<'a, console::provider::file::Api>, T),
     /// Local mock of Cloud API (V2).
     #[cfg(feature = "testing")]
     Postgres(Cow<'a, console::provider::mock::Api>, T),
@@ -56,6 +58,9 @@ pub enum BackendType<'a, T> {
 pub trait TestBackend: Send + Sync + 'static {
     fn wake_compute(&self) -> Result<CachedNodeInfo, console::errors::WakeComputeError>;
     fn get_allowed_ips(&self) -> Result<Arc<Vec<String>>, console::errors::GetAuthInfoError>;
+    fn get_allowed_vpc_endpoint_ids(
+        &self,
+    ) -> Result<Arc<Vec<String>>, console::errors::GetAuthInfoError>;
 }
 
 impl std::fmt::Display for BackendType<'_, ()> {
@@ -63,6 +68,7 @@ impl std::fmt::Display for BackendType<'_, ()> {
         use BackendType::*;
         match self {
             Console(endpoint, _) => fmt.debug_tuple("Console").field(&endpoint.url()).finish(),
+            File(endpoint, _) => fmt.debug_tuple("File").field(&endpoint.path()).finish(),
             #[cfg(feature = "testing")]
             Postgres(endpoint, _) => fmt.debug_tuple("Postgres").field(&endpoint.url()).finish(),
             Link(url) => fmt.debug_tuple("Link").field(&url.as_str()).finish(),
@@ -79,6 +85,7 @@ impl<T> BackendType<'_, T> {
         use BackendType::*;
         match self {
             Console(c, x) => Console(Cow::Borrowed(c), x),
+            File(c, x) => File(Cow::Borrowed(c), x),
             #[cfg(feature = "testing")]
             Postgres(c, x) => Postgres(Cow::Borrowed(c), x),
             Link(c) => Link(Cow::Borrowed(c)),
@@ -96,6 +103,7 @@ impl<'a, T> BackendType<'a, T> {
         use BackendType::*;
         match self {
             Console(c, x) => Console(c, f(x)),
+            File(c, x) => File(c, f(x)),
             #[cfg(feature = "testing")]
             Postgres(c, x) => Postgres(c, f(x)),
             Link(c) => Link(c),
@@ -112,6 +120,7 @@ impl<'a, T, E> BackendType<'a, Result<T, E>> {
         use BackendType::*;
         match self {
             Console(c, x) => x.map(|x| Console(c, x)),
+            File(c, x) => x.map(|x| File(c, x)),
             #[cfg(feature = "testing")]
             Postgres(c, x) => x.map(|x| Postgres(c, x)),
             Link(c) => Ok(Link(c)),
@@ -129,6 +138,8 @@ pub struct ComputeCredentials<T> {
 pub struct ComputeUserInfoNoEndpoint {
     pub user: SmolStr,
     pub peer_addr: IpAddr,
+    pub private_link_id: Option<SmolStr>,
+    pub target: ComputeTarget,
     pub cache_key: SmolStr,
 }
 
@@ -137,6 +148,18 @@ pub struct ComputeUserInfo {
     pub inner: ComputeUserInfoNoEndpoint,
 }
 
+impl ComputeUserInfo {
+    /// Key for the `wake_compute` cache. Replicas are cached apart from the primary.
+    pub fn wake_compute_cache_key(&self) -> Cow<'_, str> {
+        match self.inner.target {
+            ComputeTarget::Primary => Cow::Borrowed(&self.inner.cache_key),
+            ComputeTarget::ReadReplica | ComputeTarget::PreferReadReplica => {
+                Cow::Owned(format!("{} target:read_replica", self.inner.cache_key))
+            }
+        }
+    }
+}
+
 pub enum ComputeCredentialKeys {
     #[cfg(feature = "testing")]
     Password(Vec<u8>),
@@ -151,6 +174,8 @@ impl TryFrom<ClientCredentials> for ComputeUserInfo {
         let inner = ComputeUserInfoNoEndpoint {
             user: creds.user,
             peer_addr: creds.peer_addr,
+            private_link_id: creds.private_link_id,
+            target: creds.target,
             cache_key: creds.cache_key,
         };
         match creds.project {
@@ -189,12 +214,19 @@ async fn auth_quirks(
     let AuthInfo {
         secret,
         allowed_ips,
+        allowed_vpc_endpoint_ids,
     } = api.get_auth_info(extra, &info).await?;
 
     // check allowed list
     if !check_peer_addr_is_in_list(&info.inner.peer_addr, &allowed_ips) {
         return Err(auth::AuthError::ip_address_not_allowed());
     }
+    if !check_private_link_id_is_in_list(
+        info.inner.private_link_id.as_deref(),
+        &allowed_vpc_endpoint_ids,
+    ) {
+        return Err(auth::AuthError::private_link_not_allowed());
+    }
     let secret = secret.unwrap_or_else(|| {
         // If we don't have an authentication secret, we mock one to
         // prevent malicious probing (possible due to missing protocol steps).
@@ -253,9 +285,23 @@ async fn auth_and_wake_compute(
     )
     .await?;
 
+    let mut info = compute_credentials.info;
     let mut num_retries = 0;
     let mut node = loop {
-        let wake_res = api.wake_compute(extra, &compute_credentials.info).await;
+        let wake_res = api.wake_compute(extra, &info).await;
+        if let Some(fallback) = info.inner.target.fallback() {
+            if let Err(e) = &wake_res {
+                if e.is_replica_unavailable() {
+                    warn!(
+                        error = ?e,
+                        "no read replica is available, falling back to the primary"
+                    );
+                    info.inner.target = fallback;
+                    continue;
+                }
+            }
+        }
+
         match handle_try_wake(wake_res, num_retries) {
             Err(e) => {
                 error!(error = ?e, num_retries, retriable = false, "couldn't wake compute node");
@@ -278,7 +324,7 @@ async fn auth_and_wake_compute(
         ComputeCredentialKeys::AuthKeys(auth_keys) => node.config.auth_keys(auth_keys),
     };
 
-    Ok((node, compute_credentials.info))
+    Ok((node, info))
 }
 
 impl<'a> BackendType<'a, ClientCredentials> {
@@ -288,6 +334,7 @@ impl<'a> BackendType<'a, ClientCredentials> {
 
         match self {
             Console(_, creds) => creds.project.clone(),
+            File(_, creds) => creds.project.clone(),
             #[cfg(feature = "testing")]
             Postgres(_, creds) => creds.project.clone(),
             Link(_) => Some("link".into()),
@@ -302,6 +349,7 @@ impl<'a> BackendType<'a, ClientCredentials> {
 
         match self {
             Console(_, creds) => &creds.user,
+            File(_, creds) => &creds.user,
             #[cfg(feature = "testing")]
             Postgres(_, creds) => &creds.user,
             Link(_) => "link",
@@ -310,6 +358,44 @@ impl<'a> BackendType<'a, ClientCredentials> {
         }
     }
 
+    /// Get the requested compute target from the credentials.
+    pub fn get_target(&self) -> ComputeTarget {
+        use BackendType::*;
+
+        match self {
+            Console(_, creds) => creds.target,
+            File(_, creds) => creds.target,
+            #[cfg(feature = "testing")]
+            Postgres(_, creds) => creds.target,
+            Link(_) => ComputeTarget::Primary,
+            #[cfg(test)]
+            Test(_) => ComputeTarget::Primary,
+        }
+    }
+
+    /// Name of the auth flow [`Self::authenticate`] is going to use, mirroring `auth_quirks`.
+    pub fn auth_method(&self, allow_cleartext: bool) -> &'static str {
+        use BackendType::*;
+
+        let creds = match self {
+            Console(_, creds) => creds,
+            File(_, creds) => creds,
+            #[cfg(feature = "testing")]
+            Postgres(_, creds) => creds,
+            Link(_) => return "link",
+            #[cfg(test)]
+            Test(_) => return "test",
+        };
+
+        if creds.project.is_none() {
+            "password_hack"
+        } else if allow_cleartext {
+            "cleartext"
+        } else {
+            "scram"
+        }
+    }
+
     /// Authenticate the client via the requested backend, possibly using credentials.
     #[tracing::instrument(fields(allow_cleartext = allow_cleartext), skip_all)]
     pub async fn authenticate(
@@ -342,6 +428,25 @@ impl<'a> BackendType<'a, ClientCredentials> {
                 .await?;
                 (cache_info, BackendType::Console(api, user_info))
             }
+            File(api, creds) => {
+                info!(
+                    user = &*creds.user,
+                    project = creds.project(),
+                    "performing authentication using the endpoints file"
+                );
+
+                let (cache_info, user_info) = auth_and_wake_compute(
+                    &*api,
+                    extra,
+                    creds,
+                    client,
+                    allow_cleartext,
+                    config,
+                    latency_timer,
+                )
+                .await?;
+                (cache_info, BackendType::File(api, user_info))
+            }
             #[cfg(feature = "testing")]
             Postgres(api, creds) => {
                 info!(
@@ -385,6 +490,20 @@ impl<'a> BackendType<'a, ClientCredentials> {
 }
 
 impl BackendType<'_, ComputeUserInfo> {
+    /// Get the compute target the connection has been routed to.
+    pub fn get_target(&self) -> ComputeTarget {
+        use BackendType::*;
+        match self {
+            Console(_, creds) => creds.inner.target,
+            File(_, creds) => creds.inner.target,
+            #[cfg(feature = "testing")]
+            Postgres(_, creds) => creds.inner.target,
+            Link(_) => ComputeTarget::Primary,
+            #[cfg(test)]
+            Test(_) => ComputeTarget::Primary,
+        }
+    }
+
     pub async fn get_allowed_ips(
         &self,
         extra: &ConsoleReqExtra,
@@ -392,6 +511,7 @@ impl BackendType<'_, ComputeUserInfo> {
         use BackendType::*;
         match self {
             Console(api, creds) => api.get_allowed_ips(extra, creds).await,
+            File(api, creds) => api.get_allowed_ips(extra, creds).await,
             #[cfg(feature = "testing")]
             Postgres(api, creds) => api.get_allowed_ips(extra, creds).await,
             Link(_) => Ok(Arc::new(vec![])),
@@ -400,6 +520,22 @@ impl BackendType<'_, ComputeUserInfo> {
         }
     }
 
+    pub async fn get_allowed_vpc_endpoint_ids(
+        &self,
+        extra: &ConsoleReqExtra,
+    ) -> Result<Arc<Vec<String>>, GetAuthInfoError> {
+        use BackendType::*;
+        match self {
+            Console(api, creds) => api.get_allowed_vpc_endpoint_ids(extra, creds).await,
+            File(api, creds) => api.get_allowed_vpc_endpoint_ids(extra, creds).await,
+            #[cfg(feature = "testing")]
+            Postgres(api, creds) => api.get_allowed_vpc_endpoint_ids(extra, creds).await,
+            Link(_) => Ok(Arc::new(vec![])),
+            #[cfg(test)]
+            Test(x) => x.get_allowed_vpc_endpoint_ids(),
+        }
+    }
+
     /// When applicable, wake the compute node, gaining its connection info in the process.
     /// The link auth flow doesn't support this, so we return [`None`] in that case.
     pub async fn wake_compute(
@@ -410,6 +546,7 @@ impl BackendType<'_, ComputeUserInfo> {
 
         match self {
             Console(api, creds) => api.wake_compute(extra, creds).map_ok(Some).await,
+            File(api, creds) => api.wake_compute(extra, creds).map_ok(Some).await,
             #[cfg(feature = "testing")]
             Postgres(api, creds) => api.wake_compute(extra, creds).map_ok(Some).await,
             Link(_) => Ok(None),
@@ -105,5 +105,6 @@ pub(super) async fn authenticate(
         config,
         aux: db_info.aux,
         allow_self_signed_compute: false, // caller may override
+        limits: None,
     })
 }
@@ -8,7 +8,7 @@ use crate::{
 use itertools::Itertools;
 use pq_proto::StartupMessageParams;
 use smol_str::SmolStr;
-use std::{collections::HashSet, net::IpAddr};
+use std::{collections::HashSet, net::IpAddr, str::FromStr};
 use thiserror::Error;
 use tracing::{info, warn};
 
@@ -32,6 +32,12 @@ pub enum ClientCredsParseError {
 
     #[error("Project name ('{0}') must contain only alphanumeric characters and hyphen.")]
     MalformedProjectName(SmolStr),
+
+    #[error(
+        "Unsupported target_session_attrs ('{0}'), \
+         expected one of: any, read-write, primary, read-only, standby, prefer-standby."
+    )]
+    UnknownTargetSessionAttrs(SmolStr),
 }
 
 impl UserFacingError for ClientCredsParseError {}
@@ -46,6 +52,10 @@ pub struct ClientCredentials {
 
     pub cache_key: SmolStr,
     pub peer_addr: IpAddr,
+    /// Private link (e.g. AWS VPC endpoint) the client connected through, if any.
+    pub private_link_id: Option<SmolStr>,
+    /// Which compute of the endpoint the client wants to talk to.
+    pub target: ComputeTarget,
 }
 
 impl ClientCredentials {
@@ -55,12 +65,65 @@ impl ClientCredentials {
     }
 }
 
+/// Compute of an endpoint which should serve the connection.
+/// Requested via the `target_session_attrs=<value>` option.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ComputeTarget {
+    /// The read-write primary compute.
+    #[default]
+    Primary,
+    /// One of the read-only replicas. Fails if none is running.
+    ReadReplica,
+    /// One of the read-only replicas, or the primary if none is running.
+    PreferReadReplica,
+}
+
+impl ComputeTarget {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ComputeTarget::Primary => "primary",
+            ComputeTarget::ReadReplica | ComputeTarget::PreferReadReplica => "read_replica",
+        }
+    }
+
+    pub fn is_read_replica(&self) -> bool {
+        !matches!(self, ComputeTarget::Primary)
+    }
+
+    /// Target to try instead when the console has no running read replica.
+    pub fn fallback(&self) -> Option<ComputeTarget> {
+        match self {
+            ComputeTarget::PreferReadReplica => Some(ComputeTarget::Primary),
+            ComputeTarget::Primary | ComputeTarget::ReadReplica => None,
+        }
+    }
+}
+
+impl FromStr for ComputeTarget {
+    type Err = ClientCredsParseError;
+
+    /// Mirrors the values of libpq's `target_session_attrs`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" | "read-write" | "primary" => Ok(ComputeTarget::Primary),
+            "read-only" | "standby" => Ok(ComputeTarget::ReadReplica),
+            "prefer-standby" => Ok(ComputeTarget::PreferReadReplica),
+            _ => Err(ClientCredsParseError::UnknownTargetSessionAttrs(s.into())),
+        }
+    }
+}
+
+pub fn parse_target_session_attrs_param(bytes: &str) -> Option<&str> {
+    bytes.strip_prefix("target_session_attrs=")
+}
+
 impl ClientCredentials {
     pub fn parse(
         params: &StartupMessageParams,
         sni: Option<&str>,
         common_names: Option<HashSet<String>>,
         peer_addr: IpAddr,
+        private_link_id: Option<SmolStr>,
     ) -> Result<Self, ClientCredsParseError> {
         use ClientCredsParseError::*;
 
@@ -82,6 +145,13 @@ impl ClientCredentials {
             })
             .map(|name| name.into());
 
+        let target = params
+            .options_raw()
+            .and_then(|mut options| options.find_map(parse_target_session_attrs_param))
+            .map(ComputeTarget::from_str)
+            .transpose()?
+            .unwrap_or_default();
+
         let project_from_domain = if let Some(sni_str) = sni {
             if let Some(cn) = common_names {
                 let common_name_from_sni = sni_str.split_once('.').map(|(_, domain)| domain);
@@ -119,7 +189,7 @@ impl ClientCredentials {
         }
         .transpose()?;
 
-        info!(%user, project = project.as_deref(), "credentials");
+        info!(%user, project = project.as_deref(), target = target.as_str(), "credentials");
         if sni.is_some() {
             info!("Connection with sni");
             NUM_CONNECTION_ACCEPTED_BY_SNI
@@ -149,6 +219,8 @@ impl ClientCredentials {
             project,
             cache_key,
             peer_addr,
+            private_link_id,
+            target,
         })
     }
 }
@@ -172,6 +244,15 @@ pub fn check_peer_addr_is_in_list(peer_addr: &IpAddr, ip_list: &Vec<String>) ->
     false
 }
 
+/// Check that the private link the client came through is allowed.
+/// An empty list means that connections from anywhere are allowed.
+pub fn check_private_link_id_is_in_list(private_link_id: Option<&str>, list: &[String]) -> bool {
+    if list.is_empty() {
+        return true;
+    }
+    private_link_id.is_some_and(|id| list.iter().any(|allowed| allowed == id))
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 enum IpPattern {
     Subnet(ipnet::IpNet),
@@ -221,7 +302,7 @@ mod tests {
         // According to postgresql, only `user` should be required.
         let options = StartupMessageParams::new([("user", "john_doe")]);
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, None, None, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
         assert_eq!(creds.user, "john_doe");
         assert_eq!(creds.project, None);
 
@@ -236,7 +317,7 @@ mod tests {
             ("foo", "bar"),        // should be ignored
         ]);
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, None, None, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
         assert_eq!(creds.user, "john_doe");
         assert_eq!(creds.project, None);
 
@@ -251,7 +332,7 @@ mod tests {
         let common_names = Some(["localhost".into()].into());
 
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, sni, common_names, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, sni, common_names, peer_addr, None)?;
         assert_eq!(creds.user, "john_doe");
         assert_eq!(creds.project.as_deref(), Some("foo"));
         assert_eq!(creds.cache_key, "foo");
@@ -267,7 +348,7 @@ mod tests {
         ]);
 
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, None, None, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
         assert_eq!(creds.user, "john_doe");
         assert_eq!(creds.project.as_deref(), Some("bar"));
 
@@ -282,7 +363,7 @@ mod tests {
         ]);
 
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, None, None, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
         assert_eq!(creds.user, "john_doe");
         assert_eq!(creds.project.as_deref(), Some("bar"));
 
@@ -300,7 +381,7 @@ mod tests {
         ]);
 
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, None, None, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
         assert_eq!(creds.user, "john_doe");
         assert!(creds.project.is_none());
 
@@ -315,7 +396,7 @@ mod tests {
         ]);
 
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, None, None, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
         assert_eq!(creds.user, "john_doe");
         assert!(creds.project.is_none());
 
@@ -330,7 +411,7 @@ mod tests {
         let common_names = Some(["localhost".into()].into());
 
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, sni, common_names, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, sni, common_names, peer_addr, None)?;
         assert_eq!(creds.user, "john_doe");
         assert_eq!(creds.project.as_deref(), Some("baz"));
 
@@ -344,13 +425,13 @@ mod tests {
         let common_names = Some(["a.com".into(), "b.com".into()].into());
         let sni = Some("p1.a.com");
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, sni, common_names, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, sni, common_names, peer_addr, None)?;
         assert_eq!(creds.project.as_deref(), Some("p1"));
 
         let common_names = Some(["a.com".into(), "b.com".into()].into());
         let sni = Some("p1.b.com");
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, sni, common_names, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, sni, common_names, peer_addr, None)?;
         assert_eq!(creds.project.as_deref(), Some("p1"));
 
         Ok(())
@@ -365,7 +446,7 @@ mod tests {
         let common_names = Some(["localhost".into()].into());
 
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let err = ClientCredentials::parse(&options, sni, common_names, peer_addr)
+        let err = ClientCredentials::parse(&options, sni, common_names, peer_addr, None)
             .expect_err("should fail");
         match err {
             InconsistentProjectNames { domain, option } => {
@@ -384,7 +465,7 @@ mod tests {
         let common_names = Some(["example.com".into()].into());
 
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let err = ClientCredentials::parse(&options, sni, common_names, peer_addr)
+        let err = ClientCredentials::parse(&options, sni, common_names, peer_addr, None)
             .expect_err("should fail");
         match err {
             UnknownCommonName { cn } => {
@@ -404,13 +485,65 @@ mod tests {
         let sni = Some("project.localhost");
         let common_names = Some(["localhost".into()].into());
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
-        let creds = ClientCredentials::parse(&options, sni, common_names, peer_addr)?;
+        let creds = ClientCredentials::parse(&options, sni, common_names, peer_addr, None)?;
         assert_eq!(creds.project.as_deref(), Some("project"));
         assert_eq!(creds.cache_key, "projectendpoint_type:read_write lsn:0/2");
 
         Ok(())
     }
 
+    #[test]
+    fn parse_target_session_attrs() -> anyhow::Result<()> {
+        let peer_addr = IpAddr::from([127, 0, 0, 1]);
+
+        let options = StartupMessageParams::new([("user", "john_doe")]);
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
+        assert_eq!(creds.target, ComputeTarget::Primary);
+
+        let options = StartupMessageParams::new([
+            ("user", "john_doe"),
+            ("options", "endpoint=foo target_session_attrs=read-only"),
+        ]);
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
+        assert_eq!(creds.project.as_deref(), Some("foo"));
+        assert_eq!(creds.target, ComputeTarget::ReadReplica);
+        // the target doesn't affect the cache key of the endpoint
+        assert_eq!(creds.cache_key, "foo");
+
+        let options = StartupMessageParams::new([
+            ("user", "john_doe"),
+            ("options", "target_session_attrs=prefer-standby"),
+        ]);
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
+        assert_eq!(creds.target, ComputeTarget::PreferReadReplica);
+        assert_eq!(creds.target.fallback(), Some(ComputeTarget::Primary));
+
+        let options = StartupMessageParams::new([
+            ("user", "john_doe"),
+            ("options", "target_session_attrs=standby"),
+        ]);
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
+        assert_eq!(creds.target, ComputeTarget::ReadReplica);
+        assert_eq!(creds.target.fallback(), None);
+
+        let options = StartupMessageParams::new([
+            ("user", "john_doe"),
+            ("options", "target_session_attrs=read-write"),
+        ]);
+        let creds = ClientCredentials::parse(&options, None, None, peer_addr, None)?;
+        assert_eq!(creds.target, ComputeTarget::Primary);
+
+        let options = StartupMessageParams::new([
+            ("user", "john_doe"),
+            ("options", "target_session_attrs=whatever"),
+        ]);
+        let err = ClientCredentials::parse(&options, None, None, peer_addr, None)
+            .expect_err("should fail");
+        assert_eq!(err, UnknownTargetSessionAttrs("whatever".into()));
+
+        Ok(())
+    }
+
     #[test]
     fn test_check_peer_addr_is_in_list() {
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
@@ -429,6 +562,17 @@ mod tests {
             &vec!["88.8.8".into(), "127.0.0.1".into()]
         ));
     }
+
+    #[test]
+    fn test_check_private_link_id_is_in_list() {
+        let allowed = vec!["vpce-1".to_string(), "vpce-2".to_string()];
+        assert!(check_private_link_id_is_in_list(None, &[]));
+        assert!(check_private_link_id_is_in_list(Some("vpce-3"), &[]));
+        assert!(check_private_link_id_is_in_list(Some("vpce-2"), &allowed));
+        assert!(!check_private_link_id_is_in_list(Some("vpce-3"), &allowed));
+        // public connections are not allowed when the list is set
+        assert!(!check_private_link_id_is_in_list(None, &allowed));
+    }
     #[test]
     fn test_parse_ip_v4() -> anyhow::Result<()> {
         let peer_addr = IpAddr::from([127, 0, 0, 1]);
@@ -1,4 +1,5 @@
 use futures::future::Either;
+use proxy::audit_log;
 use proxy::auth;
 use proxy::config::AuthenticationConfig;
 use proxy::config::CacheOptions;
@@ -7,6 +8,8 @@ use proxy::console;
 use proxy::console::provider::AllowedIpsCache;
 use proxy::console::provider::NodeInfoCache;
 use proxy::http;
+use proxy::rate_limiter::ConnectionLimiterConfig;
+use proxy::rate_limiter::EndpointConnectionLimiter;
 use proxy::rate_limiter::EndpointRateLimiter;
 use proxy::rate_limiter::RateBucketInfo;
 use proxy::rate_limiter::RateLimiterConfig;
@@ -36,6 +39,7 @@ enum AuthBackend {
     #[cfg(feature = "testing")]
     Postgres,
     Link,
+    File,
 }
 
 /// Neon proxy/router
@@ -59,13 +63,16 @@ struct ProxyCliArgs {
     /// redirect unauthenticated users to the given uri in case of link auth
     #[clap(short, long, default_value = "http://localhost:3000/psql_session/")]
     uri: String,
-    /// cloud API endpoint for authenticating users
+    /// cloud API endpoint for authenticating users (path to the endpoints file for the `file` backend)
     #[clap(
         short,
         long,
         default_value = "http://localhost:3000/authenticate_proxy_request/"
     )]
     auth_endpoint: String,
+    /// how often the endpoints file of the `file` auth backend is checked for changes
+    #[clap(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    auth_file_reload_interval: tokio::time::Duration,
     /// path to TLS key for client postgres connections
     ///
     /// tls-key and tls-cert are for backwards compatibility, we can put all certs in one dir
@@ -85,6 +92,12 @@ struct ProxyCliArgs {
     /// how often metrics should be sent to a collection endpoint
     #[clap(long)]
     metric_collection_interval: Option<String>,
+    /// file or http endpoint to write the per-session audit log (JSON lines) to
+    #[clap(long)]
+    audit_log: Option<String>,
+    /// how often the buffered audit log records should be flushed
+    #[clap(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    audit_log_interval: tokio::time::Duration,
     /// cache for `wake_compute` api method (use `size=0` to disable)
     #[clap(long, default_value = config::CacheOptions::DEFAULT_OPTIONS_NODE_INFO)]
     wake_compute_cache: String,
@@ -121,12 +134,17 @@ struct ProxyCliArgs {
     /// Can be given multiple times for different bucket sizes.
     #[clap(long, default_values_t = RateBucketInfo::DEFAULT_SET)]
     endpoint_rps_limit: Vec<RateBucketInfo>,
+    /// Limit of concurrent connections per endpoint and per role. example: "endpoint=100,role=20,queue=16,timeout=1s".
+    ///
+    /// Limits reported by the console take precedence. Use `0` to disable a limit.
+    #[clap(long, default_value = ConnectionLimiterConfig::DEFAULT_OPTIONS)]
+    endpoint_connection_limit: String,
     /// Initial limit for dynamic rate limiter. Makes sense only if `rate_limit_algorithm` is *not* `None`.
     #[clap(long, default_value_t = 100)]
     initial_limit: usize,
     #[clap(flatten)]
     aimd_config: proxy::rate_limiter::AimdConfig,
-    /// cache for `allowed_ips` (use `size=0` to disable)
+    /// cache for `allowed_ips` and `allowed_vpc_endpoint_ids` (use `size=0` to disable)
     #[clap(long, default_value = config::CacheOptions::DEFAULT_OPTIONS_NODE_INFO)]
     allowed_ips_cache: String,
     /// disable ip check for http requests. If it is too time consuming, it could be turned off.
@@ -164,6 +182,9 @@ async fn main() -> anyhow::Result<()> {
     let cancellation_token = CancellationToken::new();
 
     let endpoint_rate_limiter = Arc::new(EndpointRateLimiter::new(&config.endpoint_rps_limit));
+    let endpoint_connection_limiter = Arc::new(EndpointConnectionLimiter::new(
+        config.endpoint_connection_limit,
+    ));
 
     // client facing tasks. these will exit on error or on cancellation
     // cancellation returns Ok(())
@@ -173,6 +194,7 @@ async fn main() -> anyhow::Result<()> {
         proxy_listener,
         cancellation_token.clone(),
         endpoint_rate_limiter.clone(),
+        endpoint_connection_limiter.clone(),
     ));
 
     // TODO: rename the argument to something like serverless.
@@ -187,6 +209,7 @@ async fn main() -> anyhow::Result<()> {
             serverless_listener,
             cancellation_token.clone(),
             endpoint_rate_limiter.clone(),
+            endpoint_connection_limiter.clone(),
         ));
     }
 
@@ -200,6 +223,15 @@ async fn main() -> anyhow::Result<()> {
         maintenance_tasks.spawn(usage_metrics::task_main(metrics_config));
     }
 
+    if let Some(audit_log_config) = &config.audit_log {
+        maintenance_tasks.spawn(audit_log::task_main(audit_log_config));
+    }
+
+    if let auth::BackendType::File(api, ()) = &config.auth_backend {
+        let api = api.as_ref().clone();
+        maintenance_tasks.spawn(api.reload_worker(args.auth_file_reload_interval));
+    }
+
     let maintenance = loop {
         // get one complete task
         match futures::future::select(
@@ -254,6 +286,13 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
              and metric-collection-interval must be specified"
         ),
     };
+    let audit_log = match &args.audit_log {
+        Some(sink) => Some(config::AuditLogConfig {
+            sink: sink.parse()?,
+            interval: args.audit_log_interval,
+        }),
+        None => None,
+    };
     let rate_limiter_config = RateLimiterConfig {
         disable: args.disable_dynamic_rate_limiter,
         algorithm: args.rate_limit_algorithm,
@@ -282,6 +321,12 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
                     allowed_ips_cache_config.ttl,
                     false,
                 ),
+                allowed_vpc_endpoint_ids: AllowedIpsCache::new(
+                    "allowed_vpc_endpoint_ids_cache",
+                    allowed_ips_cache_config.size,
+                    allowed_ips_cache_config.ttl,
+                    false,
+                ),
             }));
 
             let config::WakeComputeLockOptions {
@@ -313,6 +358,10 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
             let url = args.uri.parse()?;
             auth::BackendType::Link(Cow::Owned(url))
         }
+        AuthBackend::File => {
+            let api = console::provider::file::Api::new(args.auth_endpoint.clone().into())?;
+            auth::BackendType::File(Cow::Owned(api), ())
+        }
     };
     let http_config = HttpConfig {
         timeout: args.sql_over_http_timeout,
@@ -324,17 +373,21 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
 
     let mut endpoint_rps_limit = args.endpoint_rps_limit.clone();
     RateBucketInfo::validate(&mut endpoint_rps_limit)?;
+    let endpoint_connection_limit: ConnectionLimiterConfig =
+        args.endpoint_connection_limit.parse()?;
 
     let config = Box::leak(Box::new(ProxyConfig {
         tls_config,
         auth_backend,
         metric_collection,
+        audit_log,
         allow_self_signed_compute: args.allow_self_signed_compute,
         http_config,
         authentication_config,
         require_client_ip: args.require_client_ip,
         disable_ip_check_for_http: args.disable_ip_check_for_http,
         endpoint_rps_limit,
+        endpoint_connection_limit,
     }));
 
     Ok(config)
@@ -1,5 +1,5 @@
 use crate::{
-    auth::parse_endpoint_param,
+    auth::{parse_endpoint_param, parse_target_session_attrs_param},
     cancellation::CancelClosure,
     console::errors::WakeComputeError,
     error::UserFacingError,
@@ -227,6 +227,8 @@ pub struct PostgresConnection {
     pub params: std::collections::HashMap<String, String>,
     /// Query cancellation token.
     pub cancel_closure: CancelClosure,
+    /// Address of the compute node.
+    pub socket_addr: SocketAddr,
 
     _guage: IntCounterPairGuard,
 }
@@ -271,6 +273,7 @@ impl ConnCfg {
             stream,
             params,
             cancel_closure,
+            socket_addr,
             _guage: NUM_DB_CONNECTIONS_GAUGE.with_label_values(&[proto]).guard(),
         };
 
@@ -283,7 +286,11 @@ fn filtered_options(params: &StartupMessageParams) -> Option<String> {
     #[allow(unstable_name_collisions)]
     let options: String = params
         .options_raw()?
-        .filter(|opt| parse_endpoint_param(opt).is_none() && neon_option(opt).is_none())
+        .filter(|opt| {
+            parse_endpoint_param(opt).is_none()
+                && parse_target_session_attrs_param(opt).is_none()
+                && neon_option(opt).is_none()
+        })
         .intersperse(" ") // TODO: use impl from std once it's stabilized
         .collect();
 
@@ -324,5 +331,11 @@ mod tests {
             "project = foo neon_endpoint_type:read_write   neon_lsn:0/2",
         )]);
         assert_eq!(filtered_options(&params).as_deref(), Some("project = foo"));
+
+        let params = StartupMessageParams::new([(
+            "options",
+            "endpoint=foo target_session_attrs=read-only -c geqo=off",
+        )]);
+        assert_eq!(filtered_options(&params).as_deref(), Some("-c geqo=off"));
     }
 }
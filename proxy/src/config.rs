@@ -1,9 +1,13 @@
-use crate::{auth, rate_limiter::RateBucketInfo};
+use crate::{
+    auth,
+    rate_limiter::{ConnectionLimiterConfig, RateBucketInfo},
+};
 use anyhow::{bail, ensure, Context, Ok};
 use rustls::{sign, Certificate, PrivateKey};
 use sha2::{Digest, Sha256};
 use std::{
     collections::{HashMap, HashSet},
+    path::PathBuf,
     str::FromStr,
     sync::Arc,
     time::Duration,
@@ -15,12 +19,14 @@ pub struct ProxyConfig {
     pub tls_config: Option<TlsConfig>,
     pub auth_backend: auth::BackendType<'static, ()>,
     pub metric_collection: Option<MetricCollectionConfig>,
+    pub audit_log: Option<AuditLogConfig>,
     pub allow_self_signed_compute: bool,
     pub http_config: HttpConfig,
     pub authentication_config: AuthenticationConfig,
     pub require_client_ip: bool,
     pub disable_ip_check_for_http: bool,
     pub endpoint_rps_limit: Vec<RateBucketInfo>,
+    pub endpoint_connection_limit: ConnectionLimiterConfig,
 }
 
 #[derive(Debug)]
@@ -29,6 +35,34 @@ pub struct MetricCollectionConfig {
     pub interval: Duration,
 }
 
+#[derive(Debug)]
+pub struct AuditLogConfig {
+    pub sink: AuditLogSink,
+    pub interval: Duration,
+}
+
+/// Where to write the session records of [`crate::audit_log`] to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditLogSink {
+    /// Append JSON lines to a local file.
+    File(PathBuf),
+    /// POST batches of JSON lines to an HTTP endpoint.
+    Http(reqwest::Url),
+}
+
+impl FromStr for AuditLogSink {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Self::Http(s.parse()?))
+        } else {
+            ensure!(!s.is_empty(), "audit log path must not be empty");
+            Ok(Self::File(PathBuf::from(s)))
+        }
+    }
+}
+
 pub struct TlsConfig {
     pub config: Arc<rustls::ServerConfig>,
     pub common_names: Option<HashSet<String>>,
@@ -449,6 +483,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_audit_log_sink() -> anyhow::Result<()> {
+        let sink: AuditLogSink = "/var/log/proxy/sessions.log".parse()?;
+        assert_eq!(
+            sink,
+            AuditLogSink::File(PathBuf::from("/var/log/proxy/sessions.log"))
+        );
+
+        let sink: AuditLogSink = "https://audit.local/sessions".parse()?;
+        assert_eq!(
+            sink,
+            AuditLogSink::Http("https://audit.local/sessions".parse()?)
+        );
+
+        assert!("".parse::<AuditLogSink>().is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_parse_lock_options() -> anyhow::Result<()> {
         let WakeComputeLockOptions {
@@ -7,6 +7,20 @@ use std::fmt;
 #[derive(Debug, Deserialize)]
 pub struct ConsoleError {
     pub error: Box<str>,
+    /// Machine-readable cause, set for the errors the proxy handles itself.
+    #[serde(default)]
+    pub reason: Option<Reason>,
+}
+
+/// Machine-readable cause of a [`ConsoleError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Reason {
+    /// The endpoint has no running read replica.
+    ReplicaUnavailable,
+    /// A reason this version of the proxy doesn't know about.
+    #[serde(other)]
+    Unknown,
 }
 
 /// Response which holds client's auth secret, e.g. [`crate::scram::ServerSecret`].
@@ -15,6 +29,7 @@ pub struct ConsoleError {
 pub struct GetRoleSecret {
     pub role_secret: Box<str>,
     pub allowed_ips: Option<Vec<Box<str>>>,
+    pub allowed_vpc_endpoint_ids: Option<Vec<Box<str>>>,
 }
 
 // Manually implement debug to omit sensitive info.
@@ -30,6 +45,16 @@ impl fmt::Debug for GetRoleSecret {
 pub struct WakeCompute {
     pub address: Box<str>,
     pub aux: MetricsAuxInfo,
+    /// Connection limits to be enforced by the proxy, if any.
+    pub limits: Option<ConnectionLimits>,
+}
+
+/// Limits on concurrently proxied connections of an endpoint.
+/// Missing values fall back to the proxy-wide defaults.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    pub max_connections: Option<u32>,
+    pub max_connections_per_role: Option<u32>,
 }
 
 /// Async response which concludes the link auth flow.
@@ -155,6 +180,30 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_console_error() -> anyhow::Result<()> {
+        let err: ConsoleError = serde_json::from_value(json!({
+            "error": "endpoint has no running read replica",
+            "reason": "REPLICA_UNAVAILABLE",
+        }))?;
+        assert_eq!(err.reason, Some(Reason::ReplicaUnavailable));
+
+        // reasons we don't know about
+        let err: ConsoleError = serde_json::from_value(json!({
+            "error": "something new",
+            "reason": "N.E.W",
+        }))?;
+        assert_eq!(err.reason, Some(Reason::Unknown));
+
+        // no reason at all
+        let err: ConsoleError = serde_json::from_value(json!({
+            "error": "endpoint not found",
+        }))?;
+        assert_eq!(err.reason, None);
+
+        Ok(())
+    }
+
     #[test]
     fn parse_db_info() -> anyhow::Result<()> {
         // with password
@@ -197,6 +246,23 @@ mod tests {
             "aux": dummy_aux(),
         });
         let _: WakeCompute = serde_json::from_str(&json.to_string())?;
+
+        // with connection limits
+        let json = json!({
+            "address": "0.0.0.0",
+            "aux": dummy_aux(),
+            "limits": {
+                "max_connections": 100,
+            },
+        });
+        let body: WakeCompute = serde_json::from_str(&json.to_string())?;
+        assert_eq!(
+            body.limits,
+            Some(ConnectionLimits {
+                max_connections: Some(100),
+                max_connections_per_role: None,
+            })
+        );
         Ok(())
     }
 
@@ -213,6 +279,17 @@ mod tests {
             "allowed_ips": ["8.8.8.8"],
         });
         let _: GetRoleSecret = serde_json::from_str(&json.to_string())?;
+        // With `allowed_vpc_endpoint_ids` field.
+        let json = json!({
+            "role_secret": "secret",
+            "allowed_ips": ["8.8.8.8"],
+            "allowed_vpc_endpoint_ids": ["vpce-0123456789abcdef0"],
+        });
+        let body: GetRoleSecret = serde_json::from_str(&json.to_string())?;
+        assert_eq!(
+            body.allowed_vpc_endpoint_ids.as_deref(),
+            Some(&["vpce-0123456789abcdef0".into()][..])
+        );
 
         Ok(())
     }
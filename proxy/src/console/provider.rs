@@ -1,8 +1,9 @@
+pub mod file;
 #[cfg(feature = "testing")]
 pub mod mock;
 pub mod neon;
 
-use super::messages::MetricsAuxInfo;
+use super::messages::{ConnectionLimits, MetricsAuxInfo};
 use crate::{
     auth::backend::ComputeUserInfo,
     cache::{timed_lru, TimedLru},
@@ -19,6 +20,7 @@ use tracing::info;
 
 pub mod errors {
     use crate::{
+        console::messages::Reason,
         error::{io_error, UserFacingError},
         http,
         proxy::ShouldRetry,
@@ -36,6 +38,7 @@ pub mod errors {
         Console {
             status: http::StatusCode,
             text: Box<str>,
+            reason: Option<Reason>,
         },
 
         /// Various IO errors like broken pipe or malformed payload.
@@ -60,6 +63,10 @@ pub mod errors {
             match self {
                 // To minimize risks, only select errors are forwarded to users.
                 // Ask @neondatabase/control-plane for review before adding more.
+                Console {
+                    reason: Some(Reason::ReplicaUnavailable),
+                    ..
+                } => format!("{REQUEST_FAILED}: endpoint has no running read replica"),
                 Console { status, .. } => match *status {
                     http::StatusCode::NOT_FOUND => {
                         // Status 404: failed to get a project-related resource.
@@ -96,6 +103,7 @@ pub mod errors {
                 Self::Console {
                     status: http::StatusCode::LOCKED,
                     ref text,
+                    ..
                 } => {
                     // written data quota exceeded
                     // data transfer quota exceeded
@@ -168,6 +176,21 @@ pub mod errors {
         }
     }
 
+    impl WakeComputeError {
+        /// Console couldn't find a running read replica of the endpoint.
+        ///
+        /// Other errors (e.g. the endpoint itself is gone) don't qualify.
+        pub fn is_replica_unavailable(&self) -> bool {
+            matches!(
+                self,
+                WakeComputeError::ApiError(ApiError::Console {
+                    reason: Some(Reason::ReplicaUnavailable),
+                    ..
+                })
+            )
+        }
+    }
+
     impl From<tokio::sync::AcquireError> for WakeComputeError {
         fn from(_: tokio::sync::AcquireError) -> Self {
             WakeComputeError::TimeoutError
@@ -230,6 +253,8 @@ pub struct AuthInfo {
     pub secret: Option<AuthSecret>,
     /// List of IP addresses allowed for the autorization.
     pub allowed_ips: Vec<String>,
+    /// List of private link ids (e.g. AWS VPC endpoint ids) allowed for the authorization.
+    pub allowed_vpc_endpoint_ids: Vec<String>,
 }
 
 /// Info for establishing a connection to a compute node.
@@ -246,6 +271,9 @@ pub struct NodeInfo {
 
     /// Whether we should accept self-signed certificates (for testing)
     pub allow_self_signed_compute: bool,
+
+    /// Per-endpoint connection limits reported by the console.
+    pub limits: Option<ConnectionLimits>,
 }
 
 pub type NodeInfoCache = TimedLru<Arc<str>, NodeInfo>;
@@ -269,6 +297,12 @@ pub trait Api {
         creds: &ComputeUserInfo,
     ) -> Result<Arc<Vec<String>>, errors::GetAuthInfoError>;
 
+    async fn get_allowed_vpc_endpoint_ids(
+        &self,
+        extra: &ConsoleReqExtra,
+        creds: &ComputeUserInfo,
+    ) -> Result<Arc<Vec<String>>, errors::GetAuthInfoError>;
+
     /// Wake up the compute node and return the corresponding connection info.
     async fn wake_compute(
         &self,
@@ -283,6 +317,8 @@ pub struct ApiCaches {
     pub node_info: NodeInfoCache,
     /// Cache for the `get_allowed_ips`. TODO(anna): use notifications listener instead.
     pub allowed_ips: TimedLru<Arc<str>, Arc<Vec<String>>>,
+    /// Cache for the `get_allowed_vpc_endpoint_ids`.
+    pub allowed_vpc_endpoint_ids: TimedLru<Arc<str>, Arc<Vec<String>>>,
 }
 
 /// Various caches for [`console`](super).
@@ -0,0 +1,575 @@
+//! Static console backend which serves endpoints from a local TOML or JSON file.
+//! This lets the proxy run without the Neon control plane, e.g. in self-hosted setups.
+//!
+//! Example (TOML):
+//!
+//! ```toml
+//! [[endpoints]]
+//! id = "ep-foo-bar-1234"
+//! project_id = "my-project"
+//! compute = "10.0.0.1:5432"
+//! replicas = ["10.0.0.2:5432"]
+//! allowed_ips = ["10.0.0.0/8"]
+//!
+//! [[endpoints.roles]]
+//! name = "alice"
+//! secret = "SCRAM-SHA-256$4096:...$...:..."
+//! ```
+//!
+//! The file is polled for changes and reloaded in place, see [`Api::reload_worker`].
+//! A file which fails to load is reported and the last good version stays in effect.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    errors::{ApiError, GetAuthInfoError, WakeComputeError},
+    AuthInfo, AuthSecret, CachedNodeInfo, ConsoleReqExtra, NodeInfo,
+};
+use crate::{
+    auth::{backend::ComputeUserInfo, ComputeTarget},
+    compute,
+    console::messages::{ConnectionLimits, MetricsAuxInfo, Reason},
+    http, scram,
+};
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use smol_str::SmolStr;
+use thiserror::Error;
+use tokio_postgres::config::SslMode;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("failed to read endpoints file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("failed to parse endpoints file: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("failed to parse endpoints file: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("endpoint '{0}' is defined more than once")]
+    DuplicateEndpoint(SmolStr),
+
+    #[error("endpoint '{endpoint}': invalid compute address '{addr}'")]
+    InvalidAddress { endpoint: SmolStr, addr: String },
+
+    #[error("endpoint '{endpoint}': invalid SCRAM secret for role '{role}'")]
+    InvalidSecret { endpoint: SmolStr, role: SmolStr },
+}
+
+/// Contents of the endpoints file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EndpointsFile {
+    #[serde(default)]
+    pub endpoints: Vec<EndpointConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EndpointConfig {
+    /// Endpoint id, as passed by the client via SNI or the `endpoint` option.
+    pub id: SmolStr,
+    #[serde(default)]
+    pub project_id: SmolStr,
+    #[serde(default)]
+    pub branch_id: SmolStr,
+    /// Address (`host:port`) of the primary compute node.
+    pub compute: String,
+    /// Addresses (`host:port`) of the read replicas, if any.
+    #[serde(default)]
+    pub replicas: Vec<String>,
+    /// Whether the compute nodes require TLS.
+    #[serde(default)]
+    pub compute_tls: bool,
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+    #[serde(default)]
+    pub allowed_vpc_endpoint_ids: Vec<String>,
+    #[serde(default)]
+    pub limits: Option<ConnectionLimits>,
+    #[serde(default)]
+    pub roles: Vec<RoleConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoleConfig {
+    pub name: SmolStr,
+    /// SCRAM secret in the `pg_authid.rolpassword` format.
+    pub secret: String,
+}
+
+impl EndpointsFile {
+    /// Parse the file contents, using JSON for `*.json` files and TOML otherwise.
+    pub fn parse(path: &Path, contents: &str) -> Result<Self, LoadError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(serde_json::from_str(contents)?),
+            _ => Ok(toml::from_str(contents)?),
+        }
+    }
+}
+
+/// Validated endpoint, ready to be served.
+struct Endpoint {
+    aux: MetricsAuxInfo,
+    primary: (String, u16),
+    replicas: Vec<(String, u16)>,
+    compute_tls: bool,
+    allowed_ips: Arc<Vec<String>>,
+    allowed_vpc_endpoint_ids: Arc<Vec<String>>,
+    limits: Option<ConnectionLimits>,
+    /// Role name -> SCRAM secret. Secrets are validated on load.
+    roles: HashMap<SmolStr, String>,
+}
+
+impl Endpoint {
+    fn node_info(&self, addr: &(String, u16)) -> NodeInfo {
+        let mut config = compute::ConnCfg::new();
+        config
+            .host(&addr.0)
+            .port(addr.1)
+            .ssl_mode(if self.compute_tls {
+                SslMode::Require
+            } else {
+                SslMode::Disable
+            });
+
+        NodeInfo {
+            config,
+            aux: self.aux.clone(),
+            allow_self_signed_compute: false,
+            limits: self.limits,
+        }
+    }
+}
+
+/// Modification time and length of the file, used to detect changes.
+/// The length helps on filesystems with a coarse mtime resolution.
+type FileVersion = (SystemTime, u64);
+
+fn file_version(path: &Path) -> io::Result<Option<FileVersion>> {
+    let metadata = std::fs::metadata(path)?;
+    Ok(metadata
+        .modified()
+        .ok()
+        .map(|mtime| (mtime, metadata.len())))
+}
+
+struct Endpoints {
+    endpoints: HashMap<SmolStr, Endpoint>,
+    /// Version of the file this snapshot was loaded from.
+    version: Option<FileVersion>,
+}
+
+impl Endpoints {
+    fn new(file: EndpointsFile, version: Option<FileVersion>) -> Result<Self, LoadError> {
+        let mut endpoints = HashMap::with_capacity(file.endpoints.len());
+        for ep in file.endpoints {
+            let parse_addr = |addr: &str| {
+                parse_host_port(addr).ok_or_else(|| LoadError::InvalidAddress {
+                    endpoint: ep.id.clone(),
+                    addr: addr.to_owned(),
+                })
+            };
+            let primary = parse_addr(&ep.compute)?;
+            let replicas = ep
+                .replicas
+                .iter()
+                .map(|addr| parse_addr(addr))
+                .collect::<Result<_, _>>()?;
+
+            let mut roles = HashMap::with_capacity(ep.roles.len());
+            for role in ep.roles {
+                if scram::ServerSecret::parse(&role.secret).is_none() {
+                    return Err(LoadError::InvalidSecret {
+                        endpoint: ep.id,
+                        role: role.name,
+                    });
+                }
+                roles.insert(role.name, role.secret);
+            }
+
+            let endpoint = Endpoint {
+                aux: MetricsAuxInfo {
+                    endpoint_id: ep.id.clone(),
+                    project_id: ep.project_id,
+                    branch_id: ep.branch_id,
+                },
+                primary,
+                replicas,
+                compute_tls: ep.compute_tls,
+                allowed_ips: Arc::new(ep.allowed_ips),
+                allowed_vpc_endpoint_ids: Arc::new(ep.allowed_vpc_endpoint_ids),
+                limits: ep.limits,
+                roles,
+            };
+            if endpoints.insert(ep.id.clone(), endpoint).is_some() {
+                return Err(LoadError::DuplicateEndpoint(ep.id));
+            }
+        }
+
+        Ok(Self { endpoints, version })
+    }
+
+    fn load(path: &Path) -> Result<Self, LoadError> {
+        // Check the version first, so that a write racing with the read triggers another reload.
+        let version = file_version(path)?;
+        let contents = std::fs::read_to_string(path)?;
+        Self::new(EndpointsFile::parse(path, &contents)?, version)
+    }
+}
+
+/// Parse `host:port`, where the host may be a bracketed IPv6 address.
+fn parse_host_port(addr: &str) -> Option<(String, u16)> {
+    let (host, port) = addr.rsplit_once(':')?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_owned(), port.parse().ok()?))
+}
+
+fn endpoint_not_found(endpoint: &str) -> WakeComputeError {
+    ApiError::Console {
+        status: http::StatusCode::NOT_FOUND,
+        text: format!("endpoint '{endpoint}' not found").into(),
+        reason: None,
+    }
+    .into()
+}
+
+fn replica_unavailable(endpoint: &str) -> WakeComputeError {
+    ApiError::Console {
+        status: http::StatusCode::NOT_FOUND,
+        text: format!("endpoint '{endpoint}' has no running read replica").into(),
+        reason: Some(Reason::ReplicaUnavailable),
+    }
+    .into()
+}
+
+struct Inner {
+    path: PathBuf,
+    endpoints: RwLock<Arc<Endpoints>>,
+}
+
+#[derive(Clone)]
+pub struct Api {
+    inner: Arc<Inner>,
+}
+
+impl Api {
+    /// Load the endpoints file. Unlike reloads, failing to load it here is fatal.
+    pub fn new(path: PathBuf) -> Result<Self, LoadError> {
+        let endpoints = Endpoints::load(&path)?;
+        info!(
+            path = %path.display(),
+            endpoints = endpoints.endpoints.len(),
+            "loaded endpoints file"
+        );
+        Ok(Self {
+            inner: Arc::new(Inner {
+                path,
+                endpoints: RwLock::new(Arc::new(endpoints)),
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    fn endpoints(&self) -> Arc<Endpoints> {
+        self.inner.endpoints.read().clone()
+    }
+
+    /// Reload the endpoints file if it has changed since the last (re)load.
+    /// Returns `true` if the new version has been swapped in.
+    pub fn reload(&self) -> Result<bool, LoadError> {
+        let version = file_version(&self.inner.path)?;
+        if version.is_some() && version == self.endpoints().version {
+            return Ok(false);
+        }
+
+        let endpoints = Endpoints::load(&self.inner.path)?;
+        info!(
+            path = %self.inner.path.display(),
+            endpoints = endpoints.endpoints.len(),
+            "reloaded endpoints file"
+        );
+        *self.inner.endpoints.write() = Arc::new(endpoints);
+        Ok(true)
+    }
+
+    /// Poll the endpoints file for changes every `interval`.
+    pub async fn reload_worker(self, interval: Duration) -> anyhow::Result<Infallible> {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.reload() {
+                error!(
+                    path = %self.inner.path.display(),
+                    "failed to reload endpoints file, keeping the previous version: {e}"
+                );
+            }
+        }
+    }
+
+    fn do_get_auth_info(&self, creds: &ComputeUserInfo) -> AuthInfo {
+        let endpoints = self.endpoints();
+        let Some(endpoint) = endpoints.endpoints.get(&creds.endpoint) else {
+            // Same as a missing role: the secret gets mocked and authentication fails.
+            warn!("endpoint '{}' does not exist", creds.endpoint);
+            return AuthInfo::default();
+        };
+
+        let secret = match endpoint.roles.get(&creds.inner.user) {
+            Some(secret) => scram::ServerSecret::parse(secret).map(AuthSecret::Scram),
+            None => {
+                warn!("user '{}' does not exist", creds.inner.user);
+                None
+            }
+        };
+
+        AuthInfo {
+            secret,
+            allowed_ips: endpoint.allowed_ips.to_vec(),
+            allowed_vpc_endpoint_ids: endpoint.allowed_vpc_endpoint_ids.to_vec(),
+        }
+    }
+
+    fn do_wake_compute(&self, creds: &ComputeUserInfo) -> Result<NodeInfo, WakeComputeError> {
+        let endpoints = self.endpoints();
+        let endpoint = endpoints
+            .endpoints
+            .get(&creds.endpoint)
+            .ok_or_else(|| endpoint_not_found(&creds.endpoint))?;
+
+        let addr = match creds.inner.target {
+            ComputeTarget::Primary => &endpoint.primary,
+            // Same response as the console gives for endpoints without replicas.
+            ComputeTarget::ReadReplica | ComputeTarget::PreferReadReplica => endpoint
+                .replicas
+                .choose(&mut rand::thread_rng())
+                .ok_or_else(|| replica_unavailable(&creds.endpoint))?,
+        };
+
+        Ok(endpoint.node_info(addr))
+    }
+}
+
+#[async_trait]
+impl super::Api for Api {
+    #[tracing::instrument(skip_all)]
+    async fn get_auth_info(
+        &self,
+        _extra: &ConsoleReqExtra,
+        creds: &ComputeUserInfo,
+    ) -> Result<AuthInfo, GetAuthInfoError> {
+        Ok(self.do_get_auth_info(creds))
+    }
+
+    async fn get_allowed_ips(
+        &self,
+        _extra: &ConsoleReqExtra,
+        creds: &ComputeUserInfo,
+    ) -> Result<Arc<Vec<String>>, GetAuthInfoError> {
+        let endpoints = self.endpoints();
+        Ok(endpoints
+            .endpoints
+            .get(&creds.endpoint)
+            .map(|ep| ep.allowed_ips.clone())
+            .unwrap_or_default())
+    }
+
+    async fn get_allowed_vpc_endpoint_ids(
+        &self,
+        _extra: &ConsoleReqExtra,
+        creds: &ComputeUserInfo,
+    ) -> Result<Arc<Vec<String>>, GetAuthInfoError> {
+        let endpoints = self.endpoints();
+        Ok(endpoints
+            .endpoints
+            .get(&creds.endpoint)
+            .map(|ep| ep.allowed_vpc_endpoint_ids.clone())
+            .unwrap_or_default())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn wake_compute(
+        &self,
+        _extra: &ConsoleReqExtra,
+        creds: &ComputeUserInfo,
+    ) -> Result<CachedNodeInfo, WakeComputeError> {
+        self.do_wake_compute(creds)
+            .map(CachedNodeInfo::new_uncached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::backend::ComputeUserInfoNoEndpoint;
+    use crate::console::provider::Api as _;
+
+    const SECRET: &str = "SCRAM-SHA-256$4096:+/tQQax7twvwTj64mjBsxQ==$\
+        D5h6KTMBlUvDJk2Y8ELfC1Sjtc6k9YHjRyuRZyBNJns=:\
+        Pi3QHbcluX//NDfVkKlFl88GGzlJ5LkyPwcdlN/QBvI=";
+
+    fn user_info(endpoint: &str, user: &str, target: ComputeTarget) -> ComputeUserInfo {
+        ComputeUserInfo {
+            endpoint: endpoint.into(),
+            inner: ComputeUserInfoNoEndpoint {
+                user: user.into(),
+                peer_addr: [127, 0, 0, 1].into(),
+                private_link_id: None,
+                target,
+                cache_key: endpoint.into(),
+            },
+        }
+    }
+
+    #[test]
+    fn parse_toml() {
+        let toml = format!(
+            r#"
+            [[endpoints]]
+            id = "ep-foo"
+            project_id = "project"
+            compute = "127.0.0.1:5432"
+            replicas = ["[::1]:5433"]
+            allowed_ips = ["10.0.0.0/8"]
+            limits = {{ max_connections = 10 }}
+
+            [[endpoints.roles]]
+            name = "alice"
+            secret = "{SECRET}"
+            "#
+        );
+        let file = EndpointsFile::parse(Path::new("endpoints.toml"), &toml).unwrap();
+        let endpoints = Endpoints::new(file, None).unwrap();
+
+        let ep = &endpoints.endpoints["ep-foo"];
+        assert_eq!(ep.aux.project_id, "project");
+        assert_eq!(ep.primary, ("127.0.0.1".to_owned(), 5432));
+        assert_eq!(ep.replicas, [("::1".to_owned(), 5433)]);
+        assert_eq!(*ep.allowed_ips, ["10.0.0.0/8"]);
+        assert_eq!(ep.limits.unwrap().max_connections, Some(10));
+        assert!(ep.roles.contains_key("alice"));
+    }
+
+    #[test]
+    fn parse_json() {
+        let json = r#"{"endpoints": [{"id": "ep-foo", "compute": "localhost:5432"}]}"#;
+        let file = EndpointsFile::parse(Path::new("endpoints.json"), json).unwrap();
+        let endpoints = Endpoints::new(file, None).unwrap();
+        assert_eq!(endpoints.endpoints["ep-foo"].primary.0, "localhost");
+    }
+
+    #[test]
+    fn reject_invalid_files() {
+        let load = |json: &str| {
+            let file = EndpointsFile::parse(Path::new("endpoints.json"), json)?;
+            Endpoints::new(file, None).map(|_| ())
+        };
+
+        let err = load(r#"{"endpoints": [{"id": "ep", "compute": "localhost"}]}"#);
+        assert!(matches!(err, Err(LoadError::InvalidAddress { .. })));
+
+        let err = load(
+            r#"{"endpoints": [{"id": "ep", "compute": "localhost:5432",
+                "roles": [{"name": "alice", "secret": "md5deadbeef"}]}]}"#,
+        );
+        assert!(matches!(err, Err(LoadError::InvalidSecret { .. })));
+
+        let err = load(
+            r#"{"endpoints": [{"id": "ep", "compute": "localhost:5432"},
+                {"id": "ep", "compute": "localhost:5433"}]}"#,
+        );
+        assert!(matches!(err, Err(LoadError::DuplicateEndpoint(_))));
+
+        let err = load(r#"{"endpoints": [{"id": "ep", "compute": "a:1", "typo": 1}]}"#);
+        assert!(matches!(err, Err(LoadError::Json(_))));
+    }
+
+    #[tokio::test]
+    async fn serve_and_reload() {
+        let path = std::env::temp_dir().join(format!("endpoints-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            &format!(
+                r#"{{"endpoints": [{{"id": "ep-foo", "compute": "127.0.0.1:5432",
+                    "allowed_ips": ["127.0.0.1"],
+                    "roles": [{{"name": "alice", "secret": "{SECRET}"}}]}}]}}"#
+            ),
+        )
+        .unwrap();
+        let api = Api::new(path.clone()).unwrap();
+        let extra = ConsoleReqExtra {
+            session_id: uuid::Uuid::new_v4(),
+            application_name: "TEST".into(),
+            options: vec![],
+        };
+
+        let alice = user_info("ep-foo", "alice", ComputeTarget::Primary);
+        let auth_info = api.get_auth_info(&extra, &alice).await.unwrap();
+        assert!(matches!(auth_info.secret, Some(AuthSecret::Scram(_))));
+        assert_eq!(auth_info.allowed_ips, ["127.0.0.1"]);
+
+        let bob = user_info("ep-foo", "bob", ComputeTarget::Primary);
+        assert!(api
+            .get_auth_info(&extra, &bob)
+            .await
+            .unwrap()
+            .secret
+            .is_none());
+
+        let node = api.wake_compute(&extra, &alice).await.unwrap();
+        assert_eq!(node.aux.endpoint_id, "ep-foo");
+
+        // No replicas: the caller falls back to the primary if the target allows it.
+        for target in [ComputeTarget::ReadReplica, ComputeTarget::PreferReadReplica] {
+            let replica = user_info("ep-foo", "alice", target);
+            let res = api.wake_compute(&extra, &replica).await;
+            assert!(matches!(res, Err(e) if e.is_replica_unavailable()));
+        }
+
+        let unknown = user_info("ep-bar", "alice", ComputeTarget::Primary);
+        let res = api.wake_compute(&extra, &unknown).await;
+        assert!(matches!(res, Err(e) if !e.is_replica_unavailable()));
+
+        // Unchanged file isn't reloaded.
+        assert!(!api.reload().unwrap());
+
+        // A broken file keeps the previous version in effect.
+        std::fs::write(&path, "{").unwrap();
+        assert!(api.reload().is_err());
+        assert!(api.wake_compute(&extra, &alice).await.is_ok());
+
+        std::fs::write(
+            &path,
+            r#"{"endpoints": [{"id": "ep-bar", "compute": "127.0.0.1:5432"}]}"#,
+        )
+        .unwrap();
+        assert!(api.reload().unwrap());
+        assert!(api.wake_compute(&extra, &alice).await.is_err());
+        assert!(api.wake_compute(&extra, &unknown).await.is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
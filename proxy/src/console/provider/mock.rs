@@ -98,6 +98,7 @@ impl Api {
         Ok(AuthInfo {
             secret,
             allowed_ips,
+            allowed_vpc_endpoint_ids: vec![],
         })
     }
 
@@ -112,6 +113,7 @@ impl Api {
             config,
             aux: Default::default(),
             allow_self_signed_compute: false,
+            limits: None,
         };
 
         Ok(node)
@@ -158,6 +160,14 @@ impl super::Api for Api {
         Ok(Arc::new(self.do_get_auth_info(creds).await?.allowed_ips))
     }
 
+    async fn get_allowed_vpc_endpoint_ids(
+        &self,
+        _extra: &ConsoleReqExtra,
+        _creds: &ComputeUserInfo,
+    ) -> Result<Arc<Vec<String>>, GetAuthInfoError> {
+        Ok(Arc::new(vec![]))
+    }
+
     #[tracing::instrument(skip_all)]
     async fn wake_compute(
         &self,
@@ -89,9 +89,16 @@ impl Api {
                 .map(String::from)
                 .collect_vec();
             ALLOWED_IPS_NUMBER.observe(allowed_ips.len() as f64);
+            let allowed_vpc_endpoint_ids = body
+                .allowed_vpc_endpoint_ids
+                .into_iter()
+                .flatten()
+                .map(String::from)
+                .collect_vec();
             Ok(AuthInfo {
                 secret: Some(secret),
                 allowed_ips,
+                allowed_vpc_endpoint_ids,
             })
         }
         .map_err(crate::error::log_error)
@@ -117,6 +124,10 @@ impl Api {
                     ("project", creds.endpoint.as_str()),
                 ]);
 
+            if creds.inner.target.is_read_replica() {
+                request_builder = request_builder.query(&[("endpoint_type", "read_only")]);
+            }
+
             request_builder = if extra.options.is_empty() {
                 request_builder
             } else {
@@ -146,6 +157,7 @@ impl Api {
                 config,
                 aux: body.aux,
                 allow_self_signed_compute: false,
+                limits: body.limits,
             };
 
             Ok(node)
@@ -189,13 +201,34 @@ impl super::Api for Api {
         Ok(allowed_ips)
     }
 
+    async fn get_allowed_vpc_endpoint_ids(
+        &self,
+        extra: &ConsoleReqExtra,
+        creds: &ComputeUserInfo,
+    ) -> Result<Arc<Vec<String>>, GetAuthInfoError> {
+        let key: &str = &creds.endpoint;
+        if let Some(allowed_ids) = self.caches.allowed_vpc_endpoint_ids.get(key) {
+            return Ok(Arc::new(allowed_ids.to_vec()));
+        }
+        let allowed_ids = Arc::new(
+            self.do_get_auth_info(extra, creds)
+                .await?
+                .allowed_vpc_endpoint_ids,
+        );
+        self.caches
+            .allowed_vpc_endpoint_ids
+            .insert(key.into(), allowed_ids.clone());
+        Ok(allowed_ids)
+    }
+
     #[tracing::instrument(skip_all)]
     async fn wake_compute(
         &self,
         extra: &ConsoleReqExtra,
         creds: &ComputeUserInfo,
     ) -> Result<CachedNodeInfo, WakeComputeError> {
-        let key: &str = &creds.inner.cache_key;
+        let key = creds.wake_compute_cache_key();
+        let key: &str = &key;
 
         // Every time we do a wakeup http request, the compute node will stay up
         // for some time (highly depends on the console's scale-to-zero policy);
@@ -244,12 +277,20 @@ async fn parse_body<T: for<'a> serde::Deserialize<'a>>(
         warn!("failed to parse error body: {e}");
         ConsoleError {
             error: "reason unclear (malformed error message)".into(),
+            reason: None,
         }
     });
 
-    let text = body.error;
+    let ConsoleError {
+        error: text,
+        reason,
+    } = body;
     error!("console responded with an error ({status}): {text}");
-    Err(ApiError::Console { status, text })
+    Err(ApiError::Console {
+        status,
+        text,
+        reason,
+    })
 }
 
 fn parse_host_port(input: &str) -> Option<(String, u16)> {
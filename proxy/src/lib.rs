@@ -7,6 +7,7 @@ use tokio::task::JoinError;
 use tokio_util::sync::CancellationToken;
 use tracing::warn;
 
+pub mod audit_log;
 pub mod auth;
 pub mod cache;
 pub mod cancellation;
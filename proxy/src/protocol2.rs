@@ -12,8 +12,10 @@ use std::{
 use bytes::{Buf, BytesMut};
 use hyper::server::conn::{AddrIncoming, AddrStream};
 use pin_project_lite::pin_project;
+use smol_str::SmolStr;
 use tls_listener::AsyncAccept;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tracing::warn;
 
 pub struct ProxyProtocolAccept {
     pub incoming: AddrIncoming,
@@ -24,11 +26,74 @@ pin_project! {
         #[pin]
         pub inner: T,
         buf: BytesMut,
-        tlv_bytes: u16,
+        tlvs: ProxyTlvs,
         state: ProxyParse,
     }
 }
 
+/// Type-Length-Value vectors of the proxy protocol header which we know how to interpret.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProxyTlvs {
+    /// `PP2_TYPE_AUTHORITY`: the host name the client connected to (usually from SNI).
+    pub authority: Option<SmolStr>,
+    /// `PP2_TYPE_AWS` with `PP2_SUBTYPE_AWS_VPCE_ID`: the AWS VPC endpoint the connection came through.
+    pub aws_vpce_id: Option<SmolStr>,
+    /// `PP2_TYPE_AZURE` with `PP2_SUBTYPE_AZURE_PRIVATEENDPOINT_LINKID`: the Azure private endpoint link.
+    pub azure_link_id: Option<u32>,
+}
+
+impl ProxyTlvs {
+    /// ID of the private link (AWS VPC endpoint or Azure private endpoint)
+    /// the connection came through, as it's listed in the console.
+    pub fn private_link_id(&self) -> Option<SmolStr> {
+        self.aws_vpce_id
+            .clone()
+            .or_else(|| self.azure_link_id.map(|id| id.to_string().into()))
+    }
+}
+
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+const PP2_TYPE_AWS: u8 = 0xEA;
+const PP2_SUBTYPE_AWS_VPCE_ID: u8 = 0x01;
+const PP2_TYPE_AZURE: u8 = 0xEE;
+const PP2_SUBTYPE_AZURE_PRIVATEENDPOINT_LINKID: u8 = 0x01;
+
+impl ProxyTlvs {
+    /// Parse the TLV section of the header, skipping the vectors we don't know.
+    /// Malformed vectors stop the parsing, but are not considered to be an error.
+    fn parse(mut bytes: &[u8]) -> Self {
+        let mut tlvs = Self::default();
+        while !bytes.is_empty() {
+            if bytes.len() < 3 {
+                warn!("truncated proxy protocol TLV header");
+                break;
+            }
+            let kind = bytes[0];
+            let len = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+            let Some(value) = bytes.get(3..3 + len) else {
+                warn!(kind, len, "proxy protocol TLV value is out of bounds");
+                break;
+            };
+            bytes = &bytes[3 + len..];
+
+            match (kind, value) {
+                (PP2_TYPE_AUTHORITY, _) => {
+                    tlvs.authority = std::str::from_utf8(value).ok().map(SmolStr::from);
+                }
+                (PP2_TYPE_AWS, [PP2_SUBTYPE_AWS_VPCE_ID, vpce_id @ ..]) => {
+                    tlvs.aws_vpce_id = std::str::from_utf8(vpce_id).ok().map(SmolStr::from);
+                }
+                (PP2_TYPE_AZURE, [PP2_SUBTYPE_AZURE_PRIVATEENDPOINT_LINKID, link_id @ ..]) => {
+                    tlvs.azure_link_id = <[u8; 4]>::try_from(link_id).ok().map(u32::from_le_bytes);
+                }
+                // ALPN, CRC32C, NOOP, UNIQUE_ID, SSL, NETNS and custom types
+                _ => {}
+            }
+        }
+        tlvs
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 enum ProxyParse {
     NotStarted,
@@ -77,7 +142,7 @@ impl<T> WithClientIp<T> {
         WithClientIp {
             inner,
             buf: BytesMut::with_capacity(128),
-            tlv_bytes: 0,
+            tlvs: ProxyTlvs::default(),
             state: ProxyParse::NotStarted,
         }
     }
@@ -88,6 +153,12 @@ impl<T> WithClientIp<T> {
             _ => None,
         }
     }
+
+    /// Known TLVs sent along with the client address.
+    /// Empty until the header is parsed, e.g. with [`WithClientIp::wait_for_addr`].
+    pub fn tlvs(&self) -> &ProxyTlvs {
+        &self.tlvs
+    }
 }
 
 impl<T: AsyncRead + Unpin> WithClientIp<T> {
@@ -207,12 +278,18 @@ impl<T: AsyncRead> WithClientIp<T> {
             )));
         }
 
-        while self.buf.len() < 16 + address_length as usize {
+        // Read the whole header, including the TLVs. It's at most 64KiB.
+        let header_length = 16 + remaining_length as usize;
+        if self.buf.len() < header_length {
+            let additional = header_length - self.buf.len();
+            self.as_mut().project().buf.reserve(additional);
+        }
+        while self.buf.len() < header_length {
             let mut this = self.as_mut().project();
             if ready!(pin!(this.inner.read_buf(this.buf)).poll(cx)?) == 0 {
                 return Poll::Ready(Err(io::Error::new(
                     io::ErrorKind::UnexpectedEof,
-                    "stream closed while waiting for proxy protocol addresses",
+                    "stream closed while waiting for proxy protocol header",
                 )));
             }
         }
@@ -244,8 +321,11 @@ impl<T: AsyncRead> WithClientIp<T> {
             _ => None,
         };
 
-        *this.tlv_bytes = remaining_length - address_length;
-        self.as_mut().skip_tlv_inner();
+        // After the addresses, the rest of the header is a sequence of TLVs.
+        let tlvs = this
+            .buf
+            .split_to((remaining_length - address_length) as usize);
+        *this.tlvs = ProxyTlvs::parse(&tlvs);
 
         Poll::Ready(Ok(socket))
     }
@@ -259,31 +339,6 @@ impl<T: AsyncRead> WithClientIp<T> {
         }
         Poll::Ready(Ok(()))
     }
-
-    #[cold]
-    fn skip_tlv(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        let mut this = self.as_mut().project();
-        // we know that this.buf is empty
-        debug_assert_eq!(this.buf.len(), 0);
-
-        this.buf.reserve((*this.tlv_bytes).clamp(0, 1024) as usize);
-        ready!(pin!(this.inner.read_buf(this.buf)).poll(cx)?);
-        self.skip_tlv_inner();
-
-        Poll::Ready(Ok(()))
-    }
-
-    fn skip_tlv_inner(self: Pin<&mut Self>) {
-        let tlv_bytes_read = match u16::try_from(self.buf.len()) {
-            // we read more than u16::MAX therefore we must have read the full tlv_bytes
-            Err(_) => self.tlv_bytes,
-            // we might not have read the full tlv bytes yet
-            Ok(n) => u16::min(n, self.tlv_bytes),
-        };
-        let this = self.project();
-        *this.tlv_bytes -= tlv_bytes_read;
-        this.buf.advance(tlv_bytes_read as usize);
-    }
 }
 
 impl<T: AsyncRead> AsyncRead for WithClientIp<T> {
@@ -293,7 +348,7 @@ impl<T: AsyncRead> AsyncRead for WithClientIp<T> {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
-        // I'm assuming these 3 comparisons will be easy to branch predict.
+        // I'm assuming these 2 comparisons will be easy to branch predict.
         // especially with the cold attributes
         // which should make this read wrapper almost invisible
 
@@ -301,17 +356,10 @@ impl<T: AsyncRead> AsyncRead for WithClientIp<T> {
             ready!(self.as_mut().read_ip(cx)?);
         }
 
-        while self.tlv_bytes > 0 {
-            ready!(self.as_mut().skip_tlv(cx)?)
-        }
-
         let this = self.project();
         if this.buf.is_empty() {
             this.inner.poll_read(cx, buf)
         } else {
-            // we know that tlv_bytes is 0
-            debug_assert_eq!(*this.tlv_bytes, 0);
-
             let write = usize::min(this.buf.len(), buf.remaining());
             let slice = this.buf.split_to(write).freeze();
             buf.put_slice(&slice);
@@ -350,7 +398,7 @@ mod tests {
 
     use tokio::io::AsyncReadExt;
 
-    use crate::protocol2::{ProxyParse, WithClientIp};
+    use crate::protocol2::{ProxyParse, ProxyTlvs, WithClientIp};
 
     #[tokio::test]
     async fn test_ipv4() {
@@ -476,4 +524,77 @@ mod tests {
             ProxyParse::Finished(([55, 56, 57, 58], 65535).into())
         );
     }
+
+    #[tokio::test]
+    async fn test_known_tlvs() {
+        let vpce_id = b"vpce-0123456789abcdef0";
+        // PP2_TYPE_AUTHORITY
+        let tlvs: Vec<u8> = [0x02, 0, 9]
+            .into_iter()
+            .chain(*b"neon.tech")
+            // PP2_TYPE_NOOP
+            .chain([0x04, 0, 2, 0, 0])
+            // PP2_TYPE_AWS, PP2_SUBTYPE_AWS_VPCE_ID
+            .chain([0xEA, 0, 1 + vpce_id.len() as u8, 0x01])
+            .chain(*vpce_id)
+            // PP2_TYPE_AZURE, PP2_SUBTYPE_AZURE_PRIVATEENDPOINT_LINKID
+            .chain([0xEE, 0, 5, 0x01])
+            .chain(42u32.to_le_bytes())
+            .collect();
+        let len = (12 + tlvs.len() as u16).to_be_bytes();
+
+        let header = super::HEADER
+            // Proxy command, IPV4 | TCP
+            .chain([(2 << 4) | 1, (1 << 4) | 1].as_slice())
+            .chain(len.as_slice())
+            // src ip
+            .chain([127, 0, 0, 1].as_slice())
+            // dst ip
+            .chain([192, 168, 0, 1].as_slice())
+            // src port
+            .chain([255, 255].as_slice())
+            // dst port
+            .chain([1, 1].as_slice())
+            // TLV
+            .chain(tlvs.as_slice());
+
+        let extra_data = [0x55; 256];
+
+        let mut read = pin!(WithClientIp::new(header.chain(extra_data.as_slice())));
+
+        let mut bytes = vec![];
+        read.read_to_end(&mut bytes).await.unwrap();
+
+        assert_eq!(bytes, extra_data);
+        assert_eq!(
+            read.state,
+            ProxyParse::Finished(([127, 0, 0, 1], 65535).into())
+        );
+        assert_eq!(
+            read.tlvs(),
+            &ProxyTlvs {
+                authority: Some("neon.tech".into()),
+                aws_vpce_id: Some("vpce-0123456789abcdef0".into()),
+                azure_link_id: Some(42),
+            }
+        );
+        assert_eq!(
+            read.tlvs().private_link_id().as_deref(),
+            Some("vpce-0123456789abcdef0")
+        );
+    }
+
+    #[test]
+    fn test_malformed_tlvs() {
+        // value is out of bounds
+        assert_eq!(ProxyTlvs::parse(&[0x02, 0, 10, b'a']), ProxyTlvs::default());
+        // truncated tlv header after a valid entry
+        assert_eq!(
+            ProxyTlvs::parse(&[0xEE, 0, 5, 0x01, 7, 0, 0, 0, 0xEA]),
+            ProxyTlvs {
+                azure_link_id: Some(7),
+                ..Default::default()
+            }
+        );
+    }
 }
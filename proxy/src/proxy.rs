@@ -2,6 +2,7 @@
 mod tests;
 
 use crate::{
+    audit_log::{self, SessionBytes, SessionLog},
     auth,
     cancellation::{self, CancelMap},
     compute::{self, PostgresConnection},
@@ -9,7 +10,7 @@ use crate::{
     console::{self, errors::WakeComputeError, messages::MetricsAuxInfo, Api},
     http::StatusCode,
     protocol2::WithClientIp,
-    rate_limiter::EndpointRateLimiter,
+    rate_limiter::{ConnectionLimitError, EndpointConnectionLimiter, EndpointRateLimiter},
     stream::{PqStream, Stream},
     usage_metrics::{Ids, USAGE_METRICS},
 };
@@ -28,6 +29,7 @@ use prometheus::{
     IntGaugeVec,
 };
 use regex::Regex;
+use smol_str::SmolStr;
 use std::{error::Error, io, net::IpAddr, ops::ControlFlow, sync::Arc, time::Instant};
 use tokio::{
     io::{AsyncRead, AsyncWrite, AsyncWriteExt},
@@ -134,6 +136,16 @@ pub static RATE_LIMITER_LIMIT: Lazy<IntGaugeVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static CONNECTION_LIMIT_REJECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_connection_limit_rejections_total",
+        "Number of connections rejected by the per-endpoint concurrency limits.",
+        // endpoint/role
+        &["kind"],
+    )
+    .unwrap()
+});
+
 pub static NUM_CONNECTION_ACCEPTED_BY_SNI: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "proxy_accepted_connections_by_sni",
@@ -280,6 +292,7 @@ pub async fn task_main(
     listener: tokio::net::TcpListener,
     cancellation_token: CancellationToken,
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    endpoint_connection_limiter: Arc<EndpointConnectionLimiter>,
 ) -> anyhow::Result<()> {
     scopeguard::defer! {
         info!("proxy has shut down");
@@ -300,6 +313,7 @@ pub async fn task_main(
         let session_id = uuid::Uuid::new_v4();
         let cancel_map = Arc::clone(&cancel_map);
         let endpoint_rate_limiter = endpoint_rate_limiter.clone();
+        let endpoint_connection_limiter = endpoint_connection_limiter.clone();
 
         connections.spawn(
             async move {
@@ -313,6 +327,11 @@ pub async fn task_main(
                 } else if config.require_client_ip {
                     bail!("missing required client IP");
                 }
+                let private_link_id = socket.tlvs().private_link_id();
+                if let Some(id) = &private_link_id {
+                    tracing::Span::current()
+                        .record("private_link_id", &tracing::field::display(id));
+                }
 
                 socket
                     .inner
@@ -326,14 +345,17 @@ pub async fn task_main(
                     socket,
                     ClientMode::Tcp,
                     peer_addr.ip(),
+                    private_link_id,
                     endpoint_rate_limiter,
+                    endpoint_connection_limiter,
                 )
                 .await
             }
             .instrument(info_span!(
                 "handle_client",
                 ?session_id,
-                peer_addr = tracing::field::Empty
+                peer_addr = tracing::field::Empty,
+                private_link_id = tracing::field::Empty
             ))
             .unwrap_or_else(move |e| {
                 // Acknowledge that the task has finished with an error.
@@ -395,6 +417,7 @@ impl ClientMode {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
     config: &'static ProxyConfig,
     cancel_map: &CancelMap,
@@ -402,7 +425,9 @@ pub async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
     stream: S,
     mode: ClientMode,
     peer_addr: IpAddr,
+    private_link_id: Option<SmolStr>,
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    endpoint_connection_limiter: Arc<EndpointConnectionLimiter>,
 ) -> anyhow::Result<()> {
     info!(
         protocol = mode.protocol_label(),
@@ -425,33 +450,54 @@ pub async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
         None => return Ok(()), // it's a cancellation request
     };
 
-    // Extract credentials which we're going to use for auth.
-    let creds = {
-        let hostname = mode.hostname(stream.get_ref());
-        let common_names = tls.and_then(|tls| tls.common_names.clone());
-        let result = config
-            .auth_backend
-            .as_ref()
-            .map(|_| auth::ClientCredentials::parse(&params, hostname, common_names, peer_addr))
-            .transpose();
-
-        match result {
-            Ok(creds) => creds,
-            Err(e) => stream.throw_error(e).await?,
-        }
-    };
+    let session_log = SessionLog::new(session_id, proto, peer_addr, private_link_id.clone());
+    session_log.set_startup_params(&params);
+
+    let result = async {
+        // Extract credentials which we're going to use for auth.
+        let creds = {
+            let hostname = mode.hostname(stream.get_ref());
+            let common_names = tls.and_then(|tls| tls.common_names.clone());
+            let result = config
+                .auth_backend
+                .as_ref()
+                .map(|_| {
+                    auth::ClientCredentials::parse(
+                        &params,
+                        hostname,
+                        common_names,
+                        peer_addr,
+                        private_link_id,
+                    )
+                })
+                .transpose();
+
+            match result {
+                Ok(creds) => creds,
+                Err(e) => stream.throw_error(e).await?,
+            }
+        };
 
-    let client = Client::new(
-        stream,
-        creds,
-        &params,
-        session_id,
-        mode.allow_self_signed_compute(config),
-        endpoint_rate_limiter,
-    );
-    cancel_map
-        .with_session(|session| client.connect_to_db(session, mode, &config.authentication_config))
-        .await
+        let client = Client::new(
+            stream,
+            creds,
+            &params,
+            session_id,
+            mode.allow_self_signed_compute(config),
+            endpoint_rate_limiter,
+            endpoint_connection_limiter,
+            &session_log,
+        );
+        cancel_map
+            .with_session(|session| {
+                client.connect_to_db(session, mode, &config.authentication_config)
+            })
+            .await
+    }
+    .await;
+
+    session_log.finish(result.as_ref().err().map(audit_log::classify_error));
+    result
 }
 
 /// Establish a (most probably, secure) connection with the client.
@@ -628,6 +674,7 @@ fn report_error(e: &WakeComputeError, retry: bool) {
         WakeComputeError::ApiError(ApiError::Console {
             status: StatusCode::LOCKED,
             ref text,
+            ..
         }) if text.contains("written data quota exceeded")
             || text.contains("the limit for current plan reached") =>
         {
@@ -689,6 +736,7 @@ where
     let node_info = loop {
         let wake_res = match creds {
             auth::BackendType::Console(api, creds) => api.wake_compute(extra, creds).await,
+            auth::BackendType::File(api, creds) => api.wake_compute(extra, creds).await,
             #[cfg(feature = "testing")]
             auth::BackendType::Postgres(api, creds) => api.wake_compute(extra, creds).await,
             // nothing to do?
@@ -865,6 +913,7 @@ pub async fn proxy_pass(
     client: impl AsyncRead + AsyncWrite + Unpin,
     compute: impl AsyncRead + AsyncWrite + Unpin,
     aux: MetricsAuxInfo,
+    session_bytes: &SessionBytes,
 ) -> anyhow::Result<()> {
     let usage = USAGE_METRICS.register(Ids {
         endpoint_id: aux.endpoint_id.clone(),
@@ -881,6 +930,7 @@ pub async fn proxy_pass(
             m_sent.inc_by(cnt as u64);
             m_sent2.inc_by(cnt as u64);
             usage.record_egress(cnt as u64);
+            session_bytes.record_tx(cnt as u64);
         },
     );
 
@@ -893,6 +943,7 @@ pub async fn proxy_pass(
             // Number of bytes the client sent to the compute node (inbound).
             m_recv.inc_by(cnt as u64);
             m_recv2.inc_by(cnt as u64);
+            session_bytes.record_rx(cnt as u64);
         },
     );
 
@@ -917,10 +968,15 @@ struct Client<'a, S> {
     allow_self_signed_compute: bool,
     /// Rate limiter for endpoints
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    /// Limiter of concurrent connections for endpoints
+    endpoint_connection_limiter: Arc<EndpointConnectionLimiter>,
+    /// Audit log record of this session.
+    session_log: &'a SessionLog,
 }
 
 impl<'a, S> Client<'a, S> {
     /// Construct a new connection context.
+    #[allow(clippy::too_many_arguments)]
     fn new(
         stream: PqStream<Stream<S>>,
         creds: auth::BackendType<'a, auth::ClientCredentials>,
@@ -928,6 +984,8 @@ impl<'a, S> Client<'a, S> {
         session_id: uuid::Uuid,
         allow_self_signed_compute: bool,
         endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+        endpoint_connection_limiter: Arc<EndpointConnectionLimiter>,
+        session_log: &'a SessionLog,
     ) -> Self {
         Self {
             stream,
@@ -936,6 +994,8 @@ impl<'a, S> Client<'a, S> {
             session_id,
             allow_self_signed_compute,
             endpoint_rate_limiter,
+            endpoint_connection_limiter,
+            session_log,
         }
     }
 }
@@ -958,10 +1018,13 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<'_, S> {
             session_id,
             allow_self_signed_compute,
             endpoint_rate_limiter,
+            endpoint_connection_limiter,
+            session_log,
         } = self;
 
         // check rate limit
         if let Some(ep) = creds.get_endpoint() {
+            session_log.set_endpoint_id(ep.clone());
             if !endpoint_rate_limiter.check(ep) {
                 return stream
                     .throw_error(auth::AuthError::too_many_connections())
@@ -982,6 +1045,8 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<'_, S> {
         let mut latency_timer = LatencyTimer::new(proto);
 
         let user = creds.get_user().to_owned();
+        let requested_target = creds.get_target();
+        session_log.set_auth_method(creds.auth_method(mode.allow_cleartext()));
         let auth_result = match creds
             .authenticate(
                 &extra,
@@ -1005,6 +1070,20 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<'_, S> {
         let (mut node_info, creds) = auth_result;
 
         node_info.allow_self_signed_compute = allow_self_signed_compute;
+        session_log.set_metrics_aux(&node_info.aux);
+
+        // check the limit of concurrent connections, the permit is held until the session ends
+        let _connection_permit = match endpoint_connection_limiter
+            .acquire(&node_info.aux.endpoint_id, &user, node_info.limits.as_ref())
+            .await
+        {
+            Ok(permit) => permit,
+            Err(e) => {
+                return stream
+                    .throw_error_with_code(e, ConnectionLimitError::SQLSTATE)
+                    .await
+            }
+        };
 
         let aux = node_info.aux.clone();
         let mut node = connect_to_compute(
@@ -1016,6 +1095,16 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<'_, S> {
         )
         .or_else(|e| stream.throw_error(e))
         .await?;
+        session_log.set_compute_addr(node.socket_addr);
+
+        if requested_target.is_read_replica() {
+            // Let the client know whether it got a replica or we had to fall back to the primary.
+            let status = format!("target={}", creds.get_target().as_str());
+            stream.write_message_noflush(&Be::ParameterStatus {
+                name: b"NEON_PROXY",
+                value: status.as_bytes(),
+            })?;
+        }
 
         prepare_client_connection(&node, session, &mut stream).await?;
         // Before proxy passing, forward to compute whatever data is left in the
@@ -1024,7 +1113,7 @@ impl<S: AsyncRead + AsyncWrite + Unpin> Client<'_, S> {
         // immediately after opening the connection.
         let (stream, read_buf) = stream.into_inner();
         node.stream.write_all(&read_buf).await?;
-        proxy_pass(stream, node.stream, aux).await
+        proxy_pass(stream, node.stream, aux, session_log.bytes()).await
     }
 }
 
@@ -450,6 +450,7 @@ impl TestBackend for TestConnectMechanism {
                 let err = console::errors::ApiError::Console {
                     status: http::StatusCode::FORBIDDEN,
                     text: "TEST".into(),
+                    reason: None,
                 };
                 assert!(!err.could_retry());
                 Err(console::errors::WakeComputeError::ApiError(err))
@@ -458,6 +459,7 @@ impl TestBackend for TestConnectMechanism {
                 let err = console::errors::ApiError::Console {
                     status: http::StatusCode::BAD_REQUEST,
                     text: "TEST".into(),
+                    reason: None,
                 };
                 assert!(err.could_retry());
                 Err(console::errors::WakeComputeError::ApiError(err))
@@ -469,6 +471,12 @@ impl TestBackend for TestConnectMechanism {
     fn get_allowed_ips(&self) -> Result<Arc<Vec<String>>, console::errors::GetAuthInfoError> {
         unimplemented!("not used in tests")
     }
+
+    fn get_allowed_vpc_endpoint_ids(
+        &self,
+    ) -> Result<Arc<Vec<String>>, console::errors::GetAuthInfoError> {
+        unimplemented!("not used in tests")
+    }
 }
 
 fn helper_create_cached_node_info() -> CachedNodeInfo {
@@ -476,6 +484,7 @@ fn helper_create_cached_node_info() -> CachedNodeInfo {
         config: compute::ConnCfg::new(),
         aux: Default::default(),
         allow_self_signed_compute: false,
+        limits: None,
     };
     CachedNodeInfo::new_uncached(node)
 }
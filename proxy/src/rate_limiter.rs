@@ -4,4 +4,7 @@ mod limiter;
 pub use aimd::Aimd;
 pub use limit_algorithm::{AimdConfig, Fixed, RateLimitAlgorithm, RateLimiterConfig};
 pub use limiter::Limiter;
+pub use limiter::{
+    ConnectionLimitError, ConnectionLimiterConfig, ConnectionPermit, EndpointConnectionLimiter,
+};
 pub use limiter::{EndpointRateLimiter, RateBucketInfo};
@@ -3,12 +3,13 @@ use std::sync::{
     Arc,
 };
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use dashmap::DashMap;
 use itertools::Itertools;
 use rand::{thread_rng, Rng};
 use smol_str::SmolStr;
-use tokio::sync::{Mutex as AsyncMutex, Semaphore, SemaphorePermit};
+use thiserror::Error;
+use tokio::sync::{Mutex as AsyncMutex, OwnedSemaphorePermit, Semaphore, SemaphorePermit};
 use tokio::time::{timeout, Duration, Instant};
 use tracing::info;
 
@@ -16,6 +17,7 @@ use super::{
     limit_algorithm::{LimitAlgorithm, Sample},
     RateLimiterConfig,
 };
+use crate::{console::messages::ConnectionLimits, error::UserFacingError};
 
 // Simple per-endpoint rate limiter.
 //
@@ -181,6 +183,313 @@ impl EndpointRateLimiter {
     }
 }
 
+/// Limits the number of concurrently proxied connections per endpoint and per role.
+///
+/// Unlike [`EndpointRateLimiter`], which bounds the rate of *new* connections,
+/// this bounds the number of sessions which are active at the same time.
+/// When the limit is reached, a connection may wait for a short while in a
+/// bounded queue before it is rejected with [`ConnectionLimitError`].
+///
+/// Limits reported by the console (see [`ConnectionLimits`]) take precedence
+/// over the proxy-wide defaults from [`ConnectionLimiterConfig`].
+pub struct EndpointConnectionLimiter {
+    endpoints: DashMap<SmolStr, Arc<ConnectionSlots>>,
+    roles: DashMap<(SmolStr, SmolStr), Arc<ConnectionSlots>>,
+    config: ConnectionLimiterConfig,
+    access_count: AtomicUsize,
+}
+
+/// Proxy-wide defaults for [`EndpointConnectionLimiter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLimiterConfig {
+    /// Max number of concurrent connections per endpoint (0 means unlimited).
+    pub max_per_endpoint: usize,
+    /// Max number of concurrent connections per (endpoint, role) pair (0 means unlimited).
+    pub max_per_role: usize,
+    /// Max number of connections waiting for a free slot of a single endpoint or role.
+    pub queue_size: usize,
+    /// How long a queued connection waits for a free slot before giving up.
+    pub queue_timeout: Duration,
+}
+
+impl ConnectionLimiterConfig {
+    /// Default options: don't limit anything.
+    pub const DEFAULT_OPTIONS: &'static str = "endpoint=0,role=0";
+
+    /// Parse limiter options passed via cmdline.
+    /// Example: "endpoint=100,role=50,queue=16,timeout=1s".
+    fn parse(options: &str) -> anyhow::Result<Self> {
+        let mut max_per_endpoint = None;
+        let mut max_per_role = None;
+        let mut queue_size = None;
+        let mut queue_timeout = None;
+
+        for option in options.split(',') {
+            let (key, value) = option
+                .split_once('=')
+                .with_context(|| format!("bad key-value pair: {option}"))?;
+
+            match key {
+                "endpoint" => max_per_endpoint = Some(value.parse()?),
+                "role" => max_per_role = Some(value.parse()?),
+                "queue" => queue_size = Some(value.parse()?),
+                "timeout" => queue_timeout = Some(humantime::parse_duration(value)?),
+                unknown => bail!("unknown key: {unknown}"),
+            }
+        }
+
+        let max_per_endpoint = max_per_endpoint.unwrap_or(0);
+        let max_per_role = max_per_role.unwrap_or(0);
+
+        // queueing doesn't matter if nothing is limited
+        if max_per_endpoint == 0 && max_per_role == 0 {
+            queue_size.get_or_insert(0);
+            queue_timeout.get_or_insert(Duration::ZERO);
+        }
+
+        Ok(Self {
+            max_per_endpoint,
+            max_per_role,
+            queue_size: queue_size.context("missing `queue`")?,
+            queue_timeout: queue_timeout.context("missing `timeout`")?,
+        })
+    }
+}
+
+impl std::str::FromStr for ConnectionLimiterConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(options: &str) -> Result<Self, Self::Err> {
+        let error = || format!("failed to parse connection limit options '{options}'");
+        Self::parse(options).with_context(error)
+    }
+}
+
+/// Why a connection was not allowed by [`EndpointConnectionLimiter`].
+#[derive(Debug, Error)]
+pub enum ConnectionLimitError {
+    #[error("Too many active connections to this endpoint")]
+    Endpoint,
+    #[error("Too many active connections for this role")]
+    Role,
+}
+
+impl ConnectionLimitError {
+    /// SQLSTATE `too_many_connections`, same as postgres reports on `max_connections`.
+    pub const SQLSTATE: &'static [u8; 5] = b"53300";
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ConnectionLimitError::Endpoint => "endpoint",
+            ConnectionLimitError::Role => "role",
+        }
+    }
+}
+
+impl UserFacingError for ConnectionLimitError {
+    fn to_string_client(&self) -> String {
+        format!("{self}. Please try again later.")
+    }
+}
+
+/// Keeps the connection slots occupied until dropped.
+#[derive(Debug, Default)]
+pub struct ConnectionPermit {
+    endpoint: Option<SlotPermit>,
+    role: Option<SlotPermit>,
+}
+
+#[derive(Debug)]
+struct SlotPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    slots: Arc<ConnectionSlots>,
+}
+
+impl Drop for SlotPermit {
+    fn drop(&mut self) {
+        // The limit was lowered while we were holding this permit:
+        // don't give the permit back to the semaphore.
+        let mut state = self.slots.state.lock();
+        if state.debt > 0 {
+            state.debt -= 1;
+            if let Some(permit) = self.permit.take() {
+                permit.forget();
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ConnectionSlots {
+    semaphore: Arc<Semaphore>,
+    state: parking_lot::Mutex<SlotsState>,
+    waiting: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct SlotsState {
+    /// The limit the semaphore was sized for.
+    limit: usize,
+    /// Permits which must be forgotten once released to reach `limit`.
+    debt: usize,
+}
+
+impl ConnectionSlots {
+    fn new(limit: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            state: parking_lot::Mutex::new(SlotsState { limit, debt: 0 }),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Adjust the number of slots, e.g. if the console reported a new limit.
+    fn resize(&self, new_limit: usize) {
+        let mut state = self.state.lock();
+        if new_limit > state.limit {
+            let mut extra = new_limit - state.limit;
+            let repaid = extra.min(state.debt);
+            state.debt -= repaid;
+            extra -= repaid;
+            self.semaphore.add_permits(extra);
+        } else {
+            state.debt += state.limit - new_limit;
+            // take away the permits which are not in use right now
+            while state.debt > 0 {
+                match self.semaphore.try_acquire() {
+                    Ok(permit) => {
+                        permit.forget();
+                        state.debt -= 1;
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+        state.limit = new_limit;
+    }
+
+    async fn acquire(
+        self: &Arc<Self>,
+        limit: usize,
+        config: &ConnectionLimiterConfig,
+    ) -> Option<SlotPermit> {
+        if self.state.lock().limit != limit {
+            self.resize(limit);
+        }
+
+        let permit = match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                // The queue is full, don't even try to wait.
+                if self.waiting.fetch_add(1, Ordering::AcqRel) >= config.queue_size {
+                    self.waiting.fetch_sub(1, Ordering::AcqRel);
+                    return None;
+                }
+                let res =
+                    timeout(config.queue_timeout, self.semaphore.clone().acquire_owned()).await;
+                self.waiting.fetch_sub(1, Ordering::AcqRel);
+                res.ok()?.ok()?
+            }
+        };
+
+        Some(SlotPermit {
+            permit: Some(permit),
+            slots: self.clone(),
+        })
+    }
+}
+
+impl EndpointConnectionLimiter {
+    pub fn new(config: ConnectionLimiterConfig) -> Self {
+        info!(?config, "endpoint connection limiter");
+        Self {
+            endpoints: DashMap::with_shard_amount(64),
+            roles: DashMap::with_shard_amount(64),
+            config,
+            access_count: AtomicUsize::new(1), // start from 1 to avoid GC on the first request
+        }
+    }
+
+    /// Occupy a connection slot of the endpoint and of the role, waiting in the queue if needed.
+    ///
+    /// `limits` are the limits reported by the console for this endpoint, if any.
+    pub async fn acquire(
+        &self,
+        endpoint: &SmolStr,
+        role: &str,
+        limits: Option<&ConnectionLimits>,
+    ) -> Result<ConnectionPermit, ConnectionLimitError> {
+        // do a partial GC every 2k requests. This cleans up ~ 1/64th of the maps.
+        if self.access_count.fetch_add(1, Ordering::AcqRel) % 2048 == 0 {
+            self.do_gc();
+        }
+
+        let max_per_endpoint = limits
+            .and_then(|l| l.max_connections)
+            .map_or(self.config.max_per_endpoint, |n| n as usize);
+        let max_per_role = limits
+            .and_then(|l| l.max_connections_per_role)
+            .map_or(self.config.max_per_role, |n| n as usize);
+
+        let mut permit = ConnectionPermit::default();
+
+        if max_per_endpoint > 0 {
+            let slots = self
+                .endpoints
+                .entry(endpoint.clone())
+                .or_insert_with(|| Arc::new(ConnectionSlots::new(max_per_endpoint)))
+                .clone();
+            permit.endpoint = Some(
+                slots
+                    .acquire(max_per_endpoint, &self.config)
+                    .await
+                    .ok_or_else(|| Self::rejected(ConnectionLimitError::Endpoint))?,
+            );
+        }
+
+        if max_per_role > 0 {
+            let slots = self
+                .roles
+                .entry((endpoint.clone(), role.into()))
+                .or_insert_with(|| Arc::new(ConnectionSlots::new(max_per_role)))
+                .clone();
+            permit.role = Some(
+                slots
+                    .acquire(max_per_role, &self.config)
+                    .await
+                    .ok_or_else(|| Self::rejected(ConnectionLimitError::Role))?,
+            );
+        }
+
+        Ok(permit)
+    }
+
+    fn rejected(e: ConnectionLimitError) -> ConnectionLimitError {
+        info!("rejecting connection: {e}");
+        crate::proxy::CONNECTION_LIMIT_REJECTIONS
+            .with_label_values(&[e.kind()])
+            .inc();
+        e
+    }
+
+    /// Clean the maps. Simple strategy: remove unused entries in a random shard.
+    pub fn do_gc(&self) {
+        info!(
+            "cleaning up endpoint connection limiter, current size = {}",
+            self.endpoints.len()
+        );
+        let n = self.endpoints.shards().len();
+        let shard = thread_rng().gen_range(0..n);
+        // if strong_count == 1, there's no permit which could increase it while the shard is locked
+        self.endpoints.shards()[shard]
+            .write()
+            .retain(|_, slots| Arc::strong_count(slots.get()) > 1);
+        self.roles.shards()[shard]
+            .write()
+            .retain(|_, slots| Arc::strong_count(slots.get()) > 1);
+    }
+}
+
 /// Limits the number of concurrent jobs.
 ///
 /// Concurrency is limited through the use of [Token]s. Acquire a token to run a job, and release the
@@ -467,6 +776,7 @@ impl reqwest_middleware::Middleware for Limiter {
                     crate::console::errors::ApiError::Console {
                         status: crate::http::StatusCode::TOO_MANY_REQUESTS,
                         text: "Too many requests".into(),
+                        reason: None,
                     }
                     .into(),
                 )
@@ -496,7 +806,11 @@ mod tests {
     use smol_str::SmolStr;
     use tokio::time;
 
-    use super::{EndpointRateLimiter, Limiter, Outcome};
+    use super::{
+        ConnectionLimitError, ConnectionLimiterConfig, EndpointConnectionLimiter,
+        EndpointRateLimiter, Limiter, Outcome,
+    };
+    use crate::console::messages::ConnectionLimits;
     use crate::rate_limiter::{RateBucketInfo, RateLimitAlgorithm};
 
     #[tokio::test]
@@ -690,4 +1004,143 @@ mod tests {
             assert!(limiter.check(endpoint.clone()));
         }
     }
+
+    #[test]
+    fn parse_connection_limits() -> anyhow::Result<()> {
+        let config: ConnectionLimiterConfig = "endpoint=100,role=20,queue=16,timeout=1s".parse()?;
+        assert_eq!(
+            config,
+            ConnectionLimiterConfig {
+                max_per_endpoint: 100,
+                max_per_role: 20,
+                queue_size: 16,
+                queue_timeout: Duration::from_secs(1),
+            }
+        );
+
+        let config: ConnectionLimiterConfig = ConnectionLimiterConfig::DEFAULT_OPTIONS.parse()?;
+        assert_eq!(config.max_per_endpoint, 0);
+        assert_eq!(config.max_per_role, 0);
+
+        assert!("endpoint=100".parse::<ConnectionLimiterConfig>().is_err());
+        assert!("endpoint=1,role=1,queue=1,timeout=1s,foo=1"
+            .parse::<ConnectionLimiterConfig>()
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connection_limits() {
+        let limiter = EndpointConnectionLimiter::new(ConnectionLimiterConfig {
+            max_per_endpoint: 2,
+            max_per_role: 0,
+            queue_size: 1,
+            queue_timeout: Duration::from_secs(1),
+        });
+        let endpoint = SmolStr::from("ep-my-endpoint-1234");
+
+        time::pause();
+
+        let permit1 = limiter.acquire(&endpoint, "alice", None).await.unwrap();
+        let _permit2 = limiter.acquire(&endpoint, "bob", None).await.unwrap();
+
+        // other endpoints are not affected
+        let other = SmolStr::from("ep-other-endpoint-1234");
+        let _permit3 = limiter.acquire(&other, "alice", None).await.unwrap();
+
+        // the third connection waits in the queue...
+        let mut queued = pin!(limiter.acquire(&endpoint, "alice", None));
+        assert!(queued
+            .as_mut()
+            .poll(&mut Context::from_waker(noop_waker_ref()))
+            .is_pending());
+
+        // ...while the fourth one is rejected right away because the queue is full
+        assert!(matches!(
+            limiter.acquire(&endpoint, "alice", None).await,
+            Err(ConnectionLimitError::Endpoint)
+        ));
+
+        // closing a connection lets the queued one in
+        drop(permit1);
+        let permit4 = queued.await.unwrap();
+
+        // a queued connection gives up after the timeout
+        assert!(matches!(
+            limiter.acquire(&endpoint, "alice", None).await,
+            Err(ConnectionLimitError::Endpoint)
+        ));
+
+        drop(permit4);
+        let _permit5 = limiter.acquire(&endpoint, "alice", None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_role_connection_limits() {
+        let limiter = EndpointConnectionLimiter::new(ConnectionLimiterConfig {
+            max_per_endpoint: 10,
+            max_per_role: 1,
+            queue_size: 0,
+            queue_timeout: Duration::ZERO,
+        });
+        let endpoint = SmolStr::from("ep-my-endpoint-1234");
+
+        let permit = limiter.acquire(&endpoint, "alice", None).await.unwrap();
+        assert!(matches!(
+            limiter.acquire(&endpoint, "alice", None).await,
+            Err(ConnectionLimitError::Role)
+        ));
+        let _other_role = limiter.acquire(&endpoint, "bob", None).await.unwrap();
+
+        drop(permit);
+        let _permit = limiter.acquire(&endpoint, "alice", None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_console_connection_limits() {
+        let limiter = EndpointConnectionLimiter::new(ConnectionLimiterConfig {
+            max_per_endpoint: 1,
+            max_per_role: 0,
+            queue_size: 0,
+            queue_timeout: Duration::ZERO,
+        });
+        let endpoint = SmolStr::from("ep-my-endpoint-1234");
+        let limits = |max_connections| ConnectionLimits {
+            max_connections: Some(max_connections),
+            max_connections_per_role: None,
+        };
+
+        // the console allows more connections than the default
+        let mut permits = vec![];
+        for _ in 0..3 {
+            let permit = limiter.acquire(&endpoint, "alice", Some(&limits(3))).await;
+            permits.push(permit.unwrap());
+        }
+        assert!(limiter
+            .acquire(&endpoint, "alice", Some(&limits(3)))
+            .await
+            .is_err());
+
+        // the limit was lowered: released slots are not reused until we're below the new limit
+        let lowered = limits(2);
+        assert!(limiter
+            .acquire(&endpoint, "alice", Some(&lowered))
+            .await
+            .is_err());
+        permits.pop();
+        assert!(limiter
+            .acquire(&endpoint, "alice", Some(&lowered))
+            .await
+            .is_err());
+        permits.pop();
+        let _permit = limiter
+            .acquire(&endpoint, "alice", Some(&lowered))
+            .await
+            .unwrap();
+        assert!(limiter
+            .acquire(&endpoint, "alice", Some(&lowered))
+            .await
+            .is_err());
+    }
 }
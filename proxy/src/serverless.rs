@@ -11,11 +11,12 @@ use hyper::StatusCode;
 use metrics::IntCounterPairGuard;
 pub use reqwest_middleware::{ClientWithMiddleware, Error};
 pub use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use smol_str::SmolStr;
 use tokio_util::task::TaskTracker;
 
 use crate::protocol2::{ProxyProtocolAccept, WithClientIp};
 use crate::proxy::NUM_CLIENT_CONNECTION_GAUGE;
-use crate::rate_limiter::EndpointRateLimiter;
+use crate::rate_limiter::{EndpointConnectionLimiter, EndpointRateLimiter};
 use crate::{cancellation::CancelMap, config::ProxyConfig};
 use futures::StreamExt;
 use hyper::{
@@ -40,6 +41,7 @@ pub async fn task_main(
     ws_listener: TcpListener,
     cancellation_token: CancellationToken,
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    endpoint_connection_limiter: Arc<EndpointConnectionLimiter>,
 ) -> anyhow::Result<()> {
     scopeguard::defer! {
         info!("websocket server has shut down");
@@ -90,11 +92,13 @@ pub async fn task_main(
         |stream: &tokio_rustls::server::TlsStream<WithClientIp<AddrStream>>| {
             let (io, tls) = stream.get_ref();
             let client_addr = io.client_addr();
+            let private_link_id = io.tlvs().private_link_id();
             let remote_addr = io.inner.remote_addr();
             let sni_name = tls.server_name().map(|s| s.to_string());
             let conn_pool = conn_pool.clone();
             let ws_connections = ws_connections.clone();
             let endpoint_rate_limiter = endpoint_rate_limiter.clone();
+            let endpoint_connection_limiter = endpoint_connection_limiter.clone();
 
             async move {
                 let peer_addr = match client_addr {
@@ -105,9 +109,11 @@ pub async fn task_main(
                 Ok(MetricService::new(hyper::service::service_fn(
                     move |req: Request<Body>| {
                         let sni_name = sni_name.clone();
+                        let private_link_id = private_link_id.clone();
                         let conn_pool = conn_pool.clone();
                         let ws_connections = ws_connections.clone();
                         let endpoint_rate_limiter = endpoint_rate_limiter.clone();
+                        let endpoint_connection_limiter = endpoint_connection_limiter.clone();
 
                         async move {
                             let cancel_map = Arc::new(CancelMap::default());
@@ -122,7 +128,9 @@ pub async fn task_main(
                                 session_id,
                                 sni_name,
                                 peer_addr.ip(),
+                                private_link_id,
                                 endpoint_rate_limiter,
+                                endpoint_connection_limiter,
                             )
                             .instrument(info_span!(
                                 "serverless",
@@ -191,7 +199,9 @@ async fn request_handler(
     session_id: uuid::Uuid,
     sni_hostname: Option<String>,
     peer_addr: IpAddr,
+    private_link_id: Option<SmolStr>,
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    endpoint_connection_limiter: Arc<EndpointConnectionLimiter>,
 ) -> Result<Response<Body>, ApiError> {
     let host = request
         .headers()
@@ -216,7 +226,9 @@ async fn request_handler(
                     session_id,
                     host,
                     peer_addr,
+                    private_link_id,
                     endpoint_rate_limiter,
+                    endpoint_connection_limiter,
                 )
                 .await
                 {
@@ -235,7 +247,9 @@ async fn request_handler(
             conn_pool,
             session_id,
             peer_addr,
+            private_link_id,
             &config.http_config,
+            &endpoint_connection_limiter,
         )
         .await
     } else if request.uri().path() == "/sql" && request.method() == Method::OPTIONS {
@@ -19,11 +19,17 @@ use std::{
     sync::atomic::{self, AtomicUsize},
 };
 use tokio::time;
-use tokio_postgres::{AsyncMessage, ReadyForQueryStatus};
+use tokio_postgres::{config::Host, AsyncMessage, ReadyForQueryStatus};
 
 use crate::{
-    auth::{self, backend::ComputeUserInfo, check_peer_addr_is_in_list},
-    console,
+    auth::{
+        self, backend::ComputeUserInfo, check_peer_addr_is_in_list,
+        check_private_link_id_is_in_list, parse_target_session_attrs_param, ComputeTarget,
+    },
+    console::{
+        self,
+        messages::{ConnectionLimits, MetricsAuxInfo},
+    },
     proxy::{neon_options, LatencyTimer, NUM_DB_CONNECTIONS_GAUGE},
     usage_metrics::{Ids, MetricCounter, USAGE_METRICS},
 };
@@ -51,6 +57,20 @@ impl ConnInfo {
     pub fn db_and_user(&self) -> (SmolStr, SmolStr) {
         (self.dbname.clone(), self.username.clone())
     }
+
+    /// The pool doesn't tell replicas and primaries apart, so such connections are never pooled.
+    pub fn targets_read_replica(&self) -> bool {
+        self.options.as_deref().map_or(false, |options| {
+            options
+                .split_whitespace()
+                .filter_map(parse_target_session_attrs_param)
+                .any(|target| {
+                    target
+                        .parse::<ComputeTarget>()
+                        .map_or(false, |target| target.is_read_replica())
+                })
+        })
+    }
 }
 
 impl fmt::Display for ConnInfo {
@@ -138,13 +158,56 @@ impl GlobalConnPool {
         });
     }
 
+    /// Checks that the client may access the endpoint of `conn_info`, and wakes its compute
+    /// up, once per request: [`Self::get`] connects to the woken compute if the pool has no
+    /// connection for the client.
+    pub async fn wake_compute(
+        &self,
+        conn_info: &ConnInfo,
+        peer_addr: IpAddr,
+        private_link_id: Option<SmolStr>,
+    ) -> anyhow::Result<WokenCompute> {
+        let config = self.proxy_config;
+        let (mut backend, extra) =
+            console_backend(config, conn_info, peer_addr, private_link_id.clone())?;
+
+        // TODO(anna): this is a bit hacky way, consider using console notification listener.
+        if !config.disable_ip_check_for_http {
+            let allowed_ips = backend.get_allowed_ips(&extra).await?;
+            if !check_peer_addr_is_in_list(&peer_addr, &allowed_ips) {
+                return Err(auth::AuthError::ip_address_not_allowed().into());
+            }
+        }
+        check_private_link(&backend, &extra, private_link_id.as_deref()).await?;
+        let fallback = backend.get_target().fallback();
+        let node_info = match (backend.wake_compute(&extra).await, fallback) {
+            (Err(e), Some(fallback)) if e.is_replica_unavailable() => {
+                warn!(error = ?e, "no read replica is available, falling back to the primary");
+                backend = backend.map(|mut creds| {
+                    creds.inner.target = fallback;
+                    creds
+                });
+                backend.wake_compute(&extra).await?
+            }
+            (res, _) => res?,
+        }
+        .context("missing cache entry from wake_compute")?;
+
+        Ok(WokenCompute {
+            backend,
+            extra,
+            node_info,
+        })
+    }
+
     pub async fn get(
         self: &Arc<Self>,
         conn_info: &ConnInfo,
         force_new: bool,
         session_id: uuid::Uuid,
-        peer_addr: IpAddr,
+        compute: WokenCompute,
     ) -> anyhow::Result<Client> {
+        let force_new = force_new || conn_info.targets_read_replica();
         let mut client: Option<ClientInner> = None;
         let mut latency_timer = LatencyTimer::new("http");
 
@@ -156,6 +219,8 @@ impl GlobalConnPool {
 
         let mut hash_valid = false;
         if !force_new {
+            // The pooled connections were opened for other clients, which may have come through
+            // a different private link: `compute` was woken up after checking this client's one.
             let pool = self.get_or_create_endpoint_pool(&conn_info.hostname);
             let mut hash = None;
 
@@ -197,15 +262,7 @@ impl GlobalConnPool {
             if client.inner.is_closed() {
                 let conn_id = uuid::Uuid::new_v4();
                 info!(%conn_id, "pool: cached connection '{conn_info}' is closed, opening a new one");
-                connect_to_compute(
-                    self.proxy_config,
-                    conn_info,
-                    conn_id,
-                    session_id,
-                    latency_timer,
-                    peer_addr,
-                )
-                .await
+                connect_to_compute(conn_info, conn_id, session_id, latency_timer, compute).await
             } else {
                 info!("pool: reusing connection '{conn_info}'");
                 client.session.send(session_id)?;
@@ -220,15 +277,7 @@ impl GlobalConnPool {
         } else {
             let conn_id = uuid::Uuid::new_v4();
             info!(%conn_id, "pool: opening a new connection '{conn_info}'");
-            connect_to_compute(
-                self.proxy_config,
-                conn_info,
-                conn_id,
-                session_id,
-                latency_timer,
-                peer_addr,
-            )
-            .await
+            connect_to_compute(conn_info, conn_id, session_id, latency_timer, compute).await
         };
         if let Ok(client) = &new_client {
             tracing::Span::current().record(
@@ -393,18 +442,83 @@ impl ConnectMechanism for TokioMechanism<'_> {
     fn update_connect_config(&self, _config: &mut compute::ConnCfg) {}
 }
 
-// Wake up the destination if needed. Code here is a bit involved because
-// we reuse the code from the usual proxy and we need to prepare few structures
-// that this code expects.
+/// A compute woken up for a client, see [`GlobalConnPool::wake_compute`].
+pub struct WokenCompute {
+    backend: auth::BackendType<'static, ComputeUserInfo>,
+    extra: console::ConsoleReqExtra,
+    node_info: console::CachedNodeInfo,
+}
+
+impl WokenCompute {
+    /// Endpoint ID and the console-provided limits for [`crate::rate_limiter::EndpointConnectionLimiter`].
+    pub fn connection_limits(&self) -> (SmolStr, Option<ConnectionLimits>) {
+        (
+            self.node_info.aux.endpoint_id.clone(),
+            self.node_info.limits,
+        )
+    }
+
+    /// The password comes along with every request, see [`auth::BackendType::auth_method`].
+    pub fn auth_method(&self) -> &'static str {
+        self.backend.auth_method(true)
+    }
+
+    pub fn aux(&self) -> &MetricsAuxInfo {
+        &self.node_info.aux
+    }
+
+    /// `host:port` of the compute: pooled connections might have resolved it to other addresses.
+    pub fn compute_addr(&self) -> Option<String> {
+        let config = &self.node_info.config;
+        let host = match config.get_hosts().first()? {
+            Host::Tcp(host) => host,
+            Host::Unix(_) => return None,
+        };
+        let port = config.get_ports().first().copied().unwrap_or(5432);
+        Some(format!("{host}:{port}"))
+    }
+}
+
+// Code here is a bit involved because we reuse the code from the usual proxy
+// and we need to prepare few structures that this code expects.
 #[tracing::instrument(fields(pid = tracing::field::Empty), skip_all)]
 async fn connect_to_compute(
-    config: &config::ProxyConfig,
     conn_info: &ConnInfo,
     conn_id: uuid::Uuid,
     session_id: uuid::Uuid,
     latency_timer: LatencyTimer,
-    peer_addr: IpAddr,
+    compute: WokenCompute,
 ) -> anyhow::Result<ClientInner> {
+    let WokenCompute {
+        backend,
+        extra,
+        node_info,
+    } = compute;
+
+    crate::proxy::connect_to_compute(
+        &TokioMechanism {
+            conn_id,
+            conn_info,
+            session_id,
+        },
+        node_info,
+        &extra,
+        &backend,
+        latency_timer,
+    )
+    .await
+}
+
+/// The console backend and request parameters for the endpoint of `conn_info`.
+fn console_backend<'a>(
+    config: &'a config::ProxyConfig,
+    conn_info: &ConnInfo,
+    peer_addr: IpAddr,
+    private_link_id: Option<SmolStr>,
+) -> anyhow::Result<(
+    auth::BackendType<'a, ComputeUserInfo>,
+    console::ConsoleReqExtra,
+)> {
     let tls = config.tls_config.as_ref();
     let common_names = tls.and_then(|tls| tls.common_names.clone());
 
@@ -419,6 +533,7 @@ async fn connect_to_compute(
         Some(&conn_info.hostname),
         common_names,
         peer_addr,
+        private_link_id,
     )?;
 
     let creds =
@@ -432,30 +547,20 @@ async fn connect_to_compute(
         application_name: APP_NAME.to_string(),
         options: console_options,
     };
-    // TODO(anna): this is a bit hacky way, consider using console notification listener.
-    if !config.disable_ip_check_for_http {
-        let allowed_ips = backend.get_allowed_ips(&extra).await?;
-        if !check_peer_addr_is_in_list(&peer_addr, &allowed_ips) {
-            return Err(auth::AuthError::ip_address_not_allowed().into());
-        }
-    }
-    let node_info = backend
-        .wake_compute(&extra)
-        .await?
-        .context("missing cache entry from wake_compute")?;
+    Ok((backend, extra))
+}
 
-    crate::proxy::connect_to_compute(
-        &TokioMechanism {
-            conn_id,
-            conn_info,
-            session_id,
-        },
-        node_info,
-        &extra,
-        &backend,
-        latency_timer,
-    )
-    .await
+/// Checks that the endpoint accepts connections through the client's private link, if any.
+async fn check_private_link(
+    backend: &auth::BackendType<'_, ComputeUserInfo>,
+    extra: &console::ConsoleReqExtra,
+    private_link_id: Option<&str>,
+) -> anyhow::Result<()> {
+    let allowed_vpc_endpoint_ids = backend.get_allowed_vpc_endpoint_ids(extra).await?;
+    if !check_private_link_id_is_in_list(private_link_id, &allowed_vpc_endpoint_ids) {
+        return Err(auth::AuthError::private_link_not_allowed().into());
+    }
+    Ok(())
 }
 
 async fn connect_to_compute_once(
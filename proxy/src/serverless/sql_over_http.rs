@@ -14,6 +14,7 @@ use hyper::{Body, HeaderMap, Request};
 use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
+use smol_str::SmolStr;
 use tokio_postgres::error::DbError;
 use tokio_postgres::types::Kind;
 use tokio_postgres::types::Type;
@@ -28,8 +29,11 @@ use url::Url;
 use utils::http::error::ApiError;
 use utils::http::json::json_response;
 
+use crate::audit_log::{self, SessionLog};
 use crate::config::HttpConfig;
+use crate::error::UserFacingError;
 use crate::proxy::NUM_CONNECTION_REQUESTS_GAUGE;
+use crate::rate_limiter::{ConnectionLimitError, EndpointConnectionLimiter};
 
 use super::conn_pool::ConnInfo;
 use super::conn_pool::GlobalConnPool;
@@ -197,14 +201,18 @@ fn get_conn_info(
 }
 
 // TODO: return different http error codes
+#[allow(clippy::too_many_arguments)]
 pub async fn handle(
     request: Request<Body>,
     sni_hostname: Option<String>,
     conn_pool: Arc<GlobalConnPool>,
     session_id: uuid::Uuid,
     peer_addr: IpAddr,
+    private_link_id: Option<SmolStr>,
     config: &'static HttpConfig,
+    endpoint_connection_limiter: &EndpointConnectionLimiter,
 ) -> Result<Response<Body>, ApiError> {
+    let session_log = SessionLog::new(session_id, "http", peer_addr, private_link_id.clone());
     let result = tokio::time::timeout(
         config.timeout,
         handle_inner(
@@ -214,12 +222,35 @@ pub async fn handle(
             conn_pool,
             session_id,
             peer_addr,
+            private_link_id,
+            endpoint_connection_limiter,
+            &session_log,
         ),
     )
     .await;
+    let error_kind = match &result {
+        Ok(r) => r.as_ref().err().map(audit_log::classify_error),
+        Err(_) => Some("timeout"),
+    };
+    session_log.finish(error_kind);
     let mut response = match result {
         Ok(r) => match r {
             Ok(r) => r,
+            Err(e) if e.is::<ConnectionLimitError>() => {
+                let e = e
+                    .downcast::<ConnectionLimitError>()
+                    .expect("error type was checked above");
+                let code =
+                    std::str::from_utf8(ConnectionLimitError::SQLSTATE).expect("SQLSTATE is ascii");
+                error!(
+                    code,
+                    "sql-over-http per-client task finished with an error: {e:#}"
+                );
+                json_response(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    json!({ "message": e.to_string_client(), "code": code }),
+                )?
+            }
             Err(e) => {
                 let message = format!("{:?}", e);
                 let db_error = e
@@ -294,6 +325,7 @@ pub async fn handle(
     Ok(response)
 }
 
+#[allow(clippy::too_many_arguments)]
 #[instrument(name = "sql-over-http", fields(pid = tracing::field::Empty), skip_all)]
 async fn handle_inner(
     config: &'static HttpConfig,
@@ -302,6 +334,9 @@ async fn handle_inner(
     conn_pool: Arc<GlobalConnPool>,
     session_id: uuid::Uuid,
     peer_addr: IpAddr,
+    private_link_id: Option<SmolStr>,
+    endpoint_connection_limiter: &EndpointConnectionLimiter,
+    session_log: &SessionLog,
 ) -> anyhow::Result<Response<Body>> {
     let _request_gauge = NUM_CONNECTION_REQUESTS_GAUGE
         .with_label_values(&["http"])
@@ -312,6 +347,7 @@ async fn handle_inner(
     //
     let headers = request.headers();
     let conn_info = get_conn_info(headers, sni_hostname)?;
+    session_log.set_user(conn_info.username.clone(), conn_info.dbname.clone());
 
     // Determine the output options. Default behaviour is 'false'. Anything that is not
     // strictly 'true' assumed to be false.
@@ -356,10 +392,25 @@ async fn handle_inner(
     // Read the query and query params from the request body
     //
     let body = hyper::body::to_bytes(request.into_body()).await?;
+    session_log.bytes().record_rx(body.len() as u64);
     let payload: Payload = serde_json::from_slice(&body)?;
 
+    // the permit is taken before the pool hands out a connection, and outlives the client
+    let compute = conn_pool
+        .wake_compute(&conn_info, peer_addr, private_link_id)
+        .await?;
+    session_log.set_auth_method(compute.auth_method());
+    session_log.set_metrics_aux(compute.aux());
+    if let Some(addr) = compute.compute_addr() {
+        session_log.set_compute_addr(addr);
+    }
+    let (endpoint_id, limits) = compute.connection_limits();
+    let _connection_permit = endpoint_connection_limiter
+        .acquire(&endpoint_id, &conn_info.username, limits.as_ref())
+        .await?;
+
     let mut client = conn_pool
-        .get(&conn_info, !allow_pool, session_id, peer_addr)
+        .get(&conn_info, !allow_pool, session_id, compute)
         .await?;
 
     let mut response = Response::builder()
@@ -462,6 +513,7 @@ async fn handle_inner(
     // count the egress bytes - we miss the TLS and header overhead but oh well...
     // moving this later in the stack is going to be a lot of effort and ehhhh
     metrics.record_egress(len as u64);
+    session_log.bytes().record_tx(len as u64);
 
     Ok(response)
 }
@@ -3,13 +3,14 @@ use crate::{
     config::ProxyConfig,
     error::io_error,
     proxy::{handle_client, ClientMode},
-    rate_limiter::EndpointRateLimiter,
+    rate_limiter::{EndpointConnectionLimiter, EndpointRateLimiter},
 };
 use bytes::{Buf, Bytes};
 use futures::{Sink, Stream};
 use hyper::upgrade::Upgraded;
 use hyper_tungstenite::{tungstenite::Message, HyperWebsocket, WebSocketStream};
 use pin_project_lite::pin_project;
+use smol_str::SmolStr;
 
 use std::{
     net::IpAddr,
@@ -129,6 +130,7 @@ impl AsyncBufRead for WebSocketRw {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn serve_websocket(
     websocket: HyperWebsocket,
     config: &'static ProxyConfig,
@@ -136,7 +138,9 @@ pub async fn serve_websocket(
     session_id: uuid::Uuid,
     hostname: Option<String>,
     peer_addr: IpAddr,
+    private_link_id: Option<SmolStr>,
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    endpoint_connection_limiter: Arc<EndpointConnectionLimiter>,
 ) -> anyhow::Result<()> {
     let websocket = websocket.await?;
     handle_client(
@@ -146,7 +150,9 @@ pub async fn serve_websocket(
         WebSocketRw::new(websocket),
         ClientMode::Websockets { hostname },
         peer_addr,
+        private_link_id,
         endpoint_rate_limiter,
+        endpoint_connection_limiter,
     )
     .await?;
     Ok(())
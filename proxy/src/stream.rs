@@ -117,6 +117,23 @@ impl<S: AsyncWrite + Unpin> PqStream<S> {
             .await?;
         bail!(error)
     }
+
+    /// Same as [`Self::throw_error`], but reports the given SQLSTATE code instead of
+    /// the generic `internal_error`, so that clients can tell the errors apart.
+    pub async fn throw_error_with_code<T, E>(
+        &mut self,
+        error: E,
+        code: &'static [u8; 5],
+    ) -> anyhow::Result<T>
+    where
+        E: UserFacingError + Into<anyhow::Error>,
+    {
+        let msg = error.to_string_client();
+        tracing::info!("forwarding error to user: {msg}");
+        self.write_message(&BeMessage::ErrorResponse(&msg, Some(code)))
+            .await?;
+        bail!(error)
+    }
 }
 
 /// Wrapper for upgrading raw streams into secure streams.
@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 
 use anyhow::Context;
-use aws_sdk_s3::{types::ObjectIdentifier, Client};
+use camino::Utf8Path;
 use tracing::{error, info, warn};
 use utils::generation::Generation;
 
@@ -12,7 +12,7 @@ use futures_util::{pin_mut, StreamExt};
 use pageserver::tenant::remote_timeline_client::parse_remote_index_path;
 use pageserver::tenant::storage_layer::LayerFileName;
 use pageserver::tenant::IndexPart;
-use remote_storage::RemotePath;
+use remote_storage::{GenericRemoteStorage, ListingMode, RemotePath};
 use utils::id::TenantTimelineId;
 
 pub(crate) struct TimelineAnalysis {
@@ -157,17 +157,14 @@ pub(crate) async fn branch_cleanup_and_check_errors(
                         ));
                         result.garbage_keys.extend(orphan_layers.iter().map(
                             |(layer_name, layer_gen)| {
-                                let mut key = s3_root.timeline_root(id).prefix_in_bucket;
-                                let delimiter = s3_root.delimiter();
-                                if !key.ends_with(delimiter) {
-                                    key.push_str(delimiter);
-                                }
-                                key.push_str(&format!(
-                                    "{}{}",
-                                    &layer_name.file_name(),
-                                    layer_gen.get_suffix()
-                                ));
-                                key
+                                s3_root
+                                    .timeline_root(id)
+                                    .join(Utf8Path::new(&format!(
+                                        "{}{}",
+                                        layer_name.file_name(),
+                                        layer_gen.get_suffix()
+                                    )))
+                                    .to_string()
                             },
                         ));
                     }
@@ -182,7 +179,7 @@ pub(crate) async fn branch_cleanup_and_check_errors(
         }
         None => result
             .errors
-            .push("Timeline has no data on S3 at all".to_string()),
+            .push("Timeline has no data in remote storage at all".to_string()),
     }
 
     if result.errors.is_empty() {
@@ -197,7 +194,7 @@ pub(crate) async fn branch_cleanup_and_check_errors(
 
     if !result.garbage_keys.is_empty() {
         error!(
-            "The following keys should be removed from S3: {0:?}",
+            "The following keys should be removed from remote storage: {0:?}",
             result.garbage_keys
         )
     }
@@ -223,7 +220,7 @@ pub(crate) enum BlobDataParseResult {
     Incorrect(Vec<String>),
 }
 
-fn parse_layer_object_name(name: &str) -> Result<(LayerFileName, Generation), String> {
+pub(crate) fn parse_layer_object_name(name: &str) -> Result<(LayerFileName, Generation), String> {
     match name.rsplit_once('-') {
         // FIXME: this is gross, just use a regex?
         Some((layer_filename, gen)) if gen.len() == 8 => {
@@ -237,32 +234,40 @@ fn parse_layer_object_name(name: &str) -> Result<(LayerFileName, Generation), St
 }
 
 pub(crate) async fn list_timeline_blobs(
-    s3_client: &Client,
+    remote_client: &GenericRemoteStorage,
     id: TenantTimelineId,
     s3_root: &RootTarget,
+) -> anyhow::Result<S3TimelineBlobData> {
+    list_timeline_dir_blobs(remote_client, &s3_root.timeline_root(&id)).await
+}
+
+/// Like [`list_timeline_blobs`], for a timeline directory given by its path, e.g. one of a
+/// sharded tenant.
+pub(crate) async fn list_timeline_dir_blobs(
+    remote_client: &GenericRemoteStorage,
+    timeline_dir_target: &RemotePath,
 ) -> anyhow::Result<S3TimelineBlobData> {
     let mut s3_layers = HashSet::new();
 
     let mut errors = Vec::new();
     let mut keys_to_remove = Vec::new();
 
-    let mut timeline_dir_target = s3_root.timeline_root(&id);
-    timeline_dir_target.delimiter = String::new();
-
-    let mut index_parts: Vec<ObjectIdentifier> = Vec::new();
+    let mut index_parts: Vec<RemotePath> = Vec::new();
     let mut initdb_archive: bool = false;
 
-    let stream = stream_listing(s3_client, &timeline_dir_target);
+    let stream = stream_listing(remote_client, timeline_dir_target, ListingMode::NoDelimiter);
     pin_mut!(stream);
-    while let Some(obj) = stream.next().await {
-        let obj = obj?;
-        let key = obj.key();
+    while let Some(key) = stream.next().await {
+        let key = key?;
 
-        let blob_name = key.strip_prefix(&timeline_dir_target.prefix_in_bucket);
+        let blob_name = key
+            .strip_prefix(timeline_dir_target)
+            .ok()
+            .map(|name| name.as_str());
         match blob_name {
             Some(name) if name.starts_with("index_part.json") => {
                 tracing::info!("Index key {key}");
-                index_parts.push(obj)
+                index_parts.push(key)
             }
             Some("initdb.tar.zst") => {
                 tracing::info!("initdb archive {key}");
@@ -275,15 +280,15 @@ pub(crate) async fn list_timeline_blobs(
                 }
                 Err(e) => {
                     tracing::info!("Error parsing key {maybe_layer_name}");
-                    errors.push(
-                        format!("S3 list response got an object with key {key} that is not a layer name: {e}"),
-                    );
+                    errors.push(format!(
+                        "Listing got an object with key {key} that is not a layer name: {e}"
+                    ));
                     keys_to_remove.push(key.to_string());
                 }
             },
             None => {
                 tracing::info!("Peculiar key {}", key);
-                errors.push(format!("S3 list response got an object with odd key {key}"));
+                errors.push(format!("Listing got an object with odd key {key}"));
                 keys_to_remove.push(key.to_string());
             }
         }
@@ -302,14 +307,7 @@ pub(crate) async fn list_timeline_blobs(
     // Choose the index_part with the highest generation
     let (index_part_object, index_part_generation) = match index_parts
         .iter()
-        .filter_map(|k| {
-            let key = k.key();
-            // Stripping the index key to the last part, because RemotePath doesn't
-            // like absolute paths, and depending on prefix_in_bucket it's possible
-            // for the keys we read back to start with a slash.
-            let basename = key.rsplit_once('/').unwrap().1;
-            parse_remote_index_path(RemotePath::from_string(basename).unwrap()).map(|g| (k, g))
-        })
+        .filter_map(|k| parse_remote_index_path(k.clone()).map(|g| (k, g)))
         .max_by_key(|i| i.1)
         .map(|(k, g)| (k.clone(), g))
     {
@@ -320,38 +318,30 @@ pub(crate) async fn list_timeline_blobs(
         }
     };
 
-    if index_part_object.is_none() {
-        errors.push("S3 list response got no index_part.json file".to_string());
-    }
-
-    if let Some(index_part_object_key) = index_part_object.as_ref().map(|object| object.key()) {
-        let index_part_bytes = download_object_with_retries(
-            s3_client,
-            &timeline_dir_target.bucket_name,
-            index_part_object_key,
-        )
-        .await
-        .context("index_part.json download")?;
-
-        match serde_json::from_slice(&index_part_bytes) {
-            Ok(index_part) => {
-                return Ok(S3TimelineBlobData {
-                    blob_data: BlobDataParseResult::Parsed {
-                        index_part,
-                        index_part_generation,
-                        s3_layers,
-                    },
-                    keys_to_remove,
-                })
+    match index_part_object {
+        Some(index_part_object_key) => {
+            let index_part_bytes =
+                download_object_with_retries(remote_client, &index_part_object_key)
+                    .await
+                    .context("index_part.json download")?;
+
+            match serde_json::from_slice(&index_part_bytes) {
+                Ok(index_part) => {
+                    return Ok(S3TimelineBlobData {
+                        blob_data: BlobDataParseResult::Parsed {
+                            index_part,
+                            index_part_generation,
+                            s3_layers,
+                        },
+                        keys_to_remove,
+                    })
+                }
+                Err(index_parse_error) => errors.push(format!(
+                    "index_part.json body parsing error: {index_parse_error}"
+                )),
             }
-            Err(index_parse_error) => errors.push(format!(
-                "index_part.json body parsing error: {index_parse_error}"
-            )),
         }
-    } else {
-        errors.push(format!(
-            "Index part object {index_part_object:?} has no key"
-        ));
+        None => errors.push("Listing got no index_part.json file".to_string()),
     }
 
     if errors.is_empty() {
@@ -1,24 +1,24 @@
 //! Functionality for finding and purging garbage, as in "garbage collection".  Garbage means
-//! S3 objects which are either not referenced by any metadata, or are referenced by a
+//! remote storage objects which are either not referenced by any metadata, or are referenced by a
 //! control plane tenant/timeline in a deleted state.
 
 use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Context;
-use aws_sdk_s3::{
-    types::{Delete, ObjectIdentifier},
-    Client,
-};
 use futures_util::{pin_mut, TryStreamExt};
+use pageserver_api::shard::TenantShardId;
+use pageserver_client::mgmt_api;
+use remote_storage::{GenericRemoteStorage, ListingMode, RemotePath};
 use serde::{Deserialize, Serialize};
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
 use utils::id::{TenantId, TenantTimelineId};
 
 use crate::{
     cloud_admin_api::{CloudAdminApiClient, MaybeDeleted, ProjectData},
     init_remote,
     metadata_stream::{stream_listing, stream_tenant_timelines, stream_tenants},
-    BucketConfig, ConsoleConfig, NodeKind, RootTarget, TraversingDepth,
+    BucketConfig, ConsoleConfig, NodeKind, PageserverConfig, RootTarget, TraversingDepth,
 };
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -110,7 +110,7 @@ pub async fn find_garbage(
     Ok(())
 }
 
-// How many concurrent S3 operations to issue (approximately): this is the concurrency
+// How many concurrent remote storage operations to issue (approximately): this is the concurrency
 // for things like listing the timelines within tenant prefixes.
 const S3_CONCURRENCY: usize = 32;
 
@@ -123,16 +123,19 @@ async fn find_garbage_inner(
     depth: TraversingDepth,
     node_kind: NodeKind,
 ) -> anyhow::Result<GarbageList> {
-    // Construct clients for S3 and for Console API
-    let (s3_client, target) = init_remote(bucket_config.clone(), node_kind)?;
+    // Construct clients for the remote storage and for Console API
+    let (remote_client, target) = init_remote(bucket_config.clone(), node_kind)?;
+    let region_id = console_config
+        .region_id
+        .clone()
+        .or_else(|| bucket_config.console_region_id())
+        .with_context(|| format!("Console region is not known for {bucket_config}"))?;
     let cloud_admin_api_client = Arc::new(CloudAdminApiClient::new(console_config));
 
     // Build a set of console-known tenants, for quickly eliminating known-active tenants without having
     // to issue O(N) console API requests.
     let console_projects: HashMap<TenantId, ProjectData> = cloud_admin_api_client
-        // FIXME: we can't just assume that all console's region ids are aws-<something>.  This hack
-        // will go away when we are talking to Control Plane APIs, which are per-region.
-        .list_projects(format!("aws-{}", bucket_config.region))
+        .list_projects(region_id)
         .await?
         .into_iter()
         .map(|t| (t.tenant, t))
@@ -142,9 +145,9 @@ async fn find_garbage_inner(
         console_projects.len()
     );
 
-    // Enumerate Tenants in S3, and check if each one exists in Console
-    tracing::info!("Finding all tenants in bucket {}...", bucket_config.bucket);
-    let tenants = stream_tenants(&s3_client, &target);
+    // Enumerate Tenants in the remote storage, and check if each one exists in Console
+    tracing::info!("Finding all tenants in {bucket_config}...");
+    let tenants = stream_tenants(&remote_client, &target);
     let tenants_checked = tenants.map_ok(|t| {
         let api_client = cloud_admin_api_client.clone();
         let console_projects = &console_projects;
@@ -212,7 +215,7 @@ async fn find_garbage_inner(
 
     // Construct a stream of all timelines within active tenants
     let active_tenants = tokio_stream::iter(active_tenants.iter().map(Ok));
-    let timelines = active_tenants.map_ok(|t| stream_tenant_timelines(&s3_client, &target, *t));
+    let timelines = active_tenants.map_ok(|t| stream_tenant_timelines(&remote_client, &target, *t));
     let timelines = timelines.try_buffer_unordered(S3_CONCURRENCY);
     let timelines = timelines.try_flatten();
 
@@ -263,51 +266,100 @@ impl std::fmt::Display for PurgeMode {
     }
 }
 
+/// The tenant shards that the pageservers of a [`PageserverConfig`] have.
+struct PageserverTenants {
+    clients: Vec<mgmt_api::Client>,
+    /// The shards of each tenant, with the index of the client of their pageserver
+    shards: HashMap<TenantId, Vec<(usize, TenantShardId)>>,
+}
+
+impl PageserverTenants {
+    async fn load(config: &PageserverConfig) -> anyhow::Result<Self> {
+        let clients: Vec<_> = config
+            .api_urls
+            .iter()
+            .map(|url| mgmt_api::Client::new(url.clone(), config.token.as_deref()))
+            .collect();
+
+        let mut shards: HashMap<TenantId, Vec<(usize, TenantShardId)>> = HashMap::new();
+        for (i, (client, url)) in clients.iter().zip(&config.api_urls).enumerate() {
+            let tenants = client
+                .list_tenants()
+                .await
+                .with_context(|| format!("list tenants of pageserver {url}"))?;
+            tracing::info!("Pageserver {url} has {} tenant shards", tenants.len());
+            for tenant in tenants {
+                shards
+                    .entry(tenant.id.tenant_id)
+                    .or_default()
+                    .push((i, tenant.id));
+            }
+        }
+
+        Ok(Self { clients, shards })
+    }
+
+    /// Whether any of the pageservers has the entity.
+    async fn has(&self, entity: &GarbageEntity) -> anyhow::Result<bool> {
+        match entity {
+            GarbageEntity::Tenant(tenant_id) => Ok(self.shards.contains_key(tenant_id)),
+            GarbageEntity::Timeline(ttid) => {
+                for (i, tenant_shard_id) in self.shards.get(&ttid.tenant_id).into_iter().flatten() {
+                    let timelines = self.clients[*i]
+                        .list_timelines(*tenant_shard_id)
+                        .await
+                        .with_context(|| format!("list timelines of {tenant_shard_id}"))?;
+                    if timelines.iter().any(|t| t.timeline_id == ttid.timeline_id) {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
 pub async fn get_tenant_objects(
-    s3_client: &Arc<Client>,
+    remote_client: &GenericRemoteStorage,
     target: RootTarget,
     tenant_id: TenantId,
-) -> anyhow::Result<Vec<ObjectIdentifier>> {
+) -> anyhow::Result<Vec<RemotePath>> {
     tracing::debug!("Listing objects in tenant {tenant_id}");
     // TODO: apply extra validation based on object modification time.  Don't purge
     // tenants where any timeline's index_part.json has been touched recently.
 
-    let mut tenant_root = target.tenant_root(&tenant_id);
+    let tenant_root = target.tenant_root(&tenant_id);
 
-    // Remove delimiter, so that object listing lists all keys in the prefix and not just
+    // List without delimiter, so that object listing lists all keys in the prefix and not just
     // common prefixes.
-    tenant_root.delimiter = String::new();
-
-    let key_stream = stream_listing(s3_client, &tenant_root);
+    let key_stream = stream_listing(remote_client, &tenant_root, ListingMode::NoDelimiter);
     key_stream.try_collect().await
 }
 
 pub async fn get_timeline_objects(
-    s3_client: &Arc<Client>,
+    remote_client: &GenericRemoteStorage,
     target: RootTarget,
     ttid: TenantTimelineId,
-) -> anyhow::Result<Vec<ObjectIdentifier>> {
+) -> anyhow::Result<Vec<RemotePath>> {
     tracing::debug!("Listing objects in timeline {ttid}");
-    let mut timeline_root = target.timeline_root(&ttid);
+    let timeline_root = target.timeline_root(&ttid);
 
     // TODO: apply extra validation based on object modification time.  Don't purge
     // timelines whose index_part.json has been touched recently.
 
-    // Remove delimiter, so that object listing lists all keys in the prefix and not just
+    // List without delimiter, so that object listing lists all keys in the prefix and not just
     // common prefixes.
-    timeline_root.delimiter = String::new();
-    let key_stream = stream_listing(s3_client, &timeline_root);
+    let key_stream = stream_listing(remote_client, &timeline_root, ListingMode::NoDelimiter);
 
     key_stream.try_collect().await
 }
 
 const MAX_KEYS_PER_DELETE: usize = 1000;
 
-/// Drain a buffer of keys into DeleteObjects requests
-async fn do_delete(
-    s3_client: &Arc<Client>,
-    bucket_name: &str,
-    keys: &mut Vec<ObjectIdentifier>,
+/// Drain a buffer of keys into batched deletions
+pub(crate) async fn do_delete(
+    remote_client: &GenericRemoteStorage,
+    keys: &mut Vec<RemotePath>,
     dry_run: bool,
     drain: bool,
 ) -> anyhow::Result<()> {
@@ -320,12 +372,8 @@ async fn do_delete(
                 tracing::info!("  {k:?}");
             }
         } else {
-            let delete_request = s3_client
-                .delete_objects()
-                .bucket(bucket_name)
-                .delete(Delete::builder().set_objects(Some(request_keys)).build()?);
-            delete_request
-                .send()
+            remote_client
+                .delete_objects(&request_keys, &CancellationToken::new())
                 .await
                 .context("DeleteObjects request")?;
         }
@@ -337,6 +385,7 @@ async fn do_delete(
 pub async fn purge_garbage(
     input_path: String,
     mode: PurgeMode,
+    pageserver_config: Option<PageserverConfig>,
     dry_run: bool,
 ) -> anyhow::Result<()> {
     let list_bytes = tokio::fs::read(&input_path).await?;
@@ -347,7 +396,7 @@ pub async fn purge_garbage(
         input_path
     );
 
-    let (s3_client, target) =
+    let (remote_client, target) =
         init_remote(garbage_list.bucket_config.clone(), garbage_list.node_kind)?;
 
     // Sanity checks on the incoming list
@@ -355,32 +404,49 @@ pub async fn purge_garbage(
         anyhow::bail!("Refusing to purge a garbage list that reports 0 active tenants");
     }
 
-    let filtered_items = garbage_list
+    let mut filtered_items: Vec<&GarbageItem> = garbage_list
         .items
         .iter()
         .filter(|i| match (&mode, &i.reason) {
             (PurgeMode::DeletedAndMissing, _) => true,
             (PurgeMode::DeletedOnly, GarbageReason::DeletedInConsole) => true,
             (PurgeMode::DeletedOnly, GarbageReason::MissingInConsole) => false,
-        });
+        })
+        .collect();
 
     tracing::info!(
         "Filtered down to {} garbage items based on mode {}",
-        garbage_list.items.len(),
+        filtered_items.len(),
         mode
     );
 
-    let items = tokio_stream::iter(filtered_items.map(Ok));
+    if let Some(pageserver_config) = pageserver_config {
+        let pageserver_tenants = PageserverTenants::load(&pageserver_config).await?;
+        let mut unattached_items = Vec::new();
+        for item in filtered_items {
+            if pageserver_tenants.has(&item.entity).await? {
+                tracing::warn!("Skipping {:?}, a pageserver still has it", item.entity);
+            } else {
+                unattached_items.push(item);
+            }
+        }
+        filtered_items = unattached_items;
+        tracing::info!(
+            "{} garbage items are not on any pageserver",
+            filtered_items.len()
+        );
+    }
+
+    let items = tokio_stream::iter(filtered_items.into_iter().map(Ok));
     let get_objects_results = items.map_ok(|i| {
-        let s3_client = s3_client.clone();
-        let target = target.clone();
+        let remote_client = remote_client.clone();
         async move {
             match i.entity {
                 GarbageEntity::Tenant(tenant_id) => {
-                    get_tenant_objects(&s3_client, target, tenant_id).await
+                    get_tenant_objects(&remote_client, target, tenant_id).await
                 }
                 GarbageEntity::Timeline(ttid) => {
-                    get_timeline_objects(&s3_client, target, ttid).await
+                    get_timeline_objects(&remote_client, target, ttid).await
                 }
             }
         }
@@ -393,25 +459,11 @@ pub async fn purge_garbage(
         let mut object_list = result?;
         objects_to_delete.append(&mut object_list);
         if objects_to_delete.len() >= MAX_KEYS_PER_DELETE {
-            do_delete(
-                &s3_client,
-                &garbage_list.bucket_config.bucket,
-                &mut objects_to_delete,
-                dry_run,
-                false,
-            )
-            .await?;
+            do_delete(&remote_client, &mut objects_to_delete, dry_run, false).await?;
         }
     }
 
-    do_delete(
-        &s3_client,
-        &garbage_list.bucket_config.bucket,
-        &mut objects_to_delete,
-        dry_run,
-        true,
-    )
-    .await?;
+    do_delete(&remote_client, &mut objects_to_delete, dry_run, true).await?;
 
     tracing::info!("Fell through");
 
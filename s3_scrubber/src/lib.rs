@@ -4,46 +4,47 @@ pub mod checks;
 pub mod cloud_admin_api;
 pub mod garbage;
 pub mod metadata_stream;
+pub mod orphan_layers;
+pub mod resync_mirror;
 pub mod scan_metadata;
+pub mod verify_timeline;
 
 use std::env;
 use std::fmt::Display;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Context;
-use aws_config::environment::EnvironmentVariableCredentialsProvider;
+use aws_config::environment::credentials::EnvironmentVariableCredentialsProvider;
 use aws_config::imds::credentials::ImdsCredentialsProvider;
 use aws_config::meta::credentials::CredentialsProviderChain;
+use aws_config::profile::ProfileFileCredentialsProvider;
 use aws_config::sso::SsoCredentialsProvider;
-use aws_config::BehaviorVersion;
-use aws_sdk_s3::config::Region;
-use aws_sdk_s3::{Client, Config};
-
+use aws_config::Region;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use camino::{Utf8Path, Utf8PathBuf};
 use clap::ValueEnum;
+use futures_util::StreamExt;
 use pageserver::tenant::TENANTS_SEGMENT_NAME;
+use pageserver_api::shard::TenantShardId;
+use remote_storage::{
+    AzureConfig, GenericRemoteStorage, Listing, ListingMode, LocalFsConfig, RemotePath,
+    RemoteStorageConfig, RemoteStorageKind, S3Bucket, S3Config, DEFAULT_MAX_KEYS_PER_LIST_RESPONSE,
+    DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT, DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT,
+};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 use std::io::IsTerminal;
-use tokio::io::AsyncReadExt;
+use tokio_util::sync::CancellationToken;
 use tracing::error;
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
-use utils::id::{TenantId, TenantTimelineId};
+use utils::id::{TenantId, TenantTimelineId, TimelineId};
 
 const MAX_RETRIES: usize = 20;
 const CLOUD_ADMIN_API_TOKEN_ENV_VAR: &str = "CLOUD_ADMIN_API_TOKEN";
 
-#[derive(Debug, Clone)]
-pub struct S3Target {
-    pub bucket_name: String,
-    /// This `prefix_in_bucket` is only equal to the PS/SK config of the same
-    /// name for the RootTarget: other instances of S3Target will have prefix_in_bucket
-    /// with extra parts.
-    pub prefix_in_bucket: String,
-    pub delimiter: String,
-}
-
 #[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TraversingDepth {
     Tenant,
@@ -80,108 +81,171 @@ impl Display for NodeKind {
     }
 }
 
-impl S3Target {
-    pub fn with_sub_segment(&self, new_segment: &str) -> Self {
-        let mut new_self = self.clone();
-        if new_self.prefix_in_bucket.is_empty() {
-            new_self.prefix_in_bucket = format!("/{}/", new_segment);
-        } else {
-            if new_self.prefix_in_bucket.ends_with('/') {
-                new_self.prefix_in_bucket.pop();
-            }
-            new_self.prefix_in_bucket =
-                [&new_self.prefix_in_bucket, new_segment, ""].join(&new_self.delimiter);
-        }
-        new_self
-    }
-}
-
-#[derive(Clone)]
+/// The layout of a node kind's data in the remote storage.  All the paths are relative
+/// to the storage root, which already includes the bucket/container prefix.
+#[derive(Debug, Clone, Copy)]
 pub enum RootTarget {
-    Pageserver(S3Target),
-    Safekeeper(S3Target),
+    Pageserver,
+    Safekeeper,
 }
 
 impl RootTarget {
-    pub fn tenants_root(&self) -> S3Target {
-        match self {
-            Self::Pageserver(root) => root.with_sub_segment(TENANTS_SEGMENT_NAME),
-            Self::Safekeeper(root) => root.with_sub_segment("wal"),
-        }
+    pub fn tenants_root(&self) -> RemotePath {
+        let segment = match self {
+            Self::Pageserver => TENANTS_SEGMENT_NAME,
+            Self::Safekeeper => "wal",
+        };
+        RemotePath::new(Utf8Path::new(segment)).expect("relative path")
     }
 
-    pub fn tenant_root(&self, tenant_id: &TenantId) -> S3Target {
-        self.tenants_root().with_sub_segment(&tenant_id.to_string())
+    pub fn tenant_root(&self, tenant_id: &TenantId) -> RemotePath {
+        self.tenants_root()
+            .join(Utf8Path::new(&tenant_id.to_string()))
     }
 
-    pub fn timelines_root(&self, tenant_id: &TenantId) -> S3Target {
+    pub fn timelines_root(&self, tenant_id: &TenantId) -> RemotePath {
         match self {
-            Self::Pageserver(_) => self.tenant_root(tenant_id).with_sub_segment("timelines"),
-            Self::Safekeeper(_) => self.tenant_root(tenant_id),
+            Self::Pageserver => self.tenant_root(tenant_id).join(Utf8Path::new("timelines")),
+            Self::Safekeeper => self.tenant_root(tenant_id),
         }
     }
 
-    pub fn timeline_root(&self, id: &TenantTimelineId) -> S3Target {
+    pub fn timeline_root(&self, id: &TenantTimelineId) -> RemotePath {
         self.timelines_root(&id.tenant_id)
-            .with_sub_segment(&id.timeline_id.to_string())
+            .join(Utf8Path::new(&id.timeline_id.to_string()))
     }
 
-    pub fn bucket_name(&self) -> &str {
+    /// Like [`Self::timelines_root`], for a shard of a sharded tenant.  An unsharded tenant's
+    /// shard id formats as the bare tenant id, so this works for unsharded tenants too.
+    pub fn tenant_shard_timelines_root(&self, tenant_shard_id: &TenantShardId) -> RemotePath {
+        let tenant_root = self
+            .tenants_root()
+            .join(Utf8Path::new(&tenant_shard_id.to_string()));
         match self {
-            Self::Pageserver(root) => &root.bucket_name,
-            Self::Safekeeper(root) => &root.bucket_name,
+            Self::Pageserver => tenant_root.join(Utf8Path::new("timelines")),
+            Self::Safekeeper => tenant_root,
         }
     }
 
-    pub fn delimiter(&self) -> &str {
-        match self {
-            Self::Pageserver(root) => &root.delimiter,
-            Self::Safekeeper(root) => &root.delimiter,
-        }
+    pub fn tenant_shard_timeline_root(
+        &self,
+        tenant_shard_id: &TenantShardId,
+        timeline_id: &TimelineId,
+    ) -> RemotePath {
+        self.tenant_shard_timelines_root(tenant_shard_id)
+            .join(Utf8Path::new(&timeline_id.to_string()))
     }
 }
 
+/// Location of the remote storage to scrub.  Embedded into the garbage lists, so
+/// that the purge is executed against the same storage the list was built for.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct BucketConfig {
-    pub region: String,
-    pub bucket: String,
-    pub prefix_in_bucket: Option<String>,
-
-    /// Use SSO if this is set, else rely on AWS_* environment vars
-    pub sso_account_id: Option<String>,
+pub enum BucketConfig {
+    AwsS3 {
+        region: String,
+        bucket: String,
+        prefix_in_bucket: Option<String>,
+        /// Custom S3 endpoint, e.g. a mock server in tests
+        endpoint: Option<String>,
+        /// AWS account to get SSO credentials for, with a `PowerUserAccess` role
+        sso_account_id: Option<String>,
+    },
+    AzureContainer {
+        region: String,
+        container: String,
+        prefix_in_container: Option<String>,
+    },
+    LocalFs {
+        /// The remote storage root of the node, as in its `local_path` setting
+        path: Utf8PathBuf,
+    },
 }
 
 impl Display for BucketConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}/{}/{}",
-            self.sso_account_id.as_deref().unwrap_or("<none>"),
-            self.region,
-            self.bucket
-        )
+        match self {
+            Self::AwsS3 {
+                region,
+                bucket,
+                prefix_in_bucket,
+                ..
+            } => write!(
+                f,
+                "s3 {region}/{bucket} prefix {}",
+                prefix_in_bucket.as_deref().unwrap_or("<none>")
+            ),
+            Self::AzureContainer {
+                region,
+                container,
+                prefix_in_container,
+            } => write!(
+                f,
+                "azure {region}/{container} prefix {}",
+                prefix_in_container.as_deref().unwrap_or("<none>")
+            ),
+            Self::LocalFs { path } => write!(f, "local fs {path}"),
+        }
     }
 }
 
 impl BucketConfig {
+    /// `LOCAL_PATH` selects a local filesystem storage, `AZURE_CONTAINER` an Azure one,
+    /// and S3 is used otherwise.
     pub fn from_env() -> anyhow::Result<Self> {
-        let sso_account_id = env::var("SSO_ACCOUNT_ID").ok();
+        if let Ok(path) = env::var("LOCAL_PATH") {
+            return Ok(Self::LocalFs { path: path.into() });
+        }
+
         let region = env::var("REGION").context("'REGION' param retrieval")?;
+        let prefix = env::var("BUCKET_PREFIX").ok();
+
+        if let Ok(container) = env::var("AZURE_CONTAINER") {
+            return Ok(Self::AzureContainer {
+                region,
+                container,
+                prefix_in_container: prefix,
+            });
+        }
+
         let bucket = env::var("BUCKET").context("'BUCKET' param retrieval")?;
-        let prefix_in_bucket = env::var("BUCKET_PREFIX").ok();
+        let endpoint = env::var("AWS_ENDPOINT_URL").ok();
+        let sso_account_id = env::var("SSO_ACCOUNT_ID").ok();
 
-        Ok(Self {
+        Ok(Self::AwsS3 {
             region,
             bucket,
-            prefix_in_bucket,
+            prefix_in_bucket: prefix,
+            endpoint,
             sso_account_id,
         })
     }
+
+    /// Short name of the storage, suitable for file names.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::AwsS3 { bucket, .. } => bucket,
+            Self::AzureContainer { container, .. } => container,
+            Self::LocalFs { .. } => "local_fs",
+        }
+    }
+
+    /// The console region id of the storage location, if it has any.
+    fn console_region_id(&self) -> Option<String> {
+        // FIXME: we can't just assume that all console's region ids are <cloud>-<region>.  This hack
+        // will go away when we are talking to Control Plane APIs, which are per-region.
+        match self {
+            Self::AwsS3 { region, .. } => Some(format!("aws-{region}")),
+            Self::AzureContainer { region, .. } => Some(format!("azure-{region}")),
+            Self::LocalFs { .. } => None,
+        }
+    }
 }
 
 pub struct ConsoleConfig {
     pub token: String,
     pub base_url: Url,
+    /// Console region to check the tenants against, derived from the bucket if not set
+    pub region_id: Option<String>,
 }
 
 impl ConsoleConfig {
@@ -194,7 +258,36 @@ impl ConsoleConfig {
         let token = env::var(CLOUD_ADMIN_API_TOKEN_ENV_VAR)
             .context("'CLOUD_ADMIN_API_TOKEN' environment variable fetch")?;
 
-        Ok(Self { base_url, token })
+        let region_id = env::var("CLOUD_ADMIN_API_REGION_ID").ok();
+
+        Ok(Self {
+            base_url,
+            token,
+            region_id,
+        })
+    }
+}
+
+/// Pageservers to check the garbage against before purging it: whatever the console says,
+/// a tenant or timeline that a pageserver still has is not garbage.
+pub struct PageserverConfig {
+    pub api_urls: Vec<String>,
+    pub token: Option<String>,
+}
+
+impl PageserverConfig {
+    /// None if `PAGESERVER_API_URLS` is not set.
+    pub fn from_env() -> Option<Self> {
+        let api_urls = env::var("PAGESERVER_API_URLS").ok()?;
+        let token = env::var("PAGESERVER_API_TOKEN").ok();
+
+        Some(Self {
+            api_urls: api_urls
+                .split(',')
+                .map(|url| url.trim().to_string())
+                .collect(),
+            token,
+        })
     }
 }
 
@@ -219,92 +312,127 @@ pub fn init_logging(file_name: &str) -> WorkerGuard {
     guard
 }
 
-pub fn init_s3_client(account_id: Option<String>, bucket_region: Region) -> Client {
-    let credentials_provider = {
-        // uses "AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY"
-        let chain = CredentialsProviderChain::first_try(
-            "env",
-            EnvironmentVariableCredentialsProvider::new(),
-        );
-
-        // Use SSO if we were given an account ID
-        match account_id {
-            Some(sso_account) => chain.or_else(
-                "sso",
-                SsoCredentialsProvider::builder()
-                    .account_id(sso_account)
-                    .role_name("PowerUserAccess")
-                    .start_url("https://neondb.awsapps.com/start")
-                    .region(Region::from_static("eu-central-1"))
-                    .build(),
-            ),
-            None => chain,
-        }
-        .or_else(
-            // Finally try IMDS
-            "imds",
-            ImdsCredentialsProvider::builder().build(),
-        )
+/// Unlike the nodes, the scrubber runs from developer machines too: besides the credentials
+/// that the nodes' S3 client looks for, use SSO and `AWS_PROFILE` ones.
+fn s3_credentials_provider(sso_account_id: Option<String>) -> SharedCredentialsProvider {
+    // uses "AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY"
+    let chain =
+        CredentialsProviderChain::first_try("env", EnvironmentVariableCredentialsProvider::new());
+
+    // Use SSO if we were given an account ID
+    let chain = match sso_account_id {
+        Some(sso_account) => chain.or_else(
+            "sso",
+            SsoCredentialsProvider::builder()
+                .account_id(sso_account)
+                .role_name("PowerUserAccess")
+                .start_url("https://neondb.awsapps.com/start")
+                .region(Region::from_static("eu-central-1"))
+                .build(),
+        ),
+        None => chain,
     };
 
-    let mut builder = Config::builder()
-        .behavior_version(BehaviorVersion::v2023_11_09())
-        .region(bucket_region)
-        .credentials_provider(credentials_provider);
-
-    if let Ok(endpoint) = env::var("AWS_ENDPOINT_URL") {
-        builder = builder.endpoint_url(endpoint)
-    }
+    let chain = chain
+        // uses "AWS_PROFILE" and ~/.aws/config, including profiles of `aws sso login`
+        .or_else("profile", ProfileFileCredentialsProvider::builder().build())
+        // Finally try IMDS
+        .or_else("imds", ImdsCredentialsProvider::builder().build());
 
-    Client::from_conf(builder.build())
+    SharedCredentialsProvider::new(chain)
 }
 
 fn init_remote(
     bucket_config: BucketConfig,
     node_kind: NodeKind,
-) -> anyhow::Result<(Arc<Client>, RootTarget)> {
-    let bucket_region = Region::new(bucket_config.region);
-    let delimiter = "/".to_string();
-    let s3_client = Arc::new(init_s3_client(bucket_config.sso_account_id, bucket_region));
-
-    let s3_root = match node_kind {
-        NodeKind::Pageserver => RootTarget::Pageserver(S3Target {
-            bucket_name: bucket_config.bucket,
-            prefix_in_bucket: bucket_config
-                .prefix_in_bucket
-                .unwrap_or("pageserver/v1".to_string()),
-            delimiter,
-        }),
-        NodeKind::Safekeeper => RootTarget::Safekeeper(S3Target {
-            bucket_name: bucket_config.bucket,
-            prefix_in_bucket: bucket_config
-                .prefix_in_bucket
-                .unwrap_or("safekeeper/v1".to_string()),
-            delimiter,
-        }),
+) -> anyhow::Result<(GenericRemoteStorage, RootTarget)> {
+    let default_prefix = match node_kind {
+        NodeKind::Pageserver => "pageserver/v1",
+        NodeKind::Safekeeper => "safekeeper/v1",
+    };
+    // An explicitly empty prefix means the node data is stored at the bucket root
+    let prefix = |prefix: Option<String>| match prefix {
+        Some(prefix) if prefix.is_empty() => None,
+        Some(prefix) => Some(prefix),
+        None => Some(default_prefix.to_string()),
+    };
+
+    let from_kind = |storage| {
+        GenericRemoteStorage::from_config(&RemoteStorageConfig {
+            storage,
+            timeout: RemoteStorageConfig::DEFAULT_TIMEOUT,
+            small_timeout: RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
+        })
+    };
+    let remote_client = match bucket_config {
+        BucketConfig::AwsS3 {
+            region,
+            bucket,
+            prefix_in_bucket,
+            endpoint,
+            sso_account_id,
+        } => {
+            let s3_config = S3Config {
+                bucket_name: bucket,
+                bucket_region: region,
+                prefix_in_bucket: prefix(prefix_in_bucket),
+                endpoint,
+                concurrency_limit: NonZeroUsize::new(DEFAULT_REMOTE_STORAGE_S3_CONCURRENCY_LIMIT)
+                    .expect("non-zero default"),
+                max_keys_per_list_response: DEFAULT_MAX_KEYS_PER_LIST_RESPONSE,
+            };
+            GenericRemoteStorage::AwsS3(Arc::new(S3Bucket::with_credentials_provider(
+                &s3_config,
+                RemoteStorageConfig::DEFAULT_TIMEOUT,
+                RemoteStorageConfig::DEFAULT_SMALL_TIMEOUT,
+                s3_credentials_provider(sso_account_id),
+            )?))
+        }
+        BucketConfig::AzureContainer {
+            region,
+            container,
+            prefix_in_container,
+        } => from_kind(RemoteStorageKind::AzureContainer(AzureConfig {
+            container_name: container,
+            container_region: region,
+            prefix_in_container: prefix(prefix_in_container),
+            concurrency_limit: NonZeroUsize::new(DEFAULT_REMOTE_STORAGE_AZURE_CONCURRENCY_LIMIT)
+                .expect("non-zero default"),
+            max_keys_per_list_response: DEFAULT_MAX_KEYS_PER_LIST_RESPONSE,
+        }))?,
+        BucketConfig::LocalFs { path } => {
+            from_kind(RemoteStorageKind::LocalFs(LocalFsConfig::new(path)))?
+        }
+    };
+
+    let root = match node_kind {
+        NodeKind::Pageserver => RootTarget::Pageserver,
+        NodeKind::Safekeeper => RootTarget::Safekeeper,
     };
 
-    Ok((s3_client, s3_root))
+    Ok((remote_client, root))
 }
 
 async fn list_objects_with_retries(
-    s3_client: &Client,
-    s3_target: &S3Target,
-    continuation_token: Option<String>,
-) -> anyhow::Result<aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Output> {
+    remote_client: &GenericRemoteStorage,
+    prefix: &RemotePath,
+    mode: ListingMode,
+) -> anyhow::Result<Listing> {
+    // Listing modes are not `Copy`, remember which one to retry with
+    let with_delimiter = matches!(mode, ListingMode::WithDelimiter);
     for _ in 0..MAX_RETRIES {
-        match s3_client
-            .list_objects_v2()
-            .bucket(&s3_target.bucket_name)
-            .prefix(&s3_target.prefix_in_bucket)
-            .delimiter(&s3_target.delimiter)
-            .set_continuation_token(continuation_token.clone())
-            .send()
+        let mode = if with_delimiter {
+            ListingMode::WithDelimiter
+        } else {
+            ListingMode::NoDelimiter
+        };
+        match remote_client
+            .list(Some(prefix), mode, &CancellationToken::new())
             .await
         {
-            Ok(response) => return Ok(response),
+            Ok(listing) => return Ok(listing),
             Err(e) => {
-                error!("list_objects_v2 query failed: {e}");
+                error!("list query for prefix {prefix} failed: {e}");
                 tokio::time::sleep(Duration::from_secs(1)).await;
             }
         }
@@ -314,20 +442,14 @@ async fn list_objects_with_retries(
 }
 
 async fn download_object_with_retries(
-    s3_client: &Client,
-    bucket_name: &str,
-    key: &str,
+    remote_client: &GenericRemoteStorage,
+    key: &RemotePath,
 ) -> anyhow::Result<Vec<u8>> {
-    for _ in 0..MAX_RETRIES {
+    'retries: for _ in 0..MAX_RETRIES {
         let mut body_buf = Vec::new();
-        let response_stream = match s3_client
-            .get_object()
-            .bucket(bucket_name)
-            .key(key)
-            .send()
-            .await
+        let mut download_stream = match remote_client.download(key, &CancellationToken::new()).await
         {
-            Ok(response) => response,
+            Ok(download) => download.download_stream,
             Err(e) => {
                 error!("Failed to download object for key {key}: {e}");
                 tokio::time::sleep(Duration::from_secs(1)).await;
@@ -335,21 +457,22 @@ async fn download_object_with_retries(
             }
         };
 
-        match response_stream
-            .body
-            .into_async_read()
-            .read_to_end(&mut body_buf)
-            .await
-        {
-            Ok(bytes_read) => {
-                tracing::info!("Downloaded {bytes_read} bytes for object object with key {key}");
-                return Ok(body_buf);
-            }
-            Err(e) => {
-                error!("Failed to stream object body for key {key}: {e}");
-                tokio::time::sleep(Duration::from_secs(1)).await;
+        while let Some(chunk) = download_stream.next().await {
+            match chunk {
+                Ok(chunk) => body_buf.extend_from_slice(&chunk),
+                Err(e) => {
+                    error!("Failed to stream object body for key {key}: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue 'retries;
+                }
             }
         }
+
+        tracing::info!(
+            "Downloaded {} bytes for object object with key {key}",
+            body_buf.len()
+        );
+        return Ok(body_buf);
     }
 
     anyhow::bail!("Failed to download objects with key {key} {MAX_RETRIES} times")
@@ -1,6 +1,14 @@
+use std::time::Duration;
+
 use s3_scrubber::garbage::{find_garbage, purge_garbage, PurgeMode};
+use s3_scrubber::orphan_layers::{find_orphan_layers, purge_orphan_layers};
+use s3_scrubber::resync_mirror::resync_mirror;
 use s3_scrubber::scan_metadata::scan_metadata;
-use s3_scrubber::{init_logging, BucketConfig, ConsoleConfig, NodeKind, TraversingDepth};
+use s3_scrubber::verify_timeline::verify_timelines;
+use s3_scrubber::{
+    init_logging, BucketConfig, ConsoleConfig, NodeKind, PageserverConfig, TraversingDepth,
+};
+use utils::id::TenantTimelineId;
 
 use clap::{Parser, Subcommand};
 
@@ -35,24 +43,71 @@ enum Command {
         #[arg(short, long, default_value_t = false)]
         json: bool,
     },
+    FindOrphanLayers {
+        /// Only report layers not modified for at least this long
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "48h")]
+        min_age: Duration,
+        #[arg(short, long, default_value_t = String::from("orphan_layers.json"))]
+        output_path: String,
+    },
+    PurgeOrphanLayers {
+        #[arg(short, long)]
+        input_path: String,
+    },
+    VerifyTimeline {
+        /// Timelines to verify, as `<tenant_id>/<timeline_id>`.  All timelines if not set.
+        #[arg(long = "timeline")]
+        timelines: Vec<TenantTimelineId>,
+        /// Also download the layers' summary blocks and check them against the layer names
+        #[arg(long, default_value_t = false)]
+        check_layers: bool,
+    },
+    /// Copy to the mirror of a remote storage the objects that it missed, and delete from it
+    /// the objects deleted from the main storage.
+    ResyncMirror {
+        /// The remote storage config with its `mirror`, as a TOML inline table.
+        #[arg(long)]
+        remote_storage: String,
+        /// Only resync the objects under this prefix, e.g. `tenants/<tenant_id>`.
+        #[arg(long)]
+        prefix: Option<String>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
+    // Not about the bucket of the other commands, but about both storages in its own config.
+    if let Command::ResyncMirror {
+        remote_storage,
+        prefix,
+    } = &cli.command
+    {
+        let _guard = init_logging(&format!(
+            "{}_resync-mirror_{}.log",
+            std::env::args().next().unwrap(),
+            chrono::Utc::now().format("%Y_%m_%d__%H_%M_%S")
+        ));
+        return resync_mirror(remote_storage, prefix.as_deref()).await;
+    }
+
     let bucket_config = BucketConfig::from_env()?;
 
     let command_log_name = match &cli.command {
         Command::ScanMetadata { .. } => "scan",
         Command::FindGarbage { .. } => "find-garbage",
         Command::PurgeGarbage { .. } => "purge-garbage",
+        Command::FindOrphanLayers { .. } => "find-orphan-layers",
+        Command::PurgeOrphanLayers { .. } => "purge-orphan-layers",
+        Command::VerifyTimeline { .. } => "verify-timeline",
+        Command::ResyncMirror { .. } => unreachable!("handled above"),
     };
     let _guard = init_logging(&format!(
         "{}_{}_{}_{}.log",
         std::env::args().next().unwrap(),
         command_log_name,
-        bucket_config.bucket,
+        bucket_config.name(),
         chrono::Utc::now().format("%Y_%m_%d__%H_%M_%S")
     ));
 
@@ -74,13 +129,7 @@ async fn main() -> anyhow::Result<()> {
                     // Strictly speaking an empty bucket is a valid bucket, but if someone ran the
                     // scrubber they were likely expecting to scan something, and if we see no timelines
                     // at all then it's likely due to some configuration issues like a bad prefix
-                    Err(anyhow::anyhow!(
-                        "No timelines found in bucket {} prefix {}",
-                        bucket_config.bucket,
-                        bucket_config
-                            .prefix_in_bucket
-                            .unwrap_or("<none>".to_string())
-                    ))
+                    Err(anyhow::anyhow!("No timelines found in {bucket_config}"))
                 } else {
                     Ok(())
                 }
@@ -95,7 +144,34 @@ async fn main() -> anyhow::Result<()> {
             find_garbage(bucket_config, console_config, depth, node_kind, output_path).await
         }
         Command::PurgeGarbage { input_path, mode } => {
-            purge_garbage(input_path, mode, !cli.delete).await
+            let pageserver_config = PageserverConfig::from_env();
+            purge_garbage(input_path, mode, pageserver_config, !cli.delete).await
+        }
+        Command::FindOrphanLayers {
+            min_age,
+            output_path,
+        } => find_orphan_layers(bucket_config, min_age, output_path).await,
+        Command::PurgeOrphanLayers { input_path } => {
+            purge_orphan_layers(input_path, !cli.delete).await
+        }
+        Command::VerifyTimeline {
+            timelines,
+            check_layers,
+        } => {
+            let verifications = verify_timelines(bucket_config, timelines, check_layers).await?;
+            for verification in &verifications {
+                println!("{}", serde_json::to_string(verification).unwrap());
+            }
+            let failed = verifications.iter().filter(|v| v.is_fatal()).count();
+            if failed > 0 {
+                Err(anyhow::anyhow!(
+                    "{failed} out of {} timelines failed verification",
+                    verifications.len()
+                ))
+            } else {
+                Ok(())
+            }
         }
+        Command::ResyncMirror { .. } => unreachable!("handled above"),
     }
 }
@@ -1,134 +1,153 @@
 use anyhow::Context;
 use async_stream::{stream, try_stream};
-use aws_sdk_s3::{types::ObjectIdentifier, Client};
+use pageserver_api::shard::TenantShardId;
+use remote_storage::{GenericRemoteStorage, ListingMode, ListingObject, RemotePath};
 use tokio_stream::Stream;
 
-use crate::{list_objects_with_retries, RootTarget, S3Target, TenantId};
+use crate::{list_objects_with_retries, RootTarget, TenantId};
 use utils::id::{TenantTimelineId, TimelineId};
 
-/// Given an S3 bucket, output a stream of TenantIds discovered via ListObjectsv2
+/// Given a remote storage, output a stream of TenantIds discovered via listing
 pub fn stream_tenants<'a>(
-    s3_client: &'a Client,
+    remote_client: &'a GenericRemoteStorage,
     target: &'a RootTarget,
 ) -> impl Stream<Item = anyhow::Result<TenantId>> + 'a {
-    try_stream! {
-        let mut continuation_token = None;
-        let tenants_target = target.tenants_root();
-        loop {
-            let fetch_response =
-                list_objects_with_retries(s3_client, &tenants_target, continuation_token.clone()).await?;
+    stream_tenant_dirs(remote_client, target)
+}
 
-            let new_entry_ids = fetch_response
-                .common_prefixes()
-                .iter()
-                .filter_map(|prefix| prefix.prefix())
-                .filter_map(|prefix| -> Option<&str> {
-                    prefix
-                        .strip_prefix(&tenants_target.prefix_in_bucket)?
-                        .strip_suffix('/')
-                }).map(|entry_id_str| {
-                entry_id_str
-                    .parse()
-                    .with_context(|| format!("Incorrect entry id str: {entry_id_str}"))
-            });
+/// Given a remote storage, output a stream of the tenant shards discovered via listing.
+/// Unlike with [`stream_tenants`], the directories of sharded tenants are parsed too.
+pub fn stream_tenant_shards<'a>(
+    remote_client: &'a GenericRemoteStorage,
+    target: &'a RootTarget,
+) -> impl Stream<Item = anyhow::Result<TenantShardId>> + 'a {
+    stream_tenant_dirs(remote_client, target)
+}
 
-            for i in new_entry_ids {
-                yield i?;
-            }
+fn stream_tenant_dirs<'a, T>(
+    remote_client: &'a GenericRemoteStorage,
+    target: &'a RootTarget,
+) -> impl Stream<Item = anyhow::Result<T>> + 'a
+where
+    T: std::str::FromStr + 'a,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    try_stream! {
+        let tenants_target = target.tenants_root();
+        let listing =
+            list_objects_with_retries(remote_client, &tenants_target, ListingMode::WithDelimiter)
+                .await?;
 
-            match fetch_response.next_continuation_token {
-                Some(new_token) => continuation_token = Some(new_token),
-                None => break,
-            }
+        for prefix in listing.prefixes {
+            yield parse_listed_id(&prefix)?;
         }
     }
 }
 
 /// Given a TenantId, output a stream of the timelines within that tenant, discovered
-/// using ListObjectsv2.  The listing is done before the stream is built, so that this
+/// using a listing.  The listing is done before the stream is built, so that this
 /// function can be used to generate concurrency on a stream using buffer_unordered.
 pub async fn stream_tenant_timelines<'a>(
-    s3_client: &'a Client,
+    remote_client: &'a GenericRemoteStorage,
     target: &'a RootTarget,
     tenant: TenantId,
 ) -> anyhow::Result<impl Stream<Item = Result<TenantTimelineId, anyhow::Error>> + 'a> {
-    let mut timeline_ids: Vec<Result<TimelineId, anyhow::Error>> = Vec::new();
-    let mut continuation_token = None;
-    let timelines_target = target.timelines_root(&tenant);
+    tracing::info!("Listing in {}", tenant);
+    let timeline_ids = list_timeline_ids(remote_client, &target.timelines_root(&tenant)).await;
 
-    loop {
-        tracing::info!("Listing in {}", tenant);
-        let fetch_response =
-            list_objects_with_retries(s3_client, &timelines_target, continuation_token.clone())
-                .await;
-        let fetch_response = match fetch_response {
-            Err(e) => {
-                timeline_ids.push(Err(e));
-                break;
-            }
-            Ok(r) => r,
-        };
-
-        let new_entry_ids = fetch_response
-            .common_prefixes()
-            .iter()
-            .filter_map(|prefix| prefix.prefix())
-            .filter_map(|prefix| -> Option<&str> {
-                prefix
-                    .strip_prefix(&timelines_target.prefix_in_bucket)?
-                    .strip_suffix('/')
-            })
-            .map(|entry_id_str| {
-                entry_id_str
-                    .parse::<TimelineId>()
-                    .with_context(|| format!("Incorrect entry id str: {entry_id_str}"))
-            });
-
-        for i in new_entry_ids {
-            timeline_ids.push(i);
+    tracing::info!("Yielding for {}", tenant);
+    Ok(stream! {
+        for i in timeline_ids {
+            let id = i?;
+            yield Ok(TenantTimelineId::new(tenant, id));
         }
+    })
+}
 
-        match fetch_response.next_continuation_token {
-            Some(new_token) => continuation_token = Some(new_token),
-            None => break,
-        }
-    }
+/// Like [`stream_tenant_timelines`], for the timelines of a single tenant shard.
+pub async fn stream_tenant_shard_timelines<'a>(
+    remote_client: &'a GenericRemoteStorage,
+    target: &'a RootTarget,
+    tenant_shard_id: TenantShardId,
+) -> anyhow::Result<impl Stream<Item = anyhow::Result<(TenantShardId, TimelineId)>> + 'a> {
+    tracing::info!("Listing in {}", tenant_shard_id);
+    let timelines_target = target.tenant_shard_timelines_root(&tenant_shard_id);
+    let timeline_ids = list_timeline_ids(remote_client, &timelines_target).await;
 
-    tracing::info!("Yielding for {}", tenant);
     Ok(stream! {
         for i in timeline_ids {
             let id = i?;
-            yield Ok(TenantTimelineId::new(tenant, id));
+            yield Ok((tenant_shard_id, id));
         }
     })
 }
 
+async fn list_timeline_ids(
+    remote_client: &GenericRemoteStorage,
+    timelines_target: &RemotePath,
+) -> Vec<anyhow::Result<TimelineId>> {
+    match list_objects_with_retries(remote_client, timelines_target, ListingMode::WithDelimiter)
+        .await
+    {
+        Err(e) => vec![Err(e)],
+        Ok(listing) => listing.prefixes.iter().map(parse_listed_id).collect(),
+    }
+}
+
+fn parse_listed_id<T>(prefix: &RemotePath) -> anyhow::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let entry_id_str = prefix
+        .object_name()
+        .with_context(|| format!("Listed prefix has no name: {prefix}"))?;
+    entry_id_str
+        .parse()
+        .with_context(|| format!("Incorrect entry id str: {entry_id_str}"))
+}
+
+/// List the objects (or the prefixes, in the [`ListingMode::WithDelimiter`] mode) under the
+/// given prefix.  Listings match the prefix as a string, so entries of the sibling prefixes
+/// sharing the same beginning, e.g. other shards' tenant directories, are filtered out here.
 pub(crate) fn stream_listing<'a>(
-    s3_client: &'a Client,
-    target: &'a S3Target,
-) -> impl Stream<Item = anyhow::Result<ObjectIdentifier>> + 'a {
+    remote_client: &'a GenericRemoteStorage,
+    prefix: &'a RemotePath,
+    mode: ListingMode,
+) -> impl Stream<Item = anyhow::Result<RemotePath>> + 'a {
     try_stream! {
-        let mut continuation_token = None;
-        loop {
-            let fetch_response =
-                list_objects_with_retries(s3_client, target, continuation_token.clone()).await?;
+        let with_delimiter = matches!(mode, ListingMode::WithDelimiter);
+        let listing = list_objects_with_retries(remote_client, prefix, mode).await?;
 
-            if target.delimiter.is_empty() {
-                for object_key in fetch_response.contents().iter().filter_map(|object| object.key())
-                {
-                    let object_id = ObjectIdentifier::builder().key(object_key).build()?;
-                    yield object_id;
-                }
+        let entries = if with_delimiter {
+            listing.prefixes
+        } else {
+            listing.keys.into_iter().map(|object| object.key).collect()
+        };
+        for entry in entries {
+            if entry.strip_prefix(prefix).is_ok() {
+                yield entry;
             } else {
-                for prefix in fetch_response.common_prefixes().iter().filter_map(|p| p.prefix()) {
-                    let object_id = ObjectIdentifier::builder().key(prefix).build()?;
-                    yield object_id;
-                }
+                tracing::debug!("Skipping {entry} outside of prefix {prefix}");
             }
+        }
+    }
+}
+
+/// List all the objects under the given prefix, with their modification times and sizes.
+pub(crate) fn stream_objects<'a>(
+    remote_client: &'a GenericRemoteStorage,
+    prefix: &'a RemotePath,
+) -> impl Stream<Item = anyhow::Result<ListingObject>> + 'a {
+    try_stream! {
+        let listing =
+            list_objects_with_retries(remote_client, prefix, ListingMode::NoDelimiter).await?;
 
-            match fetch_response.next_continuation_token {
-                Some(new_token) => continuation_token = Some(new_token),
-                None => break,
+        for object in listing.keys {
+            if object.key.strip_prefix(prefix).is_ok() {
+                yield object;
+            } else {
+                tracing::debug!("Skipping {} outside of prefix {prefix}", object.key);
             }
         }
     }
@@ -0,0 +1,250 @@
+//! Finding and purging orphan layers: layer objects of live timelines which are not referenced
+//! by any of the timeline's index generations.  These are left behind by uploads which were not
+//! followed by an index upload, e.g. when the pageserver was restarted or the tenant migrated.
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use futures_util::{pin_mut, StreamExt, TryStreamExt};
+use pageserver::tenant::storage_layer::LayerFileName;
+use pageserver::tenant::IndexPart;
+use pageserver_api::shard::TenantShardId;
+use remote_storage::{GenericRemoteStorage, RemotePath};
+use serde::{Deserialize, Serialize};
+use utils::generation::Generation;
+use utils::id::TimelineId;
+
+use crate::checks::parse_layer_object_name;
+use crate::garbage::do_delete;
+use crate::metadata_stream::{stream_objects, stream_tenant_shard_timelines, stream_tenant_shards};
+use crate::{download_object_with_retries, init_remote, BucketConfig, NodeKind, RootTarget};
+
+/// How many timelines to process in parallel.
+const CONCURRENCY: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct OrphanLayer {
+    tenant_shard_id: TenantShardId,
+    timeline_id: TimelineId,
+    key: RemotePath,
+    size: u64,
+    #[serde(with = "humantime_serde")]
+    last_modified: SystemTime,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OrphanLayerList {
+    /// Embed the identity of the bucket, so that the list can not be purged against the wrong
+    /// bucket, and so that the user does not have to re-state the bucket details when purging.
+    bucket_config: BucketConfig,
+
+    /// Layers modified more recently than this were not considered, as they might still be
+    /// referenced by an index upload that is in flight.
+    #[serde(with = "humantime_serde")]
+    min_age: Duration,
+
+    items: Vec<OrphanLayer>,
+
+    /// Advisory information to enable consumers to do a validation that if we see orphan
+    /// layers, we saw some referenced layers too.
+    referenced_layer_count: usize,
+}
+
+/// Orphan layers of a single timeline.
+struct TimelineOrphans {
+    orphans: Vec<OrphanLayer>,
+    referenced_layer_count: usize,
+}
+
+pub async fn find_orphan_layers(
+    bucket_config: BucketConfig,
+    min_age: Duration,
+    output_path: String,
+) -> anyhow::Result<()> {
+    let orphans = find_orphan_layers_inner(bucket_config, min_age).await?;
+    let serialized = serde_json::to_vec_pretty(&orphans)?;
+
+    tokio::fs::write(&output_path, &serialized).await?;
+
+    tracing::info!(
+        "Wrote {} orphan layers of {} bytes to {output_path}",
+        orphans.items.len(),
+        orphans.items.iter().map(|i| i.size).sum::<u64>()
+    );
+
+    Ok(())
+}
+
+async fn find_orphan_layers_inner(
+    bucket_config: BucketConfig,
+    min_age: Duration,
+) -> anyhow::Result<OrphanLayerList> {
+    let (remote_client, target) = init_remote(bucket_config.clone(), NodeKind::Pageserver)?;
+
+    tracing::info!("Finding orphan layers older than {min_age:?} in {bucket_config}...");
+    let tenant_shards = stream_tenant_shards(&remote_client, &target);
+    let timelines =
+        tenant_shards.map_ok(|t| stream_tenant_shard_timelines(&remote_client, &target, t));
+    let timelines = timelines.try_buffer_unordered(CONCURRENCY);
+    let timelines = timelines.try_flatten();
+
+    let timeline_orphans = timelines
+        .map_ok(|(tenant_shard_id, timeline_id)| {
+            find_timeline_orphans(
+                &remote_client,
+                &target,
+                tenant_shard_id,
+                timeline_id,
+                min_age,
+            )
+        })
+        .try_buffer_unordered(CONCURRENCY);
+    pin_mut!(timeline_orphans);
+
+    let mut list = OrphanLayerList {
+        bucket_config,
+        min_age,
+        items: Vec::new(),
+        referenced_layer_count: 0,
+    };
+    while let Some(result) = timeline_orphans.next().await {
+        let mut orphans = result?;
+        list.items.append(&mut orphans.orphans);
+        list.referenced_layer_count += orphans.referenced_layer_count;
+    }
+
+    tracing::info!(
+        "Found {} orphan layers, {} layers are referenced",
+        list.items.len(),
+        list.referenced_layer_count
+    );
+
+    Ok(list)
+}
+
+async fn find_timeline_orphans(
+    remote_client: &GenericRemoteStorage,
+    target: &RootTarget,
+    tenant_shard_id: TenantShardId,
+    timeline_id: TimelineId,
+    min_age: Duration,
+) -> anyhow::Result<TimelineOrphans> {
+    let timeline_root = target.tenant_shard_timeline_root(&tenant_shard_id, &timeline_id);
+    let mut result = TimelineOrphans {
+        orphans: Vec::new(),
+        referenced_layer_count: 0,
+    };
+
+    let mut index_parts = Vec::new();
+    let mut layers = Vec::new();
+    let objects = stream_objects(remote_client, &timeline_root);
+    pin_mut!(objects);
+    while let Some(object) = objects.next().await {
+        let object = object?;
+        let Ok(name) = object.key.strip_prefix(&timeline_root) else {
+            continue;
+        };
+        if name.as_str().starts_with(IndexPart::FILE_NAME) {
+            index_parts.push(object.key);
+        } else if let Ok((layer, generation)) = parse_layer_object_name(name.as_str()) {
+            layers.push((layer, generation, object));
+        }
+        // Anything else is not a layer, and is reported by scan-metadata
+    }
+
+    if index_parts.is_empty() {
+        // Without an index, the timeline is being created or deleted, leave it to find-garbage
+        tracing::warn!("Timeline {tenant_shard_id}/{timeline_id} has no index, skipping it");
+        return Ok(result);
+    }
+
+    // Any generation of the index might still be used: a pageserver attached in an older
+    // generation keeps serving from its index until it learns about the newer one.
+    let mut referenced: HashSet<(LayerFileName, Generation)> = HashSet::new();
+    for index_part_key in &index_parts {
+        let index_part_bytes = download_object_with_retries(remote_client, index_part_key)
+            .await
+            .context("index_part.json download")?;
+        let index_part: IndexPart = match serde_json::from_slice(&index_part_bytes) {
+            Ok(index_part) => index_part,
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to parse {index_part_key}, skipping timeline \
+                     {tenant_shard_id}/{timeline_id}: {e}"
+                );
+                return Ok(result);
+            }
+        };
+        referenced.extend(
+            index_part
+                .layer_metadata
+                .into_iter()
+                .map(|(name, metadata)| (name, metadata.generation)),
+        );
+    }
+    result.referenced_layer_count = referenced.len();
+
+    let now = SystemTime::now();
+    for (layer, generation, object) in layers {
+        if referenced.contains(&(layer, generation)) {
+            continue;
+        }
+        let age = now
+            .duration_since(object.last_modified)
+            .unwrap_or(Duration::ZERO);
+        if age < min_age {
+            tracing::info!(
+                "Skipping recently modified unreferenced layer {}",
+                object.key
+            );
+            continue;
+        }
+        tracing::debug!("Orphan layer {}", object.key);
+        result.orphans.push(OrphanLayer {
+            tenant_shard_id,
+            timeline_id,
+            key: object.key,
+            size: object.size,
+            last_modified: object.last_modified,
+        });
+    }
+
+    Ok(result)
+}
+
+pub async fn purge_orphan_layers(input_path: String, dry_run: bool) -> anyhow::Result<()> {
+    let list_bytes = tokio::fs::read(&input_path).await?;
+    let orphan_list = serde_json::from_slice::<OrphanLayerList>(&list_bytes)?;
+    tracing::info!(
+        "Loaded {} orphan layers from {}",
+        orphan_list.items.len(),
+        input_path
+    );
+
+    let (remote_client, target) = init_remote(orphan_list.bucket_config, NodeKind::Pageserver)?;
+
+    // Sanity checks on the incoming list
+    if orphan_list.referenced_layer_count == 0 && !orphan_list.items.is_empty() {
+        anyhow::bail!("Refusing to purge an orphan list that reports 0 referenced layers");
+    }
+    let mut objects_to_delete = Vec::with_capacity(orphan_list.items.len());
+    for item in orphan_list.items {
+        let timeline_root =
+            target.tenant_shard_timeline_root(&item.tenant_shard_id, &item.timeline_id);
+        anyhow::ensure!(
+            item.key.strip_prefix(&timeline_root).is_ok(),
+            "Refusing to purge {} outside of its timeline {}/{}",
+            item.key,
+            item.tenant_shard_id,
+            item.timeline_id
+        );
+        objects_to_delete.push(item.key);
+    }
+
+    do_delete(&remote_client, &mut objects_to_delete, dry_run, true).await?;
+
+    tracing::info!("Purged orphan layers");
+
+    Ok(())
+}
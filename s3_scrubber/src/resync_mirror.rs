@@ -0,0 +1,26 @@
+//! Resyncing the mirror of a remote storage: the pageservers and safekeepers only mirror their
+//! writes as they make them, and give up on the writes that keep failing, so an operator runs
+//! this after e.g. an outage of the mirror, limited to the prefix that was affected if possible.
+
+use anyhow::Context;
+use remote_storage::{GenericRemoteStorage, RemotePath, RemoteStorageConfig};
+use tokio_util::sync::CancellationToken;
+use toml_edit::Document;
+
+/// Makes the mirror of the storage configured by `remote_storage`, a TOML inline table like
+/// the `remote_storage` setting of the pageserver, catch up with the main storage under
+/// `prefix`.
+pub async fn resync_mirror(remote_storage: &str, prefix: Option<&str>) -> anyhow::Result<()> {
+    // An inline table is not a valid document on its own, so parse it as a value of a key.
+    let document = format!("remote_storage = {remote_storage}")
+        .parse::<Document>()
+        .context("parse the remote storage config")?;
+    let config = RemoteStorageConfig::from_toml(&document["remote_storage"])?
+        .context("no remote storage configured")?;
+    let storage = GenericRemoteStorage::from_config(&config)?;
+    let prefix = prefix.map(RemotePath::from_string).transpose()?;
+
+    storage
+        .resync_mirror(prefix.as_ref(), &CancellationToken::new())
+        .await
+}
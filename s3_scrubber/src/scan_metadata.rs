@@ -6,10 +6,10 @@ use crate::checks::{
 };
 use crate::metadata_stream::{stream_tenant_timelines, stream_tenants};
 use crate::{init_remote, BucketConfig, NodeKind, RootTarget};
-use aws_sdk_s3::Client;
 use futures_util::{pin_mut, StreamExt, TryStreamExt};
 use histogram::Histogram;
 use pageserver::tenant::IndexPart;
+use remote_storage::GenericRemoteStorage;
 use serde::Serialize;
 use utils::id::TenantTimelineId;
 
@@ -140,6 +140,10 @@ impl MetadataSummary {
         if !analysis.warnings.is_empty() {
             self.with_warnings.insert(*id);
         }
+
+        if !analysis.garbage_keys.is_empty() {
+            self.with_garbage.insert(*id);
+        }
     }
 
     /// Long-form output for printing at end of a scan
@@ -180,31 +184,31 @@ Timeline layer count: {6}
     }
 }
 
-/// Scan the pageserver metadata in an S3 bucket, reporting errors and statistics.
+/// Scan the pageserver metadata in a remote storage, reporting errors and statistics.
 pub async fn scan_metadata(bucket_config: BucketConfig) -> anyhow::Result<MetadataSummary> {
-    let (s3_client, target) = init_remote(bucket_config, NodeKind::Pageserver)?;
+    let (remote_client, target) = init_remote(bucket_config, NodeKind::Pageserver)?;
 
-    let tenants = stream_tenants(&s3_client, &target);
+    let tenants = stream_tenants(&remote_client, &target);
 
     // How many tenants to process in parallel.  We need to be mindful of pageservers
     // accessing the same per tenant prefixes, so use a lower setting than pageservers.
     const CONCURRENCY: usize = 32;
 
     // Generate a stream of TenantTimelineId
-    let timelines = tenants.map_ok(|t| stream_tenant_timelines(&s3_client, &target, t));
+    let timelines = tenants.map_ok(|t| stream_tenant_timelines(&remote_client, &target, t));
     let timelines = timelines.try_buffer_unordered(CONCURRENCY);
     let timelines = timelines.try_flatten();
 
     // Generate a stream of S3TimelineBlobData
     async fn report_on_timeline(
-        s3_client: &Client,
+        remote_client: &GenericRemoteStorage,
         target: &RootTarget,
         ttid: TenantTimelineId,
     ) -> anyhow::Result<(TenantTimelineId, S3TimelineBlobData)> {
-        let data = list_timeline_blobs(s3_client, ttid, target).await?;
+        let data = list_timeline_blobs(remote_client, ttid, target).await?;
         Ok((ttid, data))
     }
-    let timelines = timelines.map_ok(|ttid| report_on_timeline(&s3_client, &target, ttid));
+    let timelines = timelines.map_ok(|ttid| report_on_timeline(&remote_client, &target, ttid));
     let timelines = timelines.try_buffer_unordered(CONCURRENCY);
 
     let mut summary = MetadataSummary::new();
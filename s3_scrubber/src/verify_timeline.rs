@@ -0,0 +1,366 @@
+//! Deep verification of timelines: checks that the layers referenced by the remote index
+//! can serve reads at every LSN above the GC cutoff, and optionally that the layer objects
+//! contain what their names claim.
+
+use std::ops::Range;
+
+use camino::Utf8Path;
+use futures_util::{pin_mut, StreamExt, TryStreamExt};
+use pageserver::page_cache::PAGE_SZ;
+use pageserver::repository::Key;
+use pageserver::tenant::layer_map::{LayerMap, SearchResult};
+use pageserver::tenant::metadata::TimelineMetadata;
+use pageserver::tenant::remote_timeline_client::index::IndexLayerMetadata;
+use pageserver::tenant::storage_layer::{delta_layer, image_layer};
+use pageserver::tenant::storage_layer::{LayerFileName, PersistentLayerDesc};
+use pageserver::tenant::IndexPart;
+use pageserver::{DELTA_FILE_MAGIC, IMAGE_FILE_MAGIC, STORAGE_FORMAT_VERSION};
+use pageserver_api::shard::TenantShardId;
+use remote_storage::GenericRemoteStorage;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use utils::bin_ser::BeSer;
+use utils::id::{TenantTimelineId, TimelineId};
+use utils::lsn::Lsn;
+
+use crate::checks::{list_timeline_dir_blobs, BlobDataParseResult};
+use crate::metadata_stream::{stream_tenant_shard_timelines, stream_tenant_shards};
+use crate::{init_remote, BucketConfig, NodeKind, RootTarget};
+
+/// How many timelines to verify in parallel.
+const CONCURRENCY: usize = 32;
+
+/// How many layer summaries to download in parallel, per timeline.
+const LAYER_CONCURRENCY: usize = 8;
+
+/// Findings of a single timeline's verification.
+#[derive(Serialize)]
+pub struct TimelineVerification {
+    pub ttid: TenantTimelineId,
+    pub tenant_shard_id: TenantShardId,
+    pub layer_count: usize,
+    pub disk_consistent_lsn: Option<Lsn>,
+    pub gc_cutoff_lsn: Option<Lsn>,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl TimelineVerification {
+    fn new(tenant_shard_id: TenantShardId, timeline_id: TimelineId) -> Self {
+        Self {
+            ttid: TenantTimelineId::new(tenant_shard_id.tenant_id, timeline_id),
+            tenant_shard_id,
+            layer_count: 0,
+            disk_consistent_lsn: None,
+            gc_cutoff_lsn: None,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        !self.errors.is_empty()
+    }
+}
+
+/// Verify the given timelines, or all the timelines in the storage if none are given.  The
+/// timelines of sharded tenants are verified in each of the tenant's shards.
+pub async fn verify_timelines(
+    bucket_config: BucketConfig,
+    timelines: Vec<TenantTimelineId>,
+    check_layers: bool,
+) -> anyhow::Result<Vec<TimelineVerification>> {
+    let (remote_client, target) = init_remote(bucket_config, NodeKind::Pageserver)?;
+
+    let tenant_shards = stream_tenant_shards(&remote_client, &target);
+    let shard_timelines: Vec<(TenantShardId, TimelineId)> = if timelines.is_empty() {
+        let timelines =
+            tenant_shards.map_ok(|t| stream_tenant_shard_timelines(&remote_client, &target, t));
+        let timelines = timelines.try_buffer_unordered(CONCURRENCY);
+        timelines.try_flatten().try_collect().await?
+    } else {
+        let tenant_shards: Vec<TenantShardId> = tenant_shards.try_collect().await?;
+        timelines
+            .iter()
+            .flat_map(|ttid| {
+                tenant_shards
+                    .iter()
+                    .filter(|shard| shard.tenant_id == ttid.tenant_id)
+                    .map(|shard| (*shard, ttid.timeline_id))
+            })
+            .collect()
+    };
+
+    let verifications = futures_util::stream::iter(shard_timelines)
+        .map(|(tenant_shard_id, timeline_id)| {
+            verify_timeline(
+                &remote_client,
+                &target,
+                tenant_shard_id,
+                timeline_id,
+                check_layers,
+            )
+        })
+        .buffer_unordered(CONCURRENCY);
+    pin_mut!(verifications);
+
+    let mut result = Vec::new();
+    while let Some(verification) = verifications.next().await {
+        let verification = verification?;
+        if verification.is_fatal() {
+            tracing::warn!(
+                "Timeline {} of {} verification errors: {:?}",
+                verification.ttid.timeline_id,
+                verification.tenant_shard_id,
+                verification.errors
+            );
+        }
+        result.push(verification);
+    }
+
+    Ok(result)
+}
+
+async fn verify_timeline(
+    remote_client: &GenericRemoteStorage,
+    target: &RootTarget,
+    tenant_shard_id: TenantShardId,
+    timeline_id: TimelineId,
+    check_layers: bool,
+) -> anyhow::Result<TimelineVerification> {
+    tracing::info!("Verifying timeline {timeline_id} of {tenant_shard_id}");
+    let mut verification = TimelineVerification::new(tenant_shard_id, timeline_id);
+    let ttid = verification.ttid;
+
+    let timeline_root = target.tenant_shard_timeline_root(&tenant_shard_id, &timeline_id);
+    let data = list_timeline_dir_blobs(remote_client, &timeline_root).await?;
+    let index_part = match data.blob_data {
+        BlobDataParseResult::Parsed { index_part, .. } => index_part,
+        BlobDataParseResult::Relic => {
+            verification
+                .warnings
+                .push("Timeline is deleted, only its initdb archive is left".to_string());
+            return Ok(verification);
+        }
+        BlobDataParseResult::Incorrect(errors) => {
+            verification.errors.extend(
+                errors
+                    .into_iter()
+                    .map(|error| format!("parse error: {error}")),
+            );
+            return Ok(verification);
+        }
+    };
+
+    verify_index(&mut verification, &index_part);
+
+    if check_layers {
+        let checks = futures_util::stream::iter(index_part.layer_metadata.iter())
+            .map(|(name, metadata)| async {
+                check_layer_summary(remote_client, target, &ttid, name, metadata)
+                    .await
+                    .map_err(|e| format!("Layer {}: {e}", name.file_name()))
+            })
+            .buffer_unordered(LAYER_CONCURRENCY);
+        pin_mut!(checks);
+        while let Some(check) = checks.next().await {
+            if let Err(e) = check {
+                verification.errors.push(e);
+            }
+        }
+    }
+
+    Ok(verification)
+}
+
+/// Check the layers of the index for coverage holes.
+fn verify_index(verification: &mut TimelineVerification, index_part: &IndexPart) {
+    let metadata = &index_part.metadata;
+    let disk_consistent_lsn = metadata.disk_consistent_lsn();
+    verification.layer_count = index_part.layer_metadata.len();
+    verification.disk_consistent_lsn = Some(disk_consistent_lsn);
+    verification.gc_cutoff_lsn = Some(metadata.latest_gc_cutoff_lsn());
+
+    let mut layer_map = LayerMap::default();
+    let mut updates = layer_map.batch_update();
+    for (name, layer_metadata) in &index_part.layer_metadata {
+        let desc = PersistentLayerDesc::from_filename(
+            verification.tenant_shard_id,
+            verification.ttid.timeline_id,
+            name.clone(),
+            layer_metadata.file_size,
+        );
+        if desc.get_lsn_range().end > disk_consistent_lsn + 1 {
+            verification.warnings.push(format!(
+                "Layer {} is above disk_consistent_lsn {disk_consistent_lsn}",
+                name.file_name()
+            ));
+        }
+        updates.insert_historic(desc);
+    }
+    updates.flush();
+
+    for hole in find_coverage_holes(&layer_map, metadata) {
+        verification.errors.push(format!(
+            "No image layer under the deltas of keys {}..{} at LSNs {}..{}",
+            hole.key_range.start, hole.key_range.end, hole.lsn_range.start, hole.lsn_range.end
+        ));
+    }
+}
+
+/// A part of the key and LSN space, reads from which can not be served by the layers.
+struct CoverageHole {
+    key_range: Range<Key>,
+    lsn_range: Range<Lsn>,
+}
+
+/// Walks the layers like the page reconstruction does, for every key range with distinct
+/// layer coverage, from `disk_consistent_lsn` down.  Keys that no layer covers did not exist
+/// yet, and LSN ranges skipped between two layers did not modify the keys, so neither is a
+/// hole: a key range only has one when its chain of deltas does not lead down to an image.
+fn find_coverage_holes(layer_map: &LayerMap, metadata: &TimelineMetadata) -> Vec<CoverageHole> {
+    // Reads below the GC cutoff are not served
+    let lowest_read_lsn = metadata.latest_gc_cutoff_lsn();
+    let read_end_lsn = metadata.disk_consistent_lsn() + 1;
+    // Reads that find no image in a branch continue into the ancestor
+    let has_ancestor = metadata.ancestor_timeline().is_some();
+
+    // Layer coverage only changes at the layer key boundaries
+    let mut boundaries = layer_map
+        .iter_historic_layers()
+        .flat_map(|layer| {
+            let key_range = layer.get_key_range();
+            [key_range.start, key_range.end]
+        })
+        .collect::<Vec<_>>();
+    boundaries.sort();
+    boundaries.dedup();
+
+    let mut holes: Vec<CoverageHole> = Vec::new();
+    for key_range in boundaries.windows(2).map(|w| w[0]..w[1]) {
+        let mut end_lsn = read_end_lsn;
+        let mut lowest_delta_lsn = None;
+        let reaches_image = loop {
+            match layer_map.search(key_range.start, end_lsn) {
+                Some(SearchResult { layer, lsn_floor }) if layer.is_delta() => {
+                    lowest_delta_lsn = Some(layer.get_lsn_range().start);
+                    end_lsn = lsn_floor;
+                }
+                Some(_) => break true,
+                None => break false,
+            }
+        };
+        if reaches_image || has_ancestor {
+            continue;
+        }
+        // Reads below the deltas find no layers: the keys did not exist yet
+        let Some(lowest_delta_lsn) = lowest_delta_lsn else {
+            continue;
+        };
+        let lsn_range = std::cmp::max(lowest_delta_lsn, lowest_read_lsn)..read_end_lsn;
+        if lsn_range.is_empty() {
+            continue;
+        }
+
+        match holes.last_mut() {
+            Some(last) if last.key_range.end == key_range.start && last.lsn_range == lsn_range => {
+                last.key_range.end = key_range.end;
+            }
+            _ => holes.push(CoverageHole {
+                key_range,
+                lsn_range,
+            }),
+        }
+    }
+
+    holes
+}
+
+/// Layers keep the format version of the pageserver that wrote them, so any version up to
+/// ours is fine.  Version 0 was never written.
+fn check_format_version(format_version: u16) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        (1..=STORAGE_FORMAT_VERSION).contains(&format_version),
+        "unknown format version {format_version}, the newest known is {STORAGE_FORMAT_VERSION}"
+    );
+    Ok(())
+}
+
+/// Download the summary block of a layer and check it against the layer name.
+async fn check_layer_summary(
+    remote_client: &GenericRemoteStorage,
+    target: &RootTarget,
+    ttid: &TenantTimelineId,
+    name: &LayerFileName,
+    metadata: &IndexLayerMetadata,
+) -> anyhow::Result<()> {
+    // Layers inherited from before a shard split are stored under the parent shard
+    let layer_shard_id = TenantShardId {
+        tenant_id: ttid.tenant_id,
+        shard_number: metadata.shard.shard_number,
+        shard_count: metadata.shard.shard_count,
+    };
+    let timeline_root = target.tenant_shard_timeline_root(&layer_shard_id, &ttid.timeline_id);
+    let path = timeline_root.join(Utf8Path::new(&format!(
+        "{}{}",
+        name.file_name(),
+        metadata.generation.get_suffix()
+    )));
+    let download = remote_client
+        .download_byte_range(&path, 0, Some(PAGE_SZ as u64), &CancellationToken::new())
+        .await?;
+    let summary_blk = download
+        .download_stream
+        .map_ok(|chunk| chunk.to_vec())
+        .try_concat()
+        .await?;
+
+    let index_start_blk = match name {
+        LayerFileName::Image(name) => {
+            let actual = image_layer::Summary::des_prefix(&summary_blk)?;
+            check_format_version(actual.format_version)?;
+            let expected = image_layer::Summary {
+                magic: IMAGE_FILE_MAGIC,
+                format_version: actual.format_version,
+                tenant_id: ttid.tenant_id,
+                timeline_id: ttid.timeline_id,
+                key_range: name.key_range.clone(),
+                lsn: name.lsn,
+                index_start_blk: actual.index_start_blk,
+                index_root_blk: actual.index_root_blk,
+            };
+            anyhow::ensure!(
+                actual == expected,
+                "summary mismatch, expected {expected:?}, got {actual:?}"
+            );
+            actual.index_start_blk
+        }
+        LayerFileName::Delta(name) => {
+            let actual = delta_layer::Summary::des_prefix(&summary_blk)?;
+            check_format_version(actual.format_version)?;
+            let expected = delta_layer::Summary {
+                magic: DELTA_FILE_MAGIC,
+                format_version: actual.format_version,
+                tenant_id: ttid.tenant_id,
+                timeline_id: ttid.timeline_id,
+                key_range: name.key_range.clone(),
+                lsn_range: name.lsn_range.clone(),
+                index_start_blk: actual.index_start_blk,
+                index_root_blk: actual.index_root_blk,
+            };
+            anyhow::ensure!(
+                actual == expected,
+                "summary mismatch, expected {expected:?}, got {actual:?}"
+            );
+            actual.index_start_blk
+        }
+    };
+
+    anyhow::ensure!(
+        (index_start_blk as u64) * (PAGE_SZ as u64) < metadata.file_size,
+        "index block {index_start_blk} is beyond the layer size {}",
+        metadata.file_size
+    );
+
+    Ok(())
+}
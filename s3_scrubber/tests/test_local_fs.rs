@@ -0,0 +1,470 @@
+//! Runs the scrubber commands against a synthetic pageserver layout in a local
+//! filesystem remote storage.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use camino_tempfile::Utf8TempDir;
+use pageserver::tenant::metadata::TimelineMetadata;
+use pageserver::tenant::remote_timeline_client::index::LayerFileMetadata;
+use pageserver::tenant::storage_layer::LayerFileName;
+use pageserver::tenant::IndexPart;
+use pageserver_api::shard::ShardIndex;
+use s3_scrubber::garbage::{purge_garbage, PurgeMode};
+use s3_scrubber::orphan_layers::{find_orphan_layers, purge_orphan_layers};
+use s3_scrubber::scan_metadata::scan_metadata;
+use s3_scrubber::verify_timeline::verify_timelines;
+use s3_scrubber::BucketConfig;
+use utils::generation::Generation;
+use utils::id::{TenantId, TenantTimelineId, TimelineId};
+use utils::lsn::Lsn;
+
+const IMAGE_LAYER: &str =
+    "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070";
+const DELTA_LAYER: &str = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9";
+/// Deltas over the same LSNs as [`DELTA_LAYER`], with a gap between their key ranges
+const LOW_DELTA_LAYER: &str = "000000000000000000000000000000000000-010000000000000000000000000000000000__0000000001696070-00000000016960E9";
+const HIGH_DELTA_LAYER: &str = "020000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__0000000001696070-00000000016960E9";
+
+fn timeline_path(root: &Utf8Path, ttid: &TenantTimelineId) -> Utf8PathBuf {
+    root.join("tenants")
+        .join(ttid.tenant_id.to_string())
+        .join("timelines")
+        .join(ttid.timeline_id.to_string())
+}
+
+fn write_file(path: &Utf8Path, contents: &[u8]) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
+
+/// Writes a timeline with an index of the given generation, referencing `indexed_layers`,
+/// and `present_layers` layer objects next to it.
+fn write_timeline(
+    root: &Utf8Path,
+    ttid: &TenantTimelineId,
+    index_generation: Generation,
+    indexed_layers: &[(&str, Generation)],
+    present_layers: &[(&str, Generation)],
+) {
+    let timeline_path = timeline_path(root, ttid);
+    let disk_consistent_lsn = Lsn(0x16960E8);
+
+    let layers = indexed_layers
+        .iter()
+        .map(|(name, generation)| {
+            let name = name.parse::<LayerFileName>().unwrap();
+            let metadata = LayerFileMetadata::new(4096, *generation, ShardIndex::unsharded());
+            (name, metadata)
+        })
+        .collect::<HashMap<_, _>>();
+    let metadata = TimelineMetadata::new(
+        disk_consistent_lsn,
+        None,
+        None,
+        Lsn(0),
+        Lsn(0x1696070),
+        Lsn(0x1696070),
+        15,
+    );
+    let index_part = IndexPart::new(layers, disk_consistent_lsn, metadata);
+    write_file(
+        &timeline_path.join(format!(
+            "{}{}",
+            IndexPart::FILE_NAME,
+            index_generation.get_suffix()
+        )),
+        &index_part.to_s3_bytes().unwrap(),
+    );
+
+    for (name, generation) in present_layers {
+        write_file(
+            &timeline_path.join(format!("{name}{}", generation.get_suffix())),
+            &[0; 4096],
+        );
+    }
+}
+
+/// Turns the tenant into the first of two shards of a sharded tenant, returns the shard id.
+fn make_shard(root: &Utf8Path, tenant_id: &TenantId) -> String {
+    let tenants_path = root.join("tenants");
+    let shard_id = format!("{tenant_id}-0102");
+    std::fs::rename(
+        tenants_path.join(tenant_id.to_string()),
+        tenants_path.join(&shard_id),
+    )
+    .unwrap();
+    shard_id
+}
+
+fn timeline_set(summary: &serde_json::Value, field: &str) -> HashSet<TenantTimelineId> {
+    serde_json::from_value(summary[field].clone()).unwrap()
+}
+
+#[tokio::test]
+async fn scan_metadata_reports_errors_and_garbage() {
+    let root = Utf8TempDir::new().unwrap();
+    let root_path = root.path();
+
+    let healthy = TenantTimelineId::generate();
+    write_timeline(
+        root_path,
+        &healthy,
+        Generation::new(1),
+        &[(IMAGE_LAYER, Generation::new(1))],
+        &[(IMAGE_LAYER, Generation::new(1))],
+    );
+
+    // The delta layer is left behind from an older generation
+    let with_garbage = TenantTimelineId::new(healthy.tenant_id, TimelineId::generate());
+    write_timeline(
+        root_path,
+        &with_garbage,
+        Generation::new(2),
+        &[(IMAGE_LAYER, Generation::new(2))],
+        &[
+            (IMAGE_LAYER, Generation::new(2)),
+            (DELTA_LAYER, Generation::new(1)),
+        ],
+    );
+
+    // The index references a layer that is not in the storage
+    let with_errors = TenantTimelineId::generate();
+    write_timeline(
+        root_path,
+        &with_errors,
+        Generation::new(1),
+        &[
+            (IMAGE_LAYER, Generation::new(1)),
+            (DELTA_LAYER, Generation::new(1)),
+        ],
+        &[(IMAGE_LAYER, Generation::new(1))],
+    );
+
+    let summary = scan_metadata(BucketConfig::LocalFs {
+        path: root_path.to_owned(),
+    })
+    .await
+    .unwrap();
+    assert!(summary.is_fatal());
+    assert!(!summary.is_empty());
+
+    let summary = serde_json::to_value(&summary).unwrap();
+    assert_eq!(summary["count"], 3);
+    assert_eq!(
+        timeline_set(&summary, "with_errors"),
+        HashSet::from([with_errors])
+    );
+    assert_eq!(
+        timeline_set(&summary, "with_garbage"),
+        HashSet::from([with_garbage])
+    );
+}
+
+#[tokio::test]
+async fn scan_metadata_of_empty_storage() {
+    let root = Utf8TempDir::new().unwrap();
+
+    let summary = scan_metadata(BucketConfig::LocalFs {
+        path: root.path().to_owned(),
+    })
+    .await
+    .unwrap();
+    assert!(summary.is_empty());
+    assert!(!summary.is_fatal());
+}
+
+#[tokio::test]
+async fn verify_timeline_finds_coverage_holes() {
+    let root = Utf8TempDir::new().unwrap();
+    let root_path = root.path();
+    let bucket_config = BucketConfig::LocalFs {
+        path: root_path.to_owned(),
+    };
+
+    // The delta covers everything between the image and disk_consistent_lsn
+    let complete = TenantTimelineId::generate();
+    let layers = [
+        (IMAGE_LAYER, Generation::new(1)),
+        (DELTA_LAYER, Generation::new(1)),
+    ];
+    write_timeline(root_path, &complete, Generation::new(1), &layers, &layers);
+
+    // The keys between the deltas were not modified after the image, in a sharded tenant
+    let with_key_gap = TenantTimelineId::generate();
+    let layers = [
+        (IMAGE_LAYER, Generation::new(1)),
+        (LOW_DELTA_LAYER, Generation::new(1)),
+        (HIGH_DELTA_LAYER, Generation::new(1)),
+    ];
+    write_timeline(
+        root_path,
+        &with_key_gap,
+        Generation::new(1),
+        &layers,
+        &layers,
+    );
+    let shard_id = make_shard(root_path, &with_key_gap.tenant_id);
+
+    // The delta has no image under it to apply to
+    let with_hole = TenantTimelineId::generate();
+    let layers = [(DELTA_LAYER, Generation::new(1))];
+    write_timeline(root_path, &with_hole, Generation::new(1), &layers, &layers);
+
+    let verifications = verify_timelines(bucket_config.clone(), Vec::new(), false)
+        .await
+        .unwrap();
+    assert_eq!(verifications.len(), 3);
+    for verification in verifications {
+        if verification.ttid == complete {
+            assert_eq!(verification.layer_count, 2);
+            assert!(!verification.is_fatal(), "{:?}", verification.errors);
+        } else if verification.ttid == with_key_gap {
+            assert_eq!(verification.tenant_shard_id.to_string(), shard_id);
+            assert_eq!(verification.layer_count, 3);
+            assert!(!verification.is_fatal(), "{:?}", verification.errors);
+        } else {
+            assert_eq!(verification.ttid, with_hole);
+            assert_eq!(verification.layer_count, 1);
+            assert_eq!(verification.errors.len(), 1);
+            assert!(
+                verification.errors[0].starts_with("No image layer under the deltas of keys"),
+                "{}",
+                verification.errors[0]
+            );
+        }
+    }
+
+    // The layer objects are zeroes, so their summaries do not match the names
+    let verifications = verify_timelines(bucket_config, vec![complete], true)
+        .await
+        .unwrap();
+    assert_eq!(verifications.len(), 1);
+    assert_eq!(verifications[0].errors.len(), 2);
+}
+
+#[tokio::test]
+async fn purge_garbage_removes_listed_entities() {
+    let root = Utf8TempDir::new().unwrap();
+    let root_path = root.path();
+
+    let layers = [(IMAGE_LAYER, Generation::new(1))];
+    let deleted_tenant = TenantTimelineId::generate();
+    let missing_tenant = TenantTimelineId::generate();
+    let active = TenantTimelineId::generate();
+    let deleted_timeline = TenantTimelineId::new(active.tenant_id, TimelineId::generate());
+    for ttid in [deleted_tenant, missing_tenant, active, deleted_timeline] {
+        write_timeline(root_path, &ttid, Generation::new(1), &layers, &layers);
+    }
+
+    // A directory sharing the deleted tenant's id as a prefix must not be touched
+    let sibling = root_path
+        .join("tenants")
+        .join(format!("{}-0104", deleted_tenant.tenant_id))
+        .join("unrelated");
+    write_file(&sibling, b"data");
+
+    let garbage_list = serde_json::json!({
+        "node_kind": "Pageserver",
+        "bucket_config": { "LocalFs": { "path": root_path } },
+        "items": [
+            {
+                "entity": { "Tenant": deleted_tenant.tenant_id },
+                "reason": "DeletedInConsole",
+            },
+            {
+                "entity": { "Tenant": missing_tenant.tenant_id },
+                "reason": "MissingInConsole",
+            },
+            {
+                "entity": { "Timeline": deleted_timeline },
+                "reason": "DeletedInConsole",
+            },
+        ],
+        "active_tenant_count": 1,
+    });
+    let garbage_path = root_path.join("garbage.json");
+    std::fs::write(&garbage_path, serde_json::to_vec(&garbage_list).unwrap()).unwrap();
+
+    let files_of = |tenant_id: TenantId| -> usize {
+        walk_files(&root_path.join("tenants").join(tenant_id.to_string())).len()
+    };
+    let files_before = [deleted_tenant, missing_tenant, active].map(|t| files_of(t.tenant_id));
+
+    // Dry run leaves everything in place
+    purge_garbage(garbage_path.to_string(), PurgeMode::DeletedOnly, None, true)
+        .await
+        .unwrap();
+    assert_eq!(
+        [deleted_tenant, missing_tenant, active].map(|t| files_of(t.tenant_id)),
+        files_before
+    );
+
+    purge_garbage(
+        garbage_path.to_string(),
+        PurgeMode::DeletedOnly,
+        None,
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(files_of(deleted_tenant.tenant_id), 0);
+    assert_eq!(
+        files_of(missing_tenant.tenant_id),
+        files_before[1],
+        "tenants missing in console are only purged in the deleted-and-missing mode"
+    );
+    assert!(walk_files(&timeline_path(root_path, &deleted_timeline)).is_empty());
+    assert!(!walk_files(&timeline_path(root_path, &active)).is_empty());
+    assert!(sibling.exists());
+
+    purge_garbage(
+        garbage_path.to_string(),
+        PurgeMode::DeletedAndMissing,
+        None,
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(files_of(missing_tenant.tenant_id), 0);
+    assert!(!walk_files(&timeline_path(root_path, &active)).is_empty());
+}
+
+#[tokio::test]
+async fn orphan_layers_are_found_and_purged() {
+    let root = Utf8TempDir::new().unwrap();
+    let root_path = root.path();
+    let bucket_config = BucketConfig::LocalFs {
+        path: root_path.to_owned(),
+    };
+
+    // The image layer is only referenced by the older index generation, the delta layer of the
+    // first generation and the image layer of the third one are not referenced at all.
+    let ttid = TenantTimelineId::generate();
+    write_timeline(
+        root_path,
+        &ttid,
+        Generation::new(1),
+        &[(IMAGE_LAYER, Generation::new(1))],
+        &[
+            (IMAGE_LAYER, Generation::new(1)),
+            (DELTA_LAYER, Generation::new(1)),
+        ],
+    );
+    write_timeline(
+        root_path,
+        &ttid,
+        Generation::new(2),
+        &[(DELTA_LAYER, Generation::new(2))],
+        &[
+            (DELTA_LAYER, Generation::new(2)),
+            (IMAGE_LAYER, Generation::new(3)),
+        ],
+    );
+
+    // The same for a shard of a sharded tenant
+    let sharded = TenantTimelineId::generate();
+    write_timeline(
+        root_path,
+        &sharded,
+        Generation::new(1),
+        &[(IMAGE_LAYER, Generation::new(1))],
+        &[
+            (IMAGE_LAYER, Generation::new(1)),
+            (DELTA_LAYER, Generation::new(1)),
+        ],
+    );
+    let shard_id = make_shard(root_path, &sharded.tenant_id);
+
+    // Without an index, nothing is known to be referenced
+    let without_index = TenantTimelineId::generate();
+    write_file(
+        &timeline_path(root_path, &without_index).join(IMAGE_LAYER),
+        &[0; 4096],
+    );
+
+    let list_path = root_path.join("orphans.json");
+    let find_orphans = |min_age| {
+        let bucket_config = bucket_config.clone();
+        let list_path = list_path.to_string();
+        async move {
+            find_orphan_layers(bucket_config, min_age, list_path.clone())
+                .await
+                .unwrap();
+            let list: serde_json::Value =
+                serde_json::from_slice(&std::fs::read(list_path).unwrap()).unwrap();
+            list["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["key"].as_str().unwrap().to_string())
+                .collect::<HashSet<_>>()
+        }
+    };
+
+    let recent = find_orphans(Duration::from_secs(24 * 3600)).await;
+    assert!(recent.is_empty(), "{recent:?}");
+
+    let orphans = find_orphans(Duration::ZERO).await;
+    let timeline_key = |name: &str, generation: Generation| {
+        format!(
+            "tenants/{}/timelines/{}/{name}{}",
+            ttid.tenant_id,
+            ttid.timeline_id,
+            generation.get_suffix()
+        )
+    };
+    let shard_timeline_key = |name: &str| {
+        format!(
+            "tenants/{shard_id}/timelines/{}/{name}{}",
+            sharded.timeline_id,
+            Generation::new(1).get_suffix()
+        )
+    };
+    assert_eq!(
+        orphans,
+        HashSet::from([
+            timeline_key(DELTA_LAYER, Generation::new(1)),
+            timeline_key(IMAGE_LAYER, Generation::new(3)),
+            shard_timeline_key(DELTA_LAYER),
+        ])
+    );
+
+    let storage_files = || {
+        walk_files(&root_path.join("tenants"))
+            .into_iter()
+            .map(|path| path.strip_prefix(root_path).unwrap().to_string())
+            .collect::<HashSet<_>>()
+    };
+    let files_before = storage_files();
+    purge_orphan_layers(list_path.to_string(), true)
+        .await
+        .unwrap();
+    assert_eq!(storage_files(), files_before);
+
+    purge_orphan_layers(list_path.to_string(), false)
+        .await
+        .unwrap();
+    let files_after = storage_files();
+    assert_eq!(files_after.len(), files_before.len() - 3);
+    assert!(files_after.is_disjoint(&orphans));
+    assert!(files_after.contains(&timeline_key(IMAGE_LAYER, Generation::new(1))));
+    assert!(files_after.contains(&shard_timeline_key(IMAGE_LAYER)));
+}
+
+fn walk_files(dir: &Utf8Path) -> Vec<Utf8PathBuf> {
+    let mut files = Vec::new();
+    let Ok(entries) = dir.read_dir_utf8() else {
+        return files;
+    };
+    for entry in entries {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            files.extend(walk_files(entry.path()));
+        } else {
+            files.push(entry.path().to_owned());
+        }
+    }
+    files
+}
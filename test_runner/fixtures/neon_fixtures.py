@@ -19,7 +19,7 @@ from functools import cached_property
 from itertools import chain, product
 from pathlib import Path
 from types import TracebackType
-from typing import Any, Dict, Iterator, List, Optional, Tuple, Type, cast
+from typing import Any, Dict, Iterator, List, Optional, Tuple, Type, Union, cast
 from urllib.parse import urlparse
 
 import asyncpg
@@ -52,6 +52,7 @@ from fixtures.pageserver.utils import wait_for_last_record_lsn, wait_for_upload
 from fixtures.pg_version import PgVersion
 from fixtures.port_distributor import PortDistributor
 from fixtures.remote_storage import (
+    LocalFsStorage,
     MockS3Server,
     RemoteStorage,
     RemoteStorageKind,
@@ -552,8 +553,11 @@ class NeonEnvBuilder:
             repo_dir / "local_fs_remote_storage", self.repo_dir / "local_fs_remote_storage"
         )
 
-        if (attachments_json := Path(repo_dir / "attachments.json")).exists():
-            shutil.copyfile(attachments_json, self.repo_dir / attachments_json.name)
+        # Snapshots from before the attachment service had a database have its state in
+        # attachments.json instead, which the service imports on its first start.
+        for attachments_file in ("attachments.db", "attachments.json"):
+            if (attachments_path := Path(repo_dir / attachments_file)).exists():
+                shutil.copyfile(attachments_path, self.repo_dir / attachments_file)
 
         # Update the config with info about tenants and timelines
         with (self.repo_dir / "config").open("r") as f:
@@ -575,11 +579,9 @@ class NeonEnvBuilder:
         the test didn't produce any invalid remote state.
         """
 
-        if not isinstance(self.pageserver_remote_storage, S3Storage):
-            # The scrubber can't talk to e.g. LocalFS -- it needs
-            # an HTTP endpoint (mock is fine) to connect to.
+        if not isinstance(self.pageserver_remote_storage, (S3Storage, LocalFsStorage)):
             raise RuntimeError(
-                "Cannot scrub with remote_storage={self.pageserver_remote_storage}, require an S3 endpoint"
+                f"Cannot scrub with remote_storage={self.pageserver_remote_storage}, require S3 or local fs storage"
             )
 
         self.scrub_on_exit = True
@@ -1188,9 +1190,13 @@ class NeonCli(AbstractNeonCli):
         timeline_id: Optional[TimelineId] = None,
         conf: Optional[Dict[str, str]] = None,
         set_default: bool = False,
+        shard_count: Optional[int] = None,
     ) -> Tuple[TenantId, TimelineId]:
         """
         Creates a new tenant, returns its id and its initial timeline's id.
+
+        With a `shard_count`, the attachment service places the tenant's shards across the
+        environment's pageservers.
         """
         tenant_id = tenant_id or TenantId.generate()
         timeline_id = timeline_id or TimelineId.generate()
@@ -1213,6 +1219,8 @@ class NeonCli(AbstractNeonCli):
             )
         if set_default:
             args.append("--set-default")
+        if shard_count is not None:
+            args.extend(["--shard-count", str(shard_count)])
 
         res = self.raw_cli(args)
         res.check_returncode()
@@ -1354,6 +1362,24 @@ class NeonCli(AbstractNeonCli):
             res.check_returncode()
             return res
 
+    def apply(
+        self,
+        config: Dict[str, Any],
+        dry_run: bool = False,
+    ) -> "subprocess.CompletedProcess[str]":
+        """
+        Converge the environment to a description of its services, tenants, branches and
+        endpoints, see control_plane/example_env.toml for the format.
+        """
+        with tempfile.NamedTemporaryFile(mode="w+") as tmp:
+            tmp.write(toml.dumps(config))
+            tmp.flush()
+
+            cmd = ["apply", tmp.name, "--pg-version", self.env.pg_version]
+            if dry_run:
+                cmd.append("--dry-run")
+            return self.raw_cli(cmd)
+
     def attachment_service_start(self):
         cmd = ["attachment_service", "start"]
         return self.raw_cli(cmd)
@@ -1629,6 +1655,52 @@ class NeonAttachmentService:
         else:
             return None
 
+    def node_list(self) -> List[Dict[str, Any]]:
+        response = requests.get(f"{self.env.control_plane_api}/node")
+        response.raise_for_status()
+        return response.json()
+
+    def node_configure(self, node_id: int, availability: str):
+        response = requests.put(
+            f"{self.env.control_plane_api}/node/{node_id}/config",
+            json={"node_id": node_id, "availability": availability},
+        )
+        response.raise_for_status()
+
+    def tenant_create(
+        self,
+        tenant_id: TenantId,
+        shard_count: int = 0,
+        placement_policy: Union[str, Dict[str, Any]] = "Single",
+    ) -> Dict[str, Any]:
+        response = requests.post(
+            f"{self.env.control_plane_api}/tenant",
+            json={
+                "new_tenant_id": str(tenant_id),
+                "shard_count": shard_count,
+                "placement_policy": placement_policy,
+            },
+        )
+        response.raise_for_status()
+        json = response.json()
+        log.info(f"tenant_create({tenant_id}): {json}")
+        return json
+
+    def locate(self, tenant_id: TenantId) -> List[Dict[str, Any]]:
+        response = requests.get(f"{self.env.control_plane_api}/tenant/{tenant_id}/locate")
+        response.raise_for_status()
+        return response.json()["shards"]
+
+    def tenant_shard_split(self, tenant_id: TenantId, shard_count: int) -> List[str]:
+        response = requests.put(
+            f"{self.env.control_plane_api}/tenant/{tenant_id}/shard_split",
+            json={"new_shard_count": shard_count},
+        )
+        response.raise_for_status()
+        new_shards = response.json()["new_shards"]
+        log.info(f"tenant_shard_split({tenant_id}, {shard_count}): {new_shards}")
+        return new_shards
+
     def __enter__(self) -> "NeonAttachmentService":
         return self
 
@@ -3089,19 +3161,23 @@ class S3Scrubber:
         self.log_dir = log_dir
 
     def scrubber_cli(self, args: list[str], timeout) -> str:
-        assert isinstance(self.env.pageserver_remote_storage, S3Storage)
-        s3_storage = self.env.pageserver_remote_storage
-
-        env = {
-            "REGION": s3_storage.bucket_region,
-            "BUCKET": s3_storage.bucket_name,
-            "BUCKET_PREFIX": s3_storage.prefix_in_bucket,
-            "RUST_LOG": "DEBUG",
-        }
-        env.update(s3_storage.access_env_vars())
+        remote_storage = self.env.pageserver_remote_storage
+        env = {"RUST_LOG": "DEBUG"}
+        if isinstance(remote_storage, LocalFsStorage):
+            env["LOCAL_PATH"] = str(remote_storage.root)
+        else:
+            assert isinstance(remote_storage, S3Storage)
+            env.update(
+                {
+                    "REGION": remote_storage.bucket_region,
+                    "BUCKET": remote_storage.bucket_name,
+                    "BUCKET_PREFIX": remote_storage.prefix_in_bucket,
+                }
+            )
+            env.update(remote_storage.access_env_vars())
 
-        if s3_storage.endpoint is not None:
-            env.update({"AWS_ENDPOINT_URL": s3_storage.endpoint})
+            if remote_storage.endpoint is not None:
+                env.update({"AWS_ENDPOINT_URL": remote_storage.endpoint})
 
         base_args = [str(self.env.neon_binpath / "s3_scrubber")]
         args = base_args + args
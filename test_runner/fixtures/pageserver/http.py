@@ -4,7 +4,7 @@ import json
 import time
 from collections import defaultdict
 from dataclasses import dataclass
-from typing import Any, Dict, List, Optional, Set, Tuple
+from typing import Any, Dict, List, Optional, Set, Tuple, Union
 
 import requests
 from requests.adapters import HTTPAdapter
@@ -294,6 +294,23 @@ class PageserverHttpClient(requests.Session):
         )
         self.verbose_error(res)
 
+    def tenant_shard_split(
+        self, tenant_shard_id: Union[TenantId, str], shard_count: int
+    ) -> List[str]:
+        """
+        Split a tenant shard into shards with `shard_count`, returning the new shards' IDs.
+        Unsharded tenants are addressed by their TenantId, sharded ones by a string like
+        `<tenant_id>-0102`.
+        """
+        res = self.put(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_shard_id}/shard_split",
+            json={"new_shard_count": shard_count},
+        )
+        self.verbose_error(res)
+        new_shards = res.json()["new_shards"]
+        assert isinstance(new_shards, list)
+        return new_shards
+
     def tenant_delete(self, tenant_id: TenantId):
         res = self.delete(f"http://localhost:{self.port}/v1/tenant/{tenant_id}")
         self.verbose_error(res)
@@ -378,7 +395,7 @@ class PageserverHttpClient(requests.Session):
 
     def timeline_list(
         self,
-        tenant_id: TenantId,
+        tenant_id: Union[TenantId, str],
         include_non_incremental_logical_size: bool = False,
         include_timeline_dir_layer_file_size_sum: bool = False,
     ) -> List[Dict[str, Any]]:
@@ -614,6 +631,64 @@ class PageserverHttpClient(requests.Session):
                 assert completed["successful_download_count"] > 0
             return completed
 
+    def timeline_import_basebackup(
+        self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        base_tar: str,
+        base_lsn: Lsn,
+        pg_version: PgVersion,
+        skip_checksum_verification: bool = False,
+    ) -> dict[str, Any]:
+        body = {
+            "source": {"kind": "LocalPath", "path": base_tar},
+            "base_lsn": str(base_lsn),
+            "pg_version": int(pg_version),
+            "skip_checksum_verification": skip_checksum_verification,
+        }
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/import_basebackup",
+            json=body,
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_import_wal(
+        self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        wal_tar: str,
+        end_lsn: Lsn,
+        start_lsn: Optional[Lsn] = None,
+    ) -> dict[str, Any]:
+        body: Dict[str, Any] = {
+            "source": {"kind": "LocalPath", "path": wal_tar},
+            "end_lsn": str(end_lsn),
+        }
+        if start_lsn is not None:
+            body["start_lsn"] = str(start_lsn)
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/import_wal",
+            json=body,
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_import_status(
+        self, tenant_id: TenantId, timeline_id: TimelineId
+    ) -> dict[str, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/import",
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def get_metrics_str(self) -> str:
         """You probably want to use get_metrics() instead."""
         res = self.get(f"http://localhost:{self.port}/metrics")
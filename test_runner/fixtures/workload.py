@@ -44,7 +44,7 @@ class Workload:
             self._endpoint.reconfigure(pageserver_id=pageserver_id)
 
         connstring = self._endpoint.safe_psql(
-            "SELECT setting FROM pg_settings WHERE name='neon.pageserver_connstring'"
+            "SELECT name, setting FROM pg_settings WHERE name LIKE 'neon.pageserver_connstring%'"
         )
         log.info(f"Workload.endpoint: connstr={connstring}")
 
@@ -166,6 +166,11 @@ def test_fully_custom_config(positive_env: NeonEnv):
         "heatmap_period": "10m",
         "image_creation_threshold": 7,
         "pitr_interval": "1m",
+        "remote_storage_throttle": {
+            "on_demand_download": {"ops_per_second": 100},
+            "upload": {"bytes_per_second": 10485760},
+            "background_download": {"ops_per_second": 10, "bytes_per_second": 1048576},
+        },
         "lagging_wal_timeout": "23m",
         "max_lsn_wal_lag": 230000,
         "min_resident_size_override": 23,
@@ -0,0 +1,228 @@
+import pytest
+import requests
+from fixtures.log_helper import log
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.pageserver.utils import wait_for_upload_queue_empty
+from fixtures.remote_storage import RemoteStorageKind
+from fixtures.types import TenantId, TimelineId
+from fixtures.utils import wait_until
+
+
+def test_attachment_service_node_failure(neon_env_builder: NeonEnvBuilder):
+    """
+    When a pageserver stops responding, the attachment service marks it offline and
+    re-attaches its tenants elsewhere with a new generation, promoting the secondary
+    location.  The old generation is fenced off, and the compute is pointed at the new
+    location.
+    """
+    neon_env_builder.num_pageservers = 3
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    assert env.attachment_service is not None
+    attachment_service = env.attachment_service
+
+    tenant_id = TenantId.generate()
+    timeline_id = TimelineId.generate()
+    attachment_service.tenant_create(tenant_id, placement_policy={"Double": 1})
+
+    (origin_shard,) = attachment_service.locate(tenant_id)
+    origin_id = origin_shard["node_id"]
+    origin = env.get_pageserver(origin_id)
+    origin_generation = attachment_service.inspect(tenant_id)
+    assert origin_generation is not None
+    log.info(f"Tenant {tenant_id} attached to {origin_id} in generation {origin_generation[0]}")
+
+    origin.http_client().timeline_create(env.pg_version, tenant_id, timeline_id)
+    env.neon_cli.map_branch("failover", tenant_id, timeline_id)
+    endpoint = env.endpoints.create_start("failover", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE EXTENSION neon_test_utils")
+    endpoint.safe_psql("CREATE TABLE t (key int primary key, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1, 10000), 'payload'")
+    # Make the data durable in the remote storage, for the new location to find it
+    origin.http_client().timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload_queue_empty(origin.http_client(), tenant_id, timeline_id)
+
+    # The secondary location is on one of the other pageservers: secondary locations
+    # are not listed by the pageserver API, but they do have a tenant directory.
+    secondary_ids = [
+        ps.id for ps in env.pageservers if ps.id != origin_id and ps.tenant_dir(tenant_id).exists()
+    ]
+    assert len(secondary_ids) == 1
+
+    origin.stop(immediate=True)
+
+    def rescheduled():
+        nodes = {n["node_id"]: n for n in attachment_service.node_list()}
+        assert nodes[origin_id]["availability"] == "Offline"
+        (shard,) = attachment_service.locate(tenant_id)
+        assert shard["node_id"] != origin_id
+        return shard["node_id"]
+
+    dest_id = wait_until(60, 1, rescheduled)
+    assert dest_id == secondary_ids[0]
+
+    dest_generation = attachment_service.inspect(tenant_id)
+    assert dest_generation is not None
+    assert dest_generation[1] == dest_id
+    assert dest_generation[0] > origin_generation[0]
+
+    # The new attachment loads the timeline from remote storage
+    dest = env.get_pageserver(dest_id)
+
+    def timeline_loaded():
+        timelines = dest.http_client().timeline_list(tenant_id)
+        assert any(t["timeline_id"] == str(timeline_id) for t in timelines)
+
+    wait_until(30, 1, timeline_loaded)
+
+    # The old location can't get its deletions validated anymore, should it come back
+    def validate(generation: int) -> bool:
+        response = requests.post(
+            f"{env.control_plane_api}/validate",
+            json={"tenants": [{"id": str(tenant_id), "gen": generation}]},
+        )
+        response.raise_for_status()
+        (tenant,) = response.json()["tenants"]
+        return bool(tenant["valid"])
+
+    assert not validate(origin_generation[0])
+    assert validate(dest_generation[0])
+
+    # The compute reads from the new location. The tenant is unsharded, so it has a
+    # single connstring rather than a list.
+    def compute_notified():
+        connstring = endpoint.safe_psql("SHOW neon.pageserver_connstring")[0][0]
+        assert connstring.endswith(f":{dest.service_port.pg}")
+        assert endpoint.safe_psql("SHOW neon.pageserver_connstrings")[0][0] == ""
+
+    wait_until(30, 1, compute_notified)
+    endpoint.safe_psql("SELECT clear_buffer_cache()")
+    assert endpoint.safe_psql("SELECT count(*) FROM t")[0][0] == 10000
+
+
+def test_attachment_service_shard_split(neon_env_builder: NeonEnvBuilder):
+    """
+    Splitting a tenant replaces each of its shards with children on the same pageserver,
+    and the children carry on serving the parent's timelines.
+    """
+    neon_env_builder.num_pageservers = 2
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    assert env.attachment_service is not None
+    attachment_service = env.attachment_service
+
+    tenant_id = TenantId.generate()
+    timeline_id = TimelineId.generate()
+    attachment_service.tenant_create(tenant_id)
+
+    (parent,) = attachment_service.locate(tenant_id)
+    pageserver = env.get_pageserver(parent["node_id"])
+    pageserver.http_client().timeline_create(env.pg_version, tenant_id, timeline_id)
+
+    new_shards = attachment_service.tenant_shard_split(tenant_id, shard_count=4)
+    assert len(new_shards) == 4
+
+    shards = attachment_service.locate(tenant_id)
+    assert sorted(s["shard_id"] for s in shards) == sorted(new_shards)
+    assert all(s["node_id"] == parent["node_id"] for s in shards)
+
+    # The parent is gone from the pageserver, and each child has the timeline
+    attached = [t["id"] for t in pageserver.http_client().tenant_list()]
+    assert parent["shard_id"] not in attached
+    for shard_id in new_shards:
+        assert shard_id in attached
+        timelines = pageserver.http_client().timeline_list(shard_id)
+        assert [t["timeline_id"] for t in timelines] == [str(timeline_id)]
+
+    # Splitting again must go to a larger multiple of the current shard count
+    with pytest.raises(requests.exceptions.HTTPError):
+        attachment_service.tenant_shard_split(tenant_id, shard_count=6)
+
+
+def test_attachment_service_shard_split_rollback(neon_env_builder: NeonEnvBuilder):
+    """
+    A split that fails after some children were attached leaves the parent as it was,
+    and can be retried.
+    """
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    assert env.attachment_service is not None
+    attachment_service = env.attachment_service
+
+    tenant_id = TenantId.generate()
+    timeline_id = TimelineId.generate()
+    attachment_service.tenant_create(tenant_id)
+
+    (parent,) = attachment_service.locate(tenant_id)
+    pageserver = env.get_pageserver(parent["node_id"])
+    pageserver.allowed_errors.extend(
+        [".*failpoint: shard-split-post-child-attach.*", ".*rolling back split.*"]
+    )
+    pageserver.http_client().timeline_create(env.pg_version, tenant_id, timeline_id)
+
+    pageserver.http_client().configure_failpoints(
+        ("shard-split-post-child-attach", "return(1)")
+    )
+    with pytest.raises(requests.exceptions.HTTPError):
+        attachment_service.tenant_shard_split(tenant_id, shard_count=2)
+    pageserver.http_client().configure_failpoints(("shard-split-post-child-attach", "off"))
+
+    # The children are gone, and the parent still serves its timeline
+    (shard,) = attachment_service.locate(tenant_id)
+    assert shard["shard_id"] == parent["shard_id"]
+    attached = [t["id"] for t in pageserver.http_client().tenant_list()]
+    assert attached == [parent["shard_id"]]
+    child_dirs = [
+        p
+        for p in pageserver.tenant_dir().iterdir()
+        if p.name.startswith(f"{tenant_id}-") and not p.name.endswith("___temp")
+    ]
+    assert child_dirs == []
+    timelines = pageserver.http_client().timeline_list(tenant_id)
+    assert [t["timeline_id"] for t in timelines] == [str(timeline_id)]
+
+    new_shards = attachment_service.tenant_shard_split(tenant_id, shard_count=2)
+    assert len(new_shards) == 2
+
+
+def test_attachment_service_shard_split_resume(neon_env_builder: NeonEnvBuilder):
+    """
+    A split that fails after some of the tenant's shards were split leaves the tenant
+    unavailable until the split is retried, which splits the remaining shards.
+    """
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    assert env.attachment_service is not None
+    attachment_service = env.attachment_service
+
+    tenant_id = TenantId.generate()
+    attachment_service.tenant_create(tenant_id, shard_count=2)
+
+    pageserver = env.pageservers[0]
+    pageserver.allowed_errors.extend(
+        [".*failpoint: shard-split-post-child-attach.*", ".*rolling back split.*"]
+    )
+
+    # The first shard splits, the second one fails to
+    pageserver.http_client().configure_failpoints(
+        ("shard-split-post-child-attach", "1*off->return(1)")
+    )
+    with pytest.raises(requests.exceptions.HTTPError):
+        attachment_service.tenant_shard_split(tenant_id, shard_count=4)
+    pageserver.http_client().configure_failpoints(("shard-split-post-child-attach", "off"))
+
+    # Shards of both counts can't be used together, or split to another count
+    with pytest.raises(requests.exceptions.HTTPError, match="503"):
+        attachment_service.locate(tenant_id)
+    with pytest.raises(requests.exceptions.HTTPError, match="409"):
+        attachment_service.tenant_shard_split(tenant_id, shard_count=8)
+
+    # The split survives a restart of the service, and completes when retried
+    attachment_service.stop()
+    attachment_service.start()
+    new_shards = attachment_service.tenant_shard_split(tenant_id, shard_count=4)
+    assert len(new_shards) == 4
+    shards = attachment_service.locate(tenant_id)
+    assert sorted(s["shard_id"] for s in shards) == sorted(new_shards)
+    attached = [t["id"] for t in pageserver.http_client().tenant_list()]
+    assert sorted(attached) == sorted(new_shards)
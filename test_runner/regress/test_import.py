@@ -5,6 +5,7 @@ import shutil
 import tarfile
 from contextlib import closing
 from pathlib import Path
+from typing import Any
 
 import pytest
 from fixtures.log_helper import log
@@ -14,6 +15,7 @@ from fixtures.neon_fixtures import (
     NeonEnvBuilder,
     PgBin,
 )
+from fixtures.pageserver.http import PageserverApiException, PageserverHttpClient
 from fixtures.pageserver.utils import (
     timeline_delete_wait_completed,
     wait_for_last_record_lsn,
@@ -21,7 +23,7 @@ from fixtures.pageserver.utils import (
 )
 from fixtures.remote_storage import RemoteStorageKind
 from fixtures.types import Lsn, TenantId, TimelineId
-from fixtures.utils import subprocess_capture
+from fixtures.utils import subprocess_capture, wait_until
 
 
 def test_import_from_vanilla(test_output_dir, pg_bin, vanilla_pg, neon_env_builder):
@@ -164,6 +166,190 @@ def test_import_from_vanilla(test_output_dir, pg_bin, vanilla_pg, neon_env_build
     assert endpoint.safe_psql("select count(*) from t") == [(300000,)]
 
 
+def test_import_via_http_api(test_output_dir, pg_bin, vanilla_pg, neon_env_builder):
+    # With data checksums enabled, the import verifies the checksum of every page
+    pg_bin.run(["pg_checksums", "--enable", "-D", str(vanilla_pg.pgdatadir)])
+    vanilla_pg.start()
+    vanilla_pg.safe_psql("create user cloud_admin with password 'postgres' superuser")
+    vanilla_pg.safe_psql(
+        "create table t as select 'some data' || g from generate_series(1,30000) g"
+    )
+    table_path = vanilla_pg.safe_psql("select pg_relation_filepath('t')")[0][0]
+
+    basebackup_dir = os.path.join(test_output_dir, "basebackup")
+    base_tar = os.path.join(basebackup_dir, "base.tar")
+    wal_tar = os.path.join(basebackup_dir, "pg_wal.tar")
+    os.mkdir(basebackup_dir)
+    vanilla_pg.safe_psql("CHECKPOINT")
+    pg_bin.run(["pg_basebackup", "-F", "tar", "-d", vanilla_pg.connstr(), "-D", basebackup_dir])
+    with open(os.path.join(basebackup_dir, "backup_manifest")) as f:
+        manifest = json.load(f)
+        start_lsn = Lsn(manifest["WAL-Ranges"][0]["Start-LSN"])
+        end_lsn = Lsn(manifest["WAL-Ranges"][0]["End-LSN"])
+
+    # Make a copy of base.tar with one corrupt page in table t
+    unpacked_base = os.path.join(basebackup_dir, "unpacked-base")
+    corrupt_base_tar = os.path.join(unpacked_base, "corrupt-base.tar")
+    os.mkdir(unpacked_base, 0o750)
+    subprocess_capture(test_output_dir, ["tar", "-xf", base_tar, "-C", unpacked_base])
+    with open(os.path.join(unpacked_base, table_path), "r+b") as f:
+        f.seek(8192 + 4000)
+        byte = f.read(1)
+        f.seek(8192 + 4000)
+        f.write(bytes([byte[0] ^ 0xFF]))
+    subprocess_capture(
+        test_output_dir,
+        ["tar", "-cf", "corrupt-base.tar"] + os.listdir(unpacked_base),
+        cwd=unpacked_base,
+    )
+
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.extend(
+        [
+            ".*checksum mismatch in .* block 1.*",
+            ".*import failed: .*pages failed checksum verification.*",
+            ".*Timeline got dropped without initializing, cleaning its files.*",
+            ".*Removing intermediate uninit mark file.*",
+            ".*cannot import WAL from .*",
+        ]
+    )
+    tenant = TenantId.generate()
+    timeline = TimelineId.generate()
+    env.pageserver.tenant_create(tenant)
+    client = env.pageserver.http_client()
+
+    def wait_for_import(client: PageserverHttpClient) -> dict[str, Any]:
+        return _wait_for_import(client, tenant, timeline)
+
+    # A page with a bad checksum fails the import, and no timeline is left behind
+    client.timeline_import_basebackup(tenant, timeline, corrupt_base_tar, start_lsn, env.pg_version)
+    status = wait_for_import(client)
+    assert status["state"] == "Failed"
+    assert status["checksum_failures"] == 1
+    assert timeline not in [TimelineId(t["timeline_id"]) for t in client.timeline_list(tenant)]
+
+    status = client.timeline_import_basebackup(
+        tenant, timeline, base_tar, start_lsn, env.pg_version
+    )
+    assert status["kind"] == "Basebackup"
+    status = wait_for_import(client)
+    assert status["state"] == "Completed", status["error"]
+    assert status["bytes_total"] == os.path.getsize(base_tar)
+    assert 0 < status["bytes_read"] <= status["bytes_total"]
+    assert status["checksum_failures"] == 0
+
+    status = client.timeline_import_wal(tenant, timeline, wal_tar, end_lsn)
+    assert status["kind"] == "Wal"
+    status = wait_for_import(client)
+    assert status["state"] == "Completed", status["error"]
+    assert Lsn(status["last_record_lsn"]) >= end_lsn
+
+    # WAL can only be imported from where the timeline ends
+    with pytest.raises(PageserverApiException, match="cannot import WAL from"):
+        client.timeline_import_wal(tenant, timeline, wal_tar, end_lsn, start_lsn=start_lsn)
+
+    wait_for_last_record_lsn(client, tenant, timeline, end_lsn)
+    wait_for_upload(client, tenant, timeline, end_lsn)
+
+    env.neon_cli.map_branch("imported-via-http", tenant, timeline)
+    endpoint = env.endpoints.create_start("imported-via-http", tenant_id=tenant)
+    assert endpoint.safe_psql("select count(*) from t") == [(30000,)]
+
+
+def test_import_wal_via_http_api_multiple_segments(
+    test_output_dir, pg_bin, vanilla_pg, neon_env_builder
+):
+    """
+    A WAL import carries on into the next segment where a segment's records end, and
+    fails rather than waits forever when the archive ends before the end LSN.
+    """
+    vanilla_pg.start()
+    vanilla_pg.safe_psql("create user cloud_admin with password 'postgres' superuser")
+    vanilla_pg.safe_psql("create table t (v text)")
+
+    basebackup_dir = os.path.join(test_output_dir, "basebackup")
+    base_tar = os.path.join(basebackup_dir, "base.tar")
+    wal_tar = os.path.join(basebackup_dir, "multi-segment-wal.tar")
+    os.mkdir(basebackup_dir)
+    vanilla_pg.safe_psql("CHECKPOINT")
+    pg_bin.run(["pg_basebackup", "-F", "tar", "-d", vanilla_pg.connstr(), "-D", basebackup_dir])
+    with open(os.path.join(basebackup_dir, "backup_manifest")) as f:
+        manifest = json.load(f)
+        start_lsn = Lsn(manifest["WAL-Ranges"][0]["Start-LSN"])
+
+    # Write WAL over several segments after the backup.  The WAL after end_lsn makes sure
+    # that the import finds a record past it.
+    for _ in range(3):
+        vanilla_pg.safe_psql("insert into t select 'data' || g from generate_series(1,10000) g")
+        vanilla_pg.safe_psql("select pg_switch_wal()")
+    vanilla_pg.safe_psql("insert into t values ('last')")
+    end_lsn = Lsn(vanilla_pg.safe_psql("select pg_current_wal_insert_lsn()")[0][0])
+    vanilla_pg.safe_psql("insert into t values ('after end_lsn')")
+    vanilla_pg.safe_psql("select pg_switch_wal()")
+
+    # Archive the segments from the backup's start up to the current one, as an
+    # archive_command would
+    first_segment = vanilla_pg.safe_psql(f"select pg_walfile_name('{start_lsn}')")[0][0]
+    last_segment = vanilla_pg.safe_psql("select pg_walfile_name(pg_current_wal_lsn())")[0][0]
+    pg_wal = Path(vanilla_pg.pgdatadir) / "pg_wal"
+    segments = sorted(
+        p.name
+        for p in pg_wal.iterdir()
+        if re.fullmatch("[0-9A-F]{24}", p.name) and first_segment <= p.name <= last_segment
+    )
+    assert len(segments) > 3
+    with tarfile.open(wal_tar, "w") as tar:
+        for segment in segments:
+            tar.add(pg_wal / segment, arcname=segment)
+
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    env.pageserver.allowed_errors.append(".*import failed: WAL archive ends at .*")
+    tenant = TenantId.generate()
+    env.pageserver.tenant_create(tenant)
+    client = env.pageserver.http_client()
+
+    # An end LSN past the end of the archive fails the import
+    short_timeline = TimelineId.generate()
+    client.timeline_import_basebackup(tenant, short_timeline, base_tar, start_lsn, env.pg_version)
+    status = _wait_for_import(client, tenant, short_timeline)
+    assert status["state"] == "Completed", status["error"]
+    client.timeline_import_wal(tenant, short_timeline, wal_tar, end_lsn + 1024 * 1024 * 1024)
+    status = _wait_for_import(client, tenant, short_timeline)
+    assert status["state"] == "Failed"
+    assert "WAL archive ends at" in status["error"]
+    assert status["files_imported"] == len(segments)
+
+    timeline = TimelineId.generate()
+    client.timeline_import_basebackup(tenant, timeline, base_tar, start_lsn, env.pg_version)
+    status = _wait_for_import(client, tenant, timeline)
+    assert status["state"] == "Completed", status["error"]
+    client.timeline_import_wal(tenant, timeline, wal_tar, end_lsn)
+    status = _wait_for_import(client, tenant, timeline)
+    assert status["state"] == "Completed", status["error"]
+    assert Lsn(status["last_record_lsn"]) >= end_lsn
+    assert status["files_imported"] > 3
+
+    wait_for_last_record_lsn(client, tenant, timeline, end_lsn)
+    wait_for_upload(client, tenant, timeline, end_lsn)
+
+    env.neon_cli.map_branch("imported-segments", tenant, timeline)
+    endpoint = env.endpoints.create_start("imported-segments", tenant_id=tenant)
+    assert endpoint.safe_psql("select count(*) from t") == [(30001,)]
+
+
+def _wait_for_import(
+    client: PageserverHttpClient, tenant: TenantId, timeline: TimelineId
+) -> dict[str, Any]:
+    def finished():
+        status = client.timeline_import_status(tenant, timeline)
+        assert status["state"] != "Running"
+        return status
+
+    return wait_until(300, 1, finished)
+
+
 def test_import_from_pageserver_small(
     pg_bin: PgBin, neon_env_builder: NeonEnvBuilder, test_output_dir: Path
 ):
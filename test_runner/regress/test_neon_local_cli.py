@@ -1,6 +1,8 @@
 import pytest
-from fixtures.neon_fixtures import NeonEnvBuilder
+import toml
+from fixtures.neon_fixtures import NeonEnvBuilder, PgProtocol
 from fixtures.port_distributor import PortDistributor
+from fixtures.types import TenantId
 
 
 # Test that neon cli is able to start and stop all processes with the user defaults.
@@ -59,3 +61,64 @@ def test_neon_two_primary_endpoints_fail(
     env.neon_cli.endpoint_stop("ep1")
     # ep1 is stopped so create ep2 will succeed
     env.neon_cli.endpoint_start("ep2")
+
+
+def test_neon_local_apply(neon_env_builder: NeonEnvBuilder, port_distributor: PortDistributor):
+    """
+    'neon_local apply' builds tenants, branches and endpoints from a description of them,
+    and applying the same description again changes nothing.
+    """
+    env = neon_env_builder.init_configs()
+    tenant_id = TenantId.generate()
+    main_pg_port = port_distributor.get_port()
+
+    # The services are described the same way as in the config of the initialized repository
+    config = toml.load(env.repo_dir / "config")
+    config["tenants"] = [
+        {
+            "id": str(tenant_id),
+            "default": True,
+            "pg_version": int(env.pg_version),
+            "branches": [{"name": "main"}, {"name": "child", "parent": "main"}],
+        }
+    ]
+    config["endpoints"] = [
+        {
+            "name": "ep-main",
+            "tenant_id": str(tenant_id),
+            "pg_port": main_pg_port,
+            "http_port": port_distributor.get_port(),
+        },
+        {
+            "name": "ep-child",
+            "tenant_id": str(tenant_id),
+            "branch": "child",
+            "pg_port": port_distributor.get_port(),
+            "http_port": port_distributor.get_port(),
+            "running": False,
+        },
+    ]
+
+    try:
+        # Services are started as needed
+        env.neon_cli.apply(config)
+        branches = {name for name, _ in env.neon_cli.list_timelines(tenant_id)}
+        assert branches == {"main", "child"}
+
+        endpoint = PgProtocol(
+            host="localhost", port=main_pg_port, user="cloud_admin", dbname="postgres"
+        )
+        assert endpoint.safe_psql("SELECT 1") == [(1,)]
+
+        assert "environment is up to date" in env.neon_cli.apply(config).stdout
+
+        # Only the difference to the description is applied
+        config["endpoints"][1]["running"] = True
+        plan = env.neon_cli.apply(config, dry_run=True).stdout
+        assert "would start endpoint ep-child" in plan
+        assert "would create" not in plan
+
+        env.neon_cli.apply(config)
+        assert "environment is up to date" in env.neon_cli.apply(config).stdout
+    finally:
+        env.neon_cli.stop()
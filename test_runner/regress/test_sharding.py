@@ -0,0 +1,87 @@
+from typing import List
+
+from fixtures.neon_fixtures import Endpoint, NeonEnvBuilder
+from fixtures.remote_storage import RemoteStorageKind
+
+
+def pageserver_connstrings(endpoint: Endpoint) -> List[str]:
+    """
+    The connection strings of the shards a compute reads from, in shard number order.
+    """
+    # a sharded tenant doesn't use the single connstring
+    assert endpoint.safe_psql("SHOW neon.pageserver_connstring")[0][0] == ""
+    setting = endpoint.safe_psql("SHOW neon.pageserver_connstrings")[0][0]
+    return [connstring.strip('"') for connstring in setting.split(",")]
+
+
+def test_sharding_smoke(neon_env_builder: NeonEnvBuilder):
+    """
+    A tenant created with several shards is spread across the pageservers, and its
+    compute sends each read to the shard that holds the page.
+    """
+    shard_count = 4
+    neon_env_builder.num_pageservers = 2
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    assert env.attachment_service is not None
+
+    tenant_id, timeline_id = env.neon_cli.create_tenant(shard_count=shard_count)
+
+    shards = env.attachment_service.locate(tenant_id)
+    assert len(shards) == shard_count
+    assert len(set(s["node_id"] for s in shards)) == 2
+
+    # Every shard has the initial timeline
+    for shard in shards:
+        pageserver = env.get_pageserver(shard["node_id"])
+        timelines = pageserver.http_client().timeline_list(shard["shard_id"])
+        assert [t["timeline_id"] for t in timelines] == [str(timeline_id)]
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    assert len(pageserver_connstrings(endpoint)) == shard_count
+
+    # Several relations, so that pages are spread across the shards
+    for i in range(8):
+        endpoint.safe_psql(f"CREATE TABLE t{i} (key int primary key, value text)")
+        endpoint.safe_psql(f"INSERT INTO t{i} SELECT generate_series(1, 10000), 'payload'")
+
+    # Start from an empty buffer cache, so that reads go to the pageservers
+    endpoint.stop()
+    endpoint.start()
+    for i in range(8):
+        assert endpoint.safe_psql(f"SELECT count(*) FROM t{i}")[0][0] == 10000
+
+
+def test_sharding_split_compute(neon_env_builder: NeonEnvBuilder):
+    """
+    Splitting a tenant switches its running compute over to the children, which serve
+    the data written before the split, and the data written after.
+    """
+    neon_env_builder.num_pageservers = 2
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    assert env.attachment_service is not None
+
+    tenant_id, _ = env.neon_cli.create_tenant(shard_count=2)
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE EXTENSION neon_test_utils")
+    for i in range(8):
+        endpoint.safe_psql(f"CREATE TABLE t{i} (key int primary key, value text)")
+        endpoint.safe_psql(f"INSERT INTO t{i} SELECT generate_series(1, 10000), 'payload'")
+
+    new_shards = env.attachment_service.tenant_shard_split(tenant_id, shard_count=4)
+    assert len(new_shards) == 4
+
+    assert len(pageserver_connstrings(endpoint)) == 4
+
+    # Read through to the pageservers, which now only have the children
+    endpoint.safe_psql("SELECT clear_buffer_cache()")
+    for i in range(8):
+        assert endpoint.safe_psql(f"SELECT count(*) FROM t{i}")[0][0] == 10000
+
+    for i in range(8):
+        endpoint.safe_psql(f"INSERT INTO t{i} SELECT generate_series(10001, 20000), 'payload'")
+    endpoint.safe_psql("SELECT clear_buffer_cache()")
+    for i in range(8):
+        assert endpoint.safe_psql(f"SELECT count(*) FROM t{i}")[0][0] == 20000
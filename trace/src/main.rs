@@ -8,10 +8,14 @@ use std::{
 };
 
 use pageserver_api::models::{PagestreamFeMessage, PagestreamGetPageRequest};
-use utils::id::{ConnectionId, TenantId, TimelineId};
+use pageserver_api::shard::TenantShardId;
+use pageserver_api::trace::TraceReader;
+use utils::id::{ConnectionId, TimelineId};
 
 use clap::{Parser, Subcommand};
 
+mod replay;
+
 /// Utils for working with pageserver read traces. For generating
 /// traces, see the `trace_read_requests` tenant config option.
 #[derive(Parser, Debug)]
@@ -40,8 +44,27 @@ enum Command {
     /// Draw the traces in svg format
     Draw,
 
-    /// Send the read requests to a pageserver
-    Replay,
+    /// Send the read requests to a pageserver, reproducing the timing and
+    /// concurrency of the traced connections
+    Replay {
+        /// libpq connection string of the pageserver to replay against
+        #[arg(long)]
+        pageserver_connstring: String,
+
+        /// libpq connection string of a second pageserver. Every request is also
+        /// sent there, and responses that differ between the two are reported.
+        #[arg(long)]
+        compare_connstring: Option<String>,
+
+        /// Speed of the replay relative to the traced timing. 0 sends requests
+        /// back to back.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+
+        /// Only replay the traces of this timeline
+        #[arg(long)]
+        timeline_id: Option<TimelineId>,
+    },
 }
 
 // HACK This function will change and improve as we see what kind of analysis is useful.
@@ -49,15 +72,16 @@ enum Command {
 //      and counts the frequency of each value. This information is useful in order to:
 //      - see how sequential a workload is by seeing how often the delta is 1
 //      - detect any prefetching anomalies by looking for negative deltas during seqscan
-fn analyze_trace<R: std::io::Read>(mut reader: R) {
+fn analyze_trace<R: std::io::BufRead>(reader: TraceReader<R>) {
     let mut total = 0; // Total requests traced
     let mut cross_rel = 0; // Requests that ask for different rel than previous request
     let mut deltas = HashMap::<i32, u32>::new(); // Consecutive blkno differences
     let mut prev: Option<PagestreamGetPageRequest> = None;
 
     // Compute stats
-    while let Ok(msg) = PagestreamFeMessage::parse(&mut reader) {
-        match msg {
+    for record in reader {
+        let Ok(record) = record else { break };
+        match record.msg {
             PagestreamFeMessage::Exists(_) => {}
             PagestreamFeMessage::Nblocks(_) => {}
             PagestreamFeMessage::GetPage(req) => {
@@ -87,18 +111,20 @@ fn analyze_trace<R: std::io::Read>(mut reader: R) {
     dbg!(deltas);
 }
 
-fn dump_trace<R: std::io::Read>(mut reader: R) {
-    while let Ok(msg) = PagestreamFeMessage::parse(&mut reader) {
-        println!("{msg:?}");
+fn dump_trace<R: std::io::BufRead>(reader: TraceReader<R>) {
+    for record in reader {
+        let Ok(record) = record else { break };
+        match record.timestamp {
+            Some(ts) => println!("{} {:?}", humantime::format_rfc3339_micros(ts), record.msg),
+            None => println!("{:?}", record.msg),
+        }
     }
 }
 
 #[derive(Debug)]
 struct TraceFile {
-    #[allow(dead_code)]
-    pub tenant_id: TenantId,
+    pub tenant_shard_id: TenantShardId,
 
-    #[allow(dead_code)]
     pub timeline_id: TimelineId,
 
     #[allow(dead_code)]
@@ -110,11 +136,11 @@ struct TraceFile {
 fn get_trace_files(traces_dir: &PathBuf) -> anyhow::Result<Vec<TraceFile>> {
     let mut trace_files = Vec::<TraceFile>::new();
 
-    // Trace files are organized as {tenant_id}/{timeline_id}/{connection_id}
+    // Trace files are organized as {tenant_shard_id}/{timeline_id}/{connection_id}
     for tenant_dir in read_dir(traces_dir)? {
         let entry = tenant_dir?;
         let path = entry.path();
-        let tenant_id = TenantId::from_str(path.file_name().unwrap().to_str().unwrap())?;
+        let tenant_shard_id = TenantShardId::from_str(path.file_name().unwrap().to_str().unwrap())?;
 
         for timeline_dir in read_dir(path)? {
             let entry = timeline_dir?;
@@ -128,7 +154,7 @@ fn get_trace_files(traces_dir: &PathBuf) -> anyhow::Result<Vec<TraceFile>> {
                     ConnectionId::from_str(path.file_name().unwrap().to_str().unwrap())?;
 
                 trace_files.push(TraceFile {
-                    tenant_id,
+                    tenant_shard_id,
                     timeline_id,
                     connection_id,
                     path,
@@ -152,7 +178,7 @@ fn main() -> anyhow::Result<()> {
         Command::Dump => {
             for trace_file in get_trace_files(&args.path)? {
                 let file = File::open(trace_file.path.clone())?;
-                let reader = BufReader::new(file);
+                let reader = TraceReader::new(BufReader::new(file))?;
                 dump_trace(reader);
             }
         }
@@ -160,12 +186,30 @@ fn main() -> anyhow::Result<()> {
             for trace_file in get_trace_files(&args.path)? {
                 println!("analyzing {trace_file:?}");
                 let file = File::open(trace_file.path.clone())?;
-                let reader = BufReader::new(file);
+                let reader = TraceReader::new(BufReader::new(file))?;
                 analyze_trace(reader);
             }
         }
         Command::Draw => todo!(),
-        Command::Replay => todo!(),
+        Command::Replay {
+            pageserver_connstring,
+            compare_connstring,
+            speed,
+            timeline_id,
+        } => {
+            let mut trace_files = get_trace_files(&args.path)?;
+            if let Some(timeline_id) = timeline_id {
+                trace_files.retain(|trace_file| trace_file.timeline_id == timeline_id);
+            }
+            replay::replay(
+                trace_files,
+                replay::ReplayOptions {
+                    pageserver_connstring,
+                    compare_connstring,
+                    speed,
+                },
+            )?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,293 @@
+//! Replay of read traces against a live pageserver.
+//!
+//! Each trace file is the request stream of one compute connection, so each one is
+//! replayed on its own pagestream connection, concurrently with the others. Within a
+//! connection requests are sent one at a time, as compute does. If the trace has
+//! timestamps, every request waits until its original offset from the start of the
+//! earliest trace (divided by the speed factor) before it is sent; legacy traces
+//! without timestamps are sent back to back.
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use pageserver_api::models::{PagestreamBeMessage, PagestreamFeMessage};
+use pageserver_api::trace::{TraceReader, TraceRecord};
+use pageserver_client::page_service;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use crate::TraceFile;
+
+pub(crate) struct ReplayOptions {
+    pub pageserver_connstring: String,
+    pub compare_connstring: Option<String>,
+    pub speed: f64,
+}
+
+/// Latencies of one kind of request against one pageserver.
+#[derive(Default)]
+struct LatencyHistogram {
+    samples: Vec<Duration>,
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency: Duration) {
+        self.samples.push(latency);
+    }
+
+    fn merge(&mut self, other: LatencyHistogram) {
+        self.samples.extend(other.samples);
+    }
+
+    fn print(&mut self, name: &str) {
+        if self.samples.is_empty() {
+            return;
+        }
+        self.samples.sort();
+        let percentile = |p: f64| {
+            let idx = ((self.samples.len() as f64 * p).ceil() as usize).max(1) - 1;
+            self.samples[idx.min(self.samples.len() - 1)]
+        };
+        println!(
+            "{name}: count={} min={:?} p50={:?} p90={:?} p99={:?} p99.9={:?} max={:?}",
+            self.samples.len(),
+            self.samples[0],
+            percentile(0.5),
+            percentile(0.9),
+            percentile(0.99),
+            percentile(0.999),
+            self.samples[self.samples.len() - 1],
+        );
+
+        // Power-of-two buckets, in microseconds
+        let mut buckets = BTreeMap::<u32, usize>::new();
+        for sample in &self.samples {
+            let micros = sample.as_micros().max(1) as u64;
+            let bucket = u64::BITS - (micros - 1).leading_zeros();
+            *buckets.entry(bucket).or_default() += 1;
+        }
+        for (bucket, count) in buckets {
+            println!("  <= {:>10}us: {count}", 1u64 << bucket);
+        }
+    }
+}
+
+/// What one connection's replay observed.
+#[derive(Default)]
+struct ConnectionStats {
+    /// Keyed by (pageserver, request kind)
+    latencies: BTreeMap<(&'static str, &'static str), LatencyHistogram>,
+    /// Error responses from the pageserver being replayed against
+    errors: usize,
+    /// Requests for which the two pageservers returned different responses
+    divergences: usize,
+}
+
+impl ConnectionStats {
+    fn merge(&mut self, other: ConnectionStats) {
+        for (key, histogram) in other.latencies {
+            self.latencies.entry(key).or_default().merge(histogram);
+        }
+        self.errors += other.errors;
+        self.divergences += other.divergences;
+    }
+}
+
+fn request_kind(msg: &PagestreamFeMessage) -> &'static str {
+    match msg {
+        PagestreamFeMessage::Exists(_) => "Exists",
+        PagestreamFeMessage::Nblocks(_) => "Nblocks",
+        PagestreamFeMessage::GetPage(_) => "GetPage",
+        PagestreamFeMessage::DbSize(_) => "DbSize",
+    }
+}
+
+/// Short description of a response, for reporting divergences. Page images are
+/// not printed.
+fn describe_response(resp: &PagestreamBeMessage) -> String {
+    match resp {
+        PagestreamBeMessage::Exists(r) => format!("exists={}", r.exists),
+        PagestreamBeMessage::Nblocks(r) => format!("n_blocks={}", r.n_blocks),
+        PagestreamBeMessage::GetPage(r) => format!("page image of {} bytes", r.page.len()),
+        PagestreamBeMessage::Error(r) => format!("error: {}", r.message),
+        PagestreamBeMessage::DbSize(r) => format!("db_size={}", r.db_size),
+    }
+}
+
+async fn timed_request(
+    client: &mut page_service::PagestreamClient,
+    msg: &PagestreamFeMessage,
+) -> anyhow::Result<(PagestreamBeMessage, Duration)> {
+    let started = Instant::now();
+    let resp = client.request(msg).await?;
+    Ok((resp, started.elapsed()))
+}
+
+async fn connect(
+    connstring: &str,
+    trace_file: &TraceFile,
+) -> anyhow::Result<page_service::PagestreamClient> {
+    page_service::Client::new(connstring)
+        .await
+        .with_context(|| format!("connect to {connstring}"))?
+        .pagestream(trace_file.tenant_shard_id.tenant_id, trace_file.timeline_id)
+        .await
+        .context("start pagestream")
+}
+
+async fn replay_connection(
+    trace_file: TraceFile,
+    records: Vec<TraceRecord>,
+    replay_start: Instant,
+    trace_start: Option<SystemTime>,
+    options: &ReplayOptions,
+) -> anyhow::Result<ConnectionStats> {
+    let mut stats = ConnectionStats::default();
+
+    let mut primary = connect(&options.pageserver_connstring, &trace_file).await?;
+    let mut compare = match &options.compare_connstring {
+        Some(connstring) => Some(connect(connstring, &trace_file).await?),
+        None => None,
+    };
+
+    for (i, record) in records.iter().enumerate() {
+        if let (Some(ts), Some(trace_start)) = (record.timestamp, trace_start) {
+            if options.speed > 0.0 {
+                let offset = ts.duration_since(trace_start).unwrap_or(Duration::ZERO);
+                tokio::time::sleep_until(replay_start + offset.div_f64(options.speed)).await;
+            }
+        }
+
+        let kind = request_kind(&record.msg);
+        let (resp, latency) = match compare.as_mut() {
+            None => timed_request(&mut primary, &record.msg).await?,
+            Some(compare) => {
+                let (primary_res, compare_res) = tokio::join!(
+                    timed_request(&mut primary, &record.msg),
+                    timed_request(compare, &record.msg),
+                );
+                let (resp, latency) = primary_res?;
+                let (compare_resp, compare_latency) = compare_res?;
+                stats
+                    .latencies
+                    .entry(("compare", kind))
+                    .or_default()
+                    .record(compare_latency);
+
+                if resp.serialize() != compare_resp.serialize() {
+                    stats.divergences += 1;
+                    let detail = match (&resp, &compare_resp) {
+                        (PagestreamBeMessage::GetPage(_), PagestreamBeMessage::GetPage(_)) => {
+                            "page images differ".to_string()
+                        }
+                        _ => format!(
+                            "{} vs {}",
+                            describe_response(&resp),
+                            describe_response(&compare_resp)
+                        ),
+                    };
+                    println!(
+                        "divergence in {} request #{i} {:?}: {detail}",
+                        trace_file.path.display(),
+                        record.msg
+                    );
+                }
+                (resp, latency)
+            }
+        };
+        stats
+            .latencies
+            .entry(("pageserver", kind))
+            .or_default()
+            .record(latency);
+        if let PagestreamBeMessage::Error(err) = &resp {
+            stats.errors += 1;
+            println!(
+                "error response in {} request #{i} {:?}: {}",
+                trace_file.path.display(),
+                record.msg,
+                err.message
+            );
+        }
+    }
+
+    primary.shutdown().await;
+    if let Some(compare) = compare {
+        compare.shutdown().await;
+    }
+    Ok(stats)
+}
+
+fn read_records(trace_file: &TraceFile) -> anyhow::Result<Vec<TraceRecord>> {
+    let file = std::fs::File::open(&trace_file.path)?;
+    TraceReader::new(std::io::BufReader::new(file))?
+        .collect::<anyhow::Result<Vec<_>>>()
+        .with_context(|| format!("read trace {}", trace_file.path.display()))
+}
+
+pub(crate) fn replay(trace_files: Vec<TraceFile>, options: ReplayOptions) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        options.speed >= 0.0 && options.speed.is_finite(),
+        "speed must be a non-negative number"
+    );
+
+    let mut traces = Vec::with_capacity(trace_files.len());
+    for trace_file in trace_files {
+        let records = read_records(&trace_file)?;
+        traces.push((trace_file, records));
+    }
+    // Offsets of all connections are relative to the earliest traced request
+    let trace_start = traces
+        .iter()
+        .filter_map(|(_, records)| records.first().and_then(|r| r.timestamp))
+        .min();
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let options = std::sync::Arc::new(options);
+
+    let (mut stats, failed) = runtime.block_on(async move {
+        let replay_start = Instant::now();
+        let mut tasks = JoinSet::new();
+        for (trace_file, records) in traces {
+            let options = options.clone();
+            tasks.spawn(async move {
+                let path = trace_file.path.clone();
+                let res =
+                    replay_connection(trace_file, records, replay_start, trace_start, &options)
+                        .await;
+                (path, res)
+            });
+        }
+
+        let mut stats = ConnectionStats::default();
+        let mut failed = 0;
+        while let Some(res) = tasks.join_next().await {
+            match res.expect("replay task panicked") {
+                (_, Ok(connection_stats)) => stats.merge(connection_stats),
+                (path, Err(e)) => {
+                    failed += 1;
+                    println!("replay of {} failed: {e:#}", path.display());
+                }
+            }
+        }
+        (stats, failed)
+    });
+
+    for ((pageserver, kind), histogram) in stats.latencies.iter_mut() {
+        histogram.print(&format!("{pageserver} {kind}"));
+    }
+    println!(
+        "error responses: {}, divergences: {}, failed connections: {failed}",
+        stats.errors, stats.divergences
+    );
+
+    if stats.divergences > 0 || failed > 0 {
+        anyhow::bail!(
+            "replay found {} divergences and {failed} failed connections",
+            stats.divergences
+        );
+    }
+    Ok(())
+}